@@ -0,0 +1,344 @@
+//! Chain analytics export
+//!
+//! `export_analytics` walks a `ChainSnapshot` up to its tip and writes three
+//! tables, one file each, for loading into a data warehouse:
+//!
+//! - `blocks`: one row per block
+//! - `forges`: one row per forge, with its position in the block
+//! - `fees`: one row per block, totalling the forge fee schedule and rewards
+//!
+//! The columns are fixed by `BLOCK_COLUMNS`, `FORGE_COLUMNS` and
+//! `FEE_COLUMNS`; columns are only ever appended, with `SCHEMA_VERSION`
+//! bumped when they are. Hashes and keys are lowercase hex. CSV files start
+//! with a header row and leave null cells empty. Parquet files (feature
+//! `parquet`) store integers as unsigned 64-bit, text as UTF-8 strings, and
+//! the schema version under the `excalibur.schema_version` metadata key.
+
+#[cfg(feature = "parquet")]
+mod parquet_writer;
+
+use crate::chain::ChainStore;
+use crate::codec::header_hash_preimage;
+use crate::consensus::Block;
+use crate::crypto::prophecy_registry_hash;
+use crate::ledger::FORGE_REWARD;
+use crate::params::FeeSchedule;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Version of the exported tables' columns
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Output file format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    /// Requires the `parquet` feature
+    Parquet,
+}
+
+impl ExportFormat {
+    /// File extension
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "parquet" => Ok(ExportFormat::Parquet),
+            _ => Err(anyhow!("Unknown export format {:?} (expected csv or parquet)", s)),
+        }
+    }
+}
+
+/// Type of a column's values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    UInt64,
+    Text,
+}
+
+/// One column of an exported table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Column {
+    pub name: &'static str,
+    pub kind: ColumnType,
+    pub nullable: bool,
+}
+
+const fn column(name: &'static str, kind: ColumnType) -> Column {
+    Column { name, kind, nullable: false }
+}
+
+const fn nullable(name: &'static str, kind: ColumnType) -> Column {
+    Column { name, kind, nullable: true }
+}
+
+/// Columns of the `blocks` table
+pub const BLOCK_COLUMNS: &[Column] = &[
+    column("height", ColumnType::UInt64),
+    column("hash", ColumnType::Text),
+    column("prev_hash", ColumnType::Text),
+    column("merkle_root", ColumnType::Text),
+    column("version", ColumnType::UInt64),
+    column("timestamp", ColumnType::UInt64),
+    nullable("timestamp_millis", ColumnType::UInt64),
+    column("difficulty", ColumnType::UInt64),
+    column("bits", ColumnType::UInt64),
+    column("nonce", ColumnType::UInt64),
+    column("forge_count", ColumnType::UInt64),
+    column("size", ColumnType::UInt64),
+    nullable("state_root", ColumnType::Text),
+];
+
+/// Columns of the `forges` table
+pub const FORGE_COLUMNS: &[Column] = &[
+    column("height", ColumnType::UInt64),
+    column("position", ColumnType::UInt64),
+    column("proof_hash", ColumnType::Text),
+    column("prophecy_hash", ColumnType::Text),
+    column("taproot_address", ColumnType::Text),
+    column("derived_key", ColumnType::Text),
+    column("timestamp", ColumnType::UInt64),
+    column("not_before_height", ColumnType::UInt64),
+    column("tempering", ColumnType::Text),
+    column("fee", ColumnType::UInt64),
+];
+
+/// Columns of the `fees` table
+pub const FEE_COLUMNS: &[Column] = &[
+    column("height", ColumnType::UInt64),
+    column("forge_count", ColumnType::UInt64),
+    column("forges_before", ColumnType::UInt64),
+    column("fee_total", ColumnType::UInt64),
+    column("reward_total", ColumnType::UInt64),
+];
+
+/// A cell of an exported row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    UInt64(u64),
+    Text(String),
+    Null,
+}
+
+impl Value {
+    fn hex(bytes: &[u8]) -> Self {
+        Value::Text(hex::encode(bytes))
+    }
+}
+
+/// Destination for the rows of one table
+trait TableWriter {
+    fn write_row(&mut self, row: Vec<Value>) -> Result<()>;
+
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+struct CsvTable(csv::Writer<File>);
+
+impl TableWriter for CsvTable {
+    fn write_row(&mut self, row: Vec<Value>) -> Result<()> {
+        let cells = row.into_iter().map(|value| match value {
+            Value::UInt64(n) => n.to_string(),
+            Value::Text(text) => text,
+            Value::Null => String::new(),
+        });
+        Ok(self.0.write_record(cells)?)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        Ok(self.0.flush()?)
+    }
+}
+
+fn create_table(path: &Path, columns: &'static [Column], format: ExportFormat) -> Result<Box<dyn TableWriter>> {
+    let file = File::create(path).map_err(|e| anyhow!("Failed to create {}: {}", path.display(), e))?;
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(file);
+            writer.write_record(columns.iter().map(|column| column.name))?;
+            Ok(Box::new(CsvTable(writer)))
+        }
+        #[cfg(feature = "parquet")]
+        ExportFormat::Parquet => Ok(Box::new(parquet_writer::ParquetTable::new(file, columns)?)),
+        #[cfg(not(feature = "parquet"))]
+        ExportFormat::Parquet => Err(anyhow!("Parquet export requires the parquet feature")),
+    }
+}
+
+/// What an export wrote
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExportSummary {
+    /// Snapshot tip the export stopped at
+    pub height: u64,
+    pub blocks: u64,
+    pub forges: u64,
+    /// Files written, `blocks`, `forges` then `fees`
+    pub files: Vec<PathBuf>,
+}
+
+/// Export the chain in `store` as of now to `blocks`, `forges` and `fees`
+/// files in `dir`, with forge fees from the chain's `fee_schedule`
+pub fn export_analytics(
+    store: &ChainStore,
+    dir: &Path,
+    format: ExportFormat,
+    fee_schedule: &FeeSchedule,
+) -> Result<ExportSummary> {
+    std::fs::create_dir_all(dir).map_err(|e| anyhow!("Failed to create {}: {}", dir.display(), e))?;
+    let files: Vec<PathBuf> = ["blocks", "forges", "fees"]
+        .iter()
+        .map(|table| dir.join(format!("{}.{}", table, format.extension())))
+        .collect();
+    let mut blocks = create_table(&files[0], BLOCK_COLUMNS, format)?;
+    let mut forges = create_table(&files[1], FORGE_COLUMNS, format)?;
+    let mut fees = create_table(&files[2], FEE_COLUMNS, format)?;
+
+    let snapshot = store.snapshot()?;
+    let height = snapshot.get_height()?;
+    let mut summary = ExportSummary {
+        height,
+        blocks: 0,
+        forges: 0,
+        files: files.clone(),
+    };
+    for entry in snapshot.iter_blocks() {
+        let (block_height, block) = entry?;
+        if block_height > height {
+            break;
+        }
+        blocks.write_row(block_row(&block))?;
+        let forges_before = summary.forges;
+        let mut fee_total = 0u64;
+        for (position, forge) in block.forges.iter().enumerate() {
+            let fee = fee_schedule.forge_fee(forges_before + position as u64);
+            fee_total = fee_total.saturating_add(fee);
+            forges.write_row(vec![
+                Value::UInt64(block_height),
+                Value::UInt64(position as u64),
+                Value::hex(&forge.proof_hash),
+                Value::hex(&prophecy_registry_hash(&forge.prophecy)),
+                Value::Text(forge.taproot_address.clone()),
+                Value::hex(&forge.derived_key),
+                Value::UInt64(forge.timestamp),
+                Value::UInt64(forge.not_before_height),
+                Value::Text(forge.tempering.name().to_string()),
+                Value::UInt64(fee),
+            ])?;
+        }
+        let forge_count = block.forges.len() as u64;
+        fees.write_row(vec![
+            Value::UInt64(block_height),
+            Value::UInt64(forge_count),
+            Value::UInt64(forges_before),
+            Value::UInt64(fee_total),
+            Value::UInt64(forge_count.saturating_mul(FORGE_REWARD)),
+        ])?;
+        summary.blocks += 1;
+        summary.forges += forge_count;
+    }
+
+    blocks.finish()?;
+    forges.finish()?;
+    fees.finish()?;
+    Ok(summary)
+}
+
+fn block_row(block: &Block) -> Vec<Value> {
+    let header = &block.header;
+    vec![
+        Value::UInt64(header.height),
+        Value::hex(&Sha256::digest(header_hash_preimage(header))),
+        Value::hex(&header.prev_block_hash),
+        Value::hex(&header.merkle_root),
+        Value::UInt64(header.version.into()),
+        Value::UInt64(header.timestamp),
+        header.timestamp_millis.map_or(Value::Null, |millis| Value::UInt64(millis.into())),
+        Value::UInt64(header.difficulty.into()),
+        Value::UInt64(header.bits.into()),
+        Value::UInt64(header.nonce),
+        Value::UInt64(block.forges.len() as u64),
+        Value::UInt64(block.encode().len() as u64),
+        header.state_root.map_or(Value::Null, |root| Value::hex(&root)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::MemoryStore;
+    use crate::consensus::{testing, ForgeTransaction};
+
+    fn block(height: u64, forges: usize) -> Block {
+        let forges = (0..forges)
+            .map(|i| ForgeTransaction {
+                prophecy: format!("prophecy {}", i),
+                derived_key: vec![2; 33],
+                taproot_address: format!("bcrt1p{}{}", height, i),
+                proof_hash: [i as u8; 32],
+                timestamp: 1_000 + height,
+                signature: vec![],
+                not_before_height: height,
+                tempering: Default::default(),
+                salt: [0; 32],
+            })
+            .collect();
+        let mut block = testing::block(height, [height as u8; 32], forges);
+        block.header.state_root = (height == 1).then_some([7; 32]);
+        block
+    }
+
+    #[test]
+    fn test_csv_export() {
+        let store = ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap();
+        for (height, forges) in [(0, 0), (1, 2), (2, 1)] {
+            store.put_block(height, &block(height, forges).encode()).unwrap();
+        }
+        store.set_height(1).unwrap();
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let schedule = FeeSchedule::mainnet();
+        let summary = export_analytics(&store, tmp.path(), ExportFormat::Csv, &schedule).unwrap();
+        // Block 2 is above the tip and left out
+        assert_eq!((summary.height, summary.blocks, summary.forges), (1, 2, 2));
+
+        let read = |path: &Path| -> Vec<Vec<String>> {
+            csv::Reader::from_path(path)
+                .unwrap()
+                .records()
+                .map(|record| record.unwrap().iter().map(str::to_string).collect())
+                .collect()
+        };
+        let header = csv::Reader::from_path(&summary.files[0]).unwrap().headers().unwrap().clone();
+        assert_eq!(header.iter().collect::<Vec<_>>(), BLOCK_COLUMNS.iter().map(|c| c.name).collect::<Vec<_>>());
+
+        let blocks = read(&summary.files[0]);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0][0], "0");
+        assert_eq!(blocks[0][6], "", "missing millis are null");
+        assert_eq!(blocks[1][12], hex::encode([7u8; 32]));
+
+        let forges = read(&summary.files[1]);
+        assert_eq!(forges.len(), 2);
+        assert_eq!(forges[1][..2], ["1", "1"]);
+        assert_eq!(forges[1][8], "pbkdf2_sha512");
+        assert_eq!(forges[1][9], schedule.forge_fee(1).to_string());
+
+        let fees = read(&summary.files[2]);
+        let total = schedule.forge_fee(0) + schedule.forge_fee(1);
+        assert_eq!(fees[1], ["1", "2", "0", &total.to_string(), &(2 * FORGE_REWARD).to_string()]);
+    }
+}
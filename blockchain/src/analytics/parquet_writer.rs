@@ -0,0 +1,158 @@
+//! Parquet output for analytics tables
+
+use super::{Column, ColumnType, TableWriter, Value, SCHEMA_VERSION};
+use anyhow::{anyhow, Result};
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use std::fs::File;
+use std::sync::Arc;
+
+/// Rows buffered per row group
+const ROW_GROUP_ROWS: usize = 64 * 1024;
+
+/// Parquet file written one row group at a time
+pub(super) struct ParquetTable {
+    writer: SerializedFileWriter<File>,
+    columns: &'static [Column],
+    rows: Vec<Vec<Value>>,
+}
+
+impl ParquetTable {
+    pub(super) fn new(file: File, columns: &'static [Column]) -> Result<Self> {
+        let fields: String = columns
+            .iter()
+            .map(|column| {
+                let repetition = if column.nullable { "OPTIONAL" } else { "REQUIRED" };
+                let kind = match column.kind {
+                    ColumnType::UInt64 => "INT64 %s (INTEGER(64,false))",
+                    ColumnType::Text => "BYTE_ARRAY %s (STRING)",
+                };
+                format!("{} {};\n", repetition, kind.replace("%s", column.name))
+            })
+            .collect();
+        let schema = parse_message_type(&format!("message excalibur {{\n{}}}", fields))?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_key_value_metadata(Some(vec![KeyValue::new(
+                "excalibur.schema_version".to_string(),
+                SCHEMA_VERSION.to_string(),
+            )]))
+            .build();
+        Ok(Self {
+            writer: SerializedFileWriter::new(file, Arc::new(schema), Arc::new(properties))?,
+            columns,
+            rows: Vec::new(),
+        })
+    }
+
+    fn flush_row_group(&mut self) -> Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let mut row_group = self.writer.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column_writer) = row_group.next_column()? {
+            let column = &self.columns[index];
+            let cells = self.rows.iter().map(|row| &row[index]);
+            let levels: Option<Vec<i16>> =
+                column.nullable.then(|| cells.clone().map(|cell| i16::from(*cell != Value::Null)).collect());
+            match column.kind {
+                ColumnType::UInt64 => {
+                    let values: Vec<i64> = cells
+                        .filter_map(|cell| match cell {
+                            // Stored as the same 64 bits, read back as unsigned
+                            Value::UInt64(n) => Some(*n as i64),
+                            _ => None,
+                        })
+                        .collect();
+                    column_writer.typed::<Int64Type>().write_batch(&values, levels.as_deref(), None)?;
+                }
+                ColumnType::Text => {
+                    let values: Vec<ByteArray> = cells
+                        .filter_map(|cell| match cell {
+                            Value::Text(text) => Some(ByteArray::from(text.as_bytes().to_vec())),
+                            _ => None,
+                        })
+                        .collect();
+                    column_writer.typed::<ByteArrayType>().write_batch(&values, levels.as_deref(), None)?;
+                }
+            }
+            column_writer.close()?;
+            index += 1;
+        }
+        row_group.close()?;
+        self.rows.clear();
+        Ok(())
+    }
+}
+
+impl TableWriter for ParquetTable {
+    fn write_row(&mut self, row: Vec<Value>) -> Result<()> {
+        if row.len() != self.columns.len() {
+            return Err(anyhow!("Row has {} cells for {} columns", row.len(), self.columns.len()));
+        }
+        self.rows.push(row);
+        if self.rows.len() >= ROW_GROUP_ROWS {
+            self.flush_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.flush_row_group()?;
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{BLOCK_COLUMNS, FEE_COLUMNS};
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+
+    #[test]
+    fn test_parquet_round_trip() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("fees.parquet");
+        let mut table = Box::new(ParquetTable::new(File::create(&path).unwrap(), FEE_COLUMNS).unwrap());
+        table.write_row((1..=5).map(Value::UInt64).collect()).unwrap();
+        table.write_row(vec![Value::UInt64(u64::MAX); 5]).unwrap();
+        assert!(table.write_row(vec![Value::UInt64(1)]).is_err());
+        table.finish().unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        let version = metadata.key_value_metadata().unwrap()[0].value.clone();
+        assert_eq!(version.as_deref(), Some("1"));
+        let rows: Vec<_> = reader.get_row_iter(None).unwrap().map(|row| row.unwrap()).collect();
+        assert_eq!(rows.len(), 2);
+        let (name, first) = rows[0].get_column_iter().next().unwrap();
+        assert_eq!((name.as_str(), first), ("height", &Field::ULong(1)));
+        assert_eq!(rows[1].get_column_iter().nth(4).unwrap().1, &Field::ULong(u64::MAX));
+
+        // Nullable text columns
+        let path = tmp.path().join("blocks.parquet");
+        let mut table = Box::new(ParquetTable::new(File::create(&path).unwrap(), BLOCK_COLUMNS).unwrap());
+        let mut row: Vec<Value> = BLOCK_COLUMNS
+            .iter()
+            .map(|column| match column.kind {
+                ColumnType::UInt64 => Value::UInt64(3),
+                ColumnType::Text => Value::Text("ab".to_string()),
+            })
+            .collect();
+        row[12] = Value::Null;
+        table.write_row(row).unwrap();
+        table.finish().unwrap();
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let row = reader.get_row_iter(None).unwrap().next().unwrap().unwrap();
+        let fields: Vec<_> = row.get_column_iter().map(|(_, field)| field.clone()).collect();
+        assert_eq!(fields[1], Field::Str("ab".to_string()));
+        assert_eq!(fields[12], Field::Null);
+    }
+}
@@ -0,0 +1,197 @@
+//! Key and address inspection
+//!
+//! Backs the `key` and `address` CLI commands. Most support questions about
+//! addresses come down to the wrong network, a legacy P2WPKH address where a
+//! P2TR one was expected, or a key that doesn't belong to the wallet; these
+//! helpers decode enough to tell them apart.
+
+use crate::crypto::{is_legacy_forge_address, DerivedAddresses};
+use anyhow::{anyhow, Result};
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey, XOnlyPublicKey};
+use bitcoin::{Address, AddressType, Network, PrivateKey};
+use rand::RngCore;
+
+/// Networks an address is tried against, in order. Testnet and signet share
+/// encodings, so signet addresses report as testnet.
+const INSPECT_NETWORKS: [Network; 3] = [Network::Bitcoin, Network::Testnet, Network::Regtest];
+
+/// Decoded private or public key
+#[derive(Debug, Clone)]
+pub struct KeyInfo {
+    /// Network encoded in a WIF key, otherwise the requested network
+    pub network: Network,
+    /// Secret key, when a private key was given
+    pub secret_key: Option<SecretKey>,
+    pub public_key: PublicKey,
+    pub x_only_key: XOnlyPublicKey,
+    /// Both forge address forms for the key on `network`
+    pub addresses: DerivedAddresses,
+}
+
+impl KeyInfo {
+    fn new(network: Network, secret_key: Option<SecretKey>, public_key: PublicKey) -> Result<Self> {
+        Ok(Self {
+            network,
+            secret_key,
+            public_key,
+            x_only_key: public_key.x_only_public_key().0,
+            addresses: DerivedAddresses::for_key(&public_key, network)?,
+        })
+    }
+
+    /// Secret key in WIF, when a private key was given
+    pub fn wif(&self) -> Option<String> {
+        self.secret_key
+            .map(|secret_key| PrivateKey::new(secret_key, self.network).to_wif())
+    }
+}
+
+/// Generate a random private key for `network`
+pub fn generate_key(network: Network) -> Result<KeyInfo> {
+    let secp = Secp256k1::new();
+    let mut rng = rand::thread_rng();
+    loop {
+        let mut bytes = [0u8; 32];
+        rng.fill_bytes(&mut bytes);
+        // Out-of-range scalars are astronomically rare; just draw again
+        if let Ok(secret_key) = SecretKey::from_slice(&bytes) {
+            return KeyInfo::new(network, Some(secret_key), PublicKey::from_secret_key(&secp, &secret_key));
+        }
+    }
+}
+
+/// Decode a key given as a 32-byte hex secret, a hex public key, or WIF.
+///
+/// WIF keys carry their own network, which overrides `network`. Testnet and
+/// regtest share a WIF prefix, so a testnet WIF stays on regtest if asked.
+pub fn inspect_key(input: &str, network: Network) -> Result<KeyInfo> {
+    let input = input.trim();
+    let secp = Secp256k1::new();
+
+    if let Ok(bytes) = hex::decode(input) {
+        return match bytes.len() {
+            32 => {
+                let secret_key = SecretKey::from_slice(&bytes).map_err(|e| anyhow!("Invalid secret key: {}", e))?;
+                KeyInfo::new(network, Some(secret_key), PublicKey::from_secret_key(&secp, &secret_key))
+            }
+            33 | 65 => {
+                let public_key = PublicKey::from_slice(&bytes).map_err(|e| anyhow!("Invalid public key: {}", e))?;
+                KeyInfo::new(network, None, public_key)
+            }
+            len => Err(anyhow!(
+                "Hex key must be a 32-byte secret or a 33/65-byte public key, got {} bytes",
+                len
+            )),
+        };
+    }
+
+    let private_key = PrivateKey::from_wif(input).map_err(|e| anyhow!("Not a hex or WIF key: {}", e))?;
+    let network = match (private_key.network, network) {
+        (Network::Testnet, Network::Regtest) => Network::Regtest,
+        (wif_network, _) => wif_network,
+    };
+    KeyInfo::new(
+        network,
+        Some(private_key.inner),
+        PublicKey::from_secret_key(&secp, &private_key.inner),
+    )
+}
+
+/// Decoded address
+#[derive(Debug, Clone)]
+pub struct AddressInfo {
+    pub address: String,
+    /// First of mainnet, testnet and regtest the address is valid for
+    pub network: Network,
+    /// Standard output type, if the address has one
+    pub address_type: Option<AddressType>,
+    /// Output key of a P2TR address
+    pub x_only_key: Option<XOnlyPublicKey>,
+    /// Whether the address has the pre-P2TR-fix forge form
+    pub legacy_forge: bool,
+}
+
+impl AddressInfo {
+    /// Which of a key's forge addresses this is, if either
+    pub fn derived_form(&self, addresses: &DerivedAddresses) -> Option<&'static str> {
+        if self.address == addresses.p2tr {
+            Some("P2TR")
+        } else if self.address == addresses.legacy_p2wpkh {
+            Some("legacy P2WPKH")
+        } else {
+            None
+        }
+    }
+}
+
+/// Decode an address on any supported network
+pub fn inspect_address(address: &str) -> Result<AddressInfo> {
+    let address = address.trim();
+    let unchecked: Address<NetworkUnchecked> = address
+        .parse()
+        .map_err(|e| anyhow!("Invalid address {}: {}", address, e))?;
+    let network = INSPECT_NETWORKS
+        .into_iter()
+        .find(|network| unchecked.is_valid_for_network(*network))
+        .ok_or_else(|| anyhow!("Address {} is not valid on any supported network", address))?;
+    let checked = unchecked.assume_checked();
+    let address_type = checked.address_type();
+
+    let x_only_key = match address_type {
+        // OP_1 OP_PUSHBYTES_32 <output key>
+        Some(AddressType::P2tr) => XOnlyPublicKey::from_slice(&checked.script_pubkey().as_bytes()[2..]).ok(),
+        _ => None,
+    };
+
+    Ok(AddressInfo {
+        address: address.to_string(),
+        network,
+        address_type,
+        x_only_key,
+        legacy_forge: is_legacy_forge_address(address),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_and_address_round_trip() {
+        let generated = generate_key(Network::Regtest).unwrap();
+        let secret_hex = hex::encode(generated.secret_key.unwrap().secret_bytes());
+
+        // Hex secret, WIF and public key all decode to the same key
+        let from_hex = inspect_key(&secret_hex, Network::Regtest).unwrap();
+        assert_eq!(from_hex.addresses, generated.addresses);
+        let from_wif = inspect_key(&generated.wif().unwrap(), Network::Regtest).unwrap();
+        assert_eq!(from_wif.network, Network::Regtest);
+        assert_eq!(from_wif.addresses, generated.addresses);
+        let from_wif = inspect_key(&generated.wif().unwrap(), Network::Bitcoin).unwrap();
+        assert_eq!(from_wif.network, Network::Testnet);
+        assert_eq!(from_wif.public_key, generated.public_key);
+        let from_public = inspect_key(&generated.public_key.to_string(), Network::Regtest).unwrap();
+        assert!(from_public.secret_key.is_none());
+        assert_eq!(from_public.addresses, generated.addresses);
+        assert!(inspect_key("abcd", Network::Regtest).is_err());
+
+        let p2tr = inspect_address(&generated.addresses.p2tr).unwrap();
+        assert_eq!(p2tr.network, Network::Regtest);
+        assert_eq!(p2tr.address_type, Some(AddressType::P2tr));
+        assert!(p2tr.x_only_key.is_some());
+        assert!(!p2tr.legacy_forge);
+        assert_eq!(p2tr.derived_form(&generated.addresses), Some("P2TR"));
+
+        let legacy = inspect_address(&generated.addresses.legacy_p2wpkh).unwrap();
+        assert_eq!(legacy.address_type, Some(AddressType::P2wpkh));
+        assert!(legacy.legacy_forge);
+        assert_eq!(legacy.derived_form(&generated.addresses), Some("legacy P2WPKH"));
+
+        let other = generate_key(Network::Bitcoin).unwrap();
+        let mainnet = inspect_address(&other.addresses.p2tr).unwrap();
+        assert_eq!(mainnet.network, Network::Bitcoin);
+        assert_eq!(mainnet.derived_form(&generated.addresses), None);
+        assert!(inspect_address("not-an-address").is_err());
+    }
+}
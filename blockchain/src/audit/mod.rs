@@ -0,0 +1,116 @@
+//! Address derivation audit
+//!
+//! Earlier releases emitted P2WPKH addresses under the Taproot name. This
+//! module derives both forms for a prophecy and finds chain data that still
+//! references the legacy form, so operators can plan the migration before
+//! `NetworkParams::p2tr_activation_height`.
+
+use crate::chain::ChainStore;
+use crate::crypto::{is_legacy_forge_address, proof_of_forge, DerivedAddresses};
+use anyhow::Result;
+use bitcoin::Network;
+use serde::{Deserialize, Serialize};
+
+mod inspect;
+
+pub use inspect::{generate_key, inspect_address, inspect_key, AddressInfo, KeyInfo};
+
+/// A forge on chain that pays to a legacy P2WPKH address
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LegacyForgeReference {
+    pub height: u64,
+    pub proof_hash: [u8; 32],
+    pub address: String,
+}
+
+/// Result of auditing one prophecy against the chain
+#[derive(Debug, Clone)]
+pub struct AddressAudit {
+    pub addresses: DerivedAddresses,
+    pub references: Vec<LegacyForgeReference>,
+}
+
+/// Derive both address forms for a prophecy
+pub fn derive_addresses(prophecy: &[String], network: Network) -> Result<DerivedAddresses> {
+    let result = proof_of_forge(prophecy, None, network)?;
+    DerivedAddresses::for_seed(&result.final_seed, network)
+}
+
+/// Scan every stored block for forges paying to legacy addresses.
+///
+/// When `only` is set, just forges paying to that exact address are reported.
+pub fn scan_legacy_forges(store: &ChainStore, only: Option<&str>) -> Result<Vec<LegacyForgeReference>> {
+    let mut references = Vec::new();
+    let tip = store.get_height()?;
+
+    for height in 0..=tip {
+        let Some(block) = store.load_block(height)? else {
+            continue;
+        };
+        for forge in &block.forges {
+            let matches = match only {
+                Some(address) => forge.taproot_address == address,
+                None => is_legacy_forge_address(&forge.taproot_address),
+            };
+            if matches {
+                references.push(LegacyForgeReference {
+                    height,
+                    proof_hash: forge.proof_hash,
+                    address: forge.taproot_address.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(references)
+}
+
+/// Derive both addresses for a prophecy and flag chain references to the
+/// legacy one
+pub fn audit_prophecy(store: &ChainStore, prophecy: &[String], network: Network) -> Result<AddressAudit> {
+    let addresses = derive_addresses(prophecy, network)?;
+    let references = scan_legacy_forges(store, Some(&addresses.legacy_p2wpkh))?;
+    Ok(AddressAudit { addresses, references })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::{testing, ForgeTransaction};
+    use crate::crypto::CANONICAL_PROPHECY;
+    use tempfile::TempDir;
+
+    fn forge_paying(address: &str, proof_byte: u8) -> ForgeTransaction {
+        ForgeTransaction {
+            prophecy: CANONICAL_PROPHECY.join(" "),
+            derived_key: vec![],
+            taproot_address: address.to_string(),
+            proof_hash: [proof_byte; 32],
+            timestamp: 1000,
+            signature: vec![],
+            not_before_height: 0,
+            tempering: Default::default(),
+            salt: [0; 32],
+        }
+    }
+
+    #[test]
+    fn test_audit_flags_legacy_references() {
+        let tmp = TempDir::new().unwrap();
+        let store = ChainStore::new(tmp.path()).unwrap();
+
+        let prophecy: Vec<String> = CANONICAL_PROPHECY.iter().map(|s| s.to_string()).collect();
+        let addresses = derive_addresses(&prophecy, Network::Regtest).unwrap();
+        assert!(addresses.legacy_p2wpkh.starts_with("bcrt1q"));
+        assert!(addresses.p2tr.starts_with("bcrt1p"));
+
+        let forges = vec![forge_paying(&addresses.legacy_p2wpkh, 1), forge_paying(&addresses.p2tr, 2)];
+        let block = testing::block(0, [0u8; 32], forges);
+        store.put_block(0, &block.encode()).unwrap();
+
+        let audit = audit_prophecy(&store, &prophecy, Network::Regtest).unwrap();
+        assert_eq!(audit.references.len(), 1);
+        assert_eq!(audit.references[0].proof_hash, [1u8; 32]);
+        assert_eq!(scan_legacy_forges(&store, None).unwrap().len(), 1);
+    }
+}
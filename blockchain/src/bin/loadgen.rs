@@ -0,0 +1,87 @@
+//! Excalibur EXS load generator
+//!
+//! Sends synthetic forges or blocks to a node and reports acceptance
+//! latency percentiles and failure causes. See `excalibur_blockchain::loadgen`.
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use excalibur_blockchain::loadgen::{self, LoadOptions, RpcClient, Transport, Workload};
+use excalibur_blockchain::params::NetworkParams;
+use libp2p::Multiaddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(name = "excalibur-loadgen")]
+#[command(about = "Synthetic traffic generator for load testing an Excalibur node", long_about = None)]
+struct Cli {
+    /// Network of the target node (mainnet, testnet, regtest)
+    #[arg(short, long, default_value = "regtest")]
+    network: String,
+
+    /// Traffic to send (canonical, mismatched, blocks)
+    #[arg(short, long, default_value = "mismatched")]
+    workload: Workload,
+
+    /// Items sent per second
+    #[arg(long, default_value_t = 10.0)]
+    rate: f64,
+
+    /// Items sent in total
+    #[arg(long, default_value_t = 100)]
+    count: usize,
+
+    /// host:port of the target's HTTP RPC server (default: localhost on the
+    /// network's RPC port)
+    #[arg(long)]
+    rpcconnect: Option<String>,
+
+    /// RPC user name
+    #[arg(long, default_value = "")]
+    rpcuser: String,
+
+    /// RPC password
+    #[arg(long)]
+    rpcpassword: Option<String>,
+
+    /// Cookie file to authenticate with instead of a password
+    #[arg(long)]
+    rpccookiefile: Option<PathBuf>,
+
+    /// Send over gossip from a peer connected to this address instead of RPC
+    #[arg(long)]
+    gossip: Option<Multiaddr>,
+
+    /// Seconds a gossiped item may go unanswered before it counts as failed
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let cli = Cli::parse();
+    let params = NetworkParams::from_name(&cli.network).ok_or_else(|| anyhow!("Unknown network {}", cli.network))?;
+    let authority = cli
+        .rpcconnect
+        .unwrap_or_else(|| format!("127.0.0.1:{}", params.default_rpc_port));
+    let client = match (&cli.rpccookiefile, &cli.rpcpassword) {
+        (Some(cookie), _) => RpcClient::with_cookie(&authority, cookie)?,
+        (None, Some(password)) => RpcClient::new(&authority, Some((&cli.rpcuser, password))),
+        (None, None) => RpcClient::new(&authority, None),
+    };
+    let options = LoadOptions {
+        workload: cli.workload,
+        transport: cli.gossip.map_or(Transport::Rpc, Transport::Gossip),
+        rate: cli.rate,
+        count: cli.count,
+        timeout: Duration::from_secs(cli.timeout),
+        params,
+    };
+
+    let report = loadgen::run(options, Arc::new(client)).await?;
+    println!("{}", serde_json::to_string_pretty(&report.to_json())?);
+    Ok(())
+}
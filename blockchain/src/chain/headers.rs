@@ -0,0 +1,266 @@
+//! Header spam protection
+//!
+//! Headers are cheap to produce at minimum difficulty, so a peer could fill
+//! the header index with branches no honest chain will ever reach. A header
+//! that extends the current tip is always indexed, but one on a competing
+//! branch is only indexed once its branch has cumulative work within
+//! `work_window` tip-difficulty blocks of the tip. Headers whose parent is
+//! unknown are held in memory, at most `max_unconnected` per peer, until
+//! the parent arrives.
+
+use super::{ChainStore, HeaderIndexEntry};
+use crate::consensus::{header_work, BlockHeader};
+use anyhow::{anyhow, Result};
+use bitcoin::pow::Work;
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Default depth, in tip-difficulty blocks, a competing branch may trail the tip
+pub const DEFAULT_HEADER_WORK_WINDOW: u64 = 144;
+
+/// Default unconnected headers held per peer
+pub const DEFAULT_MAX_UNCONNECTED_HEADERS: usize = 256;
+
+/// Filters headers before they are written to the header index
+pub struct HeaderGuard {
+    work_window: u64,
+    max_unconnected: usize,
+    /// Headers waiting for their parent, by peer and hash
+    unconnected: Mutex<HashMap<PeerId, HashMap<[u8; 32], BlockHeader>>>,
+}
+
+impl Default for HeaderGuard {
+    fn default() -> Self {
+        Self::new(DEFAULT_HEADER_WORK_WINDOW, DEFAULT_MAX_UNCONNECTED_HEADERS)
+    }
+}
+
+impl HeaderGuard {
+    /// Create a guard admitting competing branches within `work_window`
+    /// blocks of tip work and holding `max_unconnected` headers per peer
+    pub fn new(work_window: u64, max_unconnected: usize) -> Self {
+        Self {
+            work_window,
+            max_unconnected,
+            unconnected: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Index a header relayed by `peer`, along with any held headers it
+    /// connects. Returns the newly indexed entries, which are empty if the
+    /// header was already known or is being held for its parent.
+    ///
+    /// Errors mean the peer sent a header that must not be stored: an
+    /// inconsistent height, a branch with too little work, or more
+    /// unconnected headers than allowed.
+    pub fn accept(
+        &self,
+        store: &ChainStore,
+        peer: PeerId,
+        hash: &[u8; 32],
+        header: &BlockHeader,
+    ) -> Result<Vec<HeaderIndexEntry>> {
+        if store.get_header_index(hash)?.is_some() {
+            return Ok(Vec::new());
+        }
+
+        let parent = if header.height == 0 {
+            None
+        } else {
+            match store.get_header_index(&header.prev_block_hash)? {
+                Some(parent) => Some(parent),
+                None => {
+                    self.hold(peer, hash, header)?;
+                    return Ok(Vec::new());
+                }
+            }
+        };
+
+        let tip = match store.get_best_block()? {
+            Some(tip_hash) => store.get_header_index(&tip_hash)?,
+            None => None,
+        };
+        let mut indexed = vec![self.connect(store, tip.as_ref(), parent.as_ref(), hash, header)?];
+
+        // Index held headers that now connect, from any peer
+        let mut next = 0;
+        while next < indexed.len() {
+            let parent = indexed[next].clone();
+            for (child_hash, child) in self.take_children(&parent.hash) {
+                match self.connect(store, tip.as_ref(), Some(&parent), &child_hash, &child) {
+                    Ok(entry) => indexed.push(entry),
+                    Err(e) => tracing::debug!("Dropping held header {}: {:#}", hex::encode(child_hash), e),
+                }
+            }
+            next += 1;
+        }
+        Ok(indexed)
+    }
+
+    /// Check a header against its indexed parent and the tip, then index it
+    fn connect(
+        &self,
+        store: &ChainStore,
+        tip: Option<&HeaderIndexEntry>,
+        parent: Option<&HeaderIndexEntry>,
+        hash: &[u8; 32],
+        header: &BlockHeader,
+    ) -> Result<HeaderIndexEntry> {
+        let parent_work = match parent {
+            Some(parent) => {
+                if header.height != parent.height + 1 {
+                    return Err(anyhow!(
+                        "Header {} claims height {} on parent at height {}",
+                        hex::encode(hash),
+                        header.height,
+                        parent.height
+                    ));
+                }
+                parent.chainwork()
+            }
+            None => Work::from_be_bytes([0u8; 32]),
+        };
+
+        if let Some(tip) = tip {
+            let extends_tip = parent.is_some_and(|parent| parent.hash == tip.hash);
+            let chainwork = parent_work + header_work(header.bits);
+            if !extends_tip && chainwork + self.window_work(tip) < tip.chainwork() {
+                return Err(anyhow!(
+                    "Header {} is on a branch with too little work (more than {} blocks behind the tip)",
+                    hex::encode(hash),
+                    self.work_window
+                ));
+            }
+        }
+        store.index_header(hash, header)
+    }
+
+    /// Work of `work_window` blocks at the tip's difficulty
+    fn window_work(&self, tip: &HeaderIndexEntry) -> Work {
+        let block_work = header_work(tip.bits);
+        (0..self.work_window).fold(Work::from_be_bytes([0u8; 32]), |total, _| total + block_work)
+    }
+
+    fn hold(&self, peer: PeerId, hash: &[u8; 32], header: &BlockHeader) -> Result<()> {
+        let mut unconnected = self.unconnected.lock().unwrap();
+        let held = unconnected.entry(peer).or_default();
+        if !held.contains_key(hash) && held.len() >= self.max_unconnected {
+            return Err(anyhow!(
+                "Peer {} exceeded {} unconnected headers",
+                peer,
+                self.max_unconnected
+            ));
+        }
+        held.insert(*hash, header.clone());
+        Ok(())
+    }
+
+    fn take_children(&self, parent_hash: &[u8; 32]) -> Vec<([u8; 32], BlockHeader)> {
+        let mut unconnected = self.unconnected.lock().unwrap();
+        let mut children = Vec::new();
+        for held in unconnected.values_mut() {
+            let hashes: Vec<[u8; 32]> = held
+                .iter()
+                .filter(|(_, header)| header.prev_block_hash == *parent_hash)
+                .map(|(hash, _)| *hash)
+                .collect();
+            for hash in hashes {
+                if let Some(header) = held.remove(&hash) {
+                    children.push((hash, header));
+                }
+            }
+        }
+        unconnected.retain(|_, held| !held.is_empty());
+        children
+    }
+
+    /// Every held header with the peer it came from and its hash
+    pub fn unconnected_headers(&self) -> Vec<(PeerId, [u8; 32], BlockHeader)> {
+        let unconnected = self.unconnected.lock().unwrap();
+        unconnected
+            .iter()
+            .flat_map(|(peer, held)| held.iter().map(|(hash, header)| (*peer, *hash, header.clone())))
+            .collect()
+    }
+
+    /// Headers held for their parent on behalf of `peer`
+    pub fn unconnected_count(&self, peer: &PeerId) -> usize {
+        self.unconnected.lock().unwrap().get(peer).map_or(0, HashMap::len)
+    }
+
+    /// Drop the headers held for a disconnected peer
+    pub fn remove_peer(&self, peer: &PeerId) {
+        self.unconnected.lock().unwrap().remove(peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::testing::header;
+    use tempfile::TempDir;
+
+    fn hash(tag: u8, height: u64) -> [u8; 32] {
+        let mut hash = [tag; 32];
+        hash[..8].copy_from_slice(&height.to_be_bytes());
+        hash
+    }
+
+    /// Index a main chain of `length` headers and make its tip the best block
+    fn main_chain(store: &ChainStore, guard: &HeaderGuard, length: u64) {
+        let peer = PeerId::random();
+        let mut prev = [0u8; 32];
+        for height in 0..length {
+            let hash = hash(1, height);
+            guard.accept(store, peer, &hash, &header(height, prev)).unwrap();
+            store.set_best_block(&hash).unwrap();
+            prev = hash;
+        }
+    }
+
+    #[test]
+    fn test_low_work_branch_rejected() {
+        let tmp = TempDir::new().unwrap();
+        let store = ChainStore::new(tmp.path()).unwrap();
+        let guard = HeaderGuard::new(2, 8);
+        main_chain(&store, &guard, 6);
+        let peer = PeerId::random();
+
+        // Forking at height 3 trails the tip by two blocks: allowed
+        assert_eq!(guard.accept(&store, peer, &hash(2, 4), &header(4, hash(1, 3))).unwrap().len(), 1);
+        // Forking at genesis trails by four: rejected and not stored
+        assert!(guard.accept(&store, peer, &hash(3, 1), &header(1, hash(1, 0))).is_err());
+        assert!(store.get_header_index(&hash(3, 1)).unwrap().is_none());
+        // Heights must follow the parent
+        assert!(guard.accept(&store, peer, &hash(4, 9), &header(9, hash(1, 5))).is_err());
+    }
+
+    #[test]
+    fn test_unconnected_headers_held_per_peer() {
+        let tmp = TempDir::new().unwrap();
+        let store = ChainStore::new(tmp.path()).unwrap();
+        let guard = HeaderGuard::new(2, 2);
+        main_chain(&store, &guard, 3);
+        let spammer = PeerId::random();
+        let honest = PeerId::random();
+
+        // Children arrive before their parent
+        assert!(guard.accept(&store, honest, &hash(1, 5), &header(5, hash(1, 4))).unwrap().is_empty());
+        assert!(guard.accept(&store, honest, &hash(1, 4), &header(4, hash(1, 3))).unwrap().is_empty());
+        assert_eq!(guard.unconnected_count(&honest), 2);
+
+        for n in 0..2 {
+            guard.accept(&store, spammer, &[0xee - n; 32], &header(50, [0xdd; 32])).unwrap();
+        }
+        assert!(guard.accept(&store, spammer, &[0xaa; 32], &header(50, [0xdd; 32])).is_err());
+        guard.remove_peer(&spammer);
+        assert_eq!(guard.unconnected_count(&spammer), 0);
+
+        // The parent connects the whole held chain
+        let indexed = guard.accept(&store, spammer, &hash(1, 3), &header(3, hash(1, 2))).unwrap();
+        let heights: Vec<u64> = indexed.iter().map(|entry| entry.height).collect();
+        assert_eq!(heights, vec![3, 4, 5]);
+        assert_eq!(guard.unconnected_count(&honest), 0);
+    }
+}
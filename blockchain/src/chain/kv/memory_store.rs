@@ -0,0 +1,92 @@
+//! In-memory backend
+
+use super::{BatchOp, KvIter, KvSnapshot, KvStore, WriteBatch};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+/// Volatile store for tests, tools and targets without a filesystem
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    map: RwLock<BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl MemoryStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl KvStore for MemoryStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.map.read().unwrap().get(key).cloned())
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.map.write().unwrap().insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        self.map.write().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let mut map = self.map.write().unwrap();
+        for op in batch.into_ops() {
+            match op {
+                BatchOp::Put(key, value) => {
+                    map.insert(key, value);
+                }
+                BatchOp::Delete(key) => {
+                    map.remove(&key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn range(&self, start: &[u8], end: &[u8], reverse: bool) -> KvIter<'_> {
+        // Copy out the range so the lock isn't held while iterating
+        let entries = copy_range(&self.map.read().unwrap(), start, end, reverse);
+        Box::new(entries.into_iter())
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn KvSnapshot + '_>> {
+        Ok(Box::new(MemorySnapshot(self.map.read().unwrap().clone())))
+    }
+}
+
+/// Copy of the whole map
+struct MemorySnapshot(BTreeMap<Vec<u8>, Vec<u8>>);
+
+impl KvSnapshot for MemorySnapshot {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.0.get(key).cloned())
+    }
+
+    fn range(&self, start: &[u8], end: &[u8], reverse: bool) -> KvIter<'_> {
+        Box::new(copy_range(&self.0, start, end, reverse).into_iter())
+    }
+}
+
+fn copy_range(
+    map: &BTreeMap<Vec<u8>, Vec<u8>>,
+    start: &[u8],
+    end: &[u8],
+    reverse: bool,
+) -> Vec<Result<(Vec<u8>, Vec<u8>)>> {
+    if start >= end {
+        return Vec::new();
+    }
+    let mut entries: Vec<_> = map
+        .range(start.to_vec()..end.to_vec())
+        .map(|(key, value)| Ok((key.clone(), value.clone())))
+        .collect();
+    if reverse {
+        entries.reverse();
+    }
+    entries
+}
@@ -0,0 +1,175 @@
+//! Key-value storage backends for the chain store
+//!
+//! `ChainStore` only needs ordered byte keys, atomic batches and bounded
+//! range scans, so it talks to storage through the `KvStore` trait. RocksDB
+//! (feature `rocksdb`, on by default) is the production backend; redb
+//! (feature `redb`) is a pure-Rust alternative for targets RocksDB cannot
+//! be built for (ARM/musl cross builds), and `MemoryStore` works anywhere,
+//! including WASM.
+
+mod memory_store;
+#[cfg(feature = "redb")]
+mod redb_store;
+#[cfg(feature = "rocksdb")]
+mod rocks_store;
+
+pub use memory_store::MemoryStore;
+#[cfg(feature = "redb")]
+pub use redb_store::RedbStore;
+#[cfg(feature = "rocksdb")]
+pub use rocks_store::RocksStore;
+
+use anyhow::Result;
+
+/// Entries yielded by a range scan, in key order
+pub type KvIter<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>;
+
+/// Ordered byte key-value store
+pub trait KvStore: Send + Sync {
+    /// Value stored under `key`
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Store `value` under `key`, replacing any previous value
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()>;
+
+    /// Remove `key`; removing a missing key is not an error
+    fn delete(&self, key: &[u8]) -> Result<()>;
+
+    /// Apply every operation in `batch` atomically, in order
+    fn write(&self, batch: WriteBatch) -> Result<()>;
+
+    /// Entries with keys in `start..end`, ascending or (with `reverse`)
+    /// descending
+    fn range(&self, start: &[u8], end: &[u8], reverse: bool) -> KvIter<'_>;
+
+    /// Point-in-time view that later writes don't change
+    fn snapshot(&self) -> Result<Box<dyn KvSnapshot + '_>>;
+
+    /// Reclaim space after large deletions, if the backend supports it
+    fn compact(&self) {}
+}
+
+/// Consistent read-only view of a `KvStore`, for long scans while the
+/// store keeps being written
+pub trait KvSnapshot {
+    /// Value stored under `key` when the snapshot was taken
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Entries with keys in `start..end`, as `KvStore::range`
+    fn range(&self, start: &[u8], end: &[u8], reverse: bool) -> KvIter<'_>;
+}
+
+/// Write operation in a `WriteBatch`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOp {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+/// Operations applied atomically by `KvStore::write`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    /// Queue storing `value` under `key`
+    pub fn put<K: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, key: K, value: V) {
+        self.ops.push(BatchOp::Put(key.as_ref().to_vec(), value.as_ref().to_vec()));
+    }
+
+    /// Queue removing `key`
+    pub fn delete<K: AsRef<[u8]>>(&mut self, key: K) {
+        self.ops.push(BatchOp::Delete(key.as_ref().to_vec()));
+    }
+
+    /// Number of queued operations
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Whether no operations are queued
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Queued operations, in order
+    pub fn into_ops(self) -> Vec<BatchOp> {
+        self.ops
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Behaviour every backend must share
+    fn conformance(store: &dyn KvStore) {
+        assert_eq!(store.get(b"a").unwrap(), None);
+        store.put(b"a", b"1").unwrap();
+        store.put(b"a", b"2").unwrap();
+        assert_eq!(store.get(b"a").unwrap(), Some(b"2".to_vec()));
+        store.delete(b"a").unwrap();
+        store.delete(b"missing").unwrap();
+        assert_eq!(store.get(b"a").unwrap(), None);
+
+        // Later operations in a batch win
+        let mut batch = WriteBatch::default();
+        for key in [&b"k\x00"[..], b"k\x01", b"k\x02", b"k\xff", b"l"] {
+            batch.put(key, key);
+        }
+        batch.put(b"gone", b"x");
+        batch.delete(b"gone");
+        store.write(batch).unwrap();
+        assert_eq!(store.get(b"gone").unwrap(), None);
+
+        let keys = |reverse| -> Vec<Vec<u8>> {
+            store
+                .range(b"k\x01", b"l", reverse)
+                .map(|entry| entry.unwrap().0)
+                .collect()
+        };
+        let forward = vec![b"k\x01".to_vec(), b"k\x02".to_vec(), b"k\xff".to_vec()];
+        assert_eq!(keys(false), forward);
+        assert_eq!(keys(true), forward.into_iter().rev().collect::<Vec<_>>());
+        assert_eq!(store.range(b"l", b"l", false).count(), 0);
+
+        let (key, value) = store.range(b"l", b"m", false).next().unwrap().unwrap();
+        assert_eq!((key.as_slice(), value.as_slice()), (&b"l"[..], &b"l"[..]));
+
+        // Snapshots don't see later writes
+        let snapshot = store.snapshot().unwrap();
+        store.put(b"k", b"new").unwrap();
+        store.delete(b"l").unwrap();
+        assert_eq!(snapshot.get(b"l").unwrap(), Some(b"l".to_vec()));
+        assert_eq!(snapshot.get(b"k\x03").unwrap(), None);
+        let keys: Vec<Vec<u8>> = snapshot.range(b"k", b"m", true).map(|entry| entry.unwrap().0).collect();
+        assert_eq!(keys, [&b"l"[..], b"k\xff", b"k\x02", b"k\x01", b"k\x00"]);
+        drop(snapshot);
+        store.put(b"l", b"l").unwrap();
+        store.compact();
+    }
+
+    #[test]
+    fn test_memory_store_conformance() {
+        conformance(&MemoryStore::new());
+    }
+
+    #[cfg(feature = "rocksdb")]
+    #[test]
+    fn test_rocks_store_conformance() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        conformance(&RocksStore::open(tmp.path()).unwrap());
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn test_redb_store_conformance() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        conformance(&RedbStore::open(tmp.path()).unwrap());
+
+        // Data survives reopening
+        let store = RedbStore::open(tmp.path()).unwrap();
+        assert_eq!(store.get(b"l").unwrap(), Some(b"l".to_vec()));
+    }
+}
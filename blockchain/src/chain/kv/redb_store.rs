@@ -0,0 +1,126 @@
+//! redb backend (pure Rust)
+
+use super::{BatchOp, KvIter, KvSnapshot, KvStore, WriteBatch};
+use anyhow::{Context, Result};
+use redb::{Database, ReadOnlyTable, TableDefinition};
+use std::path::Path;
+
+const TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("chain");
+
+/// Database file name inside the chain directory
+const DB_FILE: &str = "chain.redb";
+
+/// redb database file inside a chain directory
+pub struct RedbStore {
+    db: Database,
+}
+
+impl RedbStore {
+    /// Open or create the database in directory `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        std::fs::create_dir_all(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        let db = Database::create(path.join(DB_FILE))?;
+
+        // Create the table up front so readers never see it missing
+        let txn = db.begin_write()?;
+        txn.open_table(TABLE)?;
+        txn.commit()?;
+        Ok(Self { db })
+    }
+
+    fn scan(&self, start: &[u8], end: &[u8], reverse: bool) -> Result<KvIter<'_>> {
+        scan_table(&self.db.begin_read()?.open_table(TABLE)?, start, end, reverse)
+    }
+}
+
+impl KvStore for RedbStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let table = self.db.begin_read()?.open_table(TABLE)?;
+        Ok(table.get(key)?.map(|value| value.value().to_vec()))
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        batch.put(key, value);
+        self.write(batch)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        batch.delete(key);
+        self.write(batch)
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut table = txn.open_table(TABLE)?;
+            for op in batch.into_ops() {
+                match op {
+                    BatchOp::Put(key, value) => {
+                        table.insert(key.as_slice(), value.as_slice())?;
+                    }
+                    BatchOp::Delete(key) => {
+                        table.remove(key.as_slice())?;
+                    }
+                }
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn range(&self, start: &[u8], end: &[u8], reverse: bool) -> KvIter<'_> {
+        if start >= end {
+            return Box::new(std::iter::empty());
+        }
+        match self.scan(start, end, reverse) {
+            Ok(entries) => entries,
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn KvSnapshot + '_>> {
+        Ok(Box::new(RedbSnapshot(self.db.begin_read()?.open_table(TABLE)?)))
+    }
+}
+
+/// Table opened in a read transaction, which sees the database as of its
+/// start
+struct RedbSnapshot(ReadOnlyTable<&'static [u8], &'static [u8]>);
+
+impl KvSnapshot for RedbSnapshot {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.0.get(key)?.map(|value| value.value().to_vec()))
+    }
+
+    fn range(&self, start: &[u8], end: &[u8], reverse: bool) -> KvIter<'_> {
+        if start >= end {
+            return Box::new(std::iter::empty());
+        }
+        match scan_table(&self.0, start, end, reverse) {
+            Ok(entries) => entries,
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+}
+
+fn scan_table<'a>(
+    table: &ReadOnlyTable<&'static [u8], &'static [u8]>,
+    start: &[u8],
+    end: &[u8],
+    reverse: bool,
+) -> Result<KvIter<'a>> {
+    let range = table.range::<&[u8]>(start..end)?;
+    let entries = range.map(|entry| {
+        let (key, value) = entry?;
+        Ok((key.value().to_vec(), value.value().to_vec()))
+    });
+    if reverse {
+        Ok(Box::new(entries.rev()))
+    } else {
+        Ok(Box::new(entries))
+    }
+}
@@ -0,0 +1,102 @@
+//! RocksDB backend
+
+use super::{BatchOp, KvIter, KvSnapshot, KvStore, WriteBatch};
+use anyhow::Result;
+use rocksdb::{IteratorMode, Options, ReadOptions, Snapshot, DB};
+use std::path::Path;
+
+/// RocksDB database directory
+pub struct RocksStore {
+    db: DB,
+}
+
+impl RocksStore {
+    /// Open or create the database in `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.set_compression_type(rocksdb::DBCompressionType::Lz4);
+        opts.set_max_open_files(1000);
+        opts.set_keep_log_file_num(10);
+        opts.set_max_background_jobs(4);
+
+        Ok(Self {
+            db: DB::open(&opts, path)?,
+        })
+    }
+}
+
+impl KvStore for RocksStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get(key)?)
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        Ok(self.db.put(key, value)?)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        Ok(self.db.delete(key)?)
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<()> {
+        let mut rocks_batch = rocksdb::WriteBatch::default();
+        for op in batch.into_ops() {
+            match op {
+                BatchOp::Put(key, value) => rocks_batch.put(key, value),
+                BatchOp::Delete(key) => rocks_batch.delete(key),
+            }
+        }
+        Ok(self.db.write(rocks_batch)?)
+    }
+
+    fn range(&self, start: &[u8], end: &[u8], reverse: bool) -> KvIter<'_> {
+        if start >= end {
+            return Box::new(std::iter::empty());
+        }
+        let (mode, opts) = bounded_scan(start, end, reverse);
+        Box::new(
+            self.db
+                .iterator_opt(mode, opts)
+                .map(|entry| entry.map(|(key, value)| (key.into_vec(), value.into_vec())).map_err(Into::into)),
+        )
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn KvSnapshot + '_>> {
+        Ok(Box::new(RocksSnapshot(self.db.snapshot())))
+    }
+
+    fn compact(&self) {
+        self.db.compact_range::<&[u8], &[u8]>(None, None);
+    }
+}
+
+/// RocksDB snapshot, pinning the database state until dropped
+struct RocksSnapshot<'a>(Snapshot<'a>);
+
+impl KvSnapshot for RocksSnapshot<'_> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.0.get(key)?)
+    }
+
+    fn range(&self, start: &[u8], end: &[u8], reverse: bool) -> KvIter<'_> {
+        if start >= end {
+            return Box::new(std::iter::empty());
+        }
+        let (mode, opts) = bounded_scan(start, end, reverse);
+        Box::new(
+            self.0
+                .iterator_opt(mode, opts)
+                .map(|entry| entry.map(|(key, value)| (key.into_vec(), value.into_vec())).map_err(Into::into)),
+        )
+    }
+}
+
+/// Iterator mode and options bounded so RocksDB never reads past the range
+fn bounded_scan(start: &[u8], end: &[u8], reverse: bool) -> (IteratorMode<'static>, ReadOptions) {
+    let mut opts = ReadOptions::default();
+    opts.set_iterate_lower_bound(start);
+    opts.set_iterate_upper_bound(end);
+    let mode = if reverse { IteratorMode::End } else { IteratorMode::Start };
+    (mode, opts)
+}
@@ -0,0 +1,179 @@
+//! Block locators for fork-point negotiation
+//!
+//! A locator lists main-chain block hashes from the tip back to genesis: the
+//! first `LOCATOR_DENSE_ENTRIES` one block apart, then doubling the step with
+//! each entry, so a chain of n blocks needs O(log n) entries. The responder
+//! takes the first entry that is also on its own main chain as the fork point
+//! and answers with the headers after it, so two nodes find their common
+//! ancestor in one round trip however far their chains have diverged.
+
+use super::ChainStore;
+use crate::codec::header_hash_preimage;
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+
+/// Locator entries taken one block apart before the step starts doubling
+pub const LOCATOR_DENSE_ENTRIES: usize = 10;
+
+/// Entries a received locator is cut to. Covers a chain of 2^90 blocks, and
+/// bounds the lookups a peer can make us do per request.
+pub const MAX_LOCATOR_ENTRIES: usize = 101;
+
+/// Main-chain block hashes, tip first, exponentially sparser towards genesis
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockLocator {
+    hashes: Vec<[u8; 32]>,
+}
+
+impl BlockLocator {
+    /// Locator of the given hashes, tip first (e.g. one received from a peer)
+    pub fn new(mut hashes: Vec<[u8; 32]>) -> Self {
+        hashes.truncate(MAX_LOCATOR_ENTRIES);
+        Self { hashes }
+    }
+
+    /// Heights a locator for a chain with tip `tip` samples, ending at genesis
+    pub fn heights(tip: u64) -> Vec<u64> {
+        let mut heights = Vec::new();
+        let mut height = tip;
+        let mut step = 1;
+        loop {
+            heights.push(height);
+            if height == 0 {
+                break;
+            }
+            if heights.len() >= LOCATOR_DENSE_ENTRIES {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+        heights
+    }
+
+    /// Locator of the store's main chain. Empty for a store without blocks;
+    /// stops at the first missing block, so a chain loaded from a snapshot
+    /// is sampled down to the snapshot height.
+    pub fn from_store(store: &ChainStore) -> Result<Self> {
+        if store.get_best_block()?.is_none() {
+            return Ok(Self::default());
+        }
+        let mut hashes = Vec::new();
+        for height in Self::heights(store.get_height()?) {
+            match store.main_chain_hash(height)? {
+                Some(hash) => hashes.push(hash),
+                None => break,
+            }
+        }
+        Ok(Self { hashes })
+    }
+
+    /// Entries, tip first
+    pub fn hashes(&self) -> &[[u8; 32]] {
+        &self.hashes
+    }
+
+    /// Entries, tip first, for sending in a headers request
+    pub fn into_hashes(self) -> Vec<[u8; 32]> {
+        self.hashes
+    }
+
+    /// Height and hash of the first entry on the store's main chain: the
+    /// last block both chains share. `None` if no entry is, in which case
+    /// the chains share nothing above genesis.
+    pub fn find_fork_point(&self, store: &ChainStore) -> Result<Option<(u64, [u8; 32])>> {
+        for hash in &self.hashes {
+            let Some(height) = store.get_block_height_by_hash(hash)? else {
+                continue;
+            };
+            // A hash from a branch we reorged away from still maps to a height
+            if store.main_chain_hash(height)? == Some(*hash) {
+                return Ok(Some((height, *hash)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl ChainStore {
+    /// Hash of the main-chain block stored at `height`
+    pub fn main_chain_hash(&self, height: u64) -> Result<Option<[u8; 32]>> {
+        Ok(self
+            .load_block(height)?
+            .map(|block| Sha256::digest(header_hash_preimage(&block.header)).into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::MemoryStore;
+    use crate::consensus::{testing, Block};
+
+    fn block(height: u64, prev_block_hash: [u8; 32], nonce: u64) -> Block {
+        let mut block = testing::block(height, prev_block_hash, vec![]);
+        block.header.nonce = nonce;
+        block
+    }
+
+    /// Store a chain of `length` blocks; returns their hashes
+    fn store_chain(store: &ChainStore, length: u64) -> Vec<[u8; 32]> {
+        let mut hashes: Vec<[u8; 32]> = Vec::new();
+        for height in 0..length {
+            let block = block(height, hashes.last().copied().unwrap_or_default(), 0);
+            let hash = Sha256::digest(header_hash_preimage(&block.header)).into();
+            store.put_block(height, &block.encode()).unwrap();
+            store.put_block_hash(&hash, height).unwrap();
+            store.set_height(height).unwrap();
+            store.set_best_block(&hash).unwrap();
+            hashes.push(hash);
+        }
+        hashes
+    }
+
+    #[test]
+    fn test_locator_heights_are_exponentially_spaced() {
+        assert_eq!(BlockLocator::heights(0), vec![0]);
+        assert_eq!(BlockLocator::heights(3), vec![3, 2, 1, 0]);
+
+        let heights = BlockLocator::heights(1_000_000);
+        assert_eq!(heights[..LOCATOR_DENSE_ENTRIES], (999_991..=1_000_000).rev().collect::<Vec<_>>());
+        assert_eq!(heights[LOCATOR_DENSE_ENTRIES], 999_989);
+        assert_eq!(*heights.last().unwrap(), 0);
+        assert!(heights.len() < 40);
+    }
+
+    #[test]
+    fn test_fork_point_skips_stale_branch_hashes() {
+        let ours = ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap();
+        let main = store_chain(&ours, 30);
+        assert!(BlockLocator::default().find_fork_point(&ours).unwrap().is_none());
+
+        // The peer shares our first 20 blocks, then follows its own branch
+        let theirs = ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap();
+        let mut peer_chain = main[..20].to_vec();
+        for height in 20..40 {
+            let block = block(height, *peer_chain.last().unwrap(), 1);
+            let hash = Sha256::digest(header_hash_preimage(&block.header)).into();
+            peer_chain.push(hash);
+            theirs.put_block(height, &block.encode()).unwrap();
+        }
+        for (height, hash) in peer_chain.iter().enumerate().take(20) {
+            theirs.put_block(height as u64, &ours.get_block(height as u64).unwrap().unwrap()).unwrap();
+            theirs.put_block_hash(hash, height as u64).unwrap();
+        }
+        theirs.set_height(39).unwrap();
+        theirs.set_best_block(peer_chain.last().unwrap()).unwrap();
+
+        let locator = BlockLocator::from_store(&theirs).unwrap();
+        assert_eq!(locator.hashes()[0], peer_chain[39]);
+        // Sampled at 16 below the dense entries, the last shared block it lists
+        assert_eq!(locator.find_fork_point(&ours).unwrap(), Some((16, main[16])));
+
+        // A block we once had at height 25, since reorged away, is not a fork point
+        ours.put_block_hash(&peer_chain[25], 25).unwrap();
+        assert_eq!(
+            BlockLocator::new(vec![peer_chain[25], main[10]]).find_fork_point(&ours).unwrap(),
+            Some((10, main[10]))
+        );
+    }
+}
@@ -1,37 +1,198 @@
-//! Blockchain storage and state management with RocksDB
+//! Blockchain storage and state management over a `KvStore` backend
 
-use rocksdb::{DB, Options, IteratorMode, Direction};
+use crate::consensus::{header_work, Block, BlockHeader, ConsensusEngine, ForgeTransaction};
+use crate::watchtower::{Evidence, WatchedOutput};
+use crate::events::PendingNotification;
+use bitcoin::pow::Work;
+use crate::ledger::{LedgerSetInfo, OutPoint};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::path::Path;
+use std::sync::Arc;
 use anyhow::{Result, anyhow};
 
-/// RocksDB-based blockchain storage
+mod headers;
+pub mod kv;
+mod locator;
+mod reorg;
+mod replay;
+mod search;
+mod snapshot;
+mod state;
+
+pub use headers::{HeaderGuard, DEFAULT_HEADER_WORK_WINDOW, DEFAULT_MAX_UNCONNECTED_HEADERS};
+pub use kv::{KvSnapshot, KvStore, MemoryStore, WriteBatch};
+pub use locator::{BlockLocator, LOCATOR_DENSE_ENTRIES, MAX_LOCATOR_ENTRIES};
+pub use reorg::{PendingReorg, ReorgDecision, ReorgGuard, DEFAULT_MAX_REORG_DEPTH};
+pub use replay::{Divergence, ReplayReport, StateRoot};
+pub use search::{parse_query, tokenize, SearchTerm, MAX_QUERY_TERMS};
+pub use snapshot::ChainSnapshot;
+pub use state::{BlockUndo, ConsensusRecord};
+
+/// How much of the existing database is verified when the node starts
+/// (mirrors bitcoind's `-checklevel`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum CheckLevel {
+    /// Level 0: height and best-block metadata are consistent
+    Metadata = 0,
+    /// Level 1: each block links to its parent's header hash
+    HeaderLinks = 1,
+    /// Level 2: block merkle roots match their forges
+    #[default]
+    MerkleRoots = 2,
+    /// Level 3: every forge's proof-of-forge is re-derived (slow)
+    ProofOfForge = 3,
+}
+
+impl TryFrom<u8> for CheckLevel {
+    type Error = anyhow::Error;
+
+    fn try_from(level: u8) -> Result<Self> {
+        match level {
+            0 => Ok(CheckLevel::Metadata),
+            1 => Ok(CheckLevel::HeaderLinks),
+            2 => Ok(CheckLevel::MerkleRoots),
+            3 => Ok(CheckLevel::ProofOfForge),
+            _ => Err(anyhow!("Invalid check level {} (expected 0-3)", level)),
+        }
+    }
+}
+
+impl From<CheckLevel> for u8 {
+    fn from(level: CheckLevel) -> u8 {
+        level as u8
+    }
+}
+
+/// Default number of recent blocks verified on startup
+pub const DEFAULT_CHECK_BLOCKS: u64 = 6;
+
+/// Outcome of a startup chain verification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainVerifyReport {
+    pub level: CheckLevel,
+    pub tip_height: u64,
+    pub blocks_checked: u64,
+    pub forges_checked: u64,
+}
+
+/// Header index entry with cumulative chainwork, so the best chain can be
+/// chosen from headers alone
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeaderIndexEntry {
+    pub hash: [u8; 32],
+    pub height: u64,
+    pub prev_hash: [u8; 32],
+    pub bits: u32,
+    /// Total work up to and including this header (big-endian)
+    pub chainwork: [u8; 32],
+}
+
+impl HeaderIndexEntry {
+    /// Cumulative work up to and including this header
+    pub fn chainwork(&self) -> Work {
+        Work::from_be_bytes(self.chainwork)
+    }
+}
+
+/// Forge of a prophecy; each prophecy can only be forged once
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProphecyOwner {
+    /// Address the forge paid to
+    pub owner: String,
+    pub height: u64,
+    pub proof_hash: [u8; 32],
+}
+
+/// Where two indexed chain tips diverge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForkPoint {
+    /// Last block shared by both chains
+    pub hash: [u8; 32],
+    pub height: u64,
+    /// Blocks that would be disconnected from the current tip
+    pub disconnect: u64,
+    /// Blocks that would be connected to reach the candidate tip
+    pub connect: u64,
+}
+
+/// Blockchain storage. Clones share the same backend.
+#[derive(Clone)]
 pub struct ChainStore {
-    db: DB,
+    db: Arc<dyn KvStore>,
 }
 
 /// Key prefixes for different data types
 const BLOCK_PREFIX: &[u8] = b"blk:";
-const BLOCK_HASH_PREFIX: &[u8] = b"bhash:";
 const BLOCK_HASH_KEY: &[u8] = b"bhash:";
 const FORGE_PREFIX: &[u8] = b"forge:";
 const META_PREFIX: &[u8] = b"meta:";
+const LEDGER_INFO_PREFIX: &[u8] = b"ledgerinfo:";
+const USED_PROOFS_HASH_PREFIX: &[u8] = b"uproofs:";
+const FORGE_INDEX_PREFIX: &[u8] = b"txidx:";
+const HEADER_INDEX_PREFIX: &[u8] = b"hidx:";
+const PROPHECY_OWNER_PREFIX: &[u8] = b"owner:";
+const EVIDENCE_PREFIX: &[u8] = b"evid:";
+/// Evidence ids by arrival sequence, for pruning the oldest
+const EVIDENCE_ORDER_PREFIX: &[u8] = b"evseq:";
+const NOTIFICATION_PREFIX: &[u8] = b"notif:";
+const WATCHED_OUTPUT_PREFIX: &[u8] = b"swatch:";
+const NOTIFICATION_SEQ_KEY: &str = "notification_seq";
+const FORGE_INDEX_KEY: &[u8] = b"meta:txindex";
 const HEIGHT_KEY: &[u8] = b"meta:height";
 const BEST_BLOCK_KEY: &[u8] = b"meta:best_block";
+/// Block key encoding version; absent for legacy little-endian height keys
+const BLOCK_KEY_VERSION_KEY: &[u8] = b"meta:block_key_version";
+const BLOCK_KEY_VERSION: u8 = 1;
 
 impl ChainStore {
-    /// Create a new chain store
+    /// Open or create a chain store in `path` with the default persistent
+    /// backend: RocksDB, or redb when built without the `rocksdb` feature
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let mut opts = Options::default();
-        opts.create_if_missing(true);
-        opts.set_compression_type(rocksdb::DBCompressionType::Lz4);
-        opts.set_max_open_files(1000);
-        opts.set_keep_log_file_num(10);
-        opts.set_max_background_jobs(4);
-        
-        let db = DB::open(&opts, path)?;
-        
-        Ok(ChainStore { db })
+        #[cfg(feature = "rocksdb")]
+        let db = kv::RocksStore::open(path)?;
+        #[cfg(all(feature = "redb", not(feature = "rocksdb")))]
+        let db = kv::RedbStore::open(path)?;
+        #[cfg(not(any(feature = "rocksdb", feature = "redb")))]
+        return Err(anyhow!(
+            "No persistent storage backend for {}; enable the rocksdb or redb feature",
+            path.as_ref().display()
+        ));
+
+        #[cfg(any(feature = "rocksdb", feature = "redb"))]
+        Self::with_backend(Box::new(db))
+    }
+
+    /// Create a chain store on an already opened backend
+    pub fn with_backend(db: Box<dyn KvStore>) -> Result<Self> {
+        let store = ChainStore { db: Arc::from(db) };
+        store.migrate_block_keys()?;
+        Ok(store)
+    }
+
+    /// Rewrite legacy little-endian block keys as big-endian, so keys sort
+    /// in height order (block 256 used to sort before block 2)
+    fn migrate_block_keys(&self) -> Result<()> {
+        if self.db.get(BLOCK_KEY_VERSION_KEY)?.is_some() {
+            return Ok(());
+        }
+
+        let mut batch = WriteBatch::default();
+        for entry in self.prefix_iter(BLOCK_PREFIX, false) {
+            let (key, value) = entry?;
+            let Ok(height_bytes) = <[u8; 8]>::try_from(&key[BLOCK_PREFIX.len()..]) else {
+                continue;
+            };
+            batch.delete(&key);
+            batch.put(Self::block_key(u64::from_le_bytes(height_bytes)), value);
+        }
+        if !batch.is_empty() {
+            tracing::info!("Migrating {} block keys to big-endian heights", batch.len() / 2);
+        }
+        batch.put(BLOCK_KEY_VERSION_KEY, [BLOCK_KEY_VERSION]);
+        self.db.write(batch)?;
+        Ok(())
     }
 
     /// Store a block by height
@@ -44,13 +205,13 @@ impl ChainStore {
     /// Get a block by height
     pub fn get_block(&self, height: u64) -> Result<Option<Vec<u8>>> {
         let key = Self::block_key(height);
-        Ok(self.db.get(&key)?)
+        self.db.get(&key)
     }
 
     /// Store a block hash mapping (hash -> height)
     pub fn put_block_hash(&self, block_hash: &[u8; 32], height: u64) -> Result<()> {
         let key = Self::block_hash_key(block_hash);
-        self.db.put(&key, height.to_le_bytes())?;
+        self.db.put(&key, &height.to_le_bytes())?;
         Ok(())
     }
 
@@ -77,7 +238,7 @@ impl ChainStore {
     /// Get a forge transaction by proof hash
     pub fn get_forge(&self, proof_hash: &[u8; 32]) -> Result<Option<Vec<u8>>> {
         let key = Self::forge_key(proof_hash);
-        Ok(self.db.get(&key)?)
+        self.db.get(&key)
     }
 
     /// Check if a forge exists (for replay protection)
@@ -88,7 +249,7 @@ impl ChainStore {
 
     /// Set the current chain height
     pub fn set_height(&self, height: u64) -> Result<()> {
-        self.db.put(HEIGHT_KEY, height.to_le_bytes())?;
+        self.db.put(HEIGHT_KEY, &height.to_le_bytes())?;
         Ok(())
     }
 
@@ -132,30 +293,406 @@ impl ChainStore {
     /// Get metadata
     pub fn get_meta(&self, key: &str) -> Result<Option<Vec<u8>>> {
         let full_key = [META_PREFIX, key.as_bytes()].concat();
-        Ok(self.db.get(&full_key)?)
-    }
-
-    /// Iterate over all blocks in order
-    pub fn iter_blocks(&self) -> impl Iterator<Item = (u64, Vec<u8>)> + '_ {
-        self.db
-            .iterator(IteratorMode::From(BLOCK_PREFIX, Direction::Forward))
-            .take_while(|(key, _)| key.starts_with(BLOCK_PREFIX))
-            .filter_map(|(key, value)| {
-                // Extract height from key
-                let height_bytes = &key[BLOCK_PREFIX.len()..];
-                if height_bytes.len() == 8 {
-                    let height_array: [u8; 8] = height_bytes.try_into().ok()?;
-                    let height = u64::from_le_bytes(height_array);
-                    Some((height, value.to_vec()))
-                } else {
-                    None
-                }
-            })
+        self.db.get(&full_key)
+    }
+
+    /// Store ledger statistics for a height
+    pub fn put_ledger_info(&self, info: &LedgerSetInfo) -> Result<()> {
+        let key = Self::ledger_info_key(info.height);
+        self.db.put(&key, &bincode::serialize(info)?)?;
+        Ok(())
+    }
+
+    /// Get ledger statistics recorded at a height
+    pub fn get_ledger_info(&self, height: u64) -> Result<Option<LedgerSetInfo>> {
+        let key = Self::ledger_info_key(height);
+        match self.db.get(&key)? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Store the used-proofs set hash reached at a height
+    pub fn put_used_proofs_hash(&self, height: u64, hash: &[u8; 32]) -> Result<()> {
+        self.db.put(&Self::used_proofs_hash_key(height), hash)
+    }
+
+    /// Get the used-proofs set hash recorded at a height
+    pub fn get_used_proofs_hash(&self, height: u64) -> Result<Option<[u8; 32]>> {
+        match self.db.get(&Self::used_proofs_hash_key(height))? {
+            Some(bytes) => Ok(Some(
+                bytes
+                    .try_into()
+                    .map_err(|_| anyhow!("Corrupt used-proofs hash at height {}", height))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Add a header to the header index, accumulating chainwork from its
+    /// parent's entry
+    pub fn index_header(&self, hash: &[u8; 32], header: &BlockHeader) -> Result<HeaderIndexEntry> {
+        let entry = self.header_entry(hash, header)?;
+        self.db.put(&Self::header_index_key(hash), &bincode::serialize(&entry)?)?;
+        Ok(entry)
+    }
+
+    fn header_entry(&self, hash: &[u8; 32], header: &BlockHeader) -> Result<HeaderIndexEntry> {
+        let parent_work = if header.height == 0 {
+            Work::from_be_bytes([0u8; 32])
+        } else {
+            self.get_header_index(&header.prev_block_hash)?
+                .ok_or_else(|| anyhow!("Parent of header {} is not indexed", hex::encode(hash)))?
+                .chainwork()
+        };
+
+        let entry = HeaderIndexEntry {
+            hash: *hash,
+            height: header.height,
+            prev_hash: header.prev_block_hash,
+            bits: header.bits,
+            chainwork: (parent_work + header_work(header.bits)).to_be_bytes(),
+        };
+        Ok(entry)
+    }
+
+    /// Get a header index entry by block hash
+    pub fn get_header_index(&self, hash: &[u8; 32]) -> Result<Option<HeaderIndexEntry>> {
+        match self.db.get(&Self::header_index_key(hash))? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Indexed headers no other indexed header builds on: the active tip
+    /// and the tips of any competing branches
+    pub fn header_tips(&self) -> Result<Vec<HeaderIndexEntry>> {
+        let mut entries = Vec::new();
+        for entry in self.prefix_iter(HEADER_INDEX_PREFIX, false) {
+            let (_, value) = entry?;
+            entries.push(bincode::deserialize::<HeaderIndexEntry>(&value)?);
+        }
+        let parents: HashSet<[u8; 32]> = entries.iter().map(|entry| entry.prev_hash).collect();
+        entries.retain(|entry| !parents.contains(&entry.hash));
+        Ok(entries)
+    }
+
+    /// Find the fork point between two tips in the header index
+    pub fn find_fork(&self, current_tip: &[u8; 32], candidate_tip: &[u8; 32]) -> Result<ForkPoint> {
+        let lookup = |hash: &[u8; 32]| -> Result<HeaderIndexEntry> {
+            self.get_header_index(hash)?
+                .ok_or_else(|| anyhow!("Header {} is not indexed", hex::encode(hash)))
+        };
+
+        let current = lookup(current_tip)?;
+        let candidate = lookup(candidate_tip)?;
+        let (mut a, mut b) = (current.clone(), candidate.clone());
+
+        while a.height > b.height {
+            a = lookup(&a.prev_hash)?;
+        }
+        while b.height > a.height {
+            b = lookup(&b.prev_hash)?;
+        }
+        while a.hash != b.hash {
+            if a.height == 0 {
+                return Err(anyhow!("Tips do not share a genesis block"));
+            }
+            a = lookup(&a.prev_hash)?;
+            b = lookup(&b.prev_hash)?;
+        }
+
+        Ok(ForkPoint {
+            hash: a.hash,
+            height: a.height,
+            disconnect: current.height - a.height,
+            connect: candidate.height - a.height,
+        })
+    }
+
+    /// Whether the forge index (txindex) exists
+    pub fn forge_index_enabled(&self) -> Result<bool> {
+        Ok(self.db.get(FORGE_INDEX_KEY)?.is_some())
+    }
+
+    /// Number of blocks (from genesis) covered by the forge index, if it exists
+    pub fn forge_index_progress(&self) -> Result<Option<u64>> {
+        match self.db.get(FORGE_INDEX_KEY)? {
+            Some(bytes) => {
+                let height_bytes: [u8; 8] = bytes.try_into()
+                    .map_err(|_| anyhow!("Invalid forge index progress"))?;
+                Ok(Some(u64::from_be_bytes(height_bytes)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Add a connected block's forges to the forge index, if it is enabled
+    pub fn index_block_forges(&self, block: &Block) -> Result<()> {
+        if !self.forge_index_enabled()? {
+            return Ok(());
+        }
+        let mut batch = WriteBatch::default();
+        Self::batch_forge_index_entries(&mut batch, block);
+        self.db.write(batch)
+    }
+
+    fn batch_forge_index_entries(batch: &mut WriteBatch, block: &Block) {
+        let height = block.header.height;
+        for forge in &block.forges {
+            batch.put(Self::forge_index_key(&forge.proof_hash), height.to_be_bytes());
+        }
+        batch.put(FORGE_INDEX_KEY, (height + 1).to_be_bytes());
+    }
+
+    /// Build the forge index from all stored blocks.
+    ///
+    /// Resumes from the last indexed block if a build was interrupted.
+    pub fn build_forge_index(&self) -> Result<u64> {
+        let start = match self.forge_index_progress()? {
+            Some(next) => next,
+            None => {
+                self.db.put(FORGE_INDEX_KEY, &0u64.to_be_bytes())?;
+                0
+            }
+        };
+
+        let mut indexed = start;
+        for height in start..=self.get_height()? {
+            let Some(block) = self.load_block(height)? else {
+                break;
+            };
+            let mut batch = WriteBatch::default();
+            Self::batch_forge_index_entries(&mut batch, &block);
+            self.db.write(batch)?;
+            indexed = height + 1;
+        }
+
+        tracing::info!("Forge index covers {} blocks", indexed);
+        Ok(indexed)
+    }
+
+    /// Delete the forge index
+    pub fn drop_forge_index(&self) -> Result<usize> {
+        let keys: Vec<Vec<u8>> = self
+            .prefix_iter(FORGE_INDEX_PREFIX, false)
+            .map(|entry| entry.map(|(key, _)| key))
+            .collect::<Result<_>>()?;
+
+        let mut batch = WriteBatch::default();
+        for key in &keys {
+            batch.delete(key);
+        }
+        batch.delete(FORGE_INDEX_KEY);
+        self.db.write(batch)?;
+
+        tracing::info!("Forge index dropped ({} entries)", keys.len());
+        Ok(keys.len())
+    }
+
+    /// Look up a historical forge and its block height through the forge index
+    pub fn lookup_forge(&self, proof_hash: &[u8; 32]) -> Result<Option<(u64, ForgeTransaction)>> {
+        if !self.forge_index_enabled()? {
+            return Err(anyhow!("Forge index is disabled"));
+        }
+        let Some(bytes) = self.db.get(&Self::forge_index_key(proof_hash))? else {
+            return Ok(None);
+        };
+        let height_bytes: [u8; 8] = bytes.as_slice().try_into()
+            .map_err(|_| anyhow!("Invalid forge index entry"))?;
+        let height = u64::from_be_bytes(height_bytes);
+
+        let block = self
+            .load_block(height)?
+            .ok_or_else(|| anyhow!("Forge index points to missing block {}", height))?;
+        Ok(block
+            .forges
+            .into_iter()
+            .find(|forge| &forge.proof_hash == proof_hash)
+            .map(|forge| (height, forge)))
+    }
+
+    /// Owner of a prophecy, by `prophecy_registry_hash`
+    pub fn get_prophecy_owner(&self, prophecy_hash: &[u8; 32]) -> Result<Option<ProphecyOwner>> {
+        match self.db.get(&Self::prophecy_owner_key(prophecy_hash))? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// All registered prophecy owners
+    pub fn prophecy_owners(&self) -> Result<Vec<([u8; 32], ProphecyOwner)>> {
+        let mut owners = Vec::new();
+        for entry in self.prefix_iter(PROPHECY_OWNER_PREFIX, false) {
+            let (key, value) = entry?;
+            let hash: [u8; 32] = key[PROPHECY_OWNER_PREFIX.len()..]
+                .try_into()
+                .map_err(|_| anyhow!("Invalid prophecy registry key"))?;
+            owners.push((hash, bincode::deserialize(&value)?));
+        }
+        Ok(owners)
+    }
+
+    /// Record watchtower evidence, keeping at most the `limit` most recent
+    /// entries. Returns `false` if it was already stored.
+    pub fn put_evidence(&self, evidence: &Evidence, limit: usize) -> Result<bool> {
+        let id = evidence.id()?;
+        let key = [EVIDENCE_PREFIX, &id].concat();
+        if self.db.get(&key)?.is_some() {
+            return Ok(false);
+        }
+        let sequence = match self.prefix_iter(EVIDENCE_ORDER_PREFIX, true).next() {
+            Some(entry) => {
+                let (last, _) = entry?;
+                Self::evidence_sequence(&last)? + 1
+            }
+            None => 0,
+        };
+
+        let mut batch = WriteBatch::default();
+        batch.put(&key, bincode::serialize(evidence)?);
+        batch.put([EVIDENCE_ORDER_PREFIX, &sequence.to_be_bytes()].concat(), id);
+        for entry in self.prefix_iter(EVIDENCE_ORDER_PREFIX, false) {
+            let (order_key, old_id) = entry?;
+            if Self::evidence_sequence(&order_key)? + limit as u64 > sequence {
+                break;
+            }
+            batch.delete([EVIDENCE_PREFIX, old_id.as_slice()].concat());
+            batch.delete(order_key);
+        }
+        self.db.write(batch)?;
+        Ok(true)
+    }
+
+    fn evidence_sequence(order_key: &[u8]) -> Result<u64> {
+        <[u8; 8]>::try_from(&order_key[EVIDENCE_ORDER_PREFIX.len()..])
+            .map(u64::from_be_bytes)
+            .map_err(|_| anyhow!("Corrupt evidence order key"))
+    }
+
+    /// All recorded watchtower evidence
+    pub fn list_evidence(&self) -> Result<Vec<Evidence>> {
+        let mut evidence = Vec::new();
+        for entry in self.prefix_iter(EVIDENCE_PREFIX, false) {
+            let (_, value) = entry?;
+            evidence.push(bincode::deserialize(&value)?);
+        }
+        Ok(evidence)
+    }
+
+    fn watched_output_key(outpoint: &OutPoint) -> Vec<u8> {
+        [WATCHED_OUTPUT_PREFIX, &outpoint.txid, &outpoint.vout.to_be_bytes()].concat()
+    }
+
+    /// Register or update an output watched for spends
+    pub fn put_watched_output(&self, watched: &WatchedOutput) -> Result<()> {
+        self.db.put(&Self::watched_output_key(&watched.outpoint), &bincode::serialize(watched)?)
+    }
+
+    /// Registration of a watched output
+    pub fn get_watched_output(&self, outpoint: &OutPoint) -> Result<Option<WatchedOutput>> {
+        match self.db.get(&Self::watched_output_key(outpoint))? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stop watching an output
+    pub fn delete_watched_output(&self, outpoint: &OutPoint) -> Result<()> {
+        self.db.delete(&Self::watched_output_key(outpoint))
+    }
+
+    /// Every watched output, by outpoint
+    pub fn list_watched_outputs(&self) -> Result<Vec<WatchedOutput>> {
+        let mut watched = Vec::new();
+        for entry in self.prefix_iter(WATCHED_OUTPUT_PREFIX, false) {
+            let (_, value) = entry?;
+            watched.push(bincode::deserialize(&value)?);
+        }
+        Ok(watched)
+    }
+
+    /// Allocate an id for a queued notification
+    pub fn next_notification_id(&self) -> Result<u64> {
+        let last = match self.get_meta(NOTIFICATION_SEQ_KEY)? {
+            Some(bytes) => u64::from_be_bytes(
+                bytes
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| anyhow!("Corrupt notification sequence"))?,
+            ),
+            None => 0,
+        };
+        self.put_meta(NOTIFICATION_SEQ_KEY, &(last + 1).to_be_bytes())?;
+        Ok(last + 1)
+    }
+
+    /// Queue or update a pending notification
+    pub fn put_notification(&self, notification: &PendingNotification) -> Result<()> {
+        let key = [NOTIFICATION_PREFIX, &notification.id.to_be_bytes()].concat();
+        self.db.put(&key, &bincode::serialize(notification)?)
+    }
+
+    /// Remove a delivered or abandoned notification
+    pub fn delete_notification(&self, id: u64) -> Result<()> {
+        self.db.delete(&[NOTIFICATION_PREFIX, &id.to_be_bytes()].concat())
+    }
+
+    /// Pending notifications, oldest first
+    pub fn list_notifications(&self) -> Result<Vec<PendingNotification>> {
+        let mut notifications = Vec::new();
+        for entry in self.prefix_iter(NOTIFICATION_PREFIX, false) {
+            let (_, value) = entry?;
+            notifications.push(bincode::deserialize(&value)?);
+        }
+        Ok(notifications)
+    }
+
+    /// Iterate over all blocks in height order
+    pub fn iter_blocks(&self) -> impl Iterator<Item = Result<(u64, Vec<u8>)>> + '_ {
+        Self::decode_blocks(self.prefix_iter(BLOCK_PREFIX, false))
+    }
+
+    /// Iterate over blocks with heights in `start..end`, in height order
+    pub fn iter_blocks_range(&self, start: u64, end: u64) -> impl Iterator<Item = Result<(u64, Vec<u8>)>> + '_ {
+        Self::decode_blocks(self.db.range(&Self::block_key(start), &Self::block_key(end), false))
+    }
+
+    /// Iterate over all blocks from the highest down
+    pub fn iter_blocks_rev(&self) -> impl Iterator<Item = Result<(u64, Vec<u8>)>> + '_ {
+        Self::decode_blocks(self.prefix_iter(BLOCK_PREFIX, true))
+    }
+
+    /// Iterate over all stored forges by proof hash
+    pub fn iter_forges(&self) -> impl Iterator<Item = Result<([u8; 32], Vec<u8>)>> + '_ {
+        self.prefix_iter(FORGE_PREFIX, false).map(|entry| {
+            let (key, value) = entry?;
+            let proof_hash = <[u8; 32]>::try_from(&key[FORGE_PREFIX.len()..])
+                .map_err(|_| anyhow!("Malformed forge key {}", hex::encode(&key)))?;
+            Ok((proof_hash, value))
+        })
+    }
+
+    /// Entries whose keys start with `prefix`
+    fn prefix_iter(&self, prefix: &[u8], reverse: bool) -> kv::KvIter<'_> {
+        self.db.range(prefix, &Self::prefix_end(prefix), reverse)
+    }
+
+    fn decode_blocks<'a>(
+        entries: kv::KvIter<'a>,
+    ) -> impl Iterator<Item = Result<(u64, Vec<u8>)>> + 'a {
+        entries.map(|entry| {
+            let (key, value) = entry?;
+            let height = <[u8; 8]>::try_from(&key[BLOCK_PREFIX.len()..])
+                .map(u64::from_be_bytes)
+                .map_err(|_| anyhow!("Malformed block key {}", hex::encode(&key)))?;
+            Ok((height, value))
+        })
     }
 
     /// Count total blocks
     pub fn count_blocks(&self) -> usize {
-        self.iter_blocks().count()
+        self.prefix_iter(BLOCK_PREFIX, false).count()
     }
 
     /// Delete a block
@@ -165,19 +702,188 @@ impl ChainStore {
         Ok(())
     }
 
-    /// Create a snapshot for consistent reads
-    pub fn snapshot(&self) -> rocksdb::Snapshot {
-        self.db.snapshot()
+    /// What connecting a block on the tip stores: the block, its hash and
+    /// header index entry, the ledger statistics and used-proofs hash it
+    /// reaches, its enabled forge and word index entries, and the new
+    /// height and best block. The engine writes the batch together with
+    /// its state in `ConsensusEngine::commit_block`.
+    pub fn connect_batch(
+        &self,
+        block: &Block,
+        hash: &[u8; 32],
+        ledger_info: &LedgerSetInfo,
+        used_proofs_hash: &[u8; 32],
+    ) -> Result<WriteBatch> {
+        let height = block.header.height;
+        let mut batch = WriteBatch::default();
+        batch.put(Self::block_key(height), block.encode());
+        batch.put(Self::block_hash_key(hash), height.to_le_bytes());
+        let entry = self.header_entry(hash, &block.header)?;
+        batch.put(Self::header_index_key(hash), bincode::serialize(&entry)?);
+        batch.put(Self::ledger_info_key(height), bincode::serialize(ledger_info)?);
+        batch.put(Self::used_proofs_hash_key(height), used_proofs_hash);
+        if self.forge_index_enabled()? {
+            Self::batch_forge_index_entries(&mut batch, block);
+        }
+        self.batch_index_words(&mut batch, block)?;
+        batch.put(HEIGHT_KEY, height.to_le_bytes());
+        batch.put(BEST_BLOCK_KEY, hash);
+        Ok(batch)
+    }
+
+    /// What disconnecting a block on the tip removes, making its parent the
+    /// best block. Its header stays indexed, like every other branch's.
+    /// The engine writes the batch together with its reverted state in
+    /// `ConsensusEngine::disconnect_block_with`.
+    pub fn disconnect_batch(&self, block: &Block, hash: &[u8; 32]) -> Result<WriteBatch> {
+        let height = block.header.height;
+        if height == 0 {
+            return Err(anyhow!("The genesis block can't be disconnected"));
+        }
+        let mut batch = WriteBatch::default();
+        self.batch_unindex_words(&mut batch, block)?;
+        batch.delete(Self::block_key(height));
+        batch.delete(Self::block_hash_key(hash));
+        batch.delete(Self::ledger_info_key(height));
+        batch.delete(Self::used_proofs_hash_key(height));
+        if let Some(next) = self.forge_index_progress()? {
+            for forge in &block.forges {
+                batch.delete(Self::forge_index_key(&forge.proof_hash));
+            }
+            batch.put(FORGE_INDEX_KEY, next.min(height).to_be_bytes());
+        }
+        batch.put(HEIGHT_KEY, (height - 1).to_le_bytes());
+        batch.put(BEST_BLOCK_KEY, block.header.prev_block_hash);
+        Ok(batch)
+    }
+
+    /// Decode the block stored at a height
+    pub fn load_block(&self, height: u64) -> Result<Option<Block>> {
+        match self.get_block(height)? {
+            Some(bytes) => {
+                let block = Block::decode(&bytes)
+                    .map_err(|e| anyhow!("Corrupt block at height {}: {}", height, e))?;
+                Ok(Some(block))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Verify the stored chain on open.
+    ///
+    /// Checks the last `check_blocks` blocks (0 = the whole chain) up to the
+    /// requested level; each level includes the checks of the levels below it.
+    pub fn verify_chain(
+        &self,
+        engine: &ConsensusEngine,
+        level: CheckLevel,
+        check_blocks: u64,
+    ) -> Result<ChainVerifyReport> {
+        let tip_height = self.get_height()?;
+        let best_block = self.get_best_block()?;
+
+        let mut report = ChainVerifyReport {
+            level,
+            tip_height,
+            blocks_checked: 0,
+            forges_checked: 0,
+        };
+
+        // Level 0: metadata consistency
+        let Some(best_hash) = best_block else {
+            if tip_height > 0 {
+                return Err(anyhow!("Height is {} but no best block is recorded", tip_height));
+            }
+            return Ok(report);
+        };
+        if self.get_block(tip_height)?.is_none() {
+            return Err(anyhow!("Missing block at tip height {}", tip_height));
+        }
+        if let Some(indexed) = self.get_block_height_by_hash(&best_hash)? {
+            if indexed != tip_height {
+                return Err(anyhow!(
+                    "Best block indexed at height {} but tip height is {}",
+                    indexed,
+                    tip_height
+                ));
+            }
+        }
+        if level == CheckLevel::Metadata {
+            return Ok(report);
+        }
+
+        let start = if check_blocks == 0 {
+            0
+        } else {
+            tip_height.saturating_sub(check_blocks - 1)
+        };
+
+        let mut expected_hash = best_hash;
+        for height in (start..=tip_height).rev() {
+            let block = self
+                .load_block(height)?
+                .ok_or_else(|| anyhow!("Missing block at height {}", height))?;
+
+            // Level 1: header links
+            if block.header.height != height {
+                return Err(anyhow!(
+                    "Block stored at height {} claims height {}",
+                    height,
+                    block.header.height
+                ));
+            }
+            if engine.compute_block_hash(&block.header) != expected_hash {
+                return Err(anyhow!("Header hash mismatch at height {}", height));
+            }
+            engine
+                .check_header_pow(&block.header)
+                .map_err(|e| anyhow!("Invalid header at height {}: {}", height, e))?;
+            expected_hash = block.header.prev_block_hash;
+
+            // Level 2: merkle roots
+            if level >= CheckLevel::MerkleRoots
+                && engine.compute_block_merkle_root(&block) != block.header.merkle_root
+            {
+                return Err(anyhow!("Merkle root mismatch at height {}", height));
+            }
+
+            // Level 3: proof-of-forge re-derivation
+            if level >= CheckLevel::ProofOfForge {
+                for forge in &block.forges {
+                    engine
+                        .check_forge_tempering(forge, height)
+                        .and_then(|_| engine.verify_forge_proof(forge, height))
+                        .map_err(|e| anyhow!("Invalid forge at height {}: {}", height, e))?;
+                    report.forges_checked += 1;
+                }
+            }
+
+            report.blocks_checked += 1;
+        }
+
+        Ok(report)
     }
 
     /// Compact the database
     pub fn compact(&self) {
-        self.db.compact_range::<&[u8], &[u8]>(None, None);
+        self.db.compact();
     }
 
     // Helper functions for key generation
     fn block_key(height: u64) -> Vec<u8> {
-        [BLOCK_PREFIX, &height.to_le_bytes()].concat()
+        [BLOCK_PREFIX, &height.to_be_bytes()].concat()
+    }
+
+    /// Smallest key greater than every key starting with `prefix`
+    fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+        let mut end = prefix.to_vec();
+        while let Some(last) = end.pop() {
+            if last < u8::MAX {
+                end.push(last + 1);
+                break;
+            }
+        }
+        end
     }
 
     fn block_hash_key(hash: &[u8; 32]) -> Vec<u8> {
@@ -187,12 +893,34 @@ impl ChainStore {
     fn forge_key(proof_hash: &[u8; 32]) -> Vec<u8> {
         [FORGE_PREFIX, proof_hash].concat()
     }
+
+    fn header_index_key(hash: &[u8; 32]) -> Vec<u8> {
+        [HEADER_INDEX_PREFIX, hash].concat()
+    }
+
+    fn prophecy_owner_key(prophecy_hash: &[u8; 32]) -> Vec<u8> {
+        [PROPHECY_OWNER_PREFIX, prophecy_hash].concat()
+    }
+
+    fn forge_index_key(proof_hash: &[u8; 32]) -> Vec<u8> {
+        [FORGE_INDEX_PREFIX, proof_hash].concat()
+    }
+
+    fn ledger_info_key(height: u64) -> Vec<u8> {
+        [LEDGER_INFO_PREFIX, &height.to_be_bytes()].concat()
+    }
+
+    fn used_proofs_hash_key(height: u64) -> Vec<u8> {
+        [USED_PROOFS_HASH_PREFIX, &height.to_be_bytes()].concat()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use tempfile::TempDir;
+    use crate::consensus::{testing, POW_LIMIT_BITS};
+    use crate::crypto::prophecy_registry_hash;
 
     #[test]
     fn test_chain_store_creation() {
@@ -244,9 +972,179 @@ mod tests {
             store.put_block(i, format!("block {}", i).as_bytes()).unwrap();
         }
         
-        let blocks: Vec<_> = store.iter_blocks().collect();
+        let blocks: Vec<_> = store.iter_blocks().collect::<Result<_>>().unwrap();
         assert_eq!(blocks.len(), 5);
         assert_eq!(blocks[0].0, 0);
         assert_eq!(blocks[4].0, 4);
     }
+
+    #[test]
+    fn test_block_range_and_reverse_iteration() {
+        let tmp = TempDir::new().unwrap();
+        let store = ChainStore::new(tmp.path()).unwrap();
+        for height in [0, 1, 2, 255, 256, 257, 1_000] {
+            store.put_block(height, &height.to_le_bytes()).unwrap();
+        }
+        store.put_forge(&[1u8; 32], b"forge").unwrap();
+
+        let heights = |iter: &mut dyn Iterator<Item = Result<(u64, Vec<u8>)>>| -> Vec<u64> {
+            iter.map(|entry| entry.unwrap().0).collect()
+        };
+        // Heights sort numerically, not by little-endian bytes
+        assert_eq!(heights(&mut store.iter_blocks()), vec![0, 1, 2, 255, 256, 257, 1_000]);
+        assert_eq!(heights(&mut store.iter_blocks_range(2, 257)), vec![2, 255, 256]);
+        assert_eq!(heights(&mut store.iter_blocks_range(300, 200)), Vec::<u64>::new());
+        assert_eq!(heights(&mut store.iter_blocks_rev()), vec![1_000, 257, 256, 255, 2, 1, 0]);
+        assert_eq!(store.count_blocks(), 7);
+
+        let forges: Vec<_> = store.iter_forges().collect::<Result<_>>().unwrap();
+        assert_eq!(forges, vec![([1u8; 32], b"forge".to_vec())]);
+    }
+
+    #[test]
+    fn test_legacy_block_keys_migrated() {
+        let tmp = TempDir::new().unwrap();
+        {
+            let store = ChainStore::new(tmp.path()).unwrap();
+            store.db.delete(BLOCK_KEY_VERSION_KEY).unwrap();
+            for height in [2u64, 256] {
+                let legacy_key = [BLOCK_PREFIX, &height.to_le_bytes()].concat();
+                store.db.put(&legacy_key, &height.to_le_bytes()).unwrap();
+            }
+        }
+
+        let store = ChainStore::new(tmp.path()).unwrap();
+        let blocks: Vec<_> = store.iter_blocks().collect::<Result<_>>().unwrap();
+        assert_eq!(blocks, vec![(2, 2u64.to_le_bytes().to_vec()), (256, 256u64.to_le_bytes().to_vec())]);
+        assert_eq!(store.get_block(256).unwrap(), Some(256u64.to_le_bytes().to_vec()));
+    }
+
+    fn store_test_chain(store: &ChainStore, engine: &ConsensusEngine, length: u64) {
+        let mut prev_hash = [0u8; 32];
+        for height in 0..length {
+            let forges = vec![ForgeTransaction {
+                prophecy: "sword legend pull magic kingdom artist stone destroy forget fire steel honey question".to_string(),
+                derived_key: vec![height as u8],
+                taproot_address: "bc1p...".to_string(),
+                proof_hash: [height as u8; 32],
+                timestamp: 1000 + height,
+                signature: vec![],
+                not_before_height: 0,
+                tempering: Default::default(),
+                salt: [0; 32],
+            }];
+            let mut block = testing::block(height, prev_hash, forges);
+            block.header.merkle_root = engine.compute_merkle_root(&block.forges);
+            assert!(engine.grind_header(&mut block.header, 1_000));
+            let hash = engine.compute_block_hash(&block.header);
+            store.index_header(&hash, &block.header).unwrap();
+            store.put_block(height, &block.encode()).unwrap();
+            store.put_block_hash(&hash, height).unwrap();
+            prev_hash = hash;
+        }
+        store.set_height(length - 1).unwrap();
+        store.set_best_block(&prev_hash).unwrap();
+    }
+
+    #[test]
+    fn test_verify_chain_levels() {
+        let tmp = TempDir::new().unwrap();
+        let store = ChainStore::new(tmp.path()).unwrap();
+        let engine = ConsensusEngine::new(0, 600);
+        store_test_chain(&store, &engine, 4);
+
+        let report = store.verify_chain(&engine, CheckLevel::MerkleRoots, 0).unwrap();
+        assert_eq!(report.blocks_checked, 4);
+        assert_eq!(report.tip_height, 3);
+
+        let report = store.verify_chain(&engine, CheckLevel::HeaderLinks, 2).unwrap();
+        assert_eq!(report.blocks_checked, 2);
+
+        let report = store.verify_chain(&engine, CheckLevel::Metadata, 0).unwrap();
+        assert_eq!(report.blocks_checked, 0);
+    }
+
+    #[test]
+    fn test_verify_chain_detects_bad_merkle_root() {
+        let tmp = TempDir::new().unwrap();
+        let store = ChainStore::new(tmp.path()).unwrap();
+        let engine = ConsensusEngine::new(0, 600);
+        store_test_chain(&store, &engine, 3);
+
+        // Tamper with a forge without updating the header
+        let mut block = store.load_block(1).unwrap().unwrap();
+        block.forges[0].timestamp += 1;
+        store.put_block(1, &block.encode()).unwrap();
+
+        assert!(store.verify_chain(&engine, CheckLevel::HeaderLinks, 0).is_ok());
+        assert!(store.verify_chain(&engine, CheckLevel::MerkleRoots, 0).is_err());
+        // Outside the checked window the corruption goes unnoticed
+        assert!(store.verify_chain(&engine, CheckLevel::MerkleRoots, 1).is_ok());
+    }
+
+    #[test]
+    fn test_forge_index_build_and_drop() {
+        let tmp = TempDir::new().unwrap();
+        let store = ChainStore::new(tmp.path()).unwrap();
+        let engine = ConsensusEngine::new(0, 600);
+        store_test_chain(&store, &engine, 3);
+
+        assert!(!store.forge_index_enabled().unwrap());
+        assert!(store.lookup_forge(&[1u8; 32]).is_err());
+
+        assert_eq!(store.build_forge_index().unwrap(), 3);
+        let (height, forge) = store.lookup_forge(&[1u8; 32]).unwrap().unwrap();
+        assert_eq!(height, 1);
+        assert_eq!(forge.proof_hash, [1u8; 32]);
+        assert!(store.lookup_forge(&[9u8; 32]).unwrap().is_none());
+
+        assert_eq!(store.drop_forge_index().unwrap(), 3);
+        assert!(!store.forge_index_enabled().unwrap());
+    }
+
+    #[test]
+    fn test_header_index_chainwork() {
+        let tmp = TempDir::new().unwrap();
+        let store = ChainStore::new(tmp.path()).unwrap();
+        let engine = ConsensusEngine::new(0, 600);
+        store_test_chain(&store, &engine, 3);
+
+        let tip = store.get_header_index(&store.get_best_block().unwrap().unwrap()).unwrap().unwrap();
+        assert_eq!(tip.height, 2);
+        let unit = header_work(POW_LIMIT_BITS);
+        assert_eq!(tip.chainwork(), unit + unit + unit);
+
+        // Headers whose parent is unknown can't be indexed
+        let mut orphan = store.load_block(2).unwrap().unwrap().header;
+        orphan.prev_block_hash = [0xee; 32];
+        assert!(store.index_header(&[0xff; 32], &orphan).is_err());
+    }
+
+    #[test]
+    fn test_prophecy_registry() {
+        let tmp = TempDir::new().unwrap();
+        let store = ChainStore::new(tmp.path()).unwrap();
+        store_test_chain(&store, &ConsensusEngine::new(0, 600), 2);
+
+        // Owners are written with the engine state as blocks are applied
+        let engine = ConsensusEngine::from_store(&store, 0, 600).unwrap();
+        let first = store.load_block(0).unwrap().unwrap();
+        engine.apply_block(&first).unwrap();
+
+        let prophecy_hash = prophecy_registry_hash(&first.forges[0].prophecy);
+        let owner = store.get_prophecy_owner(&prophecy_hash).unwrap().unwrap();
+        assert_eq!(owner.height, 0);
+        assert_eq!(owner.proof_hash, [0u8; 32]);
+
+        // and loaded with it on restart
+        assert_eq!(store.prophecy_owners().unwrap().len(), 1);
+        let reopened = ConsensusEngine::from_store(&store, 0, 600).unwrap();
+        assert_eq!(reopened.prophecy_owner(&prophecy_hash), Some(owner));
+    }
+
+    #[test]
+    fn test_check_level_from_u8() {
+        assert_eq!(CheckLevel::try_from(3).unwrap(), CheckLevel::ProofOfForge);
+        assert!(CheckLevel::try_from(4).is_err());
+    }
 }
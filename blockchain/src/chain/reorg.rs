@@ -0,0 +1,216 @@
+//! Guardrail requiring operator confirmation for deep reorganizations
+
+use super::{ChainStore, ForkPoint};
+use crate::events::{Alert, AlertSeverity, EventBus, NodeEvent, ReorgEvent};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use anyhow::Result;
+
+/// Default deepest reorg applied without operator confirmation
+pub const DEFAULT_MAX_REORG_DEPTH: u64 = 100;
+
+/// Reorg held back until an operator accepts it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingReorg {
+    pub tip_hash: [u8; 32],
+    pub fork: ForkPoint,
+    pub detected_at: u64,
+}
+
+/// Outcome of checking a candidate tip against the guard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReorgDecision {
+    /// Switch to the candidate chain
+    Proceed(ForkPoint),
+    /// Stay on the current chain until `acceptreorg` is called
+    AwaitingConfirmation(PendingReorg),
+}
+
+/// Holds back reorgs deeper than `max_depth` until accepted
+pub struct ReorgGuard {
+    /// Deepest reorg applied automatically (0 = no limit)
+    max_depth: u64,
+    pending: Mutex<HashMap<[u8; 32], PendingReorg>>,
+    accepted: Mutex<HashSet<[u8; 32]>>,
+    events: EventBus,
+}
+
+impl ReorgGuard {
+    /// Create a guard that alerts through `events`
+    pub fn new(max_depth: u64, events: EventBus) -> Self {
+        Self {
+            max_depth,
+            pending: Mutex::new(HashMap::new()),
+            accepted: Mutex::new(HashSet::new()),
+            events,
+        }
+    }
+
+    /// Deepest reorg applied without confirmation (0 = no limit)
+    pub fn max_depth(&self) -> u64 {
+        self.max_depth
+    }
+
+    /// Decide whether the chain may switch from `current_tip` to
+    /// `candidate_tip`. The first time a deep reorg is seen a critical
+    /// alert is raised; reorgs allowed to proceed are published as
+    /// `NodeEvent::Reorg`, since the caller switches branches on them.
+    pub fn check(
+        &self,
+        store: &ChainStore,
+        current_tip: &[u8; 32],
+        candidate_tip: &[u8; 32],
+    ) -> Result<ReorgDecision> {
+        let fork = store.find_fork(current_tip, candidate_tip)?;
+        if self.max_depth == 0 || fork.disconnect <= self.max_depth {
+            return Ok(self.proceed(fork, candidate_tip));
+        }
+        if self.accepted.lock().unwrap().remove(candidate_tip) {
+            self.pending.lock().unwrap().remove(candidate_tip);
+            tracing::warn!(
+                "Applying operator-accepted reorg of depth {} to {}",
+                fork.disconnect,
+                hex::encode(candidate_tip)
+            );
+            return Ok(self.proceed(fork, candidate_tip));
+        }
+
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(existing) = pending.get(candidate_tip) {
+            return Ok(ReorgDecision::AwaitingConfirmation(existing.clone()));
+        }
+
+        let reorg = PendingReorg {
+            tip_hash: *candidate_tip,
+            fork,
+            detected_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        };
+        pending.insert(*candidate_tip, reorg.clone());
+
+        self.events.alert(Alert::new(
+            AlertSeverity::Critical,
+            "deep_reorg",
+            format!(
+                "Reorg of depth {} (max {}) to {} requires acceptreorg",
+                fork.disconnect,
+                self.max_depth,
+                hex::encode(candidate_tip)
+            ),
+            json!({
+                "tip_hash": hex::encode(candidate_tip),
+                "fork_hash": hex::encode(fork.hash),
+                "fork_height": fork.height,
+                "depth": fork.disconnect,
+                "max_depth": self.max_depth,
+            }),
+        ));
+        Ok(ReorgDecision::AwaitingConfirmation(reorg))
+    }
+
+    fn proceed(&self, fork: ForkPoint, candidate_tip: &[u8; 32]) -> ReorgDecision {
+        if fork.disconnect > 0 {
+            self.events.publish(NodeEvent::Reorg(ReorgEvent {
+                fork_height: fork.height,
+                fork_hash: hex::encode(fork.hash),
+                tip_hash: hex::encode(candidate_tip),
+                disconnected: fork.disconnect,
+                connected: fork.connect,
+            }));
+        }
+        ReorgDecision::Proceed(fork)
+    }
+
+    /// Accept a pending reorg to `tip_hash`; it is applied the next time
+    /// the tip is checked. Returns `false` if no such reorg is pending.
+    pub fn accept(&self, tip_hash: &[u8; 32]) -> bool {
+        if !self.pending.lock().unwrap().contains_key(tip_hash) {
+            return false;
+        }
+        self.accepted.lock().unwrap().insert(*tip_hash);
+        true
+    }
+
+    /// Reorgs waiting for confirmation
+    pub fn pending(&self) -> Vec<PendingReorg> {
+        self.pending.lock().unwrap().values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::testing;
+    use tempfile::TempDir;
+
+    /// Index a branch of `length` headers on top of `parent`, returning the
+    /// tip hash. Hashes are synthetic; only the index links matter here.
+    fn index_branch(store: &ChainStore, parent: [u8; 32], start_height: u64, length: u64, tag: u8) -> [u8; 32] {
+        let mut prev = parent;
+        for height in start_height..start_height + length {
+            let header = testing::header(height, prev);
+            let mut hash = [tag; 32];
+            hash[..8].copy_from_slice(&height.to_be_bytes());
+            store.index_header(&hash, &header).unwrap();
+            prev = hash;
+        }
+        prev
+    }
+
+    #[test]
+    fn test_deep_reorg_requires_acceptance() {
+        let tmp = TempDir::new().unwrap();
+        let store = ChainStore::new(tmp.path()).unwrap();
+
+        let genesis = index_branch(&store, [0u8; 32], 0, 1, 0);
+        let current = index_branch(&store, genesis, 1, 5, 1);
+        let shallow = index_branch(&store, genesis, 1, 6, 2);
+        let fork_at_3 = {
+            let mut base = [1u8; 32];
+            base[..8].copy_from_slice(&3u64.to_be_bytes());
+            base
+        };
+        let short_fork = index_branch(&store, fork_at_3, 4, 3, 3);
+
+        let events = EventBus::new();
+        let mut alerts = events.subscribe();
+        let guard = ReorgGuard::new(3, events);
+
+        // Fork at height 3 disconnects 2 blocks: allowed
+        let ReorgDecision::Proceed(fork) = guard.check(&store, &current, &short_fork).unwrap() else {
+            panic!("shallow reorg should proceed");
+        };
+        assert_eq!((fork.height, fork.disconnect, fork.connect), (3, 2, 3));
+        let Ok(NodeEvent::Reorg(reorg)) = alerts.try_recv() else {
+            panic!("proceeding reorg should be published");
+        };
+        assert_eq!((reorg.fork_height, reorg.disconnected), (3, 2));
+
+        // Fork at genesis disconnects 5 blocks: held back with one alert
+        assert!(matches!(
+            guard.check(&store, &current, &shallow).unwrap(),
+            ReorgDecision::AwaitingConfirmation(_)
+        ));
+        assert!(matches!(
+            guard.check(&store, &current, &shallow).unwrap(),
+            ReorgDecision::AwaitingConfirmation(_)
+        ));
+        let NodeEvent::Alert(alert) = alerts.try_recv().unwrap() else {
+            panic!("expected an alert");
+        };
+        assert_eq!(alert.kind, "deep_reorg");
+        assert_eq!(alert.data["depth"], 5);
+        assert!(alerts.try_recv().is_err());
+
+        assert!(!guard.accept(&[0xaa; 32]));
+        assert!(guard.accept(&shallow));
+        assert!(matches!(
+            guard.check(&store, &current, &shallow).unwrap(),
+            ReorgDecision::Proceed(_)
+        ));
+        assert!(guard.pending().is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+//! Deterministic chain replay
+//!
+//! Re-executes the stored chain from genesis through a fresh consensus
+//! engine and compares the state it reaches after every block with the
+//! state roots the live node recorded when it connected that block: the
+//! ledger set hash (`ledgerinfo:`) and the used-proofs set hash
+//! (`uproofs:`), and with the state root in the block's header when it
+//! carries one. The first height where they differ, or where a stored
+//! block no longer passes the requested checks, is reported. Heights
+//! connected before a root was recorded are compared on the roots that
+//! exist.
+
+use super::{ChainStore, CheckLevel};
+use crate::consensus::{Block, ConsensusEngine};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+/// State commitments at a height
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StateRoot {
+    pub height: u64,
+    pub ledger_set_hash: Option<[u8; 32]>,
+    pub used_proofs_hash: Option<[u8; 32]>,
+}
+
+/// First point where the replayed chain departs from the live database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Divergence {
+    pub height: u64,
+    pub reason: String,
+}
+
+/// Outcome of a chain replay
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReplayReport {
+    pub level: CheckLevel,
+    pub blocks_replayed: u64,
+    /// Roots recorded by the live node at the last replayed height
+    pub live: StateRoot,
+    /// Roots the replay reached at the last replayed height
+    pub replayed: StateRoot,
+    pub divergence: Option<Divergence>,
+}
+
+impl ChainStore {
+    /// Replay every stored block through `engine`, which must hold genesis
+    /// state, checking each block up to `level` before applying it.
+    ///
+    /// Stops at the first divergence. Fails outright only if the database
+    /// can't be read or has no history to replay (for example a store that
+    /// started from a ledger snapshot).
+    pub fn replay(&self, engine: &ConsensusEngine, level: CheckLevel) -> Result<ReplayReport> {
+        if self.get_best_block()?.is_none() {
+            return Err(anyhow!("Chain database has no blocks to replay"));
+        }
+        if self.get_block(0)?.is_none() {
+            return Err(anyhow!("Chain database has no genesis block (loaded from a snapshot?)"));
+        }
+        let tip = self.get_height()?;
+
+        let mut report = ReplayReport {
+            level,
+            blocks_replayed: 0,
+            live: StateRoot::default(),
+            replayed: StateRoot::default(),
+            divergence: None,
+        };
+        for height in 0..=tip {
+            let Some(block) = self.load_block(height)? else {
+                report.divergence = Some(Divergence {
+                    height,
+                    reason: "block is missing".to_string(),
+                });
+                break;
+            };
+            let parent_hash = if height == 0 { [0u8; 32] } else { engine.get_tip_hash() };
+            if let Err(e) = check_block(engine, &block, height, &parent_hash, level) {
+                report.divergence = Some(Divergence {
+                    height,
+                    reason: format!("stored block fails validation: {}", e),
+                });
+                break;
+            }
+            engine.apply_block(&block)?;
+            report.blocks_replayed += 1;
+
+            report.live = StateRoot {
+                height,
+                ledger_set_hash: self.get_ledger_info(height)?.map(|info| info.set_hash),
+                used_proofs_hash: self.get_used_proofs_hash(height)?,
+            };
+            report.replayed = StateRoot {
+                height,
+                ledger_set_hash: Some(engine.get_ledger_info().set_hash),
+                used_proofs_hash: Some(engine.used_proofs_hash()),
+            };
+            if let Some(reason) = compare_roots(&report.live, &report.replayed) {
+                report.divergence = Some(Divergence { height, reason });
+                break;
+            }
+            if let Some(committed) = block.header.state_root {
+                let replayed = engine.state_root();
+                if committed != replayed {
+                    report.divergence = Some(Divergence {
+                        height,
+                        reason: format!(
+                            "header state root mismatch (header {}, replayed {})",
+                            hex::encode(committed),
+                            hex::encode(replayed)
+                        ),
+                    });
+                    break;
+                }
+            }
+
+            if height > 0 && height % 10_000 == 0 {
+                tracing::info!("Replayed {} of {} blocks", height, tip);
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// The checks `verify_chain` applies at `level`, run against the replayed
+/// state instead of the stored links
+fn check_block(
+    engine: &ConsensusEngine,
+    block: &Block,
+    height: u64,
+    parent_hash: &[u8; 32],
+    level: CheckLevel,
+) -> Result<()> {
+    if block.header.height != height {
+        return Err(anyhow!("block claims height {}", block.header.height));
+    }
+    match level {
+        CheckLevel::Metadata => Ok(()),
+        CheckLevel::HeaderLinks => engine.validate_header(&block.header, parent_hash, height),
+        CheckLevel::MerkleRoots => engine.prevalidate_block(block, parent_hash),
+        CheckLevel::ProofOfForge => engine.validate_block(block, parent_hash).map(|_| ()),
+    }
+}
+
+/// Why two roots at the same height differ, if they do. Roots the live
+/// node never recorded are not compared.
+fn compare_roots(live: &StateRoot, replayed: &StateRoot) -> Option<String> {
+    let mismatch = |name: &str, live: Option<[u8; 32]>, replayed: Option<[u8; 32]>| match (live, replayed) {
+        (Some(live), Some(replayed)) if live != replayed => Some(format!(
+            "{} mismatch (live {}, replayed {})",
+            name,
+            hex::encode(live),
+            hex::encode(replayed)
+        )),
+        _ => None,
+    };
+    mismatch("ledger set hash", live.ledger_set_hash, replayed.ledger_set_hash)
+        .or_else(|| mismatch("used-proofs hash", live.used_proofs_hash, replayed.used_proofs_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::MemoryStore;
+    use crate::consensus::{testing, ForgeTransaction};
+
+    /// Connect `length` blocks the way the node does, recording state roots
+    fn live_chain(length: u64) -> ChainStore {
+        let store = ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap();
+        let engine = ConsensusEngine::new(0, 600);
+        for height in 0..length {
+            let forges = vec![ForgeTransaction {
+                prophecy: format!("replay test prophecy {}", height),
+                derived_key: vec![height as u8],
+                taproot_address: "bc1p...".to_string(),
+                proof_hash: [height as u8; 32],
+                timestamp: 1000 + height,
+                signature: vec![],
+                not_before_height: 0,
+                tempering: Default::default(),
+                salt: [0; 32],
+            }];
+            let prev_block_hash = if height == 0 { [0u8; 32] } else { engine.get_tip_hash() };
+            let mut block = testing::block(height, prev_block_hash, forges);
+            block.header.merkle_root = engine.compute_merkle_root(&block.forges);
+            assert!(engine.grind_header(&mut block.header, 1_000));
+            engine.apply_block(&block).unwrap();
+
+            let hash = engine.compute_block_hash(&block.header);
+            store.put_block(height, &block.encode()).unwrap();
+            store.put_block_hash(&hash, height).unwrap();
+            store.put_ledger_info(&engine.get_ledger_info()).unwrap();
+            store.put_used_proofs_hash(height, &engine.used_proofs_hash()).unwrap();
+            store.set_height(height).unwrap();
+            store.set_best_block(&hash).unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn test_replay_matches_and_finds_first_divergence() {
+        let store = live_chain(4);
+        let report = store.replay(&ConsensusEngine::new(0, 600), CheckLevel::MerkleRoots).unwrap();
+        assert_eq!(report.divergence, None);
+        assert_eq!(report.blocks_replayed, 4);
+        assert_eq!(report.live, report.replayed);
+        assert_eq!(report.replayed.height, 3);
+
+        // A corrupted root is reported at its height, not at the tip
+        store.put_used_proofs_hash(2, &[9u8; 32]).unwrap();
+        let report = store.replay(&ConsensusEngine::new(0, 600), CheckLevel::MerkleRoots).unwrap();
+        let divergence = report.divergence.unwrap();
+        assert_eq!(divergence.height, 2);
+        assert!(divergence.reason.contains("used-proofs hash"));
+        assert_eq!(report.blocks_replayed, 3);
+
+        // Full validation rejects the test chain's forges at genesis
+        let report = live_chain(2).replay(&ConsensusEngine::new(0, 600), CheckLevel::ProofOfForge).unwrap();
+        assert_eq!(report.divergence.unwrap().height, 0);
+        assert_eq!(report.blocks_replayed, 0);
+    }
+
+    #[test]
+    fn test_replay_needs_history() {
+        let empty = ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap();
+        assert!(empty.replay(&ConsensusEngine::new(0, 600), CheckLevel::MerkleRoots).is_err());
+
+        // Snapshot-based stores keep no blocks below the snapshot
+        let store = live_chain(2);
+        store.delete_block(0).unwrap();
+        assert!(store.replay(&ConsensusEngine::new(0, 600), CheckLevel::MerkleRoots).is_err());
+    }
+}
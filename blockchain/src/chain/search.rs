@@ -0,0 +1,289 @@
+//! Forge word index
+//!
+//! Optional explorer index from the words of each forge's prophecy to the
+//! forges that contain them. Every word of every forge gets its own key, so
+//! the index is opt-in (`chain.searchindex`) like the forge index. Keys are
+//! `word:<token> 0x00 <height> <proof hash>`, so an exact word or a word
+//! prefix is a single range scan.
+
+use super::{ChainStore, WriteBatch};
+use crate::consensus::Block;
+use anyhow::{anyhow, Result};
+use std::collections::BTreeSet;
+
+const WORD_INDEX_PREFIX: &[u8] = b"word:";
+const SEARCH_INDEX_KEY: &[u8] = b"meta:searchindex";
+
+/// Longest indexed token; longer words are not indexed
+pub const MAX_TOKEN_LEN: usize = 64;
+
+/// Shortest word prefix accepted in a query, to keep prefix scans bounded
+pub const MIN_PREFIX_LEN: usize = 2;
+
+/// Most words accepted in a query
+pub const MAX_QUERY_TERMS: usize = 8;
+
+/// Lowercased alphanumeric words of `text`, without duplicates
+pub fn tokenize(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && word.len() <= MAX_TOKEN_LEN)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// One word of a search query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchTerm {
+    pub word: String,
+    /// Match any word starting with `word` rather than the word itself
+    pub prefix: bool,
+}
+
+/// Parse a query of space-separated words, where a trailing `*` makes a
+/// word a prefix match. Forges must match every term.
+pub fn parse_query(query: &str) -> Result<Vec<SearchTerm>> {
+    let mut terms = Vec::new();
+    for raw in query.split_whitespace() {
+        let (raw, prefix) = match raw.strip_suffix('*') {
+            Some(stem) => (stem, true),
+            None => (raw, false),
+        };
+        let word: String = raw.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase();
+        if word.is_empty() || word.len() > MAX_TOKEN_LEN {
+            return Err(anyhow!("Invalid search term {:?}", raw));
+        }
+        if prefix && word.chars().count() < MIN_PREFIX_LEN {
+            return Err(anyhow!("Prefix {:?} is shorter than {} characters", word, MIN_PREFIX_LEN));
+        }
+        terms.push(SearchTerm { word, prefix });
+    }
+    if terms.is_empty() {
+        return Err(anyhow!("Empty search query"));
+    }
+    if terms.len() > MAX_QUERY_TERMS {
+        return Err(anyhow!("Search query has more than {} terms", MAX_QUERY_TERMS));
+    }
+    Ok(terms)
+}
+
+impl ChainStore {
+    /// Whether the word index exists
+    pub fn search_index_enabled(&self) -> Result<bool> {
+        Ok(self.db.get(SEARCH_INDEX_KEY)?.is_some())
+    }
+
+    /// Number of blocks (from genesis) covered by the word index, if it exists
+    pub fn search_index_progress(&self) -> Result<Option<u64>> {
+        match self.db.get(SEARCH_INDEX_KEY)? {
+            Some(bytes) => {
+                let height_bytes: [u8; 8] = bytes.try_into()
+                    .map_err(|_| anyhow!("Invalid search index progress"))?;
+                Ok(Some(u64::from_be_bytes(height_bytes)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Add a connected block's forges to the word index, if it is enabled
+    pub fn index_block_words(&self, block: &Block) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        self.batch_index_words(&mut batch, block)?;
+        self.db.write(batch)
+    }
+
+    /// Add the word index entries of a connected block to `batch`
+    pub(super) fn batch_index_words(&self, batch: &mut WriteBatch, block: &Block) -> Result<()> {
+        if !self.search_index_enabled()? {
+            return Ok(());
+        }
+        for key in Self::word_keys(block) {
+            batch.put(key, b"");
+        }
+        batch.put(SEARCH_INDEX_KEY, (block.header.height + 1).to_be_bytes());
+        Ok(())
+    }
+
+    /// Remove a disconnected block's forges from the word index
+    pub fn unindex_block_words(&self, block: &Block) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        self.batch_unindex_words(&mut batch, block)?;
+        self.db.write(batch)
+    }
+
+    /// Add the removal of a disconnected block's word index entries to
+    /// `batch`
+    pub(super) fn batch_unindex_words(&self, batch: &mut WriteBatch, block: &Block) -> Result<()> {
+        let Some(next) = self.search_index_progress()? else {
+            return Ok(());
+        };
+        for key in Self::word_keys(block) {
+            batch.delete(key);
+        }
+        batch.put(SEARCH_INDEX_KEY, next.min(block.header.height).to_be_bytes());
+        Ok(())
+    }
+
+    /// Build the word index from all stored blocks.
+    ///
+    /// Resumes from the last indexed block if a build was interrupted.
+    pub fn build_search_index(&self) -> Result<u64> {
+        let start = match self.search_index_progress()? {
+            Some(next) => next,
+            None => {
+                self.db.put(SEARCH_INDEX_KEY, &0u64.to_be_bytes())?;
+                0
+            }
+        };
+
+        let mut indexed = start;
+        for height in start..=self.get_height()? {
+            let Some(block) = self.load_block(height)? else {
+                break;
+            };
+            self.index_block_words(&block)?;
+            indexed = height + 1;
+        }
+
+        tracing::info!("Search index covers {} blocks", indexed);
+        Ok(indexed)
+    }
+
+    /// Delete the word index
+    pub fn drop_search_index(&self) -> Result<usize> {
+        let keys: Vec<Vec<u8>> = self
+            .prefix_iter(WORD_INDEX_PREFIX, false)
+            .map(|entry| entry.map(|(key, _)| key))
+            .collect::<Result<_>>()?;
+
+        let mut batch = WriteBatch::default();
+        for key in &keys {
+            batch.delete(key);
+        }
+        batch.delete(SEARCH_INDEX_KEY);
+        self.db.write(batch)?;
+
+        tracing::info!("Search index dropped ({} entries)", keys.len());
+        Ok(keys.len())
+    }
+
+    /// Height and proof hash of every forge matching all terms of a query,
+    /// newest first
+    pub fn search_forges(&self, terms: &[SearchTerm]) -> Result<Vec<(u64, [u8; 32])>> {
+        if !self.search_index_enabled()? {
+            return Err(anyhow!("Search index is disabled"));
+        }
+
+        let mut matches: Option<BTreeSet<(u64, [u8; 32])>> = None;
+        for term in terms {
+            let found = self.term_matches(term)?;
+            matches = Some(match matches {
+                Some(previous) => previous.intersection(&found).copied().collect(),
+                None => found,
+            });
+        }
+        Ok(matches.unwrap_or_default().into_iter().rev().collect())
+    }
+
+    fn term_matches(&self, term: &SearchTerm) -> Result<BTreeSet<(u64, [u8; 32])>> {
+        let mut prefix = [WORD_INDEX_PREFIX, term.word.as_bytes()].concat();
+        if !term.prefix {
+            prefix.push(0);
+        }
+
+        let mut found = BTreeSet::new();
+        for entry in self.prefix_iter(&prefix, false) {
+            let (key, _) = entry?;
+            // Height and proof hash follow the token's terminator
+            let tail = key
+                .len()
+                .checked_sub(40)
+                .filter(|&start| start > 0 && key[start - 1] == 0)
+                .map(|start| &key[start..])
+                .ok_or_else(|| anyhow!("Malformed search index key {}", hex::encode(&key)))?;
+            let height = u64::from_be_bytes(tail[..8].try_into().expect("8-byte slice"));
+            let proof_hash: [u8; 32] = tail[8..].try_into().expect("32-byte slice");
+            found.insert((height, proof_hash));
+        }
+        Ok(found)
+    }
+
+    fn word_keys(block: &Block) -> Vec<Vec<u8>> {
+        let height = block.header.height.to_be_bytes();
+        block
+            .forges
+            .iter()
+            .flat_map(|forge| {
+                tokenize(&forge.prophecy).into_iter().map(move |token| {
+                    [WORD_INDEX_PREFIX, token.as_bytes(), &[0], &height, &forge.proof_hash].concat()
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::{testing, ForgeTransaction};
+    use crate::chain::MemoryStore;
+
+    fn block(height: u64, prophecies: &[&str]) -> Block {
+        let forges = prophecies
+            .iter()
+            .enumerate()
+            .map(|(i, prophecy)| ForgeTransaction {
+                prophecy: prophecy.to_string(),
+                derived_key: vec![],
+                taproot_address: "bc1p...".to_string(),
+                proof_hash: [height as u8 * 16 + i as u8; 32],
+                timestamp: 1000,
+                signature: vec![],
+                not_before_height: 0,
+                tempering: Default::default(),
+                salt: [0; 32],
+            })
+            .collect();
+        testing::block(height, [0u8; 32], forges)
+    }
+
+    #[test]
+    fn test_exact_and_prefix_search() {
+        let store = ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap();
+        let blocks = [
+            block(0, &["Sword legend pull"]),
+            block(1, &["sword, stone", "legendary kingdom"]),
+            block(2, &["swordfish legend"]),
+        ];
+        for block in &blocks {
+            store.put_block(block.header.height, &block.encode()).unwrap();
+            store.set_height(block.header.height).unwrap();
+        }
+
+        assert!(store.search_forges(&parse_query("sword").unwrap()).is_err());
+        assert_eq!(store.build_search_index().unwrap(), 3);
+
+        let heights = |query: &str| -> Vec<u64> {
+            let hits = store.search_forges(&parse_query(query).unwrap()).unwrap();
+            hits.iter().map(|(height, _)| *height).collect()
+        };
+        assert_eq!(heights("sword"), vec![1, 0]);
+        assert_eq!(heights("SWORD*"), vec![2, 1, 0]);
+        assert_eq!(heights("sword* legend"), vec![2, 0]);
+        assert_eq!(heights("legend*"), vec![2, 1, 0]);
+        assert!(heights("dragon").is_empty());
+
+        let hits = store.search_forges(&parse_query("legend*").unwrap()).unwrap();
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits[1], (1, [17; 32]));
+
+        // Disconnecting the tip block removes its words
+        store.unindex_block_words(&blocks[2]).unwrap();
+        assert_eq!(heights("sword*"), vec![1, 0]);
+        assert_eq!(store.search_index_progress().unwrap(), Some(2));
+
+        assert!(parse_query("s*").is_err());
+        assert!(parse_query("  ").is_err());
+        assert!(store.drop_search_index().unwrap() > 0);
+        assert!(!store.search_index_enabled().unwrap());
+    }
+}
@@ -0,0 +1,373 @@
+//! Persisted consensus engine state
+//!
+//! An engine opened with `ConsensusEngine::from_store` writes what it needs
+//! to resume back to the store as it applies blocks: each used proof hash
+//! (`used:`), each unspent output (`utxo:`), each prophecy owner
+//! (`owner:`) and a record of the height,
+//! tip, difficulty and chainwork (`meta:consensus`). A restarted node loads
+//! these instead of re-validating every stored block, so replay protection
+//! survives restarts without a full proof-of-forge replay.
+//!
+//! The same batch stores an undo record for the block (`undo:`, like
+//! Bitcoin's rev files): the engine record before it, the outputs, used
+//! proofs and prophecy owners it added, and the outputs its transfers spent.
+//! `ConsensusEngine::disconnect_block` reverses the block from that record
+//! alone, without replaying history, and removes it with what it undid.
+
+use super::{ChainStore, ProphecyOwner, WriteBatch};
+use crate::consensus::SnapshotStatus;
+use crate::ledger::{LedgerOutput, LedgerSnapshot, OutPoint};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+const USED_PROOF_PREFIX: &[u8] = b"used:";
+const UTXO_PREFIX: &[u8] = b"utxo:";
+const CONSENSUS_RECORD_KEY: &[u8] = b"meta:consensus";
+const UNDO_PREFIX: &[u8] = b"undo:";
+
+/// Engine state that isn't derivable from the used proofs and outputs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsensusRecord {
+    pub height: u64,
+    pub tip_hash: [u8; 32],
+    pub difficulty: u32,
+    pub total_forges: u64,
+    /// Cumulative work of applied blocks (big-endian)
+    pub chainwork: [u8; 32],
+    /// Snapshot the state was started from, if any
+    pub snapshot: Option<SnapshotStatus>,
+}
+
+/// What disconnecting a block has to reverse
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockUndo {
+    pub height: u64,
+    pub block_hash: [u8; 32],
+    /// Engine record before the block was applied
+    pub previous: ConsensusRecord,
+    /// Outputs the block created
+    pub created_outputs: Vec<OutPoint>,
+    /// Proof hashes the block marked used
+    pub used_proofs: Vec<[u8; 32]>,
+    /// Prophecies, by `prophecy_registry_hash`, the block forged first
+    pub registered_prophecies: Vec<[u8; 32]>,
+    /// Outputs the block's transfers spent, restored on disconnect
+    pub spent_outputs: Vec<(OutPoint, LedgerOutput)>,
+}
+
+impl ChainStore {
+    /// Record newly used proofs, created and spent outputs and registered
+    /// prophecy owners together with the engine record they lead to and the
+    /// undo record of the block that made them, atomically with `batch`
+    #[allow(clippy::too_many_arguments)]
+    pub fn put_consensus_state(
+        &self,
+        mut batch: WriteBatch,
+        record: &ConsensusRecord,
+        used_proofs: &[([u8; 32], u64)],
+        outputs: &[(OutPoint, LedgerOutput)],
+        spent: &[OutPoint],
+        prophecy_owners: &[([u8; 32], ProphecyOwner)],
+        undo: Option<&BlockUndo>,
+    ) -> Result<()> {
+        if let Some(undo) = undo {
+            batch.put(Self::undo_key(undo.height), bincode::serialize(undo)?);
+        }
+        for (proof_hash, height) in used_proofs {
+            batch.put(Self::used_proof_key(proof_hash), height.to_be_bytes());
+        }
+        for (outpoint, output) in outputs {
+            batch.put(Self::utxo_key(outpoint), bincode::serialize(output)?);
+        }
+        for outpoint in spent {
+            batch.delete(Self::utxo_key(outpoint));
+        }
+        for (prophecy_hash, owner) in prophecy_owners {
+            batch.put(Self::prophecy_owner_key(prophecy_hash), bincode::serialize(owner)?);
+        }
+        batch.put(CONSENSUS_RECORD_KEY, bincode::serialize(record)?);
+        self.db.write(batch)
+    }
+
+    /// Undo record of the block applied at `height`, if one was stored
+    pub fn load_undo(&self, height: u64) -> Result<Option<BlockUndo>> {
+        match self.db.get(&Self::undo_key(height))? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Remove what the block of `undo` added and restore the engine record
+    /// before it, dropping the undo record, atomically with `batch`
+    pub fn revert_consensus_state(&self, mut batch: WriteBatch, undo: &BlockUndo) -> Result<()> {
+        for proof_hash in &undo.used_proofs {
+            batch.delete(Self::used_proof_key(proof_hash));
+        }
+        for outpoint in &undo.created_outputs {
+            batch.delete(Self::utxo_key(outpoint));
+        }
+        for (outpoint, output) in &undo.spent_outputs {
+            batch.put(Self::utxo_key(outpoint), bincode::serialize(output)?);
+        }
+        for prophecy_hash in &undo.registered_prophecies {
+            batch.delete(Self::prophecy_owner_key(prophecy_hash));
+        }
+        batch.delete(Self::undo_key(undo.height));
+        batch.put(CONSENSUS_RECORD_KEY, bincode::serialize(&undo.previous)?);
+        self.db.write(batch)
+    }
+
+    /// The persisted engine record, if an engine has written one
+    pub fn consensus_record(&self) -> Result<Option<ConsensusRecord>> {
+        match self.db.get(CONSENSUS_RECORD_KEY)? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The persisted engine record with the used proofs, outputs and
+    /// prophecy owners it covers, in snapshot form
+    pub fn load_consensus_state(&self) -> Result<Option<(ConsensusRecord, LedgerSnapshot)>> {
+        let Some(record) = self.consensus_record()? else {
+            return Ok(None);
+        };
+
+        let mut used_proofs = Vec::new();
+        for entry in self.prefix_iter(USED_PROOF_PREFIX, false) {
+            let (key, value) = entry?;
+            let proof_hash = <[u8; 32]>::try_from(&key[USED_PROOF_PREFIX.len()..])
+                .map_err(|_| anyhow!("Malformed used proof key {}", hex::encode(&key)))?;
+            let height = <[u8; 8]>::try_from(value.as_slice())
+                .map(u64::from_be_bytes)
+                .map_err(|_| anyhow!("Corrupt used proof {}", hex::encode(proof_hash)))?;
+            used_proofs.push((proof_hash, height));
+        }
+
+        let mut outputs = Vec::new();
+        for entry in self.prefix_iter(UTXO_PREFIX, false) {
+            let (key, value) = entry?;
+            let outpoint = Self::decode_utxo_key(&key)?;
+            outputs.push((outpoint, bincode::deserialize(&value)?));
+        }
+
+        let snapshot = LedgerSnapshot {
+            height: record.height,
+            block_hash: record.tip_hash,
+            difficulty: record.difficulty,
+            chainwork: record.chainwork,
+            outputs,
+            used_proofs,
+            prophecy_owners: self.prophecy_owners()?,
+        };
+        Ok(Some((record, snapshot)))
+    }
+
+    /// Whether the persisted engine state (if any) is at this store's tip.
+    /// It falls behind or runs ahead only if the node stopped between
+    /// applying a block and storing it.
+    pub fn consensus_state_matches_tip(&self) -> Result<bool> {
+        let Some(record) = self.consensus_record()? else {
+            return Ok(true);
+        };
+        Ok(match self.get_best_block()? {
+            Some(best) => record.tip_hash == best && record.height == self.get_height()?,
+            // Only a snapshot-based store has state without blocks
+            None => record.snapshot.is_some_and(|snapshot| snapshot.height == record.height),
+        })
+    }
+
+    /// Remove the persisted engine state, so it is rebuilt from blocks
+    pub fn clear_consensus_state(&self) -> Result<usize> {
+        let mut batch = WriteBatch::default();
+        for prefix in [USED_PROOF_PREFIX, UTXO_PREFIX, UNDO_PREFIX, super::PROPHECY_OWNER_PREFIX] {
+            for entry in self.prefix_iter(prefix, false) {
+                batch.delete(entry?.0);
+            }
+        }
+        let removed = batch.len();
+        batch.delete(CONSENSUS_RECORD_KEY);
+        self.db.write(batch)?;
+        Ok(removed)
+    }
+
+    fn used_proof_key(proof_hash: &[u8; 32]) -> Vec<u8> {
+        [USED_PROOF_PREFIX, proof_hash].concat()
+    }
+
+    fn undo_key(height: u64) -> Vec<u8> {
+        [UNDO_PREFIX, &height.to_be_bytes()].concat()
+    }
+
+    fn utxo_key(outpoint: &OutPoint) -> Vec<u8> {
+        [UTXO_PREFIX, &outpoint.txid, &outpoint.vout.to_be_bytes()].concat()
+    }
+
+    fn decode_utxo_key(key: &[u8]) -> Result<OutPoint> {
+        let body = &key[UTXO_PREFIX.len()..];
+        if body.len() != 36 {
+            return Err(anyhow!("Malformed output key {}", hex::encode(key)));
+        }
+        let (txid, vout) = body.split_at(32);
+        Ok(OutPoint {
+            txid: txid.try_into()?,
+            vout: u32::from_be_bytes(vout.try_into()?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::MemoryStore;
+    use crate::consensus::{testing, Block, ConsensusEngine, ForgeTransaction};
+
+    fn block(engine: &ConsensusEngine, height: u64) -> Block {
+        let forges = vec![ForgeTransaction {
+            prophecy: format!("persisted prophecy {}", height),
+            derived_key: vec![],
+            taproot_address: format!("bc1p{}", height),
+            proof_hash: [height as u8 + 1; 32],
+            timestamp: 1000 + height,
+            signature: vec![],
+            not_before_height: 0,
+            tempering: Default::default(),
+            salt: [0; 32],
+        }];
+        let prev_block_hash = if height == 0 { [0u8; 32] } else { engine.get_tip_hash() };
+        let mut block = testing::block(height, prev_block_hash, forges);
+        block.header.merkle_root = engine.compute_merkle_root(&block.forges);
+        block
+    }
+
+    /// Apply blocks and store them the way the node does
+    fn connect(store: &ChainStore, engine: &ConsensusEngine, height: u64) {
+        let block = block(engine, height);
+        let hash = engine.compute_block_hash(&block.header);
+        let effects = engine.block_effects(&block).unwrap();
+        let batch = store.connect_batch(&block, &hash, &effects.ledger_info, &effects.used_proofs_hash).unwrap();
+        engine.commit_block(&block, effects, batch).unwrap();
+    }
+
+    #[test]
+    fn test_engine_state_survives_restart() {
+        let store = ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap();
+        let engine = ConsensusEngine::from_store(&store, 2, 600).unwrap();
+        for height in 0..3 {
+            connect(&store, &engine, height);
+        }
+
+        let restarted = ConsensusEngine::from_store(&store, 0, 600).unwrap();
+        assert_eq!(restarted.get_height(), 2);
+        assert_eq!(restarted.get_tip_hash(), engine.get_tip_hash());
+        assert_eq!(restarted.get_difficulty(), 2);
+        assert_eq!(restarted.get_total_forges(), 3);
+        assert_eq!(restarted.get_chainwork(), engine.get_chainwork());
+        assert_eq!(restarted.used_proofs_hash(), engine.used_proofs_hash());
+        assert_eq!(restarted.state_root(), engine.state_root());
+        assert_eq!(restarted.get_ledger_info(), engine.get_ledger_info());
+
+        // The restarted engine keeps writing
+        connect(&store, &restarted, 3);
+        let again = ConsensusEngine::from_store(&store, 0, 600).unwrap();
+        assert_eq!(again.get_height(), 3);
+        assert_eq!(again.state_root(), restarted.state_root());
+
+        // Engines not opened from a store leave it alone
+        let detached = ConsensusEngine::new(0, 600);
+        detached.apply_block(&block(&detached, 0)).unwrap();
+        assert_eq!(store.consensus_record().unwrap().unwrap().height, 3);
+    }
+
+    #[test]
+    fn test_block_committed_with_engine_state() {
+        let store = ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap();
+        let engine = ConsensusEngine::from_store(&store, 0, 600).unwrap();
+        for height in 0..2 {
+            connect(&store, &engine, height);
+        }
+
+        let tip = block(&engine, 2);
+        let hash = engine.compute_block_hash(&tip.header);
+        let effects = engine.block_effects(&tip).unwrap();
+        let stale = effects.clone();
+        let batch = store.connect_batch(&tip, &hash, &effects.ledger_info, &effects.used_proofs_hash).unwrap();
+        engine.commit_block(&tip, effects, batch).unwrap();
+        assert_eq!((store.get_height().unwrap(), store.get_best_block().unwrap()), (2, Some(hash)));
+        assert_eq!(store.get_ledger_info(2).unwrap(), Some(engine.get_ledger_info()));
+        assert_eq!(store.get_used_proofs_hash(2).unwrap(), Some(engine.used_proofs_hash()));
+        assert_eq!(store.get_header_index(&hash).unwrap().unwrap().chainwork(), engine.get_chainwork());
+        assert!(store.consensus_state_matches_tip().unwrap());
+
+        // Effects worked out on an earlier tip are refused before anything
+        // is written
+        let record = store.consensus_record().unwrap();
+        let batch = store.connect_batch(&tip, &hash, &stale.ledger_info, &stale.used_proofs_hash).unwrap();
+        assert!(engine.commit_block(&tip, stale, batch).is_err());
+        assert_eq!(store.consensus_record().unwrap(), record);
+
+        // A detached engine has nowhere to write the block
+        let detached = ConsensusEngine::new(0, 600);
+        let genesis = block(&detached, 0);
+        let effects = detached.block_effects(&genesis).unwrap();
+        let hash = detached.compute_block_hash(&genesis.header);
+        let batch = store.connect_batch(&genesis, &hash, &effects.ledger_info, &effects.used_proofs_hash).unwrap();
+        assert!(detached.commit_block(&genesis, effects, batch).is_err());
+        assert_eq!(detached.get_height(), 0);
+        assert_eq!(detached.get_total_forges(), 0);
+    }
+
+    #[test]
+    fn test_disconnect_restores_state_from_undo() {
+        let store = ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap();
+        let engine = ConsensusEngine::from_store(&store, 2, 600).unwrap();
+        for height in 0..2 {
+            connect(&store, &engine, height);
+        }
+        let before = (engine.get_tip_hash(), engine.state_root(), engine.used_proofs_hash(), engine.get_chainwork());
+        let record = store.consensus_record().unwrap();
+
+        let tip = block(&engine, 2);
+        engine.apply_block(&tip).unwrap();
+        assert!(engine.disconnect_block(&block(&engine, 1)).is_err());
+        engine.disconnect_block(&tip).unwrap();
+        let after = (engine.get_tip_hash(), engine.state_root(), engine.used_proofs_hash(), engine.get_chainwork());
+        assert_eq!(after, before);
+        assert_eq!((engine.get_height(), engine.get_total_forges(), engine.used_proof_count()), (1, 2, 2));
+        assert_eq!(engine.prophecy_owner_count(), 2);
+        assert_eq!(store.consensus_record().unwrap(), record);
+        assert!(store.load_undo(2).unwrap().is_none());
+        let prophecy_hash = crate::crypto::prophecy_registry_hash(&tip.forges[0].prophecy);
+        assert!(store.get_prophecy_owner(&prophecy_hash).unwrap().is_none());
+
+        // The reverted store restarts at the same state, which can take
+        // the block again
+        let restarted = ConsensusEngine::from_store(&store, 0, 600).unwrap();
+        assert_eq!(restarted.state_root(), before.1);
+        restarted.apply_block(&tip).unwrap();
+        assert_eq!(restarted.get_height(), 2);
+
+        // Detached engines keep no undo records
+        let detached = ConsensusEngine::new(0, 600);
+        let genesis = block(&detached, 0);
+        detached.apply_block(&genesis).unwrap();
+        assert!(detached.disconnect_block(&genesis).is_err());
+    }
+
+    #[test]
+    fn test_consensus_state_checked_against_tip() {
+        let store = ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap();
+        assert!(store.consensus_state_matches_tip().unwrap());
+        let engine = ConsensusEngine::from_store(&store, 0, 600).unwrap();
+        connect(&store, &engine, 0);
+        assert!(store.consensus_state_matches_tip().unwrap());
+
+        // Applied but never stored, as after a crash mid-connect
+        engine.apply_block(&block(&engine, 1)).unwrap();
+        assert!(!store.consensus_state_matches_tip().unwrap());
+
+        assert_eq!(store.clear_consensus_state().unwrap(), 8);
+        assert_eq!(store.consensus_record().unwrap(), None);
+        assert!(store.load_consensus_state().unwrap().is_none());
+        assert_eq!(ConsensusEngine::from_store(&store, 0, 600).unwrap().get_height(), 0);
+    }
+}
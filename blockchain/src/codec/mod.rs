@@ -0,0 +1,853 @@
+//! Canonical wire format of blocks and forges
+//!
+//! Blocks used to be bincode-encoded wherever they were stored or relayed,
+//! so their bytes depended on serde attributes and the bincode version. The
+//! layout is written out here instead: integers are little-endian and fixed
+//! width, byte strings and lists carry a `u64` length prefix, and optional
+//! fields a one-byte tag (0 = absent, 1 = present). This is byte for byte
+//! what bincode produced, so merkle leaves and block hashes are unchanged.
+//!
+//! Encoded blocks start with `BLOCK_MAGIC` and a format version byte. Blocks
+//! stored or relayed before the prefix existed have no prefix and decode as
+//! version 0, whose body layout is the same as version 1. Version 2 added
+//! the header's millisecond field; earlier versions decode without it.
+//! Version 3 follows each forge with its tempering algorithm. It is only
+//! written for blocks holding a forge that isn't PBKDF2-tempered, so blocks
+//! of PBKDF2 forges keep their version 2 bytes.
+//!
+//! Version 4 ends the block with its authority signatures and is only
+//! written for blocks that carry any, which only permissioned chains have.
+//!
+//! Version 5 follows each forge with its tempering algorithm and salt, and
+//! always ends with the authority signatures, of which there may be none.
+//! It is written for blocks holding a salted forge, which every forge that
+//! validates is.
+//!
+//! Version 6 is version 5 followed by the block's transfers, and is only
+//! written for blocks that carry any. The unsigned part of a transfer is
+//! its encoding as `fundrawtransfer` returns it and its txid commits to;
+//! its witnesses follow.
+//!
+//! A forge signature commits to the forge layout without its signature,
+//! followed by the tempering algorithm and salt whatever they are:
+//!
+//! ```text
+//! sighash = bytes(prophecy) bytes(derived_key) bytes(taproot_address)
+//!           proof_hash[32] timestamp:u64 not_before_height:u64 tempering salt[32]
+//! ```
+//!
+//! A standalone forge likewise ends with its tempering algorithm only when
+//! it isn't PBKDF2 or the forge is salted, then its salt if it has one, so
+//! the encoding of unsalted PBKDF2 forges, and the merkle leaves committing
+//! to it, are unchanged.
+//!
+//! ```text
+//! block   = magic(3) version(1) header forges [signatures] [transfers]
+//! header  = version:u32 height:u64 prev_block_hash[32] merkle_root[32]
+//!           timestamp:u64 difficulty:u32 bits:u32 nonce:u64
+//!           option(proof_root[32] tempered_keys_hash[32]) option([32])
+//!           option(timestamp_millis:u16)                      (version 2)
+//! forges  = count:u64 forge*                    (version 3, 4: (forge tempering)*)
+//!                                               (version 5: (forge tempering salt[32])*)
+//! signatures = count:u64 (signer:u32 signature[64])*      (version 4, 5, 6)
+//! transfers  = count:u64 transfer*                         (version 6)
+//! transfer   = version:u32 count:u64 input* count:u64 output* fee:u64
+//!              lock_height:u64 count:u64 witness*
+//! input   = txid[32] vout:u32 amount:u64 bytes(address)
+//! output  = bytes(address) value:u64
+//! witness = 0 bytes(signature)                                   key path
+//!         | 1 bytes(signature) bytes(script) bytes(control_block)  script path
+//! forge   = bytes(prophecy) bytes(derived_key) bytes(taproot_address)
+//!           proof_hash[32] timestamp:u64 bytes(signature) not_before_height:u64
+//! tempering = 0                                   PBKDF2-SHA512
+//!           | 1 memory_kib:u32 iterations:u32 parallelism:u32   Argon2id
+//! bytes   = len:u64 byte*
+//! ```
+
+use crate::consensus::sighash::{Transfer, TransferInput, TransferOutput};
+use crate::consensus::{
+    AggregateCommitment, AuthoritySignature, Block, BlockHeader, ForgeTransaction, SignedTransfer, TransferWitness,
+};
+use crate::ledger::OutPoint;
+use crate::crypto::TemperingAlgorithm;
+use anyhow::{anyhow, Result};
+
+/// Prefix of encoded blocks
+pub const BLOCK_MAGIC: &[u8; 3] = b"EXB";
+/// Current block format version
+pub const BLOCK_FORMAT_VERSION: u8 = 6;
+/// Last block format version without transfers, still written for blocks
+/// that carry none
+const PRE_TRANSFER_FORMAT_VERSION: u8 = 5;
+/// Last block format version without forge salts, still written for
+/// blocks of unsalted forges that carry authority signatures
+const PRE_SALT_FORMAT_VERSION: u8 = 4;
+/// Last block format version without authority signatures, still written
+/// for blocks that carry none
+const PRE_AUTHORITY_FORMAT_VERSION: u8 = 3;
+/// Last block format version without forge tempering, still written for
+/// blocks whose forges are all PBKDF2-tempered
+const PRE_TEMPERING_FORMAT_VERSION: u8 = 2;
+/// Last block format version without the header's millisecond field
+const PRE_MILLIS_FORMAT_VERSION: u8 = 1;
+
+impl Block {
+    /// Encode in the current block format if the block carries transfers,
+    /// else version 5 if it holds a salted forge, else version 4 if it
+    /// carries authority signatures, else version 3, or version 2 if every
+    /// forge is also PBKDF2-tempered
+    pub fn encode(&self) -> Vec<u8> {
+        let transfers = !self.transfers.is_empty();
+        let salted = transfers || self.forges.iter().any(ForgeTransaction::is_salted);
+        let signatures = salted || !self.authority_signatures.is_empty();
+        let tempered = signatures || self.forges.iter().any(|forge| !forge.tempering.is_pbkdf2());
+        let mut out = Vec::with_capacity(128 + self.forges.len() * 256 + self.authority_signatures.len() * 68);
+        out.extend_from_slice(BLOCK_MAGIC);
+        out.push(match (salted, signatures, tempered) {
+            _ if transfers => BLOCK_FORMAT_VERSION,
+            (true, _, _) => PRE_TRANSFER_FORMAT_VERSION,
+            (false, true, _) => PRE_SALT_FORMAT_VERSION,
+            (false, false, true) => PRE_AUTHORITY_FORMAT_VERSION,
+            (false, false, false) => PRE_TEMPERING_FORMAT_VERSION,
+        });
+        write_header(&mut out, &self.header);
+        write_u64(&mut out, self.forges.len() as u64);
+        for forge in &self.forges {
+            write_forge(&mut out, forge);
+            if tempered {
+                write_tempering(&mut out, &forge.tempering);
+            }
+            if salted {
+                out.extend_from_slice(&forge.salt);
+            }
+        }
+        if signatures {
+            write_u64(&mut out, self.authority_signatures.len() as u64);
+            for entry in &self.authority_signatures {
+                write_u32(&mut out, entry.signer);
+                out.extend_from_slice(&entry.signature);
+            }
+        }
+        if transfers {
+            write_u64(&mut out, self.transfers.len() as u64);
+            for transfer in &self.transfers {
+                write_transfer(&mut out, transfer);
+            }
+        }
+        out
+    }
+
+    /// Decode a block in any supported format version, including the
+    /// unprefixed pre-codec encoding
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let (body, has_millis, has_tempering, has_signatures, has_salts, has_transfers) =
+            match bytes.strip_prefix(BLOCK_MAGIC.as_slice()) {
+                Some([BLOCK_FORMAT_VERSION, body @ ..]) => (body, true, true, true, true, true),
+                Some([PRE_TRANSFER_FORMAT_VERSION, body @ ..]) => (body, true, true, true, true, false),
+                Some([PRE_SALT_FORMAT_VERSION, body @ ..]) => (body, true, true, true, false, false),
+                Some([PRE_AUTHORITY_FORMAT_VERSION, body @ ..]) => (body, true, true, false, false, false),
+                Some([PRE_TEMPERING_FORMAT_VERSION, body @ ..]) => (body, true, false, false, false, false),
+                Some([PRE_MILLIS_FORMAT_VERSION, body @ ..]) => (body, false, false, false, false, false),
+                Some([version, ..]) => return Err(anyhow!("Unsupported block format version {}", version)),
+                Some([]) => return Err(anyhow!("Truncated block")),
+                None => (bytes, false, false, false, false, false),
+            };
+        let mut reader = Reader::new(body);
+        let header = reader.header(has_millis)?;
+        let count = reader.u64()?;
+        let mut forges = Vec::new();
+        for _ in 0..count {
+            let mut forge = reader.forge()?;
+            if has_tempering {
+                forge.tempering = reader.tempering()?;
+            }
+            if has_salts {
+                forge.salt = reader.array()?;
+            }
+            forges.push(forge);
+        }
+        let mut authority_signatures = Vec::new();
+        if has_signatures {
+            let count = reader.u64()?;
+            for _ in 0..count {
+                authority_signatures.push(AuthoritySignature {
+                    signer: reader.u32()?,
+                    signature: reader.take(64)?.to_vec(),
+                });
+            }
+            if authority_signatures.is_empty() && !has_salts {
+                return Err(anyhow!("Version {} block without authority signatures", PRE_SALT_FORMAT_VERSION));
+            }
+            if has_salts && !has_transfers && !forges.iter().any(ForgeTransaction::is_salted) {
+                return Err(anyhow!("Version {} block without salted forges", PRE_TRANSFER_FORMAT_VERSION));
+            }
+        }
+        let mut transfers = Vec::new();
+        if has_transfers {
+            let count = reader.u64()?;
+            for _ in 0..count {
+                transfers.push(reader.transfer()?);
+            }
+            if transfers.is_empty() {
+                return Err(anyhow!("Version {} block without transfers", BLOCK_FORMAT_VERSION));
+            }
+        }
+        reader.finish()?;
+        Ok(Block {
+            header,
+            forges,
+            transfers,
+            authority_signatures,
+        })
+    }
+}
+
+impl ForgeTransaction {
+    /// Canonical encoding, as relayed and committed to by merkle leaves
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(256);
+        write_forge(&mut out, self);
+        if !self.tempering.is_pbkdf2() || self.is_salted() {
+            write_tempering(&mut out, &self.tempering);
+        }
+        if self.is_salted() {
+            out.extend_from_slice(&self.salt);
+        }
+        out
+    }
+
+    /// Decode a forge from its canonical encoding
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let mut forge = reader.forge()?;
+        if !reader.bytes.is_empty() {
+            forge.tempering = reader.tempering()?;
+            if !reader.bytes.is_empty() {
+                forge.salt = reader.array()?;
+                if !forge.is_salted() {
+                    return Err(anyhow!("All-zero salt is implied, not encoded"));
+                }
+            } else if forge.tempering.is_pbkdf2() {
+                return Err(anyhow!("PBKDF2 tempering is implied, not encoded"));
+            }
+        }
+        reader.finish()?;
+        Ok(forge)
+    }
+}
+
+impl Transfer {
+    /// Canonical encoding of the unsigned transfer, as `fundrawtransfer`
+    /// returns it and txids commit to
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(192);
+        write_unsigned_transfer(&mut out, self);
+        out
+    }
+}
+
+impl SignedTransfer {
+    /// Canonical encoding, as submitted with `sendrawtransfer` and
+    /// committed to by merkle leaves
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(256);
+        write_transfer(&mut out, self);
+        out
+    }
+
+    /// Decode a signed transfer from its canonical encoding
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let transfer = reader.transfer()?;
+        reader.finish()?;
+        Ok(transfer)
+    }
+}
+
+/// Bytes a forge signature commits to: every field but the signature, in
+/// canonical layout, with the tempering algorithm and salt always present
+pub fn forge_signing_preimage(forge: &ForgeTransaction) -> Vec<u8> {
+    let mut out = Vec::with_capacity(256);
+    write_bytes(&mut out, forge.prophecy.as_bytes());
+    write_bytes(&mut out, &forge.derived_key);
+    write_bytes(&mut out, forge.taproot_address.as_bytes());
+    out.extend_from_slice(&forge.proof_hash);
+    write_u64(&mut out, forge.timestamp);
+    write_u64(&mut out, forge.not_before_height);
+    write_tempering(&mut out, &forge.tempering);
+    out.extend_from_slice(&forge.salt);
+    out
+}
+
+/// Bytes a header's hash commits to. Absent optional fields after the last
+/// present one are left out, so headers hash as they did before those
+/// fields existed.
+pub fn header_hash_preimage(header: &BlockHeader) -> Vec<u8> {
+    let mut out = Vec::with_capacity(160);
+    write_header_base(&mut out, header);
+    let has_millis = header.timestamp_millis.is_some();
+    if header.aggregate_commitment.is_some() || header.state_root.is_some() || has_millis {
+        write_aggregate_commitment(&mut out, header.aggregate_commitment.as_ref());
+    }
+    if header.state_root.is_some() || has_millis {
+        write_option(&mut out, header.state_root.as_ref());
+    }
+    if has_millis {
+        write_timestamp_millis(&mut out, header.timestamp_millis);
+    }
+    out
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_u64(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn write_option(out: &mut Vec<u8>, value: Option<&[u8; 32]>) {
+    match value {
+        Some(bytes) => {
+            out.push(1);
+            out.extend_from_slice(bytes);
+        }
+        None => out.push(0),
+    }
+}
+
+fn write_timestamp_millis(out: &mut Vec<u8>, millis: Option<u16>) {
+    match millis {
+        Some(millis) => {
+            out.push(1);
+            out.extend_from_slice(&millis.to_le_bytes());
+        }
+        None => out.push(0),
+    }
+}
+
+fn write_aggregate_commitment(out: &mut Vec<u8>, commitment: Option<&AggregateCommitment>) {
+    match commitment {
+        Some(commitment) => {
+            out.push(1);
+            out.extend_from_slice(&commitment.proof_root);
+            out.extend_from_slice(&commitment.tempered_keys_hash);
+        }
+        None => out.push(0),
+    }
+}
+
+fn write_header_base(out: &mut Vec<u8>, header: &BlockHeader) {
+    write_u32(out, header.version);
+    write_u64(out, header.height);
+    out.extend_from_slice(&header.prev_block_hash);
+    out.extend_from_slice(&header.merkle_root);
+    write_u64(out, header.timestamp);
+    write_u32(out, header.difficulty);
+    write_u32(out, header.bits);
+    write_u64(out, header.nonce);
+}
+
+fn write_header(out: &mut Vec<u8>, header: &BlockHeader) {
+    write_header_base(out, header);
+    write_aggregate_commitment(out, header.aggregate_commitment.as_ref());
+    write_option(out, header.state_root.as_ref());
+    write_timestamp_millis(out, header.timestamp_millis);
+}
+
+fn write_forge(out: &mut Vec<u8>, forge: &ForgeTransaction) {
+    write_bytes(out, forge.prophecy.as_bytes());
+    write_bytes(out, &forge.derived_key);
+    write_bytes(out, forge.taproot_address.as_bytes());
+    out.extend_from_slice(&forge.proof_hash);
+    write_u64(out, forge.timestamp);
+    write_bytes(out, &forge.signature);
+    write_u64(out, forge.not_before_height);
+}
+
+fn write_unsigned_transfer(out: &mut Vec<u8>, transfer: &Transfer) {
+    write_u32(out, transfer.version);
+    write_u64(out, transfer.inputs.len() as u64);
+    for input in &transfer.inputs {
+        out.extend_from_slice(&input.prevout.txid);
+        write_u32(out, input.prevout.vout);
+        write_u64(out, input.amount);
+        write_bytes(out, input.address.as_bytes());
+    }
+    write_u64(out, transfer.outputs.len() as u64);
+    for output in &transfer.outputs {
+        write_bytes(out, output.address.as_bytes());
+        write_u64(out, output.value);
+    }
+    write_u64(out, transfer.fee);
+    write_u64(out, transfer.lock_height);
+}
+
+fn write_transfer(out: &mut Vec<u8>, signed: &SignedTransfer) {
+    write_unsigned_transfer(out, &signed.transfer);
+    write_u64(out, signed.witnesses.len() as u64);
+    for witness in &signed.witnesses {
+        match witness {
+            TransferWitness::KeyPath { signature } => {
+                out.push(0);
+                write_bytes(out, signature);
+            }
+            TransferWitness::ScriptPath {
+                signature,
+                script,
+                control_block,
+            } => {
+                out.push(1);
+                write_bytes(out, signature);
+                write_bytes(out, script);
+                write_bytes(out, control_block);
+            }
+        }
+    }
+}
+
+fn write_tempering(out: &mut Vec<u8>, tempering: &TemperingAlgorithm) {
+    match *tempering {
+        TemperingAlgorithm::Pbkdf2Sha512 => out.push(0),
+        TemperingAlgorithm::Argon2id {
+            memory_kib,
+            iterations,
+            parallelism,
+        } => {
+            out.push(1);
+            write_u32(out, memory_kib);
+            write_u32(out, iterations);
+            write_u32(out, parallelism);
+        }
+    }
+}
+
+/// Cursor over encoded bytes. Length prefixes are checked against the
+/// remaining input before anything is allocated.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.bytes.len() {
+            return Err(anyhow!("Truncated encoding: needed {} more bytes, {} left", len, self.bytes.len()));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array(&mut self) -> Result<[u8; 32]> {
+        Ok(self.take(32)?.try_into().expect("32-byte slice"))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().expect("4-byte slice")))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().expect("8-byte slice")))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.u64()?;
+        let len = usize::try_from(len).map_err(|_| anyhow!("Length {} out of range", len))?;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn string(&mut self) -> Result<String> {
+        String::from_utf8(self.bytes()?).map_err(|_| anyhow!("String is not valid UTF-8"))
+    }
+
+    fn present(&mut self) -> Result<bool> {
+        match self.take(1)?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(anyhow!("Invalid option tag {}", tag)),
+        }
+    }
+
+    fn header(&mut self, has_millis: bool) -> Result<BlockHeader> {
+        Ok(BlockHeader {
+            version: self.u32()?,
+            height: self.u64()?,
+            prev_block_hash: self.array()?,
+            merkle_root: self.array()?,
+            timestamp: self.u64()?,
+            difficulty: self.u32()?,
+            bits: self.u32()?,
+            nonce: self.u64()?,
+            aggregate_commitment: match self.present()? {
+                true => Some(AggregateCommitment {
+                    proof_root: self.array()?,
+                    tempered_keys_hash: self.array()?,
+                }),
+                false => None,
+            },
+            state_root: match self.present()? {
+                true => Some(self.array()?),
+                false => None,
+            },
+            timestamp_millis: match has_millis && self.present()? {
+                true => Some(u16::from_le_bytes(self.take(2)?.try_into().expect("2-byte slice"))),
+                false => None,
+            },
+        })
+    }
+
+    fn forge(&mut self) -> Result<ForgeTransaction> {
+        Ok(ForgeTransaction {
+            prophecy: self.string()?,
+            derived_key: self.bytes()?,
+            taproot_address: self.string()?,
+            proof_hash: self.array()?,
+            timestamp: self.u64()?,
+            signature: self.bytes()?,
+            not_before_height: self.u64()?,
+            tempering: TemperingAlgorithm::Pbkdf2Sha512,
+            salt: [0; 32],
+        })
+    }
+
+    fn transfer(&mut self) -> Result<SignedTransfer> {
+        let version = self.u32()?;
+        let mut inputs = Vec::new();
+        for _ in 0..self.u64()? {
+            inputs.push(TransferInput {
+                prevout: OutPoint {
+                    txid: self.array()?,
+                    vout: self.u32()?,
+                },
+                amount: self.u64()?,
+                address: self.string()?,
+            });
+        }
+        let mut outputs = Vec::new();
+        for _ in 0..self.u64()? {
+            outputs.push(TransferOutput {
+                address: self.string()?,
+                value: self.u64()?,
+            });
+        }
+        let fee = self.u64()?;
+        let lock_height = self.u64()?;
+        let mut witnesses = Vec::new();
+        for _ in 0..self.u64()? {
+            witnesses.push(match self.take(1)?[0] {
+                0 => TransferWitness::KeyPath { signature: self.bytes()? },
+                1 => TransferWitness::ScriptPath {
+                    signature: self.bytes()?,
+                    script: self.bytes()?,
+                    control_block: self.bytes()?,
+                },
+                tag => return Err(anyhow!("Unknown witness type {}", tag)),
+            });
+        }
+        Ok(SignedTransfer {
+            transfer: Transfer {
+                version,
+                inputs,
+                outputs,
+                fee,
+                lock_height,
+            },
+            witnesses,
+        })
+    }
+
+    fn tempering(&mut self) -> Result<TemperingAlgorithm> {
+        match self.take(1)?[0] {
+            0 => Ok(TemperingAlgorithm::Pbkdf2Sha512),
+            1 => Ok(TemperingAlgorithm::Argon2id {
+                memory_kib: self.u32()?,
+                iterations: self.u32()?,
+                parallelism: self.u32()?,
+            }),
+            tag => Err(anyhow!("Unknown tempering algorithm {}", tag)),
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        match self.bytes.len() {
+            0 => Ok(()),
+            extra => Err(anyhow!("{} trailing bytes after encoding", extra)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_bytes(rng: &mut StdRng, max_len: usize) -> Vec<u8> {
+        let len = rng.gen_range(0..=max_len);
+        (0..len).map(|_| rng.gen()).collect()
+    }
+
+    fn random_string(rng: &mut StdRng, max_len: usize) -> String {
+        let len = rng.gen_range(0..=max_len);
+        (0..len).map(|_| rng.gen::<char>()).collect()
+    }
+
+    fn random_block(rng: &mut StdRng) -> Block {
+        let header = BlockHeader {
+            version: rng.gen(),
+            height: rng.gen(),
+            prev_block_hash: rng.gen(),
+            merkle_root: rng.gen(),
+            timestamp: rng.gen(),
+            difficulty: rng.gen(),
+            bits: rng.gen(),
+            nonce: rng.gen(),
+            aggregate_commitment: rng.gen_bool(0.5).then(|| AggregateCommitment {
+                proof_root: rng.gen(),
+                tempered_keys_hash: rng.gen(),
+            }),
+            state_root: rng.gen_bool(0.5).then(|| rng.gen()),
+            timestamp_millis: rng.gen_bool(0.5).then(|| rng.gen()),
+        };
+        let forges = (0..rng.gen_range(0..8))
+            .map(|_| ForgeTransaction {
+                prophecy: random_string(rng, 40),
+                derived_key: random_bytes(rng, 40),
+                taproot_address: random_string(rng, 70),
+                proof_hash: rng.gen(),
+                timestamp: rng.gen(),
+                signature: random_bytes(rng, 80),
+                not_before_height: rng.gen(),
+                tempering: Default::default(),
+                salt: [0; 32],
+            })
+            .collect();
+        Block { header, forges, transfers: Vec::new(), authority_signatures: Vec::new() }
+    }
+
+    /// Unprefixed body as encoded before the millisecond field existed
+    fn encode_pre_millis(block: &Block) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_header_base(&mut out, &block.header);
+        write_aggregate_commitment(&mut out, block.header.aggregate_commitment.as_ref());
+        write_option(&mut out, block.header.state_root.as_ref());
+        write_u64(&mut out, block.forges.len() as u64);
+        for forge in &block.forges {
+            write_forge(&mut out, forge);
+        }
+        out
+    }
+
+    #[test]
+    fn test_random_blocks_round_trip_in_bincode_layout() {
+        let mut rng = StdRng::seed_from_u64(0xE75);
+        for _ in 0..500 {
+            let block = random_block(&mut rng);
+            let encoded = block.encode();
+            let decoded = Block::decode(&encoded).unwrap();
+            assert_eq!(decoded.encode(), encoded);
+            assert_eq!(decoded.forges, block.forges);
+
+            // The body is the layout bincode produces
+            let body = bincode::serialize(&block).unwrap();
+            assert_eq!(&encoded[BLOCK_MAGIC.len() + 1..], body.as_slice());
+
+            // Blocks encoded before the millisecond field still decode
+            if block.header.timestamp_millis.is_none() {
+                let legacy = encode_pre_millis(&block);
+                assert_eq!(Block::decode(&legacy).unwrap().encode(), encoded);
+                let version_1 = [BLOCK_MAGIC.as_slice(), &[1], &legacy].concat();
+                assert_eq!(Block::decode(&version_1).unwrap().encode(), encoded);
+            }
+            for forge in &block.forges {
+                assert_eq!(forge.encode(), bincode::serialize(forge).unwrap());
+                assert_eq!(&ForgeTransaction::decode(&forge.encode()).unwrap(), forge);
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_blocks_rejected() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut block = random_block(&mut rng);
+        block.forges.push(ForgeTransaction {
+            prophecy: "prophecy".to_string(),
+            derived_key: vec![2; 33],
+            taproot_address: "bc1p".to_string(),
+            proof_hash: [1; 32],
+            timestamp: 1,
+            signature: vec![3; 64],
+            not_before_height: 0,
+            tempering: Default::default(),
+            salt: [0; 32],
+        });
+        let encoded = block.encode();
+
+        for len in 0..encoded.len() {
+            assert!(Block::decode(&encoded[..len]).is_err(), "prefix of {} bytes decoded", len);
+        }
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert!(Block::decode(&trailing).unwrap_err().to_string().contains("trailing"));
+
+        let mut future = encoded.clone();
+        future[BLOCK_MAGIC.len()] = 7;
+        assert!(Block::decode(&future).unwrap_err().to_string().contains("version 7"));
+
+        // A forge count far beyond the input fails without allocating for it
+        let mut huge = BLOCK_MAGIC.to_vec();
+        huge.push(BLOCK_FORMAT_VERSION);
+        write_header(&mut huge, &block.header);
+        write_u64(&mut huge, u64::MAX);
+        assert!(Block::decode(&huge).is_err());
+    }
+
+    #[test]
+    fn test_tempered_forges_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0xA2);
+        let mut block = random_block(&mut rng);
+        while block.forges.is_empty() {
+            block = random_block(&mut rng);
+        }
+        let untempered = block.encode();
+        assert_eq!(untempered[BLOCK_MAGIC.len()], PRE_TEMPERING_FORMAT_VERSION);
+
+        // One Argon2id forge moves the whole block to version 3
+        block.forges[0].tempering = TemperingAlgorithm::argon2id();
+        let encoded = block.encode();
+        assert_eq!(encoded[BLOCK_MAGIC.len()], PRE_AUTHORITY_FORMAT_VERSION);
+        let decoded = Block::decode(&encoded).unwrap();
+        assert_eq!(decoded.forges, block.forges);
+        assert_eq!(decoded.encode(), encoded);
+
+        // Standalone forges carry the tempering only when it isn't PBKDF2
+        let forge = &block.forges[0];
+        assert_eq!(&ForgeTransaction::decode(&forge.encode()).unwrap(), forge);
+        if let Some(pbkdf2) = block.forges.get(1) {
+            assert_eq!(pbkdf2.encode(), bincode::serialize(pbkdf2).unwrap());
+        }
+        let mut explicit = block.forges[0].clone();
+        explicit.tempering = TemperingAlgorithm::Pbkdf2Sha512;
+        let mut bytes = explicit.encode();
+        bytes.push(0);
+        assert!(ForgeTransaction::decode(&bytes).is_err());
+        bytes.pop();
+        bytes.push(9);
+        assert!(ForgeTransaction::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_signed_blocks_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0xA5);
+        let mut block = random_block(&mut rng);
+        block.authority_signatures = (0..3)
+            .map(|signer| AuthoritySignature {
+                signer,
+                signature: (0..64).map(|_| rng.gen()).collect(),
+            })
+            .collect();
+        let encoded = block.encode();
+        assert_eq!(encoded[BLOCK_MAGIC.len()], PRE_SALT_FORMAT_VERSION);
+        let decoded = Block::decode(&encoded).unwrap();
+        assert_eq!(decoded.authority_signatures, block.authority_signatures);
+        assert_eq!(decoded.forges, block.forges);
+        assert_eq!(header_hash_preimage(&decoded.header), header_hash_preimage(&block.header));
+
+        // The signatures end the block and are all required
+        let body_len = encoded.len() - 8 - 3 * 68;
+        for len in body_len..encoded.len() {
+            assert!(Block::decode(&encoded[..len]).is_err(), "prefix of {} bytes decoded", len);
+        }
+        let mut empty = encoded[..body_len].to_vec();
+        write_u64(&mut empty, 0);
+        assert!(Block::decode(&empty).unwrap_err().to_string().contains("without authority signatures"));
+    }
+
+    #[test]
+    fn test_salted_forges_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0x5A);
+        let mut block = random_block(&mut rng);
+        while block.forges.is_empty() {
+            block = random_block(&mut rng);
+        }
+        let unsalted = block.forges[0].encode();
+
+        // One salted forge moves the whole block to version 5, which ends
+        // with its authority signatures even when there are none
+        block.forges[0].salt = rng.gen();
+        let encoded = block.encode();
+        assert_eq!(encoded[BLOCK_MAGIC.len()], PRE_TRANSFER_FORMAT_VERSION);
+        assert_eq!(encoded[encoded.len() - 8..], [0; 8]);
+        let decoded = Block::decode(&encoded).unwrap();
+        assert_eq!(decoded.forges, block.forges);
+        assert_eq!(decoded.encode(), encoded);
+
+        // Standalone, the tempering is written ahead of the salt even when
+        // it is PBKDF2, and an all-zero salt is never written
+        let forge = &block.forges[0];
+        assert_eq!(forge.encode().len(), unsalted.len() + 1 + 32);
+        assert_eq!(&ForgeTransaction::decode(&forge.encode()).unwrap(), forge);
+        let mut bytes = forge.encode();
+        let len = bytes.len();
+        bytes[len - 32..].fill(0);
+        assert!(ForgeTransaction::decode(&bytes).unwrap_err().to_string().contains("salt"));
+    }
+
+    #[test]
+    fn test_transfer_blocks_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0x7F);
+        let mut block = random_block(&mut rng);
+        let transfer = Transfer {
+            version: 1,
+            inputs: vec![TransferInput {
+                prevout: OutPoint { txid: rng.gen(), vout: 1 },
+                amount: 60_000,
+                address: random_string(&mut rng, 70),
+            }],
+            outputs: vec![TransferOutput {
+                address: random_string(&mut rng, 70),
+                value: 50_000,
+            }],
+            fee: 10_000,
+            lock_height: 3,
+        };
+        block.transfers = vec![SignedTransfer {
+            transfer: transfer.clone(),
+            witnesses: vec![
+                TransferWitness::KeyPath { signature: random_bytes(&mut rng, 65) },
+                TransferWitness::ScriptPath {
+                    signature: random_bytes(&mut rng, 65),
+                    script: random_bytes(&mut rng, 40),
+                    control_block: random_bytes(&mut rng, 65),
+                },
+            ],
+        }];
+        let encoded = block.encode();
+        assert_eq!(encoded[BLOCK_MAGIC.len()], BLOCK_FORMAT_VERSION);
+        let decoded = Block::decode(&encoded).unwrap();
+        assert_eq!(decoded.transfers, block.transfers);
+        assert_eq!(decoded.encode(), encoded);
+
+        // The unsigned part is the encoding fundrawtransfer returns
+        let standalone = block.transfers[0].encode();
+        assert!(standalone.starts_with(&transfer.encode()));
+        assert_eq!(SignedTransfer::decode(&standalone).unwrap(), block.transfers[0]);
+        for len in 0..standalone.len() {
+            assert!(SignedTransfer::decode(&standalone[..len]).is_err(), "prefix of {} bytes decoded", len);
+        }
+
+        // A version 6 block must carry transfers
+        let mut empty = encoded[..encoded.len() - 8 - standalone.len()].to_vec();
+        write_u64(&mut empty, 0);
+        assert!(Block::decode(&empty).unwrap_err().to_string().contains("without transfers"));
+    }
+}
@@ -0,0 +1,864 @@
+//! Node configuration file (`excalibur.toml`)
+//!
+//! Every setting has a default, and the `start` command's flags override
+//! the file. Library users can build a configuration in code with
+//! `ConfigBuilder`.
+
+use crate::chain::{
+    CheckLevel, HeaderGuard, DEFAULT_CHECK_BLOCKS, DEFAULT_HEADER_WORK_WINDOW, DEFAULT_MAX_REORG_DEPTH,
+    DEFAULT_MAX_UNCONNECTED_HEADERS,
+};
+use crate::consensus::AuthoritySet;
+use crate::crypto::musig::parse_participant_key;
+use crate::events::{parse_zmq_endpoint, AlertSeverity};
+use crate::network::reconnect::{DEFAULT_RECONNECT_INITIAL_BACKOFF, DEFAULT_RECONNECT_MAX_BACKOFF};
+use crate::network::inventory::DEFAULT_KNOWN_INVENTORY;
+use crate::network::seen::{DEFAULT_SEEN_MESSAGES, DEFAULT_SEEN_WINDOW};
+use crate::network::sync::{BODY_REQUEST_TIMEOUT, DEFAULT_DEMOTE_AFTER_STALLS};
+use crate::network::{
+    BlockRelay, GossipSettings, PeerFilter, PeerRule, ReconnectSchedule, ServiceFlags, SyncPolicy,
+};
+use crate::params::NetworkParams;
+use crate::supervisor::RestartPolicy;
+use libp2p::Multiaddr;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing_subscriber::EnvFilter;
+use anyhow::{anyhow, Context, Result};
+
+mod reload;
+
+pub use reload::{ConfigReloader, ReloadReport, ReloadTargets, IMMUTABLE_SETTINGS, RELOADABLE_SETTINGS};
+
+/// Log filter used when neither the config nor `RUST_LOG` sets one
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Handle for changing the log filter of a running node
+pub type LogFilterHandle = tracing_subscriber::reload::Handle<EnvFilter, tracing_subscriber::Registry>;
+
+/// Top-level node configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeConfig {
+    pub chain: ChainConfig,
+    pub rpc: RpcConfig,
+    pub wallet: WalletConfig,
+    pub events: EventsConfig,
+    pub network: NetworkConfig,
+    pub mempool: MempoolConfig,
+    pub watchtower: WatchtowerConfig,
+    pub supervisor: SupervisorConfig,
+    pub mining: MiningConfig,
+    pub logging: LoggingConfig,
+}
+
+/// Chain database settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChainConfig {
+    /// Network to join: mainnet, testnet or regtest (default mainnet)
+    pub network: Option<String>,
+    /// Directory holding the chain database and wallet (default
+    /// `~/.excalibur`, with a subdirectory per test network)
+    pub datadir: Option<PathBuf>,
+    /// How thoroughly the existing database is verified on startup (0-3)
+    pub check_level: CheckLevel,
+    /// Number of most recent blocks verified on startup (0 = all)
+    pub check_blocks: u64,
+    /// Ledger snapshot to load on first start instead of validating history
+    pub load_snapshot: Option<PathBuf>,
+    /// Maintain an index of all historical forges by proof hash
+    pub txindex: bool,
+    /// Maintain a word index over forge prophecies for `searchforges`
+    pub searchindex: bool,
+    /// Deepest reorg applied without `acceptreorg` confirmation (0 = no limit)
+    pub max_reorg_depth: u64,
+    /// Blocks of tip work a competing header branch may trail by and still be stored
+    pub header_work_window: u64,
+    /// Headers with unknown parents held per peer
+    pub max_unconnected_headers: usize,
+    /// Addresses whose unspent outputs `getsupplyinfo` counts as treasury
+    pub treasury_addresses: Vec<String>,
+    /// Run a permissioned chain whose blocks these authorities sign
+    /// (regtest only)
+    pub authority: Option<AuthorityConfig>,
+}
+
+/// Authorities of a permissioned chain (`[chain.authority]`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthorityConfig {
+    /// Authority public keys (hex, 32-byte x-only or 33-byte compressed),
+    /// in proposer order
+    pub keys: Vec<String>,
+    /// Signatures each block needs
+    pub threshold: usize,
+    /// First height whose blocks must be signed
+    #[serde(default)]
+    pub activation_height: u64,
+}
+
+impl AuthorityConfig {
+    /// The configured authority set
+    pub fn authority_set(&self) -> Result<AuthoritySet> {
+        let keys = self
+            .keys
+            .iter()
+            .map(|key| {
+                let bytes = hex::decode(key).map_err(|_| anyhow!("Authority key {} is not hex", key))?;
+                Ok(parse_participant_key(&bytes)?.x_only_public_key().0)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        AuthoritySet::new(keys, self.threshold, self.activation_height)
+    }
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        Self {
+            network: None,
+            datadir: None,
+            check_level: CheckLevel::default(),
+            check_blocks: DEFAULT_CHECK_BLOCKS,
+            load_snapshot: None,
+            txindex: false,
+            searchindex: false,
+            max_reorg_depth: DEFAULT_MAX_REORG_DEPTH,
+            header_work_window: DEFAULT_HEADER_WORK_WINDOW,
+            max_unconnected_headers: DEFAULT_MAX_UNCONNECTED_HEADERS,
+            treasury_addresses: Vec::new(),
+            authority: None,
+        }
+    }
+}
+
+impl ChainConfig {
+    /// Parameters of the configured network, permissioned if authorities
+    /// are configured
+    pub fn params(&self) -> Result<NetworkParams> {
+        let name = self.network.as_deref().unwrap_or("mainnet");
+        let params = NetworkParams::from_name(name)
+            .ok_or_else(|| anyhow!("Unknown network {} (expected mainnet, testnet or regtest)", name))?;
+        match &self.authority {
+            None => Ok(params),
+            Some(_) if params.name != "regtest" => {
+                Err(anyhow!("[chain.authority] is only available on regtest, not {}", params.name))
+            }
+            Some(authority) => Ok(params.with_authorities(authority.authority_set()?)),
+        }
+    }
+
+    /// Header spam guard with the configured limits
+    pub fn header_guard(&self) -> HeaderGuard {
+        HeaderGuard::new(self.header_work_window, self.max_unconnected_headers)
+    }
+}
+
+/// RPC server settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RpcConfig {
+    /// Address the HTTP server listens on (default `127.0.0.1` on the
+    /// network's RPC port)
+    pub bind: Option<String>,
+    /// Seconds in-flight requests may keep running once shutdown starts
+    pub shutdown_grace_secs: u64,
+    /// Bearer token for `GET /export/blocks`; the endpoint is off when unset
+    pub export_token: Option<String>,
+    /// Cached `getblock`/`getrawforge` responses (0 disables the cache)
+    pub response_cache_entries: usize,
+    /// Sign read responses with the node identity key, so clients can
+    /// attribute answers to this node
+    pub sign_responses: bool,
+    /// Basic-auth user name (Bitcoin Core's `rpcuser`)
+    pub user: Option<String>,
+    /// Basic-auth password (`rpcpassword`); when unset a random cookie is
+    /// written to `.cookie` in the data directory instead
+    pub password: Option<String>,
+    /// Methods anyone may call without credentials
+    pub public_methods: Vec<String>,
+    /// Methods only authenticated loopback callers may use
+    pub local_methods: Vec<String>,
+    /// Register the `debug_*` introspection methods (local-only unless
+    /// listed in `public_methods`)
+    pub debug: bool,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            bind: None,
+            shutdown_grace_secs: crate::rpc::DEFAULT_SHUTDOWN_GRACE.as_secs(),
+            export_token: None,
+            response_cache_entries: crate::rpc::DEFAULT_RESPONSE_CACHE_ENTRIES,
+            sign_responses: false,
+            user: None,
+            password: None,
+            public_methods: Vec::new(),
+            local_methods: Vec::new(),
+            debug: false,
+        }
+    }
+}
+
+impl RpcConfig {
+    /// Grace period for draining in-flight requests
+    pub fn shutdown_grace(&self) -> Duration {
+        Duration::from_secs(self.shutdown_grace_secs)
+    }
+
+    /// Per-method access policy; unlisted methods need credentials
+    pub fn policy(&self) -> crate::rpc::RpcPolicy {
+        use crate::rpc::MethodAccess;
+
+        let mut policy = crate::rpc::RpcPolicy::default();
+        if self.debug {
+            for method in crate::rpc::DEBUG_METHODS {
+                policy.set(method, MethodAccess::Local);
+            }
+        }
+        for method in &self.public_methods {
+            policy.set(method, MethodAccess::Public);
+        }
+        for method in &self.local_methods {
+            policy.set(method, MethodAccess::Local);
+        }
+        policy
+    }
+}
+
+/// Wallet settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WalletConfig {
+    /// External signer binary; when set the node never holds signing keys
+    pub signer: Option<PathBuf>,
+    /// Extra arguments passed to the external signer
+    pub signer_args: Vec<String>,
+}
+
+/// Operator notification settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventsConfig {
+    /// `http://` endpoints that receive alerts as JSON POSTs
+    pub webhooks: Vec<String>,
+    /// Lowest alert severity delivered to webhooks
+    pub webhook_min_severity: AlertSeverity,
+    /// `tcp://host:port` endpoint publishing mempool events on the ZeroMQ
+    /// `sequence` topic
+    pub zmq_pub_sequence: Option<String>,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            webhooks: Vec::new(),
+            webhook_min_severity: AlertSeverity::Warning,
+            zmq_pub_sequence: None,
+        }
+    }
+}
+
+/// P2P network settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// P2P listen port (default: the network's P2P port)
+    pub port: Option<u16>,
+    /// Peers dialed on startup, as multiaddrs
+    pub connect: Vec<String>,
+    /// Upload limit per peer in KiB/s, for metered connections (0 = unlimited)
+    pub max_peer_upload_kib: u64,
+    /// Advertise that every historical block body is kept and served
+    pub archival: bool,
+    /// Advertise that compact block filters are served
+    pub serve_filters: bool,
+    /// Advertise that light-client requests are answered
+    pub light_serve: bool,
+    /// Seconds a block body request may go unanswered before it is retried elsewhere
+    pub body_request_timeout_secs: u64,
+    /// Consecutive stalled body requests after which a peer is demoted (0 = never)
+    pub stall_demote_after: u32,
+    /// Delay before the first redial of a dropped outbound peer, in milliseconds
+    pub reconnect_initial_backoff_ms: u64,
+    /// Longest delay between redials, in seconds
+    pub reconnect_max_backoff_secs: u64,
+    /// Relay blocks after header and structural checks, before proof-of-forge verification
+    pub optimistic_block_relay: bool,
+    /// Gossip message ids remembered across restarts (0 = none)
+    pub seen_messages: usize,
+    /// Seconds a seen gossip message id is remembered
+    pub seen_messages_window_secs: u64,
+    /// Blocks and forges remembered per peer as already known, so they
+    /// aren't relayed back (0 = always relay)
+    pub known_inventory: usize,
+    /// Only these IP addresses, CIDR ranges and peer IDs may connect (empty = anyone)
+    pub allow: Vec<String>,
+    /// IP addresses, CIDR ranges and peer IDs that may never connect
+    pub deny: Vec<String>,
+    /// Gossipsub mesh and peer-scoring settings (`[network.gossip]`)
+    pub gossip: GossipSettings,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            port: None,
+            connect: Vec::new(),
+            max_peer_upload_kib: 0,
+            archival: true,
+            serve_filters: false,
+            light_serve: false,
+            body_request_timeout_secs: BODY_REQUEST_TIMEOUT.as_secs(),
+            stall_demote_after: DEFAULT_DEMOTE_AFTER_STALLS,
+            reconnect_initial_backoff_ms: DEFAULT_RECONNECT_INITIAL_BACKOFF.as_millis() as u64,
+            reconnect_max_backoff_secs: DEFAULT_RECONNECT_MAX_BACKOFF.as_secs(),
+            optimistic_block_relay: false,
+            seen_messages: DEFAULT_SEEN_MESSAGES,
+            seen_messages_window_secs: DEFAULT_SEEN_WINDOW.as_secs(),
+            known_inventory: DEFAULT_KNOWN_INVENTORY,
+            allow: Vec::new(),
+            deny: Vec::new(),
+            gossip: GossipSettings::default(),
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// Parsed `connect` addresses
+    pub fn connect_peers(&self) -> Result<Vec<Multiaddr>> {
+        self.connect
+            .iter()
+            .map(|peer| peer.parse().with_context(|| format!("Invalid peer address {}", peer)))
+            .collect()
+    }
+
+    /// Connection rules from `allow` and `deny`
+    pub fn peer_filter(&self) -> Result<PeerFilter> {
+        let (allow, deny) = self.peer_rules()?;
+        Ok(PeerFilter::new(allow, deny))
+    }
+
+    /// Parsed `allow` and `deny` rules
+    pub fn peer_rules(&self) -> Result<(Vec<PeerRule>, Vec<PeerRule>)> {
+        let parse = |rules: &[String]| -> Result<Vec<PeerRule>> {
+            rules.iter().map(|rule| rule.parse().map_err(|e: String| anyhow!(e))).collect()
+        };
+        Ok((parse(&self.allow)?, parse(&self.deny)?))
+    }
+
+    /// Per-peer upload limit in bytes per second (0 = unlimited)
+    pub fn peer_upload_limit(&self) -> u64 {
+        self.max_peer_upload_kib.saturating_mul(1024)
+    }
+
+    /// Body download timeout and stall policy
+    pub fn sync_policy(&self) -> SyncPolicy {
+        SyncPolicy {
+            request_timeout: Duration::from_secs(self.body_request_timeout_secs),
+            demote_after_stalls: self.stall_demote_after,
+        }
+    }
+
+    /// Redial schedule for dropped outbound peers
+    pub fn reconnect_schedule(&self) -> ReconnectSchedule {
+        ReconnectSchedule::new(
+            Duration::from_millis(self.reconnect_initial_backoff_ms),
+            Duration::from_secs(self.reconnect_max_backoff_secs),
+        )
+    }
+
+    /// How long seen gossip message ids are remembered
+    pub fn seen_messages_window(&self) -> Duration {
+        Duration::from_secs(self.seen_messages_window_secs)
+    }
+
+    /// Block relay policy
+    pub fn block_relay(&self) -> BlockRelay {
+        BlockRelay::new(self.optimistic_block_relay)
+    }
+
+    /// Services advertised to peers during identify
+    pub fn services(&self) -> ServiceFlags {
+        let mut services = ServiceFlags::FULL;
+        if self.archival {
+            services.insert(ServiceFlags::ARCHIVAL);
+        }
+        if self.serve_filters {
+            services.insert(ServiceFlags::FILTERS);
+        }
+        if self.light_serve {
+            services.insert(ServiceFlags::LIGHT_SERVE);
+        }
+        services
+    }
+}
+
+/// Mempool settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MempoolConfig {
+    /// Entries held before new forges are refused and the cheapest
+    /// transfers evicted
+    pub max_forges: usize,
+    /// Smallest fee a transfer must pay to be admitted
+    pub min_fee: u64,
+    /// Step the minimum fee rises past a transfer fee evicted from a full
+    /// mempool
+    pub incremental_fee: u64,
+    /// Time for a raised minimum fee to halve
+    pub min_fee_half_life_secs: u64,
+    /// WASM policy filter run on every forge before admission
+    /// (requires the `wasm-policy` feature)
+    pub policy_filter: Option<PathBuf>,
+    /// Instruction budget for one policy filter call
+    pub policy_fuel: u64,
+    /// Forges validated at once
+    pub validation_workers: usize,
+    /// Locally submitted forges that may wait for validation
+    pub local_queue_size: usize,
+    /// Gossiped forges that may wait for validation
+    pub gossip_queue_size: usize,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            max_forges: crate::node::MEMPOOL_MAX_FORGES,
+            min_fee: 0,
+            incremental_fee: crate::mempool::DEFAULT_INCREMENTAL_FEE,
+            min_fee_half_life_secs: crate::mempool::DEFAULT_MIN_FEE_HALF_LIFE_SECS,
+            policy_filter: None,
+            policy_fuel: crate::mempool::DEFAULT_POLICY_FUEL,
+            validation_workers: crate::mempool::DEFAULT_VALIDATION_WORKERS,
+            local_queue_size: crate::mempool::DEFAULT_LOCAL_QUEUE_SIZE,
+            gossip_queue_size: crate::mempool::DEFAULT_GOSSIP_QUEUE_SIZE,
+        }
+    }
+}
+
+/// Watchtower settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatchtowerConfig {
+    /// Record evidence of double forges and equivocating producers
+    pub enabled: bool,
+    /// Gossip recorded evidence to peers
+    pub gossip_evidence: bool,
+}
+
+/// Block mining settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MiningConfig {
+    /// Mine blocks from the mempool
+    pub enabled: bool,
+    /// Nonces tried before picking up a newer template
+    pub nonce_batch: u64,
+    /// File holding this node's authority secret key (hex), on a
+    /// permissioned chain. The miner only proposes blocks on its turn, and
+    /// `signblock` co-signs blocks proposed by the other authorities.
+    pub authority_key_file: Option<PathBuf>,
+}
+
+impl Default for MiningConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            nonce_batch: crate::miner::DEFAULT_NONCE_BATCH,
+            authority_key_file: None,
+        }
+    }
+}
+
+/// Task supervision settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SupervisorConfig {
+    /// Consecutive crashes of a task restarted before giving up
+    pub max_restarts: u32,
+    /// Milliseconds before the first restart, doubled for each further one
+    pub initial_backoff_ms: u64,
+    /// Upper bound on the restart delay in seconds
+    pub max_backoff_secs: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        let policy = RestartPolicy::default();
+        Self {
+            max_restarts: policy.max_restarts,
+            initial_backoff_ms: policy.initial_backoff.as_millis() as u64,
+            max_backoff_secs: policy.max_backoff.as_secs(),
+        }
+    }
+}
+
+impl SupervisorConfig {
+    /// Restart policy applied to supervised tasks
+    pub fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy {
+            max_restarts: self.max_restarts,
+            initial_backoff: Duration::from_millis(self.initial_backoff_ms),
+            max_backoff: Duration::from_secs(self.max_backoff_secs),
+            ..RestartPolicy::default()
+        }
+    }
+}
+
+/// Log output settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Log filter in `RUST_LOG` syntax, e.g. `info` or
+    /// `info,excalibur_blockchain::network=debug`
+    pub level: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: DEFAULT_LOG_LEVEL.to_string(),
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// Filter for the log subscriber; `RUST_LOG` takes precedence
+    pub fn env_filter(&self) -> Result<EnvFilter> {
+        match std::env::var(EnvFilter::DEFAULT_ENV) {
+            Ok(directives) if !directives.is_empty() => Ok(EnvFilter::try_new(directives)?),
+            _ => EnvFilter::try_new(&self.level).with_context(|| format!("Invalid log level {}", self.level)),
+        }
+    }
+
+    /// Install the global log subscriber, returning a handle to change its
+    /// filter later
+    pub fn init_subscriber(&self) -> Result<LogFilterHandle> {
+        use tracing_subscriber::prelude::*;
+
+        let (filter, handle) = tracing_subscriber::reload::Layer::new(self.env_filter()?);
+        tracing_subscriber::registry().with(filter).with(tracing_subscriber::fmt::layer()).init();
+        Ok(handle)
+    }
+}
+
+impl NodeConfig {
+    /// Load configuration from a TOML file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        Self::from_toml_str(&contents)
+            .with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Parse configuration from a TOML string
+    pub fn from_toml_str(contents: &str) -> Result<Self> {
+        let config: Self = toml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check settings that deserialize but can't be used
+    pub fn validate(&self) -> Result<()> {
+        self.chain.params()?;
+        self.network.connect_peers()?;
+        self.network.peer_filter()?;
+        if let Some(endpoint) = &self.events.zmq_pub_sequence {
+            parse_zmq_endpoint(endpoint)?;
+        }
+        EnvFilter::try_new(&self.logging.level).with_context(|| format!("Invalid log level {}", self.logging.level))?;
+        Ok(())
+    }
+}
+
+/// Builds a `NodeConfig` in code, for applications embedding the node
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    config: NodeConfig,
+}
+
+impl ConfigBuilder {
+    /// Start from the defaults
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start from a configuration file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self {
+            config: NodeConfig::load(path)?,
+        })
+    }
+
+    /// Network to join by name
+    pub fn network(mut self, name: &str) -> Self {
+        self.config.chain.network = Some(name.to_string());
+        self
+    }
+
+    pub fn data_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.chain.datadir = Some(dir.into());
+        self
+    }
+
+    /// P2P listen port
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.network.port = Some(port);
+        self
+    }
+
+    /// HTTP RPC listen address
+    pub fn rpc_bind(mut self, addr: &str) -> Self {
+        self.config.rpc.bind = Some(addr.to_string());
+        self
+    }
+
+    /// Add a peer dialed on startup
+    pub fn connect(mut self, peer: &str) -> Self {
+        self.config.network.connect.push(peer.to_string());
+        self
+    }
+
+    pub fn mempool_max_forges(mut self, max_forges: usize) -> Self {
+        self.config.mempool.max_forges = max_forges;
+        self
+    }
+
+    pub fn mempool_min_fee(mut self, min_fee: u64) -> Self {
+        self.config.mempool.min_fee = min_fee;
+        self
+    }
+
+    /// Log filter in `RUST_LOG` syntax
+    pub fn log_level(mut self, level: &str) -> Self {
+        self.config.logging.level = level.to_string();
+        self
+    }
+
+    /// Change any other setting
+    pub fn with(mut self, change: impl FnOnce(&mut NodeConfig)) -> Self {
+        change(&mut self.config);
+        self
+    }
+
+    /// The validated configuration
+    pub fn build(self) -> Result<NodeConfig> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let config = NodeConfig::from_toml_str("").unwrap();
+        assert_eq!(config.chain.check_level, CheckLevel::MerkleRoots);
+        assert_eq!(config.chain.check_blocks, DEFAULT_CHECK_BLOCKS);
+    }
+
+    #[test]
+    fn test_chain_section() {
+        let config = NodeConfig::from_toml_str(
+            "[chain]\ncheck_level = 3\ncheck_blocks = 100\nmax_unconnected_headers = 32\nsearchindex = true\n\
+             treasury_addresses = [\"bc1ptreasury\"]\n",
+        )
+        .unwrap();
+        assert_eq!(config.chain.check_level, CheckLevel::ProofOfForge);
+        assert_eq!(config.chain.check_blocks, 100);
+        assert_eq!(config.chain.header_work_window, DEFAULT_HEADER_WORK_WINDOW);
+        assert_eq!(config.chain.max_unconnected_headers, 32);
+        assert!(config.chain.searchindex);
+        assert_eq!(config.chain.treasury_addresses, vec!["bc1ptreasury".to_string()]);
+
+        assert!(NodeConfig::from_toml_str("[chain]\ncheck_level = 9\n").is_err());
+    }
+
+    #[test]
+    fn test_events_section() {
+        let config = NodeConfig::from_toml_str(
+            "[chain]\nmax_reorg_depth = 6\n[events]\nwebhooks = [\"http://127.0.0.1:9000/exs\"]\nwebhook_min_severity = \"critical\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.chain.max_reorg_depth, 6);
+        assert_eq!(config.events.webhooks.len(), 1);
+        assert_eq!(config.events.webhook_min_severity, AlertSeverity::Critical);
+        assert_eq!(config.events.zmq_pub_sequence, None);
+
+        assert!(NodeConfig::from_toml_str("[events]\nzmq_pub_sequence = \"ipc:///tmp/exs\"\n").is_err());
+    }
+
+    #[test]
+    fn test_network_section() {
+        let config = NodeConfig::from_toml_str("").unwrap();
+        assert_eq!(config.network.peer_upload_limit(), 0);
+        assert_eq!(config.network.services(), ServiceFlags::FULL | ServiceFlags::ARCHIVAL);
+        assert_eq!(config.network.sync_policy(), SyncPolicy::default());
+        assert!(!config.network.block_relay().stats().enabled);
+        assert_eq!(config.network.known_inventory, DEFAULT_KNOWN_INVENTORY);
+
+        let config = NodeConfig::from_toml_str(
+            "[network]\nmax_peer_upload_kib = 64\narchival = false\nserve_filters = true\nbody_request_timeout_secs = 5\nknown_inventory = 0\n",
+        )
+        .unwrap();
+        assert_eq!(config.network.known_inventory, 0);
+        assert_eq!(config.network.peer_upload_limit(), 64 * 1024);
+        assert_eq!(config.network.services(), ServiceFlags::FULL | ServiceFlags::FILTERS);
+        assert_eq!(config.network.sync_policy().request_timeout, Duration::from_secs(5));
+        assert_eq!(config.network.sync_policy().demote_after_stalls, DEFAULT_DEMOTE_AFTER_STALLS);
+
+        let config = NodeConfig::from_toml_str(
+            "[network.gossip]\nmesh_n = 8\nmessage_id = \"source\"\nban_threshold = -500.0\n",
+        )
+        .unwrap();
+        assert_eq!(config.network.gossip.mesh_n, 8);
+        assert_eq!(config.network.gossip.message_id, crate::network::MessageIdMode::Source);
+        assert!(!config.network.gossip.should_ban(-200.0));
+
+        let config =
+            NodeConfig::from_toml_str("[network]\nallow = [\"10.0.0.0/8\"]\ndeny = [\"10.6.6.6\"]\n").unwrap();
+        let rules = config.network.peer_filter().unwrap().rules();
+        assert_eq!((rules.allow, rules.deny), (vec!["10.0.0.0/8".to_string()], vec!["10.6.6.6".to_string()]));
+        assert!(NodeConfig::from_toml_str("[network]\ndeny = [\"10.0.0.0/40\"]\n").is_err());
+    }
+
+    #[test]
+    fn test_mempool_section() {
+        let config = NodeConfig::from_toml_str("").unwrap();
+        assert!(config.mempool.policy_filter.is_none());
+
+        let config = NodeConfig::from_toml_str("[mempool]\npolicy_filter = \"memo.wasm\"\n").unwrap();
+        assert_eq!(config.mempool.policy_filter, Some(PathBuf::from("memo.wasm")));
+        assert_eq!(config.mempool.policy_fuel, crate::mempool::DEFAULT_POLICY_FUEL);
+        assert_eq!(config.mempool.validation_workers, crate::mempool::DEFAULT_VALIDATION_WORKERS);
+
+        let config = NodeConfig::from_toml_str("[mempool]\nlocal_queue_size = 8\ngossip_queue_size = 64\n").unwrap();
+        assert_eq!((config.mempool.local_queue_size, config.mempool.gossip_queue_size), (8, 64));
+    }
+
+    #[test]
+    fn test_watchtower_section() {
+        let config = NodeConfig::from_toml_str("").unwrap();
+        assert!(!config.watchtower.enabled);
+
+        let config = NodeConfig::from_toml_str("[watchtower]\nenabled = true\n").unwrap();
+        assert!(config.watchtower.enabled);
+        assert!(!config.watchtower.gossip_evidence);
+    }
+
+    #[test]
+    fn test_supervisor_section() {
+        let config = NodeConfig::from_toml_str("").unwrap();
+        assert_eq!(config.supervisor.restart_policy(), RestartPolicy::default());
+
+        let config = NodeConfig::from_toml_str("[supervisor]\nmax_restarts = 0\n").unwrap();
+        assert_eq!(config.supervisor.restart_policy().max_restarts, 0);
+    }
+
+    #[test]
+    fn test_mining_section() {
+        let config = NodeConfig::from_toml_str("").unwrap();
+        assert!(!config.mining.enabled);
+        assert_eq!(config.mining.nonce_batch, crate::miner::DEFAULT_NONCE_BATCH);
+
+        let config = NodeConfig::from_toml_str("[mining]\nenabled = true\nnonce_batch = 500\n").unwrap();
+        assert!(config.mining.enabled);
+        assert_eq!(config.mining.nonce_batch, 500);
+    }
+
+    #[test]
+    fn test_authority_section() {
+        let key = |seed: u8| hex::encode(crate::crypto::derive_public_key(&[seed; 32]).unwrap().serialize());
+        let section = format!("[chain.authority]\nkeys = [\"{}\", \"{}\"]\nthreshold = 2\n", key(1), key(2));
+        let config = NodeConfig::from_toml_str(&format!("[chain]\nnetwork = \"regtest\"\n{}", section)).unwrap();
+        let authorities = config.chain.params().unwrap().authorities.unwrap();
+        assert_eq!((authorities.keys().len(), authorities.threshold()), (2, 2));
+
+        // Public networks never take authorities
+        assert!(NodeConfig::from_toml_str(&section).is_err());
+        let over = section.replace("threshold = 2", "threshold = 3");
+        assert!(NodeConfig::from_toml_str(&format!("[chain]\nnetwork = \"regtest\"\n{}", over)).is_err());
+    }
+
+    #[test]
+    fn test_rpc_section() {
+        let config = NodeConfig::from_toml_str("").unwrap();
+        assert_eq!(config.rpc.shutdown_grace(), crate::rpc::DEFAULT_SHUTDOWN_GRACE);
+
+        assert!(config.rpc.password.is_none());
+
+        let config =
+            NodeConfig::from_toml_str("[rpc]\nshutdown_grace_secs = 30\nuser = \"alice\"\npassword = \"secret\"\n").unwrap();
+        assert_eq!(config.rpc.shutdown_grace(), Duration::from_secs(30));
+        assert_eq!(config.rpc.user.as_deref(), Some("alice"));
+        assert_eq!(config.rpc.password.as_deref(), Some("secret"));
+
+        let config = NodeConfig::from_toml_str(
+            "[rpc]\npublic_methods = [\"getinfo\"]\nlocal_methods = [\"submitforge\"]\n",
+        )
+        .unwrap();
+        let policy = config.rpc.policy();
+        assert_eq!(policy.access("getinfo"), crate::rpc::MethodAccess::Public);
+        assert_eq!(policy.access("submitforge"), crate::rpc::MethodAccess::Local);
+        assert_eq!(policy.access("getblockcount"), crate::rpc::MethodAccess::Authenticated);
+        assert_eq!(policy.access("debug_dumporphans"), crate::rpc::MethodAccess::Authenticated);
+
+        // Debug methods are local-only unless the operator opens them up
+        let config = NodeConfig::from_toml_str("[rpc]\ndebug = true\npublic_methods = [\"debug_getchaintips\"]\n").unwrap();
+        let policy = config.rpc.policy();
+        assert_eq!(policy.access("debug_dumporphans"), crate::rpc::MethodAccess::Local);
+        assert_eq!(policy.access("debug_getchaintips"), crate::rpc::MethodAccess::Public);
+    }
+
+    #[test]
+    fn test_node_settings() {
+        let config = NodeConfig::from_toml_str("").unwrap();
+        assert_eq!(config.chain.params().unwrap().name, "mainnet");
+        assert_eq!(config.mempool.max_forges, crate::node::MEMPOOL_MAX_FORGES);
+        assert_eq!(config.logging.level, DEFAULT_LOG_LEVEL);
+
+        let config = NodeConfig::from_toml_str(
+            "[chain]\nnetwork = \"regtest\"\ndatadir = \"/srv/exs\"\n\
+             [network]\nport = 19444\nconnect = [\"/ip4/10.0.0.2/tcp/18444\"]\n\
+             [rpc]\nbind = \"0.0.0.0:18443\"\n[mempool]\nmax_forges = 500\nmin_fee = 10\n\
+             [logging]\nlevel = \"warn,excalibur_blockchain::network=debug\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.chain.params().unwrap().name, "regtest");
+        assert_eq!(config.chain.datadir, Some(PathBuf::from("/srv/exs")));
+        assert_eq!(config.network.port, Some(19444));
+        assert_eq!(config.network.connect_peers().unwrap().len(), 1);
+        assert_eq!(config.rpc.bind.as_deref(), Some("0.0.0.0:18443"));
+        assert_eq!((config.mempool.max_forges, config.mempool.min_fee), (500, 10));
+
+        assert!(NodeConfig::from_toml_str("[chain]\nnetwork = \"signet\"\n").is_err());
+        assert!(NodeConfig::from_toml_str("[network]\nconnect = [\"not an address\"]\n").is_err());
+        assert!(NodeConfig::from_toml_str("[logging]\nlevel = \"info,=[\"\n").is_err());
+    }
+
+    #[test]
+    fn test_config_builder() {
+        let config = ConfigBuilder::new()
+            .network("testnet")
+            .data_dir("/tmp/exs")
+            .port(18555)
+            .rpc_bind("127.0.0.1:18556")
+            .connect("/ip4/127.0.0.1/tcp/18333")
+            .mempool_max_forges(100)
+            .log_level("debug")
+            .with(|config| config.chain.txindex = true)
+            .build()
+            .unwrap();
+        assert_eq!(config.chain.params().unwrap().name, "testnet");
+        assert_eq!(config.network.port, Some(18555));
+        assert_eq!(config.mempool.max_forges, 100);
+        assert!(config.chain.txindex);
+
+        assert!(ConfigBuilder::new().connect("bogus").build().is_err());
+    }
+}
@@ -0,0 +1,247 @@
+//! Reloading the configuration file while the node runs
+//!
+//! On SIGHUP or `reloadconfig` the file is read and validated again, then
+//! compared setting by setting with what was read last. Changed settings in
+//! `RELOADABLE_SETTINGS` are applied straight away. Changes to
+//! `IMMUTABLE_SETTINGS` are refused, and any other change waits for a
+//! restart; both keep being reported until the node restarts. An invalid
+//! file changes nothing.
+//!
+//! Only settings edited in the file count, so command-line flags stay in
+//! force unless the file's value for the same setting changes.
+
+use super::{LogFilterHandle, NodeConfig};
+use crate::events::WebhookNotifier;
+use crate::mempool::ForgePool;
+use crate::network::{NetworkCommand, PeerFilter};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+
+/// Settings applied without a restart
+pub const RELOADABLE_SETTINGS: &[&str] = &[
+    "logging.level",
+    "mempool.max_forges",
+    "mempool.min_fee",
+    "mempool.incremental_fee",
+    "mempool.min_fee_half_life_secs",
+    "network.max_peer_upload_kib",
+    "network.allow",
+    "network.deny",
+    "events.webhooks",
+    "events.webhook_min_severity",
+];
+
+/// Settings that can't change for an existing data directory
+pub const IMMUTABLE_SETTINGS: &[&str] = &["chain.network", "chain.datadir"];
+
+/// Changed settings, by what a reload did with them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReloadReport {
+    /// Applied to the running node
+    pub applied: Vec<String>,
+    /// Refused: the node must be started again with the old value or a new
+    /// data directory
+    pub rejected: Vec<String>,
+    /// Take effect on the next start
+    pub restart_required: Vec<String>,
+}
+
+impl fmt::Display for ReloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |settings: &[String]| if settings.is_empty() { "none".to_string() } else { settings.join(", ") };
+        write!(f, "applied {}; rejected {}", list(&self.applied), list(&self.rejected))?;
+        if !self.restart_required.is_empty() {
+            write!(f, "; restart needed for {}", list(&self.restart_required))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parts of a running node that reloadable settings change
+pub struct ReloadTargets {
+    /// Absent when logging was set up by someone else
+    pub log_filter: Option<LogFilterHandle>,
+    pub pool: Arc<ForgePool>,
+    pub webhooks: WebhookNotifier,
+    pub peer_filter: Arc<PeerFilter>,
+    pub commands: mpsc::Sender<NetworkCommand>,
+}
+
+/// Re-reads a node's configuration file and applies what it can
+pub struct ConfigReloader {
+    path: PathBuf,
+    /// Settings as last read from the file, by dotted name
+    loaded: Mutex<BTreeMap<String, Value>>,
+    targets: ReloadTargets,
+}
+
+impl ConfigReloader {
+    /// Reloader for `path`, whose contents at startup were `loaded`
+    pub fn new(path: PathBuf, loaded: &NodeConfig, targets: ReloadTargets) -> Result<Self> {
+        Ok(Self {
+            path,
+            loaded: Mutex::new(settings(loaded)?),
+            targets,
+        })
+    }
+
+    /// The configuration file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the file again and apply changed reloadable settings
+    pub async fn reload(&self) -> Result<ReloadReport> {
+        let config = NodeConfig::load(&self.path)?;
+        let current = settings(&config)?;
+        let mut loaded = self.loaded.lock().await;
+        let report = compare(&loaded, &current);
+        let changed = |name: &str| report.applied.iter().any(|setting| setting == name);
+
+        // Everything that can fail is built before anything is applied
+        let log_filter = match &self.targets.log_filter {
+            Some(handle) if changed("logging.level") => Some((handle, config.logging.env_filter()?)),
+            _ => None,
+        };
+        let peer_rules = config.network.peer_rules()?;
+
+        if let Some((handle, filter)) = log_filter {
+            handle.reload(filter).map_err(|e| anyhow!("Failed to change the log filter: {}", e))?;
+        }
+        if report.applied.iter().any(|setting| setting.starts_with("mempool.")) {
+            let mempool = &config.mempool;
+            self.targets.pool.set_limits(
+                mempool.max_forges,
+                mempool.min_fee,
+                mempool.incremental_fee,
+                mempool.min_fee_half_life_secs,
+            );
+        }
+        if changed("network.max_peer_upload_kib") {
+            let limit = config.network.peer_upload_limit();
+            self.targets.commands.send(NetworkCommand::SetPeerUploadLimit(limit)).await?;
+        }
+        if changed("network.allow") || changed("network.deny") {
+            let (allow, deny) = peer_rules;
+            self.targets.peer_filter.set_rules(allow, deny);
+        }
+        if report.applied.iter().any(|setting| setting.starts_with("events.")) {
+            let events = &config.events;
+            self.targets.webhooks.set_endpoints(events.webhooks.clone(), events.webhook_min_severity);
+        }
+
+        // Refused and deferred changes stay pending until a restart
+        for setting in &report.applied {
+            loaded.insert(setting.clone(), current[setting].clone());
+        }
+        Ok(report)
+    }
+}
+
+/// Every setting by dotted name, e.g. `network.gossip.mesh_n`
+fn settings(config: &NodeConfig) -> Result<BTreeMap<String, Value>> {
+    fn flatten(prefix: &str, value: Value, out: &mut BTreeMap<String, Value>) {
+        match value {
+            Value::Object(fields) => {
+                for (name, value) in fields {
+                    let name = if prefix.is_empty() { name } else { format!("{}.{}", prefix, name) };
+                    flatten(&name, value, out);
+                }
+            }
+            value => {
+                out.insert(prefix.to_string(), value);
+            }
+        }
+    }
+    let mut out = BTreeMap::new();
+    flatten("", serde_json::to_value(config)?, &mut out);
+    Ok(out)
+}
+
+/// Sort the settings that differ between `loaded` and `current`
+fn compare(loaded: &BTreeMap<String, Value>, current: &BTreeMap<String, Value>) -> ReloadReport {
+    let mut report = ReloadReport::default();
+    let names = loaded.keys().chain(current.keys().filter(|name| !loaded.contains_key(*name)));
+    for name in names {
+        if loaded.get(name) == current.get(name) {
+            continue;
+        }
+        let list = if RELOADABLE_SETTINGS.contains(&name.as_str()) {
+            &mut report.applied
+        } else if IMMUTABLE_SETTINGS.contains(&name.as_str()) {
+            &mut report.rejected
+        } else {
+            &mut report.restart_required
+        };
+        list.push(name.clone());
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::{ChainStore, MemoryStore};
+    use crate::events::AlertSeverity;
+
+    #[tokio::test]
+    async fn test_reload_applies_and_reports() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("excalibur.toml");
+        let original = "[chain]\nnetwork = \"regtest\"\n[mempool]\nmin_fee = 10\n";
+        std::fs::write(&path, original).unwrap();
+
+        let pool = Arc::new(ForgePool::new(100, 10));
+        let store = Arc::new(ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap());
+        let webhooks = WebhookNotifier::new(vec![], AlertSeverity::Warning, store);
+        let peer_filter = Arc::new(PeerFilter::default());
+        let (commands, mut received) = mpsc::channel(4);
+        let targets = ReloadTargets {
+            log_filter: None,
+            pool: Arc::clone(&pool),
+            webhooks: webhooks.clone(),
+            peer_filter: Arc::clone(&peer_filter),
+            commands,
+        };
+        let reloader = ConfigReloader::new(path.clone(), &NodeConfig::load(&path).unwrap(), targets).unwrap();
+        assert_eq!(reloader.reload().await.unwrap(), ReloadReport::default());
+
+        std::fs::write(
+            &path,
+            "[chain]\nnetwork = \"testnet\"\n\
+             [rpc]\nbind = \"0.0.0.0:18443\"\n\
+             [mempool]\nmin_fee = 50\nmax_forges = 2\n\
+             [network]\nmax_peer_upload_kib = 8\ndeny = [\"203.0.113.0/24\"]\n\
+             [events]\nwebhooks = [\"http://127.0.0.1:9000/exs\"]\n",
+        )
+        .unwrap();
+        let report = reloader.reload().await.unwrap();
+        assert_eq!(
+            report.applied,
+            ["events.webhooks", "mempool.max_forges", "mempool.min_fee", "network.deny", "network.max_peer_upload_kib"]
+        );
+        assert_eq!(report.rejected, ["chain.network"]);
+        assert_eq!(report.restart_required, ["rpc.bind"]);
+        assert_eq!((pool.min_fee(), pool.get_stats().max_size), (50, 2));
+        assert_eq!(peer_filter.rules().deny, ["203.0.113.0/24"]);
+        assert!(matches!(received.try_recv(), Ok(NetworkCommand::SetPeerUploadLimit(8192))));
+        assert!(report.to_string().contains("rejected chain.network; restart needed for rpc.bind"));
+
+        // Applied settings are now current; the others are still pending
+        let report = reloader.reload().await.unwrap();
+        assert!(report.applied.is_empty());
+        assert_eq!((report.rejected.len(), report.restart_required.len()), (1, 1));
+
+        // An invalid file changes nothing
+        std::fs::write(&path, "[mempool]\nmin_fee = 70\n[network]\ndeny = [\"not a rule\"]\n").unwrap();
+        assert!(reloader.reload().await.is_err());
+        assert_eq!(pool.min_fee(), 50);
+        assert_eq!(peer_filter.rules().deny.len(), 1);
+    }
+}
@@ -0,0 +1,136 @@
+//! Aggregate forge commitments (experimental)
+//!
+//! A header that sets `VERSION_AGGREGATE_COMMITMENT` carries a commitment to
+//! all of its forges: a merkle root over their proof hashes and a rolling
+//! hash over their tempered (derived) keys, in block order. Full nodes check
+//! it against the forges; a future succinct sync mode could check proof
+//! hashes and tempered keys against the commitment instead of re-deriving
+//! every forge.
+
+use super::{merkle_root, BlockHeader, ForgeTransaction};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Header version bit signalling an aggregate commitment
+pub const VERSION_AGGREGATE_COMMITMENT: u32 = 1 << 8;
+
+const TEMPERED_KEYS_TAG: &[u8] = b"ExcaliburAggregate/tempered-keys";
+
+/// Commitment to every forge of a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AggregateCommitment {
+    /// Merkle root of the forges' proof hashes
+    pub proof_root: [u8; 32],
+    /// Rolling hash of the forges' tempered keys
+    pub tempered_keys_hash: [u8; 32],
+}
+
+impl AggregateCommitment {
+    /// Commitment to `forges`, in block order
+    pub fn compute(forges: &[ForgeTransaction]) -> Self {
+        Self::from_parts(
+            forges.iter().map(|forge| forge.proof_hash),
+            forges.iter().map(|forge| forge.derived_key.as_slice()),
+        )
+    }
+
+    /// Commitment from proof hashes and tempered keys alone, for clients
+    /// that never see the full forges
+    pub fn from_parts<'a>(
+        proof_hashes: impl IntoIterator<Item = [u8; 32]>,
+        tempered_keys: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Self {
+        Self {
+            proof_root: merkle_root(proof_hashes.into_iter().collect()),
+            tempered_keys_hash: tempered_keys_hash(tempered_keys),
+        }
+    }
+}
+
+fn tempered_keys_hash<'a>(keys: impl IntoIterator<Item = &'a [u8]>) -> [u8; 32] {
+    let mut rolling: [u8; 32] = Sha256::digest(TEMPERED_KEYS_TAG).into();
+    for key in keys {
+        let mut hasher = Sha256::new();
+        hasher.update(rolling);
+        hasher.update((key.len() as u64).to_le_bytes());
+        hasher.update(key);
+        rolling = hasher.finalize().into();
+    }
+    rolling
+}
+
+/// Check that a header carries a correct aggregate commitment exactly when
+/// its version bit is set
+pub fn check_aggregate_commitment(header: &BlockHeader, forges: &[ForgeTransaction]) -> Result<()> {
+    let signalled = header.version & VERSION_AGGREGATE_COMMITMENT != 0;
+    match (signalled, &header.aggregate_commitment) {
+        (false, None) => Ok(()),
+        (false, Some(_)) => Err(anyhow!("Aggregate commitment present without its version bit")),
+        (true, None) => Err(anyhow!("Header signals an aggregate commitment but has none")),
+        (true, Some(commitment)) if *commitment != AggregateCommitment::compute(forges) => {
+            Err(anyhow!("Aggregate commitment mismatch"))
+        }
+        (true, Some(_)) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::{testing, Block};
+
+    fn block(keys: &[&[u8]]) -> Block {
+        let forges = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| ForgeTransaction {
+                prophecy: "sword legend".to_string(),
+                derived_key: key.to_vec(),
+                taproot_address: "bc1p...".to_string(),
+                proof_hash: [i as u8 + 1; 32],
+                timestamp: 0,
+                signature: vec![],
+                not_before_height: 0,
+                tempering: Default::default(),
+                salt: [0; 32],
+            })
+            .collect();
+        testing::block(1, [0u8; 32], forges)
+    }
+
+    #[test]
+    fn test_commitment_binds_proofs_and_keys_in_order() {
+        let a = AggregateCommitment::compute(&block(&[b"k1", b"k2", b"k3"]).forges);
+        assert_eq!(a, AggregateCommitment::compute(&block(&[b"k1", b"k2", b"k3"]).forges));
+        assert_ne!(a, AggregateCommitment::compute(&block(&[b"k2", b"k1", b"k3"]).forges));
+        // Length-prefixing keeps key boundaries unambiguous
+        assert_ne!(
+            AggregateCommitment::compute(&block(&[b"ab", b"c"]).forges).tempered_keys_hash,
+            AggregateCommitment::compute(&block(&[b"a", b"bc"]).forges).tempered_keys_hash
+        );
+
+        let proofs = [[1u8; 32], [2u8; 32], [3u8; 32]];
+        let keys: [&[u8]; 3] = [b"k1", b"k2", b"k3"];
+        assert_eq!(AggregateCommitment::from_parts(proofs, keys), a);
+    }
+
+    #[test]
+    fn test_commitment_gated_by_version_bit() {
+        let mut block = block(&[b"k1", b"k2"]);
+        check_aggregate_commitment(&block.header, &block.forges).unwrap();
+
+        block.set_aggregate_commitment();
+        assert!(block.header.version & VERSION_AGGREGATE_COMMITMENT != 0);
+        check_aggregate_commitment(&block.header, &block.forges).unwrap();
+
+        block.forges[1].derived_key = b"other".to_vec();
+        assert!(check_aggregate_commitment(&block.header, &block.forges).is_err());
+
+        block.header.version &= !VERSION_AGGREGATE_COMMITMENT;
+        assert!(check_aggregate_commitment(&block.header, &block.forges).is_err());
+        block.header.aggregate_commitment = None;
+        block.header.version |= VERSION_AGGREGATE_COMMITMENT;
+        assert!(check_aggregate_commitment(&block.header, &block.forges).is_err());
+    }
+}
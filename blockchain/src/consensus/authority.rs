@@ -0,0 +1,278 @@
+//! Authority signatures for permissioned chains
+//!
+//! A consortium or private network can require, from an activation height
+//! on, that every block carry BIP-340 signatures from at least `threshold`
+//! of the keys in its `AuthoritySet`, in addition to valid forges and header
+//! work. The proposer at height `h` is key `h % N` and must be one of the
+//! signers, so block production rotates between the authorities.
+//!
+//! Signatures commit to the block hash, which commits to the forges through
+//! the merkle root, so they travel with the block without being part of its
+//! hash. On chains without an authority set, and below the activation
+//! height, blocks must carry no signatures.
+
+use super::Block;
+use anyhow::{anyhow, Context, Result};
+use bitcoin::secp256k1::{schnorr, Keypair, Message, Secp256k1, SecretKey, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// Tag of the message authorities sign
+const BLOCK_SIGNING_TAG: &[u8] = b"ExcaliburAuthority/block";
+
+/// Tag of an authority set's identifier
+const SET_ID_TAG: &[u8] = b"ExcaliburAuthority/set";
+
+/// Signature by one authority over a block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthoritySignature {
+    /// Index of the signing key in the authority set
+    pub signer: u32,
+    /// BIP-340 signature (64 bytes)
+    pub signature: Vec<u8>,
+}
+
+/// Keys allowed to sign blocks, and how many must
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthoritySet {
+    keys: Vec<XOnlyPublicKey>,
+    threshold: usize,
+    activation_height: u64,
+}
+
+impl AuthoritySet {
+    /// `threshold` of `keys` must sign every block from `activation_height` on
+    pub fn new(keys: Vec<XOnlyPublicKey>, threshold: usize, activation_height: u64) -> Result<Self> {
+        if keys.len() > u32::MAX as usize {
+            return Err(anyhow!("Too many authority keys"));
+        }
+        if threshold == 0 || threshold > keys.len() {
+            return Err(anyhow!("Authority threshold must be between 1 and {}, got {}", keys.len(), threshold));
+        }
+        for (i, key) in keys.iter().enumerate() {
+            if keys[..i].contains(key) {
+                return Err(anyhow!("Authority key {} is listed twice", key));
+            }
+        }
+        Ok(Self {
+            keys,
+            threshold,
+            activation_height,
+        })
+    }
+
+    /// Authority keys, in signer index order
+    pub fn keys(&self) -> &[XOnlyPublicKey] {
+        &self.keys
+    }
+
+    /// Signatures each block needs
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// First height whose blocks must be signed
+    pub fn activation_height(&self) -> u64 {
+        self.activation_height
+    }
+
+    /// Whether blocks at `height` must be signed
+    pub fn applies_at(&self, height: u64) -> bool {
+        height >= self.activation_height
+    }
+
+    /// Index of the key that proposes the block at `height`
+    pub fn proposer(&self, height: u64) -> usize {
+        (height % self.keys.len() as u64) as usize
+    }
+
+    /// Identifier committing to the keys, threshold and activation height
+    pub fn id(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(SET_ID_TAG);
+        hasher.update((self.threshold as u64).to_le_bytes());
+        hasher.update(self.activation_height.to_le_bytes());
+        for key in &self.keys {
+            hasher.update(key.serialize());
+        }
+        hasher.finalize().into()
+    }
+
+    /// Check the authority signatures of `block`, whose hash is `block_hash`.
+    /// Signers must be listed in increasing index order.
+    pub fn check_block(&self, block: &Block, block_hash: &[u8; 32]) -> Result<()> {
+        let height = block.header.height;
+        if !self.applies_at(height) {
+            return check_unsigned(block);
+        }
+        let mut previous = None;
+        for entry in &block.authority_signatures {
+            if previous.is_some_and(|previous| entry.signer <= previous) {
+                return Err(anyhow!("Authority signatures must be in increasing signer order"));
+            }
+            previous = Some(entry.signer);
+            self.check_signature(entry, block_hash)?;
+        }
+        if block.authority_signatures.len() < self.threshold {
+            return Err(anyhow!(
+                "Block has {} authority signatures, {} required",
+                block.authority_signatures.len(),
+                self.threshold
+            ));
+        }
+        let proposer = self.proposer(height);
+        if !block.authority_signatures.iter().any(|entry| entry.signer as usize == proposer) {
+            return Err(anyhow!("Block at height {} is not signed by its proposer {}", height, proposer));
+        }
+        Ok(())
+    }
+
+    /// Check one authority's signature over the block hash `block_hash`
+    pub fn check_signature(&self, entry: &AuthoritySignature, block_hash: &[u8; 32]) -> Result<()> {
+        let key = self
+            .keys
+            .get(entry.signer as usize)
+            .ok_or_else(|| anyhow!("Unknown authority signer {}", entry.signer))?;
+        schnorr::Signature::from_slice(&entry.signature)
+            .and_then(|signature| {
+                Secp256k1::verification_only().verify_schnorr(&signature, &block_signing_message(block_hash), key)
+            })
+            .map_err(|e| anyhow!("Bad signature from authority {}: {}", entry.signer, e))
+    }
+}
+
+/// Blocks of chains without authorities, or below activation, carry no
+/// signatures
+pub fn check_unsigned(block: &Block) -> Result<()> {
+    if block.authority_signatures.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("Block carries authority signatures but none are expected"))
+    }
+}
+
+fn block_signing_message(block_hash: &[u8; 32]) -> Message {
+    let mut hasher = Sha256::new();
+    hasher.update(BLOCK_SIGNING_TAG);
+    hasher.update(block_hash);
+    Message::from_digest(hasher.finalize().into())
+}
+
+/// Key of one authority, for signing blocks
+#[derive(Clone)]
+pub struct AuthorityKey {
+    index: u32,
+    keypair: Keypair,
+}
+
+impl AuthorityKey {
+    /// Key for `secret`, which must belong to `set`
+    pub fn new(set: &AuthoritySet, secret: &SecretKey) -> Result<Self> {
+        let keypair = Keypair::from_secret_key(&Secp256k1::new(), secret);
+        let (public, _) = keypair.x_only_public_key();
+        let index = set
+            .keys
+            .iter()
+            .position(|key| *key == public)
+            .ok_or_else(|| anyhow!("Key {} is not in the authority set", public))?;
+        Ok(Self {
+            index: index as u32,
+            keypair,
+        })
+    }
+
+    /// Key in the file at `path` (hex secret key), which must belong to `set`
+    pub fn load<P: AsRef<Path>>(path: P, set: &AuthoritySet) -> Result<Self> {
+        let path = path.as_ref();
+        let encoded = Zeroizing::new(
+            std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read authority key {}", path.display()))?,
+        );
+        let secret = hex::decode(encoded.trim())
+            .ok()
+            .map(Zeroizing::new)
+            .and_then(|bytes| SecretKey::from_slice(&bytes).ok())
+            .ok_or_else(|| anyhow!("Corrupt authority key {}", path.display()))?;
+        Self::new(set, &secret)
+    }
+
+    /// Index of the key in the authority set
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Add this authority's signature to `block`, whose hash is
+    /// `block_hash`, keeping signers in index order. Signing again replaces
+    /// the earlier signature.
+    pub fn sign(&self, block: &mut Block, block_hash: &[u8; 32]) {
+        let message = block_signing_message(block_hash);
+        let signature = Secp256k1::signing_only().sign_schnorr_with_aux_rand(&message, &self.keypair, &rand::random());
+        let entry = AuthoritySignature {
+            signer: self.index,
+            signature: signature.as_ref().to_vec(),
+        };
+        let signatures = &mut block.authority_signatures;
+        match signatures.binary_search_by_key(&self.index, |entry| entry.signer) {
+            Ok(position) => signatures[position] = entry,
+            Err(position) => signatures.insert(position, entry),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::testing;
+
+    fn secret(seed: u8) -> SecretKey {
+        SecretKey::from_slice(&[seed; 32]).unwrap()
+    }
+
+    fn public(seed: u8) -> XOnlyPublicKey {
+        Keypair::from_secret_key(&Secp256k1::new(), &secret(seed)).x_only_public_key().0
+    }
+
+    fn block(height: u64) -> Block {
+        testing::block(height, [0; 32], vec![])
+    }
+
+    #[test]
+    fn test_threshold_and_round_robin_proposer() {
+        let set = AuthoritySet::new(vec![public(1), public(2), public(3)], 2, 5).unwrap();
+        let keys: Vec<AuthorityKey> = (1..=3).map(|seed| AuthorityKey::new(&set, &secret(seed)).unwrap()).collect();
+        let hash = [7u8; 32];
+        assert_eq!(set.proposer(7), 1);
+
+        // Below activation blocks stay unsigned
+        let mut early = block(4);
+        assert!(set.check_block(&early, &hash).is_ok());
+        keys[0].sign(&mut early, &hash);
+        assert!(set.check_block(&early, &hash).is_err());
+
+        let mut signed = block(7);
+        keys[2].sign(&mut signed, &hash);
+        assert!(set.check_block(&signed, &hash).unwrap_err().to_string().contains("1 authority signatures"));
+        keys[0].sign(&mut signed, &hash);
+        assert!(set.check_block(&signed, &hash).unwrap_err().to_string().contains("proposer 1"));
+        keys[1].sign(&mut signed, &hash);
+        assert_eq!(signed.authority_signatures.iter().map(|entry| entry.signer).collect::<Vec<_>>(), [0, 1, 2]);
+        set.check_block(&signed, &hash).unwrap();
+
+        // Signatures commit to the block hash
+        assert!(set.check_block(&signed, &[8u8; 32]).is_err());
+        signed.authority_signatures.swap(0, 1);
+        assert!(set.check_block(&signed, &hash).is_err());
+    }
+
+    #[test]
+    fn test_invalid_sets_and_foreign_keys() {
+        assert!(AuthoritySet::new(vec![public(1)], 0, 0).is_err());
+        assert!(AuthoritySet::new(vec![public(1)], 2, 0).is_err());
+        assert!(AuthoritySet::new(vec![public(1), public(1)], 1, 0).is_err());
+        let set = AuthoritySet::new(vec![public(1), public(2)], 1, 0).unwrap();
+        assert!(AuthorityKey::new(&set, &secret(3)).is_err());
+        assert_ne!(set.id(), AuthoritySet::new(vec![public(1), public(2)], 2, 0).unwrap().id());
+    }
+}
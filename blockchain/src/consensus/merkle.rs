@@ -0,0 +1,309 @@
+//! Forge merkle roots
+//!
+//! Leaves are SHA-256 hashes of canonically encoded forges, paired level by
+//! level with the last hash of an odd level duplicated. Blocks near the
+//! forge cap hash their leaves and levels on the rayon pool. Leaf hashes of
+//! forges validated for the mempool are kept in a `LeafCache`, so
+//! assembling a block from them doesn't hash each forge again.
+//!
+//! `MerkleTree` keeps every level so it can prove a forge's inclusion: the
+//! proof is the sibling hash at each level, leaf first, and a light client
+//! holding only the header's merkle root checks it with `MerkleTree::verify`.
+//! Headers of blocks with transfers commit to the forge root tagged-hashed
+//! with the transfer root, so such a proof is checked through
+//! `MerkleTree::proof_root` and `commit_transfer_root`.
+
+use super::ForgeTransaction;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::sync::RwLock;
+
+/// Levels with at least this many hashes are hashed in parallel
+pub const PARALLEL_MERKLE_THRESHOLD: usize = 64;
+
+/// Default number of leaf hashes a `LeafCache` keeps
+pub const DEFAULT_LEAF_CACHE_CAPACITY: usize = 10_000;
+
+/// Merkle leaf of a forge
+pub fn forge_leaf_hash(forge: &ForgeTransaction) -> [u8; 32] {
+    Sha256::digest(forge.encode()).into()
+}
+
+/// Pairwise SHA-256 merkle root, duplicating the last hash of odd levels
+pub fn merkle_root(mut hashes: Vec<[u8; 32]>) -> [u8; 32] {
+    if hashes.is_empty() {
+        return [0u8; 32];
+    }
+    while hashes.len() > 1 {
+        hashes = if hashes.len() >= PARALLEL_MERKLE_THRESHOLD {
+            hashes.par_chunks(2).map(hash_pair).collect()
+        } else {
+            hashes.chunks(2).map(hash_pair).collect()
+        };
+    }
+    hashes[0]
+}
+
+/// Sibling hashes from a leaf up to the root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// Position of the leaf in the block
+    pub index: usize,
+    /// One hash per level, the leaf's sibling first. The last hash of an
+    /// odd level is its own sibling.
+    pub siblings: Vec<[u8; 32]>,
+}
+
+/// Merkle tree over a block's forges, every level kept
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    /// Leaves first, the root alone last
+    levels: Vec<Vec<[u8; 32]>>,
+}
+
+impl MerkleTree {
+    /// Build the tree over `forges` in block order
+    pub fn build(forges: &[ForgeTransaction]) -> Self {
+        let mut levels = vec![forges.iter().map(forge_leaf_hash).collect::<Vec<_>>()];
+        while levels[levels.len() - 1].len() > 1 {
+            let level = &levels[levels.len() - 1];
+            let next = if level.len() >= PARALLEL_MERKLE_THRESHOLD {
+                level.par_chunks(2).map(hash_pair).collect()
+            } else {
+                level.chunks(2).map(hash_pair).collect()
+            };
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    /// Merkle root, as `merkle_root` computes it
+    pub fn root(&self) -> [u8; 32] {
+        match self.levels.last().and_then(|level| level.first()) {
+            Some(root) => *root,
+            None => [0u8; 32],
+        }
+    }
+
+    /// Number of leaves
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// Whether the tree has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Inclusion proof for the leaf at `index`, if there is one
+    pub fn prove(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.len() {
+            return None;
+        }
+        let siblings = self.levels[..self.levels.len() - 1]
+            .iter()
+            .enumerate()
+            .map(|(depth, level)| {
+                let position = index >> depth;
+                *level.get(position ^ 1).unwrap_or(&level[position])
+            })
+            .collect();
+        Some(MerkleProof { index, siblings })
+    }
+
+    /// Whether `proof` places `leaf` under `root`
+    pub fn verify(root: &[u8; 32], proof: &MerkleProof, leaf: &[u8; 32]) -> bool {
+        Self::proof_root(proof, leaf).as_ref() == Some(root)
+    }
+
+    /// Root `proof` leads to from `leaf`, `None` if the proof is malformed
+    pub fn proof_root(proof: &MerkleProof, leaf: &[u8; 32]) -> Option<[u8; 32]> {
+        // Deeper than any tree over `usize` leaves
+        if proof.siblings.len() >= usize::BITS as usize || proof.index >> proof.siblings.len() != 0 {
+            return None;
+        }
+        let root = proof.siblings.iter().enumerate().fold(*leaf, |hash, (depth, sibling)| {
+            if (proof.index >> depth) & 1 == 0 {
+                hash_pair(&[hash, *sibling])
+            } else {
+                hash_pair(&[*sibling, hash])
+            }
+        });
+        Some(root)
+    }
+}
+
+fn hash_pair(pair: &[[u8; 32]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(pair[0]);
+    hasher.update(pair.get(1).unwrap_or(&pair[0]));
+    hasher.finalize().into()
+}
+
+/// Bounded cache of forge leaf hashes by proof hash. A hit is only used
+/// if the cached forge is identical, so a different forge reusing a proof
+/// hash is still hashed.
+pub struct LeafCache {
+    inner: RwLock<LeafCacheInner>,
+    capacity: usize,
+}
+
+#[derive(Default)]
+struct LeafCacheInner {
+    leaves: HashMap<[u8; 32], (ForgeTransaction, [u8; 32])>,
+    /// Insertion order, for evicting the oldest entry
+    order: VecDeque<[u8; 32]>,
+}
+
+impl LeafCache {
+    /// Create a cache holding at most `capacity` leaves
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: RwLock::new(LeafCacheInner::default()),
+            capacity,
+        }
+    }
+
+    /// Hash a forge's leaf and keep it
+    pub fn insert(&self, forge: &ForgeTransaction) -> [u8; 32] {
+        let leaf = forge_leaf_hash(forge);
+        if self.capacity == 0 {
+            return leaf;
+        }
+        let mut inner = self.inner.write().unwrap();
+        if inner.leaves.insert(forge.proof_hash, (forge.clone(), leaf)).is_none() {
+            inner.order.push_back(forge.proof_hash);
+        }
+        while inner.leaves.len() > self.capacity {
+            let Some(oldest) = inner.order.pop_front() else { break };
+            inner.leaves.remove(&oldest);
+        }
+        leaf
+    }
+
+    /// Leaf of a forge, from the cache if it holds this exact forge
+    pub fn leaf(&self, forge: &ForgeTransaction) -> [u8; 32] {
+        let inner = self.inner.read().unwrap();
+        match inner.leaves.get(&forge.proof_hash) {
+            Some((cached, leaf)) if cached == forge => *leaf,
+            _ => forge_leaf_hash(forge),
+        }
+    }
+
+    /// Forget the leaves of forges that have been mined
+    pub fn remove_block_forges(&self, forges: &[ForgeTransaction]) {
+        let mut inner = self.inner.write().unwrap();
+        for forge in forges {
+            inner.leaves.remove(&forge.proof_hash);
+        }
+        let LeafCacheInner { leaves, order } = &mut *inner;
+        order.retain(|proof_hash| leaves.contains_key(proof_hash));
+    }
+
+    /// Number of cached leaves
+    pub fn len(&self) -> usize {
+        self.inner.read().unwrap().leaves.len()
+    }
+
+    /// Whether no leaves are cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Leaves of `forges` in order, hashed on the rayon pool for large blocks
+    pub fn leaves(&self, forges: &[ForgeTransaction]) -> Vec<[u8; 32]> {
+        if forges.len() >= PARALLEL_MERKLE_THRESHOLD {
+            forges.par_iter().map(|forge| self.leaf(forge)).collect()
+        } else {
+            forges.iter().map(|forge| self.leaf(forge)).collect()
+        }
+    }
+}
+
+impl Default for LeafCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_LEAF_CACHE_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::testing::forge;
+
+    /// The original sequential construction
+    fn reference_root(forges: &[ForgeTransaction]) -> [u8; 32] {
+        let mut hashes: Vec<[u8; 32]> = forges.iter().map(forge_leaf_hash).collect();
+        if hashes.is_empty() {
+            return [0u8; 32];
+        }
+        while hashes.len() > 1 {
+            let mut next_level = Vec::new();
+            for chunk in hashes.chunks(2) {
+                let mut hasher = Sha256::new();
+                hasher.update(chunk[0]);
+                hasher.update(if chunk.len() > 1 { chunk[1] } else { chunk[0] });
+                next_level.push(hasher.finalize().into());
+            }
+            hashes = next_level;
+        }
+        hashes[0]
+    }
+
+    #[test]
+    fn test_parallel_root_matches_sequential() {
+        let cache = LeafCache::default();
+        for count in [0, 1, 2, 3, 63, 64, 65, 100, 129] {
+            let forges: Vec<_> = (1..=count).map(forge).collect();
+            assert_eq!(merkle_root(cache.leaves(&forges)), reference_root(&forges), "{} forges", count);
+        }
+    }
+
+    #[test]
+    fn test_merkle_proofs() {
+        for count in [1, 2, 3, 7, 64, 65] {
+            let forges: Vec<_> = (1..=count).map(forge).collect();
+            let tree = MerkleTree::build(&forges);
+            let root = reference_root(&forges);
+            assert_eq!(tree.root(), root, "{} forges", count);
+            for (index, forge) in forges.iter().enumerate() {
+                let proof = tree.prove(index).unwrap();
+                assert!(MerkleTree::verify(&root, &proof, &forge_leaf_hash(forge)), "{} of {}", index, count);
+            }
+            assert!(tree.prove(count as usize).is_none());
+        }
+
+        let forges: Vec<_> = (1..=5).map(forge).collect();
+        let tree = MerkleTree::build(&forges);
+        let proof = tree.prove(2).unwrap();
+        assert!(!MerkleTree::verify(&tree.root(), &proof, &forge_leaf_hash(&forges[3])));
+        let moved = MerkleProof { index: 3, ..proof.clone() };
+        assert!(!MerkleTree::verify(&tree.root(), &moved, &forge_leaf_hash(&forges[2])));
+        let beyond = MerkleProof { index: 8, ..proof };
+        assert!(!MerkleTree::verify(&tree.root(), &beyond, &forge_leaf_hash(&forges[2])));
+        assert_eq!(MerkleTree::build(&[]).root(), [0u8; 32]);
+    }
+
+    #[test]
+    fn test_leaf_cache_hits_only_identical_forges() {
+        let cache = LeafCache::new(2);
+        let original = forge(1);
+        assert_eq!(cache.insert(&original), forge_leaf_hash(&original));
+        assert_eq!(cache.leaf(&original), forge_leaf_hash(&original));
+
+        // Same proof hash, different forge: hashed, not served from cache
+        let resigned = ForgeTransaction { signature: vec![1], ..original.clone() };
+        assert_eq!(cache.leaf(&resigned), forge_leaf_hash(&resigned));
+
+        cache.insert(&forge(2));
+        cache.insert(&forge(3));
+        assert_eq!(cache.len(), 2, "oldest leaf evicted");
+        cache.remove_block_forges(&[forge(2)]);
+        assert_eq!(cache.len(), 1);
+        cache.insert(&forge(4));
+        cache.insert(&forge(5));
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -1,10 +1,50 @@
 //! Consensus engine for Proof-of-Forge
 
-use crate::crypto::{proof_of_forge, ProofOfForgeResult, CANONICAL_PROPHECY};
+use crate::crypto::{
+    check_prophecy_words, forge_proof_hash, forge_tempering_salt, prophecy_registry_hash, proof_of_forge_with_progress,
+    sign_taproot_key_path, verify_taproot_key_path, CancelToken, DerivedAddresses, ProofOfForgeResult, TemperingAlgorithm,
+};
+use crate::chain::{BlockUndo, ChainStore, ConsensusRecord, ProphecyOwner, WriteBatch};
+use crate::codec::{forge_signing_preimage, header_hash_preimage};
+use crate::ledger::{
+    BlockChanges, Ledger, LedgerOutput, LedgerSetInfo, LedgerSnapshot, OutPoint, SetHash, SparseMerkleTree, LeafChanges,
+    SupplyInfo,
+};
+use crate::params::{NetworkParams, DIFFICULTY_ADJUSTMENT_FORGES, MAX_FORGES_PER_BLOCK, MAX_TRANSFERS_PER_BLOCK};
+use bitcoin::pow::{CompactTarget, Target, Work};
+use bitcoin::Network;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, RwLock};
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
+
+mod aggregate;
+mod authority;
+pub mod merkle;
+mod rules;
+pub mod sighash;
+mod state_root;
+#[cfg(test)]
+pub(crate) mod testing;
+mod timestamp;
+pub mod transfer;
+mod validation;
+
+pub use aggregate::{check_aggregate_commitment, AggregateCommitment, VERSION_AGGREGATE_COMMITMENT};
+pub use authority::{AuthorityKey, AuthoritySet, AuthoritySignature};
+pub use merkle::{
+    forge_leaf_hash, merkle_root, LeafCache, MerkleProof, MerkleTree, DEFAULT_LEAF_CACHE_CAPACITY,
+    PARALLEL_MERKLE_THRESHOLD,
+};
+pub use rules::{ConsensusRule, RuleContext};
+pub use state_root::{check_state_root, state_root, used_proof_tree_value, StateProof, VERSION_STATE_ROOT};
+pub use timestamp::{
+    header_time_millis, set_header_time_millis, RecentBlockTimes, MEDIAN_TIME_SPAN, VERSION_TIMESTAMP_MILLIS,
+};
+pub use transfer::{block_merkle_root, commit_transfer_root, transfer_root, SignedTransfer, TransferWitness};
+pub use validation::{ValidationMetrics, ValidationStage, SLOW_BLOCK_THRESHOLD};
+use rules::RuleSet;
+use validation::BlockValidationTimer;
 
 /// Block header for the Excalibur blockchain
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,18 +55,167 @@ pub struct BlockHeader {
     pub merkle_root: [u8; 32],
     pub timestamp: u64,
     pub difficulty: u32,
+    /// Compact header proof-of-work target (nBits encoding)
+    #[serde(default)]
+    pub bits: u32,
     pub nonce: u64,
+    /// Commitment to all forges, present iff `VERSION_AGGREGATE_COMMITMENT`
+    /// is set
+    #[serde(default)]
+    pub aggregate_commitment: Option<AggregateCommitment>,
+    /// Root of the state after this block, present iff
+    /// `VERSION_STATE_ROOT` is set
+    #[serde(default)]
+    pub state_root: Option<[u8; 32]>,
+    /// Milliseconds past `timestamp` (0-999), present iff
+    /// `VERSION_TIMESTAMP_MILLIS` is set
+    #[serde(default)]
+    pub timestamp_millis: Option<u16>,
 }
 
 /// Forge transaction representing a successful proof-of-forge
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ForgeTransaction {
     pub prophecy: String,
+    /// Forger's compressed public key. The derivation is tempered with a
+    /// salt bound to it, the forge pays to its P2TR address and must be
+    /// signed by it, so only the holder of its secret key can claim the
+    /// forge's work or spend its output.
     pub derived_key: Vec<u8>,
     pub taproot_address: String,
     pub proof_hash: [u8; 32],
     pub timestamp: u64,
     pub signature: Vec<u8>,
+    /// Earliest block height this forge may be included in (anti-fee-sniping lock)
+    #[serde(default)]
+    pub not_before_height: u64,
+    /// How the derivation was tempered. Left out of serialized forges when
+    /// it is the default, PBKDF2, so their encoding is unchanged.
+    #[serde(default, skip_serializing_if = "TemperingAlgorithm::is_pbkdf2")]
+    pub tempering: TemperingAlgorithm,
+    /// Salt the derivation was tempered with, chosen by the forger. Each
+    /// salt gives a different proof hash, and forgers vary it until one
+    /// meets the difficulty. All zero in forges from before salts, which no
+    /// longer validate. Left out of serialized forges when all zero.
+    #[serde(default, skip_serializing_if = "is_unsalted")]
+    pub salt: [u8; 32],
+}
+
+fn is_unsalted(salt: &[u8; 32]) -> bool {
+    *salt == [0u8; 32]
+}
+
+impl ForgeTransaction {
+    /// Hash committed to by the forge signature (every field except the
+    /// signature itself)
+    pub fn signing_hash(&self) -> [u8; 32] {
+        use sha2::{Digest, Sha256};
+
+        let mut hasher = Sha256::new();
+        hasher.update(b"ExcaliburForge/sighash");
+        hasher.update(forge_signing_preimage(self));
+        hasher.finalize().into()
+    }
+
+    /// Whether the forge has a salt, as every forge that validates does
+    pub fn is_salted(&self) -> bool {
+        !is_unsalted(&self.salt)
+    }
+
+    /// Sign the forge with the Taproot key-path key of the forger's secret
+    /// key, the one `derived_key` is the public key of
+    pub fn sign(&mut self, secret_key: &[u8]) -> Result<()> {
+        self.signature = sign_taproot_key_path(secret_key, &self.signing_hash())?.to_vec();
+        Ok(())
+    }
+
+    /// Check the forge carries a BIP-340 signature by the Taproot output
+    /// key of `derived_key`, i.e. the key its P2TR address pays to
+    pub fn verify_signature(&self) -> Result<()> {
+        if self.signature.is_empty() {
+            return Err(anyhow!("Forge is unsigned"));
+        }
+        let public_key = bitcoin::secp256k1::PublicKey::from_slice(&self.derived_key)
+            .map_err(|_| anyhow!("Invalid derived key"))?;
+        verify_taproot_key_path(&public_key, &self.signing_hash(), &self.signature)
+    }
+}
+
+/// P2TR address of a forge's key, if the address it pays to is valid at
+/// `height` under the P2TR transition rule.
+///
+/// From `p2tr_activation_height` on, a forge must pay to the P2TR address
+/// of its derived key on `network`. Before that, the legacy P2WPKH address
+/// of the same key is also accepted.
+pub fn canonical_forge_address(
+    forge: &ForgeTransaction,
+    height: u64,
+    network: Network,
+    p2tr_activation_height: u64,
+) -> Result<String> {
+    let public_key = bitcoin::secp256k1::PublicKey::from_slice(&forge.derived_key)
+        .map_err(|e| anyhow!("Invalid derived key: {}", e))?;
+    let addresses = DerivedAddresses::for_key(&public_key, network)?;
+
+    if forge.taproot_address == addresses.p2tr {
+        return Ok(addresses.p2tr);
+    }
+    if height >= p2tr_activation_height {
+        return Err(if forge.taproot_address == addresses.legacy_p2wpkh {
+            anyhow!("Legacy P2WPKH forge address not allowed from height {}", p2tr_activation_height)
+        } else {
+            anyhow!("Taproot address mismatch")
+        });
+    }
+    if forge.taproot_address == addresses.legacy_p2wpkh {
+        return Ok(addresses.p2tr);
+    }
+    Err(anyhow!("Forge address does not match its derived key"))
+}
+
+/// Check a forge included at `height` against the derivation of its
+/// prophecy, tempered with `forge_tempering_salt` of the forger's key and
+/// salt: the proof hash commits to its stages and the forge's salt, and the
+/// address is one `canonical_forge_address` accepts for the forger's key
+pub fn check_forge_matches_proof(
+    forge: &ForgeTransaction,
+    pof_result: &ProofOfForgeResult,
+    height: u64,
+    network: Network,
+    p2tr_activation_height: u64,
+) -> Result<()> {
+    if forge.proof_hash != forge_proof_hash(pof_result, &forge.salt) {
+        return Err(anyhow!("Proof hash mismatch"));
+    }
+    canonical_forge_address(forge, height, network, p2tr_activation_height)?;
+    Ok(())
+}
+
+/// Whether a proof hash meets a forge difficulty: at least `difficulty`
+/// leading zero bytes
+pub fn meets_difficulty(proof_hash: &[u8; 32], difficulty: u32) -> bool {
+    proof_hash.iter().take_while(|&&b| b == 0).count() as u32 >= difficulty
+}
+
+/// Easiest permitted mainnet header target, in compact form
+pub const POW_LIMIT_BITS: u32 = 0x207fffff;
+
+/// Decode a compact header target
+pub fn header_target(bits: u32) -> Target {
+    Target::from_compact(CompactTarget::from_consensus(bits))
+}
+
+/// Expected work of a header with the given compact target
+pub fn header_work(bits: u32) -> Work {
+    header_target(bits).to_work()
+}
+
+/// Element a used proof contributes to the used-proofs set hash
+fn used_proof_element(proof_hash: &[u8; 32], height: u64) -> [u8; 40] {
+    let mut element = [0u8; 40];
+    element[..32].copy_from_slice(proof_hash);
+    element[32..].copy_from_slice(&height.to_le_bytes());
+    element
 }
 
 /// Block in the Excalibur blockchain
@@ -34,6 +223,29 @@ pub struct ForgeTransaction {
 pub struct Block {
     pub header: BlockHeader,
     pub forges: Vec<ForgeTransaction>,
+    /// Transfers of ledger outputs, applied after the forges' outputs are
+    /// created
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transfers: Vec<SignedTransfer>,
+    /// Signatures of a permissioned chain's authorities over the block
+    /// hash; empty on public chains
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authority_signatures: Vec<AuthoritySignature>,
+}
+
+impl Block {
+    /// Signal and fill in the aggregate commitment to the current forges
+    pub fn set_aggregate_commitment(&mut self) {
+        self.header.version |= VERSION_AGGREGATE_COMMITMENT;
+        self.header.aggregate_commitment = Some(AggregateCommitment::compute(&self.forges));
+    }
+
+    /// Signal and fill in the root of the state after applying the block
+    /// on top of `engine`'s tip
+    pub fn set_state_root(&mut self, engine: &ConsensusEngine) {
+        self.header.version |= VERSION_STATE_ROOT;
+        self.header.state_root = Some(engine.state_root_after(self));
+    }
 }
 
 /// Proof-of-Forge consensus engine
@@ -44,10 +256,36 @@ pub struct ConsensusEngine {
     min_block_time: u64,
     /// Maximum forges per block
     max_forges_per_block: usize,
+    /// Forges between difficulty increases (0 = never)
+    difficulty_adjustment_forges: u64,
     /// Total forges processed
     total_forges: Arc<RwLock<u64>>,
     /// Chain state
     chain_state: Arc<RwLock<ChainState>>,
+    /// Unspent output ledger
+    ledger: Arc<RwLock<Ledger>>,
+    /// Per-stage block validation timings
+    validation_metrics: Arc<ValidationMetrics>,
+    /// Leaf hashes of forges validated for the mempool
+    leaf_cache: Arc<LeafCache>,
+    /// Embedder-supplied rules, in registration order
+    rules: RuleSet,
+    /// Network forge addresses are derived for
+    network: Network,
+    /// First height whose header must commit to a state root
+    state_root_activation_height: u64,
+    /// First height whose block time must be after median-time-past
+    median_time_activation_height: u64,
+    /// First height whose forges must pay to P2TR addresses
+    p2tr_activation_height: u64,
+    /// First height whose forges may be tempered with Argon2id
+    argon2id_activation_height: u64,
+    /// Easiest permitted header target, in compact form
+    pow_limit_bits: u32,
+    /// Keys that must sign blocks on a permissioned chain
+    authorities: Option<AuthoritySet>,
+    /// Store state is written back to, for engines opened with `from_store`
+    store: Option<ChainStore>,
 }
 
 #[derive(Debug, Clone)]
@@ -58,6 +296,48 @@ struct ChainState {
     latest_hash: [u8; 32],
     /// Used prophecy hashes to prevent replay
     used_prophecies: HashMap<[u8; 32], u64>,
+    /// Set hash over `used_prophecies`, kept in step with it
+    used_proofs_hash: SetHash,
+    /// Sparse merkle tree over `used_prophecies`, for state roots
+    used_proofs_tree: SparseMerkleTree,
+    /// Forge of each prophecy, by `prophecy_registry_hash`
+    prophecy_owners: HashMap<[u8; 32], ProphecyOwner>,
+    /// Snapshot the state was loaded from, if any
+    snapshot: Option<SnapshotStatus>,
+    /// Cumulative header work of applied blocks
+    chainwork: Work,
+    /// Times of the last `MEDIAN_TIME_SPAN` applied blocks
+    recent_times: RecentBlockTimes,
+}
+
+/// State of a loaded assumeutxo snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotStatus {
+    /// Height the snapshot was taken at
+    pub height: u64,
+    /// Committed snapshot hash
+    pub snapshot_hash: [u8; 32],
+    /// Whether history up to `height` has been independently validated
+    pub validated: bool,
+}
+
+/// What applying a block changes, worked out before anything is written so
+/// the store can be updated in one batch ahead of the in-memory state
+#[derive(Debug, Clone)]
+pub struct BlockEffects {
+    /// Engine record once the block is applied
+    record: ConsensusRecord,
+    undo: BlockUndo,
+    used_proofs: Vec<([u8; 32], u64)>,
+    outputs: Vec<(OutPoint, LedgerOutput)>,
+    /// Outputs the block's transfers spend
+    spent: Vec<OutPoint>,
+    /// Prophecies the block forges first, with their owners
+    registered: Vec<([u8; 32], ProphecyOwner)>,
+    /// Output set statistics once the block is applied
+    pub ledger_info: LedgerSetInfo,
+    /// Used-proofs set hash once the block is applied
+    pub used_proofs_hash: [u8; 32],
 }
 
 impl ConsensusEngine {
@@ -66,186 +346,847 @@ impl ConsensusEngine {
         Self {
             difficulty: Arc::new(RwLock::new(initial_difficulty)),
             min_block_time,
-            max_forges_per_block: 100,
+            max_forges_per_block: MAX_FORGES_PER_BLOCK,
+            difficulty_adjustment_forges: DIFFICULTY_ADJUSTMENT_FORGES,
             total_forges: Arc::new(RwLock::new(0)),
             chain_state: Arc::new(RwLock::new(ChainState {
                 height: 0,
                 latest_hash: [0u8; 32],
                 used_prophecies: HashMap::new(),
+                used_proofs_hash: SetHash::new(),
+                used_proofs_tree: SparseMerkleTree::new(),
+                prophecy_owners: HashMap::new(),
+                snapshot: None,
+                chainwork: Work::from_be_bytes([0u8; 32]),
+                recent_times: RecentBlockTimes::default(),
             })),
+            ledger: Arc::new(RwLock::new(Ledger::new())),
+            validation_metrics: Arc::new(ValidationMetrics::default()),
+            leaf_cache: Arc::new(LeafCache::default()),
+            rules: RuleSet::default(),
+            network: Network::Bitcoin,
+            state_root_activation_height: u64::MAX,
+            median_time_activation_height: u64::MAX,
+            p2tr_activation_height: u64::MAX,
+            argon2id_activation_height: u64::MAX,
+            pow_limit_bits: POW_LIMIT_BITS,
+            authorities: None,
+            store: None,
         }
     }
 
-    /// Validate a forge transaction
-    pub fn validate_forge(&self, forge: &ForgeTransaction) -> Result<bool> {
-        // 1. Verify the prophecy is the canonical one
-        if forge.prophecy != CANONICAL_PROPHECY {
-            return Err(anyhow!("Invalid prophecy - must use canonical 13-word axiom"));
+    /// Open an engine on the state persisted in `store`, or a fresh one if
+    /// there is none, that writes its state back to `store` as it applies
+    /// blocks. `initial_difficulty` only applies to a fresh state.
+    pub fn from_store(store: &ChainStore, initial_difficulty: u32, min_block_time: u64) -> Result<Self> {
+        let mut engine = Self::new(initial_difficulty, min_block_time);
+        if let Some((record, snapshot)) = store.load_consensus_state()? {
+            let mut state = engine.chain_state.write().unwrap();
+            engine.install_snapshot(&mut state, &snapshot, Some(store))?;
+            state.snapshot = record.snapshot;
+            drop(state);
+            *engine.total_forges.write().unwrap() = record.total_forges;
+            tracing::info!(
+                "Loaded consensus state at height {} ({} used proofs)",
+                record.height,
+                snapshot.used_proofs.len()
+            );
         }
+        engine.store = Some(store.clone());
+        Ok(engine)
+    }
 
-        // 2. Verify the proof-of-forge derivation
-        let pof_result = proof_of_forge(&forge.prophecy, forge.timestamp)?;
-        
-        // 3. Check that derived key matches
-        if pof_result.derived_key != forge.derived_key {
-            return Err(anyhow!("Derived key mismatch"));
+    /// Derive forge addresses for `network` instead of mainnet
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Follow a network's address encoding, scheduled activations and
+    /// chain parameters. The initial difficulty is left alone: it only
+    /// applies to a fresh state and is given to `new` or `from_store`.
+    pub fn with_params(self, params: &NetworkParams) -> Self {
+        let mut engine = self.with_network(params.network);
+        engine.state_root_activation_height = params.state_root_activation_height;
+        engine.median_time_activation_height = params.median_time_activation_height;
+        engine.p2tr_activation_height = params.p2tr_activation_height;
+        engine.min_block_time = params.chain.min_block_time;
+        engine.max_forges_per_block = params.chain.max_forges_per_block;
+        engine.difficulty_adjustment_forges = params.chain.difficulty_adjustment_forges;
+        engine.argon2id_activation_height = params.chain.argon2id_activation_height;
+        engine.pow_limit_bits = params.chain.pow_limit_bits;
+        engine.authorities = params.authorities.clone();
+        engine
+    }
+
+    /// Keys that must sign blocks, on a permissioned chain
+    pub fn authorities(&self) -> Option<&AuthoritySet> {
+        self.authorities.as_ref()
+    }
+
+    /// Recover the times of the stored blocks up to `height` that
+    /// median-time-past is taken over. Blocks below a snapshot aren't
+    /// stored, so the median can cover fewer blocks until new ones arrive.
+    fn load_recent_times(state: &mut ChainState, store: &ChainStore, height: u64) -> Result<()> {
+        let first = (height + 1).saturating_sub(MEDIAN_TIME_SPAN as u64);
+        for height in first..=height {
+            if let Some(block) = store.load_block(height)? {
+                state.recent_times.push(header_time_millis(&block.header));
+            }
         }
+        Ok(())
+    }
+
+    /// Median time of the last `MEDIAN_TIME_SPAN` applied blocks in
+    /// milliseconds, if any are known
+    pub fn median_time_past(&self) -> Option<u64> {
+        self.chain_state.read().unwrap().recent_times.median()
+    }
+
+    /// Whether a block at `height` must be timed after median-time-past
+    pub fn requires_median_time(&self, height: u64) -> bool {
+        height >= self.median_time_activation_height
+    }
+
+    /// Register an additional consensus rule. Rules run in registration
+    /// order and must be registered before the engine is shared.
+    pub fn register_rule(&mut self, rule: Box<dyn ConsensusRule>) {
+        tracing::info!("Registered consensus rule {}", rule.name());
+        self.rules.push(rule);
+    }
+
+    /// Names of registered consensus rules, in execution order
+    pub fn rule_names(&self) -> Vec<String> {
+        self.rules.names()
+    }
+
+    /// Names of rules activating at a height in `(after, up_to]`
+    pub fn activations_between(&self, after: u64, up_to: u64) -> Vec<String> {
+        self.rules.activations_between(after, up_to)
+    }
+
+    /// Height-dependent checks of a forge included at `height`: those of
+    /// the registered rules. A forge's proof and replay status don't
+    /// depend on the height, so this is all that changes at activations.
+    pub fn check_forge_context(&self, forge: &ForgeTransaction, height: u64) -> Result<()> {
+        self.rules.check_forge(forge, height)
+    }
+
+    /// Validate a forge transaction
+    pub fn validate_forge(&self, forge: &ForgeTransaction) -> Result<bool> {
+        self.validate_forge_cancellable(forge, &CancelToken::new())
+    }
 
-        // 4. Check that taproot address matches
-        if pof_result.taproot_address != forge.taproot_address {
-            return Err(anyhow!("Taproot address mismatch"));
+    /// `validate_forge`, giving up with `ForgeCancelled` if `cancel` is
+    /// cancelled while the proof is re-derived
+    pub fn validate_forge_cancellable(&self, forge: &ForgeTransaction, cancel: &CancelToken) -> Result<bool> {
+        forge.verify_signature()?;
+        self.validate_signed_forge(forge, self.get_height() + 1, cancel)
+    }
+
+    /// Whether forges included at `height` may be tempered with Argon2id
+    pub fn allows_argon2id(&self, height: u64) -> bool {
+        height >= self.argon2id_activation_height
+    }
+
+    /// Check a forge included at `height` is tempered with an algorithm
+    /// and parameters validators accept, before paying to re-derive it
+    pub fn check_forge_tempering(&self, forge: &ForgeTransaction, height: u64) -> Result<()> {
+        if !forge.tempering.is_pbkdf2() && !self.allows_argon2id(height) {
+            return Err(anyhow!(
+                "{} tempering is not active at height {}",
+                forge.tempering.name(),
+                height
+            ));
         }
+        forge.tempering.check()
+    }
 
-        // 5. Verify proof hash meets difficulty requirement
+    /// Every `validate_forge` check except the signature, for a forge
+    /// included at `height`. The cheap checks run first, so a forge that
+    /// fails them never costs a proof-of-forge derivation.
+    fn validate_signed_forge(&self, forge: &ForgeTransaction, height: u64, cancel: &CancelToken) -> Result<bool> {
+        self.check_forge_tempering(forge, height)?;
+
+        // Verify proof hash meets difficulty requirement
         let difficulty = *self.difficulty.read().unwrap();
-        if !self.check_difficulty(&pof_result.proof_hash, difficulty) {
+        if !self.check_difficulty(&forge.proof_hash, difficulty) {
             return Err(anyhow!("Proof hash does not meet difficulty requirement"));
         }
 
-        // 6. Check for replay attacks - ensure this proof hasn't been used
+        // Check for replay attacks - ensure this proof hasn't been used
         let state = self.chain_state.read().unwrap();
-        if state.used_prophecies.contains_key(&pof_result.proof_hash) {
+        if state.used_prophecies.contains_key(&forge.proof_hash) {
             return Err(anyhow!("Proof already used (replay attack)"));
         }
+        drop(state);
+
+        // Each prophecy can only be forged once
+        self.check_prophecy_unforged(forge)?;
+
+        self.derive_forge_proof(forge, height, cancel)?;
+
+        // Registered rules, for the block the forge goes in
+        self.check_forge_context(forge, height)?;
 
+        // Valid forges are headed for a block; keep their merkle leaf
+        self.leaf_cache.insert(forge);
         Ok(true)
     }
 
+    /// Reject a forge of a prophecy an applied block already forged
+    fn check_prophecy_unforged(&self, forge: &ForgeTransaction) -> Result<()> {
+        let prophecy_hash = prophecy_registry_hash(&forge.prophecy);
+        match self.chain_state.read().unwrap().prophecy_owners.get(&prophecy_hash) {
+            Some(owner) => Err(anyhow!(
+                "Prophecy already forged at height {} by {}",
+                owner.height,
+                owner.owner
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Number of proof hashes used by applied blocks
+    pub fn used_proof_count(&self) -> usize {
+        self.chain_state.read().unwrap().used_prophecies.len()
+    }
+
+    /// Number of prophecies with a registered owner
+    pub fn prophecy_owner_count(&self) -> usize {
+        self.chain_state.read().unwrap().prophecy_owners.len()
+    }
+
+    /// Owner of a prophecy, by `prophecy_registry_hash`
+    pub fn prophecy_owner(&self, prophecy_hash: &[u8; 32]) -> Option<ProphecyOwner> {
+        self.chain_state.read().unwrap().prophecy_owners.get(prophecy_hash).cloned()
+    }
+
+    /// Re-derive the proof-of-forge for a forge included at `height` and
+    /// check it matches the claimed proof hash and address (no chain-state
+    /// checks)
+    pub fn verify_forge_proof(&self, forge: &ForgeTransaction, height: u64) -> Result<ProofOfForgeResult> {
+        self.derive_forge_proof(forge, height, &CancelToken::new())
+    }
+
+    /// `verify_forge_proof`, stopped by `cancel`
+    fn derive_forge_proof(
+        &self,
+        forge: &ForgeTransaction,
+        height: u64,
+        cancel: &CancelToken,
+    ) -> Result<ProofOfForgeResult> {
+        // 1. Verify the prophecy is a mnemonic followed by an axiom word
+        let words: Vec<String> = forge.prophecy.split_whitespace().map(str::to_string).collect();
+        check_prophecy_words(&words).map_err(|e| anyhow!("Invalid prophecy: {}", e))?;
+
+        // 2. Verify the proof-of-forge derivation, tempered as the forge says
+        // with its salt bound to the forger's key
+        if !forge.is_salted() {
+            return Err(anyhow!("Forge has no salt"));
+        }
+        let salt = forge_tempering_salt(&forge.derived_key, &forge.salt);
+        let pof_result =
+            proof_of_forge_with_progress(&words, Some(&salt), self.network, forge.tempering, |_, _| {}, cancel)?;
+        check_forge_matches_proof(forge, &pof_result, height, self.network, self.p2tr_activation_height)?;
+        Ok(pof_result)
+    }
+
     /// Validate a block
     pub fn validate_block(&self, block: &Block, parent_hash: &[u8; 32]) -> Result<bool> {
-        // 1. Check parent hash matches
-        if &block.header.prev_block_hash != parent_hash {
-            return Err(anyhow!("Parent hash mismatch"));
+        let mut timer = BlockValidationTimer::new(&self.validation_metrics, block.header.height);
+
+        timer.stage(ValidationStage::ContextualHeader, || self.check_block_header(block, parent_hash))?;
+
+        timer.stage(ValidationStage::Merkle, || self.check_block_merkle(block))?;
+
+        timer.stage(ValidationStage::Signatures, || {
+            block.forges.iter().enumerate().try_for_each(|(index, forge)| {
+                forge
+                    .verify_signature()
+                    .map_err(|e| anyhow!("Forge {} has a bad signature: {}", index, e))
+            })
+        })?;
+
+        timer.stage(ValidationStage::ProofOfForge, || {
+            let mut prophecies = HashSet::new();
+            if !block.forges.iter().all(|forge| prophecies.insert(prophecy_registry_hash(&forge.prophecy))) {
+                return Err(anyhow!("Prophecy forged twice in block"));
+            }
+            block.forges.iter().try_for_each(|forge| {
+                self.validate_signed_forge(forge, block.header.height, &CancelToken::new()).map(|_| ())
+            })
+        })?;
+
+        timer.stage(ValidationStage::Transfers, || self.check_block_transfers(block))?;
+
+        timer.stage(ValidationStage::Policy, || {
+            for forge in &block.forges {
+                if forge.not_before_height > block.header.height {
+                    return Err(anyhow!(
+                        "Forge locked until height {} included at height {}",
+                        forge.not_before_height,
+                        block.header.height
+                    ));
+                }
+            }
+
+            let context = {
+                let state = self.chain_state.read().unwrap();
+                RuleContext {
+                    tip_height: state.height,
+                    tip_hash: state.latest_hash,
+                }
+            };
+            self.rules.check_block_context(block, &context)
+        })?;
+
+        timer.stage(ValidationStage::StateRoot, || {
+            check_state_root(&block.header, self.state_root_activation_height, || {
+                self.state_root_after(block)
+            })
+        })?;
+
+        Ok(true)
+    }
+
+    /// Header and structural checks only: everything `validate_block` does
+    /// before verifying proofs of forge. Passing blocks are safe to relay
+    /// optimistically, as they cost real header work and commit to their
+    /// forges, but may still fail full validation.
+    pub fn prevalidate_block(&self, block: &Block, parent_hash: &[u8; 32]) -> Result<()> {
+        self.check_block_header(block, parent_hash)?;
+        self.check_block_merkle(block)
+    }
+
+    /// Merkle root and aggregate commitment checks
+    fn check_block_merkle(&self, block: &Block) -> Result<()> {
+        if self.compute_block_merkle_root(block) != block.header.merkle_root {
+            return Err(anyhow!("Merkle root mismatch"));
+        }
+        check_aggregate_commitment(&block.header, &block.forges)
+    }
+
+    /// Contextual header checks plus the block's forge count limits and
+    /// authority signatures
+    fn check_block_header(&self, block: &Block, parent_hash: &[u8; 32]) -> Result<()> {
+        self.validate_header(&block.header, parent_hash, self.next_height())?;
+
+        // Blocks extend the tip, so the applied blocks are its ancestors
+        if self.requires_median_time(block.header.height) {
+            self.chain_state.read().unwrap().recent_times.check(&block.header)?;
         }
 
-        // 2. Check block isn't empty
+        // Check block isn't empty
         if block.forges.is_empty() {
             return Err(anyhow!("Block must contain at least one forge"));
         }
 
-        // 3. Check max forges limit
+        // Check max forges limit
         if block.forges.len() > self.max_forges_per_block {
             return Err(anyhow!(
                 "Too many forges in block (max: {})",
                 self.max_forges_per_block
             ));
         }
+        if block.transfers.len() > MAX_TRANSFERS_PER_BLOCK {
+            return Err(anyhow!("Too many transfers in block (max: {})", MAX_TRANSFERS_PER_BLOCK));
+        }
 
-        // 4. Validate each forge transaction
-        for forge in &block.forges {
-            self.validate_forge(forge)?;
+        match &self.authorities {
+            Some(authorities) => authorities.check_block(block, &self.compute_block_hash(&block.header)),
+            None => authority::check_unsigned(block),
         }
+    }
 
-        // 5. Verify merkle root
-        let computed_merkle = self.compute_merkle_root(&block.forges);
-        if computed_merkle != block.header.merkle_root {
-            return Err(anyhow!("Merkle root mismatch"));
+    /// Check a block's transfers against the ledger at the tip, and each
+    /// transfer's witnesses against the outputs it spends
+    fn check_block_transfers(&self, block: &Block) -> Result<()> {
+        if block.transfers.is_empty() {
+            return Ok(());
+        }
+        let changes = self.ledger.read().unwrap().block_changes(block)?;
+        for (index, (transfer, inputs)) in block.transfers.iter().zip(&changes.transfer_inputs).enumerate() {
+            transfer
+                .verify(inputs, block.header.height, self.network)
+                .with_context(|| format!("Transfer {} is invalid", index))?;
+        }
+        Ok(())
+    }
+
+    /// Check that a transfer can go in the next block: its inputs are
+    /// unspent, and it is unlocked and authorized at the next height. Used
+    /// for mempool admission.
+    pub fn check_transfer(&self, transfer: &SignedTransfer) -> Result<()> {
+        let height = self.next_height();
+        let inputs = self.ledger.read().unwrap().spent_outputs(&transfer.transfer, height)?;
+        transfer.verify(&inputs, height, self.network)
+    }
+
+    /// Contextual header checks that need no block body: parent link,
+    /// height, header work, timestamp and header rules. `height` is the
+    /// parent's height plus one (0 for a genesis header). Used to validate
+    /// headers ahead of their bodies during sync.
+    pub fn validate_header(&self, header: &BlockHeader, parent_hash: &[u8; 32], height: u64) -> Result<()> {
+        // 1. Check parent hash and height match
+        if &header.prev_block_hash != parent_hash {
+            return Err(anyhow!("Parent hash mismatch"));
         }
+        // The target is chosen by height, so a header can't pick its own
+        if header.height != height {
+            return Err(anyhow!("Header claims height {}, expected {}", header.height, height));
+        }
+
+        // 2. Header proof-of-work, independent of the forges
+        self.check_header_pow(header)?;
 
-        // 6. Check timestamp is reasonable (not too far in past or future)
+        // 3. Check timestamp is reasonable (not too far in past or future)
         let now = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs();
         
-        if block.header.timestamp > now + 7200 {
+        if header.timestamp > now + 7200 {
             return Err(anyhow!("Block timestamp too far in future"));
         }
+        timestamp::check_timestamp_millis(header)?;
 
-        Ok(true)
+        self.rules.check_header(header)
+    }
+
+    /// Compact target of a new header at `height`. Targets aren't
+    /// retargeted, so this is the chain's PoW limit at every height.
+    pub fn header_bits(&self, _height: u64) -> u32 {
+        self.pow_limit_bits
+    }
+
+    /// Check that a header carries the target expected at its height and
+    /// that its hash meets it
+    pub fn check_header_pow(&self, header: &BlockHeader) -> Result<()> {
+        let expected = self.header_bits(header.height);
+        if header.bits != expected {
+            return Err(anyhow!(
+                "Header at height {} has target {:#010x}, expected {:#010x}",
+                header.height,
+                header.bits,
+                expected
+            ));
+        }
+        let target = header_target(header.bits);
+        let hash = self.compute_block_hash(header);
+        if Target::from_be_bytes(hash) > target {
+            return Err(anyhow!("Header hash does not meet its target"));
+        }
+        Ok(())
+    }
+
+    /// Search nonces until the header meets its target. Returns `false`
+    /// if none of `max_attempts` nonces did.
+    pub fn grind_header(&self, header: &mut BlockHeader, max_attempts: u64) -> bool {
+        for _ in 0..max_attempts {
+            if self.check_header_pow(header).is_ok() {
+                return true;
+            }
+            header.nonce = header.nonce.wrapping_add(1);
+        }
+        false
     }
 
-    /// Apply a validated block to the chain state
+    /// Apply a validated block to the chain state. An engine opened from a
+    /// store writes the new state with the block's undo record.
     pub fn apply_block(&self, block: &Block) -> Result<()> {
-        let mut state = self.chain_state.write().unwrap();
-        
-        // Update height
-        state.height = block.header.height;
-        
-        // Compute and store block hash
+        let effects = self.block_effects(block)?;
+        self.commit_block(block, effects, WriteBatch::default())
+    }
+
+    /// Work out what applying `block` on the tip changes, without changing
+    /// anything. Fails if the block's outputs can't be added to the ledger
+    /// or its transfers can't be applied.
+    pub fn block_effects(&self, block: &Block) -> Result<BlockEffects> {
+        let previous = self.consensus_record();
+        let state = self.chain_state.read().unwrap();
+        let height = block.header.height;
+        let (changes, ledger_info) = {
+            let ledger = self.ledger.read().unwrap();
+            let changes = ledger.block_changes(block)?;
+            let ledger_info = ledger.info_after(height, &changes)?;
+            (changes, ledger_info)
+        };
         let block_hash = self.compute_block_hash(&block.header);
-        state.latest_hash = block_hash;
-        
-        // Mark all forge proofs as used
+
+        // Mark all forge proofs as used and record prophecy owners
+        let mut used_proofs_hash = state.used_proofs_hash.clone();
+        let mut registered: Vec<([u8; 32], ProphecyOwner)> = Vec::new();
         for forge in &block.forges {
-            state.used_prophecies.insert(forge.proof_hash, block.header.height);
+            used_proofs_hash.insert(&used_proof_element(&forge.proof_hash, height));
+            let prophecy_hash = prophecy_registry_hash(&forge.prophecy);
+            let owned = state.prophecy_owners.contains_key(&prophecy_hash)
+                || registered.iter().any(|(registered, _)| *registered == prophecy_hash);
+            if !owned {
+                let owner = ProphecyOwner {
+                    owner: forge.taproot_address.clone(),
+                    height,
+                    proof_hash: forge.proof_hash,
+                };
+                registered.push((prophecy_hash, owner));
+            }
         }
-        
-        // Update total forges
-        let mut total = self.total_forges.write().unwrap();
-        *total += block.forges.len() as u64;
-        
-        // Adjust difficulty if needed
-        self.adjust_difficulty(block.header.height);
-        
+        let used_proofs: Vec<_> = block.forges.iter().map(|forge| (forge.proof_hash, height)).collect();
+        let outputs = changes.created;
+
+        let total_forges = previous.total_forges + block.forges.len() as u64;
+        let record = ConsensusRecord {
+            height,
+            tip_hash: block_hash,
+            difficulty: self.difficulty_after(previous.difficulty, total_forges),
+            total_forges,
+            chainwork: (state.chainwork + header_work(block.header.bits)).to_be_bytes(),
+            snapshot: state.snapshot,
+        };
+        let undo = BlockUndo {
+            height,
+            block_hash,
+            previous,
+            created_outputs: outputs.iter().map(|(outpoint, _)| *outpoint).collect(),
+            used_proofs: used_proofs.iter().map(|(proof_hash, _)| *proof_hash).collect(),
+            registered_prophecies: registered.iter().map(|(prophecy_hash, _)| *prophecy_hash).collect(),
+            spent_outputs: changes.spent.clone(),
+        };
+        Ok(BlockEffects {
+            record,
+            undo,
+            used_proofs,
+            outputs,
+            spent: changes.spent.into_iter().map(|(outpoint, _)| outpoint).collect(),
+            registered,
+            ledger_info,
+            used_proofs_hash: used_proofs_hash.digest(),
+        })
+    }
+
+    /// Apply a block whose effects were worked out by `block_effects`.
+    ///
+    /// The engine record, used proofs, created and spent outputs, prophecy
+    /// owners and undo record are written to the store in one batch with `batch`, and only
+    /// then is the in-memory state changed, so a failed write leaves both
+    /// at the parent. An engine without a store can only be given an empty
+    /// batch.
+    pub fn commit_block(&self, block: &Block, effects: BlockEffects, batch: WriteBatch) -> Result<()> {
+        let mut state = self.chain_state.write().unwrap();
+        let previous = &effects.undo.previous;
+        if state.latest_hash != previous.tip_hash || state.chainwork.to_be_bytes() != previous.chainwork {
+            return Err(anyhow!(
+                "Chain state changed while block {} was being applied",
+                hex::encode(effects.record.tip_hash)
+            ));
+        }
+        match &self.store {
+            Some(store) => store.put_consensus_state(
+                batch,
+                &effects.record,
+                &effects.used_proofs,
+                &effects.outputs,
+                &effects.spent,
+                &effects.registered,
+                Some(&effects.undo),
+            )?,
+            None if !batch.is_empty() => {
+                return Err(anyhow!("Writing a block needs an engine opened from a chain store"))
+            }
+            None => {}
+        }
+
+        self.ledger.write().unwrap().apply_block(block)?;
+        state.height = effects.record.height;
+        state.latest_hash = effects.record.tip_hash;
+        state.chainwork = Work::from_be_bytes(effects.record.chainwork);
+        state.recent_times.push(header_time_millis(&block.header));
+        for (proof_hash, height) in &effects.used_proofs {
+            state.used_prophecies.insert(*proof_hash, *height);
+            state.used_proofs_hash.insert(&used_proof_element(proof_hash, *height));
+            state.used_proofs_tree.insert(*proof_hash, used_proof_tree_value(*height));
+        }
+        state.prophecy_owners.extend(effects.registered);
+        self.leaf_cache.remove_block_forges(&block.forges);
+
+        *self.total_forges.write().unwrap() = effects.record.total_forges;
+        let mut difficulty = self.difficulty.write().unwrap();
+        if *difficulty != effects.record.difficulty {
+            tracing::info!(
+                "Difficulty adjusted to {} at height {} ({} forges)",
+                effects.record.difficulty,
+                effects.record.height,
+                effects.record.total_forges
+            );
+        }
+        *difficulty = effects.record.difficulty;
         Ok(())
     }
 
-    /// Check if a proof hash meets the difficulty requirement
-    fn check_difficulty(&self, hash: &[u8; 32], difficulty: u32) -> bool {
-        let leading_zeros = hash.iter()
-            .take_while(|&&b| b == 0)
-            .count() as u32;
-        leading_zeros >= difficulty
+    /// Disconnect the tip block, restoring the state before it from the
+    /// undo record written when it was applied. Needs an engine opened
+    /// from a store; blocks below a loaded snapshot can't be disconnected.
+    pub fn disconnect_block(&self, block: &Block) -> Result<()> {
+        self.disconnect_block_with(block, WriteBatch::default())
     }
 
-    /// Compute merkle root from forge transactions
-    fn compute_merkle_root(&self, forges: &[ForgeTransaction]) -> [u8; 32] {
-        use sha2::{Sha256, Digest};
-        
-        if forges.is_empty() {
-            return [0u8; 32];
+    /// Disconnect the tip block, reverting its stored state in one batch
+    /// with `batch` before the in-memory state changes
+    pub fn disconnect_block_with(&self, block: &Block, batch: WriteBatch) -> Result<()> {
+        let store = self
+            .store
+            .as_ref()
+            .ok_or_else(|| anyhow!("Disconnecting blocks needs the undo records of a chain store"))?;
+        let height = block.header.height;
+        let block_hash = self.compute_block_hash(&block.header);
+        if self.get_tip_hash() != block_hash {
+            return Err(anyhow!("Block {} is not the tip", hex::encode(block_hash)));
         }
-        
-        let mut hashes: Vec<[u8; 32]> = forges
+        let undo = store
+            .load_undo(height)?
+            .filter(|undo| undo.block_hash == block_hash)
+            .ok_or_else(|| anyhow!("No undo record for block {} at height {}", hex::encode(block_hash), height))?;
+
+        let mut state = self.chain_state.write().unwrap();
+        store.revert_consensus_state(batch, &undo)?;
+        let mut ledger = self.ledger.write().unwrap();
+        for outpoint in &undo.created_outputs {
+            ledger.spend_output(outpoint)?;
+        }
+        for (outpoint, output) in &undo.spent_outputs {
+            ledger.add_output(*outpoint, output.clone())?;
+        }
+        ledger.set_height(undo.previous.height);
+        drop(ledger);
+        for proof_hash in &undo.used_proofs {
+            state.used_prophecies.remove(proof_hash);
+            state.used_proofs_hash.remove(&used_proof_element(proof_hash, height));
+            state.used_proofs_tree.remove(proof_hash);
+        }
+        for prophecy_hash in &undo.registered_prophecies {
+            state.prophecy_owners.remove(prophecy_hash);
+        }
+        state.height = undo.previous.height;
+        state.latest_hash = undo.previous.tip_hash;
+        state.chainwork = Work::from_be_bytes(undo.previous.chainwork);
+        state.snapshot = undo.previous.snapshot;
+        state.recent_times.clear();
+        if height > 0 {
+            Self::load_recent_times(&mut state, store, height - 1)?;
+        }
+        *self.difficulty.write().unwrap() = undo.previous.difficulty;
+        *self.total_forges.write().unwrap() = undo.previous.total_forges;
+        drop(state);
+
+        tracing::info!("Disconnected block {} at height {}", hex::encode(block_hash), height);
+        Ok(())
+    }
+
+    /// What `from_store` needs besides the used proofs and outputs
+    fn consensus_record(&self) -> ConsensusRecord {
+        let state = self.chain_state.read().unwrap();
+        ConsensusRecord {
+            height: state.height,
+            tip_hash: state.latest_hash,
+            difficulty: *self.difficulty.read().unwrap(),
+            total_forges: *self.total_forges.read().unwrap(),
+            chainwork: state.chainwork.to_be_bytes(),
+            snapshot: state.snapshot,
+        }
+    }
+
+    /// Capture the ledger and replay-protection state as a snapshot
+    pub fn create_snapshot(&self) -> LedgerSnapshot {
+        let state = self.chain_state.read().unwrap();
+        let ledger = self.ledger.read().unwrap();
+
+        let mut outputs: Vec<_> = ledger
+            .outputs()
+            .map(|(outpoint, output)| (*outpoint, output.clone()))
+            .collect();
+        outputs.sort_by_key(|(outpoint, _)| *outpoint);
+
+        let mut used_proofs: Vec<_> = state
+            .used_prophecies
             .iter()
-            .map(|f| {
-                let serialized = bincode::serialize(f).unwrap();
-                let mut hasher = Sha256::new();
-                hasher.update(&serialized);
-                hasher.finalize().into()
-            })
+            .map(|(hash, height)| (*hash, *height))
             .collect();
-        
-        while hashes.len() > 1 {
-            let mut next_level = Vec::new();
-            for chunk in hashes.chunks(2) {
-                let mut hasher = Sha256::new();
-                hasher.update(&chunk[0]);
-                if chunk.len() > 1 {
-                    hasher.update(&chunk[1]);
-                } else {
-                    hasher.update(&chunk[0]);
-                }
-                next_level.push(hasher.finalize().into());
-            }
-            hashes = next_level;
+        used_proofs.sort();
+
+        let mut prophecy_owners: Vec<_> = state
+            .prophecy_owners
+            .iter()
+            .map(|(hash, owner)| (*hash, owner.clone()))
+            .collect();
+        prophecy_owners.sort_by_key(|(hash, _)| *hash);
+
+        LedgerSnapshot {
+            height: state.height,
+            block_hash: state.latest_hash,
+            difficulty: *self.difficulty.read().unwrap(),
+            chainwork: state.chainwork.to_be_bytes(),
+            outputs,
+            used_proofs,
+            prophecy_owners,
         }
-        
-        hashes[0]
+    }
+
+    /// Load a trusted ledger snapshot so new blocks can be validated from its
+    /// height immediately.
+    ///
+    /// The snapshot hash must match a commitment in the network parameters.
+    /// History below the snapshot should be checked afterwards with
+    /// `verify_snapshot_history`.
+    pub fn load_snapshot(&self, snapshot: &LedgerSnapshot, params: &NetworkParams) -> Result<()> {
+        let commitment = params.assume_utxo_for(snapshot.height).ok_or_else(|| {
+            anyhow!("No snapshot commitment for height {} on {}", snapshot.height, params.name)
+        })?;
+        let snapshot_hash = snapshot.snapshot_hash()?;
+        if snapshot_hash != commitment.snapshot_hash {
+            return Err(anyhow!(
+                "Snapshot hash {} does not match commitment {}",
+                hex::encode(snapshot_hash),
+                hex::encode(commitment.snapshot_hash)
+            ));
+        }
+
+        let mut state = self.chain_state.write().unwrap();
+        if state.height != 0 || !state.used_prophecies.is_empty() {
+            return Err(anyhow!("Snapshots can only be loaded into a fresh chain state"));
+        }
+
+        self.install_snapshot(&mut state, snapshot, self.store.as_ref())?;
+        state.snapshot = Some(SnapshotStatus {
+            height: snapshot.height,
+            snapshot_hash,
+            validated: false,
+        });
+        drop(state);
+        *self.total_forges.write().unwrap() = snapshot.used_proofs.len() as u64;
+        if let Some(store) = &self.store {
+            store.put_consensus_state(
+                WriteBatch::default(),
+                &self.consensus_record(),
+                &snapshot.used_proofs,
+                &snapshot.outputs,
+                &[],
+                &snapshot.prophecy_owners,
+                None,
+            )?;
+        }
+
+        tracing::info!(
+            "Loaded ledger snapshot at height {} ({} outputs)",
+            snapshot.height,
+            snapshot.outputs.len()
+        );
+        Ok(())
+    }
+
+    /// Replace the ledger, replay-protection, difficulty and chainwork
+    /// state with a snapshot's, taking the recent block times from `store`
+    fn install_snapshot(
+        &self,
+        state: &mut ChainState,
+        snapshot: &LedgerSnapshot,
+        store: Option<&ChainStore>,
+    ) -> Result<()> {
+        let ledger = snapshot.to_ledger()?;
+        state.height = snapshot.height;
+        state.latest_hash = snapshot.block_hash;
+        state.chainwork = Work::from_be_bytes(snapshot.chainwork);
+        state.recent_times.clear();
+        if let Some(store) = store {
+            Self::load_recent_times(state, store, snapshot.height)?;
+        }
+        *self.difficulty.write().unwrap() = snapshot.difficulty;
+        state.used_prophecies = snapshot.used_proofs.iter().copied().collect();
+        for (proof_hash, height) in &snapshot.used_proofs {
+            state.used_proofs_hash.insert(&used_proof_element(proof_hash, *height));
+            state.used_proofs_tree.insert(*proof_hash, used_proof_tree_value(*height));
+        }
+        state.prophecy_owners = snapshot.prophecy_owners.iter().cloned().collect();
+        *self.ledger.write().unwrap() = ledger;
+        Ok(())
+    }
+
+    /// Status of the loaded snapshot, if the state came from one
+    pub fn snapshot_status(&self) -> Option<SnapshotStatus> {
+        self.chain_state.read().unwrap().snapshot
+    }
+
+    /// Validate and apply stored blocks `0..=to_height` on top of this engine's state
+    pub fn replay_from_store(&self, store: &ChainStore, to_height: u64) -> Result<()> {
+        for height in 0..=to_height {
+            let block = store
+                .load_block(height)?
+                .ok_or_else(|| anyhow!("Missing block at height {}", height))?;
+            let parent_hash = self.chain_state.read().unwrap().latest_hash;
+            self.validate_block(&block, &parent_hash)
+                .map_err(|e| anyhow!("Block {} failed validation: {}", height, e))?;
+            self.apply_block(&block)?;
+        }
+        Ok(())
+    }
+
+    /// Background-verify the history below a loaded snapshot.
+    ///
+    /// Replays stored blocks up to the snapshot height through `fresh` (an
+    /// engine with genesis state) and checks the result matches the snapshot.
+    pub fn verify_snapshot_history(&self, store: &ChainStore, fresh: &ConsensusEngine) -> Result<()> {
+        let status = self
+            .snapshot_status()
+            .ok_or_else(|| anyhow!("Chain state was not loaded from a snapshot"))?;
+
+        fresh.replay_from_store(store, status.height)?;
+        let replayed = fresh.create_snapshot().snapshot_hash()?;
+        if replayed != status.snapshot_hash {
+            return Err(anyhow!(
+                "Replayed history does not match snapshot at height {}",
+                status.height
+            ));
+        }
+
+        if let Some(snapshot) = self.chain_state.write().unwrap().snapshot.as_mut() {
+            snapshot.validated = true;
+        }
+        if let Some(store) = &self.store {
+            store.put_consensus_state(WriteBatch::default(), &self.consensus_record(), &[], &[], &[], &[], None)?;
+        }
+        tracing::info!("Snapshot history validated up to height {}", status.height);
+        Ok(())
+    }
+
+    /// Check if a proof hash meets the difficulty requirement
+    fn check_difficulty(&self, hash: &[u8; 32], difficulty: u32) -> bool {
+        meets_difficulty(hash, difficulty)
+    }
+
+    /// Compute merkle root from forge transactions, reusing leaf hashes of
+    /// forges this engine validated
+    pub fn compute_merkle_root(&self, forges: &[ForgeTransaction]) -> [u8; 32] {
+        merkle_root(self.leaf_cache.leaves(forges))
+    }
+
+    /// Merkle root a block's header must carry, committing to its forges
+    /// and transfers
+    pub fn compute_block_merkle_root(&self, block: &Block) -> [u8; 32] {
+        block_merkle_root(self.compute_merkle_root(&block.forges), &block.transfers)
     }
 
     /// Compute hash of a block header
-    fn compute_block_hash(&self, header: &BlockHeader) -> [u8; 32] {
+    pub fn compute_block_hash(&self, header: &BlockHeader) -> [u8; 32] {
         use sha2::{Sha256, Digest};
-        let serialized = bincode::serialize(header).unwrap();
-        let mut hasher = Sha256::new();
-        hasher.update(&serialized);
-        hasher.finalize().into()
+        Sha256::digest(header_hash_preimage(header)).into()
     }
 
-    /// Adjust difficulty based on block height (every 10,000 forges)
-    fn adjust_difficulty(&self, height: u64) {
-        let total_forges = *self.total_forges.read().unwrap();
-        if total_forges % 10_000 == 0 && total_forges > 0 {
-            let mut difficulty = self.difficulty.write().unwrap();
-            *difficulty += 1;
-            tracing::info!(
-                "Difficulty adjusted to {} at height {} ({} forges)",
-                *difficulty,
-                height,
-                total_forges
-            );
+    /// Raise the difficulty every `difficulty_adjustment_forges` forges
+    fn difficulty_after(&self, difficulty: u32, total_forges: u64) -> u32 {
+        let interval = self.difficulty_adjustment_forges;
+        if interval > 0 && total_forges.is_multiple_of(interval) && total_forges > 0 {
+            difficulty + 1
+        } else {
+            difficulty
         }
     }
 
@@ -254,20 +1195,125 @@ impl ConsensusEngine {
         *self.difficulty.read().unwrap()
     }
 
+    /// Most forges a block may carry
+    pub fn max_forges_per_block(&self) -> usize {
+        self.max_forges_per_block
+    }
+
+    /// Whether a block at `height` must commit to the state root
+    pub fn requires_state_root(&self, height: u64) -> bool {
+        height >= self.state_root_activation_height
+    }
+
     /// Get current chain height
     pub fn get_height(&self) -> u64 {
         self.chain_state.read().unwrap().height
     }
 
+    /// Hash of the last applied block (or the snapshot's block)
+    pub fn get_tip_hash(&self) -> [u8; 32] {
+        self.chain_state.read().unwrap().latest_hash
+    }
+
+    /// Height of the next block on the tip
+    pub fn next_height(&self) -> u64 {
+        let state = self.chain_state.read().unwrap();
+        // An all-zero tip hash means no block has been applied yet
+        if state.latest_hash == [0u8; 32] { 0 } else { state.height + 1 }
+    }
+
+    /// Cumulative header work of applied blocks (counted from the
+    /// snapshot base when the state was loaded from a snapshot)
+    pub fn get_chainwork(&self) -> Work {
+        self.chain_state.read().unwrap().chainwork
+    }
+
     /// Get total forges processed
     pub fn get_total_forges(&self) -> u64 {
         *self.total_forges.read().unwrap()
     }
+
+    /// Run `f` with read access to the unspent output ledger
+    pub fn with_ledger<R>(&self, f: impl FnOnce(&Ledger) -> R) -> R {
+        f(&self.ledger.read().unwrap())
+    }
+
+    /// Get output count, total value, and set hash of the ledger
+    pub fn get_ledger_info(&self) -> LedgerSetInfo {
+        self.ledger.read().unwrap().info()
+    }
+
+    /// Audit the ledger's supply against the emission for every forge in
+    /// the chain, counting `treasury_addresses` toward the treasury balance
+    pub fn get_supply_info(&self, treasury_addresses: &[String]) -> SupplyInfo {
+        // Block application holds the chain state lock across the ledger
+        // and forge count updates, so both are read at one height
+        let _state = self.chain_state.read().unwrap();
+        let forges = *self.total_forges.read().unwrap();
+        self.ledger.read().unwrap().supply_info(forges, treasury_addresses)
+    }
+
+    /// Set hash over every used proof and the height it was forged at
+    pub fn used_proofs_hash(&self) -> [u8; 32] {
+        self.chain_state.read().unwrap().used_proofs_hash.digest()
+    }
+
+    /// Root of the current state (outputs and used proofs)
+    pub fn state_root(&self) -> [u8; 32] {
+        let used_proofs_root = self.chain_state.read().unwrap().used_proofs_tree.root();
+        state_root(&self.ledger.read().unwrap().output_root(), &used_proofs_root)
+    }
+
+    /// Root the state would have after applying `block` on the tip
+    pub fn state_root_after(&self, block: &Block) -> [u8; 32] {
+        let used_proofs: LeafChanges = block
+            .forges
+            .iter()
+            .map(|forge| (forge.proof_hash, Some(used_proof_tree_value(block.header.height))))
+            .collect();
+        let used_proofs_root = self.chain_state.read().unwrap().used_proofs_tree.root_with(&used_proofs);
+        let ledger = self.ledger.read().unwrap();
+        // A block whose transfers don't apply fails validation before its
+        // state root is checked
+        let changes = ledger.block_changes(block).unwrap_or_else(|_| BlockChanges::forges_only(block));
+        state_root(&ledger.output_root_after(&changes), &used_proofs_root)
+    }
+
+    /// Proof of an output's presence or absence in the current state
+    pub fn prove_output(&self, outpoint: &OutPoint) -> StateProof {
+        let state = self.chain_state.read().unwrap();
+        let ledger = self.ledger.read().unwrap();
+        StateProof {
+            output_root: ledger.output_root(),
+            used_proofs_root: state.used_proofs_tree.root(),
+            proof: ledger.prove_output(outpoint),
+        }
+    }
+
+    /// Proof that a proof hash is used (or unused) in the current state
+    pub fn prove_used_proof(&self, proof_hash: &[u8; 32]) -> StateProof {
+        let state = self.chain_state.read().unwrap();
+        let ledger = self.ledger.read().unwrap();
+        StateProof {
+            output_root: ledger.output_root(),
+            used_proofs_root: state.used_proofs_tree.root(),
+            proof: state.used_proofs_tree.prove(proof_hash),
+        }
+    }
+
+    /// Per-stage block validation timings
+    pub fn validation_metrics(&self) -> &ValidationMetrics {
+        &self.validation_metrics
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::testing::{self, LIGHT_TEMPERING};
+    use crate::crypto::{
+        derive_public_key, p2tr_address_for_key, proof_of_forge_with_tempering, CANONICAL_PROPHECY, MAX_ARGON2_MEMORY_KIB,
+    };
 
     #[test]
     fn test_consensus_engine_creation() {
@@ -277,6 +1323,20 @@ mod tests {
         assert_eq!(engine.get_total_forges(), 0);
     }
 
+    #[test]
+    fn test_chain_params_applied() {
+        let mut params = NetworkParams::regtest();
+        params.chain.max_forges_per_block = 3;
+        let chain = &params.chain;
+        let engine = ConsensusEngine::new(chain.initial_difficulty, chain.min_block_time).with_params(&params);
+        assert_eq!((engine.get_difficulty(), engine.max_forges_per_block()), (0, 3));
+
+        // Regtest difficulty never rises; mainnet's does
+        assert_eq!(engine.difficulty_after(0, DIFFICULTY_ADJUSTMENT_FORGES), 0);
+        let mainnet = ConsensusEngine::new(0, 600).with_params(&NetworkParams::mainnet());
+        assert_eq!(mainnet.difficulty_after(0, DIFFICULTY_ADJUSTMENT_FORGES), 1);
+    }
+
     #[test]
     fn test_difficulty_check() {
         let engine = ConsensusEngine::new(2, 600);
@@ -288,4 +1348,556 @@ mod tests {
         assert!(engine.check_difficulty(&hash_with_2_zeros, 2));
         assert!(!engine.check_difficulty(&hash_with_2_zeros, 3));
     }
+
+    fn test_block(height: u64, prev_block_hash: [u8; 32], proof_byte: u8) -> Block {
+        let forge = ForgeTransaction {
+            prophecy: CANONICAL_PROPHECY.join(" "),
+            derived_key: vec![],
+            taproot_address: "bc1p...".to_string(),
+            proof_hash: [proof_byte; 32],
+            timestamp: 1000 + height,
+            signature: vec![],
+            not_before_height: 0,
+            tempering: Default::default(),
+            salt: [0; 32],
+        };
+        let mut block = testing::block(height, prev_block_hash, vec![forge]);
+        assert!(ConsensusEngine::new(0, 600).grind_header(&mut block.header, 1_000));
+        block
+    }
+
+    /// Example deployment rule: only allow-listed addresses may forge
+    struct AllowListedForgers(Vec<String>);
+
+    impl ConsensusRule for AllowListedForgers {
+        fn name(&self) -> &str {
+            "allow-listed-forgers"
+        }
+
+        fn check_forge(&self, forge: &ForgeTransaction, _height: u64) -> Result<()> {
+            if self.0.contains(&forge.taproot_address) {
+                Ok(())
+            } else {
+                Err(anyhow!("{} is not an allow-listed forger", forge.taproot_address))
+            }
+        }
+    }
+
+    struct MaxVersion(u32);
+
+    impl ConsensusRule for MaxVersion {
+        fn name(&self) -> &str {
+            "max-version"
+        }
+
+        fn check_header(&self, header: &BlockHeader) -> Result<()> {
+            if header.version > self.0 {
+                return Err(anyhow!("Header version {} not supported", header.version));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_consensus_rules() {
+        let mut engine = ConsensusEngine::new(0, 600);
+        engine.register_rule(Box::new(MaxVersion(0)));
+        engine.register_rule(Box::new(AllowListedForgers(vec!["bc1pknight".to_string()])));
+        assert_eq!(engine.rule_names(), vec!["max-version", "allow-listed-forgers"]);
+
+        // Header rules run with the contextual header checks
+        let mut block = test_block(0, [0u8; 32], 1);
+        block.header.merkle_root = engine.compute_merkle_root(&block.forges);
+        assert!(engine.grind_header(&mut block.header, 1_000));
+        let err = engine.validate_block(&block, &[0u8; 32]).unwrap_err();
+        assert!(format!("{:#}", err).contains("Rejected by rule max-version"));
+
+        let forge = &block.forges[0];
+        assert!(engine.rules.check_forge(forge, 1).is_err());
+        let mut knight = forge.clone();
+        knight.taproot_address = "bc1pknight".to_string();
+        assert!(engine.rules.check_forge(&knight, 1).is_ok());
+    }
+
+    #[test]
+    fn test_prophecy_forged_only_once() {
+        use super::testing::{derived_forge_of, test_prophecy};
+
+        let engine = ConsensusEngine::new(0, 600).with_params(&NetworkParams::regtest());
+        let prophecy = test_prophecy(7, 1);
+        let first = derived_forge_of(&prophecy, 7, 1);
+        let again = derived_forge_of(&prophecy, 8, 2);
+        let with_forges = |height: u64, parent: [u8; 32], forges: Vec<ForgeTransaction>| {
+            let mut block = test_block(height, parent, 1);
+            block.forges = forges;
+            block.header.merkle_root = engine.compute_merkle_root(&block.forges);
+            assert!(engine.grind_header(&mut block.header, 1_000));
+            block
+        };
+
+        engine.apply_block(&test_block(0, [0u8; 32], 0)).unwrap();
+        let genesis = engine.get_tip_hash();
+
+        // Not twice in one block
+        let block = with_forges(1, genesis, vec![first.clone(), again.clone()]);
+        let error = engine.validate_block(&block, &genesis).unwrap_err();
+        assert!(error.to_string().contains("forged twice"), "{}", error);
+
+        // Nor again in a later block, by anyone
+        let block = with_forges(1, genesis, vec![first.clone()]);
+        engine.apply_block(&block).unwrap();
+        let owner = engine.prophecy_owner(&prophecy_registry_hash(&first.prophecy)).unwrap();
+        assert_eq!((owner.owner, owner.height), (first.taproot_address.clone(), 1));
+        let tip = engine.get_tip_hash();
+        let later = with_forges(2, tip, vec![again]);
+        let error = engine.validate_block(&later, &tip).unwrap_err();
+        assert!(format!("{:#}", error).contains("already forged at height 1"), "{:#}", error);
+        assert!(engine.validate_forge(&derived_forge_of(&test_prophecy(8, 2), 8, 2)).unwrap());
+
+        // Nor with its last words split differently, which derives the
+        // same binding
+        let mut act = test_prophecy(9, 1)[..11].to_vec();
+        let mut action = act.clone();
+        act.extend(["act".to_string(), "ionX".to_string()]);
+        action.extend(["action".to_string(), "X".to_string()]);
+        let tip = engine.get_tip_hash();
+        engine.apply_block(&with_forges(2, tip, vec![derived_forge_of(&act, 9, 1)])).unwrap();
+        let error = engine.validate_forge(&derived_forge_of(&action, 10, 1)).unwrap_err();
+        assert!(format!("{:#}", error).contains("already forged at height 2"), "{:#}", error);
+    }
+
+    #[test]
+    fn test_canonical_forge_address_transition() {
+        let mut params = NetworkParams::regtest();
+        params.p2tr_activation_height = 100;
+        let public_key = derive_public_key(&[7u8; 32]).unwrap();
+        let addresses = DerivedAddresses::for_key(&public_key, params.network).unwrap();
+
+        let mut forge = test_block(1, [0u8; 32], 1).forges.remove(0);
+        forge.derived_key = public_key.serialize().to_vec();
+
+        forge.taproot_address = addresses.p2tr.clone();
+        let address = |forge: &ForgeTransaction, height| {
+            canonical_forge_address(forge, height, params.network, params.p2tr_activation_height)
+        };
+        assert_eq!(address(&forge, 500).unwrap(), addresses.p2tr);
+
+        // Legacy addresses map to P2TR until activation
+        forge.taproot_address = addresses.legacy_p2wpkh.clone();
+        assert_eq!(address(&forge, 99).unwrap(), addresses.p2tr);
+        assert!(address(&forge, 100).is_err());
+
+        forge.taproot_address = "bcrt1p...".to_string();
+        assert!(address(&forge, 1).is_err());
+    }
+
+    /// Unsigned forge of the canonical prophecy by the key of
+    /// `[key_seed; 32]`, tempered with `salt` bound to that key, and the
+    /// derivation behind it
+    fn salted_forge(
+        key_seed: u8,
+        salt: [u8; 32],
+        tempering: TemperingAlgorithm,
+    ) -> (ForgeTransaction, ProofOfForgeResult) {
+        let words: Vec<String> = CANONICAL_PROPHECY.iter().map(|w| w.to_string()).collect();
+        let forger_key = derive_public_key(&[key_seed; 32]).unwrap();
+        let bound_salt = forge_tempering_salt(&forger_key.serialize(), &salt);
+        let result = proof_of_forge_with_tempering(&words, Some(&bound_salt), Network::Regtest, tempering).unwrap();
+        let mut forge = test_block(0, [0u8; 32], 1).forges.remove(0);
+        forge.derived_key = forger_key.serialize().to_vec();
+        forge.taproot_address = p2tr_address_for_key(&forger_key, Network::Regtest);
+        forge.proof_hash = forge_proof_hash(&result, &salt);
+        forge.tempering = tempering;
+        forge.salt = salt;
+        (forge, result)
+    }
+
+    /// Generated independently from the canonical signing layout
+    #[test]
+    fn test_forge_signing_hash_vectors() {
+        let mut forge = ForgeTransaction {
+            prophecy: "excalibur".to_string(),
+            derived_key: vec![2; 33],
+            taproot_address: "bcrt1pforge".to_string(),
+            proof_hash: [0x11; 32],
+            timestamp: 1_700_000_000,
+            signature: vec![0xAA; 64],
+            not_before_height: 0,
+            tempering: TemperingAlgorithm::Pbkdf2Sha512,
+            salt: [0x22; 32],
+        };
+        assert_eq!(
+            hex::encode(forge.signing_hash()),
+            "40c4032f5835d1273515a33d9f61147ca350f6f9cf53357d306145db5657ddec"
+        );
+
+        forge.tempering = TemperingAlgorithm::Argon2id {
+            memory_kib: 19456,
+            iterations: 2,
+            parallelism: 1,
+        };
+        forge.salt = [0x33; 32];
+        forge.not_before_height = 840;
+        forge.signature.clear();
+        assert_eq!(
+            hex::encode(forge.signing_hash()),
+            "0a07e3e96b32b112d9fde48304bd167a315e989337407df1c250a8fd2b2eb8b7"
+        );
+    }
+
+    #[test]
+    fn test_forge_proof_verification() {
+        let (mut forge, result) = salted_forge(7, [1u8; 32], TemperingAlgorithm::Pbkdf2Sha512);
+
+        let engine = ConsensusEngine::new(0, 600).with_network(Network::Regtest);
+        assert!(engine.validate_forge(&forge).unwrap_err().to_string().contains("unsigned"));
+        forge.sign(&[7u8; 32]).unwrap();
+        assert!(engine.validate_forge(&forge).unwrap());
+        let check = |forge: &ForgeTransaction, network| check_forge_matches_proof(forge, &result, 1, network, 0);
+        check(&forge, Network::Regtest).unwrap();
+
+        // The derivation is public, so its seed doesn't sign for the forger
+        let mut stolen = forge.clone();
+        stolen.sign(&result.final_seed).unwrap();
+        assert!(engine.validate_forge(&stolen).unwrap_err().to_string().contains("signature"));
+
+        // Nor can the work be claimed under another key
+        let thief = derive_public_key(&[8u8; 32]).unwrap();
+        stolen.derived_key = thief.serialize().to_vec();
+        stolen.taproot_address = p2tr_address_for_key(&thief, Network::Regtest);
+        stolen.sign(&[8u8; 32]).unwrap();
+        assert!(engine.validate_forge(&stolen).unwrap_err().to_string().contains("Proof hash"));
+
+        // The proof is re-derived with the forge's salt, which must be set
+        let mut tampered = forge.clone();
+        tampered.salt[0] ^= 1;
+        let error = check(&tampered, Network::Regtest).unwrap_err();
+        assert!(error.to_string().contains("Proof hash"));
+        tampered.salt = [0u8; 32];
+        tampered.sign(&[7u8; 32]).unwrap();
+        assert!(engine.validate_forge(&tampered).unwrap_err().to_string().contains("salt"));
+
+        let mut tampered = forge.clone();
+        tampered.proof_hash[0] ^= 1;
+        let error = check(&tampered, Network::Regtest).unwrap_err();
+        assert!(error.to_string().contains("Proof hash"));
+        let error = check(&forge, Network::Bitcoin).unwrap_err();
+        assert!(error.to_string().contains("address"));
+
+        // Rejected before any derivation work
+        let mut tampered = forge;
+        tampered.prophecy = tampered.prophecy.replace("sword", "swordfish");
+        assert!(engine.validate_forge(&tampered).unwrap_err().to_string().contains("signature"));
+        tampered.sign(&[7u8; 32]).unwrap();
+        assert!(engine.validate_forge(&tampered).unwrap_err().to_string().contains("Invalid prophecy"));
+    }
+
+    #[test]
+    fn test_legacy_forge_address_until_p2tr_activation() {
+        let (mut forge, _) = salted_forge(7, [1u8; 32], TemperingAlgorithm::Pbkdf2Sha512);
+        let key = derive_public_key(&[7u8; 32]).unwrap();
+        forge.taproot_address = DerivedAddresses::for_key(&key, Network::Regtest).unwrap().legacy_p2wpkh;
+        forge.sign(&[7u8; 32]).unwrap();
+
+        // Regtest requires P2TR from genesis; a chain activating it later
+        // still accepts the legacy address below the activation height
+        let engine = ConsensusEngine::new(0, 600).with_params(&NetworkParams::regtest());
+        assert!(engine.validate_forge(&forge).unwrap_err().to_string().contains("Legacy"));
+        let mut params = NetworkParams::regtest();
+        params.p2tr_activation_height = 2;
+        let engine = ConsensusEngine::new(0, 600).with_params(&params);
+        assert!(engine.validate_forge(&forge).unwrap());
+        assert!(engine.verify_forge_proof(&forge, 2).unwrap_err().to_string().contains("Legacy"));
+    }
+
+    #[test]
+    fn test_argon2id_forge_verification() {
+        let (mut forge, _) = salted_forge(7, [1u8; 32], LIGHT_TEMPERING);
+        forge.sign(&[7u8; 32]).unwrap();
+
+        // Argon2id forges are only valid once activated
+        let inactive = ConsensusEngine::new(0, 600).with_network(Network::Regtest);
+        assert!(inactive.validate_forge(&forge).unwrap_err().to_string().contains("not active"));
+        let engine = ConsensusEngine::new(0, 600).with_params(&NetworkParams::regtest());
+        assert!(engine.validate_forge(&forge).unwrap());
+
+        // The signature covers the tempering parameters
+        let mut tampered = forge.clone();
+        tampered.tempering = TemperingAlgorithm::Argon2id {
+            memory_kib: 128,
+            iterations: 1,
+            parallelism: 1,
+        };
+        assert!(engine.validate_forge(&tampered).unwrap_err().to_string().contains("signature"));
+
+        // Re-derived with the parameters the forge claims
+        tampered.sign(&[7u8; 32]).unwrap();
+        assert!(engine.validate_forge(&tampered).unwrap_err().to_string().contains("mismatch"));
+        tampered.tempering = TemperingAlgorithm::Argon2id {
+            memory_kib: MAX_ARGON2_MEMORY_KIB * 2,
+            iterations: 1,
+            parallelism: 1,
+        };
+        tampered.sign(&[7u8; 32]).unwrap();
+        assert!(engine.validate_forge(&tampered).unwrap_err().to_string().contains("memory"));
+    }
+
+    #[test]
+    fn test_distinct_salts_meet_nonzero_difficulty() {
+        let engine = ConsensusEngine::new(1, 600).with_params(&NetworkParams::regtest());
+
+        // Grind salts until two derivations meet the difficulty, keeping
+        // one that doesn't
+        let mut accepted = Vec::new();
+        let mut missed = None;
+        for nonce in 1u64.. {
+            let mut salt = [0u8; 32];
+            salt[..8].copy_from_slice(&nonce.to_le_bytes());
+            let (mut forge, _) = salted_forge(7, salt, LIGHT_TEMPERING);
+            forge.sign(&[7u8; 32]).unwrap();
+            if meets_difficulty(&forge.proof_hash, 1) {
+                accepted.push(forge);
+                if accepted.len() == 2 {
+                    break;
+                }
+            } else {
+                missed.get_or_insert(forge);
+            }
+        }
+
+        assert_ne!(accepted[0].proof_hash, accepted[1].proof_hash);
+        for forge in &accepted {
+            assert!(engine.validate_forge(forge).unwrap());
+        }
+        let error = engine.validate_forge(&missed.unwrap()).unwrap_err();
+        assert!(error.to_string().contains("difficulty"));
+
+        // Once the first is in a block its proof is spent, and the other
+        // forge of the same prophecy is too late
+        let mut block = test_block(1, [0u8; 32], 1);
+        block.forges = vec![accepted[0].clone()];
+        engine.apply_block(&block).unwrap();
+        let error = engine.validate_forge(&accepted[1]).unwrap_err();
+        assert!(error.to_string().contains("already forged at height 1"), "{}", error);
+        let error = engine.validate_forge(&accepted[0]).unwrap_err();
+        assert!(error.to_string().contains("replay"));
+    }
+
+    #[test]
+    fn test_header_pow() {
+        let engine = ConsensusEngine::new(0, 600);
+        let mut header = test_block(1, [0u8; 32], 1).header;
+        assert!(engine.grind_header(&mut header, 1_000));
+        assert!(engine.check_header_pow(&header).is_ok());
+
+        // Headers must carry the chain's target, easier or harder
+        header.bits = 0x2100ffff;
+        assert!(engine.check_header_pow(&header).is_err());
+        header.bits = 0x1d00ffff;
+        let error = engine.check_header_pow(&header).unwrap_err();
+        assert_eq!(error.to_string(), "Header at height 1 has target 0x1d00ffff, expected 0x207fffff");
+
+        // The limit comes from the chain's parameters
+        let mut params = NetworkParams::regtest();
+        params.chain.pow_limit_bits = 0x1f00ffff;
+        let engine = engine.with_params(&params);
+        assert_eq!(engine.header_bits(1), 0x1f00ffff);
+        header.bits = POW_LIMIT_BITS;
+        assert!(engine.check_header_pow(&header).is_err());
+        header.bits = 0x1f00ffff;
+        assert!(engine.grind_header(&mut header, 1_000_000));
+
+        assert!(header_work(0x1d00ffff) > header_work(POW_LIMIT_BITS));
+    }
+
+    #[test]
+    fn test_header_height_follows_parent() {
+        let engine = ConsensusEngine::new(0, 600);
+        let mut header = test_block(1, [0u8; 32], 1).header;
+        assert!(engine.grind_header(&mut header, 1_000));
+        assert!(engine.validate_header(&header, &[0u8; 32], 1).is_ok());
+
+        // A header can't skip ahead of its parent
+        let error = engine.validate_header(&header, &[0u8; 32], 2).unwrap_err();
+        assert_eq!(error.to_string(), "Header claims height 1, expected 2");
+    }
+
+    #[test]
+    fn test_block_height_follows_tip() {
+        let mut params = NetworkParams::regtest();
+        params.p2tr_activation_height = 100;
+        let engine = ConsensusEngine::new(0, 600).with_params(&params);
+        engine.apply_block(&test_block(0, [0u8; 32], 0)).unwrap();
+        let tip = engine.get_tip_hash();
+
+        // Claiming a height past activation doesn't pick the rules the
+        // block is checked under
+        let mut block = test_block(100, tip, 1);
+        block.header.merkle_root = engine.compute_merkle_root(&block.forges);
+        assert!(engine.grind_header(&mut block.header, 1_000));
+        let error = engine.prevalidate_block(&block, &tip).unwrap_err();
+        assert_eq!(error.to_string(), "Header claims height 100, expected 1");
+        assert_eq!(engine.validate_block(&block, &tip).unwrap_err().to_string(), error.to_string());
+    }
+
+    #[test]
+    fn test_chainwork_accumulates() {
+        let engine = ConsensusEngine::new(0, 600);
+        engine.apply_block(&test_block(1, [0u8; 32], 1)).unwrap();
+        engine.apply_block(&test_block(2, [0u8; 32], 2)).unwrap();
+        assert_eq!(
+            engine.get_chainwork(),
+            header_work(POW_LIMIT_BITS) + header_work(POW_LIMIT_BITS)
+        );
+    }
+
+    #[test]
+    fn test_validation_stage_timings() {
+        let engine = ConsensusEngine::new(0, 600);
+        let block = test_block(0, [0u8; 32], 1);
+
+        // Bad merkle root: the header stage passes, merkle fails, and
+        // later stages never run
+        assert!(engine.validate_block(&block, &[0u8; 32]).is_err());
+
+        let metrics = engine.validation_metrics();
+        assert_eq!(metrics.stage(ValidationStage::ContextualHeader).count, 1);
+        assert_eq!(metrics.stage(ValidationStage::Merkle).count, 1);
+        assert_eq!(metrics.stage(ValidationStage::ProofOfForge).count, 0);
+        assert_eq!(metrics.stage(ValidationStage::Policy).count, 0);
+
+        // Wrong parent fails in the header stage
+        assert!(engine.validate_block(&block, &[9u8; 32]).is_err());
+        assert_eq!(metrics.stage(ValidationStage::ContextualHeader).count, 2);
+        assert_eq!(metrics.stage(ValidationStage::Merkle).count, 1);
+    }
+
+    #[test]
+    fn test_prevalidation_skips_proof_of_forge() {
+        let engine = ConsensusEngine::new(0, 600);
+        let mut block = test_block(0, [0u8; 32], 1);
+        assert!(engine.prevalidate_block(&block, &[0u8; 32]).is_err());
+
+        block.header.merkle_root = engine.compute_merkle_root(&block.forges);
+        assert!(engine.grind_header(&mut block.header, 1_000));
+        engine.prevalidate_block(&block, &[0u8; 32]).unwrap();
+        assert!(engine.prevalidate_block(&block, &[9u8; 32]).is_err());
+
+        // The forge itself is unsigned, which only full validation notices
+        assert!(engine.validate_block(&block, &[0u8; 32]).unwrap_err().to_string().contains("signature"));
+        assert_eq!(engine.validation_metrics().stage(ValidationStage::Signatures).count, 1);
+    }
+
+    #[test]
+    fn test_permissioned_blocks_need_authority_signatures() {
+        use bitcoin::secp256k1::{Keypair, Secp256k1, SecretKey};
+
+        let secrets: Vec<SecretKey> = (1..=3).map(|seed| SecretKey::from_slice(&[seed; 32]).unwrap()).collect();
+        let keys = secrets
+            .iter()
+            .map(|secret| Keypair::from_secret_key(&Secp256k1::new(), secret).x_only_public_key().0);
+        let authorities = AuthoritySet::new(keys.collect(), 2, 0).unwrap();
+        let params = NetworkParams::regtest().with_authorities(authorities.clone());
+        let engine = ConsensusEngine::new(0, 600).with_params(&params);
+        engine.apply_block(&test_block(0, [0u8; 32], 0)).unwrap();
+        let genesis = engine.get_tip_hash();
+
+        let mut block = test_block(1, genesis, 1);
+        block.header.merkle_root = engine.compute_merkle_root(&block.forges);
+        assert!(engine.grind_header(&mut block.header, 1_000));
+        let hash = engine.compute_block_hash(&block.header);
+        assert!(engine.prevalidate_block(&block, &genesis).unwrap_err().to_string().contains("required"));
+
+        // Height 1 is proposed by the second authority
+        for secret in [&secrets[0], &secrets[2]] {
+            AuthorityKey::new(&authorities, secret).unwrap().sign(&mut block, &hash);
+        }
+        assert!(engine.prevalidate_block(&block, &genesis).unwrap_err().to_string().contains("proposer 1"));
+        AuthorityKey::new(&authorities, &secrets[1]).unwrap().sign(&mut block, &hash);
+        engine.prevalidate_block(&block, &genesis).unwrap();
+
+        // Public chains take no signatures
+        let public = ConsensusEngine::new(0, 600).with_params(&NetworkParams::regtest());
+        public.apply_block(&test_block(0, [0u8; 32], 0)).unwrap();
+        assert!(public.prevalidate_block(&block, &genesis).unwrap_err().to_string().contains("none are expected"));
+    }
+
+    #[test]
+    fn test_snapshot_load() {
+        use crate::params::AssumeUtxoData;
+
+        let source = ConsensusEngine::new(0, 600);
+        let genesis = test_block(0, [0u8; 32], 1);
+        source.apply_block(&genesis).unwrap();
+        let genesis_hash = source.compute_block_hash(&genesis.header);
+        source.apply_block(&test_block(1, genesis_hash, 2)).unwrap();
+
+        let snapshot = source.create_snapshot();
+        let mut params = NetworkParams::regtest();
+
+        // No commitment for this height
+        let engine = ConsensusEngine::new(4, 600);
+        assert!(engine.load_snapshot(&snapshot, &params).is_err());
+
+        params.assume_utxo.push(AssumeUtxoData {
+            height: 1,
+            snapshot_hash: snapshot.snapshot_hash().unwrap(),
+        });
+
+        // Tampered snapshot
+        let mut tampered = snapshot.clone();
+        tampered.used_proofs.pop();
+        assert!(engine.load_snapshot(&tampered, &params).is_err());
+        let mut reowned = snapshot.clone();
+        reowned.prophecy_owners[0].1.owner = "bc1pknight".to_string();
+        assert!(engine.load_snapshot(&reowned, &params).is_err());
+        let mut reworked = snapshot.clone();
+        reworked.chainwork = [0u8; 32];
+        assert!(engine.load_snapshot(&reworked, &params).is_err());
+        let mut retargeted = snapshot.clone();
+        retargeted.difficulty += 1;
+        assert!(engine.load_snapshot(&retargeted, &params).is_err());
+
+        engine.load_snapshot(&snapshot, &params).unwrap();
+        assert_eq!(engine.get_height(), 1);
+        assert_eq!(engine.get_difficulty(), source.get_difficulty());
+        assert_eq!(engine.get_chainwork(), source.get_chainwork());
+        assert_ne!(engine.get_chainwork(), Work::from_be_bytes([0u8; 32]));
+        assert_eq!(engine.get_ledger_info(), source.get_ledger_info());
+        assert_eq!(engine.used_proofs_hash(), source.used_proofs_hash());
+        assert_ne!(engine.used_proofs_hash(), ConsensusEngine::new(0, 600).used_proofs_hash());
+        assert!(!engine.snapshot_status().unwrap().validated);
+
+        // Replay protection and the prophecy registry survive the snapshot
+        assert!(engine.chain_state.read().unwrap().used_prophecies.contains_key(&[2u8; 32]));
+        let prophecy_hash = prophecy_registry_hash(&genesis.forges[0].prophecy);
+        assert_eq!(engine.prophecy_owner(&prophecy_hash), source.prophecy_owner(&prophecy_hash));
+        assert_eq!(engine.prophecy_owner_count(), 1);
+    }
+
+    #[test]
+    fn test_snapshot_file_roundtrip() {
+        let source = ConsensusEngine::new(0, 600);
+        source.apply_block(&test_block(0, [0u8; 32], 1)).unwrap();
+        let snapshot = source.create_snapshot();
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("ledger.snapshot");
+        snapshot.write_to(&path).unwrap();
+        let loaded = LedgerSnapshot::read_from(&path).unwrap();
+        assert_eq!(loaded, snapshot);
+
+        let mut params = NetworkParams::regtest();
+        params.assume_utxo.push(crate::params::AssumeUtxoData {
+            height: 0,
+            snapshot_hash: loaded.snapshot_hash().unwrap(),
+        });
+        let engine = ConsensusEngine::new(4, 600);
+        engine.load_snapshot(&loaded, &params).unwrap();
+        assert_eq!(engine.get_difficulty(), source.get_difficulty());
+        assert_eq!(engine.get_chainwork(), source.get_chainwork());
+
+        std::fs::write(&path, b"garbage").unwrap();
+        assert!(LedgerSnapshot::read_from(&path).is_err());
+    }
 }
@@ -0,0 +1,100 @@
+//! Embedder-supplied consensus rules
+//!
+//! Private deployments can add validation (e.g. allow-listed forgers) by
+//! registering `ConsensusRule`s with `ConsensusEngine::register_rule` before
+//! the engine is shared. Rules only ever tighten consensus: they run after
+//! the built-in checks of the same kind and can reject, never accept.
+
+use super::{Block, BlockHeader, ForgeTransaction};
+use anyhow::Result;
+
+/// Chain state a block is validated against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuleContext {
+    /// Height of the current tip
+    pub tip_height: u64,
+    /// Hash of the current tip
+    pub tip_hash: [u8; 32],
+}
+
+/// Additional validation run by the consensus engine.
+///
+/// Every hook defaults to accepting, so a rule only implements the checks
+/// it needs. Rules run in registration order within each hook:
+///
+/// 1. `check_header` after the built-in contextual header checks
+/// 2. `check_forge` for each forge, after its proof-of-forge is verified
+/// 3. `check_block_context` last, after the built-in policy checks
+pub trait ConsensusRule: Send + Sync {
+    /// Name used in rejection messages
+    fn name(&self) -> &str;
+
+    /// Check a block header
+    fn check_header(&self, _header: &BlockHeader) -> Result<()> {
+        Ok(())
+    }
+
+    /// Check a forge included at `height`
+    fn check_forge(&self, _forge: &ForgeTransaction, _height: u64) -> Result<()> {
+        Ok(())
+    }
+
+    /// Check a whole block against the chain it extends
+    fn check_block_context(&self, _block: &Block, _context: &RuleContext) -> Result<()> {
+        Ok(())
+    }
+
+    /// First height the rule applies at, for rules scheduled to activate.
+    /// When the next block reaches it the mempool is revalidated.
+    fn activation_height(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Ordered set of registered rules
+#[derive(Default)]
+pub(super) struct RuleSet {
+    rules: Vec<Box<dyn ConsensusRule>>,
+}
+
+impl RuleSet {
+    pub(super) fn push(&mut self, rule: Box<dyn ConsensusRule>) {
+        self.rules.push(rule);
+    }
+
+    pub(super) fn names(&self) -> Vec<String> {
+        self.rules.iter().map(|rule| rule.name().to_string()).collect()
+    }
+
+    pub(super) fn activations_between(&self, after: u64, up_to: u64) -> Vec<String> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.activation_height().is_some_and(|height| height > after && height <= up_to))
+            .map(|rule| rule.name().to_string())
+            .collect()
+    }
+
+    pub(super) fn check_header(&self, header: &BlockHeader) -> Result<()> {
+        for rule in &self.rules {
+            rule.check_header(header)
+                .map_err(|e| e.context(format!("Rejected by rule {}", rule.name())))?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn check_forge(&self, forge: &ForgeTransaction, height: u64) -> Result<()> {
+        for rule in &self.rules {
+            rule.check_forge(forge, height)
+                .map_err(|e| e.context(format!("Rejected by rule {}", rule.name())))?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn check_block_context(&self, block: &Block, context: &RuleContext) -> Result<()> {
+        for rule in &self.rules {
+            rule.check_block_context(block, context)
+                .map_err(|e| e.context(format!("Rejected by rule {}", rule.name())))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+//! Post-block state root commitments
+//!
+//! A header that sets `VERSION_STATE_ROOT` commits to the state after its
+//! block is applied: the unspent outputs and the used proofs, each kept in
+//! a sparse merkle tree, hashed together as
+//!
+//! ```text
+//! state_root = sha256("ExcaliburState/root" | output root | used-proofs root)
+//! ```
+//!
+//! Light clients holding a header can then check a `StateProof` for an
+//! output or a proof hash without the rest of the state, and replays get a
+//! per-block anchor. Roots are optional until the network's
+//! `state_root_activation_height` and required from it on.
+
+use super::BlockHeader;
+use crate::ledger::{output_tree_key, output_tree_value, LedgerOutput, OutPoint, SmtProof};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Header version bit signalling a state root
+pub const VERSION_STATE_ROOT: u32 = 1 << 9;
+
+const STATE_ROOT_TAG: &[u8] = b"ExcaliburState/root";
+
+/// State root over the output and used-proofs tree roots
+pub fn state_root(output_root: &[u8; 32], used_proofs_root: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(STATE_ROOT_TAG);
+    hasher.update(output_root);
+    hasher.update(used_proofs_root);
+    hasher.finalize().into()
+}
+
+/// Used-proofs tree value hash for a proof used at `height`
+pub fn used_proof_tree_value(height: u64) -> [u8; 32] {
+    Sha256::digest(height.to_le_bytes()).into()
+}
+
+/// Proof of one entry of the state against a state root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateProof {
+    pub output_root: [u8; 32],
+    pub used_proofs_root: [u8; 32],
+    /// Path in whichever tree the entry belongs to
+    pub proof: SmtProof,
+}
+
+impl StateProof {
+    /// Check that `outpoint` is unspent as `output` (or spent/never
+    /// created, for `None`) under `root`
+    pub fn verify_output(&self, root: &[u8; 32], outpoint: &OutPoint, output: Option<&LedgerOutput>) -> bool {
+        let value = output.map(|output| output_tree_value(outpoint, output));
+        self.commits_to(root) && self.proof.verify(&self.output_root, &output_tree_key(outpoint), value.as_ref())
+    }
+
+    /// Check that `proof_hash` was used at `height` (or is unused, for
+    /// `None`) under `root`
+    pub fn verify_used_proof(&self, root: &[u8; 32], proof_hash: &[u8; 32], height: Option<u64>) -> bool {
+        let value = height.map(used_proof_tree_value);
+        self.commits_to(root) && self.proof.verify(&self.used_proofs_root, proof_hash, value.as_ref())
+    }
+
+    fn commits_to(&self, root: &[u8; 32]) -> bool {
+        state_root(&self.output_root, &self.used_proofs_root) == *root
+    }
+}
+
+/// Check a header's state root against the expected post-block root.
+/// Headers at or above `activation_height` must carry one; below it the
+/// root is optional but checked when present. `expected` is only computed
+/// when there is a root to check.
+pub fn check_state_root(
+    header: &BlockHeader,
+    activation_height: u64,
+    expected: impl FnOnce() -> [u8; 32],
+) -> Result<()> {
+    let signalled = header.version & VERSION_STATE_ROOT != 0;
+    match (signalled, &header.state_root) {
+        (false, None) if header.height >= activation_height => {
+            Err(anyhow!("State root required from height {}", activation_height))
+        }
+        (false, None) => Ok(()),
+        (false, Some(_)) => Err(anyhow!("State root present without its version bit")),
+        (true, None) => Err(anyhow!("Header signals a state root but has none")),
+        (true, Some(root)) if *root != expected() => Err(anyhow!("State root mismatch")),
+        (true, Some(_)) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::{testing, Block, ConsensusEngine, ForgeTransaction};
+    use crate::ledger::FORGE_REWARD;
+
+    fn block(height: u64, proof_bytes: &[u8]) -> Block {
+        let forges = proof_bytes
+            .iter()
+            .map(|byte| ForgeTransaction {
+                prophecy: "sword legend".to_string(),
+                derived_key: vec![],
+                taproot_address: format!("bc1p{}", byte),
+                proof_hash: [*byte; 32],
+                timestamp: 0,
+                signature: vec![],
+                not_before_height: 0,
+                tempering: Default::default(),
+                salt: [0; 32],
+            })
+            .collect();
+        testing::block(height, [0u8; 32], forges)
+    }
+
+    #[test]
+    fn test_state_root_gated_by_version_bit_and_activation() {
+        let engine = ConsensusEngine::new(0, 600);
+        let mut block = block(3, &[1, 2]);
+        let expected = engine.state_root_after(&block);
+        check_state_root(&block.header, u64::MAX, || expected).unwrap();
+        assert!(check_state_root(&block.header, 3, || expected).unwrap_err().to_string().contains("required"));
+
+        let unrooted_hash = engine.compute_block_hash(&block.header);
+        block.set_state_root(&engine);
+        assert!(block.header.version & VERSION_STATE_ROOT != 0);
+        assert_ne!(engine.compute_block_hash(&block.header), unrooted_hash);
+        check_state_root(&block.header, 3, || expected).unwrap();
+        check_state_root(&block.header, u64::MAX, || expected).unwrap();
+
+        // The root commits to the forges' outputs and proofs
+        block.forges[1].taproot_address = "bc1pother".to_string();
+        let changed = engine.state_root_after(&block);
+        assert!(check_state_root(&block.header, 3, || changed).unwrap_err().to_string().contains("mismatch"));
+
+        block.header.version &= !VERSION_STATE_ROOT;
+        assert!(check_state_root(&block.header, u64::MAX, || changed).is_err());
+        block.header.version |= VERSION_STATE_ROOT;
+        block.header.state_root = None;
+        assert!(check_state_root(&block.header, u64::MAX, || changed).is_err());
+    }
+
+    #[test]
+    fn test_state_proofs_against_applied_root() {
+        let engine = ConsensusEngine::new(0, 600);
+        let genesis = block(0, &[1, 2, 3]);
+        let predicted = engine.state_root_after(&genesis);
+        engine.apply_block(&genesis).unwrap();
+        let next = block(1, &[4]);
+        let predicted_next = engine.state_root_after(&next);
+        assert_eq!(engine.state_root(), predicted);
+        engine.apply_block(&next).unwrap();
+        let root = engine.state_root();
+        assert_eq!(root, predicted_next);
+
+        let outpoint = OutPoint { txid: [2; 32], vout: 0 };
+        let output = LedgerOutput {
+            address: "bc1p2".to_string(),
+            value: FORGE_REWARD,
+            height: 0,
+        };
+        let proof = engine.prove_output(&outpoint);
+        assert!(proof.verify_output(&root, &outpoint, Some(&output)));
+        assert!(!proof.verify_output(&root, &outpoint, None));
+        assert!(!proof.verify_output(&predicted, &outpoint, Some(&output)));
+        let forged = LedgerOutput { value: FORGE_REWARD * 2, ..output };
+        assert!(!proof.verify_output(&root, &outpoint, Some(&forged)));
+
+        let missing = OutPoint { txid: [9; 32], vout: 0 };
+        assert!(engine.prove_output(&missing).verify_output(&root, &missing, None));
+
+        assert!(engine.prove_used_proof(&[4; 32]).verify_used_proof(&root, &[4; 32], Some(1)));
+        assert!(!engine.prove_used_proof(&[4; 32]).verify_used_proof(&root, &[4; 32], Some(0)));
+        assert!(engine.prove_used_proof(&[9; 32]).verify_used_proof(&root, &[9; 32], None));
+    }
+}
@@ -0,0 +1,101 @@
+//! Forge, header and block fixtures shared by unit tests
+
+use super::{Block, BlockHeader, ForgeTransaction, POW_LIMIT_BITS};
+use crate::crypto::{
+    derive_public_key, entropy_to_mnemonic, forge_proof_hash, forge_tempering_salt, p2tr_address_for_key,
+    proof_of_forge_with_tempering, prophecy_from_mnemonic, TemperingAlgorithm, CANONICAL_PROPHECY,
+};
+use bitcoin::Network;
+
+/// Argon2id parameters cheap enough to derive many forges
+pub(crate) const LIGHT_TEMPERING: TemperingAlgorithm = TemperingAlgorithm::Argon2id {
+    memory_kib: 64,
+    iterations: 1,
+    parallelism: 1,
+};
+
+/// Forge signed by the key derived from `[seed; 32]` (so `seed` must not
+/// be 0), with proof hash `[seed; 32]`. Its proof fields are not a real
+/// derivation, so it only passes checks that don't re-derive it.
+pub(crate) fn forge(seed: u8) -> ForgeTransaction {
+    forge_with(seed, |_| {})
+}
+
+/// `forge(seed)` with `edit` applied before it is signed
+pub(crate) fn forge_with(seed: u8, edit: impl FnOnce(&mut ForgeTransaction)) -> ForgeTransaction {
+    let mut forge = ForgeTransaction {
+        prophecy: format!("test prophecy {}", seed),
+        derived_key: derive_public_key(&[seed; 32]).unwrap().serialize().to_vec(),
+        taproot_address: "bc1p...".to_string(),
+        proof_hash: [seed; 32],
+        timestamp: 1000,
+        signature: vec![],
+        not_before_height: 0,
+        tempering: Default::default(),
+        salt: [0; 32],
+    };
+    edit(&mut forge);
+    forge.sign(&[seed; 32]).unwrap();
+    forge
+}
+
+/// Prophecy unique to `seed` and `salt`: a mnemonic of their bytes
+/// followed by the canonical axiom word
+pub(crate) fn test_prophecy(seed: u8, salt: u8) -> Vec<String> {
+    let mut entropy = [0u8; 16];
+    entropy[0] = seed;
+    entropy[1] = salt;
+    let mnemonic = entropy_to_mnemonic(&entropy).unwrap().join(" ");
+    prophecy_from_mnemonic(&mnemonic, CANONICAL_PROPHECY[12], true).unwrap()
+}
+
+/// Regtest forge of `test_prophecy(seed, salt)` by the key of
+/// `[seed; 32]`, really derived with `LIGHT_TEMPERING` under `[salt; 32]`
+/// and signed, so it passes full validation
+pub(crate) fn derived_forge(seed: u8, salt: u8) -> ForgeTransaction {
+    derived_forge_of(&test_prophecy(seed, salt), seed, salt)
+}
+
+/// `derived_forge` of the prophecy `words`
+pub(crate) fn derived_forge_of(words: &[String], seed: u8, salt: u8) -> ForgeTransaction {
+    let forger_key = derive_public_key(&[seed; 32]).unwrap();
+    let bound_salt = forge_tempering_salt(&forger_key.serialize(), &[salt; 32]);
+    let result = proof_of_forge_with_tempering(words, Some(&bound_salt), Network::Regtest, LIGHT_TEMPERING).unwrap();
+    forge_with(seed, |forge| {
+        forge.prophecy = words.join(" ");
+        forge.taproot_address = p2tr_address_for_key(&forger_key, Network::Regtest);
+        forge.proof_hash = forge_proof_hash(&result, &[salt; 32]);
+        forge.tempering = LIGHT_TEMPERING;
+        forge.salt = [salt; 32];
+    })
+}
+
+/// Version 1 header at `height` on `prev_block_hash`, timestamped
+/// `1000 + height` and carrying the mainnet target. It commits to nothing
+/// and isn't ground, so tests that validate it set the merkle root and
+/// call `grind_header`.
+pub(crate) fn header(height: u64, prev_block_hash: [u8; 32]) -> BlockHeader {
+    BlockHeader {
+        version: 1,
+        height,
+        prev_block_hash,
+        merkle_root: [0; 32],
+        timestamp: 1000 + height,
+        difficulty: 0,
+        bits: POW_LIMIT_BITS,
+        nonce: 0,
+        aggregate_commitment: None,
+        state_root: None,
+        timestamp_millis: None,
+    }
+}
+
+/// Block of `forges` and no transfers under `header(height, prev_block_hash)`
+pub(crate) fn block(height: u64, prev_block_hash: [u8; 32], forges: Vec<ForgeTransaction>) -> Block {
+    Block {
+        header: header(height, prev_block_hash),
+        forges,
+        transfers: Vec::new(),
+        authority_signatures: Vec::new(),
+    }
+}
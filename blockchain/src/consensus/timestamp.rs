@@ -0,0 +1,149 @@
+//! Millisecond block times and median-time-past ordering
+//!
+//! Header timestamps are whole seconds. A header that sets
+//! `VERSION_TIMESTAMP_MILLIS` also carries the milliseconds past its
+//! `timestamp`, so chains whose blocks arrive faster than once a second can
+//! still order them. Block times are compared in milliseconds; a header
+//! without the field is at the start of its second.
+//!
+//! From the network's `median_time_activation_height` on, a block's time
+//! must be strictly later than the median time of the `MEDIAN_TIME_SPAN`
+//! blocks before it (median-time-past, MTP). The median moves forward with
+//! the chain but can't be dragged by a single miner's clock.
+
+use super::BlockHeader;
+use anyhow::{anyhow, Result};
+use std::collections::VecDeque;
+
+/// Header version bit signalling a millisecond field
+pub const VERSION_TIMESTAMP_MILLIS: u32 = 1 << 10;
+
+/// Number of preceding blocks whose median a block's time must exceed
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Time of a header in milliseconds since the epoch
+pub fn header_time_millis(header: &BlockHeader) -> u64 {
+    header
+        .timestamp
+        .saturating_mul(1000)
+        .saturating_add(u64::from(header.timestamp_millis.unwrap_or(0)))
+}
+
+/// Set a header's time from milliseconds since the epoch, with the
+/// millisecond field and its version bit
+pub fn set_header_time_millis(header: &mut BlockHeader, time_millis: u64) {
+    header.timestamp = time_millis / 1000;
+    header.timestamp_millis = Some((time_millis % 1000) as u16);
+    header.version |= VERSION_TIMESTAMP_MILLIS;
+}
+
+/// Check a header's millisecond field against its version bit
+pub fn check_timestamp_millis(header: &BlockHeader) -> Result<()> {
+    let signalled = header.version & VERSION_TIMESTAMP_MILLIS != 0;
+    match (signalled, header.timestamp_millis) {
+        (false, None) => Ok(()),
+        (false, Some(_)) => Err(anyhow!("Timestamp milliseconds present without their version bit")),
+        (true, None) => Err(anyhow!("Header signals timestamp milliseconds but has none")),
+        (true, Some(millis)) if millis >= 1000 => Err(anyhow!("Timestamp milliseconds {} out of range", millis)),
+        (true, Some(_)) => Ok(()),
+    }
+}
+
+/// Times of the most recent blocks, oldest first, in milliseconds
+#[derive(Debug, Clone, Default)]
+pub struct RecentBlockTimes {
+    times: VecDeque<u64>,
+}
+
+impl RecentBlockTimes {
+    /// Record the time of a newly applied block
+    pub fn push(&mut self, time_millis: u64) {
+        if self.times.len() == MEDIAN_TIME_SPAN {
+            self.times.pop_front();
+        }
+        self.times.push_back(time_millis);
+    }
+
+    /// Forget every block, e.g. when the state is replaced by a snapshot
+    pub fn clear(&mut self) {
+        self.times.clear();
+    }
+
+    /// Median of the recorded times, or `None` before any block
+    pub fn median(&self) -> Option<u64> {
+        let mut sorted: Vec<u64> = self.times.iter().copied().collect();
+        sorted.sort_unstable();
+        sorted.get(sorted.len() / 2).copied()
+    }
+
+    /// Check a header's time is strictly later than the median. Passes
+    /// when no block times are known.
+    pub fn check(&self, header: &BlockHeader) -> Result<()> {
+        match self.median() {
+            Some(median) if header_time_millis(header) <= median => Err(anyhow!(
+                "Block time {}ms is not after median time past {}ms",
+                header_time_millis(header),
+                median
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::testing;
+
+    fn header(timestamp: u64) -> BlockHeader {
+        BlockHeader {
+            timestamp,
+            ..testing::header(0, [0u8; 32])
+        }
+    }
+
+    #[test]
+    fn test_millisecond_field_follows_version_bit() {
+        let mut header = header(1_700_000_000);
+        assert_eq!(header_time_millis(&header), 1_700_000_000_000);
+        check_timestamp_millis(&header).unwrap();
+
+        set_header_time_millis(&mut header, 1_700_000_000_250);
+        assert_eq!((header.timestamp, header.timestamp_millis), (1_700_000_000, Some(250)));
+        assert_eq!(header_time_millis(&header), 1_700_000_000_250);
+        check_timestamp_millis(&header).unwrap();
+
+        header.timestamp_millis = Some(1000);
+        assert!(check_timestamp_millis(&header).is_err());
+        header.timestamp_millis = None;
+        assert!(check_timestamp_millis(&header).is_err());
+        header.version &= !VERSION_TIMESTAMP_MILLIS;
+        header.timestamp_millis = Some(1);
+        assert!(check_timestamp_millis(&header).is_err());
+    }
+
+    #[test]
+    fn test_block_time_must_pass_median() {
+        let mut recent = RecentBlockTimes::default();
+        assert_eq!(recent.median(), None);
+        recent.check(&header(0)).unwrap();
+
+        // Blocks within one second are ordered by their milliseconds
+        let mut next = header(5);
+        for millis in [5_000, 5_100, 5_200] {
+            recent.push(millis);
+        }
+        assert_eq!(recent.median(), Some(5_100));
+        assert!(recent.check(&next).is_err());
+        set_header_time_millis(&mut next, 5_100);
+        assert!(recent.check(&next).is_err());
+        set_header_time_millis(&mut next, 5_101);
+        recent.check(&next).unwrap();
+
+        // Only the last MEDIAN_TIME_SPAN blocks count
+        for millis in 0..MEDIAN_TIME_SPAN as u64 {
+            recent.push(9_000 + millis);
+        }
+        assert_eq!(recent.median(), Some(9_005));
+    }
+}
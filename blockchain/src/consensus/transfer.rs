@@ -0,0 +1,373 @@
+//! Signed transfers
+//!
+//! A transfer's txid covers only its unsigned body; the witnesses that
+//! authorize its inputs travel alongside it, one per input in order, so a
+//! relayed transfer can't be re-signed into a different txid. Every input
+//! spends a Taproot output and is unlocked one of two ways:
+//!
+//! - key path: a BIP-340 signature by the output key, as produced for the
+//!   BIP-86 addresses forges pay (`crypto::sign_taproot_key_path`);
+//! - script path: a signature, the leaf script and its control block. Only
+//!   the two leaf forms vaults use are accepted: `<key> OP_CHECKSIG`, and
+//!   `<delay> OP_CSV OP_DROP <key> OP_CHECKSIG`, which unlocks once the
+//!   spent output is `delay` blocks deep.
+//!
+//! Signatures are 64 bytes for `SighashType::ALL` or 65 with the hash type
+//! appended, over `transfer_sighash` for the input and spend type.
+
+use super::merkle::merkle_root;
+use super::sighash::{tagged_hash, transfer_sighash, SighashType, SpendType, Transfer};
+use crate::ledger::LedgerOutput;
+use anyhow::{anyhow, Context, Result};
+use bitcoin::opcodes::all::{OP_CHECKSIG, OP_CSV, OP_DROP, OP_PUSHNUM_1, OP_PUSHNUM_16};
+use bitcoin::script::{read_scriptint, Instruction, Script};
+use bitcoin::secp256k1::{schnorr, Keypair, Message, Secp256k1, SecretKey, XOnlyPublicKey};
+use bitcoin::taproot::ControlBlock;
+use bitcoin::{Address, Network};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+
+/// Tag for the merkle commitment to a block's transfers
+pub const TRANSFER_ROOT_TAG: &[u8] = b"ExcaliburTransfer/root";
+
+/// What unlocks one transfer input
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransferWitness {
+    /// Signature by the spent output's key
+    KeyPath { signature: Vec<u8> },
+    /// Signature for a script leaf of the spent output's tree
+    ScriptPath {
+        signature: Vec<u8>,
+        script: Vec<u8>,
+        control_block: Vec<u8>,
+    },
+}
+
+/// A transfer with the witnesses for its inputs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedTransfer {
+    pub transfer: Transfer,
+    pub witnesses: Vec<TransferWitness>,
+}
+
+impl SignedTransfer {
+    /// Identifier of the unsigned transfer
+    pub fn txid(&self) -> Result<[u8; 32]> {
+        self.transfer.txid()
+    }
+
+    /// Check every input's witness against `spent`, the outputs the inputs
+    /// spend in order, for inclusion at `height`
+    pub fn verify(&self, spent: &[LedgerOutput], height: u64, network: Network) -> Result<()> {
+        if self.witnesses.len() != self.transfer.inputs.len() || spent.len() != self.transfer.inputs.len() {
+            return Err(anyhow!(
+                "Transfer has {} witnesses for {} inputs",
+                self.witnesses.len(),
+                self.transfer.inputs.len()
+            ));
+        }
+        for (index, (witness, output)) in self.witnesses.iter().zip(spent).enumerate() {
+            self.verify_input(index, witness, output, height, network)
+                .with_context(|| format!("Transfer input {} is not authorized", index))?;
+        }
+        Ok(())
+    }
+
+    fn verify_input(
+        &self,
+        index: usize,
+        witness: &TransferWitness,
+        output: &LedgerOutput,
+        height: u64,
+        network: Network,
+    ) -> Result<()> {
+        let output_key = taproot_output_key(&output.address, network)?;
+        match witness {
+            TransferWitness::KeyPath { signature } => {
+                verify_signature(&self.transfer, index, SpendType::KeyPath, signature, &output_key, network)
+            }
+            TransferWitness::ScriptPath {
+                signature,
+                script,
+                control_block,
+            } => {
+                let script = Script::from_bytes(script);
+                let control_block =
+                    ControlBlock::decode(control_block).map_err(|e| anyhow!("Malformed control block: {}", e))?;
+                if !control_block.verify_taproot_commitment(&Secp256k1::verification_only(), output_key, script) {
+                    return Err(anyhow!("Script is not committed to by the spent output"));
+                }
+                let (key, delay) = parse_leaf(script)?;
+                if let Some(delay) = delay {
+                    let unlocks_at = output.height.saturating_add(delay);
+                    if height < unlocks_at {
+                        return Err(anyhow!("Delayed leaf unlocks at height {}, not {}", unlocks_at, height));
+                    }
+                }
+                verify_signature(&self.transfer, index, SpendType::ScriptPath, signature, &key, network)
+            }
+        }
+    }
+}
+
+/// Output key of a Taproot address on `network`
+fn taproot_output_key(address: &str, network: Network) -> Result<XOnlyPublicKey> {
+    let script_pubkey = Address::from_str(address)
+        .map_err(|e| anyhow!("Invalid address {}: {}", address, e))?
+        .require_network(network)?
+        .script_pubkey();
+    if !script_pubkey.is_p2tr() {
+        return Err(anyhow!("{} is not a Taproot address", address));
+    }
+    Ok(XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])?)
+}
+
+/// Key and optional block delay of a supported leaf script
+fn parse_leaf(script: &Script) -> Result<(XOnlyPublicKey, Option<u64>)> {
+    let instructions = script
+        .instructions_minimal()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("Malformed leaf script: {}", e))?;
+    let (delay, key) = match instructions.as_slice() {
+        [Instruction::PushBytes(key), Instruction::Op(checksig)] if *checksig == OP_CHECKSIG => (None, key),
+        [delay, Instruction::Op(csv), Instruction::Op(drop), Instruction::PushBytes(key), Instruction::Op(checksig)]
+            if *csv == OP_CSV && *drop == OP_DROP && *checksig == OP_CHECKSIG =>
+        {
+            (Some(script_number(delay)?), key)
+        }
+        _ => return Err(anyhow!("Unsupported leaf script")),
+    };
+    let key = XOnlyPublicKey::from_slice(key.as_bytes()).map_err(|_| anyhow!("Leaf key is not an x-only key"))?;
+    Ok((key, delay))
+}
+
+/// Positive number pushed by a leaf instruction
+fn script_number(instruction: &Instruction) -> Result<u64> {
+    let number = match instruction {
+        Instruction::Op(op) if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&op.to_u8()) => {
+            i64::from(op.to_u8() - OP_PUSHNUM_1.to_u8() + 1)
+        }
+        Instruction::PushBytes(bytes) => read_scriptint(bytes.as_bytes()).map_err(|e| anyhow!("Bad delay: {}", e))?,
+        _ => return Err(anyhow!("Leaf delay is not a number")),
+    };
+    u64::try_from(number).ok().filter(|delay| *delay > 0).ok_or_else(|| anyhow!("Leaf delay must be positive"))
+}
+
+/// Split a witness signature into the BIP-340 signature and its hash type
+fn split_signature(signature: &[u8]) -> Result<(schnorr::Signature, SighashType)> {
+    let (signature, hash_type) = match signature {
+        [signature @ .., hash_type] if signature.len() == 64 => (signature, SighashType::from_u8(*hash_type)?),
+        signature => (signature, SighashType::ALL),
+    };
+    Ok((schnorr::Signature::from_slice(signature).context("Malformed Schnorr signature")?, hash_type))
+}
+
+fn verify_signature(
+    transfer: &Transfer,
+    index: usize,
+    spend_type: SpendType,
+    signature: &[u8],
+    key: &XOnlyPublicKey,
+    network: Network,
+) -> Result<()> {
+    let (signature, hash_type) = split_signature(signature)?;
+    let sighash = transfer_sighash(transfer, index, hash_type, spend_type, network)?;
+    Secp256k1::verification_only()
+        .verify_schnorr(&signature, &Message::from_digest(sighash), key)
+        .map_err(|_| anyhow!("Invalid signature"))
+}
+
+/// Witness signature by `keypair` for input `index`, with the hash type
+/// appended unless it is `SighashType::ALL`
+fn sign(
+    transfer: &Transfer,
+    index: usize,
+    spend_type: SpendType,
+    keypair: &Keypair,
+    hash_type: SighashType,
+    network: Network,
+) -> Result<Vec<u8>> {
+    let sighash = transfer_sighash(transfer, index, hash_type, spend_type, network)?;
+    let signature = Secp256k1::signing_only().sign_schnorr_with_aux_rand(
+        &Message::from_digest(sighash),
+        keypair,
+        &rand::random(),
+    );
+    let mut signature = signature.serialize().to_vec();
+    if hash_type != SighashType::ALL {
+        signature.push(hash_type.to_u8());
+    }
+    Ok(signature)
+}
+
+/// Key-path witness for input `index`, spending an output paid to the
+/// BIP-86 address of `secret_key`
+pub fn sign_key_path(
+    transfer: &Transfer,
+    index: usize,
+    secret_key: &SecretKey,
+    hash_type: SighashType,
+    network: Network,
+) -> Result<TransferWitness> {
+    use bitcoin::key::TapTweak;
+    let secp = Secp256k1::new();
+    let keypair = Keypair::from_secret_key(&secp, secret_key).tap_tweak(&secp, None).to_inner();
+    Ok(TransferWitness::KeyPath {
+        signature: sign(transfer, index, SpendType::KeyPath, &keypair, hash_type, network)?,
+    })
+}
+
+/// Script-path witness for input `index` through `script`, whose key is
+/// that of `secret_key`
+pub fn sign_script_path(
+    transfer: &Transfer,
+    index: usize,
+    secret_key: &SecretKey,
+    script: &Script,
+    control_block: &ControlBlock,
+    hash_type: SighashType,
+    network: Network,
+) -> Result<TransferWitness> {
+    let keypair = Keypair::from_secret_key(&Secp256k1::new(), secret_key);
+    Ok(TransferWitness::ScriptPath {
+        signature: sign(transfer, index, SpendType::ScriptPath, &keypair, hash_type, network)?,
+        script: script.to_bytes(),
+        control_block: control_block.serialize(),
+    })
+}
+
+/// Merkle leaf of a signed transfer, witnesses included
+pub fn transfer_leaf_hash(transfer: &SignedTransfer) -> [u8; 32] {
+    Sha256::digest(transfer.encode()).into()
+}
+
+/// Merkle root over a block's transfers, `None` if it has none
+pub fn transfer_root(transfers: &[SignedTransfer]) -> Option<[u8; 32]> {
+    match transfers.is_empty() {
+        true => None,
+        false => Some(merkle_root(transfers.iter().map(transfer_leaf_hash).collect())),
+    }
+}
+
+/// Merkle root a header commits to: the forge root alone for blocks without
+/// transfers, so their hashes are unchanged, else a tagged hash of the
+/// forge root and the root over the transfers
+pub fn block_merkle_root(forge_root: [u8; 32], transfers: &[SignedTransfer]) -> [u8; 32] {
+    commit_transfer_root(forge_root, transfer_root(transfers))
+}
+
+/// `block_merkle_root` from the two roots, for checking a forge's merkle
+/// path against a header without the block's transfers
+pub fn commit_transfer_root(forge_root: [u8; 32], transfer_root: Option<[u8; 32]>) -> [u8; 32] {
+    match transfer_root {
+        Some(transfer_root) => tagged_hash(TRANSFER_ROOT_TAG, &[forge_root, transfer_root].concat()),
+        None => forge_root,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::sighash::{TransferInput, TransferOutput};
+    use crate::crypto::p2tr_address_for_key;
+    use crate::ledger::OutPoint;
+    use crate::wallet::{delayed_leaf, recovery_leaf, vault_spend_info};
+    use bitcoin::taproot::LeafVersion;
+
+    fn secret(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).unwrap()
+    }
+
+    fn spending(address: &str) -> (Transfer, LedgerOutput) {
+        let output = LedgerOutput {
+            address: address.to_string(),
+            value: 50_000,
+            height: 10,
+        };
+        let transfer = Transfer {
+            version: 1,
+            inputs: vec![TransferInput {
+                prevout: OutPoint { txid: [1; 32], vout: 0 },
+                amount: output.value,
+                address: output.address.clone(),
+            }],
+            outputs: vec![TransferOutput {
+                address: "bcrt1pdest".to_string(),
+                value: 49_000,
+            }],
+            fee: 1_000,
+            lock_height: 0,
+        };
+        (transfer, output)
+    }
+
+    #[test]
+    fn test_key_path_witness() {
+        let secp = Secp256k1::new();
+        let address = p2tr_address_for_key(&secret(3).public_key(&secp), Network::Regtest);
+        let (transfer, output) = spending(&address);
+        let witness = sign_key_path(&transfer, 0, &secret(3), SighashType::ALL, Network::Regtest).unwrap();
+        let signed = SignedTransfer {
+            transfer: transfer.clone(),
+            witnesses: vec![witness.clone()],
+        };
+        signed.verify(std::slice::from_ref(&output), 11, Network::Regtest).unwrap();
+        assert!(signed.verify(std::slice::from_ref(&output), 11, Network::Testnet).is_err(), "other network");
+
+        // Another key, a changed transfer, or a missing witness fail
+        let wrong = sign_key_path(&transfer, 0, &secret(4), SighashType::ALL, Network::Regtest).unwrap();
+        let forged = SignedTransfer {
+            transfer: transfer.clone(),
+            witnesses: vec![wrong],
+        };
+        assert!(forged.verify(std::slice::from_ref(&output), 11, Network::Regtest).is_err());
+        let mut changed = signed.clone();
+        changed.transfer.outputs[0].address = "bcrt1pthief".to_string();
+        assert!(changed.verify(std::slice::from_ref(&output), 11, Network::Regtest).is_err());
+        let unsigned = SignedTransfer {
+            transfer,
+            witnesses: Vec::new(),
+        };
+        assert!(unsigned.verify(&[output], 11, Network::Regtest).is_err());
+    }
+
+    #[test]
+    fn test_vault_leaf_witnesses() {
+        let secp = Secp256k1::new();
+        let hot = secret(1).x_only_public_key(&secp).0;
+        let recovery = secret(2).x_only_public_key(&secp).0;
+        let info = vault_spend_info(&hot, &recovery, 5).unwrap();
+        let address = Address::p2tr_tweaked(info.output_key(), Network::Regtest).to_string();
+        let (transfer, output) = spending(&address);
+        let witness = |key: u8, leaf: bitcoin::ScriptBuf| {
+            let control_block = info.control_block(&(leaf.clone(), LeafVersion::TapScript)).unwrap();
+            let witness =
+                sign_script_path(&transfer, 0, &secret(key), &leaf, &control_block, SighashType::ALL, Network::Regtest);
+            SignedTransfer {
+                transfer: transfer.clone(),
+                witnesses: vec![witness.unwrap()],
+            }
+        };
+
+        // The recovery key spends at once; the hot key only after the delay
+        let recovered = witness(2, recovery_leaf(&recovery));
+        recovered.verify(std::slice::from_ref(&output), 11, Network::Regtest).unwrap();
+        let delayed = witness(1, delayed_leaf(&hot, 5));
+        let error = delayed.verify(std::slice::from_ref(&output), 14, Network::Regtest).unwrap_err();
+        assert!(format!("{:#}", error).contains("unlocks at height 15"), "{:#}", error);
+        delayed.verify(std::slice::from_ref(&output), 15, Network::Regtest).unwrap();
+
+        // A leaf signed by the wrong key, or not in the tree, fails
+        let wrong_key = witness(1, recovery_leaf(&recovery));
+        assert!(wrong_key.verify(std::slice::from_ref(&output), 15, Network::Regtest).is_err());
+        let foreign = recovery_leaf(&hot);
+        let control_block = info.control_block(&(recovery_leaf(&recovery), LeafVersion::TapScript)).unwrap();
+        let witnesses = vec![
+            sign_script_path(&transfer, 0, &secret(1), &foreign, &control_block, SighashType::ALL, Network::Regtest)
+                .unwrap(),
+        ];
+        let foreign = SignedTransfer { transfer, witnesses };
+        assert!(foreign.verify(&[output], 15, Network::Regtest).is_err());
+    }
+}
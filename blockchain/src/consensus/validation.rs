@@ -0,0 +1,144 @@
+//! Named block validation stages and their timing metrics
+
+use crate::metrics::{Histogram, HistogramSnapshot};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Blocks taking longer than this to validate are logged with a
+/// per-stage breakdown
+pub const SLOW_BLOCK_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Stages of `ConsensusEngine::validate_block`, in execution order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValidationStage {
+    /// Parent link, timestamp and block size checks
+    ContextualHeader,
+    /// Merkle root recomputation
+    Merkle,
+    /// Forge signature checks
+    Signatures,
+    /// Proof-of-forge re-derivation for every forge
+    ProofOfForge,
+    /// Transfer inputs against the ledger, and their witnesses
+    Transfers,
+    /// Lock heights and other inclusion rules
+    Policy,
+    /// Post-block state root commitment
+    StateRoot,
+}
+
+impl ValidationStage {
+    /// All stages in execution order
+    pub const ALL: [ValidationStage; 7] = [
+        ValidationStage::ContextualHeader,
+        ValidationStage::Merkle,
+        ValidationStage::Signatures,
+        ValidationStage::ProofOfForge,
+        ValidationStage::Transfers,
+        ValidationStage::Policy,
+        ValidationStage::StateRoot,
+    ];
+
+    /// Stable name used in logs and RPC output
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValidationStage::ContextualHeader => "contextual_header",
+            ValidationStage::Merkle => "merkle",
+            ValidationStage::Signatures => "signatures",
+            ValidationStage::ProofOfForge => "proof_of_forge",
+            ValidationStage::Transfers => "transfers",
+            ValidationStage::Policy => "policy",
+            ValidationStage::StateRoot => "state_root",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl fmt::Display for ValidationStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Per-stage validation time histograms (microseconds)
+#[derive(Debug)]
+pub struct ValidationMetrics {
+    stages: [Histogram; 7],
+}
+
+impl Default for ValidationMetrics {
+    fn default() -> Self {
+        Self {
+            stages: ValidationStage::ALL.map(|_| Histogram::latency()),
+        }
+    }
+}
+
+impl ValidationMetrics {
+    /// Record how long a stage took
+    pub fn record(&self, stage: ValidationStage, elapsed: Duration) {
+        self.stages[stage.index()].observe_duration(elapsed);
+    }
+
+    /// Snapshot of one stage's histogram
+    pub fn stage(&self, stage: ValidationStage) -> HistogramSnapshot {
+        self.stages[stage.index()].snapshot()
+    }
+
+    /// Snapshots of all stages, in execution order
+    pub fn snapshot(&self) -> Vec<(ValidationStage, HistogramSnapshot)> {
+        ValidationStage::ALL
+            .iter()
+            .map(|stage| (*stage, self.stage(*stage)))
+            .collect()
+    }
+}
+
+/// Times the stages of a single block validation, logging a breakdown
+/// when dropped if the block was slow
+pub(crate) struct BlockValidationTimer<'a> {
+    metrics: &'a ValidationMetrics,
+    height: u64,
+    started: Instant,
+    timings: Vec<(ValidationStage, Duration)>,
+}
+
+impl<'a> BlockValidationTimer<'a> {
+    pub(crate) fn new(metrics: &'a ValidationMetrics, height: u64) -> Self {
+        Self {
+            metrics,
+            height,
+            started: Instant::now(),
+            timings: Vec::with_capacity(ValidationStage::ALL.len()),
+        }
+    }
+
+    /// Run one stage, recording its time whether it passes or fails
+    pub(crate) fn stage<T>(&mut self, stage: ValidationStage, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        self.metrics.record(stage, elapsed);
+        self.timings.push((stage, elapsed));
+        result
+    }
+}
+
+impl Drop for BlockValidationTimer<'_> {
+    fn drop(&mut self) {
+        let total = self.started.elapsed();
+        if total < SLOW_BLOCK_THRESHOLD {
+            return;
+        }
+        let breakdown = self
+            .timings
+            .iter()
+            .map(|(stage, elapsed)| format!("{}={:?}", stage, elapsed))
+            .collect::<Vec<_>>()
+            .join(" ");
+        tracing::debug!("Slow block {} validated in {:?}: {}", self.height, total, breakdown);
+    }
+}
@@ -0,0 +1,170 @@
+//! BIP-39 mnemonic interop for prophecy words
+//!
+//! A prophecy is a BIP-39 mnemonic of 12 or 24 English words followed by
+//! one axiom word. The canonical prophecy is 12 wordlist words and the axiom
+//! "question", though its words don't carry a valid BIP-39 checksum, so
+//! checksums are verified only on request. A wallet's standard mnemonic can
+//! be turned into a prophecy with `prophecy_from_mnemonic` and back into its
+//! entropy with `prophecy_to_entropy`.
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+
+/// The BIP-39 English wordlist, one word per line. Its SHA-256 is
+/// `2f5eed53a4727b4bf8880d8f3f199efc90e58503646d9ff8eff3a2ed3b24dbda`.
+const BIP39_ENGLISH: &str = include_str!("bip39_english.txt");
+
+/// Mnemonic lengths a prophecy may be built from
+pub const MNEMONIC_WORD_COUNTS: [usize; 2] = [12, 24];
+
+/// Bits each mnemonic word encodes
+const BITS_PER_WORD: usize = 11;
+
+/// The 2048 BIP-39 English words, in wordlist (alphabetical) order
+pub fn bip39_wordlist() -> &'static [&'static str] {
+    static WORDS: OnceLock<Vec<&'static str>> = OnceLock::new();
+    WORDS.get_or_init(|| BIP39_ENGLISH.lines().collect())
+}
+
+/// Index of `word` in the BIP-39 English wordlist
+pub fn bip39_word_index(word: &str) -> Option<usize> {
+    bip39_wordlist().binary_search(&word).ok()
+}
+
+/// Mnemonic encoding 16 or 32 bytes of entropy
+pub fn entropy_to_mnemonic(entropy: &[u8]) -> Result<Vec<&'static str>> {
+    if entropy.len() != 16 && entropy.len() != 32 {
+        return Err(anyhow!("Mnemonic entropy must be 16 or 32 bytes, got {}", entropy.len()));
+    }
+    let checksum = Sha256::digest(entropy)[0];
+    let mut bits: Vec<bool> = entropy
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1 == 1))
+        .collect();
+    let checksum_bits = entropy.len() / 4;
+    bits.extend((0..checksum_bits).map(|bit| (checksum >> (7 - bit)) & 1 == 1));
+
+    Ok(bits
+        .chunks(BITS_PER_WORD)
+        .map(|chunk| bip39_wordlist()[chunk.iter().fold(0, |index, &bit| (index << 1) | usize::from(bit))])
+        .collect())
+}
+
+/// Build a prophecy from a 12- or 24-word BIP-39 mnemonic and one axiom
+/// word. Every mnemonic word must be in the wordlist; with
+/// `verify_checksum` the mnemonic's checksum must also match.
+pub fn prophecy_from_mnemonic(mnemonic: &str, axiom: &str, verify_checksum: bool) -> Result<Vec<String>> {
+    let words: Vec<&str> = mnemonic.split_whitespace().collect();
+    mnemonic_entropy(&words, verify_checksum)?;
+    let axiom = axiom.trim();
+    if axiom.is_empty() || axiom.contains(char::is_whitespace) {
+        return Err(anyhow!("Axiom must be a single word"));
+    }
+    Ok(words.into_iter().chain([axiom]).map(str::to_string).collect())
+}
+
+/// Entropy of the mnemonic a prophecy was built from: every word but the
+/// trailing axiom. With `verify_checksum` the mnemonic's checksum must
+/// match.
+pub fn prophecy_to_entropy(prophecy_words: &[String], verify_checksum: bool) -> Result<Vec<u8>> {
+    let (_axiom, mnemonic) = prophecy_words
+        .split_last()
+        .ok_or_else(|| anyhow!("Prophecy is empty"))?;
+    let words: Vec<&str> = mnemonic.iter().map(String::as_str).collect();
+    mnemonic_entropy(&words, verify_checksum)
+}
+
+/// Check `words` form a prophecy: a 12- or 24-word mnemonic of wordlist
+/// words followed by one axiom word. Checksums aren't required, so the
+/// canonical prophecy is one.
+pub fn check_prophecy_words(prophecy_words: &[String]) -> Result<()> {
+    prophecy_to_entropy(prophecy_words, false).map(|_| ())
+}
+
+/// Decode mnemonic words to their entropy
+fn mnemonic_entropy(words: &[&str], verify_checksum: bool) -> Result<Vec<u8>> {
+    if !MNEMONIC_WORD_COUNTS.contains(&words.len()) {
+        return Err(anyhow!("Mnemonic must have 12 or 24 words, got {}", words.len()));
+    }
+    let mut bits = Vec::with_capacity(words.len() * BITS_PER_WORD);
+    for (position, word) in words.iter().enumerate() {
+        let index = bip39_word_index(word)
+            .ok_or_else(|| anyhow!("Word {} ({:?}) is not in the BIP-39 wordlist", position + 1, word))?;
+        bits.extend((0..BITS_PER_WORD).rev().map(|bit| (index >> bit) & 1 == 1));
+    }
+
+    let checksum_bits = bits.len() / 33;
+    let (entropy_bits, checksum) = bits.split_at(bits.len() - checksum_bits);
+    let entropy: Vec<u8> = entropy_bits
+        .chunks(8)
+        .map(|chunk| chunk.iter().fold(0u8, |byte, &bit| (byte << 1) | u8::from(bit)))
+        .collect();
+    if verify_checksum {
+        let expected = Sha256::digest(&entropy)[0];
+        let valid = checksum
+            .iter()
+            .enumerate()
+            .all(|(bit, &set)| ((expected >> (7 - bit)) & 1 == 1) == set);
+        if !valid {
+            return Err(anyhow!("Mnemonic checksum mismatch"));
+        }
+    }
+    Ok(entropy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{proof_of_forge, CANONICAL_PROPHECY};
+    use bitcoin::Network;
+
+    #[test]
+    fn test_bip39_vectors() {
+        let list = bip39_wordlist();
+        assert_eq!(list.len(), 2048);
+        assert!(list.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(hex::encode(Sha256::digest(BIP39_ENGLISH)), "2f5eed53a4727b4bf8880d8f3f199efc90e58503646d9ff8eff3a2ed3b24dbda");
+
+        // Vectors from the BIP-39 reference test suite
+        let vectors = [
+            ("00000000000000000000000000000000", "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"),
+            ("7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f", "legal winner thank year wave sausage worth useful legal winner thank yellow"),
+            ("ffffffffffffffffffffffffffffffff", "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong"),
+            (
+                "8080808080808080808080808080808080808080808080808080808080808080",
+                "letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic bless",
+            ),
+        ];
+        for (entropy, mnemonic) in vectors {
+            let entropy = hex::decode(entropy).unwrap();
+            assert_eq!(entropy_to_mnemonic(&entropy).unwrap().join(" "), mnemonic);
+            let prophecy = prophecy_from_mnemonic(mnemonic, "excalibur", true).unwrap();
+            assert_eq!(prophecy.last().unwrap(), "excalibur");
+            assert_eq!(prophecy_to_entropy(&prophecy, true).unwrap(), entropy);
+        }
+    }
+
+    #[test]
+    fn test_prophecy_words_and_checksums() {
+        // The canonical prophecy is wordlist words without a valid checksum
+        let canonical: Vec<String> = CANONICAL_PROPHECY.iter().map(|s| s.to_string()).collect();
+        assert_eq!(hex::encode(prophecy_to_entropy(&canonical, false).unwrap()), "dc4ff2b542e7aa1a3591e15b4ae754b6");
+        assert!(prophecy_to_entropy(&canonical, true).unwrap_err().to_string().contains("checksum"));
+        let mnemonic = CANONICAL_PROPHECY[..12].join(" ");
+        assert_eq!(prophecy_from_mnemonic(&mnemonic, "question", false).unwrap(), canonical);
+
+        let zoo = "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo";
+        assert!(prophecy_from_mnemonic(zoo, "axiom", true).is_err());
+        assert!(prophecy_from_mnemonic("zoo zoo zoo", "axiom", false).is_err());
+        let unknown = prophecy_from_mnemonic(&zoo.replacen("zoo zoo", "zoo excalibur", 1), "axiom", false);
+        assert!(unknown.unwrap_err().to_string().contains("Word 2"));
+        assert!(prophecy_from_mnemonic(zoo, "two words", false).is_err());
+
+        // A 24-word mnemonic forges like the 13-word prophecy
+        let mnemonic = entropy_to_mnemonic(&[0x80; 32]).unwrap().join(" ");
+        let prophecy = prophecy_from_mnemonic(&mnemonic, "excalibur", true).unwrap();
+        assert_eq!(prophecy.len(), 25);
+        assert!(proof_of_forge(&prophecy, None, Network::Regtest).is_ok());
+    }
+}
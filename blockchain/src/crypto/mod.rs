@@ -3,17 +3,38 @@
 //! This module implements the complete Proof-of-Forge pipeline:
 //! 1. Prophecy Binding: SHA-512 of concatenated words
 //! 2. Tetra-POW: 128 rounds of nonlinear state transformation
-//! 3. PBKDF2 Tempering: 600,000 iterations for quantum hardening
+//! 3. Tempering: 600,000 PBKDF2 iterations for quantum hardening, or
+//!    memory-hard Argon2id
 //! 4. Zetahash Pythagoras: Sacred geometric transformation
 //! 5. Taproot Derivation: BIP-340/341 address generation
 
 use anyhow::{Context, Result};
-use bitcoin::secp256k1::{Secp256k1, SecretKey, PublicKey};
+use bitcoin::key::{TapTweak, TweakedPublicKey};
+use bitcoin::secp256k1::{schnorr, Keypair, Message, Secp256k1, SecretKey, PublicKey, XOnlyPublicKey};
 use bitcoin::Address;
 use bitcoin::Network;
+use hmac::{Hmac, Mac};
 use pbkdf2::pbkdf2_hmac;
 use sha2::{Sha256, Sha512, Digest};
-use std::convert::TryInto;
+use std::fmt;
+use zeroize::{Zeroize, Zeroizing};
+
+mod mnemonic;
+pub mod musig;
+mod progress;
+mod tempering;
+pub mod test_vectors;
+mod tetra;
+mod zetahash;
+
+pub use mnemonic::{
+    bip39_word_index, bip39_wordlist, check_prophecy_words, entropy_to_mnemonic, prophecy_from_mnemonic,
+    prophecy_to_entropy, MNEMONIC_WORD_COUNTS,
+};
+pub use progress::{CancelToken, ForgeCancelled, ForgeStage};
+pub use tempering::{TemperingAlgorithm, MAX_ARGON2_ITERATIONS, MAX_ARGON2_MEMORY_KIB, MAX_ARGON2_PARALLELISM};
+pub use tetra::{tetra_pow_batch, TetraPow, PARALLEL_TETRA_THRESHOLD};
+pub use zetahash::final_zetahash_pythagoras;
 
 /// The canonical 13-word prophecy axiom
 pub const CANONICAL_PROPHECY: [&str; 13] = [
@@ -27,68 +48,97 @@ pub const TETRA_POW_ROUNDS: usize = 128;
 /// Number of PBKDF2 iterations for quantum hardening (600,000)
 pub const HPP1_ITERATIONS: u32 = 600_000;
 
+/// Tempering iterations between progress reports
+pub const TEMPERING_PROGRESS_INTERVAL: u32 = 10_000;
+
+/// Tempering salt used when a forge doesn't set one
+const DEFAULT_TEMPERING_SALT: &[u8] = b"Excalibur-EXS-Forge";
+
 /// Result of the complete Proof-of-Forge derivation
-#[derive(Debug, Clone)]
+///
+/// `tempered_key` and `final_seed` are wiped from memory when the result
+/// is dropped. Neither `Debug` nor `redacted()` prints them in full.
+#[derive(Clone)]
 pub struct ProofOfForgeResult {
     pub prophecy_hash: Vec<u8>,
     pub tetra_hash: Vec<u8>,
-    pub tempered_key: Vec<u8>,
-    pub final_seed: Vec<u8>,
+    pub tempered_key: Zeroizing<Vec<u8>>,
+    pub final_seed: Zeroizing<Vec<u8>>,
+    /// Compressed public key of the final seed (a forge's `derived_key`)
+    pub public_key: [u8; 33],
+    /// Untweaked x-only key derived from the final seed
+    pub internal_key: [u8; 32],
+    /// BIP-341 tweaked x-only output key the address pays to
+    pub output_key: [u8; 32],
+    /// Bech32m encoding of `output_key`
     pub taproot_address: String,
+    /// Algorithm that produced `tempered_key`
+    pub tempering: TemperingAlgorithm,
 }
 
-/// Tetra-POW state for 128-round nonlinear transformation
-#[derive(Debug, Clone)]
-struct TetraPoWState {
-    state: [u64; 4],
+impl ProofOfForgeResult {
+    /// Display form safe for logs: public keys and the address in full,
+    /// secrets only as a short prefix
+    pub fn redacted(&self) -> RedactedForge<'_> {
+        RedactedForge(self)
+    }
 }
 
-impl TetraPoWState {
-    /// Create new Tetra-POW state from seed
-    fn new(seed: &[u8]) -> Self {
-        let mut state = [0u64; 4];
-        if seed.len() >= 32 {
-            state[0] = u64::from_le_bytes(seed[0..8].try_into().unwrap());
-            state[1] = u64::from_le_bytes(seed[8..16].try_into().unwrap());
-            state[2] = u64::from_le_bytes(seed[16..24].try_into().unwrap());
-            state[3] = u64::from_le_bytes(seed[24..32].try_into().unwrap());
-        }
-        Self { state }
+impl fmt::Debug for ProofOfForgeResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProofOfForgeResult")
+            .field("prophecy_hash", &Redacted(&self.prophecy_hash))
+            .field("tetra_hash", &Redacted(&self.tetra_hash))
+            .field("tempered_key", &Redacted(&self.tempered_key))
+            .field("final_seed", &Redacted(&self.final_seed))
+            .field("public_key", &hex::encode(self.public_key))
+            .field("internal_key", &hex::encode(self.internal_key))
+            .field("output_key", &hex::encode(self.output_key))
+            .field("taproot_address", &self.taproot_address)
+            .field("tempering", &self.tempering)
+            .finish()
     }
+}
+
+/// Bytes shown as their first four bytes and length
+struct Redacted<'a>(&'a [u8]);
 
-    /// Perform a single nonlinear state shift
-    fn round(&mut self) {
-        // Nonlinear mixing using bitwise operations
-        self.state[0] ^= (self.state[1] << 13) ^ (self.state[3] >> 7);
-        self.state[1] ^= (self.state[2] << 17) ^ (self.state[0] >> 5);
-        self.state[2] ^= (self.state[3] << 23) ^ (self.state[1] >> 11);
-        self.state[3] ^= (self.state[0] << 29) ^ (self.state[2] >> 3);
-
-        // Add entropy (mathematical constants)
-        self.state[0] = self.state[0].wrapping_add(0x9E3779B97F4A7C15);
-        self.state[1] = self.state[1].wrapping_add(0x243F6A8885A308D3);
-        self.state[2] = self.state[2].wrapping_add(0x13198A2E03707344);
-        self.state[3] = self.state[3].wrapping_add(0xA4093822299F31D0);
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shown = self.0.len().min(4);
+        write!(f, "{}… ({} bytes)", hex::encode(&self.0[..shown]), self.0.len())
     }
+}
 
-    /// Perform 128 rounds of Tetra-POW
-    fn compute(&mut self) -> Vec<u8> {
-        for _ in 0..TETRA_POW_ROUNDS {
-            self.round();
-        }
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
 
-        let mut result = Vec::with_capacity(32);
-        for val in &self.state {
-            result.extend_from_slice(&val.to_le_bytes());
-        }
-        result
+/// `ProofOfForgeResult` displayed without its secrets
+pub struct RedactedForge<'a>(&'a ProofOfForgeResult);
+
+impl fmt::Display for RedactedForge<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let result = self.0;
+        write!(
+            f,
+            "{} (key {}, {}, tempered key {}, seed {})",
+            result.taproot_address,
+            hex::encode(result.public_key),
+            result.tempering.name(),
+            Redacted(&result.tempered_key),
+            Redacted(&result.final_seed),
+        )
     }
 }
 
-/// Step 1: Prophecy Binding - SHA-512 of concatenated prophecy words
+/// Step 1: Prophecy Binding - SHA-512 of concatenated prophecy words.
+/// A prophecy has 13 words, or 25 when built from a 24-word mnemonic.
 pub fn prophecy_binding(prophecy_words: &[String]) -> Result<Vec<u8>> {
-    if prophecy_words.len() != 13 {
-        anyhow::bail!("Prophecy must contain exactly 13 words");
+    if prophecy_words.len() != 13 && prophecy_words.len() != 25 {
+        anyhow::bail!("Prophecy must contain exactly 13 or 25 words");
     }
 
     let concatenated = prophecy_words.join("");
@@ -99,93 +149,246 @@ pub fn prophecy_binding(prophecy_words: &[String]) -> Result<Vec<u8>> {
 
 /// Step 2: Tetra-POW - 128 rounds of nonlinear transformation
 pub fn tetra_pow_128_rounds(prophecy_hash: &[u8]) -> Vec<u8> {
-    let mut state = TetraPoWState::new(prophecy_hash);
-    state.compute()
+    TetraPow::default().hash(prophecy_hash)
 }
 
 /// Step 3: PBKDF2 Tempering - 600,000 iterations for quantum hardening
 pub fn pbkdf2_tempering(tetra_hash: &[u8], salt: Option<&[u8]>) -> Vec<u8> {
-    let default_salt = b"Excalibur-EXS-Forge";
-    let salt = salt.unwrap_or(default_salt);
+    let salt = salt.unwrap_or(DEFAULT_TEMPERING_SALT);
 
     let mut output = vec![0u8; 64];
     pbkdf2_hmac::<Sha512>(tetra_hash, salt, HPP1_ITERATIONS, &mut output);
     output
 }
 
-/// Step 4: Final Zetahash Pythagoras - Sacred geometric transformation
-pub fn final_zetahash_pythagoras(tempered_key: &[u8]) -> Vec<u8> {
-    // Pythagorean ratios (sacred geometry)
-    let ratios = [
-        1.0,              // Unity
-        1.618033988749895, // Golden Ratio (φ)
-        1.414213562373095, // √2
-        1.732050807568877, // √3
-        2.0,              // Octave
-        0.75,             // Perfect Fourth (3:4)
-        0.8,              // Perfect Fifth (4:5)
-        1.25,             // Major Third (5:4)
-    ];
-
-    let mut result = vec![0u8; 32];
-
-    // Process tempered_key in 8-byte chunks
-    for i in 0..4 {
-        let offset = i * 8;
-        if offset + 8 > tempered_key.len() {
-            break;
-        }
+/// Step 3 with progress: `progress(done, total)` is called every
+/// `TEMPERING_PROGRESS_INTERVAL` iterations, the last call at completion.
+/// The output is the same as `pbkdf2_tempering`.
+pub fn pbkdf2_tempering_with_progress<F: FnMut(u32, u32)>(
+    tetra_hash: &[u8],
+    salt: Option<&[u8]>,
+    progress: F,
+) -> Vec<u8> {
+    temper(tetra_hash, salt.unwrap_or(DEFAULT_TEMPERING_SALT), HPP1_ITERATIONS, progress, &CancelToken::new())
+        .expect("tempering without a cancel request runs to completion")
+}
 
-        // Extract 64-bit value
-        let value = u64::from_le_bytes(tempered_key[offset..offset + 8].try_into().unwrap());
+/// Step 3 on tokio's blocking pool, so forging from async code doesn't
+/// stall the runtime for the length of 600,000 iterations
+pub async fn pbkdf2_tempering_async<F>(tetra_hash: Vec<u8>, salt: Option<Vec<u8>>, progress: F) -> Result<Vec<u8>>
+where
+    F: FnMut(u32, u32) + Send + 'static,
+{
+    let tempered = tokio::task::spawn_blocking(move || {
+        pbkdf2_tempering_with_progress(&tetra_hash, salt.as_deref(), progress)
+    })
+    .await?;
+    Ok(tempered)
+}
 
-        // Apply Pythagorean ratio transformation
-        let ratio = ratios[i % ratios.len()];
-        let transformed = (value as f64 * ratio) as u64;
+/// PBKDF2-HMAC-SHA512 with a 64-byte output. That is a single PBKDF2 block,
+/// so the iterations are run here one by one to report progress. `cancel`
+/// is checked with each report; None if it stopped the tempering.
+fn temper<F: FnMut(u32, u32)>(
+    tetra_hash: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    mut progress: F,
+    cancel: &CancelToken,
+) -> Option<Vec<u8>> {
+    let prf = Hmac::<Sha512>::new_from_slice(tetra_hash).expect("HMAC accepts keys of any length");
+    let mut mac = prf.clone();
+    mac.update(salt);
+    mac.update(&1u32.to_be_bytes());
+    let mut block = mac.finalize().into_bytes();
+    let mut output = block.to_vec();
+    for iteration in 2..=iterations {
+        let mut mac = prf.clone();
+        mac.update(&block);
+        block = mac.finalize().into_bytes();
+        output.iter_mut().zip(block.iter()).for_each(|(out, byte)| *out ^= byte);
+        if iteration % TEMPERING_PROGRESS_INTERVAL == 0 || iteration == iterations {
+            if cancel.is_cancelled() {
+                block.as_mut_slice().zeroize();
+                output.zeroize();
+                return None;
+            }
+            progress(iteration, iterations);
+        }
+    }
+    block.as_mut_slice().zeroize();
+    Some(output)
+}
 
-        // Mix with SHA-256 for additional entropy
-        let mut mix_data = Vec::with_capacity(16);
-        mix_data.extend_from_slice(&value.to_le_bytes());
-        mix_data.extend_from_slice(&transformed.to_le_bytes());
+/// Key-path-only Taproot output (BIP-86): the internal key, the output key
+/// it is tweaked to, and the address paying to the output key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaprootOutput {
+    pub internal_key: XOnlyPublicKey,
+    pub output_key: TweakedPublicKey,
+}
 
-        let mut hasher = Sha256::new();
-        hasher.update(&mix_data);
-        let hash = hasher.finalize();
+impl TaprootOutput {
+    /// Tweak a public key per BIP-341 with no script tree:
+    /// `Q = P + H_TapTweak(P)·G`, with `P` the key's even-y x-only form
+    pub fn for_key(public_key: &PublicKey) -> Self {
+        let secp = Secp256k1::verification_only();
+        let (internal_key, _) = public_key.x_only_public_key();
+        let (output_key, _) = internal_key.tap_tweak(&secp, None);
+        Self {
+            internal_key,
+            output_key,
+        }
+    }
 
-        // Place in result
-        result[i * 8..(i + 1) * 8].copy_from_slice(&hash[0..8]);
+    /// Bech32m address for the output key
+    pub fn address(&self, network: Network) -> String {
+        Address::p2tr_tweaked(self.output_key, network).to_string()
     }
+}
 
-    result
+/// Step 5: Taproot Derivation - BIP-86 key-path P2TR output for the key
+/// derived from the final seed
+pub fn derive_taproot_output(final_seed: &[u8]) -> Result<TaprootOutput> {
+    Ok(TaprootOutput::for_key(&derive_public_key(final_seed)?))
 }
 
-/// Step 5: Taproot Address Derivation (simplified for demonstration)
-/// In production, use proper BIP-340/341 implementation
+/// Bech32m P2TR address of `derive_taproot_output`
 pub fn derive_taproot_address(final_seed: &[u8], network: Network) -> Result<String> {
-    // For production, implement proper Taproot derivation with BIP-340/341
-    // This is a simplified version for demonstration
-    
-    let secp = Secp256k1::new();
-    
-    // Derive private key from final seed
-    let secret_key = SecretKey::from_slice(&final_seed[..32])
-        .context("Failed to create secret key")?;
-    
-    let public_key = PublicKey::from_secret_key(&secp, &secret_key);
-    
-    // Create Taproot address (P2TR)
-    // In production, use proper Taproot construction
-    let address = Address::p2wpkh(&bitcoin::PublicKey::new(public_key), network)
+    Ok(derive_taproot_output(final_seed)?.address(network))
+}
+
+/// Address emitted by releases before the P2TR fix: a P2WPKH output
+/// labelled as Taproot. Kept for auditing and migration only.
+pub fn derive_legacy_p2wpkh_address(final_seed: &[u8], network: Network) -> Result<String> {
+    let public_key = derive_public_key(final_seed)?;
+    legacy_address_for_key(&public_key, network)
+}
+
+/// BIP-86 key-path P2TR address for a public key
+pub fn p2tr_address_for_key(public_key: &PublicKey, network: Network) -> String {
+    TaprootOutput::for_key(public_key).address(network)
+}
+
+/// Legacy P2WPKH address for a public key
+pub fn legacy_address_for_key(public_key: &PublicKey, network: Network) -> Result<String> {
+    let address = Address::p2wpkh(&bitcoin::PublicKey::new(*public_key), network)
         .context("Failed to create address")?;
-    
     Ok(address.to_string())
 }
 
+/// Whether an address has the legacy P2WPKH form (witness v0 key hash)
+pub fn is_legacy_forge_address(address: &str) -> bool {
+    address
+        .parse::<Address<bitcoin::address::NetworkUnchecked>>()
+        .map(|address| address.assume_checked().address_type() == Some(bitcoin::AddressType::P2wpkh))
+        .unwrap_or(false)
+}
+
+/// Legacy and corrected addresses for the same derived key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivedAddresses {
+    pub legacy_p2wpkh: String,
+    pub p2tr: String,
+}
+
+impl DerivedAddresses {
+    /// Both address forms for a public key
+    pub fn for_key(public_key: &PublicKey, network: Network) -> Result<Self> {
+        Ok(Self {
+            legacy_p2wpkh: legacy_address_for_key(public_key, network)?,
+            p2tr: p2tr_address_for_key(public_key, network),
+        })
+    }
+
+    /// Both address forms for a final seed
+    pub fn for_seed(final_seed: &[u8], network: Network) -> Result<Self> {
+        Self::for_key(&derive_public_key(final_seed)?, network)
+    }
+}
+
+/// Secret key derived from the final seed (its first 32 bytes)
+pub fn derive_secret_key(final_seed: &[u8]) -> Result<SecretKey> {
+    if final_seed.len() < 32 {
+        anyhow::bail!("Final seed must be at least 32 bytes");
+    }
+    SecretKey::from_slice(&final_seed[..32]).context("Failed to create secret key")
+}
+
+/// Compressed public key for the key derived from the final seed
+pub fn derive_public_key(final_seed: &[u8]) -> Result<PublicKey> {
+    let secp = Secp256k1::new();
+    Ok(PublicKey::from_secret_key(&secp, &derive_secret_key(final_seed)?))
+}
+
+/// BIP-340 Schnorr signature of `sighash` by the key-path spending key of
+/// the Taproot output derived from `final_seed`, i.e. the secret key
+/// tweaked per BIP-341 so it matches the output key the address pays to
+pub fn sign_taproot_key_path(final_seed: &[u8], sighash: &[u8; 32]) -> Result<[u8; 64]> {
+    let secp = Secp256k1::new();
+    let keypair = Keypair::from_secret_key(&secp, &derive_secret_key(final_seed)?);
+    let tweaked = keypair.tap_tweak(&secp, None).to_inner();
+    let aux_rand: [u8; 32] = rand::random();
+    Ok(secp
+        .sign_schnorr_with_aux_rand(&Message::from_digest(*sighash), &tweaked, &aux_rand)
+        .serialize())
+}
+
+/// Check a BIP-340 signature of `sighash` against the Taproot output key
+/// of `public_key`
+pub fn verify_taproot_key_path(public_key: &PublicKey, sighash: &[u8; 32], signature: &[u8]) -> Result<()> {
+    let signature = schnorr::Signature::from_slice(signature).context("Malformed Schnorr signature")?;
+    let output_key = TaprootOutput::for_key(public_key).output_key.to_inner();
+    Secp256k1::verification_only()
+        .verify_schnorr(&signature, &Message::from_digest(*sighash), &output_key)
+        .map_err(|_| anyhow::anyhow!("Invalid Schnorr signature for the Taproot output key"))
+}
+
+/// Registry key for a prophecy: SHA-256 of its words concatenated as
+/// `prophecy_binding` concatenates them. Prophecies that only differ in
+/// whitespace or in where one word ends and the next begins derive the
+/// same binding, so they share a key and can't forge it twice.
+pub fn prophecy_registry_hash(prophecy: &str) -> [u8; 32] {
+    let concatenated: String = prophecy.split_whitespace().collect();
+    Sha256::digest(concatenated.as_bytes()).into()
+}
+
+/// Salt a forge is tempered with: its own salt bound to the forger's public
+/// key, so the work behind a forge can't be claimed under another key
+pub fn forge_tempering_salt(forger_key: &[u8], salt: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"ExcaliburForge/salt");
+    hasher.update(forger_key);
+    hasher.update(salt);
+    hasher.finalize().into()
+}
+
+/// Proof hash committing to every stage of a proof-of-forge derivation
+/// and the per-forge salt it was tempered with
+pub fn forge_proof_hash(result: &ProofOfForgeResult, salt: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(&result.prophecy_hash);
+    hasher.update(&result.tetra_hash);
+    hasher.update(&result.tempered_key);
+    hasher.update(salt);
+    hasher.finalize().into()
+}
+
 /// Complete Proof-of-Forge pipeline
 pub fn proof_of_forge(
     prophecy_words: &[String],
     salt: Option<&[u8]>,
     network: Network,
+) -> Result<ProofOfForgeResult> {
+    proof_of_forge_with_tempering(prophecy_words, salt, network, TemperingAlgorithm::default())
+}
+
+/// Complete Proof-of-Forge pipeline, tempering with `tempering`
+pub fn proof_of_forge_with_tempering(
+    prophecy_words: &[String],
+    salt: Option<&[u8]>,
+    network: Network,
+    tempering: TemperingAlgorithm,
 ) -> Result<ProofOfForgeResult> {
     // Step 1: Prophecy Binding
     let prophecy_hash = prophecy_binding(prophecy_words)?;
@@ -193,36 +396,112 @@ pub fn proof_of_forge(
     // Step 2: Tetra-POW 128 rounds
     let tetra_hash = tetra_pow_128_rounds(&prophecy_hash);
 
-    // Step 3: PBKDF2 Tempering (600k iterations)
-    let tempered_key = pbkdf2_tempering(&tetra_hash, salt);
+    // Step 3: Tempering (PBKDF2 600k iterations unless Argon2id is chosen)
+    let tempered_key = Zeroizing::new(tempering.temper(&tetra_hash, salt)?);
+
+    finish_proof_of_forge(prophecy_hash, tetra_hash, tempered_key, tempering, network)
+}
+
+/// Complete Proof-of-Forge pipeline with tempering on the blocking pool,
+/// reporting its progress as `pbkdf2_tempering_with_progress` does
+pub async fn proof_of_forge_async<F>(
+    prophecy_words: &[String],
+    salt: Option<&[u8]>,
+    network: Network,
+    progress: F,
+) -> Result<ProofOfForgeResult>
+where
+    F: FnMut(u32, u32) + Send + 'static,
+{
+    let prophecy_hash = prophecy_binding(prophecy_words)?;
+    let tetra_hash = tetra_pow_128_rounds(&prophecy_hash);
+    let tempered_key =
+        Zeroizing::new(pbkdf2_tempering_async(tetra_hash.clone(), salt.map(<[u8]>::to_vec), progress).await?);
+    finish_proof_of_forge(prophecy_hash, tetra_hash, tempered_key, TemperingAlgorithm::Pbkdf2Sha512, network)
+}
+
+/// Complete Proof-of-Forge pipeline, tempering with `tempering`, reporting
+/// `progress(stage, percent)` as it goes and stopping with `ForgeCancelled`
+/// once `cancel` is cancelled.
+///
+/// `percent` is the share of the whole derivation done, which is nearly
+/// all tempering: PBKDF2 reports every `TEMPERING_PROGRESS_INTERVAL`
+/// iterations and checks `cancel` as often. Argon2id reports only its start
+/// and end and can't be stopped part way through.
+pub fn proof_of_forge_with_progress<F: FnMut(ForgeStage, u8)>(
+    prophecy_words: &[String],
+    salt: Option<&[u8]>,
+    network: Network,
+    tempering: TemperingAlgorithm,
+    mut progress: F,
+    cancel: &CancelToken,
+) -> Result<ProofOfForgeResult> {
+    let mut checkpoint = |stage: ForgeStage, percent: u8| {
+        if cancel.is_cancelled() {
+            return Err(ForgeCancelled);
+        }
+        progress(stage, percent);
+        Ok(())
+    };
+
+    checkpoint(ForgeStage::ProphecyBinding, 0)?;
+    let prophecy_hash = prophecy_binding(prophecy_words)?;
+
+    checkpoint(ForgeStage::TetraPow, 0)?;
+    let tetra_hash = tetra_pow_128_rounds(&prophecy_hash);
+
+    checkpoint(ForgeStage::Tempering, 0)?;
+    let tempered_key = if tempering.is_pbkdf2() {
+        let report = |done: u32, total: u32| {
+            // Stays below 100 until the derivation is done; `temper` checks
+            // `cancel` itself before each report
+            let percent = (u64::from(done) * 99 / u64::from(total)) as u8;
+            let _ = checkpoint(ForgeStage::Tempering, percent);
+        };
+        let salt = salt.unwrap_or(DEFAULT_TEMPERING_SALT);
+        Zeroizing::new(temper(&tetra_hash, salt, HPP1_ITERATIONS, report, cancel).ok_or(ForgeCancelled)?)
+    } else {
+        Zeroizing::new(tempering.temper(&tetra_hash, salt)?)
+    };
+
+    checkpoint(ForgeStage::Zetahash, 99)?;
+    let result = finish_proof_of_forge(prophecy_hash, tetra_hash, tempered_key, tempering, network)?;
+    checkpoint(ForgeStage::Taproot, 100)?;
+    Ok(result)
+}
 
+/// Steps 4 and 5 of the pipeline
+fn finish_proof_of_forge(
+    prophecy_hash: Vec<u8>,
+    tetra_hash: Vec<u8>,
+    tempered_key: Zeroizing<Vec<u8>>,
+    tempering: TemperingAlgorithm,
+    network: Network,
+) -> Result<ProofOfForgeResult> {
     // Step 4: Final Zetahash Pythagoras
-    let final_seed = final_zetahash_pythagoras(&tempered_key);
+    let final_seed = Zeroizing::new(final_zetahash_pythagoras(&tempered_key));
 
     // Step 5: Taproot Derivation
-    let taproot_address = derive_taproot_address(&final_seed, network)?;
+    let public_key = derive_public_key(&final_seed)?;
+    let taproot = TaprootOutput::for_key(&public_key);
 
     Ok(ProofOfForgeResult {
         prophecy_hash,
         tetra_hash,
         tempered_key,
         final_seed,
-        taproot_address,
+        public_key: public_key.serialize(),
+        internal_key: taproot.internal_key.serialize(),
+        output_key: taproot.output_key.to_inner().serialize(),
+        taproot_address: taproot.address(network),
+        tempering,
     })
 }
 
 /// Calculate dynamic forge fee based on completed forges
 /// Starts at 1 BTC, increases by 0.1 BTC every 10,000 forges, capped at 21 BTC
 pub fn calculate_forge_fee(forges_completed: u64) -> u64 {
-    const BASE_FEE: u64 = 100_000_000; // 1 BTC in satoshis
-    const INCREMENT: u64 = 10_000_000; // 0.1 BTC
-    const INCREMENT_INTERVAL: u64 = 10_000;
-    const MAX_FEE: u64 = 2_100_000_000; // 21 BTC cap
-
-    let increments = forges_completed / INCREMENT_INTERVAL;
-    let fee = BASE_FEE + (increments * INCREMENT);
-
-    fee.min(MAX_FEE)
+    crate::params::FeeSchedule::mainnet().forge_fee(forges_completed)
 }
 
 #[cfg(test)]
@@ -250,6 +529,27 @@ mod tests {
         assert_eq!(output.len(), 64);
     }
 
+    #[tokio::test]
+    async fn test_pbkdf2_tempering_progress() {
+        // Iterating by hand agrees with the pbkdf2 crate
+        let mut expected = vec![0u8; 64];
+        pbkdf2_hmac::<Sha512>(&[1u8; 32], b"salt", 25_000, &mut expected);
+        let mut reports = Vec::new();
+        let report = |done, total| reports.push((done, total));
+        assert_eq!(temper(&[1u8; 32], b"salt", 25_000, report, &CancelToken::new()), Some(expected));
+        assert_eq!(reports, [(10_000, 25_000), (20_000, 25_000), (25_000, 25_000)]);
+
+        let vector = &test_vectors::FORGE_VECTORS[0];
+        let tetra_hash = hex::decode(vector.tetra_hash).unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tempered = pbkdf2_tempering_async(tetra_hash, None, move |done, _| tx.send(done).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(hex::encode(tempered), vector.tempered_key);
+        let reports: Vec<u32> = rx.iter().collect();
+        assert_eq!((reports.len(), reports.last()), (60, Some(&HPP1_ITERATIONS)));
+    }
+
     #[test]
     fn test_zetahash() {
         let input = vec![0u8; 64];
@@ -267,6 +567,130 @@ mod tests {
         assert!(!result.tempered_key.is_empty());
         assert!(!result.final_seed.is_empty());
         assert!(!result.taproot_address.is_empty());
+        assert!(result.taproot_address.starts_with("bc1p"));
+
+        let address: Address = result.taproot_address.parse::<Address<_>>().unwrap().assume_checked();
+        assert_eq!(address.script_pubkey().as_bytes()[2..], result.output_key);
+        assert_ne!(result.output_key, result.internal_key);
+        assert_eq!(result.public_key[1..], result.internal_key);
+    }
+
+    #[test]
+    fn test_proof_of_forge_progress_and_cancel() {
+        let prophecy: Vec<String> = CANONICAL_PROPHECY.iter().map(|s| s.to_string()).collect();
+        let mut reports = Vec::new();
+        let cancel = CancelToken::new();
+        let report = |stage, percent| reports.push((stage, percent));
+        let result =
+            proof_of_forge_with_progress(&prophecy, None, Network::Bitcoin, Default::default(), report, &cancel)
+                .unwrap();
+        assert_eq!(result.taproot_address, proof_of_forge(&prophecy, None, Network::Bitcoin).unwrap().taproot_address);
+        assert_eq!(reports.first(), Some(&(ForgeStage::ProphecyBinding, 0)));
+        assert_eq!(reports.last(), Some(&(ForgeStage::Taproot, 100)));
+        assert!(reports.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+        assert_eq!(reports.iter().filter(|(stage, _)| *stage == ForgeStage::Tempering).count(), 61);
+
+        // Cancelled part way through tempering
+        let mut tempering_reports = 0;
+        let report = |stage, _| {
+            if stage == ForgeStage::Tempering {
+                tempering_reports += 1;
+                if tempering_reports == 3 {
+                    cancel.cancel();
+                }
+            }
+        };
+        let error =
+            proof_of_forge_with_progress(&prophecy, None, Network::Bitcoin, Default::default(), report, &cancel)
+                .unwrap_err();
+        assert!(error.is::<ForgeCancelled>());
+        assert_eq!(tempering_reports, 3);
+    }
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        let prophecy: Vec<String> = CANONICAL_PROPHECY.iter().map(|s| s.to_string()).collect();
+        let result = proof_of_forge_with_tempering(&prophecy, None, Network::Bitcoin, TemperingAlgorithm::argon2id())
+            .unwrap();
+        let shown = [result.redacted().to_string(), format!("{:?}", result)];
+        for text in &shown {
+            for secret in [&result.prophecy_hash, &result.tetra_hash, &*result.tempered_key, &*result.final_seed] {
+                assert!(!text.contains(&hex::encode(secret)), "{}", text);
+            }
+            assert!(text.contains(&format!("{}… (32 bytes)", hex::encode(&result.final_seed[..4]))), "{}", text);
+        }
+        assert!(shown[0].starts_with(&format!("{} (key {}", result.taproot_address, hex::encode(result.public_key))));
+        assert_eq!(Redacted(&[0xab]).to_string(), "ab… (1 bytes)");
+    }
+
+    #[test]
+    fn test_prophecy_registry_hash_follows_binding() {
+        let canonical = CANONICAL_PROPHECY.join(" ");
+        let spaced = CANONICAL_PROPHECY.join("   ");
+        assert_eq!(prophecy_registry_hash(&canonical), prophecy_registry_hash(&spaced));
+        assert_ne!(prophecy_registry_hash(&canonical), prophecy_registry_hash("sword legend"));
+
+        // Splitting the same letters into other words gives the same binding
+        let mut act = CANONICAL_PROPHECY[..11].iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let mut action = act.clone();
+        act.extend(["act".to_string(), "ionX".to_string()]);
+        action.extend(["action".to_string(), "X".to_string()]);
+        assert_eq!(prophecy_binding(&act).unwrap(), prophecy_binding(&action).unwrap());
+        assert_eq!(prophecy_registry_hash(&act.join(" ")), prophecy_registry_hash(&action.join(" ")));
+    }
+
+    #[test]
+    fn test_legacy_and_p2tr_addresses() {
+        let addresses = DerivedAddresses::for_seed(&[7u8; 32], Network::Bitcoin).unwrap();
+        assert!(addresses.legacy_p2wpkh.starts_with("bc1q"));
+        assert!(addresses.p2tr.starts_with("bc1p"));
+        assert_eq!(addresses.p2tr, derive_taproot_address(&[7u8; 32], Network::Bitcoin).unwrap());
+
+        assert!(is_legacy_forge_address(&addresses.legacy_p2wpkh));
+        assert!(!is_legacy_forge_address(&addresses.p2tr));
+        assert!(!is_legacy_forge_address("bc1p..."));
+    }
+
+    #[test]
+    fn test_taproot_output_bip86_vector() {
+        // BIP-86 test vector for m/86'/0'/0'/0/0
+        let internal: PublicKey = "02cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115"
+            .parse()
+            .unwrap();
+        let output = TaprootOutput::for_key(&internal);
+        assert_eq!(
+            hex::encode(output.output_key.to_inner().serialize()),
+            "a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c"
+        );
+        assert_eq!(
+            output.address(Network::Bitcoin),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+
+        // The odd-y form of the same key tweaks to the same output
+        let odd: PublicKey = "03cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115"
+            .parse()
+            .unwrap();
+        assert_eq!(TaprootOutput::for_key(&odd), output);
+    }
+
+    #[test]
+    fn test_taproot_key_path_signature() {
+        let secp = Secp256k1::new();
+        // Seeds whose keys have both y parities
+        for seed in 1u8..=4 {
+            let final_seed = [seed; 32];
+            let public_key = derive_public_key(&final_seed).unwrap();
+            let signature = sign_taproot_key_path(&final_seed, &[9u8; 32]).unwrap();
+            verify_taproot_key_path(&public_key, &[9u8; 32], &signature).unwrap();
+            assert!(verify_taproot_key_path(&public_key, &[8u8; 32], &signature).is_err());
+            assert!(verify_taproot_key_path(&public_key, &[9u8; 32], &signature[..63]).is_err());
+
+            // The untweaked key doesn't control the output
+            let keypair = Keypair::from_secret_key(&secp, &derive_secret_key(&final_seed).unwrap());
+            let untweaked = secp.sign_schnorr_no_aux_rand(&Message::from_digest([9u8; 32]), &keypair);
+            assert!(verify_taproot_key_path(&public_key, &[9u8; 32], &untweaked.serialize()).is_err());
+        }
     }
 
     #[test]
@@ -274,6 +698,8 @@ mod tests {
         assert_eq!(calculate_forge_fee(0), 100_000_000); // 1 BTC
         assert_eq!(calculate_forge_fee(10_000), 110_000_000); // 1.1 BTC
         assert_eq!(calculate_forge_fee(100_000), 200_000_000); // 2 BTC
-        assert_eq!(calculate_forge_fee(1_000_000), 2_100_000_000); // 21 BTC (capped)
+        assert_eq!(calculate_forge_fee(1_000_000), 1_100_000_000); // 11 BTC
+        assert_eq!(calculate_forge_fee(2_000_000), 2_100_000_000); // 21 BTC (capped)
+        assert_eq!(calculate_forge_fee(20_000_000), 2_100_000_000);
     }
 }
@@ -0,0 +1,164 @@
+//! MuSig2 key aggregation (BIP-327) for shared forge destinations
+//!
+//! N participants combine their public keys into one aggregate key that
+//! becomes the internal key of a BIP-86 key-path Taproot output. Spending it
+//! takes a MuSig2 signature from all of them; to sign, each participant needs
+//! the key order, the per-key coefficients and the Taproot tweak, which are
+//! returned together as a `KeyAggContext`.
+
+use super::TaprootOutput;
+use anyhow::{anyhow, Result};
+use bitcoin::hashes::Hash;
+use bitcoin::key::TapTweak;
+use bitcoin::secp256k1::{PublicKey, Scalar, Secp256k1};
+use bitcoin::taproot::TapTweakHash;
+use bitcoin::Network;
+use sha2::{Digest, Sha256};
+
+/// BIP-340 tagged hash
+fn tagged_hash(tag: &str, parts: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = Sha256::digest(tag.as_bytes());
+    let mut hasher = Sha256::new();
+    hasher.update(tag_hash);
+    hasher.update(tag_hash);
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// Parse a participant key: 33-byte compressed, or 32-byte x-only (taken
+/// with even y)
+pub fn parse_participant_key(bytes: &[u8]) -> Result<PublicKey> {
+    let compressed = match bytes.len() {
+        32 => [&[0x02][..], bytes].concat(),
+        33 => bytes.to_vec(),
+        len => return Err(anyhow!("Expected a 32-byte x-only or 33-byte compressed key, got {} bytes", len)),
+    };
+    PublicKey::from_slice(&compressed).map_err(|e| anyhow!("Invalid public key: {}", e))
+}
+
+/// Everything a participant needs to sign for an aggregate key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyAggContext {
+    /// Participant keys in aggregation order
+    pub keys: Vec<PublicKey>,
+    /// Coefficient each key is multiplied by, in the same order
+    pub coefficients: Vec<[u8; 32]>,
+    /// Aggregate key `Q = Σ aᵢ·Pᵢ`
+    pub aggregate_key: PublicKey,
+    /// Taproot tweak applied to the x-only aggregate key
+    pub tap_tweak: [u8; 32],
+    /// Taproot output the participants jointly control
+    pub output: TaprootOutput,
+    /// Whether the tweaked output key has odd y
+    pub output_key_odd: bool,
+}
+
+impl KeyAggContext {
+    /// Aggregate `keys` in the given order. With `sort`, keys are first put
+    /// in BIP-327 KeySort order so every participant derives the same key
+    /// regardless of the order they were listed in.
+    pub fn new(keys: &[PublicKey], sort: bool) -> Result<Self> {
+        if keys.is_empty() {
+            return Err(anyhow!("At least one key is required"));
+        }
+        let mut keys = keys.to_vec();
+        if sort {
+            keys.sort_by_key(|key| key.serialize());
+        }
+
+        let serialized: Vec<[u8; 33]> = keys.iter().map(PublicKey::serialize).collect();
+        let list: Vec<u8> = serialized.concat();
+        let list_hash = tagged_hash("KeyAgg list", &[&list]);
+        // The first key differing from the first gets coefficient 1
+        let second = serialized.iter().find(|key| **key != serialized[0]);
+
+        let secp = Secp256k1::verification_only();
+        let mut coefficients = Vec::with_capacity(keys.len());
+        let mut terms = Vec::with_capacity(keys.len());
+        for (key, bytes) in keys.iter().zip(&serialized) {
+            let coefficient = if Some(bytes) == second {
+                let mut one = [0u8; 32];
+                one[31] = 1;
+                terms.push(*key);
+                one
+            } else {
+                let coefficient = tagged_hash("KeyAgg coefficient", &[&list_hash, bytes]);
+                let scalar = Scalar::from_be_bytes(coefficient)
+                    .map_err(|_| anyhow!("Key aggregation coefficient out of range"))?;
+                terms.push(key.mul_tweak(&secp, &scalar)?);
+                coefficient
+            };
+            coefficients.push(coefficient);
+        }
+        let term_refs: Vec<&PublicKey> = terms.iter().collect();
+        let aggregate_key = PublicKey::combine_keys(&term_refs)
+            .map_err(|_| anyhow!("Keys aggregate to the point at infinity"))?;
+
+        let (internal_key, _) = aggregate_key.x_only_public_key();
+        let tap_tweak = TapTweakHash::from_key_and_tweak(internal_key, None).to_byte_array();
+        let (_, parity) = internal_key.tap_tweak(&secp, None);
+        Ok(Self {
+            keys,
+            coefficients,
+            output: TaprootOutput::for_key(&aggregate_key),
+            aggregate_key,
+            tap_tweak,
+            output_key_odd: parity == bitcoin::secp256k1::Parity::Odd,
+        })
+    }
+
+    /// Bech32m address of the jointly controlled output
+    pub fn address(&self, network: Network) -> String {
+        self.output.address(network)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(hex_key: &str) -> PublicKey {
+        parse_participant_key(&hex::decode(hex_key).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_key_agg_vectors() {
+        // BIP-327 key_agg_vectors.json
+        let x = [
+            key("02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9"),
+            key("03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659"),
+            key("023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66"),
+        ];
+        let cases: [(&[usize], &str); 4] = [
+            (&[0, 1, 2], "90539EEDE565F5D054F32CC0C220126889ED1E5D193BAF15AEF344FE59D4610C"),
+            (&[2, 1, 0], "6204DE8B083426DC6EAF9502D27024D53FC826BF7D2012148A0575435DF54B2B"),
+            (&[0, 0, 0], "B436E3BAD62B8CD409969A224731C193D051162D8C5AE8B109306127DA3AA935"),
+            (&[0, 0, 1, 1], "69BC22BFA5D106306E48A20679DE1D7389386124D07571D0D872686028C26A3E"),
+        ];
+        for (indices, expected) in cases {
+            let keys: Vec<PublicKey> = indices.iter().map(|i| x[*i]).collect();
+            let context = KeyAggContext::new(&keys, false).unwrap();
+            let (aggregate, _) = context.aggregate_key.x_only_public_key();
+            assert_eq!(hex::encode_upper(aggregate.serialize()), expected, "keys {:?}", indices);
+        }
+    }
+
+    #[test]
+    fn test_sorted_aggregation_ignores_order() {
+        let a = key("02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9");
+        let b = key("023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66");
+        let forward = KeyAggContext::new(&[a, b], true).unwrap();
+        let reverse = KeyAggContext::new(&[b, a], true).unwrap();
+        assert_eq!(forward, reverse);
+        assert_eq!(forward.keys, vec![b, a]);
+        assert!(forward.address(Network::Bitcoin).starts_with("bc1p"));
+
+        // An x-only key is taken with even y
+        let x_only = parse_participant_key(&a.x_only_public_key().0.serialize()).unwrap();
+        assert_eq!(x_only, a);
+        assert!(parse_participant_key(&[1u8; 20]).is_err());
+        assert!(KeyAggContext::new(&[], true).is_err());
+    }
+}
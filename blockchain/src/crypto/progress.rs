@@ -0,0 +1,59 @@
+//! Progress reports and cancellation for long proof-of-forge derivations
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Stage of the proof-of-forge pipeline being run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForgeStage {
+    ProphecyBinding,
+    TetraPow,
+    Tempering,
+    Zetahash,
+    Taproot,
+}
+
+impl ForgeStage {
+    /// Name for progress output
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ProphecyBinding => "prophecy binding",
+            Self::TetraPow => "tetra-pow",
+            Self::Tempering => "tempering",
+            Self::Zetahash => "zetahash",
+            Self::Taproot => "taproot",
+        }
+    }
+}
+
+/// Asks a running derivation to stop. Clones share the request.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the derivation to stop at its next check
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Error returned by a derivation stopped through its `CancelToken`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForgeCancelled;
+
+impl fmt::Display for ForgeCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Proof-of-forge derivation cancelled")
+    }
+}
+
+impl std::error::Error for ForgeCancelled {}
@@ -0,0 +1,201 @@
+//! Tempering algorithms
+//!
+//! Step 3 of the pipeline stretches the Tetra-POW output into the tempered
+//! key. PBKDF2-HMAC-SHA512 with 600,000 iterations is the original mode and
+//! the default. Argon2id is a memory-hard alternative: its cost is set by
+//! memory, passes and lanes, which makes GPU and ASIC search far more
+//! expensive per guess than iterating SHA-512.
+//!
+//! A forge records the algorithm it was tempered with so validators
+//! re-derive it the same way. Validators run whatever a forge asks for, so
+//! Argon2id parameters are bounded by `MAX_ARGON2_MEMORY_KIB`,
+//! `MAX_ARGON2_ITERATIONS` and `MAX_ARGON2_PARALLELISM`.
+
+use super::{pbkdf2_tempering, DEFAULT_TEMPERING_SALT};
+use anyhow::{anyhow, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Largest Argon2id memory cost a forge may use (64 MiB, the default)
+pub const MAX_ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+/// Most Argon2id passes a forge may use
+pub const MAX_ARGON2_ITERATIONS: u32 = 4;
+/// Most Argon2id lanes a forge may use
+pub const MAX_ARGON2_PARALLELISM: u32 = 16;
+
+/// `TemperingAlgorithm::argon2id` parameters
+const ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+const ARGON2_ITERATIONS: u32 = 3;
+const ARGON2_PARALLELISM: u32 = 4;
+
+/// How a forge's tempered key is derived
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "snake_case")]
+pub enum TemperingAlgorithm {
+    /// PBKDF2-HMAC-SHA512, 600,000 iterations
+    #[default]
+    Pbkdf2Sha512,
+    /// Argon2id (version 0x13)
+    Argon2id {
+        /// Memory cost in KiB
+        memory_kib: u32,
+        /// Passes over memory
+        iterations: u32,
+        /// Lanes
+        parallelism: u32,
+    },
+}
+
+impl TemperingAlgorithm {
+    /// Argon2id with 64 MiB, 3 passes and 4 lanes
+    pub fn argon2id() -> Self {
+        Self::Argon2id {
+            memory_kib: ARGON2_MEMORY_KIB,
+            iterations: ARGON2_ITERATIONS,
+            parallelism: ARGON2_PARALLELISM,
+        }
+    }
+
+    /// Whether this is the default, PBKDF2
+    pub fn is_pbkdf2(&self) -> bool {
+        *self == Self::Pbkdf2Sha512
+    }
+
+    /// Name for logs and RPC output
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Pbkdf2Sha512 => "pbkdf2_sha512",
+            Self::Argon2id { .. } => "argon2id",
+        }
+    }
+
+    /// Check the parameters are ones validators accept
+    pub fn check(&self) -> Result<()> {
+        let Self::Argon2id {
+            memory_kib,
+            iterations,
+            parallelism,
+        } = *self
+        else {
+            return Ok(());
+        };
+        if parallelism == 0 || parallelism > MAX_ARGON2_PARALLELISM {
+            return Err(anyhow!("Argon2id parallelism must be 1-{}", MAX_ARGON2_PARALLELISM));
+        }
+        if iterations == 0 || iterations > MAX_ARGON2_ITERATIONS {
+            return Err(anyhow!("Argon2id iterations must be 1-{}", MAX_ARGON2_ITERATIONS));
+        }
+        if memory_kib < 8 * parallelism || memory_kib > MAX_ARGON2_MEMORY_KIB {
+            return Err(anyhow!(
+                "Argon2id memory must be {}-{} KiB for {} lanes",
+                8 * parallelism,
+                MAX_ARGON2_MEMORY_KIB,
+                parallelism
+            ));
+        }
+        Ok(())
+    }
+
+    /// Temper `tetra_hash` into a 64-byte key
+    pub fn temper(&self, tetra_hash: &[u8], salt: Option<&[u8]>) -> Result<Vec<u8>> {
+        match *self {
+            Self::Pbkdf2Sha512 => Ok(pbkdf2_tempering(tetra_hash, salt)),
+            Self::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                self.check()?;
+                let params = Params::new(memory_kib, iterations, parallelism, Some(64))
+                    .map_err(|e| anyhow!("Invalid Argon2id parameters: {}", e))?;
+                let mut output = vec![0u8; 64];
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(tetra_hash, salt.unwrap_or(DEFAULT_TEMPERING_SALT), &mut output)
+                    .map_err(|e| anyhow!("Argon2id tempering failed: {}", e))?;
+                Ok(output)
+            }
+        }
+    }
+}
+
+/// Parses `pbkdf2`, `argon2id`, or `argon2id:m=<KiB>,t=<passes>,p=<lanes>`
+/// with any parameters left out taken from `TemperingAlgorithm::argon2id`
+impl FromStr for TemperingAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, params) = s.split_once(':').unwrap_or((s, ""));
+        match name {
+            "pbkdf2" | "pbkdf2_sha512" if params.is_empty() => return Ok(Self::Pbkdf2Sha512),
+            "argon2id" => {}
+            _ => return Err(anyhow!("Unknown tempering algorithm {:?}", s)),
+        }
+        let (mut memory_kib, mut iterations, mut parallelism) =
+            (ARGON2_MEMORY_KIB, ARGON2_ITERATIONS, ARGON2_PARALLELISM);
+        for param in params.split(',').filter(|param| !param.is_empty()) {
+            let (key, value) = param
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected key=value, got {:?}", param))?;
+            let value: u32 = value.parse().map_err(|_| anyhow!("Invalid Argon2id {} {:?}", key, value))?;
+            match key {
+                "m" => memory_kib = value,
+                "t" => iterations = value,
+                "p" => parallelism = value,
+                _ => return Err(anyhow!("Unknown Argon2id parameter {:?}", key)),
+            }
+        }
+        let algorithm = Self::Argon2id {
+            memory_kib,
+            iterations,
+            parallelism,
+        };
+        algorithm.check()?;
+        Ok(algorithm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_argon2id_tempering() {
+        let light = TemperingAlgorithm::Argon2id {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let tempered = light.temper(&[1u8; 32], None).unwrap();
+        assert_eq!(tempered.len(), 64);
+        assert_eq!(tempered, light.temper(&[1u8; 32], None).unwrap());
+        assert_ne!(tempered, light.temper(&[1u8; 32], Some(b"another salt")).unwrap());
+
+        // Parameters change the key
+        let wider = TemperingAlgorithm::Argon2id {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 2,
+        };
+        assert_ne!(tempered, wider.temper(&[1u8; 32], None).unwrap());
+
+        // Validators refuse unbounded work
+        let heavy = TemperingAlgorithm::Argon2id {
+            memory_kib: MAX_ARGON2_MEMORY_KIB + 1,
+            iterations: 1,
+            parallelism: 1,
+        };
+        assert!(heavy.temper(&[1u8; 32], None).is_err());
+        assert!(TemperingAlgorithm::argon2id().check().is_ok());
+
+        assert_eq!("argon2id:m=64,t=1,p=1".parse::<TemperingAlgorithm>().unwrap(), light);
+        assert_eq!("argon2id".parse::<TemperingAlgorithm>().unwrap(), TemperingAlgorithm::argon2id());
+        assert!("pbkdf2".parse::<TemperingAlgorithm>().unwrap().is_pbkdf2());
+        assert!("argon2id:m=1".parse::<TemperingAlgorithm>().is_err());
+        assert!("scrypt".parse::<TemperingAlgorithm>().is_err());
+
+        let json = serde_json::to_value(light).unwrap();
+        assert_eq!(json["algorithm"], "argon2id");
+        assert_eq!(serde_json::from_value::<TemperingAlgorithm>(json).unwrap(), light);
+    }
+}
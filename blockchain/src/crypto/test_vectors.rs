@@ -0,0 +1,147 @@
+//! Deterministic test vectors for the Proof-of-Forge pipeline
+//!
+//! Each vector fixes a prophecy, PBKDF2 salt and network together with the
+//! output of every pipeline stage, hex-encoded, and the Taproot address.
+//! Another implementation agrees with this one when it reproduces all of
+//! them; `verify_vector` names the first stage that doesn't match, so a
+//! divergence can be traced to its step.
+//!
+//! The vectors cover the canonical prophecy with the default salt, the same
+//! prophecy with a custom salt (only tempering onwards changes), and a
+//! 25-word prophecy built from the BIP-39 mnemonic of 32 bytes of `0x80`.
+
+use super::{proof_of_forge, ProofOfForgeResult};
+use anyhow::{anyhow, Result};
+use bitcoin::Network;
+
+/// A fixed Proof-of-Forge input and its expected outputs
+#[derive(Debug, Clone, Copy)]
+pub struct ForgeVector {
+    pub name: &'static str,
+    /// Prophecy words, space-separated
+    pub prophecy: &'static str,
+    /// PBKDF2 salt, or `None` for the default
+    pub salt: Option<&'static [u8]>,
+    pub network: Network,
+    /// SHA-512 of the concatenated words
+    pub prophecy_hash: &'static str,
+    /// Tetra-POW output
+    pub tetra_hash: &'static str,
+    /// PBKDF2-HMAC-SHA512 output
+    pub tempered_key: &'static str,
+    /// Zetahash Pythagoras output, the secret key
+    pub final_seed: &'static str,
+    pub taproot_address: &'static str,
+}
+
+/// The published vectors
+pub const FORGE_VECTORS: [ForgeVector; 3] = [
+    ForgeVector {
+        name: "canonical-mainnet",
+        prophecy: "sword legend pull magic kingdom artist stone destroy forget fire steel honey question",
+        salt: None,
+        network: Network::Bitcoin,
+        prophecy_hash: "88c11dc1c4a2b18fd65d94c5de70a7b12cb4e4de8c3b17fd0a01ce7890505b6c\
+                        987cda46e5a5bece7eb765692b712cb7fef85673195b1b8357b5dbaafa227504",
+        tetra_hash: "f42883a4c4659f9149329c0e96ef9255ee637a348cabd7709e0c15e5876e03f1",
+        tempered_key: "842a0f1549126287372a6b060ac1c9d1fca64d514fce7c40a4e2d7fc1b0343f7\
+                       0e8cf253bf3c117f1f5e60e2aedbd3a427cfdd9cfe4f8d1878cf9eaee145b51c",
+        final_seed: "d7bac7439cf90c0b509dd57532096f24f937025a2a5479cb6ceb67bfee55c223",
+        taproot_address: "bc1pktr0ytwryz4vhmkkusxu6m7cu0ssq8fcxk2ytahmg9lrhmg3cm0s8v3ec4",
+    },
+    ForgeVector {
+        name: "canonical-salted-testnet",
+        prophecy: "sword legend pull magic kingdom artist stone destroy forget fire steel honey question",
+        salt: Some(b"excalibur-test-vector"),
+        network: Network::Testnet,
+        prophecy_hash: "88c11dc1c4a2b18fd65d94c5de70a7b12cb4e4de8c3b17fd0a01ce7890505b6c\
+                        987cda46e5a5bece7eb765692b712cb7fef85673195b1b8357b5dbaafa227504",
+        tetra_hash: "f42883a4c4659f9149329c0e96ef9255ee637a348cabd7709e0c15e5876e03f1",
+        tempered_key: "0bbed23ba187698320e5f0383aa6c9a91ad6eab0ab3d358af83500960ab6ab91\
+                       d86be4a6dba5b9289e6cb643b4e8c35dbe520d5cb10d6c3091ad1e5a4fb47d6f",
+        final_seed: "ec00b88c1dd14a2e86d40fb706d9911d5e3d81f12f1fb5a991a43055a3d756ca",
+        taproot_address: "tb1phl0vdnsl5ghac7ezqvknz38vgrwc4w8dzckzu0aas7lcjl8khe8s98a7fl",
+    },
+    ForgeVector {
+        name: "mnemonic-24-regtest",
+        prophecy: "letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd \
+                   amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic bless excalibur",
+        salt: None,
+        network: Network::Regtest,
+        prophecy_hash: "4f1e7becae599551df978e00f703863794c435c6c87566b59cc140d18a67dd26\
+                        01158003edeae0a709034d9a02a740e50d69f6a8e9ff1d0b027fb3ce02b65cb2",
+        tetra_hash: "44fdf5aa6030b3fa2f981262f544aedb79895b70b233b278d81dc7294eade339",
+        tempered_key: "bd5626eb181f7c6feb5b0747a2319fa77e81beab3a4e38b9264b9c97a775cc49\
+                       5135eb438b64fbcc154a01b83885f84da83b1b6d6673ddfcf0a1ea0931233167",
+        final_seed: "e2d0a66bc08909c79c459091c98dd069e789cbc046bc99540d18d90462f2769c",
+        taproot_address: "bcrt1pdvymxpdpp9r8ne3lw208trg5tq7s2vag9dheltzeld9pphlnw3vsvpwx37",
+    },
+];
+
+impl ForgeVector {
+    /// Prophecy words of the vector
+    pub fn words(&self) -> Vec<String> {
+        self.prophecy.split_whitespace().map(str::to_string).collect()
+    }
+}
+
+/// Run a vector through `proof_of_forge` and compare every stage
+pub fn verify_vector(vector: &ForgeVector) -> Result<ProofOfForgeResult> {
+    let result = proof_of_forge(&vector.words(), vector.salt, vector.network)?;
+    compare_result(vector, &result)?;
+    Ok(result)
+}
+
+/// Compare a derivation's stages with a vector's, failing at the first
+/// that differs
+pub fn compare_result(vector: &ForgeVector, result: &ProofOfForgeResult) -> Result<()> {
+    let stages = [
+        ("prophecy_hash", vector.prophecy_hash, hex::encode(&result.prophecy_hash)),
+        ("tetra_hash", vector.tetra_hash, hex::encode(&result.tetra_hash)),
+        ("tempered_key", vector.tempered_key, hex::encode(&result.tempered_key)),
+        ("final_seed", vector.final_seed, hex::encode(&result.final_seed)),
+        ("taproot_address", vector.taproot_address, result.taproot_address.clone()),
+    ];
+    for (stage, expected, actual) in stages {
+        if expected != actual {
+            return Err(anyhow!(
+                "Vector {}: {} is {}, expected {}",
+                vector.name,
+                stage,
+                actual,
+                expected
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Verify every published vector
+pub fn verify_forge_vectors() -> Result<()> {
+    for vector in &FORGE_VECTORS {
+        verify_vector(vector)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{entropy_to_mnemonic, CANONICAL_PROPHECY};
+
+    #[test]
+    fn test_forge_vectors() {
+        let results: Vec<ProofOfForgeResult> = FORGE_VECTORS.iter().map(|vector| verify_vector(vector).unwrap()).collect();
+
+        // The inputs are the ones the module documents
+        assert_eq!(FORGE_VECTORS[0].prophecy, CANONICAL_PROPHECY.join(" "));
+        let mnemonic = entropy_to_mnemonic(&[0x80; 32]).unwrap().join(" ");
+        assert_eq!(FORGE_VECTORS[2].prophecy, format!("{} excalibur", mnemonic));
+
+        // A wrong expectation is reported at its stage
+        let mut vector = FORGE_VECTORS[0];
+        vector.final_seed = "00";
+        let error = compare_result(&vector, &results[0]).unwrap_err().to_string();
+        assert!(error.contains("canonical-mainnet: final_seed"), "{}", error);
+    }
+}
@@ -0,0 +1,197 @@
+//! Configurable Tetra-POW
+//!
+//! `TetraPow` runs the Tetra-POW state transformation with a chosen number
+//! of rounds and an optional domain-separation tag. With a tag the seed is
+//! first replaced by its BIP-340 style tagged hash, so the same seed gives
+//! unrelated outputs under different tags. The default, 128 rounds and no
+//! tag, is what `proof_of_forge` uses on every network; other settings give
+//! outputs that don't verify as forges.
+//!
+//! Miners evaluating many candidates can hash them together with
+//! `tetra_pow_batch` or `TetraPow::hash_batch`, which spread large batches
+//! over the rayon pool.
+
+use super::TETRA_POW_ROUNDS;
+use crate::consensus::sighash::tagged_hash;
+use bitcoin::Network;
+use rayon::prelude::*;
+use std::convert::TryInto;
+use zeroize::Zeroize;
+
+/// Batches with at least this many seeds are hashed in parallel
+pub const PARALLEL_TETRA_THRESHOLD: usize = 16;
+
+/// Tetra-POW state for the nonlinear transformation. Its words are
+/// derived from the prophecy, so they are wiped on drop.
+#[derive(Clone)]
+struct TetraPoWState {
+    state: [u64; 4],
+}
+
+impl Drop for TetraPoWState {
+    fn drop(&mut self) {
+        self.state.zeroize();
+    }
+}
+
+impl TetraPoWState {
+    /// Create new Tetra-POW state from seed
+    fn new(seed: &[u8]) -> Self {
+        let mut state = [0u64; 4];
+        if seed.len() >= 32 {
+            state[0] = u64::from_le_bytes(seed[0..8].try_into().unwrap());
+            state[1] = u64::from_le_bytes(seed[8..16].try_into().unwrap());
+            state[2] = u64::from_le_bytes(seed[16..24].try_into().unwrap());
+            state[3] = u64::from_le_bytes(seed[24..32].try_into().unwrap());
+        }
+        Self { state }
+    }
+
+    /// Perform a single nonlinear state shift
+    fn round(&mut self) {
+        // Nonlinear mixing using bitwise operations
+        self.state[0] ^= (self.state[1] << 13) ^ (self.state[3] >> 7);
+        self.state[1] ^= (self.state[2] << 17) ^ (self.state[0] >> 5);
+        self.state[2] ^= (self.state[3] << 23) ^ (self.state[1] >> 11);
+        self.state[3] ^= (self.state[0] << 29) ^ (self.state[2] >> 3);
+
+        // Add entropy (mathematical constants)
+        self.state[0] = self.state[0].wrapping_add(0x9E3779B97F4A7C15);
+        self.state[1] = self.state[1].wrapping_add(0x243F6A8885A308D3);
+        self.state[2] = self.state[2].wrapping_add(0x13198A2E03707344);
+        self.state[3] = self.state[3].wrapping_add(0xA4093822299F31D0);
+    }
+
+    /// Perform `rounds` rounds of Tetra-POW
+    fn compute(&mut self, rounds: usize) -> Vec<u8> {
+        for _ in 0..rounds {
+            self.round();
+        }
+
+        let mut result = Vec::with_capacity(32);
+        for val in &self.state {
+            result.extend_from_slice(&val.to_le_bytes());
+        }
+        result
+    }
+}
+
+/// Tetra-POW with a configurable round count and domain tag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TetraPow {
+    rounds: usize,
+    domain: Option<Vec<u8>>,
+}
+
+impl Default for TetraPow {
+    fn default() -> Self {
+        Self {
+            rounds: TETRA_POW_ROUNDS,
+            domain: None,
+        }
+    }
+}
+
+impl TetraPow {
+    /// The consensus configuration: 128 rounds, no tag
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tetra-POW tagged `excalibur/tetra-pow/<network>`, so candidates for
+    /// one network are useless on another
+    pub fn for_network(network: Network) -> Self {
+        Self::new().domain(format!("excalibur/tetra-pow/{}", network).as_bytes())
+    }
+
+    /// Run `rounds` rounds
+    pub fn rounds(mut self, rounds: usize) -> Self {
+        self.rounds = rounds;
+        self
+    }
+
+    /// Separate outputs from other uses with `tag`
+    pub fn domain(mut self, tag: &[u8]) -> Self {
+        self.domain = Some(tag.to_vec());
+        self
+    }
+
+    /// Number of rounds run
+    pub fn round_count(&self) -> usize {
+        self.rounds
+    }
+
+    /// Domain tag, if any
+    pub fn domain_tag(&self) -> Option<&[u8]> {
+        self.domain.as_deref()
+    }
+
+    /// Tetra-POW output of `seed`
+    pub fn hash(&self, seed: &[u8]) -> Vec<u8> {
+        match &self.domain {
+            Some(tag) => {
+                let mut tagged = tagged_hash(tag, seed);
+                let output = TetraPoWState::new(&tagged).compute(self.rounds);
+                tagged.zeroize();
+                output
+            }
+            None => TetraPoWState::new(seed).compute(self.rounds),
+        }
+    }
+
+    /// Outputs of `seeds` in order, hashed on the rayon pool for large
+    /// batches
+    pub fn hash_batch<S: AsRef<[u8]> + Sync>(&self, seeds: &[S]) -> Vec<Vec<u8>> {
+        if seeds.len() >= PARALLEL_TETRA_THRESHOLD {
+            seeds.par_iter().map(|seed| self.hash(seed.as_ref())).collect()
+        } else {
+            seeds.iter().map(|seed| self.hash(seed.as_ref())).collect()
+        }
+    }
+}
+
+/// Consensus Tetra-POW outputs of `seeds` in order, in parallel for large
+/// batches
+pub fn tetra_pow_batch<S: AsRef<[u8]> + Sync>(seeds: &[S]) -> Vec<Vec<u8>> {
+    TetraPow::default().hash_batch(seeds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{prophecy_binding, tetra_pow_128_rounds, CANONICAL_PROPHECY};
+
+    #[test]
+    fn test_default_matches_consensus() {
+        let words: Vec<String> = CANONICAL_PROPHECY.iter().map(|s| s.to_string()).collect();
+        let prophecy_hash = prophecy_binding(&words).unwrap();
+        assert_eq!(
+            hex::encode(TetraPow::new().hash(&prophecy_hash)),
+            "f42883a4c4659f9149329c0e96ef9255ee637a348cabd7709e0c15e5876e03f1"
+        );
+
+        // Zero rounds is the seed's first 32 bytes
+        assert_eq!(TetraPow::new().rounds(0).hash(&prophecy_hash), prophecy_hash[..32]);
+        assert_ne!(TetraPow::new().rounds(64).hash(&prophecy_hash), tetra_pow_128_rounds(&prophecy_hash));
+
+        // Tags separate networks
+        let mainnet = TetraPow::for_network(Network::Bitcoin);
+        let testnet = TetraPow::for_network(Network::Testnet);
+        assert_eq!(mainnet.domain_tag(), Some(&b"excalibur/tetra-pow/bitcoin"[..]));
+        assert_ne!(mainnet.hash(&prophecy_hash), testnet.hash(&prophecy_hash));
+        assert_ne!(mainnet.hash(&prophecy_hash), tetra_pow_128_rounds(&prophecy_hash));
+    }
+
+    #[test]
+    fn test_batch_matches_single() {
+        let seeds: Vec<[u8; 64]> = (0..PARALLEL_TETRA_THRESHOLD as u8 + 3).map(|n| [n; 64]).collect();
+        let batch = tetra_pow_batch(&seeds);
+        assert_eq!(batch.len(), seeds.len());
+        for (seed, output) in seeds.iter().zip(&batch) {
+            assert_eq!(*output, tetra_pow_128_rounds(seed));
+        }
+
+        let tagged = TetraPow::new().rounds(8).domain(b"miner");
+        assert_eq!(tagged.hash_batch(&seeds[..2]), vec![tagged.hash(&seeds[0]), tagged.hash(&seeds[1])]);
+    }
+}
@@ -0,0 +1,175 @@
+//! Zetahash Pythagoras in integer arithmetic
+//!
+//! Step 4 scales each 64-bit word of the tempered key by a Pythagorean
+//! ratio. The ratios used to be `f64` literals multiplied in floating
+//! point, which leaves a consensus-critical result to the platform's float
+//! unit and the compiler. Each ratio is now its exact `f64` value held as an
+//! integer mantissa and a binary exponent, and `scale` reproduces the two
+//! IEEE 754 roundings the float code performed (`value as f64`, then the
+//! product) and its saturating truncation back to `u64` in integer
+//! arithmetic only. The output is bit-for-bit what the float code produced,
+//! so existing derivations and addresses don't change.
+//!
+//! The `float-zetahash` feature switches back to the floating-point path,
+//! for comparing against builds from before the change.
+
+use sha2::{Digest, Sha256};
+use std::convert::TryInto;
+
+/// Significant bits of an `f64`
+const F64_PRECISION: u32 = 53;
+
+/// A ratio equal to `mantissa * 2^exponent`, with a 53-bit mantissa
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Ratio {
+    mantissa: u64,
+    exponent: i32,
+}
+
+impl Ratio {
+    const fn new(mantissa: u64, exponent: i32) -> Self {
+        Self { mantissa, exponent }
+    }
+
+    /// The `f64` the ratio was originally written as
+    fn to_f64(self) -> f64 {
+        self.mantissa as f64 * 2f64.powi(self.exponent)
+    }
+}
+
+/// Pythagorean ratios (sacred geometry), as the `f64` values of the
+/// original literals
+const RATIOS: [Ratio; 8] = [
+    Ratio::new(0x10_0000_0000_0000, -52), // Unity
+    Ratio::new(0x19_e377_9b97_f4a8, -52), // Golden Ratio (φ), 1.618033988749895
+    Ratio::new(0x16_a09e_667f_3bcc, -52), // √2, 1.414213562373095 (one ulp below `SQRT_2`)
+    Ratio::new(0x1b_b67a_e858_4ca9, -52), // √3, 1.732050807568877
+    Ratio::new(0x10_0000_0000_0000, -51), // Octave
+    Ratio::new(0x18_0000_0000_0000, -53), // Perfect Fourth (3:4)
+    Ratio::new(0x19_9999_9999_999a, -53), // Perfect Fifth (4:5), 0.8
+    Ratio::new(0x14_0000_0000_0000, -52), // Major Third (5:4)
+];
+
+/// Round `x` to 53 significant bits, ties to even, as `m * 2^shift`
+fn round_to_precision(x: u128) -> (u128, i32) {
+    let bits = 128 - x.leading_zeros();
+    if bits <= F64_PRECISION {
+        return (x, 0);
+    }
+    let shift = bits - F64_PRECISION;
+    let half = 1u128 << (shift - 1);
+    let remainder = x & ((half << 1) - 1);
+    let mut mantissa = x >> shift;
+    if remainder > half || (remainder == half && mantissa & 1 == 1) {
+        mantissa += 1;
+    }
+    (mantissa, shift as i32)
+}
+
+/// `(value as f64 * ratio) as u64` without floating point
+fn scale(value: u64, ratio: Ratio) -> u64 {
+    let (value, value_shift) = round_to_precision(value.into());
+    // Both factors have at most 54 bits, so the exact product fits
+    let (product, product_shift) = round_to_precision(value * u128::from(ratio.mantissa));
+    let exponent = value_shift + product_shift + ratio.exponent;
+    if exponent < 0 {
+        let shift = exponent.unsigned_abs();
+        return if shift >= u128::BITS { 0 } else { (product >> shift) as u64 };
+    }
+    // Float-to-int casts saturate
+    if product != 0 && exponent as u32 >= product.leading_zeros() {
+        return u64::MAX;
+    }
+    u64::try_from(product << exponent).unwrap_or(u64::MAX)
+}
+
+/// Scale `value` by `ratio`, in floating point under `float-zetahash`
+fn transform(value: u64, ratio: Ratio) -> u64 {
+    if cfg!(feature = "float-zetahash") {
+        (value as f64 * ratio.to_f64()) as u64
+    } else {
+        scale(value, ratio)
+    }
+}
+
+/// Step 4: Final Zetahash Pythagoras - Sacred geometric transformation
+pub fn final_zetahash_pythagoras(tempered_key: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8; 32];
+
+    // Process tempered_key in 8-byte chunks
+    for i in 0..4 {
+        let offset = i * 8;
+        if offset + 8 > tempered_key.len() {
+            break;
+        }
+
+        // Extract 64-bit value
+        let value = u64::from_le_bytes(tempered_key[offset..offset + 8].try_into().unwrap());
+
+        // Apply Pythagorean ratio transformation
+        let transformed = transform(value, RATIOS[i % RATIOS.len()]);
+
+        // Mix with SHA-256 for additional entropy
+        let mut mix_data = Vec::with_capacity(16);
+        mix_data.extend_from_slice(&value.to_le_bytes());
+        mix_data.extend_from_slice(&transformed.to_le_bytes());
+
+        let mut hasher = Sha256::new();
+        hasher.update(&mix_data);
+        let hash = hasher.finalize();
+
+        // Place in result
+        result[i * 8..(i + 1) * 8].copy_from_slice(&hash[0..8]);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn test_ratios_are_the_original_literals() {
+        let literals = [1.0, 1.618033988749895, 1.414213562373095, 1.732050807568877, 2.0, 0.75, 0.8, 1.25];
+        for (ratio, literal) in RATIOS.iter().zip(literals) {
+            assert_eq!(ratio.to_f64().to_bits(), f64::to_bits(literal));
+        }
+    }
+
+    #[test]
+    fn test_integer_scaling_matches_float() {
+        let float = |value: u64, ratio: Ratio| (value as f64 * ratio.to_f64()) as u64;
+        let mut edges = vec![0, 1, 2, 3, u64::MAX, u64::MAX - 1, u64::MAX / 2, (1 << 53) + 1, (1 << 54) + 2];
+        for bit in 0..64 {
+            let power = 1u64 << bit;
+            edges.extend([power - 1, power, power + 1, power | (power >> 1)]);
+        }
+        let mut rng = StdRng::seed_from_u64(0x2E7A);
+        let random = (0..200_000).map(|_| {
+            // Spread over every magnitude, not just the top bits
+            let value: u64 = rng.gen();
+            value >> rng.gen_range(0..64)
+        });
+        for value in edges.into_iter().chain(random) {
+            for ratio in RATIOS {
+                assert_eq!(scale(value, ratio), float(value, ratio), "{} * {:?}", value, ratio);
+            }
+        }
+
+        // Whole-step output for random keys
+        for _ in 0..1_000 {
+            let key: Vec<u8> = (0..64).map(|_| rng.gen()).collect();
+            let mut expected = vec![0u8; 32];
+            for i in 0..4 {
+                let value = u64::from_le_bytes(key[i * 8..i * 8 + 8].try_into().unwrap());
+                let mix = [value.to_le_bytes(), float(value, RATIOS[i]).to_le_bytes()].concat();
+                expected[i * 8..i * 8 + 8].copy_from_slice(&Sha256::digest(mix)[..8]);
+            }
+            assert_eq!(final_zetahash_pythagoras(&key), expected);
+        }
+    }
+}
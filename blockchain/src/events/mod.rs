@@ -0,0 +1,155 @@
+//! Node event bus and operator alerts
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+mod webhook;
+mod zmq;
+
+pub use webhook::{PendingNotification, WebhookNotifier};
+pub use zmq::{parse_zmq_endpoint, ZmqPublisher, ZMQ_SEQUENCE_TOPIC};
+
+/// Number of events buffered for slow subscribers
+pub const EVENT_BUS_CAPACITY: usize = 256;
+
+/// How urgently an operator needs to act on an alert
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertSeverity {
+    Info,
+    Warning,
+    Critical,
+}
+
+/// Operator alert
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    pub severity: AlertSeverity,
+    /// Machine-readable alert kind, e.g. `deep_reorg`
+    pub kind: String,
+    pub message: String,
+    /// Alert-specific details
+    pub data: Value,
+    pub timestamp: u64,
+}
+
+impl Alert {
+    /// Create an alert stamped with the current time
+    pub fn new(severity: AlertSeverity, kind: &str, message: impl Into<String>, data: Value) -> Self {
+        Self {
+            severity,
+            kind: kind.to_string(),
+            message: message.into(),
+            data,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        }
+    }
+}
+
+/// The chain switched to a competing branch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReorgEvent {
+    /// Last block shared by both branches
+    pub fork_height: u64,
+    pub fork_hash: String,
+    /// Tip of the branch switched to
+    pub tip_hash: String,
+    pub disconnected: u64,
+    pub connected: u64,
+}
+
+/// A block was connected to the tip
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockEvent {
+    pub height: u64,
+    pub hash: String,
+    pub forges: usize,
+    pub timestamp: u64,
+}
+
+/// A peer connected or disconnected
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerEvent {
+    pub peer: String,
+    pub connected: bool,
+}
+
+/// Event published on the node event bus
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NodeEvent {
+    Alert(Alert),
+    Reorg(ReorgEvent),
+    Block(BlockEvent),
+    Peer(PeerEvent),
+}
+
+/// Fan-out of node events to in-process subscribers (webhooks, RPC)
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<NodeEvent>,
+}
+
+impl EventBus {
+    /// Create a new event bus
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_BUS_CAPACITY).0,
+        }
+    }
+
+    /// Subscribe to all subsequent events
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Publish an event
+    pub fn publish(&self, event: NodeEvent) {
+        // No subscribers is not an error
+        let _ = self.sender.send(event);
+    }
+
+    /// Log and publish an alert
+    pub fn alert(&self, alert: Alert) {
+        match alert.severity {
+            AlertSeverity::Critical => tracing::error!("ALERT [{}] {}", alert.kind, alert.message),
+            AlertSeverity::Warning => tracing::warn!("ALERT [{}] {}", alert.kind, alert.message),
+            AlertSeverity::Info => tracing::info!("ALERT [{}] {}", alert.kind, alert.message),
+        }
+        self.publish(NodeEvent::Alert(alert));
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_alert_published() {
+        let bus = EventBus::new();
+        let mut events = bus.subscribe();
+
+        bus.alert(Alert::new(AlertSeverity::Critical, "deep_reorg", "test", json!({ "depth": 7 })));
+
+        let NodeEvent::Alert(alert) = events.try_recv().unwrap() else {
+            panic!("expected an alert");
+        };
+        assert_eq!(alert.kind, "deep_reorg");
+        assert_eq!(alert.data["depth"], 7);
+
+        let encoded = serde_json::to_value(NodeEvent::Alert(alert)).unwrap();
+        assert_eq!(encoded["type"], "alert");
+        assert_eq!(encoded["severity"], "critical");
+    }
+}
@@ -0,0 +1,366 @@
+//! Delivery of node events to operator webhooks (plain HTTP POST)
+//!
+//! Every delivery is written to the chain store before it is attempted and
+//! removed once the endpoint accepts it. Failed deliveries are retried with
+//! exponential backoff, and deliveries still pending at shutdown resume when
+//! the node restarts, so an endpoint that is down for a while misses nothing.
+
+use super::{AlertSeverity, NodeEvent};
+use crate::chain::ChainStore;
+use crate::network::unix_now;
+use crate::shutdown::ShutdownSignal;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+use anyhow::{anyhow, Result};
+
+/// Time allowed for a single webhook delivery
+pub const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often pending deliveries are checked for a due retry
+pub const WEBHOOK_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Delay before the first retry; doubled after each further failure
+pub const WEBHOOK_RETRY_BASE_SECS: u64 = 5;
+
+/// Longest delay between retries
+pub const WEBHOOK_RETRY_MAX_SECS: u64 = 3600;
+
+/// Attempts after which a delivery is dropped
+pub const WEBHOOK_MAX_ATTEMPTS: u32 = 12;
+
+/// A webhook delivery not yet accepted by its endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingNotification {
+    pub id: u64,
+    pub url: String,
+    /// JSON-encoded event
+    pub body: Vec<u8>,
+    /// Failed attempts so far
+    pub attempts: u32,
+    /// Unix time of the next attempt
+    pub next_attempt: u64,
+    pub created_at: u64,
+    pub last_error: Option<String>,
+}
+
+impl PendingNotification {
+    /// Record a failed attempt and schedule the next one. Returns `false`
+    /// once the delivery has used up its attempts.
+    pub fn record_failure(&mut self, error: String, now: u64) -> bool {
+        self.attempts += 1;
+        self.last_error = Some(error);
+        let backoff = WEBHOOK_RETRY_BASE_SECS
+            .saturating_mul(1 << (self.attempts - 1).min(20))
+            .min(WEBHOOK_RETRY_MAX_SECS);
+        self.next_attempt = now + backoff;
+        self.attempts < WEBHOOK_MAX_ATTEMPTS
+    }
+
+    /// Summary for `listpendingnotifications`
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "url": self.url,
+            "event": serde_json::from_slice::<serde_json::Value>(&self.body).unwrap_or_default(),
+            "attempts": self.attempts,
+            "next_attempt": self.next_attempt,
+            "created_at": self.created_at,
+            "last_error": self.last_error,
+        })
+    }
+}
+
+/// Posts node events as JSON to configured `http://` endpoints
+#[derive(Clone)]
+pub struct WebhookNotifier {
+    /// Shared by clones, so endpoints can be changed while `run` is going
+    endpoints: Arc<RwLock<Endpoints>>,
+    store: Arc<ChainStore>,
+}
+
+#[derive(Debug)]
+struct Endpoints {
+    urls: Vec<String>,
+    min_severity: AlertSeverity,
+}
+
+impl WebhookNotifier {
+    /// Create a notifier for `urls`, delivering alerts at or above
+    /// `min_severity` and queueing deliveries in `store`
+    pub fn new(urls: Vec<String>, min_severity: AlertSeverity, store: Arc<ChainStore>) -> Self {
+        Self {
+            endpoints: Arc::new(RwLock::new(Endpoints { urls, min_severity })),
+            store,
+        }
+    }
+
+    /// Deliver new events to `urls` from now on. Deliveries already
+    /// queued still go to the endpoint they were queued for.
+    pub fn set_endpoints(&self, urls: Vec<String>, min_severity: AlertSeverity) {
+        *self.endpoints.write().unwrap() = Endpoints { urls, min_severity };
+    }
+
+    /// Whether an event should be delivered
+    pub fn wants(&self, event: &NodeEvent) -> bool {
+        let endpoints = self.endpoints.read().unwrap();
+        match event {
+            NodeEvent::Alert(alert) => !endpoints.urls.is_empty() && alert.severity >= endpoints.min_severity,
+            NodeEvent::Reorg(_) | NodeEvent::Block(_) | NodeEvent::Peer(_) => false,
+        }
+    }
+
+    /// Queue an event for every endpoint and make a first attempt at each
+    pub async fn deliver(&self, event: &NodeEvent) {
+        if let Err(e) = self.try_deliver(event).await {
+            tracing::error!("Failed to queue webhook event: {:#}", e);
+        }
+    }
+
+    async fn try_deliver(&self, event: &NodeEvent) -> Result<()> {
+        let body = serde_json::to_vec(event)?;
+        let now = unix_now();
+        let urls = self.endpoints.read().unwrap().urls.clone();
+        for url in urls {
+            let notification = PendingNotification {
+                id: self.store.next_notification_id()?,
+                url,
+                body: body.clone(),
+                attempts: 0,
+                next_attempt: now,
+                created_at: now,
+                last_error: None,
+            };
+            self.store.put_notification(&notification)?;
+            self.attempt(notification).await?;
+        }
+        Ok(())
+    }
+
+    /// Attempt every queued delivery whose retry is due
+    pub async fn retry_due(&self, now: u64) -> Result<()> {
+        for notification in self.store.list_notifications()? {
+            if notification.next_attempt <= now {
+                self.attempt(notification).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Post a queued delivery, then drop it from the queue or reschedule it
+    async fn attempt(&self, mut notification: PendingNotification) -> Result<()> {
+        let error = match tokio::time::timeout(WEBHOOK_TIMEOUT, post_json(&notification.url, &notification.body)).await {
+            Ok(Ok(())) => return self.store.delete_notification(notification.id),
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => "timed out".to_string(),
+        };
+        if notification.record_failure(error.clone(), unix_now()) {
+            tracing::warn!(
+                "Webhook {} failed (attempt {}): {}",
+                notification.url,
+                notification.attempts,
+                error
+            );
+            self.store.put_notification(&notification)
+        } else {
+            tracing::warn!(
+                "Webhook {} failed {} times, dropping delivery {}: {}",
+                notification.url,
+                notification.attempts,
+                notification.id,
+                error
+            );
+            self.store.delete_notification(notification.id)
+        }
+    }
+
+    /// Forward events from the bus until shutdown,
+    /// and retry queued deliveries, starting with those left from the last run
+    pub async fn run(self, mut events: broadcast::Receiver<NodeEvent>, mut shutdown: ShutdownSignal) {
+        let mut retries = tokio::time::interval(WEBHOOK_RETRY_INTERVAL);
+        retries.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = retries.tick() => {
+                    if let Err(e) = self.retry_due(unix_now()).await {
+                        tracing::error!("Failed to retry webhook deliveries: {:#}", e);
+                    }
+                }
+                event = events.recv() => match event {
+                    Ok(event) if self.wants(&event) => self.deliver(&event).await,
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::warn!("Webhook notifier fell behind, {} events dropped", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = shutdown.recv() => break,
+            }
+        }
+    }
+}
+
+/// Split an `http://host[:port][/path]` URL
+fn parse_http_url(url: &str) -> Result<(String, String)> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| anyhow!("Only http:// webhook URLs are supported: {}", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    if authority.is_empty() {
+        return Err(anyhow!("Webhook URL has no host: {}", url));
+    }
+    let authority = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    Ok((authority, path.to_string()))
+}
+
+async fn post_json(url: &str, body: &[u8]) -> Result<()> {
+    let (authority, path) = parse_http_url(url)?;
+    let mut stream = TcpStream::connect(&authority).await?;
+
+    let header = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        authority,
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(body).await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let status_line = response.split(|b| *b == b'\n').next().unwrap_or_default();
+    let status = String::from_utf8_lossy(status_line);
+    let code = status.split_whitespace().nth(1).unwrap_or_default();
+    if !code.starts_with('2') {
+        return Err(anyhow!("Unexpected response: {}", status.trim()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::MemoryStore;
+    use crate::events::Alert;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_parse_http_url() {
+        assert_eq!(
+            parse_http_url("http://127.0.0.1:9000/hooks/exs").unwrap(),
+            ("127.0.0.1:9000".to_string(), "/hooks/exs".to_string())
+        );
+        assert_eq!(
+            parse_http_url("http://alerts.local").unwrap(),
+            ("alerts.local:80".to_string(), "/".to_string())
+        );
+        assert!(parse_http_url("https://alerts.local").is_err());
+    }
+
+    fn store() -> Arc<ChainStore> {
+        Arc::new(ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap())
+    }
+
+    /// Accept one webhook request, answer 204 and return the request text
+    fn serve_once(listener: TcpListener) -> tokio::task::JoinHandle<String> {
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            // The JSON body is the last thing sent
+            while !request.ends_with(b"}") {
+                let n = socket.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            socket.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await.unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_deliver_posts_json() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = serve_once(listener);
+
+        let store = store();
+        let notifier = WebhookNotifier::new(vec![url], AlertSeverity::Warning, Arc::clone(&store));
+        let info = NodeEvent::Alert(Alert::new(AlertSeverity::Info, "note", "ignored", json!({})));
+        assert!(!notifier.wants(&info));
+
+        let event = NodeEvent::Alert(Alert::new(AlertSeverity::Critical, "deep_reorg", "reorg", json!({})));
+        assert!(notifier.wants(&event));
+        notifier.deliver(&event).await;
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1"));
+        assert!(request.contains("\"kind\":\"deep_reorg\""));
+        assert!(store.list_notifications().unwrap().is_empty(), "delivered notifications are dequeued");
+    }
+
+    #[tokio::test]
+    async fn test_failed_delivery_is_queued_and_retried() {
+        // Nothing listens on a port we bound and released
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let store = store();
+        let notifier = WebhookNotifier::new(vec![format!("http://{}/hook", closed)], AlertSeverity::Info, Arc::clone(&store));
+        let event = NodeEvent::Alert(Alert::new(AlertSeverity::Warning, "stall", "stalled", json!({})));
+        notifier.deliver(&event).await;
+
+        let pending = store.list_notifications().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].attempts, 1);
+        assert!(pending[0].last_error.is_some());
+        assert_eq!(pending[0].to_json()["event"]["kind"], "stall");
+
+        // Not yet due
+        let now = unix_now();
+        notifier.retry_due(now).await.unwrap();
+        assert_eq!(store.list_notifications().unwrap()[0].attempts, 1);
+
+        // A restarted notifier picks the queued delivery up once the endpoint is back
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut queued = pending[0].clone();
+        queued.url = format!("http://{}/hook", listener.local_addr().unwrap());
+        store.put_notification(&queued).unwrap();
+        let server = serve_once(listener);
+        let restarted = WebhookNotifier::new(vec![], AlertSeverity::Info, Arc::clone(&store));
+        restarted.retry_due(now + WEBHOOK_RETRY_MAX_SECS).await.unwrap();
+        assert!(server.await.unwrap().contains("\"kind\":\"stall\""));
+        assert!(store.list_notifications().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let mut notification = PendingNotification {
+            id: 1,
+            url: "http://alerts.local".to_string(),
+            body: b"{}".to_vec(),
+            attempts: 0,
+            next_attempt: 0,
+            created_at: 0,
+            last_error: None,
+        };
+        assert!(notification.record_failure("refused".to_string(), 100));
+        assert_eq!(notification.next_attempt, 100 + WEBHOOK_RETRY_BASE_SECS);
+        assert!(notification.record_failure("refused".to_string(), 100));
+        assert_eq!(notification.next_attempt, 100 + 2 * WEBHOOK_RETRY_BASE_SECS);
+        while notification.record_failure("refused".to_string(), 100) {}
+        assert_eq!(notification.attempts, WEBHOOK_MAX_ATTEMPTS);
+        assert_eq!(notification.next_attempt, 100 + WEBHOOK_RETRY_MAX_SECS);
+    }
+}
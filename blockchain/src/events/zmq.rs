@@ -0,0 +1,345 @@
+//! Mempool `sequence` notifications over ZeroMQ
+//!
+//! Speaks the part of ZMTP 3.0 a PUB socket needs (NULL security, topic
+//! subscriptions), so `zmq` SUB sockets such as pyzmq's can connect without
+//! the node linking libzmq. Each event is a three-frame message laid out
+//! like bitcoind's `sequence` topic: the topic, then the 32-byte proof hash
+//! followed by `A` (added) or `R` (removed) and the mempool sequence number
+//! as 8 little-endian bytes, then a 4-byte little-endian message counter.
+//! A subscriber that falls behind loses messages, which shows as a gap in
+//! both numbers; it then resyncs with `getmempoolsequence`.
+
+use crate::mempool::{MempoolEvent, MempoolEventKind};
+use crate::shutdown::ShutdownSignal;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+use anyhow::{anyhow, Result};
+
+/// Topic mempool events are published under
+pub const ZMQ_SEQUENCE_TOPIC: &[u8] = b"sequence";
+
+/// Messages queued for a subscriber before newer ones are dropped, like
+/// ZeroMQ's default send high-water mark
+pub const ZMQ_SEND_HWM: usize = 1000;
+
+/// Time a connecting subscriber has to complete the handshake
+pub const ZMQ_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest frame accepted from a subscriber (subscriptions are short)
+const MAX_INBOUND_FRAME: u64 = 4096;
+
+const FLAG_MORE: u8 = 0x01;
+const FLAG_LONG: u8 = 0x02;
+const FLAG_COMMAND: u8 = 0x04;
+
+/// Socket address of a `tcp://host:port` endpoint
+pub fn parse_zmq_endpoint(endpoint: &str) -> Result<String> {
+    let address = endpoint
+        .strip_prefix("tcp://")
+        .ok_or_else(|| anyhow!("Only tcp:// ZeroMQ endpoints are supported: {}", endpoint))?;
+    match address.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(address.to_string()),
+        _ => Err(anyhow!("ZeroMQ endpoint needs a host and port: {}", endpoint)),
+    }
+}
+
+/// Publishes mempool events to ZeroMQ subscribers
+pub struct ZmqPublisher {
+    listener: TcpListener,
+}
+
+impl ZmqPublisher {
+    /// Listen on a `tcp://host:port` endpoint
+    pub async fn bind(endpoint: &str) -> Result<Self> {
+        let address = parse_zmq_endpoint(endpoint)?;
+        let listener = TcpListener::bind(&address)
+            .await
+            .map_err(|e| anyhow!("Failed to bind ZeroMQ endpoint {}: {}", endpoint, e))?;
+        Ok(Self { listener })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accept subscribers and publish events until shutdown
+    pub async fn run(self, mut events: broadcast::Receiver<MempoolEvent>, mut shutdown: ShutdownSignal) {
+        let mut subscribers: Vec<mpsc::Sender<Arc<Vec<u8>>>> = Vec::new();
+        let mut counter: u32 = 0;
+        loop {
+            tokio::select! {
+                accepted = self.listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        let (sender, messages) = mpsc::channel(ZMQ_SEND_HWM);
+                        subscribers.push(sender);
+                        tokio::spawn(async move {
+                            if let Err(e) = serve_subscriber(stream, messages).await {
+                                tracing::debug!("ZeroMQ subscriber {} dropped: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => tracing::warn!("Failed to accept ZeroMQ subscriber: {}", e),
+                },
+                event = events.recv() => match event {
+                    Ok(event) => {
+                        let message = Arc::new(sequence_message(&event, counter));
+                        counter = counter.wrapping_add(1);
+                        // Full queues drop the message; closed ones are gone
+                        subscribers.retain(|subscriber| {
+                            !matches!(
+                                subscriber.try_send(Arc::clone(&message)),
+                                Err(mpsc::error::TrySendError::Closed(_))
+                            )
+                        });
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::warn!("ZeroMQ publisher fell behind, {} mempool events dropped", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = shutdown.recv() => break,
+            }
+        }
+    }
+}
+
+/// Encode an event as a `sequence` message, frames included
+fn sequence_message(event: &MempoolEvent, counter: u32) -> Vec<u8> {
+    let label = match event.kind {
+        MempoolEventKind::Added => b'A',
+        MempoolEventKind::Removed { .. } => b'R',
+    };
+    let mut body = Vec::with_capacity(41);
+    body.extend_from_slice(&event.proof_hash);
+    body.push(label);
+    body.extend_from_slice(&event.sequence.to_le_bytes());
+
+    let mut message = Vec::new();
+    write_frame(&mut message, FLAG_MORE, ZMQ_SEQUENCE_TOPIC);
+    write_frame(&mut message, FLAG_MORE, &body);
+    write_frame(&mut message, 0, &counter.to_le_bytes());
+    message
+}
+
+fn write_frame(out: &mut Vec<u8>, flags: u8, body: &[u8]) {
+    match u8::try_from(body.len()) {
+        Ok(len) => out.extend_from_slice(&[flags, len]),
+        Err(_) => {
+            out.push(flags | FLAG_LONG);
+            out.extend_from_slice(&(body.len() as u64).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(body);
+}
+
+/// ZMTP 3.0 greeting offering the NULL mechanism
+fn greeting() -> [u8; 64] {
+    let mut greeting = [0u8; 64];
+    greeting[0] = 0xff;
+    greeting[9] = 0x7f;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    greeting
+}
+
+/// `READY` command announcing our socket type
+fn ready_command(socket_type: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.push(5);
+    body.extend_from_slice(b"READY");
+    body.push(11);
+    body.extend_from_slice(b"Socket-Type");
+    body.extend_from_slice(&(socket_type.len() as u32).to_be_bytes());
+    body.extend_from_slice(socket_type);
+    let mut command = Vec::new();
+    write_frame(&mut command, FLAG_COMMAND, &body);
+    command
+}
+
+/// Socket type from the body of a peer's `READY` command
+fn ready_socket_type(body: &[u8]) -> Result<Vec<u8>> {
+    let rest = body.strip_prefix(b"\x05READY").ok_or_else(|| anyhow!("Expected a READY command"))?;
+    let mut properties = rest;
+    while let Some((&name_len, rest)) = properties.split_first() {
+        let name_len = name_len as usize;
+        if rest.len() < name_len + 4 {
+            break;
+        }
+        let (name, rest) = rest.split_at(name_len);
+        let value_len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let rest = &rest[4..];
+        if rest.len() < value_len {
+            break;
+        }
+        let (value, rest) = rest.split_at(value_len);
+        if name.eq_ignore_ascii_case(b"Socket-Type") {
+            return Ok(value.to_vec());
+        }
+        properties = rest;
+    }
+    Err(anyhow!("READY command has no valid Socket-Type"))
+}
+
+/// Read one frame, returning its flags and body
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(u8, Vec<u8>)> {
+    let flags = reader.read_u8().await?;
+    let len = if flags & FLAG_LONG != 0 {
+        reader.read_u64().await?
+    } else {
+        u64::from(reader.read_u8().await?)
+    };
+    if len > MAX_INBOUND_FRAME {
+        return Err(anyhow!("Frame of {} bytes is too large", len));
+    }
+    let mut body = vec![0u8; len as usize];
+    reader.read_exact(&mut body).await?;
+    Ok((flags, body))
+}
+
+/// Exchange greetings and `READY` commands with a connecting SUB socket
+async fn handshake(stream: &mut TcpStream) -> Result<()> {
+    stream.write_all(&greeting()).await?;
+    let mut theirs = [0u8; 64];
+    stream.read_exact(&mut theirs).await?;
+    if theirs[0] != 0xff || theirs[9] & 0x01 == 0 {
+        return Err(anyhow!("Not a ZMTP peer"));
+    }
+    if theirs[10] < 3 || !theirs[12..32].starts_with(b"NULL\0") {
+        return Err(anyhow!("Peer needs ZMTP {} or a security mechanism", theirs[10]));
+    }
+
+    stream.write_all(&ready_command(b"PUB")).await?;
+    let (flags, body) = read_frame(stream).await?;
+    if flags & FLAG_COMMAND == 0 {
+        return Err(anyhow!("Expected a READY command"));
+    }
+    let socket_type = ready_socket_type(&body)?;
+    if socket_type != b"SUB" && socket_type != b"XSUB" {
+        return Err(anyhow!("{} sockets can't subscribe", String::from_utf8_lossy(&socket_type)));
+    }
+    Ok(())
+}
+
+/// Apply subscription changes sent by a subscriber until it disconnects.
+/// ZMTP 3.0 peers send them as messages starting with 1 (subscribe) or 0
+/// (cancel); ZMTP 3.1 peers as `SUBSCRIBE` and `CANCEL` commands.
+async fn read_subscriptions(mut reader: OwnedReadHalf, subscriptions: Arc<Mutex<Vec<Vec<u8>>>>) -> Result<()> {
+    loop {
+        let (flags, body) = read_frame(&mut reader).await?;
+        let change = if flags & FLAG_COMMAND != 0 {
+            if let Some(topic) = body.strip_prefix(b"\x09SUBSCRIBE") {
+                Some((true, topic))
+            } else {
+                body.strip_prefix(b"\x06CANCEL").map(|topic| (false, topic))
+            }
+        } else {
+            body.split_first().and_then(|(&kind, topic)| match kind {
+                1 => Some((true, topic)),
+                0 => Some((false, topic)),
+                _ => None,
+            })
+        };
+        let mut subscriptions = subscriptions.lock().unwrap();
+        match change {
+            Some((true, topic)) => subscriptions.push(topic.to_vec()),
+            Some((false, topic)) => {
+                if let Some(index) = subscriptions.iter().position(|subscribed| subscribed == topic) {
+                    subscriptions.remove(index);
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+/// Handshake with a subscriber, then forward the messages it subscribed to
+async fn serve_subscriber(mut stream: TcpStream, mut messages: mpsc::Receiver<Arc<Vec<u8>>>) -> Result<()> {
+    tokio::time::timeout(ZMQ_HANDSHAKE_TIMEOUT, handshake(&mut stream))
+        .await
+        .map_err(|_| anyhow!("Handshake timed out"))??;
+
+    let (reader, mut writer) = stream.into_split();
+    let subscriptions = Arc::new(Mutex::new(Vec::new()));
+    let mut reading = tokio::spawn(read_subscriptions(reader, Arc::clone(&subscriptions)));
+    let result = loop {
+        tokio::select! {
+            message = messages.recv() => {
+                let Some(message) = message else { break Ok(()) };
+                let subscribed = subscriptions
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .any(|topic| ZMQ_SEQUENCE_TOPIC.starts_with(topic));
+                if subscribed {
+                    if let Err(e) = writer.write_all(&message).await {
+                        break Err(e.into());
+                    }
+                }
+            }
+            read = &mut reading => break read.map_err(|e| anyhow!(e)).and_then(|read| read),
+        }
+    };
+    reading.abort();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mempool::RemovalReason;
+    use crate::shutdown::ShutdownCoordinator;
+
+    /// Connect and subscribe the way a ZMTP 3.0 SUB socket does
+    async fn subscribe(addr: SocketAddr, topic: &[u8]) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&greeting()).await.unwrap();
+        let mut theirs = [0u8; 64];
+        stream.read_exact(&mut theirs).await.unwrap();
+        assert_eq!(&theirs[12..16], b"NULL");
+        stream.write_all(&ready_command(b"SUB")).await.unwrap();
+        let (flags, body) = read_frame(&mut stream).await.unwrap();
+        assert_eq!(flags, FLAG_COMMAND);
+        assert_eq!(ready_socket_type(&body).unwrap(), b"PUB");
+
+        let mut subscription = vec![1];
+        subscription.extend_from_slice(topic);
+        let mut frame = Vec::new();
+        write_frame(&mut frame, 0, &subscription);
+        stream.write_all(&frame).await.unwrap();
+        stream
+    }
+
+    #[tokio::test]
+    async fn test_sequence_messages_reach_subscribers() {
+        let publisher = ZmqPublisher::bind("tcp://127.0.0.1:0").await.unwrap();
+        let addr = publisher.local_addr().unwrap();
+        let (events, receiver) = broadcast::channel(16);
+        let shutdown = ShutdownCoordinator::new();
+        tokio::spawn(publisher.run(receiver, shutdown.subscribe()));
+
+        let mut stream = subscribe(addr, b"seq").await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        events
+            .send(MempoolEvent { sequence: 7, proof_hash: [1u8; 32], kind: MempoolEventKind::Added })
+            .unwrap();
+        let removed = MempoolEventKind::Removed { reason: RemovalReason::Block };
+        events.send(MempoolEvent { sequence: 8, proof_hash: [1u8; 32], kind: removed }).unwrap();
+
+        for (counter, label, sequence) in [(0u32, b'A', 7u64), (1, b'R', 8)] {
+            assert_eq!(read_frame(&mut stream).await.unwrap(), (FLAG_MORE, ZMQ_SEQUENCE_TOPIC.to_vec()));
+            let (flags, body) = read_frame(&mut stream).await.unwrap();
+            assert_eq!((flags, &body[..32], body[32]), (FLAG_MORE, &[1u8; 32][..], label));
+            assert_eq!(u64::from_le_bytes(body[33..].try_into().unwrap()), sequence);
+            assert_eq!(read_frame(&mut stream).await.unwrap(), (0, counter.to_le_bytes().to_vec()));
+        }
+
+        assert!(parse_zmq_endpoint("ipc:///tmp/exs").is_err());
+        assert!(parse_zmq_endpoint("tcp://127.0.0.1").is_err());
+        shutdown.trigger();
+    }
+}
@@ -0,0 +1,666 @@
+//! Output ledger created by forges, with an incremental set hash
+
+pub mod smt;
+pub mod snapshot;
+
+pub use smt::{LeafChanges, SmtProof, SparseMerkleTree};
+pub use snapshot::LedgerSnapshot;
+
+use crate::consensus::sighash::Transfer;
+use crate::consensus::Block;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, XOnlyPublicKey};
+use bitcoin::secp256k1::Parity;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use anyhow::{Context, Result, anyhow};
+
+/// Base units per EXS
+pub const COIN: u64 = 100_000_000;
+
+/// Reward minted by each forge
+pub const FORGE_REWARD: u64 = 50 * COIN;
+
+/// Smallest value a transfer output may carry (consensus)
+pub const MIN_OUTPUT_VALUE: u64 = 1_000;
+
+/// Default relay policy dust threshold: outputs worth less are rejected by
+/// the mempool, since spending them would cost more than they hold
+pub const DUST_THRESHOLD: u64 = 10_000;
+
+/// Supply the emission schedule has minted once `forges` forges are in the
+/// chain (a fixed reward per forge)
+pub fn expected_emission(forges: u64) -> u64 {
+    forges.saturating_mul(FORGE_REWARD)
+}
+
+/// Whether an output of `value` is dust under `dust_threshold`
+pub fn is_dust(value: u64, dust_threshold: u64) -> bool {
+    value < dust_threshold
+}
+
+/// Consensus checks on a transfer's outputs and declared fee
+pub fn check_transfer_outputs(transfer: &Transfer) -> Result<()> {
+    if transfer.inputs.is_empty() || transfer.outputs.is_empty() {
+        return Err(anyhow!("Transfer must have inputs and outputs"));
+    }
+    let mut output_total: u64 = 0;
+    for (index, output) in transfer.outputs.iter().enumerate() {
+        if output.value < MIN_OUTPUT_VALUE {
+            return Err(anyhow!(
+                "Transfer output {} value {} is below the minimum {}",
+                index,
+                output.value,
+                MIN_OUTPUT_VALUE
+            ));
+        }
+        output_total = output_total
+            .checked_add(output.value)
+            .ok_or_else(|| anyhow!("Transfer output value overflow"))?;
+    }
+    let input_total = transfer
+        .inputs
+        .iter()
+        .try_fold(0u64, |total, input| total.checked_add(input.amount))
+        .ok_or_else(|| anyhow!("Transfer input value overflow"))?;
+    if input_total.checked_sub(output_total) != Some(transfer.fee) {
+        return Err(anyhow!(
+            "Transfer fee {} does not match inputs {} minus outputs {}",
+            transfer.fee,
+            input_total,
+            output_total
+        ));
+    }
+    Ok(())
+}
+
+/// Reference to a ledger output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct OutPoint {
+    /// Hash of the creating transaction (the proof hash for forges)
+    pub txid: [u8; 32],
+    pub vout: u32,
+}
+
+/// An unspent ledger output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerOutput {
+    pub address: String,
+    pub value: u64,
+    /// Height of the block that created the output
+    pub height: u64,
+}
+
+/// Summary of the output set at a height (like `gettxoutsetinfo`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerSetInfo {
+    pub height: u64,
+    pub output_count: u64,
+    pub total_value: u64,
+    pub set_hash: [u8; 32],
+}
+
+/// Supply audit of the output set against the emission schedule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SupplyInfo {
+    pub height: u64,
+    /// Sum of all unspent outputs
+    pub circulating: u64,
+    pub output_count: u64,
+    /// Transfer fees removed from the supply (fees are not paid out)
+    pub burned_fees: u64,
+    /// Unspent value held by the treasury addresses
+    pub treasury_balance: u64,
+    pub forges: u64,
+    /// Minted by the emission schedule for `forges` forges
+    pub expected_emission: u64,
+    /// `expected_emission` less `burned_fees`
+    pub expected_supply: u64,
+    /// `circulating` minus `expected_supply`
+    pub difference: i64,
+    /// Whether the output set disagrees with the schedule or with the
+    /// ledger's running total
+    pub discrepancy: bool,
+}
+
+/// Elliptic-curve multiset hash (ECMH) over secp256k1.
+///
+/// Each element is hashed onto a curve point and the set hash is the sum of
+/// all points, so inserts and removals are O(1) and independent of order.
+#[derive(Debug, Clone, Default)]
+pub struct SetHash {
+    /// Running sum of element points (`None` is the point at infinity)
+    sum: Option<PublicKey>,
+}
+
+impl SetHash {
+    const TAG: &'static [u8] = b"Excalibur/LedgerSetHash";
+
+    /// Create an empty set hash
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an element to the set
+    pub fn insert(&mut self, element: &[u8]) {
+        let point = Self::hash_to_point(element);
+        self.add_point(point);
+    }
+
+    /// Remove a previously inserted element from the set
+    pub fn remove(&mut self, element: &[u8]) {
+        let secp = Secp256k1::verification_only();
+        let point = Self::hash_to_point(element).negate(&secp);
+        self.add_point(point);
+    }
+
+    /// 32-byte digest of the current set
+    pub fn digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(Self::TAG);
+        if let Some(sum) = &self.sum {
+            hasher.update(sum.serialize());
+        }
+        hasher.finalize().into()
+    }
+
+    fn add_point(&mut self, point: PublicKey) {
+        self.sum = match self.sum {
+            None => Some(point),
+            // Summing to the point at infinity is the only possible error
+            Some(sum) => sum.combine(&point).ok(),
+        };
+    }
+
+    /// Map an element to a curve point by try-and-increment
+    fn hash_to_point(element: &[u8]) -> PublicKey {
+        let mut counter: u32 = 0;
+        loop {
+            let mut hasher = Sha256::new();
+            hasher.update(Self::TAG);
+            hasher.update(element);
+            hasher.update(counter.to_le_bytes());
+            let candidate: [u8; 32] = hasher.finalize().into();
+            if let Ok(x_only) = XOnlyPublicKey::from_slice(&candidate) {
+                return PublicKey::from_x_only_public_key(x_only, Parity::Even);
+            }
+            counter += 1;
+        }
+    }
+}
+
+/// Output tree key of an outpoint
+pub fn output_tree_key(outpoint: &OutPoint) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(outpoint.txid);
+    hasher.update(outpoint.vout.to_le_bytes());
+    hasher.finalize().into()
+}
+
+/// Output tree value hash of an unspent output
+pub fn output_tree_value(outpoint: &OutPoint, output: &LedgerOutput) -> [u8; 32] {
+    Sha256::digest(Ledger::element(outpoint, output)).into()
+}
+
+/// Outputs a block spends and creates
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockChanges {
+    /// Outputs the block's transfers spend, with what they held
+    pub spent: Vec<(OutPoint, LedgerOutput)>,
+    /// Outputs of the block's forges, then of its transfers
+    pub created: Vec<(OutPoint, LedgerOutput)>,
+    /// Outputs each transfer spends, in input order
+    pub transfer_inputs: Vec<Vec<LedgerOutput>>,
+    /// Fees of the block's transfers
+    pub fees: u64,
+}
+
+impl BlockChanges {
+    /// Changes of a block as if it held only its forges
+    pub fn forges_only(block: &Block) -> Self {
+        Self {
+            created: Ledger::block_outputs(block).collect(),
+            ..Self::default()
+        }
+    }
+}
+
+/// Unspent output ledger
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    outputs: HashMap<OutPoint, LedgerOutput>,
+    set_hash: SetHash,
+    /// Sparse merkle tree of the outputs, for inclusion proofs
+    tree: SparseMerkleTree,
+    total_value: u64,
+    /// Transfer fees burned by applied transfers
+    burned_fees: u64,
+    height: u64,
+}
+
+impl Ledger {
+    /// Create an empty ledger
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Outputs created by a block's forges
+    pub fn block_outputs(block: &Block) -> impl Iterator<Item = (OutPoint, LedgerOutput)> + '_ {
+        block.forges.iter().map(|forge| {
+            (
+                OutPoint {
+                    txid: forge.proof_hash,
+                    vout: 0,
+                },
+                LedgerOutput {
+                    address: forge.taproot_address.clone(),
+                    value: FORGE_REWARD,
+                    height: block.header.height,
+                },
+            )
+        })
+    }
+
+    /// Outputs created by a transfer included at `height`
+    pub fn transfer_outputs(transfer: &Transfer, height: u64) -> Result<Vec<(OutPoint, LedgerOutput)>> {
+        let txid = transfer.txid()?;
+        Ok(transfer
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(vout, output)| {
+                (
+                    OutPoint { txid, vout: vout as u32 },
+                    LedgerOutput {
+                        address: output.address.clone(),
+                        value: output.value,
+                        height,
+                    },
+                )
+            })
+            .collect())
+    }
+
+    /// Add the outputs created by a block's forges, then apply its transfers
+    pub fn apply_block(&mut self, block: &Block) -> Result<()> {
+        for (outpoint, output) in Self::block_outputs(block) {
+            self.add_output(outpoint, output)?;
+        }
+        for transfer in &block.transfers {
+            self.apply_transfer(&transfer.transfer, block.header.height)?;
+        }
+        self.height = block.header.height;
+        Ok(())
+    }
+
+    /// What applying `block` would spend and create, without changing the
+    /// ledger. Its transfers must each be includable at the block's height
+    /// (`spent_outputs`), may only spend outputs that existed before the
+    /// block, and may not spend an output twice. Witnesses aren't checked.
+    pub fn block_changes(&self, block: &Block) -> Result<BlockChanges> {
+        let height = block.header.height;
+        let mut changes = BlockChanges::forges_only(block);
+        let mut spent = HashSet::new();
+        for (index, signed) in block.transfers.iter().enumerate() {
+            let transfer = &signed.transfer;
+            let inputs = self
+                .spent_outputs(transfer, height)
+                .with_context(|| format!("Transfer {} can't be applied", index))?;
+            for (input, output) in transfer.inputs.iter().zip(&inputs) {
+                if !spent.insert(input.prevout) {
+                    return Err(anyhow!(
+                        "Output {}:{} is spent twice in the block",
+                        hex::encode(input.prevout.txid),
+                        input.prevout.vout
+                    ));
+                }
+                changes.spent.push((input.prevout, output.clone()));
+            }
+            changes.fees = changes
+                .fees
+                .checked_add(transfer.fee)
+                .ok_or_else(|| anyhow!("Block fee overflow"))?;
+            changes.created.extend(Self::transfer_outputs(transfer, height)?);
+            changes.transfer_inputs.push(inputs);
+        }
+
+        let mut added = HashSet::new();
+        for (outpoint, _) in &changes.created {
+            if self.outputs.contains_key(outpoint) || !added.insert(*outpoint) {
+                return Err(anyhow!(
+                    "Output {}:{} already exists",
+                    hex::encode(outpoint.txid),
+                    outpoint.vout
+                ));
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Statistics the output set would have at `height` after `changes`
+    pub fn info_after(&self, height: u64, changes: &BlockChanges) -> Result<LedgerSetInfo> {
+        let mut set_hash = self.set_hash.clone();
+        let mut total_value = self.total_value;
+        for (outpoint, output) in &changes.spent {
+            total_value -= output.value;
+            set_hash.remove(&Self::element(outpoint, output));
+        }
+        for (outpoint, output) in &changes.created {
+            total_value = total_value
+                .checked_add(output.value)
+                .ok_or_else(|| anyhow!("Ledger value overflow"))?;
+            set_hash.insert(&Self::element(outpoint, output));
+        }
+        Ok(LedgerSetInfo {
+            height,
+            output_count: (self.outputs.len() - changes.spent.len() + changes.created.len()) as u64,
+            total_value,
+            set_hash: set_hash.digest(),
+        })
+    }
+
+    /// Outputs `transfer` spends, in input order, if it can be included at
+    /// `height`: it must pass the consensus output checks, be unlocked at
+    /// `height`, and spend unspent outputs holding the amounts and
+    /// addresses its inputs claim
+    pub fn spent_outputs(&self, transfer: &Transfer, height: u64) -> Result<Vec<LedgerOutput>> {
+        check_transfer_outputs(transfer)?;
+        if transfer.lock_height > height {
+            return Err(anyhow!(
+                "Transfer locked until height {} included at height {}",
+                transfer.lock_height,
+                height
+            ));
+        }
+        transfer
+            .inputs
+            .iter()
+            .map(|input| match self.outputs.get(&input.prevout) {
+                Some(spent) if spent.value == input.amount && spent.address == input.address => Ok(spent.clone()),
+                Some(_) => Err(anyhow!(
+                    "Transfer input {}:{} does not match the spent output",
+                    hex::encode(input.prevout.txid),
+                    input.prevout.vout
+                )),
+                None => Err(anyhow!(
+                    "Output {}:{} is missing or already spent",
+                    hex::encode(input.prevout.txid),
+                    input.prevout.vout
+                )),
+            })
+            .collect()
+    }
+
+    /// Spend a transfer's inputs and add its outputs at `height`
+    pub fn apply_transfer(&mut self, transfer: &Transfer, height: u64) -> Result<()> {
+        self.spent_outputs(transfer, height)?;
+        let outputs = Self::transfer_outputs(transfer, height)?;
+        for input in &transfer.inputs {
+            self.spend_output(&input.prevout)?;
+        }
+        self.burned_fees = self.burned_fees.saturating_add(transfer.fee);
+        for (outpoint, output) in outputs {
+            self.add_output(outpoint, output)?;
+        }
+        Ok(())
+    }
+
+    /// Add an unspent output
+    pub fn add_output(&mut self, outpoint: OutPoint, output: LedgerOutput) -> Result<()> {
+        if self.outputs.contains_key(&outpoint) {
+            return Err(anyhow!(
+                "Output {}:{} already exists",
+                hex::encode(outpoint.txid),
+                outpoint.vout
+            ));
+        }
+        self.total_value = self
+            .total_value
+            .checked_add(output.value)
+            .ok_or_else(|| anyhow!("Ledger value overflow"))?;
+        self.set_hash.insert(&Self::element(&outpoint, &output));
+        self.tree.insert(output_tree_key(&outpoint), output_tree_value(&outpoint, &output));
+        self.outputs.insert(outpoint, output);
+        Ok(())
+    }
+
+    /// Spend (remove) an unspent output
+    pub fn spend_output(&mut self, outpoint: &OutPoint) -> Result<LedgerOutput> {
+        let output = self.outputs.remove(outpoint).ok_or_else(|| {
+            anyhow!(
+                "Output {}:{} is missing or already spent",
+                hex::encode(outpoint.txid),
+                outpoint.vout
+            )
+        })?;
+        self.total_value -= output.value;
+        self.set_hash.remove(&Self::element(outpoint, &output));
+        self.tree.remove(&output_tree_key(outpoint));
+        Ok(output)
+    }
+
+    /// Iterate over all unspent outputs
+    pub fn outputs(&self) -> impl Iterator<Item = (&OutPoint, &LedgerOutput)> {
+        self.outputs.iter()
+    }
+
+    /// Set the height the ledger reflects
+    pub fn set_height(&mut self, height: u64) {
+        self.height = height;
+    }
+
+    /// Look up an unspent output
+    pub fn get_output(&self, outpoint: &OutPoint) -> Option<&LedgerOutput> {
+        self.outputs.get(outpoint)
+    }
+
+    /// Statistics and set hash of the current output set
+    pub fn info(&self) -> LedgerSetInfo {
+        LedgerSetInfo {
+            height: self.height,
+            output_count: self.outputs.len() as u64,
+            total_value: self.total_value,
+            set_hash: self.set_hash.digest(),
+        }
+    }
+
+    /// Audit the supply: sums the unspent outputs and compares them with
+    /// the emission for `forges` forges less burned fees
+    pub fn supply_info(&self, forges: u64, treasury_addresses: &[String]) -> SupplyInfo {
+        let (circulating, treasury_balance) =
+            self.outputs.values().fold((0u64, 0u64), |(total, treasury), output| {
+                let treasury = if treasury_addresses.contains(&output.address) {
+                    treasury.saturating_add(output.value)
+                } else {
+                    treasury
+                };
+                (total.saturating_add(output.value), treasury)
+            });
+        let expected_emission = expected_emission(forges);
+        let expected_supply = expected_emission.saturating_sub(self.burned_fees);
+        let difference = i128::from(circulating) - i128::from(expected_supply);
+        SupplyInfo {
+            height: self.height,
+            circulating,
+            output_count: self.outputs.len() as u64,
+            burned_fees: self.burned_fees,
+            treasury_balance,
+            forges,
+            expected_emission,
+            expected_supply,
+            difference: difference.clamp(i64::MIN.into(), i64::MAX.into()) as i64,
+            discrepancy: difference != 0 || circulating != self.total_value,
+        }
+    }
+
+    /// Root of the output tree
+    pub fn output_root(&self) -> [u8; 32] {
+        self.tree.root()
+    }
+
+    /// Root of the output tree once `changes` are applied
+    pub fn output_root_after(&self, changes: &BlockChanges) -> [u8; 32] {
+        let leaves: LeafChanges = changes
+            .spent
+            .iter()
+            .map(|(outpoint, _)| (output_tree_key(outpoint), None))
+            .chain(
+                changes
+                    .created
+                    .iter()
+                    .map(|(outpoint, output)| (output_tree_key(outpoint), Some(output_tree_value(outpoint, output)))),
+            )
+            .collect();
+        self.tree.root_with(&leaves)
+    }
+
+    /// Proof that an output is (or is not) unspent, against `output_root`
+    pub fn prove_output(&self, outpoint: &OutPoint) -> SmtProof {
+        self.tree.prove(&output_tree_key(outpoint))
+    }
+
+    /// Canonical serialization of an output for set hashing
+    fn element(outpoint: &OutPoint, output: &LedgerOutput) -> Vec<u8> {
+        let mut data = Vec::with_capacity(52 + output.address.len());
+        data.extend_from_slice(&outpoint.txid);
+        data.extend_from_slice(&outpoint.vout.to_le_bytes());
+        data.extend_from_slice(&output.value.to_le_bytes());
+        data.extend_from_slice(&output.height.to_le_bytes());
+        data.extend_from_slice(output.address.as_bytes());
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(value: u64) -> LedgerOutput {
+        LedgerOutput {
+            address: "bc1p...".to_string(),
+            value,
+            height: 1,
+        }
+    }
+
+    fn outpoint(n: u8) -> OutPoint {
+        OutPoint { txid: [n; 32], vout: 0 }
+    }
+
+    #[test]
+    fn test_set_hash_order_independent() {
+        let mut a = SetHash::new();
+        a.insert(b"one");
+        a.insert(b"two");
+
+        let mut b = SetHash::new();
+        b.insert(b"two");
+        b.insert(b"one");
+
+        assert_eq!(a.digest(), b.digest());
+    }
+
+    #[test]
+    fn test_set_hash_remove() {
+        let empty = SetHash::new().digest();
+
+        let mut hash = SetHash::new();
+        hash.insert(b"one");
+        let one = hash.digest();
+        hash.insert(b"two");
+        assert_ne!(hash.digest(), one);
+
+        hash.remove(b"two");
+        assert_eq!(hash.digest(), one);
+        hash.remove(b"one");
+        assert_eq!(hash.digest(), empty);
+    }
+
+    #[test]
+    fn test_ledger_add_and_spend() {
+        let mut ledger = Ledger::new();
+        let empty = ledger.info();
+
+        ledger.add_output(outpoint(1), output(10)).unwrap();
+        ledger.add_output(outpoint(2), output(20)).unwrap();
+        assert!(ledger.add_output(outpoint(1), output(10)).is_err());
+
+        let info = ledger.info();
+        assert_eq!(info.output_count, 2);
+        assert_eq!(info.total_value, 30);
+
+        ledger.spend_output(&outpoint(1)).unwrap();
+        ledger.spend_output(&outpoint(2)).unwrap();
+        assert!(ledger.spend_output(&outpoint(2)).is_err());
+        assert_eq!(ledger.info().set_hash, empty.set_hash);
+        assert_eq!(ledger.info().total_value, 0);
+    }
+
+    #[test]
+    fn test_transfer_output_minimum() {
+        use crate::consensus::sighash::{TransferInput, TransferOutput};
+
+        let mut ledger = Ledger::new();
+        ledger.add_output(outpoint(1), output(50_000)).unwrap();
+
+        let mut transfer = Transfer {
+            version: 1,
+            inputs: vec![TransferInput {
+                prevout: outpoint(1),
+                amount: 50_000,
+                address: "bc1p...".to_string(),
+            }],
+            outputs: vec![
+                TransferOutput { address: "bc1q...".to_string(), value: 40_000 },
+                TransferOutput { address: "bc1p...".to_string(), value: MIN_OUTPUT_VALUE - 1 },
+            ],
+            fee: 10_000 - MIN_OUTPUT_VALUE + 1,
+            lock_height: 0,
+        };
+        assert!(ledger.apply_transfer(&transfer, 2).is_err());
+
+        transfer.outputs[1].value = MIN_OUTPUT_VALUE;
+        assert!(ledger.apply_transfer(&transfer, 2).is_err(), "fee no longer balances");
+        transfer.fee = 10_000 - MIN_OUTPUT_VALUE;
+        ledger.apply_transfer(&transfer, 2).unwrap();
+
+        let info = ledger.info();
+        assert_eq!(info.output_count, 2);
+        assert_eq!(info.total_value, 40_000 + MIN_OUTPUT_VALUE);
+        assert!(ledger.apply_transfer(&transfer, 3).is_err(), "inputs already spent");
+    }
+
+    #[test]
+    fn test_supply_info() {
+        use crate::consensus::sighash::{TransferInput, TransferOutput};
+
+        let mut ledger = Ledger::new();
+        ledger.add_output(outpoint(1), output(FORGE_REWARD)).unwrap();
+        ledger.add_output(outpoint(2), output(FORGE_REWARD)).unwrap();
+        let transfer = Transfer {
+            version: 1,
+            inputs: vec![TransferInput {
+                prevout: outpoint(1),
+                amount: FORGE_REWARD,
+                address: "bc1p...".to_string(),
+            }],
+            outputs: vec![TransferOutput { address: "treasury".to_string(), value: FORGE_REWARD - 5_000 }],
+            fee: 5_000,
+            lock_height: 0,
+        };
+        ledger.apply_transfer(&transfer, 2).unwrap();
+
+        let info = ledger.supply_info(2, &["treasury".to_string()]);
+        assert_eq!(info.circulating, 2 * FORGE_REWARD - 5_000);
+        assert_eq!(info.burned_fees, 5_000);
+        assert_eq!(info.treasury_balance, FORGE_REWARD - 5_000);
+        assert_eq!(info.expected_supply, info.circulating);
+        assert!(!info.discrepancy);
+
+        // Value the schedule never minted
+        ledger.add_output(outpoint(3), output(10_000)).unwrap();
+        let info = ledger.supply_info(2, &[]);
+        assert_eq!((info.difference, info.treasury_balance), (10_000, 0));
+        assert!(info.discrepancy);
+    }
+}
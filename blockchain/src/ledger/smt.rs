@@ -0,0 +1,353 @@
+//! Sparse merkle tree over 256-bit keys
+//!
+//! Leaves sit at the path spelled by their key's bits, most significant
+//! first. A subtree holding a single leaf hashes to that leaf wherever it
+//! sits, and an empty subtree hashes to zero, so only subtrees with two or
+//! more leaves cost an internal node and a proof is as long as the point
+//! where the key's subtree thins out to one leaf (about log2 of the leaf
+//! count). Internal node hashes are cached, so an update rehashes one path.
+//!
+//! ```text
+//! leaf  = sha256(0x00 | key | value hash)
+//! node  = sha256(0x01 | left | right)
+//! empty = 0^32
+//! ```
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound::{Excluded, Unbounded};
+
+/// Hash of an empty subtree
+pub const EMPTY_SUBTREE: [u8; 32] = [0u8; 32];
+
+const KEY_BITS: usize = 256;
+
+/// Pending leaf changes: a new value hash, or `None` to remove the key
+pub type LeafChanges = BTreeMap<[u8; 32], Option<[u8; 32]>>;
+
+/// Internal node hashes by (depth, key prefix); `None` drops the node
+type NodeChanges = HashMap<(usize, [u8; 32]), Option<[u8; 32]>>;
+
+/// Sparse merkle tree of value hashes
+#[derive(Debug, Clone, Default)]
+pub struct SparseMerkleTree {
+    leaves: BTreeMap<[u8; 32], [u8; 32]>,
+    /// Hashes of subtrees holding at least two leaves
+    nodes: HashMap<(usize, [u8; 32]), [u8; 32]>,
+}
+
+/// Path from a key's position to the root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmtProof {
+    /// Sibling hashes from the root down
+    pub siblings: Vec<[u8; 32]>,
+    /// The single leaf in the key's subtree below the last sibling, if any.
+    /// For a present key this is the key itself.
+    pub leaf: Option<([u8; 32], [u8; 32])>,
+}
+
+impl SparseMerkleTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn get(&self, key: &[u8; 32]) -> Option<[u8; 32]> {
+        self.leaves.get(key).copied()
+    }
+
+    pub fn insert(&mut self, key: [u8; 32], value_hash: [u8; 32]) {
+        self.apply(&LeafChanges::from([(key, Some(value_hash))]));
+    }
+
+    pub fn remove(&mut self, key: &[u8; 32]) {
+        self.apply(&LeafChanges::from([(*key, None)]));
+    }
+
+    /// Current root
+    pub fn root(&self) -> [u8; 32] {
+        self.subtree_hash(&LeafChanges::new(), &NodeChanges::new(), 0, &[0u8; 32])
+    }
+
+    /// Root the tree would have after `changes`, without applying them
+    pub fn root_with(&self, changes: &LeafChanges) -> [u8; 32] {
+        let nodes = self.rehash(changes);
+        self.subtree_hash(changes, &nodes, 0, &[0u8; 32])
+    }
+
+    /// Apply a batch of leaf changes
+    pub fn apply(&mut self, changes: &LeafChanges) {
+        let nodes = self.rehash(changes);
+        for (key, value) in changes {
+            match value {
+                Some(value) => self.leaves.insert(*key, *value),
+                None => self.leaves.remove(key),
+            };
+        }
+        for (position, hash) in nodes {
+            match hash {
+                Some(hash) => self.nodes.insert(position, hash),
+                None => self.nodes.remove(&position),
+            };
+        }
+    }
+
+    /// Proof of a key's presence (with its value) or absence
+    pub fn prove(&self, key: &[u8; 32]) -> SmtProof {
+        let (changes, nodes) = (LeafChanges::new(), NodeChanges::new());
+        let mut siblings = Vec::new();
+        let mut depth = 0;
+        loop {
+            let leaves = self.leaves_below(&changes, depth, &prefix(key, depth));
+            if leaves.len() < 2 {
+                return SmtProof {
+                    siblings,
+                    leaf: leaves.first().copied(),
+                };
+            }
+            let sibling = flip_bit(&prefix(key, depth + 1), depth);
+            siblings.push(self.subtree_hash(&changes, &nodes, depth + 1, &sibling));
+            depth += 1;
+        }
+    }
+
+    /// Recompute the internal nodes on the paths of changed keys, bottom up
+    fn rehash(&self, changes: &LeafChanges) -> NodeChanges {
+        // Below the longest prefix a key shares with another leaf, before
+        // or after the changes, its subtree never holds a node
+        let deepest: HashMap<[u8; 32], usize> =
+            changes.keys().map(|key| (*key, self.shared_prefix_len(changes, key))).collect();
+        let mut nodes = NodeChanges::new();
+        for depth in (0..KEY_BITS).rev() {
+            let mut done = HashSet::new();
+            for key in changes.keys() {
+                if depth > deepest[key] {
+                    continue;
+                }
+                let position = prefix(key, depth);
+                if !done.insert(position) {
+                    continue;
+                }
+                let hash = if self.leaves_below(changes, depth, &position).len() < 2 {
+                    None
+                } else {
+                    let left = self.subtree_hash(changes, &nodes, depth + 1, &position);
+                    let right = self.subtree_hash(changes, &nodes, depth + 1, &flip_bit(&position, depth));
+                    Some(node_hash(&left, &right))
+                };
+                nodes.insert((depth, position), hash);
+            }
+        }
+        nodes
+    }
+
+    /// Longest prefix `key` shares with any other key in the tree or in `changes`
+    fn shared_prefix_len(&self, changes: &LeafChanges, key: &[u8; 32]) -> usize {
+        let neighbours = [
+            self.leaves.range(..*key).next_back().map(|(other, _)| other),
+            self.leaves.range((Excluded(*key), Unbounded)).next().map(|(other, _)| other),
+            changes.range(..*key).next_back().map(|(other, _)| other),
+            changes.range((Excluded(*key), Unbounded)).next().map(|(other, _)| other),
+        ];
+        neighbours
+            .into_iter()
+            .flatten()
+            .map(|other| common_prefix_len(key, other))
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn subtree_hash(&self, changes: &LeafChanges, nodes: &NodeChanges, depth: usize, position: &[u8; 32]) -> [u8; 32] {
+        let leaves = self.leaves_below(changes, depth, position);
+        match leaves.as_slice() {
+            [] => EMPTY_SUBTREE,
+            [(key, value)] => leaf_hash(key, value),
+            _ => match nodes.get(&(depth, *position)) {
+                Some(hash) => hash.expect("subtree with two leaves has a node"),
+                None => self.nodes[&(depth, *position)],
+            },
+        }
+    }
+
+    /// Up to two leaves under a subtree, with `changes` applied
+    fn leaves_below(&self, changes: &LeafChanges, depth: usize, position: &[u8; 32]) -> Vec<([u8; 32], [u8; 32])> {
+        let (low, high) = (*position, fill_below(position, depth));
+        let mut leaves: Vec<_> = self
+            .leaves
+            .range(low..=high)
+            .filter(|(key, _)| !changes.contains_key(*key))
+            .take(2)
+            .map(|(key, value)| (*key, *value))
+            .collect();
+        let remaining = 2 - leaves.len();
+        leaves.extend(
+            changes
+                .range(low..=high)
+                .filter_map(|(key, value)| value.map(|value| (*key, value)))
+                .take(remaining),
+        );
+        leaves
+    }
+}
+
+impl SmtProof {
+    /// Check the proof against `root`: `value_hash` is the key's value, or
+    /// `None` to prove the key is absent
+    pub fn verify(&self, root: &[u8; 32], key: &[u8; 32], value_hash: Option<&[u8; 32]>) -> bool {
+        if self.siblings.len() > KEY_BITS {
+            return false;
+        }
+        let depth = self.siblings.len();
+        let leaf_matches = match (&self.leaf, value_hash) {
+            (Some((leaf_key, leaf_value)), Some(value)) => leaf_key == key && leaf_value == value,
+            (Some((leaf_key, _)), None) => leaf_key != key && prefix(leaf_key, depth) == prefix(key, depth),
+            (None, Some(_)) => false,
+            (None, None) => true,
+        };
+        if !leaf_matches {
+            return false;
+        }
+
+        let mut hash = self
+            .leaf
+            .map(|(leaf_key, leaf_value)| leaf_hash(&leaf_key, &leaf_value))
+            .unwrap_or(EMPTY_SUBTREE);
+        for (depth, sibling) in self.siblings.iter().enumerate().rev() {
+            hash = if bit(key, depth) { node_hash(sibling, &hash) } else { node_hash(&hash, sibling) };
+        }
+        &hash == root
+    }
+}
+
+fn leaf_hash(key: &[u8; 32], value_hash: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0x00]);
+    hasher.update(key);
+    hasher.update(value_hash);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0x01]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+fn common_prefix_len(a: &[u8; 32], b: &[u8; 32]) -> usize {
+    a.iter()
+        .zip(b.iter())
+        .position(|(x, y)| x != y)
+        .map(|byte| byte * 8 + (a[byte] ^ b[byte]).leading_zeros() as usize)
+        .unwrap_or(KEY_BITS)
+}
+
+fn bit(key: &[u8; 32], index: usize) -> bool {
+    key[index / 8] & (0x80 >> (index % 8)) != 0
+}
+
+fn flip_bit(key: &[u8; 32], index: usize) -> [u8; 32] {
+    let mut flipped = *key;
+    flipped[index / 8] ^= 0x80 >> (index % 8);
+    flipped
+}
+
+/// The first `depth` bits of `key`, zero-filled
+fn prefix(key: &[u8; 32], depth: usize) -> [u8; 32] {
+    let mut masked = [0u8; 32];
+    let (bytes, bits) = (depth / 8, depth % 8);
+    masked[..bytes].copy_from_slice(&key[..bytes]);
+    if bits > 0 {
+        masked[bytes] = key[bytes] & (0xffu8 << (8 - bits));
+    }
+    masked
+}
+
+/// The last key under the subtree at `position`, `depth` bits down
+fn fill_below(position: &[u8; 32], depth: usize) -> [u8; 32] {
+    let mut filled = *position;
+    let (bytes, bits) = (depth / 8, depth % 8);
+    if bytes < 32 {
+        filled[bytes] |= 0xffu8 >> bits;
+        filled[bytes + 1..].fill(0xff);
+    }
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(n: u8) -> [u8; 32] {
+        Sha256::digest([n]).into()
+    }
+
+    #[test]
+    fn test_root_is_order_independent_and_batches_match() {
+        let mut forward = SparseMerkleTree::new();
+        let mut backward = SparseMerkleTree::new();
+        assert_eq!(forward.root(), EMPTY_SUBTREE);
+        for n in 0..40 {
+            forward.insert(key(n), [n; 32]);
+            backward.insert(key(39 - n), [39 - n; 32]);
+        }
+        assert_eq!(forward.root(), backward.root());
+
+        // A single leaf is its own root
+        let mut single = SparseMerkleTree::new();
+        single.insert(key(1), [1; 32]);
+        assert_eq!(single.root(), leaf_hash(&key(1), &[1; 32]));
+
+        // root_with predicts apply, and removals restore earlier roots
+        let before = forward.root();
+        let changes = LeafChanges::from([(key(100), Some([9; 32])), (key(3), None), (key(4), Some([0; 32]))]);
+        let predicted = forward.root_with(&changes);
+        assert_eq!(forward.root(), before);
+        forward.apply(&changes);
+        assert_eq!(forward.root(), predicted);
+        forward.apply(&LeafChanges::from([(key(100), None), (key(3), Some([3; 32])), (key(4), Some([4; 32]))]));
+        assert_eq!(forward.root(), before);
+
+        for n in 0..40 {
+            forward.remove(&key(n));
+        }
+        assert_eq!(forward.root(), EMPTY_SUBTREE);
+        assert!(forward.nodes.is_empty());
+    }
+
+    #[test]
+    fn test_inclusion_and_exclusion_proofs() {
+        let mut tree = SparseMerkleTree::new();
+        for n in 0..25 {
+            tree.insert(key(n), [n; 32]);
+        }
+        let root = tree.root();
+
+        let proof = tree.prove(&key(7));
+        assert!(proof.verify(&root, &key(7), Some(&[7; 32])));
+        assert!(!proof.verify(&root, &key(7), Some(&[8; 32])));
+        assert!(!proof.verify(&root, &key(7), None));
+        assert!(!proof.verify(&tree.root_with(&LeafChanges::from([(key(99), Some([0; 32]))])), &key(7), Some(&[7; 32])));
+
+        let absent = tree.prove(&key(200));
+        assert!(absent.verify(&root, &key(200), None));
+        assert!(!absent.verify(&root, &key(200), Some(&[0; 32])));
+
+        // A present key can't be passed off as absent
+        let mut forged = proof.clone();
+        forged.leaf = None;
+        assert!(!forged.verify(&root, &key(7), None));
+
+        let empty = SparseMerkleTree::new();
+        assert!(empty.prove(&key(1)).verify(&EMPTY_SUBTREE, &key(1), None));
+    }
+}
@@ -0,0 +1,104 @@
+//! Trusted ledger snapshots (assumeutxo)
+
+use super::{Ledger, LedgerOutput, OutPoint};
+use crate::chain::ProphecyOwner;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use anyhow::{Context, Result, anyhow};
+
+/// Magic prefix of snapshot files
+const SNAPSHOT_MAGIC: &[u8; 7] = b"EXSSNAP";
+/// Current snapshot file format version
+const SNAPSHOT_VERSION: u8 = 3;
+
+/// Serialized ledger and replay-protection state at a block height
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerSnapshot {
+    /// Height of the last block included in the snapshot
+    pub height: u64,
+    /// Hash of the block at `height`
+    pub block_hash: [u8; 32],
+    /// Forge difficulty in force for the block after `height`
+    pub difficulty: u32,
+    /// Total header work up to and including `height`, big-endian
+    pub chainwork: [u8; 32],
+    /// Unspent outputs, sorted by outpoint
+    pub outputs: Vec<(OutPoint, LedgerOutput)>,
+    /// Used proof hashes and the height they were used at, sorted by hash
+    pub used_proofs: Vec<([u8; 32], u64)>,
+    /// First forge of each prophecy, by `prophecy_registry_hash`, sorted
+    /// by hash
+    pub prophecy_owners: Vec<([u8; 32], ProphecyOwner)>,
+}
+
+impl LedgerSnapshot {
+    /// Hash committed in `NetworkParams::assume_utxo`.
+    ///
+    /// Commits to the height, block hash, difficulty and chainwork, the
+    /// ledger set hash, every used proof hash so a snapshot can't weaken
+    /// replay protection, and every prophecy owner so it can't rewrite the
+    /// registry.
+    pub fn snapshot_hash(&self) -> Result<[u8; 32]> {
+        let ledger = self.to_ledger()?;
+        let info = ledger.info();
+
+        let mut hasher = Sha256::new();
+        hasher.update(SNAPSHOT_MAGIC);
+        hasher.update(self.height.to_le_bytes());
+        hasher.update(self.block_hash);
+        hasher.update(self.difficulty.to_le_bytes());
+        hasher.update(self.chainwork);
+        hasher.update(info.set_hash);
+        hasher.update(info.output_count.to_le_bytes());
+        hasher.update((self.used_proofs.len() as u64).to_le_bytes());
+        for (proof_hash, height) in &self.used_proofs {
+            hasher.update(proof_hash);
+            hasher.update(height.to_le_bytes());
+        }
+        hasher.update((self.prophecy_owners.len() as u64).to_le_bytes());
+        for (prophecy_hash, owner) in &self.prophecy_owners {
+            hasher.update(prophecy_hash);
+            hasher.update((owner.owner.len() as u64).to_le_bytes());
+            hasher.update(owner.owner.as_bytes());
+            hasher.update(owner.height.to_le_bytes());
+            hasher.update(owner.proof_hash);
+        }
+        Ok(hasher.finalize().into())
+    }
+
+    /// Rebuild the ledger contained in the snapshot
+    pub fn to_ledger(&self) -> Result<Ledger> {
+        let mut ledger = Ledger::new();
+        for (outpoint, output) in &self.outputs {
+            ledger.add_output(*outpoint, output.clone())?;
+        }
+        ledger.set_height(self.height);
+        Ok(ledger)
+    }
+
+    /// Write the snapshot to a file
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut data = Vec::new();
+        data.extend_from_slice(SNAPSHOT_MAGIC);
+        data.push(SNAPSHOT_VERSION);
+        data.extend_from_slice(&bincode::serialize(self)?);
+        std::fs::write(path.as_ref(), data)
+            .with_context(|| format!("Failed to write snapshot {}", path.as_ref().display()))
+    }
+
+    /// Read a snapshot from a file
+    pub fn read_from<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let data = std::fs::read(path.as_ref())
+            .with_context(|| format!("Failed to read snapshot {}", path.as_ref().display()))?;
+        let header_len = SNAPSHOT_MAGIC.len() + 1;
+        if data.len() < header_len || &data[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC {
+            return Err(anyhow!("Not a ledger snapshot file"));
+        }
+        let version = data[SNAPSHOT_MAGIC.len()];
+        if version != SNAPSHOT_VERSION {
+            return Err(anyhow!("Unsupported snapshot version {}", version));
+        }
+        Ok(bincode::deserialize(&data[header_len..])?)
+    }
+}
@@ -1,15 +1,44 @@
 //! Excalibur EXS Blockchain Library
 
 pub mod crypto;
+pub mod codec;
 pub mod consensus;
 pub mod network;
 pub mod chain;
 pub mod mempool;
+pub mod miner;
 pub mod rpc;
+pub mod config;
+pub mod wallet;
+pub mod ledger;
+pub mod params;
+pub mod shutdown;
+pub mod metrics;
+pub mod events;
+pub mod audit;
+pub mod watchtower;
+pub mod supervisor;
+pub mod node;
+pub mod sync;
+pub mod light;
+pub mod loadgen;
+pub mod analytics;
 
-pub use crypto::{proof_of_forge, ProofOfForgeResult, CANONICAL_PROPHECY};
-pub use consensus::{ConsensusEngine, Block, BlockHeader, ForgeTransaction};
-pub use network::{NetworkManager, NetworkCommand, NetworkEvent};
-pub use chain::ChainStore;
-pub use mempool::{ForgePool, MempoolStats};
+pub use crypto::{
+    proof_of_forge, proof_of_forge_async, proof_of_forge_with_progress, proof_of_forge_with_tempering, CancelToken,
+    DerivedAddresses, ForgeCancelled, ForgeStage, ProofOfForgeResult, TemperingAlgorithm, CANONICAL_PROPHECY,
+};
+pub use consensus::{ConsensusEngine, ConsensusRule, RuleContext, Block, BlockHeader, ForgeTransaction, AuthoritySet};
+pub use network::{NetworkManager, NetworkCommand, NetworkEvent, RejectCode, RejectMessage};
+pub use chain::{ChainStore, CheckLevel, HeaderIndexEntry, ProphecyOwner, ReorgGuard};
+pub use mempool::{ForgePolicy, ForgePool, MempoolStats, MempoolSnapshotHash, MempoolEvent};
 pub use rpc::{RpcServer, JsonRpcRequest, JsonRpcResponse};
+pub use config::{ConfigBuilder, NodeConfig};
+pub use wallet::{Wallet, ForgeOptions, AddressPurpose, ExternalSigner};
+pub use ledger::{Ledger, LedgerSetInfo, LedgerSnapshot, OutPoint};
+pub use params::{ChainParams, NetworkParams};
+pub use shutdown::{ShutdownCoordinator, ShutdownSignal};
+pub use events::{Alert, AlertSeverity, BlockEvent, EventBus, NodeEvent, PeerEvent, ReorgEvent};
+pub use watchtower::{Evidence, Watchtower};
+pub use supervisor::{RestartPolicy, Supervisor};
+pub use node::{Node, NodeOptions};
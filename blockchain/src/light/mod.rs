@@ -0,0 +1,530 @@
+//! Light client mode
+//!
+//! A light client keeps block headers only. It asks full peers for the
+//! headers following its best chain, checks each against its parent with
+//! `ConsensusEngine::validate_header` as a full node does, and follows the
+//! branch with the most work. Headers live in memory; at roughly a hundred
+//! bytes each, a wallet on a constrained device can hold the whole chain.
+//!
+//! Forges are checked one at a time: a peer advertising
+//! `ServiceFlags::LIGHT_SERVE` answers `SyncRequest::GetForgeProof` with
+//! the forge, its merkle path and the block's transfer root, and the client
+//! accepts it once the path and transfer root lead to the merkle root of a
+//! header on its best chain. The forge's own
+//! proof of forge is trusted to the work on top of it, and on permissioned
+//! chains the authority signatures, which travel outside the header, are
+//! not checked.
+
+use crate::chain::BlockLocator;
+use crate::config::NodeConfig;
+use crate::consensus::{
+    commit_transfer_root, forge_leaf_hash, header_work, Block, BlockHeader, ConsensusEngine, MerkleTree,
+};
+use crate::network::sync::MAX_HEADERS_PER_REQUEST;
+use crate::network::{
+    ForgeProof, NetworkCommand, NetworkEvent, NetworkManager, RelayPreferences, ServiceFlags, SyncRequest,
+    SyncResponse,
+};
+use crate::node::NodeOptions;
+use crate::sync::HEADER_REQUEST_TIMEOUT;
+use anyhow::{anyhow, Context, Result};
+use bitcoin::pow::Work;
+use libp2p::{Multiaddr, PeerId};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// How often unanswered header and proof requests are retried
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A header and the total work of the chain it ends
+#[derive(Debug, Clone)]
+struct HeaderEntry {
+    header: BlockHeader,
+    chainwork: Work,
+}
+
+/// Validated headers of every branch seen, and the one with the most work
+#[derive(Debug, Default)]
+pub struct HeaderChain {
+    headers: HashMap<[u8; 32], HeaderEntry>,
+    /// Best chain hashes by height
+    best: Vec<[u8; 32]>,
+}
+
+impl HeaderChain {
+    /// Chain holding no headers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Height of the best tip, if any header is known
+    pub fn height(&self) -> Option<u64> {
+        self.best.len().checked_sub(1).map(|height| height as u64)
+    }
+
+    /// Hash of the best tip
+    pub fn tip(&self) -> Option<[u8; 32]> {
+        self.best.last().copied()
+    }
+
+    /// Total work of the best chain
+    pub fn chainwork(&self) -> Work {
+        self.tip()
+            .map_or(Work::from_be_bytes([0u8; 32]), |tip| self.headers[&tip].chainwork)
+    }
+
+    /// Hash of the best-chain header at `height`
+    pub fn main_chain_hash(&self, height: u64) -> Option<[u8; 32]> {
+        self.best.get(height as usize).copied()
+    }
+
+    /// Header with the given hash, on any branch
+    pub fn header(&self, hash: &[u8; 32]) -> Option<&BlockHeader> {
+        self.headers.get(hash).map(|entry| &entry.header)
+    }
+
+    /// Block locator of the best chain, tip first
+    pub fn locator(&self) -> Vec<[u8; 32]> {
+        match self.height() {
+            Some(tip) => BlockLocator::heights(tip)
+                .into_iter()
+                .map(|height| self.best[height as usize])
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Validate and add headers sent by a peer, switching the best chain
+    /// if they end a branch with more work. Returns how many were new.
+    ///
+    /// Errors mean the headers don't connect to a known header, don't link
+    /// up or fail validation; none of the batch is added then.
+    pub fn add_headers(&mut self, engine: &ConsensusEngine, headers: &[BlockHeader]) -> Result<usize> {
+        let Some(first) = headers.first() else {
+            return Ok(0);
+        };
+        let (mut parent_hash, mut chainwork) = if first.height == 0 {
+            ([0u8; 32], Work::from_be_bytes([0u8; 32]))
+        } else {
+            let parent = self
+                .headers
+                .get(&first.prev_block_hash)
+                .filter(|parent| parent.header.height + 1 == first.height)
+                .ok_or_else(|| anyhow!("Headers do not connect to a known header"))?;
+            (first.prev_block_hash, parent.chainwork)
+        };
+
+        // Check the whole batch before adding any of it
+        let mut entries = Vec::with_capacity(headers.len());
+        for (offset, header) in headers.iter().enumerate() {
+            let height = first.height + offset as u64;
+            engine
+                .validate_header(header, &parent_hash, height)
+                .with_context(|| format!("Invalid header at height {}", height))?;
+            parent_hash = engine.compute_block_hash(header);
+            chainwork = chainwork + header_work(header.bits);
+            entries.push((parent_hash, chainwork));
+        }
+
+        let mut added = 0;
+        for (header, (hash, chainwork)) in headers.iter().zip(entries) {
+            if let Entry::Vacant(entry) = self.headers.entry(hash) {
+                let header = header.clone();
+                entry.insert(HeaderEntry { header, chainwork });
+                added += 1;
+            }
+        }
+        if chainwork > self.chainwork() {
+            self.switch_to(parent_hash);
+        }
+        Ok(added)
+    }
+
+    /// Make the branch ending at `tip` the best chain
+    fn switch_to(&mut self, tip: [u8; 32]) {
+        let height = self.headers[&tip].header.height as usize;
+        self.best.truncate(height + 1);
+        self.best.resize(height + 1, [0u8; 32]);
+        let mut hash = tip;
+        for slot in self.best.iter_mut().rev() {
+            if *slot == hash {
+                break;
+            }
+            *slot = hash;
+            hash = self.headers[&hash].header.prev_block_hash;
+        }
+    }
+
+    /// Check that `proof` places its forge in a block on the best chain.
+    /// Returns the forge's confirmations.
+    pub fn verify_forge(&self, proof: &ForgeProof) -> Result<u64> {
+        let header = self
+            .header(&proof.block_hash)
+            .ok_or_else(|| anyhow!("Unknown block {}", hex::encode(proof.block_hash)))?;
+        if header.height != proof.height || self.main_chain_hash(header.height) != Some(proof.block_hash) {
+            return Err(anyhow!("Block {} is not on the best chain", hex::encode(proof.block_hash)));
+        }
+        let forge_root = MerkleTree::proof_root(&proof.proof, &forge_leaf_hash(&proof.forge));
+        if forge_root.map(|root| commit_transfer_root(root, proof.transfer_root)) != Some(header.merkle_root) {
+            return Err(anyhow!("Merkle proof does not lead to the block's merkle root"));
+        }
+        let tip = self.height().expect("the chain holds the block");
+        Ok(tip - header.height + 1)
+    }
+}
+
+/// Header-only node that verifies the inclusion of watched forges
+pub struct LightClient {
+    engine: ConsensusEngine,
+    chain: HeaderChain,
+    /// Proof hashes of the forges to verify, with the block each was found
+    /// in once verified
+    watched: BTreeMap<[u8; 32], Option<[u8; 32]>>,
+    /// Peers serving headers, with any outstanding request
+    full_peers: HashMap<PeerId, Option<Instant>>,
+    /// Peers serving forge proofs
+    proof_peers: HashSet<PeerId>,
+}
+
+impl LightClient {
+    /// Light client validating headers with `engine`'s consensus rules
+    pub fn new(engine: ConsensusEngine) -> Self {
+        Self {
+            engine,
+            chain: HeaderChain::new(),
+            watched: BTreeMap::new(),
+            full_peers: HashMap::new(),
+            proof_peers: HashSet::new(),
+        }
+    }
+
+    /// Verify the inclusion of the forge with `proof_hash`
+    pub fn watch(&mut self, proof_hash: [u8; 32]) {
+        self.watched.entry(proof_hash).or_insert(None);
+    }
+
+    /// Headers downloaded so far
+    pub fn chain(&self) -> &HeaderChain {
+        &self.chain
+    }
+
+    /// Block each watched forge was verified in, `None` while unverified
+    pub fn watched(&self) -> &BTreeMap<[u8; 32], Option<[u8; 32]>> {
+        &self.watched
+    }
+
+    /// Run until SIGINT, syncing headers from the configured peers and
+    /// those found through discovery. The client offers no services.
+    pub async fn run(mut self, config: &NodeConfig, options: &NodeOptions) -> Result<()> {
+        let listen_addr: Multiaddr = format!("/ip4/0.0.0.0/tcp/{}", options.port).parse()?;
+        let preferences = RelayPreferences {
+            services: ServiceFlags::empty(),
+            ..RelayPreferences::default()
+        };
+        let (network, commands, mut network_events) = NetworkManager::for_network(
+            listen_addr,
+            options.connect.clone(),
+            preferences,
+            &config.network.gossip,
+            &options.params,
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to start networking: {}", e))?;
+        let network_task = tokio::spawn(network.run());
+        for address in &options.connect {
+            commands.send(NetworkCommand::ConnectPeer(address.clone())).await?;
+        }
+
+        tracing::info!("Light client running on {} (P2P port {})", options.params.name, options.port);
+        let mut retry = tokio::time::interval(RETRY_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {
+                    tracing::info!("Received SIGINT, shutting down");
+                    break;
+                }
+                Some(event) = network_events.recv() => self.handle_network_event(event, &commands).await,
+                _ = retry.tick() => {
+                    let peers: Vec<PeerId> = self.full_peers.keys().copied().collect();
+                    for peer in peers {
+                        self.request_headers(peer, &commands).await;
+                    }
+                    self.request_proofs(&commands).await;
+                }
+            }
+        }
+
+        network_task.abort();
+        tracing::info!("Light client stopped at height {:?}", self.chain.height());
+        Ok(())
+    }
+
+    async fn handle_network_event(&mut self, event: NetworkEvent, commands: &mpsc::Sender<NetworkCommand>) {
+        match event {
+            NetworkEvent::PeerPreferences(peer, preferences) => {
+                if preferences.services.contains(ServiceFlags::LIGHT_SERVE) {
+                    self.proof_peers.insert(peer);
+                }
+                if preferences.services.contains(ServiceFlags::FULL) {
+                    self.full_peers.entry(peer).or_insert(None);
+                    self.request_headers(peer, commands).await;
+                }
+            }
+            NetworkEvent::PeerDisconnected(peer) | NetworkEvent::SyncFailed(peer) => {
+                self.full_peers.remove(&peer);
+                self.proof_peers.remove(&peer);
+            }
+            NetworkEvent::BlockReceived(data, peer) => {
+                // A new tip extends the chain directly; anything else means
+                // the peer knows headers we don't
+                let Ok(block) = Block::decode(&data) else { return };
+                let header = block.header;
+                if self.chain.header(&header.prev_block_hash).is_none() {
+                    self.request_headers(peer, commands).await;
+                } else if self.accept_headers(peer, &[header], commands).await {
+                    self.request_proofs(commands).await;
+                }
+            }
+            NetworkEvent::SyncRequested(_, id, request) => {
+                let _ = commands.send(NetworkCommand::RespondSync(id, request.empty_response())).await;
+            }
+            NetworkEvent::SyncResponseReceived(peer, SyncResponse::Headers { headers }) => {
+                if let Some(sent) = self.full_peers.get_mut(&peer) {
+                    *sent = None;
+                }
+                if !self.accept_headers(peer, &headers, commands).await {
+                    return;
+                }
+                if headers.len() == MAX_HEADERS_PER_REQUEST {
+                    self.request_headers(peer, commands).await;
+                } else {
+                    self.request_proofs(commands).await;
+                }
+            }
+            NetworkEvent::SyncResponseReceived(peer, SyncResponse::ForgeProof { proof: Some(proof) }) => {
+                self.proof_received(peer, &proof);
+            }
+            _ => {}
+        }
+    }
+
+    /// Add headers from `peer`, disconnecting it if they're invalid.
+    /// Returns whether the headers were accepted.
+    async fn accept_headers(
+        &mut self,
+        peer: PeerId,
+        headers: &[BlockHeader],
+        commands: &mpsc::Sender<NetworkCommand>,
+    ) -> bool {
+        let tip = self.chain.tip();
+        match self.chain.add_headers(&self.engine, headers) {
+            Ok(added) => {
+                tracing::debug!("{} new headers from {}, height {:?}", added, peer, self.chain.height());
+                if self.chain.tip() != tip {
+                    // A reorg may have dropped the blocks forges were found in
+                    let chain = &self.chain;
+                    for block in self.watched.values_mut() {
+                        if block.is_some_and(|hash| {
+                            chain.header(&hash).map(|header| chain.main_chain_hash(header.height)) != Some(Some(hash))
+                        }) {
+                            *block = None;
+                        }
+                    }
+                }
+                true
+            }
+            Err(e) => {
+                tracing::warn!("Disconnecting peer {}: bad headers: {:#}", peer, e);
+                self.full_peers.remove(&peer);
+                let _ = commands.send(NetworkCommand::DisconnectPeer(peer)).await;
+                false
+            }
+        }
+    }
+
+    /// Ask `peer` for the headers following the best chain, unless a
+    /// request is already outstanding
+    async fn request_headers(&mut self, peer: PeerId, commands: &mpsc::Sender<NetworkCommand>) {
+        let now = Instant::now();
+        let sent = self.full_peers.entry(peer).or_insert(None);
+        if sent.is_some_and(|sent| now.saturating_duration_since(sent) < HEADER_REQUEST_TIMEOUT) {
+            return;
+        }
+        *sent = Some(now);
+        let request = SyncRequest::GetHeaders {
+            locator: self.chain.locator(),
+            max: MAX_HEADERS_PER_REQUEST as u32,
+        };
+        let _ = commands.send(NetworkCommand::RequestSync(peer, request)).await;
+    }
+
+    /// Ask a proof-serving peer for each watched forge not yet verified
+    async fn request_proofs(&self, commands: &mpsc::Sender<NetworkCommand>) {
+        let peers: Vec<&PeerId> = self.proof_peers.iter().collect();
+        let unverified = self.watched.iter().filter(|(_, block)| block.is_none());
+        for (i, (proof_hash, _)) in unverified.enumerate() {
+            let Some(peer) = peers.get(i % peers.len().max(1)) else { return };
+            let request = SyncRequest::GetForgeProof { proof_hash: *proof_hash };
+            let _ = commands.send(NetworkCommand::RequestSync(**peer, request)).await;
+        }
+    }
+
+    /// Record a watched forge whose proof checks out against the best
+    /// chain. Returns its confirmations.
+    fn proof_received(&mut self, peer: PeerId, proof: &ForgeProof) -> Option<u64> {
+        let proof_hash = proof.forge.proof_hash;
+        let block = self.watched.get_mut(&proof_hash)?;
+        match self.chain.verify_forge(proof) {
+            Ok(confirmations) => {
+                if block.is_none() {
+                    tracing::info!(
+                        "Forge {} verified in block {} at height {} ({} confirmations)",
+                        hex::encode(proof_hash),
+                        hex::encode(proof.block_hash),
+                        proof.height,
+                        confirmations
+                    );
+                }
+                *block = Some(proof.block_hash);
+                Some(confirmations)
+            }
+            Err(e) => {
+                // Possibly a block we haven't synced yet; asked again later
+                tracing::debug!("Forge proof from {} not accepted: {:#}", peer, e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::testing::{self, forge};
+
+    /// Headers extending `parent`, the first at `height`, with the given
+    /// merkle roots
+    fn branch(engine: &ConsensusEngine, parent: Option<&BlockHeader>, roots: &[[u8; 32]]) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = Vec::new();
+        for root in roots {
+            let previous = headers.last().or(parent);
+            let height = previous.map_or(0, |previous| previous.height + 1);
+            let prev_block_hash = previous.map_or([0u8; 32], |previous| engine.compute_block_hash(previous));
+            let mut header = BlockHeader {
+                merkle_root: *root,
+                timestamp: 1000 + headers.len() as u64,
+                ..testing::header(height, prev_block_hash)
+            };
+            assert!(engine.grind_header(&mut header, 1_000_000));
+            headers.push(header);
+        }
+        headers
+    }
+
+    #[test]
+    fn test_headers_follow_most_work_branch() {
+        let engine = ConsensusEngine::new(0, 600);
+        let mut chain = HeaderChain::new();
+        let main = branch(&engine, None, &[[0; 32]; 3]);
+        assert_eq!(chain.add_headers(&engine, &main).unwrap(), 3);
+        assert_eq!(chain.add_headers(&engine, &main).unwrap(), 0);
+        assert_eq!(chain.tip(), Some(engine.compute_block_hash(&main[2])));
+        assert_eq!(chain.locator().len(), 3);
+
+        // Unconnected or unworked headers are refused whole
+        assert!(chain.add_headers(&engine, &branch(&engine, None, &[[1; 32]; 2])[1..]).is_err());
+        let mut unworked = branch(&engine, Some(&main[2]), &[[0; 32]]);
+        while engine.check_header_pow(&unworked[0]).is_ok() {
+            unworked[0].nonce = unworked[0].nonce.wrapping_add(1);
+        }
+        assert!(chain.add_headers(&engine, &unworked).is_err());
+        assert_eq!(chain.height(), Some(2));
+
+        // An equal-work fork doesn't switch; a longer one does
+        let fork = branch(&engine, Some(&main[0]), &[[1; 32]; 3]);
+        chain.add_headers(&engine, &fork[..2]).unwrap();
+        assert_eq!(chain.tip(), Some(engine.compute_block_hash(&main[2])));
+        chain.add_headers(&engine, &fork[2..]).unwrap();
+        assert_eq!(chain.height(), Some(3));
+        assert_eq!(chain.main_chain_hash(1), Some(engine.compute_block_hash(&fork[0])));
+        assert_eq!(chain.main_chain_hash(0), Some(engine.compute_block_hash(&main[0])));
+    }
+
+    #[test]
+    fn test_forge_verified_against_best_chain() {
+        let engine = ConsensusEngine::new(0, 600);
+        let forges = vec![forge(1), forge(2), forge(3)];
+        let tree = MerkleTree::build(&forges);
+        let main = branch(&engine, None, &[[0; 32], tree.root(), [0; 32]]);
+        let mut client = LightClient::new(ConsensusEngine::new(0, 600));
+        client.chain.add_headers(&engine, &main).unwrap();
+        client.watch([2; 32]);
+
+        let proof = ForgeProof {
+            block_hash: engine.compute_block_hash(&main[1]),
+            height: 1,
+            forge: forges[1].clone(),
+            proof: tree.prove(1).unwrap(),
+            transfer_root: None,
+        };
+        let peer = PeerId::random();
+        assert_eq!(client.proof_received(peer, &proof), Some(2));
+        assert_eq!(client.watched()[&[2; 32]], Some(proof.block_hash));
+
+        // The path must lead to the root of the claimed block, and only
+        // watched forges are recorded
+        let wrong_leaf = ForgeProof {
+            forge: forges[0].clone(),
+            ..proof.clone()
+        };
+        assert!(client.chain().verify_forge(&wrong_leaf).is_err());
+        assert_eq!(client.proof_received(peer, &wrong_leaf), None);
+        let wrong_block = ForgeProof {
+            block_hash: engine.compute_block_hash(&main[2]),
+            height: 2,
+            ..proof.clone()
+        };
+        assert!(client.chain().verify_forge(&wrong_block).is_err());
+
+        // Once a longer fork replaces the block, the proof no longer holds
+        let fork = branch(&engine, Some(&main[0]), &[[1; 32]; 3]);
+        client.chain.add_headers(&engine, &fork).unwrap();
+        assert!(client.chain().verify_forge(&proof).is_err());
+    }
+
+    #[test]
+    fn test_forge_verified_through_transfer_root() {
+        let engine = ConsensusEngine::new(0, 600);
+        let forges = vec![forge(1), forge(2)];
+        let tree = MerkleTree::build(&forges);
+        let transfer_root = [8; 32];
+        let header_root = commit_transfer_root(tree.root(), Some(transfer_root));
+        let main = branch(&engine, None, &[[0; 32], header_root]);
+        let mut chain = HeaderChain::new();
+        chain.add_headers(&engine, &main).unwrap();
+
+        let proof = ForgeProof {
+            block_hash: engine.compute_block_hash(&main[1]),
+            height: 1,
+            forge: forges[0].clone(),
+            proof: tree.prove(0).unwrap(),
+            transfer_root: Some(transfer_root),
+        };
+        assert_eq!(chain.verify_forge(&proof).unwrap(), 1);
+
+        // The forge root alone, or another transfer root, isn't what the
+        // header commits to
+        let forge_only = ForgeProof {
+            transfer_root: None,
+            ..proof.clone()
+        };
+        assert!(chain.verify_forge(&forge_only).is_err());
+        let other_transfers = ForgeProof {
+            transfer_root: Some([9; 32]),
+            ..proof
+        };
+        assert!(chain.verify_forge(&other_transfers).is_err());
+    }
+}
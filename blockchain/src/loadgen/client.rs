@@ -0,0 +1,156 @@
+//! Minimal JSON-RPC client for the node's HTTP server
+//!
+//! One connection per call, like the webhook notifier, so concurrent calls
+//! from the load generator never queue behind each other on the client side.
+
+use super::generalize;
+use crate::rpc::{basic_authorization, JsonRpcRequest, JsonRpcResponse, COOKIE_USER};
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Why a call failed
+#[derive(Debug)]
+pub enum CallError {
+    /// The request never got a JSON-RPC answer
+    Transport(anyhow::Error),
+    /// The node answered with an error
+    Rejected { code: i32, message: String },
+}
+
+impl CallError {
+    /// Failure cause as reported by the load generator
+    pub fn cause(&self) -> String {
+        match self {
+            CallError::Transport(e) => format!("transport: {}", generalize(&format!("{:#}", e))),
+            CallError::Rejected { code, message } => format!("{} ({})", generalize(message), code),
+        }
+    }
+}
+
+/// Client for the node's `/rpc` endpoint
+pub struct RpcClient {
+    /// `host:port` of the RPC server
+    authority: String,
+    authorization: Option<String>,
+    next_id: AtomicU64,
+}
+
+impl RpcClient {
+    /// Client for the server at `authority` (`host:port`), presenting
+    /// `user`/`password` if given
+    pub fn new(authority: &str, credentials: Option<(&str, &str)>) -> Self {
+        Self {
+            authority: authority.to_string(),
+            authorization: credentials.map(|(user, password)| basic_authorization(user, password)),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Client presenting the credentials in a node's cookie file
+    pub fn with_cookie(authority: &str, cookie: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(cookie)
+            .with_context(|| format!("Failed to read RPC cookie {}", cookie.display()))?;
+        let password = contents
+            .trim()
+            .strip_prefix(COOKIE_USER)
+            .and_then(|rest| rest.strip_prefix(':'))
+            .ok_or_else(|| anyhow!("Malformed RPC cookie {}", cookie.display()))?;
+        Ok(Self::new(authority, Some((COOKIE_USER, password))))
+    }
+
+    /// Call `method` and return its result
+    pub async fn call(&self, method: &str, params: Option<Value>) -> Result<Value, CallError> {
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params,
+            id: Value::from(self.next_id.fetch_add(1, Ordering::Relaxed)),
+        };
+        let body = serde_json::to_vec(&request).map_err(|e| CallError::Transport(e.into()))?;
+        let response = self.post(&body).await.map_err(CallError::Transport)?;
+        match (response.result, response.error) {
+            (_, Some(error)) => Err(CallError::Rejected {
+                code: error.code,
+                message: error.message,
+            }),
+            (Some(result), None) => Ok(result),
+            (None, None) => Ok(Value::Null),
+        }
+    }
+
+    async fn post(&self, body: &[u8]) -> Result<JsonRpcResponse> {
+        let mut stream = TcpStream::connect(&self.authority).await?;
+        let mut header = format!(
+            "POST /rpc HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.authority,
+            body.len()
+        );
+        if let Some(authorization) = &self.authorization {
+            header.push_str(&format!("Authorization: {}\r\n", authorization));
+        }
+        header.push_str("\r\n");
+        stream.write_all(header.as_bytes()).await?;
+        stream.write_all(body).await?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        let split = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(|| anyhow!("Malformed HTTP response"))?;
+        let head = String::from_utf8_lossy(&response[..split]);
+        let status = head.lines().next().unwrap_or_default();
+        let code = status.split_whitespace().nth(1).unwrap_or_default();
+        if !code.starts_with('2') {
+            return Err(anyhow!("Unexpected response: {}", status.trim()));
+        }
+        serde_json::from_slice(&response[split + 4..]).context("Malformed JSON-RPC response")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    /// Answer one request with `body`, returning what was received
+    async fn serve_once(listener: TcpListener, status: &'static str, body: Value) -> String {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = vec![0u8; 4096];
+        let read = socket.read(&mut request).await.unwrap();
+        let body = body.to_string();
+        let response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n{}", status, body.len(), body);
+        socket.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8_lossy(&request[..read]).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_call_results_and_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let authority = listener.local_addr().unwrap().to_string();
+        let client = RpcClient::new(&authority, Some(("alice", "secret")));
+
+        let server = tokio::spawn(serve_once(listener, "200 OK", json!({"jsonrpc": "2.0", "result": 7, "id": 0})));
+        assert_eq!(client.call("getblockcount", None).await.unwrap(), json!(7));
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /rpc HTTP/1.1"));
+        assert!(request.contains("Authorization: Basic YWxpY2U6c2VjcmV0"));
+
+        let listener = TcpListener::bind(authority.as_str()).await.unwrap();
+        let error = json!({"jsonrpc": "2.0", "error": {"code": -26, "message": "Invalid prophecy"}, "id": 1});
+        tokio::spawn(serve_once(listener, "200 OK", error));
+        let rejected = client.call("submitforge", Some(json!("00"))).await.unwrap_err();
+        assert_eq!(rejected.cause(), "Invalid prophecy (-26)");
+
+        let listener = TcpListener::bind(authority.as_str()).await.unwrap();
+        tokio::spawn(serve_once(listener, "401 Unauthorized", json!(null)));
+        let unauthorized = client.call("getblockcount", None).await.unwrap_err();
+        assert!(matches!(unauthorized, CallError::Transport(_)));
+        assert_eq!(unauthorized.cause(), "transport: Unexpected response: HTTP/#.# # Unauthorized");
+    }
+}
@@ -0,0 +1,139 @@
+//! Gossip transport for the load generator
+//!
+//! Joins the target's network as a peer and publishes items on the block
+//! and forge topics. Gossip carries no answer, so acceptance is observed
+//! by polling the target's RPC for each published hash, and rejections
+//! arrive as reject messages from the target.
+
+use super::client::RpcClient;
+use super::{generalize, LoadReport};
+use crate::network::{GossipSettings, NetworkCommand, NetworkEvent, NetworkManager, RejectedItem, RelayPreferences};
+use crate::params::NetworkParams;
+use anyhow::{anyhow, Result};
+use libp2p::Multiaddr;
+use serde_json::json;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// How long to wait for the target to accept the connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Peer connection to the target node
+pub struct GossipTarget {
+    commands: mpsc::Sender<NetworkCommand>,
+    events: mpsc::Receiver<NetworkEvent>,
+    /// Publish times of items awaiting an outcome, by hash. Re-published
+    /// copies of an item share its hash and queue behind it.
+    pending: HashMap<[u8; 32], (RejectedItem, VecDeque<Instant>)>,
+    /// Hashes seen accepted; later copies can only be rejected or time out
+    accepted: HashSet<[u8; 32]>,
+}
+
+impl GossipTarget {
+    /// Start a network manager on `params`' network and connect it to `peer`
+    pub async fn connect(peer: Multiaddr, params: &NetworkParams) -> Result<Self> {
+        let listen_addr: Multiaddr = "/ip4/0.0.0.0/tcp/0".parse()?;
+        let (network, commands, mut events) = NetworkManager::for_network(
+            listen_addr,
+            vec![peer.clone()],
+            RelayPreferences::default(),
+            &GossipSettings::default(),
+            params,
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to start networking: {}", e))?;
+        tokio::spawn(network.run());
+        commands.send(NetworkCommand::ConnectPeer(peer.clone())).await?;
+
+        tokio::time::timeout(CONNECT_TIMEOUT, async {
+            while let Some(event) = events.recv().await {
+                if matches!(event, NetworkEvent::PeerConnected(_)) {
+                    return Ok(());
+                }
+            }
+            Err(anyhow!("Network stopped"))
+        })
+        .await
+        .map_err(|_| anyhow!("Timed out connecting to {}", peer))??;
+
+        Ok(Self {
+            commands,
+            events,
+            pending: HashMap::new(),
+            accepted: HashSet::new(),
+        })
+    }
+
+    /// Publish an encoded item and start waiting for its outcome
+    pub async fn publish(&mut self, item: RejectedItem, hash: [u8; 32], encoded: Vec<u8>) -> Result<()> {
+        let command = match item {
+            RejectedItem::Block => NetworkCommand::PublishBlock(encoded),
+            RejectedItem::Forge => NetworkCommand::PublishTransaction(encoded),
+        };
+        self.commands.send(command).await?;
+        self.pending
+            .entry(hash)
+            .or_insert_with(|| (item, VecDeque::new()))
+            .1
+            .push_back(Instant::now());
+        Ok(())
+    }
+
+    /// Whether any published item still awaits an outcome
+    pub fn is_settled(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Next network event, recording rejections of published items
+    pub async fn next_event(&mut self, report: &mut LoadReport) -> Option<()> {
+        let event = self.events.recv().await?;
+        if let NetworkEvent::RejectReceived(_, reject) = event {
+            if let Some(published) = self.take(&reject.item_hash) {
+                report.record_failure(&generalize(&reject.reason), Some(published.elapsed()));
+            }
+        }
+        Some(())
+    }
+
+    /// Ask the target about every pending item, recording acceptances and
+    /// items older than `timeout`
+    pub async fn poll(&mut self, client: &RpcClient, timeout: Duration, report: &mut LoadReport) {
+        let hashes: Vec<([u8; 32], RejectedItem)> = self
+            .pending
+            .iter()
+            .filter(|(hash, _)| !self.accepted.contains(*hash))
+            .map(|(hash, (item, _))| (*hash, *item))
+            .collect();
+        for (hash, item) in hashes {
+            let found = match item {
+                RejectedItem::Block => client.call("getblock", Some(json!([hex::encode(hash), 0]))).await,
+                RejectedItem::Forge => client.call("getforge", Some(json!(hex::encode(hash)))).await,
+            };
+            if found.is_ok() {
+                self.accepted.insert(hash);
+                if let Some(published) = self.take(&hash) {
+                    report.record_accepted(published.elapsed());
+                }
+            }
+        }
+
+        for (_, sent) in self.pending.values_mut() {
+            while sent.front().is_some_and(|published| published.elapsed() > timeout) {
+                sent.pop_front();
+                report.record_failure(&format!("not accepted within {}s", timeout.as_secs()), None);
+            }
+        }
+        self.pending.retain(|_, (_, sent)| !sent.is_empty());
+    }
+
+    /// Oldest outstanding publish of `hash`
+    fn take(&mut self, hash: &[u8; 32]) -> Option<Instant> {
+        let (_, sent) = self.pending.get_mut(hash)?;
+        let published = sent.pop_front();
+        if sent.is_empty() {
+            self.pending.remove(hash);
+        }
+        published
+    }
+}
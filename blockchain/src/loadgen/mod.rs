@@ -0,0 +1,469 @@
+//! Synthetic traffic for load testing a node
+//!
+//! `excalibur-loadgen` submits forges, or blocks mined on the target's own
+//! templates, at a fixed rate over RPC (`submitforge`) or gossip, and
+//! reports how long the target took to accept or reject each item along
+//! with a histogram of failure causes.
+//!
+//! Each prophecy can only be forged once, and each new forge costs a full
+//! derivation, so the workloads derive at most one, of a fresh prophecy
+//! (a random mnemonic followed by the canonical axiom word):
+//!
+//! - `canonical` derives one forge once and submits it followed by
+//!   re-timestamped, re-signed copies. The first is accepted; the copies
+//!   share its proof hash and exercise the duplicate and replay paths.
+//! - `mismatched` signs a fresh prophecy with a fresh key each time. The
+//!   target only rejects these after running the full derivation, the
+//!   expensive path the validation queue and caches exist for.
+//! - `blocks` mines the target's block template and gossips the result.
+//!   The template needs a forge, so run `canonical` against the node first.
+
+mod client;
+mod gossip;
+
+pub use client::{CallError, RpcClient};
+pub use gossip::GossipTarget;
+
+use crate::consensus::{
+    Block, BlockHeader, ConsensusEngine, ForgeTransaction, VERSION_STATE_ROOT, VERSION_TIMESTAMP_MILLIS,
+};
+use crate::crypto::{
+    derive_public_key, entropy_to_mnemonic, forge_proof_hash, forge_tempering_salt, proof_of_forge_async,
+    prophecy_from_mnemonic, TaprootOutput, TemperingAlgorithm, CANONICAL_PROPHECY,
+};
+use crate::network::{unix_now, RejectedItem};
+use crate::params::NetworkParams;
+use anyhow::{anyhow, Context, Result};
+use bitcoin::Network;
+use libp2p::Multiaddr;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use zeroize::Zeroizing;
+
+/// How often gossiped items are looked up on the target
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Nonces tried per block before giving up on a template
+const MAX_NONCES: u64 = 1 << 32;
+
+/// What the load generator sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Workload {
+    Canonical,
+    Mismatched,
+    Blocks,
+}
+
+impl FromStr for Workload {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "canonical" => Ok(Workload::Canonical),
+            "mismatched" => Ok(Workload::Mismatched),
+            "blocks" => Ok(Workload::Blocks),
+            _ => Err(anyhow!("Unknown workload {} (canonical, mismatched, blocks)", s)),
+        }
+    }
+}
+
+/// How items reach the target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    /// `submitforge` calls
+    Rpc,
+    /// Gossip from a peer connected to this address
+    Gossip(Multiaddr),
+}
+
+/// Load generator settings
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    pub workload: Workload,
+    pub transport: Transport,
+    /// Items sent per second
+    pub rate: f64,
+    /// Items sent in total
+    pub count: usize,
+    /// How long a gossiped item may go unanswered before it counts as failed
+    pub timeout: Duration,
+    pub params: NetworkParams,
+}
+
+/// Source of forges
+pub struct ForgeGenerator {
+    network: Network,
+    /// Derived forge and the forger's secret key that signs it, for the
+    /// `canonical` workload
+    canonical: Option<(ForgeTransaction, Zeroizing<Vec<u8>>)>,
+    sequence: u64,
+}
+
+impl ForgeGenerator {
+    /// Generator of one derived forge of a fresh prophecy and its copies.
+    /// Runs the full derivation once, tempering on the blocking pool.
+    pub async fn canonical(network: Network) -> Result<Self> {
+        let words = fresh_prophecy()?;
+        let secret_key = Zeroizing::new(rand::random::<[u8; 32]>().to_vec());
+        let forger_key = derive_public_key(&secret_key)?;
+        let salt: [u8; 32] = rand::random();
+        let bound_salt = forge_tempering_salt(&forger_key.serialize(), &salt);
+        let result = proof_of_forge_async(&words, Some(&bound_salt), network, |_, _| {}).await?;
+        let forge = ForgeTransaction {
+            prophecy: words.join(" "),
+            derived_key: forger_key.serialize().to_vec(),
+            taproot_address: TaprootOutput::for_key(&forger_key).address(network),
+            proof_hash: forge_proof_hash(&result, &salt),
+            timestamp: unix_now(),
+            signature: vec![],
+            not_before_height: 0,
+            tempering: result.tempering,
+            salt,
+        };
+        Ok(Self {
+            network,
+            canonical: Some((forge, secret_key)),
+            sequence: 0,
+        })
+    }
+
+    /// Generator of underived forges of fresh prophecies, signed by fresh
+    /// keys
+    pub fn mismatched(network: Network) -> Self {
+        Self {
+            network,
+            canonical: None,
+            sequence: 0,
+        }
+    }
+
+    /// Next signed forge
+    pub fn next_forge(&mut self) -> Result<ForgeTransaction> {
+        self.sequence += 1;
+        let (mut forge, seed) = match &self.canonical {
+            Some((forge, seed)) => (forge.clone(), seed.clone()),
+            None => {
+                let seed: [u8; 32] = rand::random();
+                let public_key = derive_public_key(&seed)?;
+                let forge = ForgeTransaction {
+                    prophecy: fresh_prophecy()?.join(" "),
+                    derived_key: public_key.serialize().to_vec(),
+                    taproot_address: TaprootOutput::for_key(&public_key).address(self.network),
+                    proof_hash: rand::random(),
+                    timestamp: unix_now(),
+                    signature: vec![],
+                    not_before_height: 0,
+                    tempering: TemperingAlgorithm::Pbkdf2Sha512,
+                    salt: rand::random(),
+                };
+                (forge, Zeroizing::new(seed.to_vec()))
+            }
+        };
+        // Copies differ in timestamp so each is a distinct gossip message
+        forge.timestamp += self.sequence - 1;
+        forge.sign(&seed)?;
+        Ok(forge)
+    }
+}
+
+/// Random mnemonic followed by the canonical axiom word, a prophecy nobody
+/// has forged
+fn fresh_prophecy() -> Result<Vec<String>> {
+    let mnemonic = entropy_to_mnemonic(&rand::random::<[u8; 16]>())?.join(" ");
+    prophecy_from_mnemonic(&mnemonic, CANONICAL_PROPHECY[12], true)
+}
+
+/// Block for a `getblocktemplate` result, not yet mined
+pub fn block_from_template(template: &Value) -> Result<Block> {
+    let field = |name: &str| template.get(name).ok_or_else(|| anyhow!("Template has no {}", name));
+    let hash = |name: &str| -> Result<[u8; 32]> {
+        let hex = field(name)?.as_str().ok_or_else(|| anyhow!("Template {} is not a string", name))?;
+        <[u8; 32]>::try_from(hex::decode(hex)?).map_err(|_| anyhow!("Template {} is not 32 bytes", name))
+    };
+    let number = |name: &str| field(name)?.as_u64().ok_or_else(|| anyhow!("Template {} is not a number", name));
+
+    let bits = field("bits")?.as_str().ok_or_else(|| anyhow!("Template bits is not a string"))?;
+    let forges = field("forges")?
+        .as_array()
+        .ok_or_else(|| anyhow!("Template forges is not an array"))?
+        .iter()
+        .map(|forge| {
+            let encoded = forge.as_str().ok_or_else(|| anyhow!("Template forge is not a string"))?;
+            ForgeTransaction::decode(&hex::decode(encoded)?)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut block = Block {
+        header: BlockHeader {
+            version: 1,
+            height: number("height")?,
+            prev_block_hash: hash("previousblockhash")?,
+            merkle_root: hash("merkleroot")?,
+            timestamp: number("curtime")?,
+            difficulty: u32::try_from(number("difficulty")?)?,
+            bits: u32::from_str_radix(bits, 16).context("Template bits is not hex")?,
+            nonce: 0,
+            aggregate_commitment: None,
+            state_root: None,
+            timestamp_millis: None,
+        },
+        forges,
+        transfers: Vec::new(),
+        authority_signatures: Vec::new(),
+    };
+    if !field("state_root")?.is_null() {
+        block.header.version |= VERSION_STATE_ROOT;
+        block.header.state_root = Some(hash("state_root")?);
+    }
+    if let Some(millis) = template.get("curtime_millis").and_then(Value::as_u64) {
+        block.header.version |= VERSION_TIMESTAMP_MILLIS;
+        block.header.timestamp_millis = Some(u16::try_from(millis)?);
+    }
+    Ok(block)
+}
+
+/// Outcomes of a load run
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    pub sent: usize,
+    /// Time to acceptance of each accepted item
+    pub accepted: Vec<Duration>,
+    /// Time to rejection of each item the target answered with a rejection
+    pub rejected: Vec<Duration>,
+    /// Failed items by cause, including those with no answer
+    pub failures: BTreeMap<String, usize>,
+}
+
+impl LoadReport {
+    /// Record an accepted item
+    pub fn record_accepted(&mut self, latency: Duration) {
+        self.accepted.push(latency);
+    }
+
+    /// Record a failed item, with the time to its rejection if the target
+    /// answered
+    pub fn record_failure(&mut self, cause: &str, latency: Option<Duration>) {
+        *self.failures.entry(cause.to_string()).or_default() += 1;
+        self.rejected.extend(latency);
+    }
+
+    /// JSON summary with latency percentiles in milliseconds
+    pub fn to_json(&self) -> Value {
+        json!({
+            "sent": self.sent,
+            "accepted": self.accepted.len(),
+            "failed": self.failures.values().sum::<usize>(),
+            "accept_latency_ms": latency_summary(&self.accepted),
+            "reject_latency_ms": latency_summary(&self.rejected),
+            "failures": self.failures,
+        })
+    }
+}
+
+/// Nearest-rank percentile of `latencies`
+pub fn percentile(latencies: &[Duration], percent: f64) -> Option<Duration> {
+    let mut sorted = latencies.to_vec();
+    sorted.sort();
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.clamp(1, sorted.len().max(1)) - 1).copied()
+}
+
+fn latency_summary(latencies: &[Duration]) -> Value {
+    let ms = |percent: f64| percentile(latencies, percent).map(|latency| latency.as_secs_f64() * 1000.0);
+    json!({ "p50": ms(50.0), "p90": ms(90.0), "p99": ms(99.0), "max": ms(100.0) })
+}
+
+/// Replace every word containing a digit with `#`, so failures that differ
+/// only in heights, hashes or addresses share a cause
+pub fn generalize(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    let mut word = String::new();
+    fn flush(word: &mut String, out: &mut String) {
+        if word.chars().any(|c| c.is_ascii_digit()) {
+            out.push('#');
+        } else {
+            out.push_str(word);
+        }
+        word.clear();
+    }
+    for c in message.chars() {
+        if c.is_alphanumeric() {
+            word.push(c);
+        } else {
+            flush(&mut word, &mut out);
+            out.push(c);
+        }
+    }
+    flush(&mut word, &mut out);
+    out
+}
+
+/// Send `options.count` items to the target and collect their outcomes
+pub async fn run(options: LoadOptions, client: Arc<RpcClient>) -> Result<LoadReport> {
+    if !options.rate.is_finite() || options.rate <= 0.0 {
+        return Err(anyhow!("Rate must be positive"));
+    }
+    if options.workload == Workload::Blocks && options.transport == Transport::Rpc {
+        return Err(anyhow!("The node has no block submission RPC; send blocks over gossip"));
+    }
+    let network = options.params.network;
+    let mut forges = match options.workload {
+        Workload::Canonical => {
+            Some(ForgeGenerator::canonical(network).await?)
+        }
+        Workload::Mismatched => Some(ForgeGenerator::mismatched(network)),
+        Workload::Blocks => None,
+    };
+    let mut gossip = match &options.transport {
+        Transport::Rpc => None,
+        Transport::Gossip(peer) => Some(GossipTarget::connect(peer.clone(), &options.params).await?),
+    };
+    let engine = Arc::new(ConsensusEngine::new(0, 600).with_network(network));
+
+    let mut report = LoadReport::default();
+    let mut submissions: JoinSet<(Duration, Result<(), CallError>)> = JoinSet::new();
+    let mut ticks = tokio::time::interval(Duration::from_secs_f64(1.0 / options.rate));
+    let mut polls = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        let sending = report.sent < options.count;
+        if !sending && submissions.is_empty() && gossip.as_ref().is_none_or(GossipTarget::is_settled) {
+            break;
+        }
+        tokio::select! {
+            _ = ticks.tick(), if sending => {
+                report.sent += 1;
+                match &mut forges {
+                    Some(generator) => {
+                        let forge = generator.next_forge()?;
+                        match &mut gossip {
+                            Some(target) => target.publish(RejectedItem::Forge, forge.proof_hash, forge.encode()).await?,
+                            None => {
+                                let client = Arc::clone(&client);
+                                submissions.spawn(async move {
+                                    let started = Instant::now();
+                                    let outcome = client.call("submitforge", Some(json!(hex::encode(forge.encode())))).await;
+                                    (started.elapsed(), outcome.map(drop))
+                                });
+                            }
+                        }
+                    }
+                    None => {
+                        let target = gossip.as_mut().expect("blocks are only sent over gossip");
+                        match mine_template(&client, &engine).await {
+                            Ok(block) => {
+                                let hash = engine.compute_block_hash(&block.header);
+                                target.publish(RejectedItem::Block, hash, block.encode()).await?;
+                            }
+                            Err(cause) => report.record_failure(&cause, None),
+                        }
+                    }
+                }
+            }
+            Some(joined) = submissions.join_next() => {
+                let (latency, outcome) = joined?;
+                match outcome {
+                    Ok(()) => report.record_accepted(latency),
+                    Err(e @ CallError::Rejected { .. }) => report.record_failure(&e.cause(), Some(latency)),
+                    Err(e) => report.record_failure(&e.cause(), None),
+                }
+            }
+            _ = polls.tick(), if gossip.is_some() => {
+                if let Some(target) = &mut gossip {
+                    target.poll(&client, options.timeout, &mut report).await;
+                }
+            }
+            Some(()) = async {
+                match &mut gossip {
+                    Some(target) => target.next_event(&mut report).await,
+                    None => None,
+                }
+            } => {}
+            else => break,
+        }
+    }
+    Ok(report)
+}
+
+/// Fetch the target's block template and mine it. Errors are failure
+/// causes.
+async fn mine_template(client: &RpcClient, engine: &Arc<ConsensusEngine>) -> Result<Block, String> {
+    let template = client.call("getblocktemplate", None).await.map_err(|e| e.cause())?;
+    let mut block = block_from_template(&template).map_err(|e| format!("bad template: {}", e))?;
+    if block.forges.is_empty() {
+        return Err("template has no forges".to_string());
+    }
+    let engine = Arc::clone(engine);
+    tokio::task::spawn_blocking(move || {
+        block.header.nonce = rand::random();
+        engine.grind_header(&mut block.header, MAX_NONCES).then_some(block)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| "no nonce met the template target".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mempool::BlockTemplate;
+
+    #[test]
+    fn test_report_percentiles_and_causes() {
+        let latencies: Vec<Duration> = (1..=10).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&latencies, 50.0), Some(Duration::from_millis(5)));
+        assert_eq!(percentile(&latencies, 90.0), Some(Duration::from_millis(9)));
+        assert_eq!(percentile(&latencies, 99.0), Some(Duration::from_millis(10)));
+        assert_eq!(percentile(&latencies, 0.0), Some(Duration::from_millis(1)));
+        assert_eq!(percentile(&[], 50.0), None);
+
+        assert_eq!(
+            generalize("Forge 3 has a bad signature: bcrt1pq0"),
+            "Forge # has a bad signature: #"
+        );
+        let mut report = LoadReport { sent: 3, ..LoadReport::default() };
+        report.record_accepted(Duration::from_millis(4));
+        report.record_failure(&generalize("Proof hash mismatch at 12"), Some(Duration::from_millis(2)));
+        report.record_failure(&generalize("Proof hash mismatch at 40"), None);
+        let summary = report.to_json();
+        assert_eq!((summary["accepted"].clone(), summary["failed"].clone()), (json!(1), json!(2)));
+        assert_eq!(summary["failures"], json!({"Proof hash mismatch at #": 2}));
+        assert_eq!(summary["reject_latency_ms"]["p50"], json!(2.0));
+    }
+
+    #[test]
+    fn test_template_block_and_mismatched_forges() {
+        let forge = ForgeGenerator::mismatched(Network::Regtest).next_forge().unwrap();
+        forge.verify_signature().unwrap();
+        let template = BlockTemplate {
+            height: 4,
+            prev_block_hash: [7u8; 32],
+            merkle_root: [8u8; 32],
+            state_root: Some([9u8; 32]),
+            difficulty: 1,
+            bits: 0x207fffff,
+            timestamp: 1_700_000_000,
+            timestamp_millis: Some(250),
+            mempool_sequence: 0,
+            transfer_sequence: 0,
+            forges: vec![forge],
+            transfers: Vec::new(),
+        };
+        // As served by getblocktemplate
+        let served = json!({
+            "height": template.height,
+            "previousblockhash": hex::encode(template.prev_block_hash),
+            "merkleroot": hex::encode(template.merkle_root),
+            "state_root": template.state_root.map(hex::encode),
+            "difficulty": template.difficulty,
+            "bits": format!("{:08x}", template.bits),
+            "curtime": template.timestamp,
+            "curtime_millis": template.timestamp_millis,
+            "forges": template.forges.iter().map(|forge| hex::encode(forge.encode())).collect::<Vec<_>>(),
+        });
+        let block = block_from_template(&served).unwrap();
+        assert_eq!(block.encode(), template.block().encode());
+    }
+}
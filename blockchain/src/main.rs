@@ -1,9 +1,26 @@
 //! Excalibur EXS Blockchain Node
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::{Parser, Subcommand};
-use excalibur_blockchain::crypto::{proof_of_forge, CANONICAL_PROPHECY};
+use excalibur_blockchain::crypto::test_vectors::{verify_vector, FORGE_VECTORS};
+use excalibur_blockchain::crypto::{
+    proof_of_forge_with_progress, prophecy_from_mnemonic, CancelToken, ForgeStage, TemperingAlgorithm,
+    CANONICAL_PROPHECY,
+};
+use excalibur_blockchain::analytics::{export_analytics, ExportFormat};
+use excalibur_blockchain::audit::{
+    audit_prophecy, derive_addresses, generate_key, inspect_address, inspect_key, KeyInfo,
+};
+use excalibur_blockchain::chain::{ChainStore, CheckLevel};
+use excalibur_blockchain::config::NodeConfig;
+use excalibur_blockchain::consensus::ConsensusEngine;
+use excalibur_blockchain::light::LightClient;
+use excalibur_blockchain::node::{default_data_dir, Node, NodeOptions};
+use excalibur_blockchain::params::NetworkParams;
+use excalibur_blockchain::wallet::Wallet;
 use bitcoin::Network;
+use libp2p::Multiaddr;
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "excalibur-node")]
@@ -16,74 +33,562 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Start the blockchain node
+    ///
+    /// Flags override the configuration file.
     Start {
-        /// Network to connect to (mainnet, testnet, regtest)
-        #[arg(short, long, default_value = "mainnet")]
-        network: String,
+        /// Network to connect to (mainnet, testnet, regtest; default mainnet)
+        #[arg(short, long)]
+        network: Option<String>,
         
-        /// Port to listen on
-        #[arg(short, long, default_value = "8333")]
-        port: u16,
+        /// Port to listen on (default: the network's P2P port)
+        #[arg(short, long)]
+        port: Option<u16>,
+
+        /// Path to the node configuration file
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+
+        /// Startup database verification level (0-3, overrides config file)
+        #[arg(long)]
+        checklevel: Option<u8>,
+
+        /// Number of recent blocks verified at startup (0 = all, overrides config file)
+        #[arg(long)]
+        checkblocks: Option<u64>,
+
+        /// Maintain a forge index for historical lookups (overrides config file)
+        #[arg(long)]
+        txindex: Option<bool>,
+
+        /// Maintain a word index over forge prophecies for searchforges (overrides config file)
+        #[arg(long)]
+        searchindex: Option<bool>,
+
+        /// Data directory (default ~/.excalibur, with a subdirectory per test network)
+        #[arg(long)]
+        datadir: Option<PathBuf>,
+
+        /// Address the HTTP RPC server listens on (default: 127.0.0.1 on the
+        /// network's RPC port; requires the http-server feature)
+        #[arg(long)]
+        rpcbind: Option<String>,
+
+        /// Peer to connect to on startup (repeatable; replaces the
+        /// configured peers)
+        #[arg(long)]
+        connect: Vec<Multiaddr>,
+
+        /// Log filter in RUST_LOG syntax (default info)
+        #[arg(long)]
+        loglevel: Option<String>,
+
+        /// Run as a light client: download and validate headers only, with
+        /// no chain database, wallet or RPC server
+        #[arg(long)]
+        light: bool,
+
+        /// Proof hash of a forge the light client verifies the inclusion of
+        /// (hex, repeatable; requires --light)
+        #[arg(long, requires = "light")]
+        watch_forge: Vec<String>,
     },
     
+    /// Replay the stored chain from genesis and compare the state it reaches
+    /// with the state roots recorded by the live node
+    Replay {
+        /// Network the database belongs to (mainnet, testnet, regtest)
+        #[arg(short, long, default_value = "mainnet")]
+        network: String,
+
+        /// Data directory (default ~/.excalibur, with a subdirectory per test network)
+        #[arg(long)]
+        datadir: Option<PathBuf>,
+
+        /// Checks applied to each block before it is replayed (0-3)
+        #[arg(long, default_value_t = 3)]
+        checklevel: u8,
+    },
+
+    /// Export blocks, forges and fees from the stored chain as CSV or
+    /// Parquet files
+    ExportAnalytics {
+        /// Network the database belongs to (mainnet, testnet, regtest)
+        #[arg(short, long, default_value = "mainnet")]
+        network: String,
+
+        /// Data directory (default ~/.excalibur, with a subdirectory per test network)
+        #[arg(long)]
+        datadir: Option<PathBuf>,
+
+        /// Directory the blocks, forges and fees files are written to
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// File format: csv, or parquet (requires the parquet feature)
+        #[arg(long, default_value = "csv")]
+        format: ExportFormat,
+    },
+
     /// Perform a proof-of-forge derivation
     Forge {
+        /// Use custom prophecy words (13 words, space-separated)
+        #[arg(short, long, conflicts_with = "mnemonic")]
+        prophecy: Option<String>,
+
+        /// Build the prophecy from a 12- or 24-word BIP-39 mnemonic
+        #[arg(long, requires = "axiom")]
+        mnemonic: Option<String>,
+
+        /// Axiom word appended to the mnemonic
+        #[arg(long, requires = "mnemonic")]
+        axiom: Option<String>,
+
+        /// Accept a mnemonic whose BIP-39 checksum doesn't match
+        #[arg(long, requires = "mnemonic")]
+        skip_checksum: bool,
+
+        /// Check this build against the published Proof-of-Forge test vectors
+        #[arg(long, conflicts_with_all = ["prophecy", "mnemonic"])]
+        verify_vectors: bool,
+
+        /// Network (mainnet, testnet, regtest)
+        #[arg(short, long, default_value = "mainnet")]
+        network: String,
+
+        /// Tempering algorithm: pbkdf2, or argon2id[:m=<KiB>,t=<passes>,p=<lanes>]
+        #[arg(long, default_value = "pbkdf2")]
+        tempering: TemperingAlgorithm,
+    },
+
+    /// Compare legacy P2WPKH and P2TR addresses for a prophecy
+    AuditAddresses {
         /// Use custom prophecy words (13 words, space-separated)
         #[arg(short, long)]
         prophecy: Option<String>,
-        
+
+        /// Network (mainnet, testnet, regtest)
+        #[arg(short, long, default_value = "mainnet")]
+        network: String,
+
+        /// Chain database to scan for references to the legacy address
+        #[arg(long)]
+        chain_db: Option<PathBuf>,
+    },
+
+    /// Generate and inspect keys
+    Key {
+        #[command(subcommand)]
+        command: KeyCommands,
+    },
+
+    /// Inspect addresses
+    Address {
+        #[command(subcommand)]
+        command: AddressCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeyCommands {
+    /// Generate a random private key
+    Generate {
         /// Network (mainnet, testnet, regtest)
         #[arg(short, long, default_value = "mainnet")]
         network: String,
     },
+
+    /// Show the public key and addresses for a key
+    Inspect {
+        /// 32-byte hex secret, hex public key, or WIF private key
+        key: String,
+
+        /// Network for hex keys (mainnet, testnet, regtest); WIF keys carry their own
+        #[arg(short, long, default_value = "mainnet")]
+        network: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum AddressCommands {
+    /// Show an address's network, type and key, and whether it is a known forge address
+    Inspect {
+        address: String,
+
+        /// Prophecy to check the address against (13 words, space-separated; defaults to the canonical prophecy)
+        #[arg(short, long)]
+        prophecy: Option<String>,
+
+        /// Wallet file whose address book to check
+        #[arg(long)]
+        wallet: Option<PathBuf>,
+    },
+}
+
+fn parse_network(network: &str) -> Network {
+    match network {
+        "mainnet" => Network::Bitcoin,
+        "testnet" => Network::Testnet,
+        "regtest" => Network::Regtest,
+        _ => Network::Bitcoin,
+    }
+}
+
+fn network_name(network: Network) -> &'static str {
+    match network {
+        Network::Bitcoin => "mainnet",
+        Network::Regtest => "regtest",
+        _ => "testnet",
+    }
+}
+
+fn print_key(key: &KeyInfo) {
+    println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+    println!("Network:       {}", network_name(key.network));
+    if let (Some(secret_key), Some(wif)) = (key.secret_key, key.wif()) {
+        println!("Secret Key:    {}", hex::encode(secret_key.secret_bytes()));
+        println!("WIF:           {}", wif);
+    }
+    println!("Public Key:    {}", key.public_key);
+    println!("X-only Key:    {}", key.x_only_key);
+    println!("\nP2TR:          {}", key.addresses.p2tr);
+    println!("Legacy P2WPKH: {}", key.addresses.legacy_p2wpkh);
+    println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+}
+
+fn root_hex(root: Option<[u8; 32]>) -> String {
+    root.map(hex::encode).unwrap_or_else(|| "(not recorded)".to_string())
+}
+
+fn prophecy_words(prophecy: Option<String>) -> Vec<String> {
+    if let Some(p) = prophecy {
+        p.split_whitespace().map(|s| s.to_string()).collect()
+    } else {
+        CANONICAL_PROPHECY.iter().map(|s| s.to_string()).collect()
+    }
+}
+
+/// Redraw the forge progress bar on stderr
+fn render_forge_progress(stage: ForgeStage, percent: u8) {
+    const WIDTH: usize = 40;
+    let filled = usize::from(percent) * WIDTH / 100;
+    eprint!("\r[{}{}] {:>3}% {:<16}", "#".repeat(filled), "-".repeat(WIDTH - filled), percent, stage.name());
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
-
     let cli = Cli::parse();
 
+    // `start` sets up logging once its configuration is loaded
+    if !matches!(cli.command, Commands::Start { .. }) {
+        tracing_subscriber::fmt::init();
+    }
+
     match cli.command {
-        Commands::Start { network, port } => {
+        Commands::Start {
+            network,
+            port,
+            config,
+            checklevel,
+            checkblocks,
+            txindex,
+            searchindex,
+            datadir,
+            rpcbind,
+            connect,
+            loglevel,
+            light,
+            watch_forge,
+        } => {
+            let file_config = config.as_ref().map(NodeConfig::load).transpose()?;
+            let mut node_config = file_config.clone().unwrap_or_default();
+            if network.is_some() {
+                node_config.chain.network = network;
+            }
+            if datadir.is_some() {
+                node_config.chain.datadir = datadir;
+            }
+            if port.is_some() {
+                node_config.network.port = port;
+            }
+            if rpcbind.is_some() {
+                node_config.rpc.bind = rpcbind;
+            }
+            if !connect.is_empty() {
+                node_config.network.connect = connect.iter().map(Multiaddr::to_string).collect();
+            }
+            if let Some(level) = loglevel {
+                node_config.logging.level = level;
+            }
+            node_config.validate()?;
+            let log_filter = node_config.logging.init_subscriber()?;
+
+            if let Some(level) = checklevel {
+                node_config.chain.check_level = CheckLevel::try_from(level)?;
+            }
+            if let Some(blocks) = checkblocks {
+                node_config.chain.check_blocks = blocks;
+            }
+            if let Some(enabled) = txindex {
+                node_config.chain.txindex = enabled;
+            }
+            if let Some(enabled) = searchindex {
+                node_config.chain.searchindex = enabled;
+            }
+
+            let options = NodeOptions::from_config(&node_config)?;
+
+            if light {
+                let params = &options.params;
+                let engine = ConsensusEngine::new(params.chain.initial_difficulty, params.chain.min_block_time)
+                    .with_params(params);
+                let mut client = LightClient::new(engine);
+                for proof_hash in &watch_forge {
+                    let proof_hash = hex::decode(proof_hash)
+                        .ok()
+                        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                        .ok_or_else(|| anyhow!("Invalid forge proof hash {}", proof_hash))?;
+                    client.watch(proof_hash);
+                }
+                println!("🗡️  Starting Excalibur EXS Light Client");
+                println!("Network: {}", params.name);
+                println!("Port: {}", options.port);
+                println!("Watched forges: {}", watch_forge.len());
+                client.run(&node_config, &options).await?;
+                println!("🗡️  Light client stopped");
+                return Ok(());
+            }
+
             println!("🗡️  Starting Excalibur EXS Blockchain Node");
-            println!("Network: {}", network);
-            println!("Port: {}", port);
-            println!("\n⚠️  Node implementation is in progress.");
-            println!("This is the foundation for the full P2P blockchain node.");
+            println!("Network: {}", options.params.name);
+            println!("Port: {}", options.port);
+            println!("RPC: {}", options.rpc_bind);
+            println!("Data directory: {}", options.data_dir.display());
+            println!(
+                "Startup verification: level {} over {} blocks",
+                u8::from(node_config.chain.check_level),
+                node_config.chain.check_blocks
+            );
+            println!("Forge index: {}", if node_config.chain.txindex { "enabled" } else { "disabled" });
+            println!("Search index: {}", if node_config.chain.searchindex { "enabled" } else { "disabled" });
+            for warning in options.port_warnings() {
+                println!("⚠️  {}. Check --network and --port.", warning);
+            }
+
+            let mut node = Node::open(node_config, options)?;
+            if let (Some(path), Some(loaded)) = (config, file_config) {
+                node.watch_config(path, loaded, Some(log_filter));
+            }
+            node.run().await?;
+            println!("🗡️  Node stopped");
             Ok(())
         }
-        Commands::Forge { prophecy, network } => {
-            let network = match network.as_str() {
-                "mainnet" => Network::Bitcoin,
-                "testnet" => Network::Testnet,
-                "regtest" => Network::Regtest,
-                _ => Network::Bitcoin,
+        Commands::Replay { network, datadir, checklevel } => {
+            let params = NetworkParams::from_name(&network)
+                .ok_or_else(|| anyhow!("Unknown network {} (expected mainnet, testnet or regtest)", network))?;
+            let level = CheckLevel::try_from(checklevel)?;
+            let data_dir = match datadir {
+                Some(dir) => dir,
+                None => default_data_dir(&params)?,
             };
+            let chain_dir = data_dir.join("chain");
+            if !chain_dir.exists() {
+                return Err(anyhow!("No chain database at {}", chain_dir.display()));
+            }
+            let store = ChainStore::new(&chain_dir)?;
 
-            let words: Vec<String> = if let Some(p) = prophecy {
-                p.split_whitespace().map(|s| s.to_string()).collect()
-            } else {
-                CANONICAL_PROPHECY.iter().map(|s| s.to_string()).collect()
+            println!("🔁 Replaying {} at check level {}", chain_dir.display(), checklevel);
+            let engine =
+                ConsensusEngine::new(params.chain.initial_difficulty, params.chain.min_block_time).with_params(&params);
+            let report = store.replay(&engine, level)?;
+
+            println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+            println!("Blocks replayed:  {}", report.blocks_replayed);
+            println!("Height:           {}", report.replayed.height);
+            println!("Ledger set hash:  {}", root_hex(report.replayed.ledger_set_hash));
+            println!("  live:           {}", root_hex(report.live.ledger_set_hash));
+            println!("Used-proofs hash: {}", root_hex(report.replayed.used_proofs_hash));
+            println!("  live:           {}", root_hex(report.live.used_proofs_hash));
+            println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+
+            match report.divergence {
+                None => {
+                    println!("✅ Replayed state matches the live database");
+                    Ok(())
+                }
+                Some(divergence) => {
+                    println!("❌ Diverged at height {}: {}", divergence.height, divergence.reason);
+                    Err(anyhow!("Replay diverged at height {}", divergence.height))
+                }
+            }
+        }
+        Commands::ExportAnalytics { network, datadir, output, format } => {
+            let params = NetworkParams::from_name(&network)
+                .ok_or_else(|| anyhow!("Unknown network {} (expected mainnet, testnet or regtest)", network))?;
+            let data_dir = match datadir {
+                Some(dir) => dir,
+                None => default_data_dir(&params)?,
+            };
+            let chain_dir = data_dir.join("chain");
+            if !chain_dir.exists() {
+                return Err(anyhow!("No chain database at {}", chain_dir.display()));
+            }
+            let store = ChainStore::new(&chain_dir)?;
+
+            println!("📊 Exporting {} as {}", chain_dir.display(), format.extension());
+            let summary = export_analytics(&store, &output, format, &params.chain.fee_schedule)?;
+            println!("Height:  {}", summary.height);
+            println!("Blocks:  {}", summary.blocks);
+            println!("Forges:  {}", summary.forges);
+            for file in &summary.files {
+                println!("Wrote    {}", file.display());
+            }
+            Ok(())
+        }
+        Commands::Forge { verify_vectors: true, .. } => {
+            println!("🔮 Verifying Proof-of-Forge test vectors...");
+            let mut failures = 0;
+            for vector in &FORGE_VECTORS {
+                match verify_vector(vector) {
+                    Ok(_) => println!("✅ {}", vector.name),
+                    Err(e) => {
+                        failures += 1;
+                        println!("❌ {}", e);
+                    }
+                }
+            }
+            if failures > 0 {
+                return Err(anyhow!("{} of {} test vectors failed", failures, FORGE_VECTORS.len()));
+            }
+            println!("All {} test vectors match", FORGE_VECTORS.len());
+            Ok(())
+        }
+        Commands::Forge { prophecy, mnemonic, axiom, skip_checksum, network, tempering, .. } => {
+            let network = parse_network(&network);
+            let words = match (mnemonic, axiom) {
+                (Some(mnemonic), Some(axiom)) => prophecy_from_mnemonic(&mnemonic, &axiom, !skip_checksum)?,
+                _ => prophecy_words(prophecy),
             };
 
             println!("🔮 Performing Proof-of-Forge...");
             println!("Prophecy: {}", words.join(" "));
             
-            let result = proof_of_forge(&words, None, network)?;
+            // Ctrl-C stops the derivation at its next progress report
+            let cancel = CancelToken::new();
+            let interrupt = cancel.clone();
+            let interrupted = tokio::spawn(async move {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    interrupt.cancel();
+                }
+            });
+            let result = tokio::task::spawn_blocking(move || {
+                proof_of_forge_with_progress(&words, None, network, tempering, render_forge_progress, &cancel)
+            })
+            .await?;
+            interrupted.abort();
+            eprintln!();
+            let result = result?;
             
             println!("\n✨ Proof-of-Forge Complete!");
             println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
-            println!("Prophecy Hash: {}", hex::encode(&result.prophecy_hash[..8]));
-            println!("Tetra Hash:    {}", hex::encode(&result.tetra_hash[..8]));
-            println!("Tempered Key:  {}", hex::encode(&result.tempered_key[..8]));
-            println!("Final Seed:    {}", hex::encode(&result.final_seed[..8]));
+            println!("Forge:         {}", result.redacted());
+            println!("Internal Key:  {}", hex::encode(result.internal_key));
+            println!("Output Key:    {}", hex::encode(result.output_key));
             println!("\n🏰 Taproot Address:");
             println!("{}", result.taproot_address);
             println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
             
             Ok(())
         }
+        Commands::AuditAddresses { prophecy, network, chain_db } => {
+            let network = parse_network(&network);
+            let words = prophecy_words(prophecy);
+
+            println!("🔍 Auditing address derivation...");
+            println!("Prophecy: {}", words.join(" "));
+
+            let (addresses, references) = match chain_db {
+                Some(path) => {
+                    let store = ChainStore::new(path)?;
+                    let audit = audit_prophecy(&store, &words, network)?;
+                    (audit.addresses, Some(audit.references))
+                }
+                None => (derive_addresses(&words, network)?, None),
+            };
+
+            println!("\nLegacy P2WPKH: {}", addresses.legacy_p2wpkh);
+            println!("P2TR:          {}", addresses.p2tr);
+
+            match references {
+                Some(references) if references.is_empty() => {
+                    println!("\n✅ No chain data references the legacy address");
+                }
+                Some(references) => {
+                    println!("\n⚠️  {} forge(s) pay to the legacy address:", references.len());
+                    for reference in references {
+                        println!("  height {}  proof {}", reference.height, hex::encode(reference.proof_hash));
+                    }
+                }
+                None => println!("\n(pass --chain-db to scan chain data for legacy references)"),
+            }
+
+            Ok(())
+        }
+        Commands::Key { command: KeyCommands::Generate { network } } => {
+            let key = generate_key(parse_network(&network))?;
+
+            println!("🔑 Generated key");
+            print_key(&key);
+            println!("⚠️  Store the secret key safely; anyone holding it controls these addresses.");
+            Ok(())
+        }
+        Commands::Key { command: KeyCommands::Inspect { key, network } } => {
+            let key = inspect_key(&key, parse_network(&network))?;
+
+            println!("🔍 Key inspection");
+            print_key(&key);
+            Ok(())
+        }
+        Commands::Address { command: AddressCommands::Inspect { address, prophecy, wallet } } => {
+            let info = inspect_address(&address)?;
+            let words = prophecy_words(prophecy);
+
+            println!("🔍 Address inspection");
+            println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+            println!("Address:       {}", info.address);
+            println!("Network:       {}", network_name(info.network));
+            println!(
+                "Script Type:   {}",
+                info.address_type.map_or("non-standard".to_string(), |t| t.to_string())
+            );
+            if let Some(key) = info.x_only_key {
+                println!("Output Key:    {} (x-only)", key);
+            }
+            if info.legacy_forge {
+                println!("\n⚠️  P2WPKH is the legacy forge address form used before the P2TR fix");
+            }
+
+            let derived = derive_addresses(&words, info.network)?;
+            match info.derived_form(&derived) {
+                Some(form) => println!("\n✅ {} address of prophecy: {}", form, words.join(" ")),
+                None => println!("\n❌ Not derived from prophecy: {}", words.join(" ")),
+            }
+
+            if let Some(path) = wallet {
+                if !path.exists() {
+                    anyhow::bail!("Wallet file {} does not exist", path.display());
+                }
+                let wallet = Wallet::open(&path, info.network)?;
+                match wallet.address_entry(&info.address) {
+                    Some(entry) => println!(
+                        "✅ In wallet address book ({:?}{})",
+                        entry.purpose,
+                        if entry.label.is_empty() { String::new() } else { format!(", label \"{}\"", entry.label) }
+                    ),
+                    None => println!("❌ Not in wallet address book"),
+                }
+            }
+            println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,132 @@
+//! Rolling mempool minimum fee
+//!
+//! When the pool is full, admitting a transfer evicts the held transfer
+//! paying the lowest fee, and the minimum fee rises to that fee plus an
+//! incremental step. With no more evictions it halves every half-life and
+//! drops back to the configured floor once it falls below the floor or
+//! below half a step.
+//! During congestion every round of low-fee spam costs more than the last,
+//! and the pool recovers on its own once the congestion passes.
+
+use serde::Serialize;
+
+/// Default step added to an evicted entry's fee
+pub const DEFAULT_INCREMENTAL_FEE: u64 = 1_000;
+
+/// Default time for the raised minimum fee to halve
+pub const DEFAULT_MIN_FEE_HALF_LIFE_SECS: u64 = 12 * 60 * 60;
+
+/// Minimum fee counters, for RPC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MinFeeStats {
+    /// Configured floor
+    pub floor: u64,
+    /// Minimum fee currently enforced
+    pub current: u64,
+    pub incremental_fee: u64,
+    pub half_life_secs: u64,
+    /// Entries evicted because the pool was full
+    pub evictions: u64,
+}
+
+/// Minimum fee raised by evictions and decaying over time
+#[derive(Debug, Clone)]
+pub struct RollingMinFee {
+    floor: u64,
+    incremental: u64,
+    half_life_secs: u64,
+    /// Raised fee, before applying the floor
+    rolling: f64,
+    /// When `rolling` was last decayed (Unix seconds)
+    updated_at: u64,
+    evictions: u64,
+}
+
+impl RollingMinFee {
+    /// A minimum fee of at least `floor`, raised `incremental` past each
+    /// evicted fee and halving every `half_life_secs`
+    pub fn new(floor: u64, incremental: u64, half_life_secs: u64) -> Self {
+        Self {
+            floor,
+            incremental,
+            half_life_secs: half_life_secs.max(1),
+            rolling: 0.0,
+            updated_at: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Configured floor
+    pub fn floor(&self) -> u64 {
+        self.floor
+    }
+
+    /// Change the floor, step and half-life, keeping a fee already raised
+    /// by evictions
+    pub fn set_policy(&mut self, floor: u64, incremental: u64, half_life_secs: u64) {
+        self.floor = floor;
+        self.incremental = incremental;
+        self.half_life_secs = half_life_secs.max(1);
+    }
+
+    /// Minimum fee to enforce at `now`
+    pub fn current(&mut self, now: u64) -> u64 {
+        self.decay(now);
+        self.floor.max(self.rolling.ceil() as u64)
+    }
+
+    /// Raise the minimum fee past an entry evicted at `now` that was
+    /// admitted paying `fee`
+    pub fn record_eviction(&mut self, fee: u64, now: u64) {
+        self.decay(now);
+        self.rolling = self.rolling.max(fee.saturating_add(self.incremental) as f64);
+        self.evictions += 1;
+    }
+
+    /// Current counters
+    pub fn stats(&mut self, now: u64) -> MinFeeStats {
+        MinFeeStats {
+            floor: self.floor,
+            current: self.current(now),
+            incremental_fee: self.incremental,
+            half_life_secs: self.half_life_secs,
+            evictions: self.evictions,
+        }
+    }
+
+    fn decay(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.updated_at);
+        self.updated_at = self.updated_at.max(now);
+        if self.rolling == 0.0 || elapsed == 0 {
+            return;
+        }
+        self.rolling *= 0.5f64.powf(elapsed as f64 / self.half_life_secs as f64);
+        if self.rolling < (self.floor as f64).max(self.incremental as f64 / 2.0) {
+            self.rolling = 0.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rises_on_eviction_and_decays() {
+        let mut fee = RollingMinFee::new(100, 1_000, 3_600);
+        assert_eq!(fee.current(0), 100);
+
+        // Each eviction prices out the evicted fee
+        fee.record_eviction(100, 10);
+        assert_eq!(fee.current(10), 1_100);
+        fee.record_eviction(1_100, 10);
+        fee.record_eviction(500, 10);
+        assert_eq!(fee.current(10), 2_100);
+
+        // Halves each half-life, then falls back to the floor
+        assert_eq!(fee.current(10 + 3_600), 1_050);
+        assert_eq!(fee.current(10 + 2 * 3_600), 525);
+        assert_eq!(fee.current(10 + 3 * 3_600), 100);
+        assert_eq!(fee.stats(10 + 3 * 3_600).evictions, 3);
+    }
+}
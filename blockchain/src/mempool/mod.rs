@@ -1,35 +1,140 @@
 //! Mempool for pending forge transactions
 
-use crate::consensus::{ForgeTransaction, Block};
+mod fee;
+mod policy;
+mod queue;
+mod revalidate;
+mod template;
+#[cfg(feature = "wasm-policy")]
+pub mod wasm;
+
+pub use fee::{MinFeeStats, RollingMinFee, DEFAULT_INCREMENTAL_FEE, DEFAULT_MIN_FEE_HALF_LIFE_SECS};
+pub use policy::{ForgePolicy, DEFAULT_POLICY_FUEL};
+pub use queue::{
+    ForgeOrigin, ValidationQueue, ValidationQueueStats, DEFAULT_GOSSIP_QUEUE_SIZE, DEFAULT_LOCAL_QUEUE_SIZE,
+    DEFAULT_VALIDATION_WORKERS,
+};
+pub use revalidate::{MempoolRevalidator, RevalidationReport};
+pub use template::{BlockTemplate, BlockTemplateCache, TemplateStats, TEMPLATE_TIP_CHECK_INTERVAL};
+
+use crate::consensus::sighash::Transfer;
+use crate::consensus::{Block, ForgeTransaction, SignedTransfer};
+use crate::crypto::prophecy_registry_hash;
+use crate::ledger::{check_transfer_outputs, is_dust, OutPoint, DUST_THRESHOLD};
+use crate::params::{ChainParams, MAX_FORGES_PER_BLOCK};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use std::collections::{HashMap, BTreeSet};
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::broadcast;
 use anyhow::{Result, anyhow};
 
+/// Number of mempool events buffered for slow subscribers
+pub const MEMPOOL_EVENT_CAPACITY: usize = 1024;
+
+/// Why a forge left the mempool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemovalReason {
+    /// Included in a connected block
+    Block,
+    /// Sat in the mempool past the expiry timeout
+    Expiry,
+    /// Replaced by a conflicting forge
+    Replacement,
+    /// A connected block forged the same prophecy
+    Conflict,
+    /// Removed explicitly (RPC or mempool clear)
+    Manual,
+    /// No longer valid under rules that activated since admission
+    Invalid,
+}
+
+/// Change to the mempool contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum MempoolEventKind {
+    Added,
+    Removed { reason: RemovalReason },
+}
+
+/// Ordered mempool notification. Sequence numbers increase by one per
+/// event, so subscribers can detect gaps and resync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MempoolEvent {
+    pub sequence: u64,
+    pub proof_hash: [u8; 32],
+    pub kind: MempoolEventKind,
+}
+
 /// Priority ordering for forge transactions
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct ForgePriority {
     timestamp: u64,
-    fee: u64,
 }
 
+/// Pending proof hashes ordered by priority
+type PriorityQueue = BTreeSet<([u8; 32], ForgePriority)>;
+
 /// Mempool entry
 #[derive(Debug, Clone)]
 struct MempoolEntry {
     forge: Arc<ForgeTransaction>,
+    /// `prophecy_registry_hash` of the forge's prophecy
+    prophecy_hash: [u8; 32],
     priority: ForgePriority,
     added_at: u64,
 }
 
+/// Signed transfers waiting for a block
+#[derive(Default)]
+struct PendingTransfers {
+    by_txid: HashMap<[u8; 32], SignedTransfer>,
+    /// Pending transfer spending each output
+    spends: HashMap<OutPoint, [u8; 32]>,
+}
+
+impl PendingTransfers {
+    fn remove(&mut self, txid: &[u8; 32]) -> Option<SignedTransfer> {
+        let transfer = self.by_txid.remove(txid)?;
+        for input in &transfer.transfer.inputs {
+            self.spends.remove(&input.prevout);
+        }
+        Some(transfer)
+    }
+}
+
 /// Forge transaction mempool
 pub struct ForgePool {
     /// Pending forges by proof hash
     pending: Arc<RwLock<HashMap<[u8; 32], MempoolEntry>>>,
     /// Ordered set of forges by priority
-    priority_queue: Arc<RwLock<BTreeSet<([u8; 32], ForgePriority)>>>,
+    priority_queue: Arc<RwLock<PriorityQueue>>,
+    /// Pending forge of each prophecy, by `prophecy_registry_hash`
+    prophecies: Arc<RwLock<HashMap<[u8; 32], [u8; 32]>>>,
     /// Maximum mempool size
-    max_size: usize,
-    /// Minimum fee required
-    min_fee: u64,
+    max_size: AtomicUsize,
+    /// Minimum fee required, raised by evictions
+    min_fee: Mutex<RollingMinFee>,
+    /// Transfer outputs below this value are refused as dust
+    dust_threshold: u64,
+    /// Most forges handed out for one block
+    max_forges_per_block: usize,
+    /// Operator-supplied admission policies
+    policies: Vec<Box<dyn ForgePolicy>>,
+    /// Current chain tip height, used for lock-height policy
+    tip_height: Arc<RwLock<u64>>,
+    /// Sequence number of the last emitted event
+    sequence: Arc<AtomicU64>,
+    /// Mempool event notifications
+    events: broadcast::Sender<MempoolEvent>,
+    /// Pending signed transfers
+    transfers: Arc<RwLock<PendingTransfers>>,
+    /// Bumped whenever the pending transfers change
+    transfer_sequence: Arc<AtomicU64>,
+    /// Transfers admitted to the mempool
+    transfer_events: broadcast::Sender<SignedTransfer>,
 }
 
 impl ForgePool {
@@ -38,55 +143,219 @@ impl ForgePool {
         Self {
             pending: Arc::new(RwLock::new(HashMap::new())),
             priority_queue: Arc::new(RwLock::new(BTreeSet::new())),
-            max_size,
-            min_fee,
+            prophecies: Arc::new(RwLock::new(HashMap::new())),
+            max_size: AtomicUsize::new(max_size),
+            min_fee: Mutex::new(RollingMinFee::new(
+                min_fee,
+                DEFAULT_INCREMENTAL_FEE,
+                DEFAULT_MIN_FEE_HALF_LIFE_SECS,
+            )),
+            dust_threshold: DUST_THRESHOLD,
+            max_forges_per_block: MAX_FORGES_PER_BLOCK,
+            policies: Vec::new(),
+            tip_height: Arc::new(RwLock::new(0)),
+            sequence: Arc::new(AtomicU64::new(0)),
+            events: broadcast::channel(MEMPOOL_EVENT_CAPACITY).0,
+            transfers: Arc::new(RwLock::new(PendingTransfers::default())),
+            transfer_sequence: Arc::new(AtomicU64::new(0)),
+            transfer_events: broadcast::channel(MEMPOOL_EVENT_CAPACITY).0,
+        }
+    }
+
+    /// Use a custom dust threshold for transfer outputs
+    pub fn with_dust_threshold(mut self, dust_threshold: u64) -> Self {
+        self.dust_threshold = dust_threshold;
+        self
+    }
+
+    /// Follow a chain's block limit instead of mainnet's
+    pub fn with_chain_params(mut self, params: &ChainParams) -> Self {
+        self.max_forges_per_block = params.max_forges_per_block;
+        self
+    }
+
+    /// Raise the minimum fee `incremental_fee` past each evicted transfer's
+    /// fee, halving every `half_life_secs`
+    pub fn with_rolling_min_fee(mut self, incremental_fee: u64, half_life_secs: u64) -> Self {
+        let min_fee = self.min_fee.get_mut().unwrap();
+        *min_fee = RollingMinFee::new(min_fee.floor(), incremental_fee, half_life_secs);
+        self
+    }
+
+    /// Minimum fee currently enforced: the configured one, or higher after
+    /// recent evictions
+    pub fn min_fee(&self) -> u64 {
+        self.min_fee.lock().unwrap().current(unix_now())
+    }
+
+    /// Minimum fee counters
+    pub fn min_fee_stats(&self) -> MinFeeStats {
+        self.min_fee.lock().unwrap().stats(unix_now())
+    }
+
+    /// Change the size limit and minimum fee settings of a running pool.
+    /// Entries already held stay; a smaller limit is reached by evicting
+    /// transfers and refusing forges as new ones arrive.
+    pub fn set_limits(&self, max_size: usize, min_fee: u64, incremental_fee: u64, half_life_secs: u64) {
+        self.max_size.store(max_size, Ordering::Relaxed);
+        self.min_fee.lock().unwrap().set_policy(min_fee, incremental_fee, half_life_secs);
+    }
+
+    /// Add an admission policy checked after the built-in rules
+    pub fn with_policy(mut self, policy: Box<dyn ForgePolicy>) -> Self {
+        self.policies.push(policy);
+        self
+    }
+
+    /// Dust threshold applied to transfer outputs
+    pub fn dust_threshold(&self) -> u64 {
+        self.dust_threshold
+    }
+
+    /// Relay policy for a transfer: consensus output rules, a fee of at
+    /// least the current minimum, and no output below the dust threshold
+    pub fn check_transfer_policy(&self, transfer: &Transfer) -> Result<()> {
+        check_transfer_outputs(transfer)?;
+        let min_fee = self.min_fee();
+        if transfer.fee < min_fee {
+            return Err(anyhow!("Transfer fee {} is below the mempool minimum {}", transfer.fee, min_fee));
         }
+        if let Some((index, output)) = transfer
+            .outputs
+            .iter()
+            .enumerate()
+            .find(|(_, output)| is_dust(output.value, self.dust_threshold))
+        {
+            return Err(anyhow!(
+                "Transfer output {} value {} is dust (threshold {})",
+                index,
+                output.value,
+                self.dust_threshold
+            ));
+        }
+        Ok(())
+    }
+
+    /// Subscribe to ordered mempool events
+    pub fn subscribe(&self) -> broadcast::Receiver<MempoolEvent> {
+        self.events.subscribe()
+    }
+
+    /// Sequence number of the last emitted event (0 if none)
+    pub fn sequence(&self) -> u64 {
+        self.sequence.load(Ordering::SeqCst)
+    }
+
+    /// Current proof hashes together with the sequence number they are
+    /// consistent with; events after that number apply on top
+    pub fn hashes_with_sequence(&self) -> (u64, Vec<[u8; 32]>) {
+        let pending = self.pending.read().unwrap();
+        (self.sequence(), pending.keys().cloned().collect())
+    }
+
+    /// Emit an event. Callers hold the `pending` write lock so sequence
+    /// order matches the order changes were applied.
+    fn notify(&self, proof_hash: [u8; 32], kind: MempoolEventKind) {
+        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst) + 1;
+        // No subscribers is not an error
+        let _ = self.events.send(MempoolEvent {
+            sequence,
+            proof_hash,
+            kind,
+        });
+    }
+
+    /// Update the chain tip height used for lock-height policy
+    pub fn set_tip_height(&self, height: u64) {
+        *self.tip_height.write().unwrap() = height;
+    }
+
+    /// Get the chain tip height known to the mempool
+    pub fn tip_height(&self) -> u64 {
+        *self.tip_height.read().unwrap()
     }
 
     /// Add a forge transaction to the mempool
     pub fn add_forge(&self, forge: ForgeTransaction) -> Result<()> {
+        // Only accept forges that can be mined in the next block
+        let next_height = self.tip_height() + 1;
+        if forge.not_before_height > next_height {
+            return Err(anyhow!(
+                "Forge is locked until height {} (next block is {})",
+                forge.not_before_height,
+                next_height
+            ));
+        }
+        forge.verify_signature()?;
+
+        for policy in &self.policies {
+            policy
+                .check_forge(&forge)
+                .map_err(|e| e.context(format!("Rejected by mempool policy {}", policy.name())))?;
+        }
+
+        let prophecy_hash = prophecy_registry_hash(&forge.prophecy);
         let mut pending = self.pending.write().unwrap();
         let mut priority_queue = self.priority_queue.write().unwrap();
+        let mut prophecies = self.prophecies.write().unwrap();
 
         // Check if already in mempool
         if pending.contains_key(&forge.proof_hash) {
             return Err(anyhow!("Forge already in mempool"));
         }
 
-        // Check mempool size limit
-        if pending.len() >= self.max_size {
+        // Each prophecy can only be forged once, so only one forge of it waits
+        if let Some(other) = prophecies.get(&prophecy_hash) {
+            return Err(anyhow!(
+                "Forge of the same prophecy already pending: {}",
+                hex::encode(other)
+            ));
+        }
+
+        // Forges carry no fee to outbid each other with, so a full pool
+        // keeps what it has rather than evicting honest forges
+        if pending.len() >= self.max_size.load(Ordering::Relaxed) {
             return Err(anyhow!("Mempool is full"));
         }
+        let now = unix_now();
 
         // Calculate priority (earlier timestamp = higher priority)
         let priority = ForgePriority {
             timestamp: forge.timestamp,
-            fee: self.min_fee,
         };
-        
+
         let proof_hash = forge.proof_hash;
 
         // Create entry (transfer ownership to Arc without cloning)
         let entry = MempoolEntry {
             forge: Arc::new(forge),
+            prophecy_hash,
             priority,
-            added_at: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
+            added_at: now,
         };
 
         // Add to mempool
         pending.insert(proof_hash, entry);
         priority_queue.insert((proof_hash, priority));
+        prophecies.insert(prophecy_hash, proof_hash);
+        self.notify(proof_hash, MempoolEventKind::Added);
 
-        tracing::info!("Added forge to mempool: {:?}", hex::encode(&proof_hash));
+        tracing::info!("Added forge to mempool: {:?}", hex::encode(proof_hash));
 
         Ok(())
     }
 
     /// Remove a forge from the mempool
     pub fn remove_forge(&self, proof_hash: &[u8; 32]) -> Result<Arc<ForgeTransaction>> {
+        self.remove_forge_with_reason(proof_hash, RemovalReason::Manual)
+    }
+
+    /// Remove a forge from the mempool, reporting why to subscribers
+    pub fn remove_forge_with_reason(
+        &self,
+        proof_hash: &[u8; 32],
+        reason: RemovalReason,
+    ) -> Result<Arc<ForgeTransaction>> {
         let mut pending = self.pending.write().unwrap();
         let mut priority_queue = self.priority_queue.write().unwrap();
 
@@ -95,6 +364,8 @@ impl ForgePool {
             .ok_or_else(|| anyhow!("Forge not found in mempool"))?;
 
         priority_queue.remove(&(*proof_hash, entry.priority));
+        self.prophecies.write().unwrap().remove(&entry.prophecy_hash);
+        self.notify(*proof_hash, MempoolEventKind::Removed { reason });
 
         Ok(entry.forge)
     }
@@ -117,8 +388,10 @@ impl ForgePool {
         pending.len()
     }
 
-    /// Get forges for inclusion in a new block
+    /// Get forges for inclusion in a new block, no more than `max_forges`
+    /// or the chain's block limit
     pub fn get_forges_for_block(&self, max_forges: usize) -> Vec<Arc<ForgeTransaction>> {
+        let max_forges = max_forges.min(self.max_forges_per_block);
         let pending = self.pending.read().unwrap();
         let priority_queue = self.priority_queue.read().unwrap();
 
@@ -130,13 +403,128 @@ impl ForgePool {
             .collect()
     }
 
-    /// Remove forges that are included in a block
+    /// Remove forges that are included in a block, and pending forges of
+    /// the prophecies it forged, which can no longer be mined
     pub fn remove_block_forges(&self, block: &Block) -> Result<()> {
         for forge in &block.forges {
             if self.contains(&forge.proof_hash) {
-                self.remove_forge(&forge.proof_hash)?;
+                self.remove_forge_with_reason(&forge.proof_hash, RemovalReason::Block)?;
+            }
+        }
+        let conflicting: Vec<[u8; 32]> = {
+            let prophecies = self.prophecies.read().unwrap();
+            block
+                .forges
+                .iter()
+                .filter_map(|forge| prophecies.get(&prophecy_registry_hash(&forge.prophecy)).copied())
+                .collect()
+        };
+        for proof_hash in conflicting {
+            // Removed meanwhile is fine
+            let _ = self.remove_forge_with_reason(&proof_hash, RemovalReason::Conflict);
+        }
+        Ok(())
+    }
+
+    /// Add a signed transfer after relay policy, fee and conflict checks.
+    /// Callers check it against the ledger first
+    /// (`ConsensusEngine::check_transfer`). Returns its txid.
+    ///
+    /// A full pool evicts the lowest-fee transfer to make room for one
+    /// paying more, and the minimum fee rises past the evicted fee.
+    pub fn add_transfer(&self, transfer: SignedTransfer) -> Result<[u8; 32]> {
+        self.check_transfer_policy(&transfer.transfer)?;
+        let txid = transfer.txid()?;
+
+        let mut transfers = self.transfers.write().unwrap();
+        if transfers.by_txid.contains_key(&txid) {
+            return Err(anyhow!("Transfer already in mempool"));
+        }
+        if let Some((input, other)) = transfer
+            .transfer
+            .inputs
+            .iter()
+            .find_map(|input| transfers.spends.get(&input.prevout).map(|other| (input, other)))
+        {
+            return Err(anyhow!(
+                "Transfer spending {}:{} conflicts with pending transfer {}",
+                hex::encode(input.prevout.txid),
+                input.prevout.vout,
+                hex::encode(other)
+            ));
+        }
+        if transfers.by_txid.len() >= self.max_size.load(Ordering::Relaxed) {
+            let lowest = transfers
+                .by_txid
+                .iter()
+                .min_by_key(|(txid, pending)| (pending.transfer.fee, **txid))
+                .map(|(txid, pending)| (*txid, pending.transfer.fee));
+            let Some((evicted, evicted_fee)) = lowest.filter(|(_, fee)| *fee < transfer.transfer.fee) else {
+                return Err(anyhow!("Mempool is full"));
+            };
+            transfers.remove(&evicted);
+            self.min_fee.lock().unwrap().record_eviction(evicted_fee, unix_now());
+            tracing::debug!("Evicted transfer {} from full mempool", hex::encode(evicted));
+        }
+
+        for input in &transfer.transfer.inputs {
+            transfers.spends.insert(input.prevout, txid);
+        }
+        transfers.by_txid.insert(txid, transfer.clone());
+        self.transfer_sequence.fetch_add(1, Ordering::SeqCst);
+        // No subscribers is not an error
+        let _ = self.transfer_events.send(transfer);
+        tracing::info!("Added transfer to mempool: {}", hex::encode(txid));
+        Ok(txid)
+    }
+
+    /// Subscribe to transfers as they are admitted
+    pub fn subscribe_transfers(&self) -> broadcast::Receiver<SignedTransfer> {
+        self.transfer_events.subscribe()
+    }
+
+    /// Get a pending transfer by txid
+    pub fn get_transfer(&self, txid: &[u8; 32]) -> Option<SignedTransfer> {
+        self.transfers.read().unwrap().by_txid.get(txid).cloned()
+    }
+
+    /// Number of pending transfers
+    pub fn transfer_count(&self) -> usize {
+        self.transfers.read().unwrap().by_txid.len()
+    }
+
+    /// Counter bumped whenever the pending transfers change, so templates
+    /// can tell they are stale
+    pub fn transfer_sequence(&self) -> u64 {
+        self.transfer_sequence.load(Ordering::SeqCst)
+    }
+
+    /// Pending transfers for a new block, highest fee first, no more than
+    /// `max_transfers`
+    pub fn get_transfers_for_block(&self, max_transfers: usize) -> Vec<SignedTransfer> {
+        let transfers = self.transfers.read().unwrap();
+        let mut selected: Vec<(&[u8; 32], &SignedTransfer)> = transfers.by_txid.iter().collect();
+        selected.sort_by(|(a_txid, a), (b_txid, b)| b.transfer.fee.cmp(&a.transfer.fee).then(a_txid.cmp(b_txid)));
+        selected
+            .into_iter()
+            .take(max_transfers)
+            .map(|(_, transfer)| transfer.clone())
+            .collect()
+    }
+
+    /// Remove transfers included in a block, and pending transfers spending
+    /// outputs the block's transfers spent
+    pub fn remove_block_transfers(&self, block: &Block) -> Result<()> {
+        let mut transfers = self.transfers.write().unwrap();
+        for transfer in &block.transfers {
+            transfers.remove(&transfer.txid()?);
+            for input in &transfer.transfer.inputs {
+                if let Some(conflicting) = transfers.spends.get(&input.prevout).copied() {
+                    transfers.remove(&conflicting);
+                }
             }
         }
+        self.transfer_sequence.fetch_add(1, Ordering::SeqCst);
         Ok(())
     }
 
@@ -150,8 +538,14 @@ impl ForgePool {
     pub fn clear(&self) {
         let mut pending = self.pending.write().unwrap();
         let mut priority_queue = self.priority_queue.write().unwrap();
+        for proof_hash in pending.keys() {
+            self.notify(*proof_hash, MempoolEventKind::Removed { reason: RemovalReason::Manual });
+        }
         pending.clear();
         priority_queue.clear();
+        self.prophecies.write().unwrap().clear();
+        *self.transfers.write().unwrap() = PendingTransfers::default();
+        self.transfer_sequence.fetch_add(1, Ordering::SeqCst);
     }
 
     /// Remove expired forges (older than timeout)
@@ -175,6 +569,8 @@ impl ForgePool {
         for hash in expired {
             if let Some(entry) = pending.remove(&hash) {
                 priority_queue.remove(&(hash, entry.priority));
+                self.prophecies.write().unwrap().remove(&entry.prophecy_hash);
+                self.notify(hash, MempoolEventKind::Removed { reason: RemovalReason::Expiry });
             }
         }
 
@@ -185,14 +581,60 @@ impl ForgePool {
         count
     }
 
+    /// Re-check every entry with `check`, evicting those that fail with
+    /// `RemovalReason::Invalid`. Returns the evicted proof hashes with
+    /// their errors. Entries are checked without holding the pool locks.
+    pub fn revalidate<F>(&self, check: F) -> Vec<([u8; 32], String)>
+    where
+        F: Fn(&ForgeTransaction) -> Result<()>,
+    {
+        let entries: Vec<Arc<ForgeTransaction>> =
+            self.pending.read().unwrap().values().map(|entry| Arc::clone(&entry.forge)).collect();
+        let mut evicted = Vec::new();
+        for forge in entries {
+            let Err(e) = check(&forge) else {
+                continue;
+            };
+            // Mined or removed meanwhile
+            if self.remove_forge_with_reason(&forge.proof_hash, RemovalReason::Invalid).is_ok() {
+                evicted.push((forge.proof_hash, format!("{:#}", e)));
+            }
+        }
+        evicted
+    }
+
+    /// Canonical hash of the current entries, for comparing mempools
+    /// across nodes.
+    ///
+    /// Entries are hashed in proof-hash order and commit to the full forge,
+    /// so two pools hash equal only if they hold identical forges.
+    pub fn snapshot_hash(&self) -> MempoolSnapshotHash {
+        let pending = self.pending.read().unwrap();
+
+        let mut entries: Vec<_> = pending.iter().collect();
+        entries.sort_unstable_by_key(|(proof_hash, _)| **proof_hash);
+
+        let mut hasher = Sha256::new();
+        hasher.update((entries.len() as u64).to_le_bytes());
+        for (proof_hash, entry) in &entries {
+            hasher.update(proof_hash);
+            hasher.update(Sha256::digest(entry.forge.encode()));
+        }
+
+        MempoolSnapshotHash {
+            size: entries.len(),
+            hash: hasher.finalize().into(),
+        }
+    }
+
     /// Get mempool statistics
     pub fn get_stats(&self) -> MempoolStats {
         let pending = self.pending.read().unwrap();
 
         MempoolStats {
             size: pending.len(),
-            max_size: self.max_size,
-            min_fee: self.min_fee,
+            max_size: self.max_size.load(Ordering::Relaxed),
+            min_fee: self.min_fee(),
         }
     }
 }
@@ -202,34 +644,151 @@ impl ForgePool {
 pub struct MempoolStats {
     pub size: usize,
     pub max_size: usize,
+    /// Minimum fee currently enforced
     pub min_fee: u64,
 }
 
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Canonical hash of the mempool contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MempoolSnapshotHash {
+    pub size: usize,
+    pub hash: [u8; 32],
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::consensus::testing;
 
-    fn create_test_forge(timestamp: u64, proof_hash: [u8; 32]) -> ForgeTransaction {
-        ForgeTransaction {
-            prophecy: "sword legend pull magic kingdom artist stone destroy forget fire steel honey question".to_string(),
-            derived_key: vec![1, 2, 3],
-            taproot_address: "bc1p...".to_string(),
-            proof_hash,
-            timestamp,
-            signature: vec![],
+    #[test]
+    fn test_forge_pool_creation() {
+        let pool = ForgePool::new(100, 1000);
+        assert_eq!(pool.size(), 0);
+    }
+
+    #[test]
+    fn test_transfer_dust_rejected() {
+        use crate::consensus::sighash::{TransferInput, TransferOutput};
+        use crate::ledger::{OutPoint, MIN_OUTPUT_VALUE};
+
+        let transfer = |change: u64| Transfer {
+            version: 1,
+            inputs: vec![TransferInput {
+                prevout: OutPoint { txid: [1u8; 32], vout: 0 },
+                amount: 100_000,
+                address: "bc1p...".to_string(),
+            }],
+            outputs: vec![
+                TransferOutput { address: "bc1q...".to_string(), value: 50_000 },
+                TransferOutput { address: "bc1p...".to_string(), value: change },
+            ],
+            fee: 50_000 - change,
+            lock_height: 0,
+        };
+
+        let pool = ForgePool::new(100, 1000);
+        assert!(pool.check_transfer_policy(&transfer(DUST_THRESHOLD)).is_ok());
+        // Valid under consensus, but dust under policy
+        assert!(check_transfer_outputs(&transfer(MIN_OUTPUT_VALUE)).is_ok());
+        assert!(pool.check_transfer_policy(&transfer(MIN_OUTPUT_VALUE)).is_err());
+
+        let lenient = ForgePool::new(100, 1000).with_dust_threshold(MIN_OUTPUT_VALUE);
+        assert!(lenient.check_transfer_policy(&transfer(MIN_OUTPUT_VALUE)).is_ok());
+
+        // The fee must meet the mempool minimum
+        let error = ForgePool::new(100, 50_000).check_transfer_policy(&transfer(DUST_THRESHOLD)).unwrap_err();
+        assert!(error.to_string().contains("below the mempool minimum"), "{}", error);
+    }
+
+    struct NoFutureForges;
+
+    impl ForgePolicy for NoFutureForges {
+        fn name(&self) -> &str {
+            "no-future-forges"
+        }
+
+        fn check_forge(&self, forge: &ForgeTransaction) -> Result<()> {
+            if forge.timestamp > 1500 {
+                return Err(anyhow!("timestamp too far ahead"));
+            }
+            Ok(())
         }
     }
 
     #[test]
-    fn test_forge_pool_creation() {
+    fn test_conflicting_transfers() {
+        use crate::consensus::sighash::{TransferInput, TransferOutput};
+
+        let transfer = |vout: u32, value: u64| SignedTransfer {
+            transfer: Transfer {
+                version: 1,
+                inputs: vec![TransferInput {
+                    prevout: OutPoint { txid: [1u8; 32], vout },
+                    amount: 100_000,
+                    address: "bc1p...".to_string(),
+                }],
+                outputs: vec![TransferOutput { address: "bc1q...".to_string(), value }],
+                fee: 100_000 - value,
+                lock_height: 0,
+            },
+            witnesses: Vec::new(),
+        };
+
         let pool = ForgePool::new(100, 1000);
-        assert_eq!(pool.size(), 0);
+        assert!(pool.add_transfer(transfer(0, 99_500)).is_err(), "fee below minimum");
+        pool.add_transfer(transfer(0, 90_000)).unwrap();
+        // A second spend of the same output conflicts
+        assert!(pool.add_transfer(transfer(0, 80_000)).unwrap_err().to_string().contains("conflicts"));
+        pool.add_transfer(transfer(1, 50_000)).unwrap();
+        assert_eq!(pool.get_transfers_for_block(10)[0], transfer(1, 50_000), "highest fee first");
+
+        // A block spending output 0 another way evicts the pending spend of it
+        let sequence = pool.transfer_sequence();
+        let mut block = testing::block(1, [0u8; 32], Vec::new());
+        block.transfers = vec![transfer(0, 80_000)];
+        pool.remove_block_transfers(&block).unwrap();
+        assert_eq!(pool.get_transfers_for_block(10), vec![transfer(1, 50_000)]);
+        assert!(pool.transfer_sequence() > sequence);
+    }
+
+    #[test]
+    fn test_policy_rejects_forge() {
+        let pool = ForgePool::new(100, 1000).with_policy(Box::new(NoFutureForges));
+
+        let err = pool.add_forge(testing::forge_with(1, |forge| forge.timestamp = 2000)).unwrap_err();
+        assert!(format!("{:#}", err).contains("no-future-forges"));
+
+        assert!(pool.add_forge(testing::forge(2)).is_ok());
+        assert_eq!(pool.size(), 1);
+    }
+
+    #[test]
+    fn test_unsigned_forge_rejected() {
+        let pool = ForgePool::new(100, 1000);
+
+        let mut unsigned = testing::forge(1);
+        unsigned.signature.clear();
+        assert!(pool.add_forge(unsigned).unwrap_err().to_string().contains("unsigned"));
+
+        // Any change after signing invalidates the signature
+        let mut altered = testing::forge(2);
+        altered.taproot_address = "bc1pother".to_string();
+        assert!(pool.add_forge(altered.clone()).is_err());
+        altered.sign(&[2u8; 32]).unwrap();
+        assert!(pool.add_forge(altered).is_ok());
     }
 
     #[test]
     fn test_add_forge() {
         let pool = ForgePool::new(100, 1000);
-        let forge = create_test_forge(1000, [1u8; 32]);
+        let forge = testing::forge(1);
         
         assert!(pool.add_forge(forge).is_ok());
         assert_eq!(pool.size(), 1);
@@ -238,7 +797,7 @@ mod tests {
     #[test]
     fn test_add_duplicate_forge() {
         let pool = ForgePool::new(100, 1000);
-        let forge = create_test_forge(1000, [1u8; 32]);
+        let forge = testing::forge(1);
         
         pool.add_forge(forge.clone()).unwrap();
         
@@ -250,7 +809,7 @@ mod tests {
     fn test_remove_forge() {
         let pool = ForgePool::new(100, 1000);
         let proof_hash = [1u8; 32];
-        let forge = create_test_forge(1000, proof_hash);
+        let forge = testing::forge(1);
         
         pool.add_forge(forge).unwrap();
         assert_eq!(pool.size(), 1);
@@ -263,7 +822,7 @@ mod tests {
     fn test_contains() {
         let pool = ForgePool::new(100, 1000);
         let proof_hash = [1u8; 32];
-        let forge = create_test_forge(1000, proof_hash);
+        let forge = testing::forge(1);
         
         assert!(!pool.contains(&proof_hash));
         pool.add_forge(forge).unwrap();
@@ -275,35 +834,164 @@ mod tests {
         let pool = ForgePool::new(100, 1000);
         
         // Add multiple forges with different timestamps
-        for i in 0..5 {
-            let forge = create_test_forge(1000 + i, [i as u8; 32]);
-            pool.add_forge(forge).unwrap();
+        for seed in 1..=5 {
+            pool.add_forge(testing::forge(seed)).unwrap();
         }
         
         let forges = pool.get_forges_for_block(3);
         assert_eq!(forges.len(), 3);
+
+        // One forge of a prophecy waits at a time, and mining the prophecy
+        // drops it
+        let rival = testing::forge_with(9, |forge| forge.prophecy = testing::forge(1).prophecy);
+        let error = pool.add_forge(rival.clone()).unwrap_err();
+        assert!(error.to_string().contains("same prophecy"), "{}", error);
+        let mut events = pool.subscribe();
+        let block = testing::block(1, [0u8; 32], vec![rival]);
+        pool.remove_block_forges(&block).unwrap();
+        assert!(!pool.contains(&[1u8; 32]));
+        assert_eq!(events.try_recv().unwrap().kind, MempoolEventKind::Removed { reason: RemovalReason::Conflict });
+        assert_eq!(pool.size(), 4);
+
+        // Never more than the chain's block limit
+        let params = ChainParams {
+            max_forges_per_block: 2,
+            ..ChainParams::regtest()
+        };
+        let pool = pool.with_chain_params(&params);
+        assert_eq!(pool.get_forges_for_block(3).len(), 2);
     }
 
     #[test]
     fn test_mempool_size_limit() {
         let pool = ForgePool::new(2, 1000);
-        
-        pool.add_forge(create_test_forge(1000, [1u8; 32])).unwrap();
-        pool.add_forge(create_test_forge(1001, [2u8; 32])).unwrap();
-        
-        let result = pool.add_forge(create_test_forge(1002, [3u8; 32]));
-        assert!(result.is_err());
+        let mut events = pool.subscribe();
+
+        pool.add_forge(testing::forge(1)).unwrap();
+        pool.add_forge(testing::forge(2)).unwrap();
+
+        // A full pool refuses new forges instead of evicting held ones
+        let error = pool.add_forge(testing::forge(3)).unwrap_err();
+        assert!(error.to_string().contains("full"), "{}", error);
+        assert!(pool.contains(&[1u8; 32]) && pool.contains(&[2u8; 32]));
+        let kinds: Vec<MempoolEventKind> = std::iter::from_fn(|| events.try_recv().ok()).map(|e| e.kind).collect();
+        assert_eq!(kinds, [MempoolEventKind::Added, MempoolEventKind::Added]);
+
+        // Room frees up as forges leave
+        pool.remove_forge(&[1u8; 32]).unwrap();
+        pool.add_forge(testing::forge(3)).unwrap();
+        assert_eq!(pool.size(), 2);
+    }
+
+    #[test]
+    fn test_full_pool_evicts_cheapest_transfer() {
+        use crate::consensus::sighash::{TransferInput, TransferOutput};
+
+        let transfer = |vout: u32, fee: u64| SignedTransfer {
+            transfer: Transfer {
+                version: 1,
+                inputs: vec![TransferInput {
+                    prevout: OutPoint { txid: [1u8; 32], vout },
+                    amount: 100_000,
+                    address: "bc1p...".to_string(),
+                }],
+                outputs: vec![TransferOutput { address: "bc1q...".to_string(), value: 100_000 - fee }],
+                fee,
+                lock_height: 0,
+            },
+            witnesses: Vec::new(),
+        };
+
+        let pool = ForgePool::new(2, 1000);
+        let cheap = pool.add_transfer(transfer(0, 1_000)).unwrap();
+        pool.add_transfer(transfer(1, 5_000)).unwrap();
+
+        // Paying no more than the cheapest held transfer is refused
+        assert!(pool.add_transfer(transfer(2, 1_000)).unwrap_err().to_string().contains("full"));
+
+        // Outbidding it evicts it and prices it out
+        pool.add_transfer(transfer(2, 3_000)).unwrap();
+        assert_eq!(pool.transfer_count(), 2);
+        assert!(pool.get_transfer(&cheap).is_none());
+        assert_eq!(pool.min_fee(), 1_000 + DEFAULT_INCREMENTAL_FEE);
+        let error = pool.add_transfer(transfer(0, 1_500)).unwrap_err();
+        assert!(error.to_string().contains("below the mempool minimum"), "{}", error);
+
+        // Evicting a pricier transfer raises it further
+        pool.add_transfer(transfer(3, 4_000)).unwrap();
+        assert_eq!(pool.min_fee(), 3_000 + DEFAULT_INCREMENTAL_FEE);
+        assert_eq!(pool.min_fee_stats().evictions, 2);
+        assert_eq!(pool.get_stats().min_fee, pool.min_fee());
     }
 
     #[test]
     fn test_clear() {
         let pool = ForgePool::new(100, 1000);
         
-        pool.add_forge(create_test_forge(1000, [1u8; 32])).unwrap();
-        pool.add_forge(create_test_forge(1001, [2u8; 32])).unwrap();
+        pool.add_forge(testing::forge(1)).unwrap();
+        pool.add_forge(testing::forge(2)).unwrap();
         
         assert_eq!(pool.size(), 2);
         pool.clear();
         assert_eq!(pool.size(), 0);
     }
+
+    #[test]
+    fn test_lock_height_policy() {
+        let pool = ForgePool::new(100, 1000);
+        pool.set_tip_height(10);
+
+        // Locked to the next block: accepted
+        let forge = testing::forge_with(1, |forge| forge.not_before_height = 11);
+        assert!(pool.add_forge(forge).is_ok());
+
+        // Locked beyond the next block: rejected
+        let forge = testing::forge_with(2, |forge| forge.not_before_height = 12);
+        assert!(pool.add_forge(forge).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_hash_is_order_independent() {
+        let a = ForgePool::new(100, 0);
+        let b = ForgePool::new(100, 0);
+        assert_eq!(a.snapshot_hash(), b.snapshot_hash());
+
+        // Signatures are randomized, so both pools get the same copies
+        let first = testing::forge(1);
+        let second = testing::forge_with(2, |forge| forge.timestamp = 2000);
+        a.add_forge(first.clone()).unwrap();
+        a.add_forge(second.clone()).unwrap();
+        b.add_forge(second).unwrap();
+        b.add_forge(first).unwrap();
+        assert_eq!(a.snapshot_hash(), b.snapshot_hash());
+        assert_eq!(a.snapshot_hash().size, 2);
+
+        // Same proof hash but different contents diverges
+        b.remove_forge(&[2u8; 32]).unwrap();
+        b.add_forge(testing::forge_with(2, |forge| forge.timestamp = 2001)).unwrap();
+        assert_ne!(a.snapshot_hash(), b.snapshot_hash());
+    }
+
+    #[test]
+    fn test_event_sequence() {
+        let pool = ForgePool::new(100, 0);
+        let mut events = pool.subscribe();
+
+        pool.add_forge(testing::forge(1)).unwrap();
+        pool.add_forge(testing::forge(2)).unwrap();
+        pool.remove_forge(&[1u8; 32]).unwrap();
+
+        let added = events.try_recv().unwrap();
+        assert_eq!(added.sequence, 1);
+        assert_eq!(added.kind, MempoolEventKind::Added);
+        assert_eq!(events.try_recv().unwrap().sequence, 2);
+        let removed = events.try_recv().unwrap();
+        assert_eq!(removed.sequence, 3);
+        assert_eq!(removed.proof_hash, [1u8; 32]);
+        assert_eq!(removed.kind, MempoolEventKind::Removed { reason: RemovalReason::Manual });
+
+        let (sequence, hashes) = pool.hashes_with_sequence();
+        assert_eq!(sequence, 3);
+        assert_eq!(hashes, vec![[2u8; 32]]);
+    }
 }
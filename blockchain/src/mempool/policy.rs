@@ -0,0 +1,19 @@
+//! Operator-supplied mempool admission policy
+
+use crate::consensus::ForgeTransaction;
+use anyhow::Result;
+
+/// Default instruction budget for one WASM policy filter call
+pub const DEFAULT_POLICY_FUEL: u64 = 10_000_000;
+
+/// Extra relay policy checked before a forge enters the mempool.
+///
+/// Policies only tighten admission; they run after the built-in checks in
+/// registration order and the first rejection wins.
+pub trait ForgePolicy: Send + Sync {
+    /// Name used in rejection messages
+    fn name(&self) -> &str;
+
+    /// Accept or reject a forge for relay
+    fn check_forge(&self, forge: &ForgeTransaction) -> Result<()>;
+}
@@ -0,0 +1,285 @@
+//! Bounded queue of forges awaiting validation
+//!
+//! Full validation reruns the proof-of-forge pipeline, so forges wait their
+//! turn for a fixed number of workers on the blocking pool. Forges submitted
+//! locally (RPC, wallet) and forges received over gossip are queued
+//! separately, each with its own capacity, and workers always take local
+//! forges first. A gossip flood fills only the gossip queue and cannot delay
+//! the operator's own submissions. Forges returned by a reorg were already
+//! mined once, so they get a third queue, bounded by the disconnected
+//! blocks and taken after local forges but ahead of gossip.
+
+use super::ForgePool;
+use crate::consensus::{ConsensusEngine, ForgeTransaction};
+use crate::crypto::CancelToken;
+use crate::shutdown::ShutdownSignal;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{oneshot, Notify, Semaphore};
+
+/// Default number of forges validated at once
+pub const DEFAULT_VALIDATION_WORKERS: usize = 4;
+
+/// Default number of queued local submissions
+pub const DEFAULT_LOCAL_QUEUE_SIZE: usize = 256;
+
+/// Default number of queued gossiped forges
+pub const DEFAULT_GOSSIP_QUEUE_SIZE: usize = 1024;
+
+/// Where a forge came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForgeOrigin {
+    /// Submitted through RPC or the wallet
+    Local,
+    /// Received from a peer
+    Gossip,
+    /// From a block disconnected by a reorg
+    Reorg,
+}
+
+/// Queue lengths and outcome counters
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ValidationQueueStats {
+    pub local_queued: usize,
+    pub gossip_queued: usize,
+    pub reorg_queued: usize,
+    pub local_capacity: usize,
+    pub gossip_capacity: usize,
+    /// Local submissions refused because their queue was full
+    pub local_refused: u64,
+    /// Gossiped forges dropped because their queue was full
+    pub gossip_dropped: u64,
+    pub accepted: u64,
+    pub rejected: u64,
+}
+
+struct PendingForge {
+    forge: ForgeTransaction,
+    cancel: CancelToken,
+    reply: oneshot::Sender<Result<()>>,
+}
+
+#[derive(Default)]
+struct Queues {
+    local: VecDeque<PendingForge>,
+    gossip: VecDeque<PendingForge>,
+    reorg: VecDeque<PendingForge>,
+}
+
+/// Forges waiting for validation and mempool admission
+pub struct ValidationQueue {
+    engine: Arc<ConsensusEngine>,
+    pool: Arc<ForgePool>,
+    workers: usize,
+    local_capacity: usize,
+    gossip_capacity: usize,
+    queues: Mutex<Queues>,
+    queued: Notify,
+    local_refused: AtomicU64,
+    gossip_dropped: AtomicU64,
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl ValidationQueue {
+    /// Create a queue validating with `workers` workers (at least one)
+    pub fn new(
+        engine: Arc<ConsensusEngine>,
+        pool: Arc<ForgePool>,
+        workers: usize,
+        local_capacity: usize,
+        gossip_capacity: usize,
+    ) -> Self {
+        Self {
+            engine,
+            pool,
+            workers: workers.max(1),
+            local_capacity,
+            gossip_capacity,
+            queues: Mutex::new(Queues::default()),
+            queued: Notify::new(),
+            local_refused: AtomicU64::new(0),
+            gossip_dropped: AtomicU64::new(0),
+            accepted: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Queue a forge. The receiver yields the validation outcome; queueing
+    /// fails if the origin's queue is full. Reorged forges always queue.
+    pub fn submit(&self, forge: ForgeTransaction, origin: ForgeOrigin) -> Result<oneshot::Receiver<Result<()>>> {
+        self.submit_cancellable(forge, origin, CancelToken::new())
+    }
+
+    /// `submit`, with validation stopped by `cancel` whether the forge is
+    /// still queued or being validated. The outcome is then `ForgeCancelled`.
+    pub fn submit_cancellable(
+        &self,
+        forge: ForgeTransaction,
+        origin: ForgeOrigin,
+        cancel: CancelToken,
+    ) -> Result<oneshot::Receiver<Result<()>>> {
+        let (reply, outcome) = oneshot::channel();
+        {
+            let mut queues = self.queues.lock().unwrap();
+            let (queue, limit) = match origin {
+                ForgeOrigin::Local => (&mut queues.local, Some((self.local_capacity, &self.local_refused))),
+                ForgeOrigin::Gossip => (&mut queues.gossip, Some((self.gossip_capacity, &self.gossip_dropped))),
+                ForgeOrigin::Reorg => (&mut queues.reorg, None),
+            };
+            if let Some((_, full)) = limit.filter(|(capacity, _)| queue.len() >= *capacity) {
+                full.fetch_add(1, Ordering::Relaxed);
+                return Err(anyhow!("Validation queue for {:?} forges is full", origin));
+            }
+            queue.push_back(PendingForge { forge, cancel, reply });
+        }
+        self.queued.notify_one();
+        Ok(outcome)
+    }
+
+    /// Queue a forge and wait for it to be validated and admitted
+    pub async fn validate(&self, forge: ForgeTransaction, origin: ForgeOrigin) -> Result<()> {
+        self.submit(forge, origin)?
+            .await
+            .map_err(|_| anyhow!("Validation queue stopped"))?
+    }
+
+    /// Next forge to validate: local submissions, then reorged forges,
+    /// then gossip
+    fn next(&self) -> Option<PendingForge> {
+        let mut queues = self.queues.lock().unwrap();
+        queues
+            .local
+            .pop_front()
+            .or_else(|| queues.reorg.pop_front())
+            .or_else(|| queues.gossip.pop_front())
+    }
+
+    /// Queue lengths and outcome counters
+    pub fn stats(&self) -> ValidationQueueStats {
+        let queues = self.queues.lock().unwrap();
+        ValidationQueueStats {
+            local_queued: queues.local.len(),
+            gossip_queued: queues.gossip.len(),
+            reorg_queued: queues.reorg.len(),
+            local_capacity: self.local_capacity,
+            gossip_capacity: self.gossip_capacity,
+            local_refused: self.local_refused.load(Ordering::Relaxed),
+            gossip_dropped: self.gossip_dropped.load(Ordering::Relaxed),
+            accepted: self.accepted.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Proof hashes of the queued forges by origin, in the order workers
+    /// will take them
+    pub fn queued(&self) -> Vec<(ForgeOrigin, [u8; 32])> {
+        let queues = self.queues.lock().unwrap();
+        let local = queues.local.iter().map(|pending| (ForgeOrigin::Local, pending.forge.proof_hash));
+        let reorg = queues.reorg.iter().map(|pending| (ForgeOrigin::Reorg, pending.forge.proof_hash));
+        let gossip = queues.gossip.iter().map(|pending| (ForgeOrigin::Gossip, pending.forge.proof_hash));
+        local.chain(reorg).chain(gossip).collect()
+    }
+
+    /// Forges validated at once
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Validate queued forges on the blocking pool until shutdown
+    pub async fn run(self: Arc<Self>, mut shutdown: ShutdownSignal) {
+        let workers = Arc::new(Semaphore::new(self.workers));
+        loop {
+            let permit = tokio::select! {
+                permit = Arc::clone(&workers).acquire_owned() => permit.expect("worker semaphore is never closed"),
+                _ = shutdown.recv() => return,
+            };
+            // Picked only once a worker is free, so a local forge queued
+            // meanwhile still goes ahead of waiting gossip
+            let pending = loop {
+                if let Some(pending) = self.next() {
+                    break pending;
+                }
+                tokio::select! {
+                    _ = self.queued.notified() => {}
+                    _ = shutdown.recv() => return,
+                }
+            };
+            let queue = Arc::clone(&self);
+            tokio::task::spawn_blocking(move || {
+                let outcome = queue
+                    .engine
+                    .validate_forge_cancellable(&pending.forge, &pending.cancel)
+                    .and_then(|_| queue.pool.add_forge(pending.forge));
+                let counter = if outcome.is_ok() { &queue.accepted } else { &queue.rejected };
+                counter.fetch_add(1, Ordering::Relaxed);
+                // The submitter may have stopped waiting
+                let _ = pending.reply.send(outcome);
+                drop(permit);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::testing::{derived_forge, forge};
+    use crate::params::NetworkParams;
+
+    fn queue(local: usize, gossip: usize) -> ValidationQueue {
+        ValidationQueue::new(
+            Arc::new(ConsensusEngine::new(0, 600).with_params(&NetworkParams::regtest())),
+            Arc::new(ForgePool::new(10, 0)),
+            1,
+            local,
+            gossip,
+        )
+    }
+
+    #[test]
+    fn test_local_forges_first_with_separate_quotas() {
+        let queue = queue(1, 2);
+        queue.submit(forge(1), ForgeOrigin::Gossip).unwrap();
+        queue.submit(forge(2), ForgeOrigin::Gossip).unwrap();
+        assert!(queue.submit(forge(3), ForgeOrigin::Gossip).is_err(), "gossip quota is full");
+        queue.submit(forge(4), ForgeOrigin::Local).unwrap();
+        assert!(queue.submit(forge(5), ForgeOrigin::Local).is_err());
+
+        // Reorged forges aren't held to either quota and go ahead of gossip
+        queue.submit(forge(6), ForgeOrigin::Reorg).unwrap();
+        queue.submit(forge(7), ForgeOrigin::Reorg).unwrap();
+        assert_eq!(queue.queued()[1], (ForgeOrigin::Reorg, [6; 32]));
+
+        let order: Vec<u8> = std::iter::from_fn(|| queue.next()).map(|pending| pending.forge.proof_hash[0]).collect();
+        assert_eq!(order, vec![4, 6, 7, 1, 2]);
+        let stats = queue.stats();
+        assert_eq!((stats.gossip_dropped, stats.local_refused), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_outcome_reported_to_submitter() {
+        let queue = Arc::new(queue(4, 4));
+        let shutdown = crate::shutdown::ShutdownCoordinator::new();
+        let task = tokio::spawn(Arc::clone(&queue).run(shutdown.subscribe()));
+
+        // A valid forge is admitted to the pool
+        let valid = derived_forge(7, 1);
+        queue.validate(valid.clone(), ForgeOrigin::Local).await.unwrap();
+        assert!(queue.pool.contains(&valid.proof_hash));
+        assert_eq!(queue.stats().accepted, 1);
+
+        // A forge of a made-up prophecy is rejected before it is derived
+        let error = queue.validate(forge(1), ForgeOrigin::Gossip).await.unwrap_err();
+        assert!(error.to_string().starts_with("Invalid prophecy"), "{}", error);
+        assert!(!queue.pool.contains(&forge(1).proof_hash));
+        assert_eq!(queue.stats().rejected, 1);
+
+        shutdown.trigger();
+        task.await.unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+//! Mempool revalidation at rule activations
+//!
+//! Forges are checked against the rules in force for the next block when
+//! they are admitted. A consensus rule scheduled to activate
+//! (`ConsensusRule::activation_height`) can make admitted forges invalid
+//! once the next block reaches its height, so the revalidator watches the
+//! tip and, when an activation comes into force, re-checks the whole
+//! mempool on the blocking pool and evicts what no longer passes. Forges
+//! refused before an activation that would now pass are not recovered;
+//! peers and wallets resubmit them.
+
+use super::ForgePool;
+use crate::consensus::ConsensusEngine;
+use crate::shutdown::ShutdownSignal;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// Outcome of one mempool revalidation
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RevalidationReport {
+    /// Height of the block the mempool was checked for
+    pub height: u64,
+    /// Rules that activated at or before `height`
+    pub activations: Vec<String>,
+    /// Entries checked
+    pub checked: usize,
+    /// Evicted proof hashes (hex) with their rejection reasons
+    pub evicted: Vec<(String, String)>,
+}
+
+/// Revalidates the mempool whenever a scheduled rule activates
+pub struct MempoolRevalidator {
+    engine: Arc<ConsensusEngine>,
+    pool: Arc<ForgePool>,
+    /// Height of the next block when the tip was last checked
+    checked_height: Mutex<u64>,
+    last_report: Mutex<Option<RevalidationReport>>,
+}
+
+impl MempoolRevalidator {
+    /// Create a revalidator for the engine's current tip
+    pub fn new(engine: Arc<ConsensusEngine>, pool: Arc<ForgePool>) -> Self {
+        let checked_height = engine.get_height() + 1;
+        Self {
+            engine,
+            pool,
+            checked_height: Mutex::new(checked_height),
+            last_report: Mutex::new(None),
+        }
+    }
+
+    /// Revalidate the mempool if a rule activated between the last check
+    /// and the current tip's next block. Returns the report if it ran.
+    pub fn check_tip(&self) -> Option<RevalidationReport> {
+        let height = self.engine.get_height() + 1;
+        let previous = std::mem::replace(&mut *self.checked_height.lock().unwrap(), height);
+        // After a reorg to a lower tip, activations are crossed again as the
+        // chain regrows
+        if height <= previous {
+            return None;
+        }
+        let activations = self.engine.activations_between(previous, height);
+        if activations.is_empty() {
+            return None;
+        }
+        Some(self.revalidate(height, activations))
+    }
+
+    /// Re-check every mempool entry for inclusion at `height`
+    pub fn revalidate(&self, height: u64, activations: Vec<String>) -> RevalidationReport {
+        let checked = self.pool.size();
+        let evicted: Vec<(String, String)> = self
+            .pool
+            .revalidate(|forge| self.engine.check_forge_context(forge, height))
+            .into_iter()
+            .map(|(hash, reason)| (hex::encode(hash), reason))
+            .collect();
+        for (hash, reason) in &evicted {
+            tracing::debug!("Evicted forge {} after activation: {}", hash, reason);
+        }
+        tracing::info!(
+            "Revalidated {} mempool forges for height {} after activation of {}: {} evicted",
+            checked,
+            height,
+            activations.join(", "),
+            evicted.len()
+        );
+        let report = RevalidationReport {
+            height,
+            activations,
+            checked,
+            evicted,
+        };
+        *self.last_report.lock().unwrap() = Some(report.clone());
+        report
+    }
+
+    /// Most recent revalidation, if any ran
+    pub fn last_report(&self) -> Option<RevalidationReport> {
+        self.last_report.lock().unwrap().clone()
+    }
+
+    /// Check each new tip until shutdown, revalidating on the blocking pool
+    pub async fn run(self: Arc<Self>, mut tips: watch::Receiver<[u8; 32]>, mut shutdown: ShutdownSignal) {
+        loop {
+            tokio::select! {
+                changed = tips.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                    let revalidator = Arc::clone(&self);
+                    if let Err(e) = tokio::task::spawn_blocking(move || revalidator.check_tip()).await {
+                        tracing::warn!("Mempool revalidation failed: {}", e);
+                    }
+                }
+                _ = shutdown.recv() => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::{ConsensusRule, ForgeTransaction};
+    use crate::consensus::testing::{self, forge};
+    use crate::mempool::{MempoolEventKind, RemovalReason};
+    use anyhow::{anyhow, Result};
+
+    /// Refuses forges with an odd first proof-hash byte from height 2
+    struct EvenProofsFromTwo;
+
+    impl ConsensusRule for EvenProofsFromTwo {
+        fn name(&self) -> &str {
+            "even-proofs"
+        }
+
+        fn check_forge(&self, forge: &ForgeTransaction, height: u64) -> Result<()> {
+            if height >= 2 && forge.proof_hash[0] % 2 == 1 {
+                return Err(anyhow!("odd proof hash"));
+            }
+            Ok(())
+        }
+
+        fn activation_height(&self) -> Option<u64> {
+            Some(2)
+        }
+    }
+
+    #[test]
+    fn test_revalidation_evicts_at_activation() {
+        let mut engine = ConsensusEngine::new(0, 600);
+        engine.register_rule(Box::new(EvenProofsFromTwo));
+        let engine = Arc::new(engine);
+        let pool = Arc::new(ForgePool::new(10, 0));
+        let mut events = pool.subscribe();
+        pool.add_forge(forge(1)).unwrap();
+        pool.add_forge(forge(2)).unwrap();
+        while events.try_recv().is_ok() {}
+
+        let revalidator = MempoolRevalidator::new(Arc::clone(&engine), Arc::clone(&pool));
+        assert!(revalidator.check_tip().is_none(), "next block is below the activation");
+
+        engine.apply_block(&testing::block(1, engine.get_tip_hash(), vec![])).unwrap();
+        let report = revalidator.check_tip().unwrap();
+        assert_eq!((report.height, report.checked), (2, 2));
+        assert_eq!(report.activations, vec!["even-proofs".to_string()]);
+        assert_eq!(
+            report.evicted,
+            vec![(hex::encode([1u8; 32]), "Rejected by rule even-proofs: odd proof hash".to_string())]
+        );
+        assert!(pool.contains(&[2u8; 32]));
+        assert_eq!(
+            events.try_recv().unwrap().kind,
+            MempoolEventKind::Removed { reason: RemovalReason::Invalid }
+        );
+
+        // Activations are acted on once
+        assert!(revalidator.check_tip().is_none());
+        assert_eq!(revalidator.last_report(), Some(report));
+    }
+}
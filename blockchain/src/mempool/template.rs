@@ -0,0 +1,335 @@
+//! Prewarmed block templates
+//!
+//! Assembling a candidate block means picking forges from the mempool,
+//! hashing them into a merkle root and, once state roots are active,
+//! computing the state after the block. `BlockTemplateCache` keeps the next
+//! template assembled in the background and rebuilds it whenever the mempool
+//! changes or a block is connected, so `getblocktemplate` and the miner only
+//! clone an `Arc` instead of reassembling on demand.
+
+use super::ForgePool;
+use crate::consensus::{
+    block_merkle_root, Block, BlockHeader, ConsensusEngine, ForgeTransaction, SignedTransfer, VERSION_STATE_ROOT,
+    VERSION_TIMESTAMP_MILLIS,
+};
+use crate::crypto::prophecy_registry_hash;
+use crate::metrics::{Histogram, HistogramSnapshot};
+use crate::params::MAX_TRANSFERS_PER_BLOCK;
+use crate::shutdown::ShutdownSignal;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// Interval at which the template is checked against the chain tip
+pub const TEMPLATE_TIP_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Candidate for the next block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockTemplate {
+    pub height: u64,
+    pub prev_block_hash: [u8; 32],
+    pub forges: Vec<ForgeTransaction>,
+    /// Pending transfers valid on the tip
+    pub transfers: Vec<SignedTransfer>,
+    pub merkle_root: [u8; 32],
+    /// State root after the block, once state roots are active
+    pub state_root: Option<[u8; 32]>,
+    pub difficulty: u32,
+    pub bits: u32,
+    pub timestamp: u64,
+    /// Milliseconds past `timestamp`, once block times must pass
+    /// median-time-past
+    pub timestamp_millis: Option<u16>,
+    /// Mempool event sequence the forges were taken at
+    pub mempool_sequence: u64,
+    /// Mempool transfer sequence the transfers were taken at
+    pub transfer_sequence: u64,
+}
+
+impl BlockTemplate {
+    /// Block built from the template, with nonce 0
+    pub fn block(&self) -> Block {
+        let mut block = Block {
+            header: BlockHeader {
+                version: 1,
+                height: self.height,
+                prev_block_hash: self.prev_block_hash,
+                merkle_root: self.merkle_root,
+                timestamp: self.timestamp,
+                difficulty: self.difficulty,
+                bits: self.bits,
+                nonce: 0,
+                aggregate_commitment: None,
+                state_root: None,
+                timestamp_millis: None,
+            },
+            forges: self.forges.clone(),
+            transfers: self.transfers.clone(),
+            authority_signatures: Vec::new(),
+        };
+        if let Some(root) = self.state_root {
+            block.header.version |= VERSION_STATE_ROOT;
+            block.header.state_root = Some(root);
+        }
+        if let Some(millis) = self.timestamp_millis {
+            block.header.version |= VERSION_TIMESTAMP_MILLIS;
+            block.header.timestamp_millis = Some(millis);
+        }
+        block
+    }
+}
+
+/// Template freshness and rebuild counters
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TemplateStats {
+    /// Height of the current template, if one was built
+    pub height: Option<u64>,
+    pub forges: usize,
+    /// Milliseconds since the current template was built
+    pub age_ms: u64,
+    /// Mempool events since the template's forges were taken
+    pub mempool_events_behind: u64,
+    /// Whether a block was connected since the template was built
+    pub tip_changed: bool,
+    pub rebuilds: u64,
+    /// Time spent assembling templates, in microseconds
+    pub build_time: HistogramSnapshot,
+}
+
+struct CachedTemplate {
+    template: Arc<BlockTemplate>,
+    built_at: Instant,
+}
+
+/// Next block template, kept up to date with the mempool and chain tip
+pub struct BlockTemplateCache {
+    engine: Arc<ConsensusEngine>,
+    pool: Arc<ForgePool>,
+    current: RwLock<Option<CachedTemplate>>,
+    rebuilds: AtomicU64,
+    build_time: Histogram,
+}
+
+impl BlockTemplateCache {
+    /// Create an empty cache; the first template is built on demand or by `run`
+    pub fn new(engine: Arc<ConsensusEngine>, pool: Arc<ForgePool>) -> Self {
+        Self {
+            engine,
+            pool,
+            current: RwLock::new(None),
+            rebuilds: AtomicU64::new(0),
+            build_time: Histogram::latency(),
+        }
+    }
+
+    /// Assemble a template on the current tip and mempool and cache it
+    pub fn rebuild(&self) -> Arc<BlockTemplate> {
+        let started = Instant::now();
+        // Read the sequence first: a change racing with assembly leaves the
+        // template marked stale rather than missing the change
+        let mempool_sequence = self.pool.sequence();
+        let transfer_sequence = self.pool.transfer_sequence();
+        let prev_block_hash = self.engine.get_tip_hash();
+        // An all-zero tip hash means no block has been applied yet
+        let height = if prev_block_hash == [0u8; 32] { 0 } else { self.engine.get_height() + 1 };
+
+        let mut prophecies = HashSet::new();
+        let forges: Vec<ForgeTransaction> = self
+            .pool
+            .get_forges_for_block(self.engine.max_forges_per_block())
+            .into_iter()
+            .filter(|forge| forge.not_before_height <= height)
+            // Two forges of one prophecy can't share a block
+            .filter(|forge| prophecies.insert(prophecy_registry_hash(&forge.prophecy)))
+            .map(|forge| ForgeTransaction::clone(&forge))
+            .collect();
+
+        // Pending transfers were checked on admission; drop any a connected
+        // block or reorg has since invalidated
+        let transfers: Vec<SignedTransfer> = self
+            .pool
+            .get_transfers_for_block(MAX_TRANSFERS_PER_BLOCK)
+            .into_iter()
+            .filter(|transfer| self.engine.check_transfer(transfer).is_ok())
+            .collect();
+
+        let mut template = BlockTemplate {
+            height,
+            prev_block_hash,
+            merkle_root: block_merkle_root(self.engine.compute_merkle_root(&forges), &transfers),
+            forges,
+            transfers,
+            state_root: None,
+            difficulty: self.engine.get_difficulty(),
+            bits: self.engine.header_bits(height),
+            timestamp: crate::network::unix_now(),
+            timestamp_millis: None,
+            mempool_sequence,
+            transfer_sequence,
+        };
+        if self.engine.requires_median_time(height) {
+            // A clock behind the chain still yields a valid time
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            let time = self.engine.median_time_past().map_or(now, |median| now.max(median + 1));
+            template.timestamp = time / 1000;
+            template.timestamp_millis = Some((time % 1000) as u16);
+        }
+        if self.engine.requires_state_root(height) {
+            template.state_root = Some(self.engine.state_root_after(&template.block()));
+        }
+
+        let template = Arc::new(template);
+        *self.current.write().unwrap() = Some(CachedTemplate {
+            template: Arc::clone(&template),
+            built_at: Instant::now(),
+        });
+        self.rebuilds.fetch_add(1, Ordering::Relaxed);
+        self.build_time.observe_duration(started.elapsed());
+        template
+    }
+
+    /// Whether `template` no longer matches the chain tip or mempool
+    pub fn is_stale(&self, template: &BlockTemplate) -> bool {
+        template.prev_block_hash != self.engine.get_tip_hash()
+            || template.mempool_sequence != self.pool.sequence()
+            || template.transfer_sequence != self.pool.transfer_sequence()
+    }
+
+    /// Cached template, whether or not it is stale
+    pub fn current(&self) -> Option<Arc<BlockTemplate>> {
+        self.current
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|cached| Arc::clone(&cached.template))
+    }
+
+    /// Up-to-date template, rebuilt first only if the cached one is stale
+    pub fn get(&self) -> Arc<BlockTemplate> {
+        match self.current() {
+            Some(template) if !self.is_stale(&template) => template,
+            _ => self.rebuild(),
+        }
+    }
+
+    /// Freshness of the cached template and rebuild counters
+    pub fn stats(&self) -> TemplateStats {
+        let current = self.current.read().unwrap();
+        let (height, forges, age_ms, behind, tip_changed) = match current.as_ref() {
+            Some(cached) => (
+                Some(cached.template.height),
+                cached.template.forges.len(),
+                cached.built_at.elapsed().as_millis() as u64,
+                self.pool.sequence().saturating_sub(cached.template.mempool_sequence),
+                cached.template.prev_block_hash != self.engine.get_tip_hash(),
+            ),
+            None => (None, 0, 0, 0, false),
+        };
+        TemplateStats {
+            height,
+            forges,
+            age_ms,
+            mempool_events_behind: behind,
+            tip_changed,
+            rebuilds: self.rebuilds.load(Ordering::Relaxed),
+            build_time: self.build_time.snapshot(),
+        }
+    }
+
+    /// Rebuild on mempool changes and new tips until shutdown
+    pub async fn run(self: Arc<Self>, mut shutdown: ShutdownSignal) {
+        let mut events = self.pool.subscribe();
+        let mut tip_check = tokio::time::interval(TEMPLATE_TIP_CHECK_INTERVAL);
+        self.rebuild();
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    if let Err(broadcast::error::RecvError::Closed) = event {
+                        break;
+                    }
+                    // Fold a burst of changes into one rebuild
+                    while events.try_recv().is_ok() {}
+                    self.rebuild();
+                }
+                _ = tip_check.tick() => {
+                    if self.current().is_some_and(|template| self.is_stale(&template)) {
+                        self.rebuild();
+                    }
+                }
+                _ = shutdown.recv() => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::testing::forge_with;
+
+    fn forge(seed: u8, prophecy: &str) -> ForgeTransaction {
+        forge_with(seed, |forge| {
+            forge.prophecy = prophecy.to_string();
+            forge.timestamp = 1000 + u64::from(seed);
+        })
+    }
+
+    #[test]
+    fn test_template_follows_mempool_and_tip() {
+        let engine = Arc::new(ConsensusEngine::new(0, 600));
+        let pool = Arc::new(ForgePool::new(100, 0));
+        let cache = BlockTemplateCache::new(Arc::clone(&engine), Arc::clone(&pool));
+        assert_eq!(cache.stats().height, None);
+
+        let empty = cache.get();
+        assert_eq!((empty.height, empty.forges.len()), (0, 0));
+        assert!(Arc::ptr_eq(&empty, &cache.get()), "fresh template is reused");
+
+        pool.add_forge(forge(1, "first prophecy")).unwrap();
+        assert!(pool.add_forge(forge(2, "first prophecy")).is_err());
+        pool.add_forge(forge(3, "second prophecy")).unwrap();
+        assert!(cache.is_stale(&empty));
+        assert_eq!(cache.stats().mempool_events_behind, 2);
+
+        let template = cache.get();
+        assert_eq!(template.forges.len(), 2, "one forge per prophecy");
+        assert_eq!(template.merkle_root, engine.compute_merkle_root(&template.forges));
+        assert_eq!(cache.stats().rebuilds, 2);
+
+        // Connecting a block moves the template onto the new tip
+        engine.apply_block(&template.block()).unwrap();
+        assert!(cache.stats().tip_changed);
+        let next = cache.get();
+        assert_eq!(next.height, 1);
+        assert_eq!(next.prev_block_hash, engine.compute_block_hash(&template.block().header));
+    }
+
+    #[tokio::test]
+    async fn test_background_rebuild() {
+        let engine = Arc::new(ConsensusEngine::new(0, 600));
+        let pool = Arc::new(ForgePool::new(100, 0));
+        let cache = Arc::new(BlockTemplateCache::new(engine, Arc::clone(&pool)));
+        let shutdown = crate::shutdown::ShutdownCoordinator::new();
+        let task = tokio::spawn(Arc::clone(&cache).run(shutdown.subscribe()));
+
+        pool.add_forge(forge(1, "prewarmed prophecy")).unwrap();
+        for _ in 0..100 {
+            if cache.current().is_some_and(|template| template.forges.len() == 1) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let template = cache.current().unwrap();
+        assert_eq!(template.forges.len(), 1);
+        assert!(!cache.is_stale(&template));
+
+        shutdown.trigger();
+        task.await.unwrap();
+    }
+}
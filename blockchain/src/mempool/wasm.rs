@@ -0,0 +1,183 @@
+//! Sandboxed WASM mempool policy filters
+//!
+//! Operators can customize relay policy (e.g. memo filtering) by loading a
+//! small WASM module instead of recompiling the node. The module must export:
+//!
+//! - `memory`: its linear memory
+//! - `alloc(len: i32) -> i32`: a buffer of `len` bytes for the host to fill
+//! - `accept_forge(ptr: i32, len: i32) -> i32`: the verdict for the
+//!   canonically encoded forge at `ptr`; `0` accepts, anything else rejects
+//!
+//! The module gets no imports, runs in a fresh instance for every forge, and
+//! is limited to `fuel` instructions and `POLICY_MEMORY_LIMIT` bytes of
+//! memory. A filter that traps or runs out of fuel rejects the forge.
+
+use super::ForgePolicy;
+use crate::consensus::ForgeTransaction;
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+use wasmi::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+/// Maximum linear memory a filter may use
+pub const POLICY_MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Mempool policy implemented by a WASM module
+pub struct WasmPolicy {
+    name: String,
+    engine: Engine,
+    module: Module,
+    fuel: u64,
+}
+
+impl WasmPolicy {
+    /// Load a filter from a `.wasm` file
+    pub fn load<P: AsRef<Path>>(path: P, fuel: u64) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Failed to read policy filter {}", path.display()))?;
+        Self::from_bytes(&path.display().to_string(), &bytes, fuel)
+    }
+
+    /// Compile a filter from module bytes
+    pub fn from_bytes(name: &str, bytes: &[u8], fuel: u64) -> Result<Self> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, bytes)
+            .map_err(|e| anyhow!("Invalid policy filter {}: {}", name, e))?;
+
+        let policy = Self {
+            name: name.to_string(),
+            engine,
+            module,
+            fuel,
+        };
+        // Fail at load time rather than on the first forge
+        policy.instantiate()?;
+        Ok(policy)
+    }
+
+    fn instantiate(&self) -> Result<(Store<StoreLimits>, wasmi::Instance)> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(POLICY_MEMORY_LIMIT)
+            .instances(1)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store.add_fuel(self.fuel).map_err(|e| anyhow!("{}", e))?;
+
+        let instance = Linker::<StoreLimits>::new(&self.engine)
+            .instantiate(&mut store, &self.module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|e| anyhow!("Policy filter {} failed to start: {}", self.name, e))?;
+        for export in ["alloc", "accept_forge", "memory"] {
+            if instance.get_export(&store, export).is_none() {
+                return Err(anyhow!("Policy filter {} does not export {}", self.name, export));
+            }
+        }
+        Ok((store, instance))
+    }
+
+    /// Run the filter on encoded forge bytes, returning its verdict code
+    pub fn verdict(&self, data: &[u8]) -> Result<i32> {
+        let (mut store, instance) = self.instantiate()?;
+        let len = i32::try_from(data.len()).map_err(|_| anyhow!("Forge too large for policy filter"))?;
+
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|e| anyhow!("{}", e))?;
+        let accept = instance
+            .get_typed_func::<(i32, i32), i32>(&store, "accept_forge")
+            .map_err(|e| anyhow!("{}", e))?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| anyhow!("Policy filter does not export memory"))?;
+
+        let ptr = alloc.call(&mut store, len).map_err(|e| anyhow!("alloc trapped: {}", e))?;
+        memory
+            .write(&mut store, ptr as u32 as usize, data)
+            .map_err(|e| anyhow!("alloc returned an invalid buffer: {}", e))?;
+        accept
+            .call(&mut store, (ptr, len))
+            .map_err(|e| anyhow!("accept_forge trapped: {}", e))
+    }
+}
+
+impl ForgePolicy for WasmPolicy {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check_forge(&self, forge: &ForgeTransaction) -> Result<()> {
+        match self.verdict(&forge.encode()) {
+            Ok(0) => Ok(()),
+            Ok(code) => Err(anyhow!("Rejected by policy filter (code {})", code)),
+            Err(e) => {
+                tracing::warn!("Policy filter {} failed: {:#}", self.name, e);
+                Err(e.context("Policy filter failed"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mempool::DEFAULT_POLICY_FUEL;
+    use crate::consensus::testing::forge_with;
+
+    fn forge(prophecy: &str) -> ForgeTransaction {
+        forge_with(7, |forge| forge.prophecy = prophecy.to_string())
+    }
+
+    /// Rejects forges whose encoding contains the byte sequence "spam"
+    const MEMO_FILTER: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32) i32.const 1024)
+          (func (export "accept_forge") (param $ptr i32) (param $len i32) (result i32)
+            (local $end i32)
+            (local.set $end (i32.sub (i32.add (local.get $ptr) (local.get $len)) (i32.const 3)))
+            (block $done
+              (loop $scan
+                (br_if $done (i32.ge_s (local.get $ptr) (local.get $end)))
+                (if (i32.eq (i32.load (local.get $ptr)) (i32.const 0x6d617073))
+                  (then (return (i32.const 1))))
+                (local.set $ptr (i32.add (local.get $ptr) (i32.const 1)))
+                (br $scan)))
+            i32.const 0))
+    "#;
+
+    const SPIN_FILTER: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32) i32.const 0)
+          (func (export "accept_forge") (param i32 i32) (result i32)
+            (loop $forever (br $forever))
+            i32.const 0))
+    "#;
+
+    #[test]
+    fn test_memo_filter() {
+        let wasm = wat::parse_str(MEMO_FILTER).unwrap();
+        let policy = WasmPolicy::from_bytes("memo", &wasm, DEFAULT_POLICY_FUEL).unwrap();
+
+        assert!(policy.check_forge(&forge("sword legend pull magic")).is_ok());
+        assert!(policy.check_forge(&forge("buy spam now")).is_err());
+    }
+
+    #[test]
+    fn test_fuel_limit_rejects() {
+        let wasm = wat::parse_str(SPIN_FILTER).unwrap();
+        let policy = WasmPolicy::from_bytes("spin", &wasm, 10_000).unwrap();
+        let err = policy.check_forge(&forge("anything")).unwrap_err();
+        assert!(format!("{:#}", err).contains("trapped"));
+    }
+
+    #[test]
+    fn test_missing_exports_rejected_at_load() {
+        let wasm = wat::parse_str(r#"(module (memory (export "memory") 1))"#).unwrap();
+        assert!(WasmPolicy::from_bytes("empty", &wasm, DEFAULT_POLICY_FUEL).is_err());
+        assert!(WasmPolicy::from_bytes("garbage", b"not wasm", DEFAULT_POLICY_FUEL).is_err());
+    }
+}
@@ -0,0 +1,107 @@
+//! Lightweight in-process metrics
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Bucket upper bounds (in microseconds) for latency histograms
+pub const LATENCY_BUCKETS_MICROS: &[u64] = &[
+    100, 500, 1_000, 5_000, 10_000, 50_000, 100_000, 500_000, 1_000_000, 5_000_000, 10_000_000,
+];
+
+/// Bucket upper bounds (in bytes) for size histograms
+pub const SIZE_BUCKETS_BYTES: &[u64] = &[
+    1_024, 16_384, 65_536, 262_144, 1_048_576, 4_194_304, 16_777_216, 67_108_864,
+];
+
+/// Fixed-bucket histogram that can be updated concurrently
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [u64],
+    /// One counter per bound plus a final overflow bucket
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+/// Point-in-time copy of a histogram
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistogramSnapshot {
+    /// (upper bound, observations) pairs; `None` is the overflow bucket
+    pub buckets: Vec<(Option<u64>, u64)>,
+    pub count: u64,
+    pub sum: u64,
+    pub max: u64,
+}
+
+impl Histogram {
+    /// Create a histogram with the given ascending bucket bounds
+    pub fn new(bounds: &'static [u64]) -> Self {
+        Self {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    /// Create a histogram for durations, bucketed in microseconds
+    pub fn latency() -> Self {
+        Self::new(LATENCY_BUCKETS_MICROS)
+    }
+
+    /// Record one observation
+    pub fn observe(&self, value: u64) {
+        let index = self.bounds.partition_point(|bound| *bound < value);
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    /// Record a duration in microseconds
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_micros().min(u64::MAX as u128) as u64);
+    }
+
+    /// Take a snapshot of the current counts
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let buckets = self
+            .bounds
+            .iter()
+            .map(|bound| Some(*bound))
+            .chain(std::iter::once(None))
+            .zip(&self.buckets)
+            .map(|(bound, count)| (bound, count.load(Ordering::Relaxed)))
+            .collect();
+
+        HistogramSnapshot {
+            buckets,
+            count: self.count.load(Ordering::Relaxed),
+            sum: self.sum.load(Ordering::Relaxed),
+            max: self.max.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets() {
+        let histogram = Histogram::new(&[10, 100]);
+        histogram.observe(5);
+        histogram.observe(10);
+        histogram.observe(50);
+        histogram.observe(1_000);
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.buckets, vec![(Some(10), 2), (Some(100), 1), (None, 1)]);
+        assert_eq!(snapshot.count, 4);
+        assert_eq!(snapshot.sum, 1_065);
+        assert_eq!(snapshot.max, 1_000);
+    }
+}
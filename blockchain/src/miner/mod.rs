@@ -0,0 +1,328 @@
+//! Block miner
+//!
+//! The miner takes the prewarmed block template (the highest-priority
+//! mempool forges on the current tip, with their merkle root), searches
+//! nonces until the header hash meets its target, and hands the block to the
+//! node. The node connects it through the same path as a gossiped block —
+//! validation, `ConsensusEngine::apply_block`, the chain store — and then
+//! announces it to peers. Nonces are searched in batches on the blocking
+//! pool, so a mempool change is picked up between batches.
+//!
+//! The node publishes every tip it connects on a watch channel. A new tip
+//! while a batch is in flight means a competing block won the race: the
+//! batch's `StaleWorkToken` is cancelled, grinding stops at the next chunk,
+//! and the template is rebuilt on the new tip at once.
+//!
+//! On a permissioned chain the miner only proposes blocks at the heights
+//! its authority key is the proposer for, and signs what it finds. A block
+//! that needs more signatures than its own is kept as the pending proposal,
+//! shown by `getmininginfo`, for the other authorities to co-sign with
+//! `signblock` before it is sent with `submitblock`.
+
+use crate::consensus::{AuthorityKey, Block, ConsensusEngine};
+use crate::mempool::BlockTemplateCache;
+use crate::shutdown::ShutdownSignal;
+use anyhow::Result;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+
+/// Default nonces tried per batch
+pub const DEFAULT_NONCE_BATCH: u64 = 100_000;
+
+/// Wait between checks of an empty template
+pub const MINER_IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Nonces tried between checks for cancellation
+const GRIND_CHUNK: u64 = 4096;
+
+/// Cancels the grinding of one batch
+#[derive(Debug, Clone, Default)]
+pub struct StaleWorkToken(Arc<AtomicBool>);
+
+impl StaleWorkToken {
+    /// Stop the batch at its next chunk
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the batch was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Miner counters
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MinerStats {
+    /// Nonces tried
+    pub hashes: u64,
+    /// Blocks found and handed to the node
+    pub blocks_found: u64,
+    /// Batches abandoned because another block took the tip
+    pub stale_work_abandoned: u64,
+}
+
+/// Searches nonces over the current block template
+pub struct Miner {
+    engine: Arc<ConsensusEngine>,
+    templates: Arc<BlockTemplateCache>,
+    nonce_batch: u64,
+    hashes: AtomicU64,
+    blocks_found: AtomicU64,
+    stale_work_abandoned: AtomicU64,
+    /// Key blocks are signed with on a permissioned chain
+    authority: Option<AuthorityKey>,
+    /// Block found on this node's turn that still lacks signatures
+    proposal: Mutex<Option<Block>>,
+}
+
+impl Miner {
+    /// Create a miner trying `nonce_batch` nonces per template
+    pub fn new(engine: Arc<ConsensusEngine>, templates: Arc<BlockTemplateCache>, nonce_batch: u64) -> Self {
+        Self {
+            engine,
+            templates,
+            nonce_batch: nonce_batch.max(1),
+            hashes: AtomicU64::new(0),
+            blocks_found: AtomicU64::new(0),
+            stale_work_abandoned: AtomicU64::new(0),
+            authority: None,
+            proposal: Mutex::new(None),
+        }
+    }
+
+    /// Propose and sign blocks with an authority key
+    pub fn with_authority(mut self, key: AuthorityKey) -> Self {
+        self.authority = Some(key);
+        self
+    }
+
+    /// Whether the miner may propose the block at `height`: always on a
+    /// public chain, only on its turn on a permissioned one
+    fn may_propose(&self, height: u64) -> bool {
+        match self.engine.authorities() {
+            Some(set) if set.applies_at(height) => {
+                self.authority.as_ref().is_some_and(|key| set.proposer(height) == key.index() as usize)
+            }
+            _ => true,
+        }
+    }
+
+    /// Whether `block` carries the authority signatures it needs
+    fn has_quorum(&self, block: &Block) -> bool {
+        match self.engine.authorities() {
+            Some(set) if set.applies_at(block.header.height) => block.authority_signatures.len() >= set.threshold(),
+            _ => true,
+        }
+    }
+
+    /// Block awaiting co-signatures from the other authorities
+    pub fn proposal(&self) -> Option<Block> {
+        self.proposal.lock().unwrap().clone()
+    }
+
+    /// Try one batch of nonces on the current template. Returns the block
+    /// if a nonce met the target; `None` if the template has no forges (a
+    /// block needs at least one), the batch ran out or `cancel` fired.
+    pub fn mine_batch(&self, cancel: &StaleWorkToken) -> Option<Block> {
+        let template = self.templates.get();
+        if template.forges.is_empty() || !self.may_propose(template.height) {
+            return None;
+        }
+        let mut block = template.block();
+        // Start from a random nonce so restarts don't repeat the search
+        block.header.nonce = rand::random();
+        let mut remaining = self.nonce_batch;
+        let found = loop {
+            if cancel.is_cancelled() {
+                self.stale_work_abandoned.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            let chunk = remaining.min(GRIND_CHUNK);
+            let start = block.header.nonce;
+            let found = self.engine.grind_header(&mut block.header, chunk);
+            self.hashes
+                .fetch_add(block.header.nonce.wrapping_sub(start) + u64::from(found), Ordering::Relaxed);
+            remaining -= chunk;
+            if found || remaining == 0 {
+                break found;
+            }
+        };
+        if !found {
+            return None;
+        }
+        if let Some(key) = &self.authority {
+            if self.engine.authorities().is_some_and(|set| set.applies_at(block.header.height)) {
+                let hash = self.engine.compute_block_hash(&block.header);
+                key.sign(&mut block, &hash);
+            }
+        }
+        self.blocks_found.fetch_add(1, Ordering::Relaxed);
+        Some(block)
+    }
+
+    /// Miner counters
+    pub fn stats(&self) -> MinerStats {
+        MinerStats {
+            hashes: self.hashes.load(Ordering::Relaxed),
+            blocks_found: self.blocks_found.load(Ordering::Relaxed),
+            stale_work_abandoned: self.stale_work_abandoned.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Mine until shutdown, sending each block found to `blocks`. `tips`
+    /// carries the hash of each block the node connects.
+    pub async fn run(
+        self: Arc<Self>,
+        blocks: mpsc::Sender<Block>,
+        mut tips: watch::Receiver<[u8; 32]>,
+        mut shutdown: ShutdownSignal,
+    ) -> Result<()> {
+        // Parent of the last block sent, until the node has connected it
+        let mut pending_parent = None;
+        loop {
+            let tip = self.engine.get_tip_hash();
+            let idle = pending_parent == Some(tip)
+                || self.proposal.lock().unwrap().as_ref().is_some_and(|block| block.header.prev_block_hash == tip)
+                || self
+                    .templates
+                    .current()
+                    .is_some_and(|template| template.forges.is_empty() || !self.may_propose(template.height));
+            if idle {
+                // A rejected block is not retried after one interval
+                pending_parent = None;
+                tokio::select! {
+                    _ = tokio::time::sleep(MINER_IDLE_INTERVAL) => continue,
+                    _ = shutdown.recv() => return Ok(()),
+                }
+            }
+            if shutdown.is_triggered() {
+                return Ok(());
+            }
+
+            // Tips connected so far are already in the template
+            tips.borrow_and_update();
+            let cancel = StaleWorkToken::default();
+            let token = cancel.clone();
+            let miner = Arc::clone(&self);
+            let mut work = tokio::task::spawn_blocking(move || miner.mine_batch(&token));
+            let found = tokio::select! {
+                found = &mut work => found?,
+                Ok(()) = tips.changed() => {
+                    cancel.cancel();
+                    tracing::debug!("New tip while mining; abandoning stale work");
+                    self.templates.rebuild();
+                    // A block found before the cancel landed builds on the old tip
+                    let _ = work.await?;
+                    None
+                }
+                _ = shutdown.recv() => {
+                    cancel.cancel();
+                    return Ok(());
+                }
+            };
+            if let Some(block) = found {
+                if !self.has_quorum(&block) {
+                    tracing::info!(
+                        "Proposed block at height {}; waiting for the other authorities to sign it",
+                        block.header.height
+                    );
+                    *self.proposal.lock().unwrap() = Some(block);
+                    continue;
+                }
+                tracing::info!("Mined block at height {} with {} forges", block.header.height, block.forges.len());
+                pending_parent = Some(block.header.prev_block_hash);
+                if blocks.send(block).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::testing::forge;
+    use crate::mempool::ForgePool;
+
+    #[test]
+    fn test_mined_block_meets_target_and_commits_to_forges() {
+        let engine = Arc::new(ConsensusEngine::new(0, 600));
+        let pool = Arc::new(ForgePool::new(10, 0));
+        let templates = Arc::new(BlockTemplateCache::new(Arc::clone(&engine), Arc::clone(&pool)));
+        let miner = Miner::new(Arc::clone(&engine), templates, 1_000);
+        let cancel = StaleWorkToken::default();
+        assert!(miner.mine_batch(&cancel).is_none(), "no forges to mine");
+
+        pool.add_forge(forge(1)).unwrap();
+        pool.add_forge(forge(2)).unwrap();
+        let block = miner.mine_batch(&cancel).expect("regtest target is met within the batch");
+        assert_eq!(block.header.height, 0);
+        assert_eq!(block.forges.len(), 2);
+        assert_eq!(block.header.merkle_root, engine.compute_merkle_root(&block.forges));
+        engine.check_header_pow(&block.header).unwrap();
+
+        let stats = miner.stats();
+        assert_eq!(stats.blocks_found, 1);
+        assert!(stats.hashes >= 1);
+
+        cancel.cancel();
+        assert!(miner.mine_batch(&cancel).is_none());
+        assert_eq!(miner.stats().stale_work_abandoned, 1);
+    }
+
+    #[test]
+    fn test_authorities_propose_in_turn() {
+        use crate::consensus::AuthoritySet;
+        use bitcoin::secp256k1::{Keypair, Secp256k1, SecretKey};
+
+        let secret = |seed: u8| SecretKey::from_slice(&[seed; 32]).unwrap();
+        let public = |seed: u8| Keypair::from_secret_key(&Secp256k1::new(), &secret(seed)).x_only_public_key().0;
+        let set = AuthoritySet::new(vec![public(1), public(2)], 2, 0).unwrap();
+        let params = crate::params::NetworkParams::regtest().with_authorities(set.clone());
+        let engine = Arc::new(ConsensusEngine::new(0, 600).with_params(&params));
+        let pool = Arc::new(ForgePool::new(10, 0));
+        pool.add_forge(forge(4)).unwrap();
+        let templates = Arc::new(BlockTemplateCache::new(Arc::clone(&engine), pool));
+        let miner = |seed: u8| {
+            Miner::new(Arc::clone(&engine), Arc::clone(&templates), 1_000)
+                .with_authority(AuthorityKey::new(&set, &secret(seed)).unwrap())
+        };
+        let cancel = StaleWorkToken::default();
+        assert!(miner(2).mine_batch(&cancel).is_none(), "height 0 is the first authority's turn");
+
+        let first = miner(1);
+        let mut block = first.mine_batch(&cancel).unwrap();
+        assert_eq!(block.authority_signatures.len(), 1);
+        assert!(!first.has_quorum(&block));
+        let hash = engine.compute_block_hash(&block.header);
+        AuthorityKey::new(&set, &secret(2)).unwrap().sign(&mut block, &hash);
+        assert!(first.has_quorum(&block));
+        set.check_block(&block, &hash).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_sends_mined_blocks() {
+        let engine = Arc::new(ConsensusEngine::new(0, 600));
+        let pool = Arc::new(ForgePool::new(10, 0));
+        pool.add_forge(forge(3)).unwrap();
+        let templates = Arc::new(BlockTemplateCache::new(Arc::clone(&engine), pool));
+        let miner = Arc::new(Miner::new(engine, templates, 1_000));
+        let shutdown = crate::shutdown::ShutdownCoordinator::new();
+        let (sender, mut blocks) = mpsc::channel(1);
+        let (_tips, tip_updates) = watch::channel([0u8; 32]);
+        let task = tokio::spawn(Arc::clone(&miner).run(sender, tip_updates, shutdown.subscribe()));
+
+        let block = blocks.recv().await.unwrap();
+        assert_eq!(block.forges[0].proof_hash, [3; 32]);
+        // Nothing more is mined on the same parent while the block is pending
+        assert!(tokio::time::timeout(Duration::from_millis(100), blocks.recv()).await.is_err());
+        shutdown.trigger();
+        task.await.unwrap().unwrap();
+        assert_eq!(miner.stats().blocks_found, 1);
+    }
+}
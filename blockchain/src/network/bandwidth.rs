@@ -0,0 +1,254 @@
+//! Bandwidth accounting and per-peer upload limits
+
+use libp2p::PeerId;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Seconds of traffic a peer may burst above its upload rate
+pub const UPLOAD_BURST: Duration = Duration::from_secs(4);
+
+/// Kind of message counted by the bandwidth tracker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageKind {
+    Block,
+    Header,
+    Forge,
+    Reject,
+    Evidence,
+    /// Header and block download requests and responses
+    Sync,
+}
+
+impl MessageKind {
+    /// Message kind for a gossip topic, if it is one we count
+    pub fn for_topic(topic: &str) -> Option<Self> {
+        match topic {
+            super::BLOCK_TOPIC => Some(MessageKind::Block),
+            super::HEADER_TOPIC => Some(MessageKind::Header),
+            super::TRANSACTION_TOPIC => Some(MessageKind::Forge),
+            super::EVIDENCE_TOPIC => Some(MessageKind::Evidence),
+            _ => None,
+        }
+    }
+}
+
+/// Byte and message counters in both directions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TrafficCounters {
+    pub bytes_recv: u64,
+    pub bytes_sent: u64,
+    pub messages_recv: u64,
+    pub messages_sent: u64,
+}
+
+impl TrafficCounters {
+    fn add_recv(&mut self, len: usize) {
+        self.bytes_recv += len as u64;
+        self.messages_recv += 1;
+    }
+
+    fn add_sent(&mut self, len: usize) {
+        self.bytes_sent += len as u64;
+        self.messages_sent += 1;
+    }
+}
+
+/// Traffic exchanged with one peer
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PeerTraffic {
+    pub totals: TrafficCounters,
+    /// Sends withheld because the peer exceeded its upload limit
+    pub throttled: u64,
+}
+
+/// Point-in-time network totals (`getnettotals`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetTotals {
+    pub totals: TrafficCounters,
+    pub by_message: HashMap<MessageKind, TrafficCounters>,
+    /// Connected peers only; totals keep traffic from disconnected ones
+    pub peers: HashMap<PeerId, PeerTraffic>,
+    /// Per-peer upload limit in bytes per second (0 = unlimited)
+    pub peer_upload_limit: u64,
+    pub time_millis: u64,
+}
+
+#[derive(Debug, Default)]
+struct TrafficState {
+    totals: TrafficCounters,
+    by_message: HashMap<MessageKind, TrafficCounters>,
+    peers: HashMap<PeerId, PeerTraffic>,
+    peer_upload_limit: u64,
+}
+
+/// Shared bandwidth counters, updated by the network task and read by RPC
+#[derive(Debug, Default)]
+pub struct BandwidthTracker {
+    state: Mutex<TrafficState>,
+}
+
+impl BandwidthTracker {
+    /// Create an empty tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a message received from `peer`
+    pub fn record_recv(&self, peer: &PeerId, kind: MessageKind, len: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.totals.add_recv(len);
+        state.by_message.entry(kind).or_default().add_recv(len);
+        state.peers.entry(*peer).or_default().totals.add_recv(len);
+    }
+
+    /// Count a message sent to `peer`, or to the gossip mesh when `peer` is `None`
+    pub fn record_sent(&self, peer: Option<&PeerId>, kind: MessageKind, len: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.totals.add_sent(len);
+        state.by_message.entry(kind).or_default().add_sent(len);
+        if let Some(peer) = peer {
+            state.peers.entry(*peer).or_default().totals.add_sent(len);
+        }
+    }
+
+    /// Count a send withheld by the upload limit
+    pub fn record_throttled(&self, peer: &PeerId) {
+        self.state.lock().unwrap().peers.entry(*peer).or_default().throttled += 1;
+    }
+
+    /// Drop per-peer counters for a disconnected peer
+    pub fn remove_peer(&self, peer: &PeerId) {
+        self.state.lock().unwrap().peers.remove(peer);
+    }
+
+    pub(super) fn set_peer_upload_limit(&self, bytes_per_sec: u64) {
+        self.state.lock().unwrap().peer_upload_limit = bytes_per_sec;
+    }
+
+    /// Snapshot of all counters
+    pub fn totals(&self) -> NetTotals {
+        let state = self.state.lock().unwrap();
+        NetTotals {
+            totals: state.totals,
+            by_message: state.by_message.clone(),
+            peers: state.peers.clone(),
+            peer_upload_limit: state.peer_upload_limit,
+            time_millis: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Token-bucket limit on bytes uploaded to each peer, so peers that only
+/// download cannot saturate a metered connection
+#[derive(Debug)]
+pub struct UploadLimiter {
+    bytes_per_sec: u64,
+    buckets: HashMap<PeerId, (u64, Instant)>,
+}
+
+impl UploadLimiter {
+    /// Create a limiter; a rate of 0 disables limiting
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Configured rate in bytes per second (0 = unlimited)
+    pub fn rate(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    fn burst(&self) -> u64 {
+        self.bytes_per_sec.saturating_mul(UPLOAD_BURST.as_secs())
+    }
+
+    /// Whether `len` bytes may be sent to `peer` now, consuming budget if so.
+    /// A send larger than the whole burst goes out from a full bucket and
+    /// empties it, so large sync responses are slowed rather than refused
+    /// forever.
+    pub fn allow(&mut self, peer: &PeerId, len: usize, now: Instant) -> bool {
+        if self.bytes_per_sec == 0 {
+            return true;
+        }
+        let burst = self.burst();
+        let rate = self.bytes_per_sec;
+        let (tokens, last) = self.buckets.entry(*peer).or_insert((burst, now));
+
+        let elapsed = now.saturating_duration_since(*last);
+        let refill = (elapsed.as_millis() as u64).saturating_mul(rate) / 1000;
+        if refill > 0 {
+            *tokens = (*tokens + refill).min(burst);
+            *last = now;
+        }
+
+        let len = (len as u64).min(burst);
+        if *tokens < len {
+            return false;
+        }
+        *tokens -= len;
+        true
+    }
+
+    /// Forget budget state for a disconnected peer
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.buckets.remove(peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker_counts_by_peer_and_kind() {
+        let tracker = BandwidthTracker::new();
+        let peer = PeerId::random();
+
+        tracker.record_recv(&peer, MessageKind::Block, 1_000);
+        tracker.record_sent(Some(&peer), MessageKind::Reject, 50);
+        tracker.record_sent(None, MessageKind::Forge, 200);
+
+        let totals = tracker.totals();
+        assert_eq!(totals.totals.bytes_recv, 1_000);
+        assert_eq!(totals.totals.bytes_sent, 250);
+        assert_eq!(totals.by_message[&MessageKind::Forge].messages_sent, 1);
+        assert_eq!(totals.peers[&peer].totals.bytes_sent, 50);
+
+        tracker.remove_peer(&peer);
+        let totals = tracker.totals();
+        assert!(totals.peers.is_empty());
+        assert_eq!(totals.totals.bytes_recv, 1_000);
+    }
+
+    #[test]
+    fn test_upload_limiter_refills() {
+        let mut limiter = UploadLimiter::new(1_000);
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        // Burst allowance, then throttled
+        assert!(limiter.allow(&peer, 4_000, now));
+        assert!(!limiter.allow(&peer, 1, now));
+
+        // Half a second refills half the rate
+        assert!(limiter.allow(&peer, 500, now + Duration::from_millis(500)));
+        assert!(!limiter.allow(&peer, 100, now + Duration::from_millis(500)));
+
+        // Sends beyond the burst wait for a full bucket
+        let later = now + Duration::from_secs(10);
+        assert!(limiter.allow(&peer, 10_000, later));
+        assert!(!limiter.allow(&peer, 10_000, later + Duration::from_secs(1)));
+
+        // Other peers have their own budget; zero disables limiting
+        assert!(limiter.allow(&PeerId::random(), 4_000, now));
+        assert!(UploadLimiter::new(0).allow(&peer, usize::MAX, now));
+    }
+}
@@ -0,0 +1,488 @@
+//! Wire protocol conformance fixtures and a loopback simulator
+//!
+//! The fixtures pin the exact bytes this node puts on the wire: the
+//! identify handshake strings, JSON request-response messages (sync and
+//! reject) and the binary block and forge encodings gossiped on the block
+//! and transaction topics. `verify_fixtures` re-encodes every fixture and
+//! decodes it back, so a refactor that changes a byte fails here before it
+//! splits the network. Another implementation can check itself against the
+//! same bytes.
+//!
+//! `Loopback` runs two protocol stacks against each other in-process:
+//! each presents its handshake and checks the other's network magic and
+//! relay preferences, then one syncs the other's chain headers-first, with
+//! every message passing through its wire encoding. The bytes exchanged are
+//! kept as a transcript.
+
+use super::reject::REJECT_PROTOCOL;
+use super::sync::{MAX_HEADERS_PER_REQUEST, SYNC_PROTOCOL};
+use super::{
+    handshake_protocol, protocol_magic, RejectCode, RejectMessage, RejectedItem, RelayPreferences, ServiceFlags,
+    SyncPolicy, SyncRequest, SyncResponse, BLOCK_TOPIC, TRANSACTION_TOPIC,
+};
+use crate::chain::{ChainStore, HeaderGuard, MemoryStore};
+use crate::consensus::{Block, BlockHeader, ConsensusEngine, ForgeTransaction, POW_LIMIT_BITS};
+use crate::crypto::TemperingAlgorithm;
+use crate::sync::{serve, ChainSync};
+use anyhow::{anyhow, Result};
+use libp2p::PeerId;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::time::Instant;
+
+/// Identify handshake a node presents for a network magic and preferences
+#[derive(Debug, Clone, Copy)]
+pub struct HandshakeFixture {
+    pub name: &'static str,
+    pub magic: [u8; 4],
+    pub preferences: RelayPreferences,
+    /// Identify `protocol_version`
+    pub protocol_version: &'static str,
+    /// Identify `agent_version`
+    pub agent_version: &'static str,
+}
+
+/// Published handshake fixtures
+pub const HANDSHAKE_FIXTURES: [HandshakeFixture; 2] = [
+    HandshakeFixture {
+        name: "mainnet-archival",
+        magic: [0xe5, 0x78, 0x53, 0xb1],
+        preferences: RelayPreferences {
+            no_forge_relay: false,
+            compact_blocks: false,
+            services: ServiceFlags::from_bits(0x03),
+            max_message_size: 4 * 1024 * 1024,
+        },
+        protocol_version: "/excalibur/1.0.0/e57853b1",
+        agent_version: "excalibur-node/1.0.0;prefs=00/4194304;services=03",
+    },
+    HandshakeFixture {
+        name: "regtest-blocks-only-filters",
+        magic: [0xe5, 0x78, 0x53, 0xfa],
+        preferences: RelayPreferences {
+            no_forge_relay: true,
+            compact_blocks: true,
+            services: ServiceFlags::from_bits(0x05),
+            max_message_size: 1024 * 1024,
+        },
+        protocol_version: "/excalibur/1.0.0/e57853fa",
+        agent_version: "excalibur-node/1.0.0;prefs=07/1048576;services=05",
+    },
+];
+
+/// A message and its exact encoding
+#[derive(Debug, Clone, Copy)]
+pub struct MessageFixture {
+    pub name: &'static str,
+    /// Protocol or gossip topic the message is sent on
+    pub protocol: &'static str,
+    /// Hex of the encoded message
+    pub bytes: &'static str,
+}
+
+/// Published message fixtures
+pub const MESSAGE_FIXTURES: [MessageFixture; 5] = [
+    MessageFixture {
+        name: "forge",
+        protocol: TRANSACTION_TOPIC,
+        bytes: FORGE_HEX,
+    },
+    MessageFixture {
+        name: "block",
+        protocol: BLOCK_TOPIC,
+        bytes: BLOCK_HEX,
+    },
+    MessageFixture {
+        name: "get-headers",
+        protocol: SYNC_PROTOCOL,
+        // {"type":"get_headers","locator":[[1,1,...]],"max":2000}
+        bytes: "7b2274797065223a226765745f68656164657273222c226c6f6361746f72223a5b5b312c312c312c312c312c312c312c\
+                312c312c312c312c312c312c312c312c312c312c312c312c312c312c312c312c312c312c312c312c312c312c312c312c\
+                315d5d2c226d6178223a323030307d",
+    },
+    MessageFixture {
+        name: "headers-empty",
+        protocol: SYNC_PROTOCOL,
+        // {"type":"headers","headers":[]}
+        bytes: "7b2274797065223a2268656164657273222c2268656164657273223a5b5d7d",
+    },
+    MessageFixture {
+        name: "reject-forge",
+        protocol: REJECT_PROTOCOL,
+        // {"item":"Forge","item_hash":[2,2,...],"code":"Duplicate","reason":"proof already used"}
+        bytes: "7b226974656d223a22466f726765222c226974656d5f68617368223a5b322c322c322c322c322c322c322c322c322c32\
+                2c322c322c322c322c322c322c322c322c322c322c322c322c322c322c322c322c322c322c322c322c322c325d2c2263\
+                6f6465223a224475706c6963617465222c22726561736f6e223a2270726f6f6620616c72656164792075736564227d",
+    },
+];
+
+const FORGE_HEX: &str = "0d00000000000000636f6e666f726d616e63652d312100000000000000020303030303030303030303030303\
+                         0303030303030303030303030303030303030f00000000000000626331706669787475726561646472040404\
+                         0404040404040404040404040404040404040404040404040404040404e80300000000000040000000000000\
+                         0005050505050505050505050505050505050505050505050505050505050505050505050505050505050505\
+                         0505050505050505050505050505050505050505050000000000000000000808080808080808080808080808\
+                         080808080808080808080808080808080808";
+
+const BLOCK_HEX: &str = "4558420501000000070000000000000006060606060606060606060606060606060606060606060606060606\
+                         060606060707070707070707070707070707070707070707070707070707070707070707e803000000000000\
+                         00000000ffff7f202a0000000000000000000001000000000000000d00000000000000636f6e666f726d616e\
+                         63652d3121000000000000000203030303030303030303030303030303030303030303030303030303030303\
+                         030f000000000000006263317066697874757265616464720404040404040404040404040404040404040404\
+                         040404040404040404040404e803000000000000400000000000000005050505050505050505050505050505\
+                         0505050505050505050505050505050505050505050505050505050505050505050505050505050505050505\
+                         0505050500000000000000000008080808080808080808080808080808080808080808080808080808080808\
+                         080000000000000000";
+
+/// The forge in `MESSAGE_FIXTURES`
+pub fn fixture_forge() -> ForgeTransaction {
+    ForgeTransaction {
+        prophecy: "conformance-1".to_string(),
+        derived_key: [[0x02].as_slice(), &[0x03; 32]].concat(),
+        taproot_address: "bc1pfixtureaddr".to_string(),
+        proof_hash: [0x04; 32],
+        timestamp: 1000,
+        signature: vec![0x05; 64],
+        not_before_height: 0,
+        tempering: TemperingAlgorithm::Pbkdf2Sha512,
+        salt: [0x08; 32],
+    }
+}
+
+/// The block in `MESSAGE_FIXTURES`
+pub fn fixture_block() -> Block {
+    Block {
+        header: BlockHeader {
+            version: 1,
+            height: 7,
+            prev_block_hash: [0x06; 32],
+            merkle_root: [0x07; 32],
+            timestamp: 1000,
+            difficulty: 0,
+            bits: POW_LIMIT_BITS,
+            nonce: 42,
+            aggregate_commitment: None,
+            state_root: None,
+            timestamp_millis: None,
+        },
+        forges: vec![fixture_forge()],
+        transfers: Vec::new(),
+        authority_signatures: Vec::new(),
+    }
+}
+
+/// Message a fixture describes, encoded the way it goes on the wire
+fn fixture_encoding(name: &str) -> Result<Vec<u8>> {
+    Ok(match name {
+        "forge" => fixture_forge().encode(),
+        "block" => fixture_block().encode(),
+        "get-headers" => encode_json(&SyncRequest::GetHeaders {
+            locator: vec![[1; 32]],
+            max: 2000,
+        })?,
+        "headers-empty" => encode_json(&SyncResponse::Headers { headers: vec![] })?,
+        "reject-forge" => encode_json(&RejectMessage::new(
+            RejectedItem::Forge,
+            [2; 32],
+            RejectCode::Duplicate,
+            "proof already used",
+        ))?,
+        other => return Err(anyhow!("No encoder for fixture {}", other)),
+    })
+}
+
+/// Check this build produces and accepts every fixture byte for byte,
+/// failing at the first that differs
+pub fn verify_fixtures() -> Result<()> {
+    for fixture in &HANDSHAKE_FIXTURES {
+        let protocol_version = handshake_protocol(fixture.magic);
+        let agent_version = fixture.preferences.to_agent_version();
+        if protocol_version != fixture.protocol_version || agent_version != fixture.agent_version {
+            return Err(anyhow!(
+                "Handshake {}: presents {:?} / {:?}, expected {:?} / {:?}",
+                fixture.name,
+                protocol_version,
+                agent_version,
+                fixture.protocol_version,
+                fixture.agent_version
+            ));
+        }
+        if protocol_magic(fixture.protocol_version) != Some(fixture.magic)
+            || RelayPreferences::from_agent_version(fixture.agent_version) != fixture.preferences
+        {
+            return Err(anyhow!("Handshake {}: not parsed back to its magic and preferences", fixture.name));
+        }
+    }
+
+    for fixture in &MESSAGE_FIXTURES {
+        let expected = hex::decode(fixture.bytes)?;
+        let encoded = fixture_encoding(fixture.name)?;
+        if encoded != expected {
+            return Err(anyhow!(
+                "Message {}: encodes as {}, expected {}",
+                fixture.name,
+                hex::encode(&encoded),
+                fixture.bytes
+            ));
+        }
+        let decoded = match fixture.name {
+            "forge" => ForgeTransaction::decode(&expected)?.encode(),
+            "block" => Block::decode(&expected)?.encode(),
+            "get-headers" => encode_json(&decode_json::<SyncRequest>(&expected)?)?,
+            "headers-empty" => encode_json(&decode_json::<SyncResponse>(&expected)?)?,
+            _ => encode_json(&decode_json::<RejectMessage>(&expected)?)?,
+        };
+        if decoded != expected {
+            return Err(anyhow!("Message {}: does not decode back to itself", fixture.name));
+        }
+    }
+    Ok(())
+}
+
+/// JSON encoding of request-response messages
+fn encode_json<T: Serialize>(message: &T) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec(message)?)
+}
+
+fn decode_json<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    Ok(serde_json::from_slice(bytes)?)
+}
+
+/// Which stack of a `Loopback` sent a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    A,
+    B,
+}
+
+/// A message as it crossed the loopback
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WireMessage {
+    pub from: Side,
+    pub protocol: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+/// One node's protocol state: its network, advertised preferences, chain
+/// and sync state
+pub struct ProtocolStack {
+    pub peer_id: PeerId,
+    pub magic: [u8; 4],
+    pub preferences: RelayPreferences,
+    pub store: ChainStore,
+    pub engine: ConsensusEngine,
+    sync: ChainSync,
+    /// The other stack's preferences, once its handshake was accepted
+    remote: Option<RelayPreferences>,
+}
+
+impl ProtocolStack {
+    /// A stack on `magic` with an empty in-memory chain
+    pub fn new(magic: [u8; 4], preferences: RelayPreferences, engine: ConsensusEngine) -> Result<Self> {
+        Ok(Self {
+            peer_id: PeerId::random(),
+            magic,
+            preferences,
+            store: ChainStore::with_backend(Box::new(MemoryStore::new()))?,
+            engine,
+            sync: ChainSync::new(SyncPolicy::default(), HeaderGuard::default()),
+            remote: None,
+        })
+    }
+
+    /// Preferences the other stack advertised
+    pub fn remote_preferences(&self) -> Option<RelayPreferences> {
+        self.remote
+    }
+
+    /// Accept the other stack's identify strings, or refuse another network
+    fn accept_handshake(&mut self, protocol_version: &str, agent_version: &str) -> Result<()> {
+        if protocol_magic(protocol_version) != Some(self.magic) {
+            return Err(anyhow!(
+                "Handshake {:?} does not carry network magic {}",
+                protocol_version,
+                hex::encode(self.magic)
+            ));
+        }
+        self.remote = Some(RelayPreferences::from_agent_version(agent_version));
+        Ok(())
+    }
+
+    /// Store a downloaded block as the new tip
+    fn connect(&self, block: &Block) -> Result<()> {
+        let hash = self.engine.compute_block_hash(&block.header);
+        self.store.put_block(block.header.height, &block.encode())?;
+        self.store.put_block_hash(&hash, block.header.height)?;
+        self.store.set_height(block.header.height)?;
+        self.store.set_best_block(&hash)
+    }
+}
+
+/// Two protocol stacks connected in-process
+pub struct Loopback {
+    pub a: ProtocolStack,
+    pub b: ProtocolStack,
+    transcript: Vec<WireMessage>,
+}
+
+impl Loopback {
+    pub fn new(a: ProtocolStack, b: ProtocolStack) -> Self {
+        Self {
+            a,
+            b,
+            transcript: Vec::new(),
+        }
+    }
+
+    /// Every message exchanged so far, in order
+    pub fn transcript(&self) -> &[WireMessage] {
+        &self.transcript
+    }
+
+    /// Exchange identify handshakes; fails if either side refuses
+    pub fn handshake(&mut self) -> Result<()> {
+        for from in [Side::A, Side::B] {
+            let (local, remote) = self.stacks(from);
+            let protocol_version = handshake_protocol(local.magic);
+            let agent_version = local.preferences.to_agent_version();
+            remote.accept_handshake(&protocol_version, &agent_version)?;
+            self.transcript.push(WireMessage {
+                from,
+                protocol: "/ipfs/id/1.0.0",
+                bytes: [protocol_version.as_bytes(), b"\n", agent_version.as_bytes()].concat(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Have B sync A's chain headers-first, every request and response
+    /// crossing as bytes. Returns the number of blocks B connected.
+    pub fn sync_b_from_a(&mut self) -> Result<u64> {
+        if self.b.remote.is_none() {
+            return Err(anyhow!("Handshake has not completed"));
+        }
+        let now = Instant::now();
+        let a_id = self.a.peer_id;
+        let mut connected = 0;
+        loop {
+            let Some(request) = self.b.sync.request_headers(&self.b.store, &self.b.engine, a_id, now)? else {
+                break;
+            };
+            let SyncResponse::Headers { headers } = self.exchange(request)? else {
+                return Err(anyhow!("Headers request answered with blocks"));
+            };
+            let full = headers.len() == MAX_HEADERS_PER_REQUEST;
+            self.b.sync.headers_received(&self.b.store, &self.b.engine, a_id, &headers)?;
+            if !full {
+                break;
+            }
+        }
+
+        while self.b.sync.is_syncing() {
+            let requests = self.b.sync.block_requests(now);
+            if requests.is_empty() {
+                break;
+            }
+            for (_, request) in requests {
+                let SyncResponse::Blocks { blocks } = self.exchange(request)? else {
+                    return Err(anyhow!("Blocks request answered with headers"));
+                };
+                for block in blocks {
+                    self.b.sync.block_received(&self.b.engine, a_id, block);
+                }
+            }
+            let next_height = match self.b.store.get_best_block()? {
+                Some(_) => self.b.store.get_height()? + 1,
+                None => 0,
+            };
+            for (_, block) in self.b.sync.connectable(next_height) {
+                self.b.connect(&block)?;
+                connected += 1;
+            }
+        }
+        Ok(connected)
+    }
+
+    /// Send a sync request from B to A and return A's response, both
+    /// through their JSON encoding
+    fn exchange(&mut self, request: SyncRequest) -> Result<SyncResponse> {
+        let request_bytes = encode_json(&request)?;
+        self.transcript.push(WireMessage {
+            from: Side::B,
+            protocol: SYNC_PROTOCOL,
+            bytes: request_bytes.clone(),
+        });
+        let response = serve(&self.a.store, &decode_json(&request_bytes)?)?;
+        let response_bytes = encode_json(&response)?;
+        self.transcript.push(WireMessage {
+            from: Side::A,
+            protocol: SYNC_PROTOCOL,
+            bytes: response_bytes.clone(),
+        });
+        decode_json(&response_bytes)
+    }
+
+    /// The sending stack and the receiving one
+    fn stacks(&mut self, from: Side) -> (&ProtocolStack, &mut ProtocolStack) {
+        match from {
+            Side::A => (&self.a, &mut self.b),
+            Side::B => (&self.b, &mut self.a),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::NetworkParams;
+
+    #[test]
+    fn test_fixtures_match_this_build() {
+        verify_fixtures().unwrap();
+        assert_eq!(HANDSHAKE_FIXTURES[0].magic, crate::params::MAINNET_MAGIC);
+        assert_eq!(HANDSHAKE_FIXTURES[1].magic, NetworkParams::regtest().magic);
+    }
+
+    #[test]
+    fn test_loopback_handshake_and_sync() {
+        let stack = |magic, preferences| ProtocolStack::new(magic, preferences, ConsensusEngine::new(0, 600)).unwrap();
+        let regtest = NetworkParams::regtest().magic;
+        let preferences = HANDSHAKE_FIXTURES[1].preferences;
+        let a = stack(regtest, RelayPreferences::default());
+        let b = stack(regtest, preferences);
+
+        // A mines a short chain
+        let mut prev_block_hash = [0u8; 32];
+        for height in 0..3 {
+            let mut header = fixture_block().header;
+            header.height = height;
+            header.prev_block_hash = prev_block_hash;
+            header.timestamp = 1000 + height;
+            assert!(a.engine.grind_header(&mut header, 1_000_000));
+            prev_block_hash = a.engine.compute_block_hash(&header);
+            a.connect(&Block {
+                header,
+                forges: vec![],
+                transfers: Vec::new(),
+                authority_signatures: Vec::new(),
+            })
+            .unwrap();
+        }
+
+        let mut loopback = Loopback::new(a, b);
+        assert!(loopback.sync_b_from_a().is_err(), "no sync before the handshake");
+        loopback.handshake().unwrap();
+        assert_eq!(loopback.a.remote_preferences(), Some(preferences));
+        assert_eq!(loopback.sync_b_from_a().unwrap(), 3);
+        assert_eq!(loopback.b.store.get_best_block().unwrap(), Some(prev_block_hash));
+
+        // Handshakes, then a headers round and a blocks round
+        let protocols: Vec<(Side, &str)> = loopback.transcript().iter().map(|m| (m.from, m.protocol)).collect();
+        let round = [(Side::B, SYNC_PROTOCOL), (Side::A, SYNC_PROTOCOL)];
+        assert_eq!(protocols[2..], [round, round].concat());
+
+        // Stacks on different networks refuse each other
+        let default = RelayPreferences::default();
+        let mut mismatched = Loopback::new(stack(crate::params::MAINNET_MAGIC, default), stack(regtest, default));
+        assert!(mismatched.handshake().is_err());
+    }
+}
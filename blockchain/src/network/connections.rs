@@ -0,0 +1,136 @@
+//! Open connections by direction and the node's listen addresses
+//!
+//! Kept by the network manager and read by `getnetworkinfo`. Connections
+//! are counted rather than peers, as Bitcoin Core does: a peer that both
+//! dialed us and was dialed counts once in each direction.
+
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Address the node listens on, in `getnetworkinfo`'s `localaddresses` form
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LocalAddress {
+    pub address: String,
+    pub port: Option<u16>,
+}
+
+impl LocalAddress {
+    /// Host and TCP port of a listen address; other multiaddrs are kept whole
+    pub fn from_multiaddr(addr: &Multiaddr) -> Self {
+        let mut host = None;
+        let mut port = None;
+        for protocol in addr.iter() {
+            match protocol {
+                Protocol::Ip4(ip) => host = Some(ip.to_string()),
+                Protocol::Ip6(ip) => host = Some(ip.to_string()),
+                Protocol::Dns(name) | Protocol::Dns4(name) | Protocol::Dns6(name) => host = Some(name.to_string()),
+                Protocol::Tcp(tcp) => port = Some(tcp),
+                _ => {}
+            }
+        }
+        match host {
+            Some(address) => Self { address, port },
+            None => Self {
+                address: addr.to_string(),
+                port: None,
+            },
+        }
+    }
+}
+
+/// Shared connection counters
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+    inbound: AtomicUsize,
+    outbound: AtomicUsize,
+    listen_addrs: Mutex<Vec<Multiaddr>>,
+}
+
+impl ConnectionStats {
+    /// Create empty counters
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a newly established connection
+    pub fn opened(&self, dialer: bool) {
+        self.counter(dialer).fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a closed connection
+    pub fn closed(&self, dialer: bool) {
+        let _ = self
+            .counter(dialer)
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| Some(count.saturating_sub(1)));
+    }
+
+    fn counter(&self, dialer: bool) -> &AtomicUsize {
+        if dialer {
+            &self.outbound
+        } else {
+            &self.inbound
+        }
+    }
+
+    /// Open connections peers dialed
+    pub fn inbound(&self) -> usize {
+        self.inbound.load(Ordering::Relaxed)
+    }
+
+    /// Open connections this node dialed
+    pub fn outbound(&self) -> usize {
+        self.outbound.load(Ordering::Relaxed)
+    }
+
+    /// Record a new listen address
+    pub fn add_listen_addr(&self, addr: Multiaddr) {
+        let mut addrs = self.listen_addrs.lock().unwrap();
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+
+    /// Forget a listen address that expired
+    pub fn remove_listen_addr(&self, addr: &Multiaddr) {
+        self.listen_addrs.lock().unwrap().retain(|known| known != addr);
+    }
+
+    /// Addresses the node listens on
+    pub fn local_addresses(&self) -> Vec<LocalAddress> {
+        self.listen_addrs.lock().unwrap().iter().map(LocalAddress::from_multiaddr).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_counts_and_addresses() {
+        let stats = ConnectionStats::new();
+        stats.opened(true);
+        stats.opened(false);
+        stats.opened(false);
+        stats.closed(false);
+        stats.closed(true);
+        stats.closed(true);
+        assert_eq!((stats.inbound(), stats.outbound()), (1, 0));
+
+        let addr: Multiaddr = "/ip4/10.0.0.5/tcp/8333".parse().unwrap();
+        stats.add_listen_addr(addr.clone());
+        stats.add_listen_addr(addr.clone());
+        stats.add_listen_addr("/memory/7".parse().unwrap());
+        assert_eq!(
+            stats.local_addresses(),
+            vec![
+                LocalAddress { address: "10.0.0.5".to_string(), port: Some(8333) },
+                LocalAddress { address: "/memory/7".to_string(), port: None },
+            ]
+        );
+        stats.remove_listen_addr(&addr);
+        assert_eq!(stats.local_addresses().len(), 1);
+    }
+}
@@ -0,0 +1,506 @@
+//! Connection allow and deny rules
+//!
+//! Rules match a remote IP address (a single address or a CIDR range) or a
+//! peer ID. An inbound connection's IP is checked as soon as the TCP
+//! connection is accepted, before the noise handshake runs; its peer ID is
+//! checked once the handshake has authenticated it. Dials to a denied peer
+//! ID, or to addresses that are all denied, are refused outright.
+//!
+//! A deny rule always wins. While the allowlist is empty every peer that
+//! isn't denied may connect; once it has rules, a peer must match one of
+//! them. Rules changed at runtime also close open connections they now
+//! refuse.
+
+use libp2p::core::Endpoint;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::{
+    dummy, CloseConnection, ConnectionClosed, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour,
+    THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+};
+use libp2p::{Multiaddr, PeerId};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll, Waker};
+
+/// An IP address range in CIDR notation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    /// Range of addresses sharing the first `prefix` bits of `address`
+    pub fn new(address: IpAddr, prefix: u8) -> Result<Self, String> {
+        let bits = Self::bits(&address);
+        if prefix > bits {
+            return Err(format!("Prefix /{} is longer than {} bits", prefix, bits));
+        }
+        let mask = u128::MAX.checked_shl(u32::from(bits - prefix)).unwrap_or(0);
+        let network = match address {
+            IpAddr::V4(v4) => IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask as u32)),
+            IpAddr::V6(v6) => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask)),
+        };
+        Ok(Self { network, prefix })
+    }
+
+    fn bits(address: &IpAddr) -> u8 {
+        if address.is_ipv4() {
+            32
+        } else {
+            128
+        }
+    }
+
+    /// Whether `address` is in the range. IPv4-mapped IPv6 addresses match
+    /// IPv4 ranges.
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        let address = match address {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*address),
+            IpAddr::V4(_) => *address,
+        };
+        Self::new(address, self.prefix).is_ok_and(|range| range == *self)
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.prefix == Self::bits(&self.network) {
+            write!(f, "{}", self.network)
+        } else {
+            write!(f, "{}/{}", self.network, self.prefix)
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| format!("Invalid IP address {}", address))?;
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| format!("Invalid prefix length {}", prefix))?,
+            None => Self::bits(&address),
+        };
+        Self::new(address, prefix)
+    }
+}
+
+/// What a rule matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeerRule {
+    Ip(IpRange),
+    Peer(PeerId),
+}
+
+impl PeerRule {
+    fn matches(&self, peer: Option<&PeerId>, address: Option<&IpAddr>) -> bool {
+        match self {
+            PeerRule::Ip(range) => address.is_some_and(|address| range.contains(address)),
+            PeerRule::Peer(id) => peer == Some(id),
+        }
+    }
+}
+
+impl fmt::Display for PeerRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerRule::Ip(range) => range.fmt(f),
+            PeerRule::Peer(peer) => peer.fmt(f),
+        }
+    }
+}
+
+impl FromStr for PeerRule {
+    type Err = String;
+
+    /// An IP address, a CIDR range or a peer ID
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(peer) = s.parse() {
+            return Ok(PeerRule::Peer(peer));
+        }
+        s.parse()
+            .map(PeerRule::Ip)
+            .map_err(|e| format!("{} is not an IP address, CIDR range or peer ID ({})", s, e))
+    }
+}
+
+/// Which list a rule belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleList {
+    Allow,
+    Deny,
+}
+
+impl FromStr for RuleList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(RuleList::Allow),
+            "deny" => Ok(RuleList::Deny),
+            _ => Err(format!("Unknown rule list {} (expected \"allow\" or \"deny\")", s)),
+        }
+    }
+}
+
+/// Why a connection was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Refusal {
+    Denied(PeerRule),
+    NotAllowed,
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refusal::Denied(rule) => write!(f, "matches deny rule {}", rule),
+            Refusal::NotAllowed => write!(f, "not on the allowlist"),
+        }
+    }
+}
+
+impl std::error::Error for Refusal {}
+
+/// Configured rules, for `listpeerrules`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PeerRules {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    /// Connections and dials refused since startup
+    pub refused: u64,
+}
+
+#[derive(Debug, Default)]
+struct Rules {
+    allow: Vec<PeerRule>,
+    deny: Vec<PeerRule>,
+    /// Bumped on every change, so open connections are re-checked
+    generation: u64,
+}
+
+/// Allow and deny rules shared between the swarm and the RPC server
+#[derive(Debug, Default)]
+pub struct PeerFilter {
+    rules: RwLock<Rules>,
+    refused: AtomicU64,
+    /// Wakes the connection filter to re-check open connections
+    waker: Mutex<Option<Waker>>,
+}
+
+impl PeerFilter {
+    /// Filter with initial rules
+    pub fn new(allow: Vec<PeerRule>, deny: Vec<PeerRule>) -> Self {
+        Self {
+            rules: RwLock::new(Rules { allow, deny, generation: 0 }),
+            ..Self::default()
+        }
+    }
+
+    /// Add a rule. Returns false if it was already there.
+    pub fn add(&self, list: RuleList, rule: PeerRule) -> bool {
+        self.update(list, |rules| {
+            if rules.contains(&rule) {
+                return false;
+            }
+            rules.push(rule);
+            true
+        })
+    }
+
+    /// Remove a rule. Returns false if there was no such rule.
+    pub fn remove(&self, list: RuleList, rule: &PeerRule) -> bool {
+        self.update(list, |rules| {
+            let before = rules.len();
+            rules.retain(|existing| existing != rule);
+            rules.len() != before
+        })
+    }
+
+    /// Replace every rule, e.g. with ones reloaded from the config file
+    pub fn set_rules(&self, allow: Vec<PeerRule>, deny: Vec<PeerRule>) {
+        let mut rules = self.rules.write().unwrap();
+        rules.allow = allow;
+        rules.deny = deny;
+        self.changed(&mut rules);
+    }
+
+    fn update(&self, list: RuleList, change: impl FnOnce(&mut Vec<PeerRule>) -> bool) -> bool {
+        let mut rules = self.rules.write().unwrap();
+        let changed = change(match list {
+            RuleList::Allow => &mut rules.allow,
+            RuleList::Deny => &mut rules.deny,
+        });
+        if changed {
+            self.changed(&mut rules);
+        }
+        changed
+    }
+
+    /// Have the connection filter re-check open connections
+    fn changed(&self, rules: &mut Rules) {
+        rules.generation += 1;
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+
+    /// Current rules and refusal count
+    pub fn rules(&self) -> PeerRules {
+        let rules = self.rules.read().unwrap();
+        let names = |list: &[PeerRule]| list.iter().map(PeerRule::to_string).collect();
+        PeerRules {
+            allow: names(&rules.allow),
+            deny: names(&rules.deny),
+            refused: self.refused.load(Ordering::Relaxed),
+        }
+    }
+
+    fn generation(&self) -> u64 {
+        self.rules.read().unwrap().generation
+    }
+
+    /// Check a connection whose peer ID or IP may not be known yet. A peer
+    /// that could still match a peer ID on the allowlist isn't refused
+    /// until its ID is known.
+    pub fn check(&self, peer: Option<&PeerId>, address: Option<&IpAddr>) -> Result<(), Refusal> {
+        let rules = self.rules.read().unwrap();
+        if let Some(rule) = rules.deny.iter().find(|rule| rule.matches(peer, address)) {
+            return Err(Refusal::Denied(*rule));
+        }
+        if rules.allow.is_empty() || rules.allow.iter().any(|rule| rule.matches(peer, address)) {
+            return Ok(());
+        }
+        let undecided = peer.is_none() && rules.allow.iter().any(|rule| matches!(rule, PeerRule::Peer(_)));
+        if undecided {
+            Ok(())
+        } else {
+            Err(Refusal::NotAllowed)
+        }
+    }
+
+    fn enforce(&self, peer: Option<&PeerId>, address: &Multiaddr) -> Result<(), ConnectionDenied> {
+        self.check(peer, ip_address(address).as_ref()).map_err(|refusal| {
+            self.refused.fetch_add(1, Ordering::Relaxed);
+            tracing::debug!("Refusing connection to {} ({:?}): {}", address, peer, refusal);
+            ConnectionDenied::new(refusal)
+        })
+    }
+}
+
+/// First IP address in a multiaddr
+fn ip_address(address: &Multiaddr) -> Option<IpAddr> {
+    address.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+        Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    })
+}
+
+/// Swarm behaviour enforcing a `PeerFilter`
+pub struct ConnectionFilter {
+    filter: Arc<PeerFilter>,
+    /// Open connections, re-checked when the rules change
+    connections: HashMap<ConnectionId, (PeerId, Multiaddr)>,
+    generation: u64,
+    to_close: VecDeque<(PeerId, ConnectionId)>,
+}
+
+impl ConnectionFilter {
+    pub fn new(filter: Arc<PeerFilter>) -> Self {
+        Self {
+            generation: filter.generation(),
+            filter,
+            connections: HashMap::new(),
+            to_close: VecDeque::new(),
+        }
+    }
+
+    /// Rules enforced by this behaviour
+    pub fn filter(&self) -> Arc<PeerFilter> {
+        Arc::clone(&self.filter)
+    }
+}
+
+impl NetworkBehaviour for ConnectionFilter {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.filter.enforce(None, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        peer: PeerId,
+        _local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.filter.enforce(Some(&peer), remote_addr)?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        _effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        if let Some(peer) = &peer {
+            if let Err(Refusal::Denied(rule)) = self.filter.check(Some(peer), None) {
+                self.filter.refused.fetch_add(1, Ordering::Relaxed);
+                return Err(ConnectionDenied::new(Refusal::Denied(rule)));
+            }
+        }
+        // Only refuse the dial when no address could be allowed
+        let mut last_refusal = None;
+        for address in addresses {
+            match self.filter.check(peer.as_ref(), ip_address(address).as_ref()) {
+                Ok(()) => return Ok(Vec::new()),
+                Err(refusal) => last_refusal = Some(refusal),
+            }
+        }
+        match last_refusal {
+            Some(refusal) => {
+                self.filter.refused.fetch_add(1, Ordering::Relaxed);
+                Err(ConnectionDenied::new(refusal))
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        _role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.filter.enforce(Some(&peer), addr)?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::ConnectionEstablished(established) => {
+                let address = established.endpoint.get_remote_address().clone();
+                self.connections.insert(established.connection_id, (established.peer_id, address));
+            }
+            FromSwarm::ConnectionClosed(ConnectionClosed { connection_id, .. }) => {
+                self.connections.remove(&connection_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _peer_id: PeerId,
+        _connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        *self.filter.waker.lock().unwrap() = Some(cx.waker().clone());
+        let generation = self.filter.generation();
+        if generation != self.generation {
+            self.generation = generation;
+            for (connection_id, (peer, address)) in &self.connections {
+                if let Err(refusal) = self.filter.check(Some(peer), ip_address(address).as_ref()) {
+                    tracing::info!("Closing connection to {} at {}: {}", peer, address, refusal);
+                    self.filter.refused.fetch_add(1, Ordering::Relaxed);
+                    self.to_close.push_back((*peer, *connection_id));
+                }
+            }
+        }
+        match self.to_close.pop_front() {
+            Some((peer_id, connection_id)) => Poll::Ready(ToSwarm::CloseConnection {
+                peer_id,
+                connection: CloseConnection::One(connection_id),
+            }),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rule_parsing() {
+        let range: IpRange = "10.1.2.3/8".parse().unwrap();
+        assert_eq!(range.to_string(), "10.0.0.0/8");
+        assert!(range.contains(&"10.200.0.1".parse().unwrap()));
+        assert!(range.contains(&"::ffff:10.0.0.1".parse().unwrap()));
+        assert!(!range.contains(&"11.0.0.1".parse().unwrap()));
+        assert!("0.0.0.0/0".parse::<IpRange>().unwrap().contains(&"8.8.8.8".parse().unwrap()));
+        let v6: IpRange = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains(&"2001:db8:1::1".parse().unwrap()));
+        assert!(!v6.contains(&"10.0.0.1".parse().unwrap()));
+        assert_eq!("192.0.2.7".parse::<PeerRule>().unwrap().to_string(), "192.0.2.7");
+        assert!("10.0.0.0/33".parse::<PeerRule>().is_err());
+        assert!("not-a-peer".parse::<PeerRule>().is_err());
+
+        let peer = PeerId::random();
+        assert_eq!(peer.to_string().parse::<PeerRule>().unwrap(), PeerRule::Peer(peer));
+        assert_eq!("deny".parse::<RuleList>().unwrap(), RuleList::Deny);
+        assert!("block".parse::<RuleList>().is_err());
+    }
+
+    #[test]
+    fn test_deny_wins_and_allowlist_waits_for_peer_id() {
+        let trusted = PeerId::random();
+        let stranger = PeerId::random();
+        let lan: IpAddr = "192.168.1.20".parse().unwrap();
+        let wan: IpAddr = "203.0.113.9".parse().unwrap();
+        let filter = PeerFilter::default();
+        assert_eq!(filter.check(Some(&stranger), Some(&wan)), Ok(()));
+
+        filter.add(RuleList::Deny, "203.0.113.0/24".parse().unwrap());
+        let denied = Err(Refusal::Denied("203.0.113.0/24".parse().unwrap()));
+        assert_eq!(filter.check(None, Some(&wan)), denied);
+
+        // IP-only allowlist decides before the handshake
+        filter.add(RuleList::Allow, "192.168.0.0/16".parse().unwrap());
+        assert_eq!(filter.check(None, Some(&lan)), Ok(()));
+        assert_eq!(filter.check(None, Some(&"198.51.100.1".parse().unwrap())), Err(Refusal::NotAllowed));
+
+        // With a peer ID rule, unknown IPs wait for the authenticated ID
+        let other: IpAddr = "198.51.100.1".parse().unwrap();
+        assert!(filter.add(RuleList::Allow, PeerRule::Peer(trusted)));
+        assert!(!filter.add(RuleList::Allow, PeerRule::Peer(trusted)));
+        assert_eq!(filter.check(None, Some(&other)), Ok(()));
+        assert_eq!(filter.check(Some(&trusted), Some(&other)), Ok(()));
+        assert_eq!(filter.check(Some(&stranger), Some(&other)), Err(Refusal::NotAllowed));
+        // Deny still wins over an allowed peer ID
+        assert_eq!(filter.check(Some(&trusted), Some(&wan)), denied);
+
+        assert!(filter.remove(RuleList::Deny, &"203.0.113.0/24".parse().unwrap()));
+        assert!(!filter.remove(RuleList::Deny, &"203.0.113.0/24".parse().unwrap()));
+        let rules = filter.rules();
+        assert_eq!(rules.allow, vec!["192.168.0.0/16".to_string(), trusted.to_string()]);
+        assert!(rules.deny.is_empty());
+        assert_eq!(filter.generation(), 4);
+    }
+}
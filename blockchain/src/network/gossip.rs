@@ -0,0 +1,188 @@
+//! Gossipsub mesh and peer-scoring settings
+//!
+//! The mesh sizes, heartbeat, message-id function and libp2p's peer-scoring
+//! thresholds are read from `[network.gossip]` so operators can tune gossip
+//! without recompiling. With scoring on, peers whose gossipsub score sinks to
+//! `ban_threshold` are handed to the node's own ban list, so a peer
+//! misbehaving at the gossip layer is disconnected and refused like any other
+//! banned peer.
+
+use libp2p::gossipsub::{self, MessageId, PeerScoreParams, PeerScoreThresholds};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+/// Default interval between gossipsub heartbeats
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Default score at or below which a peer is banned
+pub const DEFAULT_BAN_THRESHOLD: f64 = -100.0;
+
+/// How gossip messages are identified for deduplication
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageIdMode {
+    /// SHA-256 of the payload, so the same block or forge published by
+    /// several nodes is only relayed once
+    #[default]
+    Content,
+    /// Publisher and sequence number (the libp2p default)
+    Source,
+}
+
+/// `[network.gossip]` settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GossipSettings {
+    /// Target number of peers in each topic mesh
+    pub mesh_n: usize,
+    /// Fewest mesh peers before more are grafted
+    pub mesh_n_low: usize,
+    /// Most mesh peers before some are pruned
+    pub mesh_n_high: usize,
+    /// Outbound peers kept in each mesh
+    pub mesh_outbound_min: usize,
+    /// Milliseconds between heartbeats
+    pub heartbeat_interval_ms: u64,
+    /// Message-id function
+    pub message_id: MessageIdMode,
+    /// Score peers and act on the thresholds below
+    pub scoring: bool,
+    /// Score below which no gossip is exchanged with a peer
+    pub gossip_threshold: f64,
+    /// Score below which our own messages are not published to a peer
+    pub publish_threshold: f64,
+    /// Score below which a peer's messages are ignored
+    pub graylist_threshold: f64,
+    /// Score above which peer exchange from a peer is accepted
+    pub accept_px_threshold: f64,
+    /// Median mesh score below which better peers are grafted
+    pub opportunistic_graft_threshold: f64,
+    /// Score at or below which a peer is banned
+    pub ban_threshold: f64,
+}
+
+impl Default for GossipSettings {
+    fn default() -> Self {
+        let defaults = gossipsub::Config::default();
+        let thresholds = PeerScoreThresholds::default();
+        Self {
+            mesh_n: defaults.mesh_n(),
+            mesh_n_low: defaults.mesh_n_low(),
+            mesh_n_high: defaults.mesh_n_high(),
+            mesh_outbound_min: defaults.mesh_outbound_min(),
+            heartbeat_interval_ms: DEFAULT_HEARTBEAT_INTERVAL.as_millis() as u64,
+            message_id: MessageIdMode::default(),
+            scoring: true,
+            gossip_threshold: thresholds.gossip_threshold,
+            publish_threshold: thresholds.publish_threshold,
+            graylist_threshold: thresholds.graylist_threshold,
+            accept_px_threshold: thresholds.accept_px_threshold,
+            opportunistic_graft_threshold: thresholds.opportunistic_graft_threshold,
+            ban_threshold: DEFAULT_BAN_THRESHOLD,
+        }
+    }
+}
+
+impl GossipSettings {
+    /// Interval between heartbeats
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_millis(self.heartbeat_interval_ms)
+    }
+
+    /// Gossipsub configuration for messages of at most `max_transmit_size` bytes
+    pub fn gossipsub_config(&self, max_transmit_size: usize) -> Result<gossipsub::Config, String> {
+        if self.heartbeat_interval_ms == 0 {
+            return Err("Invalid gossip settings: heartbeat_interval_ms must be positive".to_string());
+        }
+        let mut builder = gossipsub::ConfigBuilder::default();
+        builder
+            .heartbeat_interval(self.heartbeat_interval())
+            .validation_mode(gossipsub::ValidationMode::Strict)
+            // Messages are only propagated once checked against the
+            // persisted seen set
+            .validate_messages()
+            .max_transmit_size(max_transmit_size)
+            .mesh_n(self.mesh_n)
+            .mesh_n_low(self.mesh_n_low)
+            .mesh_n_high(self.mesh_n_high)
+            .mesh_outbound_min(self.mesh_outbound_min);
+        if self.message_id == MessageIdMode::Content {
+            builder.message_id_fn(|message: &gossipsub::Message| {
+                MessageId::from(Sha256::digest(&message.data).to_vec())
+            });
+        }
+        builder.build().map_err(|e| format!("Invalid gossip settings: {}", e))
+    }
+
+    /// Peer-scoring thresholds
+    pub fn score_thresholds(&self) -> PeerScoreThresholds {
+        PeerScoreThresholds {
+            gossip_threshold: self.gossip_threshold,
+            publish_threshold: self.publish_threshold,
+            graylist_threshold: self.graylist_threshold,
+            accept_px_threshold: self.accept_px_threshold,
+            opportunistic_graft_threshold: self.opportunistic_graft_threshold,
+        }
+    }
+
+    /// Enable peer scoring on `gossipsub` if configured
+    pub fn apply_scoring(&self, gossipsub: &mut gossipsub::Behaviour) -> Result<(), String> {
+        if !self.scoring {
+            return Ok(());
+        }
+        let thresholds = self.score_thresholds();
+        thresholds.validate().map_err(|e| format!("Invalid gossip settings: {}", e))?;
+        if self.ban_threshold > self.graylist_threshold {
+            return Err("Invalid gossip settings: ban_threshold must be <= graylist_threshold".to_string());
+        }
+        gossipsub.with_peer_score(PeerScoreParams::default(), thresholds)
+    }
+
+    /// Whether a peer with gossipsub `score` should be banned
+    pub fn should_ban(&self, score: f64) -> bool {
+        self.scoring && score <= self.ban_threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_build_gossipsub_config() {
+        let settings = GossipSettings {
+            mesh_n: 8,
+            mesh_n_low: 5,
+            mesh_n_high: 12,
+            heartbeat_interval_ms: 700,
+            ..GossipSettings::default()
+        };
+        let config = settings.gossipsub_config(1 << 20).unwrap();
+        assert_eq!((config.mesh_n(), config.mesh_n_low(), config.mesh_n_high()), (8, 5, 12));
+        assert_eq!(config.heartbeat_interval(), Duration::from_millis(700));
+
+        // Mesh bounds must bracket the target
+        let inverted = GossipSettings { mesh_n_low: 10, ..settings };
+        assert!(inverted.gossipsub_config(1 << 20).is_err());
+    }
+
+    #[test]
+    fn test_scoring_thresholds() {
+        let settings = GossipSettings::default();
+        assert!(settings.should_ban(DEFAULT_BAN_THRESHOLD));
+        assert!(!settings.should_ban(settings.graylist_threshold));
+        assert!(!GossipSettings { scoring: false, ..settings.clone() }.should_ban(-1000.0));
+
+        let keypair = libp2p::identity::Keypair::generate_ed25519();
+        let config = settings.gossipsub_config(1 << 20).unwrap();
+        let mut behaviour =
+            gossipsub::Behaviour::new(gossipsub::MessageAuthenticity::Signed(keypair.clone()), config.clone()).unwrap();
+        settings.apply_scoring(&mut behaviour).unwrap();
+
+        let lenient = GossipSettings { ban_threshold: 0.0, ..settings };
+        let mut behaviour =
+            gossipsub::Behaviour::new(gossipsub::MessageAuthenticity::Signed(keypair), config).unwrap();
+        assert!(lenient.apply_scoring(&mut behaviour).is_err());
+    }
+}
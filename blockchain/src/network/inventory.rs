@@ -0,0 +1,157 @@
+//! Per-peer known inventory
+//!
+//! Remembers which blocks and forges each peer is known to have: items it
+//! relayed to us and items we published while it was subscribed. Gossipsub
+//! floods a publish to every subscribed peer, so an item is only published
+//! when at least one peer that would receive it doesn't already know it. In
+//! a dense mesh a validated block has usually arrived from every neighbour
+//! by the time it would be relayed, and the relay is skipped.
+//!
+//! Items are identified by the SHA-256 of their encoding, and each peer's
+//! set is bounded, forgetting its oldest items first.
+
+use libp2p::PeerId;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+/// Default number of items remembered per peer
+pub const DEFAULT_KNOWN_INVENTORY: usize = 5_000;
+
+/// Inventory id of a gossiped item
+pub fn inventory_hash(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Known inventory counters, for RPC
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct InventoryStats {
+    /// Peers with at least one known item
+    pub peers: usize,
+    /// Known items summed over peers
+    pub known_items: usize,
+    /// Publishes skipped because every recipient knew the item
+    pub relays_skipped: u64,
+}
+
+#[derive(Debug, Default)]
+struct KnownSet {
+    items: HashSet<[u8; 32]>,
+    order: VecDeque<[u8; 32]>,
+}
+
+#[derive(Debug, Default)]
+struct Inventory {
+    peers: HashMap<PeerId, KnownSet>,
+    relays_skipped: u64,
+}
+
+/// Bounded sets of the items each peer knows
+#[derive(Debug)]
+pub struct PeerInventory {
+    capacity: usize,
+    inner: Mutex<Inventory>,
+}
+
+impl Default for PeerInventory {
+    fn default() -> Self {
+        Self::new(DEFAULT_KNOWN_INVENTORY)
+    }
+}
+
+impl PeerInventory {
+    /// Remember at most `capacity` items per peer (0 disables tracking, so
+    /// every item is relayed)
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Inventory::default()),
+        }
+    }
+
+    /// Record that `peer` has an item, because it relayed it or we sent it
+    pub fn mark_known(&self, peer: PeerId, hash: &[u8; 32]) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let known = inner.peers.entry(peer).or_default();
+        if !known.items.insert(*hash) {
+            return;
+        }
+        known.order.push_back(*hash);
+        if known.order.len() > self.capacity {
+            if let Some(oldest) = known.order.pop_front() {
+                known.items.remove(&oldest);
+            }
+        }
+    }
+
+    /// Whether `peer` is known to have an item
+    pub fn knows(&self, peer: &PeerId, hash: &[u8; 32]) -> bool {
+        self.inner
+            .lock()
+            .unwrap()
+            .peers
+            .get(peer)
+            .is_some_and(|known| known.items.contains(hash))
+    }
+
+    /// Whether an item should be published to `recipients`: true unless
+    /// every one of them already knows it. A skipped relay is counted.
+    pub fn should_relay(&self, recipients: &[PeerId], hash: &[u8; 32]) -> bool {
+        if recipients.is_empty() || !recipients.iter().all(|peer| self.knows(peer, hash)) {
+            return true;
+        }
+        self.inner.lock().unwrap().relays_skipped += 1;
+        false
+    }
+
+    /// Forget a disconnected peer
+    pub fn remove(&self, peer: &PeerId) {
+        self.inner.lock().unwrap().peers.remove(peer);
+    }
+
+    /// Current counters
+    pub fn stats(&self) -> InventoryStats {
+        let inner = self.inner.lock().unwrap();
+        InventoryStats {
+            peers: inner.peers.len(),
+            known_items: inner.peers.values().map(|known| known.order.len()).sum(),
+            relays_skipped: inner.relays_skipped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relay_skipped_when_every_peer_knows() {
+        let inventory = PeerInventory::new(2);
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let block = inventory_hash(b"block");
+
+        assert!(inventory.should_relay(&[a, b], &block));
+        inventory.mark_known(a, &block);
+        assert!(inventory.should_relay(&[a, b], &block), "b hasn't seen it");
+        inventory.mark_known(b, &block);
+        assert!(!inventory.should_relay(&[a, b], &block));
+        assert!(inventory.should_relay(&[], &block), "no peers yet: publish queues it");
+
+        // Each peer's set is bounded, oldest first
+        inventory.mark_known(a, &[1; 32]);
+        inventory.mark_known(a, &[2; 32]);
+        assert!(!inventory.knows(&a, &block));
+        assert!(inventory.should_relay(&[a, b], &block));
+
+        inventory.remove(&b);
+        assert_eq!(inventory.stats(), InventoryStats { peers: 1, known_items: 2, relays_skipped: 1 });
+
+        let disabled = PeerInventory::new(0);
+        disabled.mark_known(a, &block);
+        assert!(disabled.should_relay(&[a], &block));
+    }
+}
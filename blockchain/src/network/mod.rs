@@ -1,22 +1,230 @@
 //! P2P networking with libp2p
 
+pub mod bandwidth;
+pub mod conformance;
+pub mod connections;
+pub mod filter;
+pub mod gossip;
+pub mod inventory;
+pub mod peer;
+pub mod reconnect;
+pub mod reject;
+pub mod relay;
+pub mod seen;
+pub mod services;
+pub mod sync;
+
+pub use bandwidth::{BandwidthTracker, MessageKind, NetTotals};
+pub use connections::{ConnectionStats, LocalAddress};
+pub use filter::{ConnectionFilter, IpRange, PeerFilter, PeerRule, PeerRules, Refusal, RuleList};
+pub use gossip::{GossipSettings, MessageIdMode};
+pub use inventory::{inventory_hash, InventoryStats, PeerInventory};
+pub use peer::{PeerState, PeerTable};
+pub use reconnect::{ReconnectSchedule, ReconnectStatus};
+pub use reject::{RejectCode, RejectMessage, RejectedItem};
+pub use relay::{BlockRelay, RelayStats, Revocation};
+pub use seen::SeenMessages;
+pub use services::{PeerServices, RoleDistribution, ServiceFlags};
+pub use sync::{
+    BodyFetchQueue, ForgeProof, HeaderAnnouncement, PeerSyncStatus, SyncPolicy, SyncRequest, SyncResponse, SyncStatus,
+};
+
+use futures::StreamExt;
+use serde_json::value::RawValue;
 use libp2p::{
     gossipsub, identify, kad,
-    noise,
+    noise, request_response,
     swarm::{NetworkBehaviour, SwarmEvent},
-    tcp, yamux, Multiaddr, PeerId, Swarm, Transport,
+    tcp, yamux, Multiaddr, PeerId, StreamProtocol, Swarm, Transport,
 };
-use std::collections::HashSet;
+use crate::params::{GossipTopics, NetworkParams, MAINNET_MAGIC};
+use bandwidth::UploadLimiter;
+use reject::{RejectLimiter, REJECT_PROTOCOL};
+use relay::HeldBlocks;
+use sync::SYNC_PROTOCOL;
+use std::collections::{HashMap, VecDeque};
 use std::error::Error;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
+/// Gossip topic for block announcements
+pub const BLOCK_TOPIC: &str = "excalibur-blocks";
+/// Gossip topic for forge transaction announcements
+pub const TRANSACTION_TOPIC: &str = "excalibur-transactions";
+/// Gossip topic for header announcements with fee commitments
+pub const HEADER_TOPIC: &str = "excalibur-headers";
+/// Gossip topic for watchtower evidence of rule violations
+pub const EVIDENCE_TOPIC: &str = "excalibur-evidence";
+
+/// Maximum number of announcements held while no peers are available
+pub const PUBLISH_QUEUE_CAPACITY: usize = 256;
+/// How long a queued announcement stays eligible for re-publishing
+pub const PUBLISH_QUEUE_TTL: Duration = Duration::from_secs(300);
+/// Interval at which the retry queue is flushed and expired
+const PUBLISH_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+/// Interval at which due peer redials are dialed
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Identify protocol version, followed by the network's connection magic
+pub const PROTOCOL_VERSION: &str = "/excalibur/1.0.0";
+/// Node software version advertised in the identify agent string
+pub const AGENT_VERSION: &str = "excalibur-node/1.0.0";
+/// Node version as Bitcoin Core reports it: `major * 10000 + minor * 100 + patch`
+pub fn node_version() -> u32 {
+    let version = AGENT_VERSION.rsplit('/').next().unwrap_or_default();
+    version
+        .split('.')
+        .take(3)
+        .fold(0, |total, part| total * 100 + part.parse::<u32>().unwrap_or(0))
+}
+
+/// BIP-14 subversion string, e.g. `/excalibur-node:1.0.0/`
+pub fn subversion() -> String {
+    format!("/{}/", AGENT_VERSION.replace('/', ":"))
+}
+
+/// Default maximum gossip message size (4 MiB)
+pub const DEFAULT_MAX_MESSAGE_SIZE: u32 = 4 * 1024 * 1024;
+
+/// Current time in Unix seconds
+pub(crate) fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Identify protocol version advertising a network's connection magic
+pub fn handshake_protocol(magic: [u8; 4]) -> String {
+    format!("{}/{}", PROTOCOL_VERSION, hex::encode(magic))
+}
+
+/// Connection magic in a peer's identify protocol version. Peers that
+/// predate magic are mainnet peers; non-Excalibur peers have none.
+pub fn protocol_magic(protocol_version: &str) -> Option<[u8; 4]> {
+    let rest = protocol_version.strip_prefix(PROTOCOL_VERSION)?;
+    if rest.is_empty() {
+        return Some(MAINNET_MAGIC);
+    }
+    hex::decode(rest.strip_prefix('/')?).ok()?.try_into().ok()
+}
+
+/// Relay preferences a peer declares during the identify handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelayPreferences {
+    /// Peer does not want forge transactions relayed (blocks-only node)
+    pub no_forge_relay: bool,
+    /// Peer prefers compact block announcements
+    pub compact_blocks: bool,
+    /// Services the peer offers (archival history, filters, ...)
+    pub services: ServiceFlags,
+    /// Largest message the peer accepts, in bytes
+    pub max_message_size: u32,
+}
+
+impl Default for RelayPreferences {
+    fn default() -> Self {
+        Self {
+            no_forge_relay: false,
+            compact_blocks: false,
+            services: ServiceFlags::FULL,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+impl RelayPreferences {
+    const NO_FORGE_RELAY: u8 = 1 << 0;
+    const COMPACT_BLOCKS: u8 = 1 << 1;
+    const FILTERS_SERVED: u8 = 1 << 2;
+    const AGENT_TAG: &'static str = ";prefs=";
+    const SERVICES_TAG: &'static str = ";services=";
+
+    /// Build the identify agent string advertising these preferences
+    pub fn to_agent_version(&self) -> String {
+        let mut flags = 0u8;
+        if self.no_forge_relay {
+            flags |= Self::NO_FORGE_RELAY;
+        }
+        if self.compact_blocks {
+            flags |= Self::COMPACT_BLOCKS;
+        }
+        // Kept so peers predating the services bitfield still find filters
+        if self.services.contains(ServiceFlags::FILTERS) {
+            flags |= Self::FILTERS_SERVED;
+        }
+        format!(
+            "{}{}{:02x}/{}{}{:02x}",
+            AGENT_VERSION,
+            Self::AGENT_TAG,
+            flags,
+            self.max_message_size,
+            Self::SERVICES_TAG,
+            self.services.bits()
+        )
+    }
+
+    /// Parse preferences from a peer's identify agent string.
+    ///
+    /// Peers that don't advertise preferences get the defaults (full relay).
+    /// Peers that predate the services bitfield are treated as full nodes
+    /// of unknown history depth, never as archival.
+    pub fn from_agent_version(agent: &str) -> Self {
+        let Some((_, prefs)) = agent.split_once(Self::AGENT_TAG) else {
+            return Self::default();
+        };
+        let prefs = prefs.split(';').next().unwrap_or_default();
+        let Some((flags, max_size)) = prefs.split_once('/') else {
+            return Self::default();
+        };
+        let (Ok(flags), Ok(max_message_size)) =
+            (u8::from_str_radix(flags, 16), max_size.parse::<u32>())
+        else {
+            return Self::default();
+        };
+
+        let services = agent
+            .split_once(Self::SERVICES_TAG)
+            .and_then(|(_, bits)| u8::from_str_radix(bits.split(';').next().unwrap_or_default(), 16).ok())
+            .map(ServiceFlags::from_bits)
+            .unwrap_or_else(|| {
+                let mut services = ServiceFlags::FULL;
+                if flags & Self::FILTERS_SERVED != 0 {
+                    services.insert(ServiceFlags::FILTERS);
+                }
+                services
+            });
+
+        Self {
+            no_forge_relay: flags & Self::NO_FORGE_RELAY != 0,
+            compact_blocks: flags & Self::COMPACT_BLOCKS != 0,
+            services,
+            max_message_size,
+        }
+    }
+
+    /// Whether a message of `len` bytes on `topic` should be sent to this peer
+    pub fn accepts(&self, topic: &str, len: usize) -> bool {
+        if self.no_forge_relay && topic == TRANSACTION_TOPIC {
+            return false;
+        }
+        len <= self.max_message_size as usize
+    }
+}
+
 /// Network behavior for Excalibur blockchain
 #[derive(NetworkBehaviour)]
 pub struct ExcaliburBehaviour {
+    /// First, so denied connections are refused before other behaviours see them
+    pub filter: ConnectionFilter,
     pub gossipsub: gossipsub::Behaviour,
     pub kad: kad::Behaviour<kad::store::MemoryStore>,
     pub identify: identify::Behaviour,
+    pub reject: request_response::json::Behaviour<RejectMessage, ()>,
+    /// Responses travel as already-encoded JSON, so their size is known
+    /// before they are sent without encoding them a second time
+    pub sync: request_response::json::Behaviour<SyncRequest, Box<RawValue>>,
 }
 
 /// Network manager for P2P communications
@@ -24,6 +232,33 @@ pub struct NetworkManager {
     swarm: Swarm<ExcaliburBehaviour>,
     command_receiver: mpsc::Receiver<NetworkCommand>,
     event_sender: mpsc::Sender<NetworkEvent>,
+    publish_queue: PublishRetryQueue,
+    local_preferences: RelayPreferences,
+    peer_preferences: HashMap<PeerId, RelayPreferences>,
+    peer_services: Arc<PeerServices>,
+    reject_limiter: RejectLimiter,
+    bandwidth: Arc<BandwidthTracker>,
+    connections: Arc<ConnectionStats>,
+    upload_limiter: UploadLimiter,
+    reconnect: Arc<ReconnectSchedule>,
+    peers: Arc<PeerTable>,
+    /// Connection magic of the network this node is on
+    magic: [u8; 4],
+    /// Wire names of the network's gossip topics
+    topics: GossipTopics,
+    /// Mesh and peer-scoring settings
+    gossip: GossipSettings,
+    /// Gossip message ids seen recently, including before a restart
+    seen: Arc<SeenMessages>,
+    /// Items each peer is known to have, to skip redundant relays
+    inventory: Arc<PeerInventory>,
+    /// Whether gossiped blocks wait for the node's relay decision
+    hold_blocks: bool,
+    held_blocks: HeldBlocks,
+    /// Inbound sync requests waiting for the node's response, by the id
+    /// passed in `NetworkEvent::SyncRequested`
+    sync_responses: HashMap<u64, (PeerId, request_response::ResponseChannel<Box<RawValue>>)>,
+    next_sync_request: u64,
 }
 
 /// Commands that can be sent to the network
@@ -31,19 +266,199 @@ pub struct NetworkManager {
 pub enum NetworkCommand {
     PublishBlock(Vec<u8>),
     PublishTransaction(Vec<u8>),
+    /// Gossip an encoded `HeaderAnnouncement` ahead of the block body
+    PublishHeader(Vec<u8>),
+    /// Gossip encoded watchtower evidence
+    PublishEvidence(Vec<u8>),
     ConnectPeer(Multiaddr),
     DisconnectPeer(PeerId),
     GetPeers,
+    GetPublishQueueStats,
+    /// Tell the peer that relayed an item why it was rejected
+    RejectItem(PeerId, RejectMessage),
+    /// Tell every block-topic peer that an optimistically relayed block
+    /// failed full validation
+    RevokeBlock([u8; 32], String),
+    /// Propagate a held gossip block, by `inventory_hash`
+    RelayBlock([u8; 32]),
+    /// Drop a held gossip block, by `inventory_hash`, without propagating
+    /// it; penalizes the peer that sent it if the block is invalid
+    DiscardBlock([u8; 32], bool),
+    /// Disconnect a peer and refuse its connections
+    BanPeer(PeerId),
+    /// Limit bytes uploaded to each peer per second (0 = unlimited)
+    SetPeerUploadLimit(u64),
+    /// Ask a peer for headers or blocks
+    RequestSync(PeerId, SyncRequest),
+    /// Answer the inbound sync request with the given id
+    RespondSync(u64, SyncResponse),
 }
 
 /// Events emitted by the network
 #[derive(Debug, Clone)]
 pub enum NetworkEvent {
-    BlockReceived(Vec<u8>),
-    TransactionReceived(Vec<u8>),
+    /// A gossiped block and the peer that relayed it
+    BlockReceived(Vec<u8>, PeerId),
+    /// A gossiped forge transaction and the peer that relayed it
+    TransactionReceived(Vec<u8>, PeerId),
+    /// A gossiped `HeaderAnnouncement` and the peer that relayed it
+    HeaderReceived(Vec<u8>, PeerId),
+    /// Gossiped watchtower evidence and the peer that relayed it
+    EvidenceReceived(Vec<u8>, PeerId),
     PeerConnected(PeerId),
     PeerDisconnected(PeerId),
     PeerList(Vec<PeerId>),
+    PublishQueueStats(PublishQueueStats),
+    PeerPreferences(PeerId, RelayPreferences),
+    /// A peer rejected an item we relayed
+    RejectReceived(PeerId, RejectMessage),
+    /// A peer asked for headers or blocks; answer with
+    /// `NetworkCommand::RespondSync` and the given id
+    SyncRequested(PeerId, u64, SyncRequest),
+    /// A peer answered our sync request
+    SyncResponseReceived(PeerId, SyncResponse),
+    /// A sync request to a peer failed or timed out
+    SyncFailed(PeerId),
+}
+
+/// Counters describing the gossip publish retry queue
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PublishQueueStats {
+    /// Announcements currently waiting for peers
+    pub pending: usize,
+    /// Announcements that were queued because publishing failed
+    pub queued: u64,
+    /// Announcements successfully re-published from the queue
+    pub republished: u64,
+    /// Announcements dropped because they outlived the TTL
+    pub dropped_expired: u64,
+    /// Announcements dropped because the queue was full
+    pub dropped_overflow: u64,
+    /// Announcements dropped because re-publishing failed for a reason
+    /// other than missing peers
+    pub dropped_error: u64,
+    /// Announcements dropped because by the retry no subscribed peer
+    /// accepted them or every one already had them
+    pub dropped_unwanted: u64,
+}
+
+/// What became of one attempt to re-publish a queued announcement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepublishOutcome {
+    /// Published, or already published before
+    Published,
+    /// No peers to publish to yet; stays queued
+    Retry,
+    /// No peer wants it any more; dropped
+    Unwanted,
+    /// Failed for good; dropped
+    Failed,
+}
+
+/// An announcement waiting to be re-published
+#[derive(Debug, Clone)]
+struct PendingPublish {
+    topic: String,
+    data: Vec<u8>,
+    queued_at: Instant,
+}
+
+/// Bounded FIFO of announcements that could not be published
+/// (e.g. `InsufficientPeers` at startup)
+#[derive(Debug)]
+pub struct PublishRetryQueue {
+    entries: VecDeque<PendingPublish>,
+    capacity: usize,
+    ttl: Duration,
+    stats: PublishQueueStats,
+}
+
+impl PublishRetryQueue {
+    /// Create a new retry queue
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+            ttl,
+            stats: PublishQueueStats::default(),
+        }
+    }
+
+    /// Queue an announcement, evicting the oldest one if the queue is full
+    pub fn push(&mut self, topic: &str, data: Vec<u8>) {
+        self.push_at(topic, data, Instant::now());
+    }
+
+    fn push_at(&mut self, topic: &str, data: Vec<u8>, queued_at: Instant) {
+        if self.capacity == 0 {
+            self.stats.dropped_overflow += 1;
+            return;
+        }
+        while self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+            self.stats.dropped_overflow += 1;
+        }
+        self.entries.push_back(PendingPublish {
+            topic: topic.to_string(),
+            data,
+            queued_at,
+        });
+        self.stats.queued += 1;
+    }
+
+    /// Drop announcements older than the TTL, returning how many were dropped
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.entries.len();
+        let ttl = self.ttl;
+        self.entries
+            .retain(|entry| now.saturating_duration_since(entry.queued_at) < ttl);
+        let dropped = before - self.entries.len();
+        self.stats.dropped_expired += dropped as u64;
+        dropped
+    }
+
+    /// Try to re-publish every queued announcement in order.
+    ///
+    /// Entries `publish` asks to retry stay queued (in their original
+    /// order); failed ones are dropped. Returns how many were published.
+    pub fn flush<F>(&mut self, mut publish: F) -> usize
+    where
+        F: FnMut(&str, &[u8]) -> RepublishOutcome,
+    {
+        self.expire(Instant::now());
+
+        let mut republished = 0;
+        let mut remaining = VecDeque::with_capacity(self.entries.len());
+        while let Some(entry) = self.entries.pop_front() {
+            match publish(&entry.topic, &entry.data) {
+                RepublishOutcome::Published => republished += 1,
+                RepublishOutcome::Retry => remaining.push_back(entry),
+                RepublishOutcome::Unwanted => self.stats.dropped_unwanted += 1,
+                RepublishOutcome::Failed => self.stats.dropped_error += 1,
+            }
+        }
+        self.entries = remaining;
+        self.stats.republished += republished as u64;
+        republished
+    }
+
+    /// Number of announcements waiting
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Snapshot of queue counters
+    pub fn stats(&self) -> PublishQueueStats {
+        PublishQueueStats {
+            pending: self.entries.len(),
+            ..self.stats.clone()
+        }
+    }
 }
 
 impl NetworkManager {
@@ -51,6 +466,35 @@ impl NetworkManager {
     pub async fn new(
         listen_addr: Multiaddr,
         bootstrap_peers: Vec<Multiaddr>,
+    ) -> Result<(Self, mpsc::Sender<NetworkCommand>, mpsc::Receiver<NetworkEvent>), Box<dyn Error>> {
+        Self::with_preferences(listen_addr, bootstrap_peers, RelayPreferences::default()).await
+    }
+
+    /// Create a new mainnet network manager advertising the given relay
+    /// preferences
+    pub async fn with_preferences(
+        listen_addr: Multiaddr,
+        bootstrap_peers: Vec<Multiaddr>,
+        local_preferences: RelayPreferences,
+    ) -> Result<(Self, mpsc::Sender<NetworkCommand>, mpsc::Receiver<NetworkEvent>), Box<dyn Error>> {
+        Self::for_network(
+            listen_addr,
+            bootstrap_peers,
+            local_preferences,
+            &GossipSettings::default(),
+            &NetworkParams::mainnet(),
+        )
+        .await
+    }
+
+    /// Create a network manager for a network, using its connection magic
+    /// and gossip topics
+    pub async fn for_network(
+        listen_addr: Multiaddr,
+        bootstrap_peers: Vec<Multiaddr>,
+        local_preferences: RelayPreferences,
+        gossip: &GossipSettings,
+        params: &NetworkParams,
     ) -> Result<(Self, mpsc::Sender<NetworkCommand>, mpsc::Receiver<NetworkEvent>), Box<dyn Error>> {
         // Generate keypair
         let local_key = libp2p::identity::Keypair::generate_ed25519();
@@ -66,22 +510,22 @@ impl NetworkManager {
             .boxed();
 
         // Configure Gossipsub
-        let gossipsub_config = gossipsub::ConfigBuilder::default()
-            .heartbeat_interval(Duration::from_secs(10))
-            .validation_mode(gossipsub::ValidationMode::Strict)
-            .build()
-            .expect("Valid gossipsub config");
+        let gossipsub_config = gossip.gossipsub_config(local_preferences.max_message_size as usize)?;
         
         let mut gossipsub = gossipsub::Behaviour::new(
             gossipsub::MessageAuthenticity::Signed(local_key.clone()),
             gossipsub_config,
         )?;
+        gossip.apply_scoring(&mut gossipsub)?;
 
         // Subscribe to topics
-        let block_topic = gossipsub::IdentTopic::new("excalibur-blocks");
-        let tx_topic = gossipsub::IdentTopic::new("excalibur-transactions");
-        gossipsub.subscribe(&block_topic)?;
-        gossipsub.subscribe(&tx_topic)?;
+        let topics = &params.chain.topics;
+        gossipsub.subscribe(&gossipsub::IdentTopic::new(topics.wire(BLOCK_TOPIC)))?;
+        gossipsub.subscribe(&gossipsub::IdentTopic::new(topics.wire(HEADER_TOPIC)))?;
+        gossipsub.subscribe(&gossipsub::IdentTopic::new(topics.wire(EVIDENCE_TOPIC)))?;
+        if !local_preferences.no_forge_relay {
+            gossipsub.subscribe(&gossipsub::IdentTopic::new(topics.wire(TRANSACTION_TOPIC)))?;
+        }
 
         // Configure Kademlia
         let store = kad::store::MemoryStore::new(local_peer_id);
@@ -101,16 +545,31 @@ impl NetworkManager {
         }
 
         // Configure identify
-        let identify = identify::Behaviour::new(identify::Config::new(
-            "/excalibur/1.0.0".to_string(),
-            local_key.public(),
-        ));
+        let identify = identify::Behaviour::new(
+            identify::Config::new(handshake_protocol(params.magic), local_key.public())
+                .with_agent_version(local_preferences.to_agent_version()),
+        );
+
+        // Configure reject notifications
+        let reject = request_response::json::Behaviour::new(
+            [(StreamProtocol::new(REJECT_PROTOCOL), request_response::ProtocolSupport::Full)],
+            request_response::Config::default(),
+        );
+
+        // Configure header and block downloads
+        let sync = request_response::json::Behaviour::new(
+            [(StreamProtocol::new(SYNC_PROTOCOL), request_response::ProtocolSupport::Full)],
+            request_response::Config::default(),
+        );
 
         // Create behaviour
         let behaviour = ExcaliburBehaviour {
+            filter: ConnectionFilter::new(Arc::new(PeerFilter::default())),
             gossipsub,
             kad,
             identify,
+            reject,
+            sync,
         };
 
         // Create swarm
@@ -132,6 +591,25 @@ impl NetworkManager {
             swarm,
             command_receiver,
             event_sender,
+            publish_queue: PublishRetryQueue::new(PUBLISH_QUEUE_CAPACITY, PUBLISH_QUEUE_TTL),
+            local_preferences,
+            peer_preferences: HashMap::new(),
+            peer_services: Arc::new(PeerServices::new()),
+            reject_limiter: RejectLimiter::new(),
+            bandwidth: Arc::new(BandwidthTracker::new()),
+            connections: Arc::new(ConnectionStats::new()),
+            upload_limiter: UploadLimiter::new(0),
+            reconnect: Arc::new(ReconnectSchedule::default()),
+            peers: Arc::new(PeerTable::new()),
+            magic: params.magic,
+            topics: params.chain.topics.clone(),
+            gossip: gossip.clone(),
+            seen: Arc::new(SeenMessages::default()),
+            inventory: Arc::new(PeerInventory::default()),
+            hold_blocks: false,
+            held_blocks: HeldBlocks::default(),
+            sync_responses: HashMap::new(),
+            next_sync_request: 0,
         };
 
         Ok((manager, command_sender, event_receiver))
@@ -139,6 +617,9 @@ impl NetworkManager {
 
     /// Run the network manager
     pub async fn run(mut self) {
+        let mut retry_interval = tokio::time::interval(PUBLISH_RETRY_INTERVAL);
+        let mut reconnect_interval = tokio::time::interval(RECONNECT_INTERVAL);
+        let mut score_interval = tokio::time::interval(self.gossip.heartbeat_interval());
         loop {
             tokio::select! {
                 // Handle incoming commands
@@ -150,70 +631,614 @@ impl NetworkManager {
                 event = self.swarm.select_next_some() => {
                     self.handle_swarm_event(event).await;
                 }
+
+                // Periodically retry queued announcements
+                _ = retry_interval.tick() => {
+                    self.flush_publish_queue();
+                }
+
+                // Redial dropped peers whose backoff has elapsed
+                _ = reconnect_interval.tick() => {
+                    for (peer_id, address) in self.reconnect.due(Instant::now()) {
+                        tracing::debug!("Redialing peer {} at {}", peer_id, address);
+                        if let Err(e) = self.swarm.dial(address) {
+                            tracing::debug!("Failed to redial peer {}: {:?}", peer_id, e);
+                        }
+                    }
+                }
+
+                // Ban peers whose gossip score fell too low
+                _ = score_interval.tick() => {
+                    self.ban_low_scoring_peers();
+                }
+            }
+        }
+    }
+
+    /// Publish to a gossip topic, queueing the message if no peers are available
+    fn publish(&mut self, topic: &str, data: Vec<u8>) {
+        if self.try_publish(topic, &data) == RepublishOutcome::Retry {
+            tracing::debug!("No peers for {}, queueing announcement for retry", topic);
+            self.publish_queue.push(topic, data);
+        }
+    }
+
+    /// Publish to a gossip topic unless no subscribed peer wants the
+    /// message, recording what was sent and to whom. First publishes and
+    /// retries from the queue both go through here.
+    fn try_publish(&mut self, topic: &str, data: &[u8]) -> RepublishOutcome {
+        if !self.relay_wanted(topic, data.len()) {
+            tracing::debug!(
+                "Skipping {} announcement ({} bytes): no subscribed peer accepts it",
+                topic,
+                data.len()
+            );
+            return RepublishOutcome::Unwanted;
+        }
+
+        let hash = inventory_hash(data);
+        let recipients = self.subscribed_peers(topic);
+        if !self.inventory.should_relay(&recipients, &hash) {
+            tracing::debug!(
+                "Skipping {} announcement {}: every subscribed peer already has it",
+                topic,
+                hex::encode(hash)
+            );
+            return RepublishOutcome::Unwanted;
+        }
+
+        let ident = gossipsub::IdentTopic::new(self.topics.wire(topic));
+        match self.swarm.behaviour_mut().gossipsub.publish(ident, data.to_vec()) {
+            Ok(message_id) => {
+                self.seen.insert(&message_id.0, unix_now());
+                for peer_id in recipients {
+                    self.inventory.mark_known(peer_id, &hash);
+                }
+                if let Some(kind) = MessageKind::for_topic(topic) {
+                    self.bandwidth.record_sent(None, kind, data.len());
+                }
+                RepublishOutcome::Published
+            }
+            Err(gossipsub::PublishError::Duplicate) => RepublishOutcome::Published,
+            Err(gossipsub::PublishError::InsufficientPeers) => RepublishOutcome::Retry,
+            Err(e) => {
+                tracing::error!("Failed to publish to {}: {:?}", topic, e);
+                RepublishOutcome::Failed
             }
         }
     }
 
+    /// Whether any peer subscribed to `topic` accepts a message of `len` bytes.
+    ///
+    /// Returns true when no peer is subscribed yet so the announcement can be
+    /// queued for later.
+    fn relay_wanted(&self, topic: &str, len: usize) -> bool {
+        let subscribed = self.subscribed_peers(topic);
+        if subscribed.is_empty() {
+            return true;
+        }
+
+        subscribed.iter().any(|peer_id| {
+            self.peer_preferences
+                .get(peer_id)
+                .copied()
+                .unwrap_or_default()
+                .accepts(topic, len)
+        })
+    }
+
+    /// Peers subscribed to a gossip topic
+    fn subscribed_peers(&self, topic: &str) -> Vec<PeerId> {
+        let topic_hash = gossipsub::IdentTopic::new(self.topics.wire(topic)).hash();
+        self.swarm
+            .behaviour()
+            .gossipsub
+            .all_peers()
+            .filter(|(_, topics)| topics.contains(&&topic_hash))
+            .map(|(peer_id, _)| *peer_id)
+            .collect()
+    }
+
+    /// Report the node's decision on a held gossip block to gossipsub, which
+    /// propagates accepted blocks and penalizes the sender of rejected ones
+    fn release_block(&mut self, hash: &[u8; 32], acceptance: gossipsub::MessageAcceptance) {
+        if let Some((message_id, source)) = self.held_blocks.release(hash) {
+            let _ = self
+                .swarm
+                .behaviour_mut()
+                .gossipsub
+                .report_message_validation_result(&message_id, &source, acceptance);
+        }
+    }
+
+    /// Send a reject to the peer that relayed an item, subject to rate limits
+    fn send_reject(&mut self, peer_id: PeerId, message: RejectMessage) {
+        if !self.swarm.is_connected(&peer_id) {
+            return;
+        }
+        if !self.reject_limiter.allow(&peer_id, &message.item_hash, Instant::now()) {
+            tracing::trace!("Suppressing reject to {} (rate limited or duplicate)", peer_id);
+            return;
+        }
+        let len = serde_json::to_vec(&message).map(|bytes| bytes.len()).unwrap_or_default();
+        if !self.upload_limiter.allow(&peer_id, len, Instant::now()) {
+            tracing::trace!("Suppressing reject to {} (upload limit)", peer_id);
+            self.bandwidth.record_throttled(&peer_id);
+            return;
+        }
+        self.bandwidth.record_sent(Some(&peer_id), MessageKind::Reject, len);
+        tracing::debug!(
+            "Rejecting {:?} {} from {}: {:?} {}",
+            message.item,
+            hex::encode(message.item_hash),
+            peer_id,
+            message.code,
+            message.reason
+        );
+        self.swarm.behaviour_mut().reject.send_request(&peer_id, message);
+    }
+
+    /// Relay preferences declared by a connected peer
+    pub fn peer_preferences(&self, peer_id: &PeerId) -> Option<&RelayPreferences> {
+        self.peer_preferences.get(peer_id)
+    }
+
+    /// Relay preferences this node advertises
+    pub fn local_preferences(&self) -> &RelayPreferences {
+        &self.local_preferences
+    }
+
+    /// Services advertised by connected peers, for `getpeerroles`
+    pub fn peer_services(&self) -> Arc<PeerServices> {
+        Arc::clone(&self.peer_services)
+    }
+
+    /// Shared bandwidth counters, for `getnettotals`
+    pub fn bandwidth(&self) -> Arc<BandwidthTracker> {
+        Arc::clone(&self.bandwidth)
+    }
+
+    /// Open connections and listen addresses, for `getnetworkinfo`
+    pub fn connections(&self) -> Arc<ConnectionStats> {
+        Arc::clone(&self.connections)
+    }
+
+    /// Limit bytes uploaded to each peer (0 = unlimited)
+    pub fn set_peer_upload_limit(&mut self, bytes_per_sec: u64) {
+        self.upload_limiter = UploadLimiter::new(bytes_per_sec);
+        self.bandwidth.set_peer_upload_limit(bytes_per_sec);
+    }
+
+    /// Lifecycle state of every known peer, shared with sync and RPC
+    pub fn peer_table(&self) -> Arc<PeerTable> {
+        Arc::clone(&self.peers)
+    }
+
+    /// Peers waiting to be redialed, shared with the RPC server
+    pub fn reconnects(&self) -> Arc<ReconnectSchedule> {
+        Arc::clone(&self.reconnect)
+    }
+
+    /// Replace the redial schedule, e.g. with configured backoff limits.
+    /// Call before handing out `reconnects()`.
+    pub fn set_reconnect_schedule(&mut self, schedule: ReconnectSchedule) {
+        self.reconnect = Arc::new(schedule);
+    }
+
+    /// Gossip message ids seen recently, to be saved on shutdown
+    pub fn seen_messages(&self) -> Arc<SeenMessages> {
+        Arc::clone(&self.seen)
+    }
+
+    /// Replace the seen message set, e.g. with one loaded from disk.
+    /// Call before handing out `seen_messages()`.
+    pub fn set_seen_messages(&mut self, seen: SeenMessages) {
+        self.seen = Arc::new(seen);
+    }
+
+    /// Items each peer is known to have
+    pub fn inventory(&self) -> Arc<PeerInventory> {
+        Arc::clone(&self.inventory)
+    }
+
+    /// Remember at most `capacity` known items per peer (0 disables
+    /// tracking). Call before handing out `inventory()`.
+    pub fn set_known_inventory(&mut self, capacity: usize) {
+        self.inventory = Arc::new(PeerInventory::new(capacity));
+    }
+
+    /// Hold gossiped blocks until the node sends `RelayBlock` or
+    /// `DiscardBlock` for them, instead of propagating them on receipt
+    pub fn set_hold_blocks(&mut self, hold: bool) {
+        self.hold_blocks = hold;
+    }
+
+    /// Connection allow and deny rules, shared with the RPC server
+    pub fn peer_filter(&self) -> Arc<PeerFilter> {
+        self.swarm.behaviour().filter.filter()
+    }
+
+    /// Replace the connection rules, e.g. with configured ones. Call before
+    /// handing out `peer_filter()`.
+    pub fn set_peer_filter(&mut self, filter: PeerFilter) {
+        self.swarm.behaviour_mut().filter = ConnectionFilter::new(Arc::new(filter));
+    }
+
+    /// Disconnect a peer and refuse it at the gossip and connection layers
+    fn ban_peer(&mut self, peer_id: PeerId) {
+        self.reconnect.suppress(peer_id);
+        let _ = self.peers.transition(peer_id, PeerState::Banned);
+        self.swarm.behaviour_mut().gossipsub.blacklist_peer(&peer_id);
+        self.swarm.disconnect_peer_id(peer_id).ok();
+    }
+
+    /// Ban peers whose gossipsub score reached the configured ban threshold
+    fn ban_low_scoring_peers(&mut self) {
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+        let low_scoring: Vec<(PeerId, f64)> = gossipsub
+            .all_peers()
+            .filter_map(|(peer_id, _)| Some((*peer_id, gossipsub.peer_score(peer_id)?)))
+            .filter(|(peer_id, score)| self.gossip.should_ban(*score) && !self.peers.is_banned(peer_id))
+            .collect();
+        for (peer_id, score) in low_scoring {
+            tracing::info!("Banning peer {}: gossip score {:.1}", peer_id, score);
+            self.ban_peer(peer_id);
+        }
+    }
+
+    /// Re-publish queued announcements now that peers may be available
+    fn flush_publish_queue(&mut self) {
+        if self.publish_queue.is_empty() {
+            return;
+        }
+
+        // Retries get the same relay checks and accounting as first
+        // publishes; the queue is set aside meanwhile, and nothing is
+        // queued while it is
+        let mut queue = std::mem::replace(&mut self.publish_queue, PublishRetryQueue::new(0, Duration::ZERO));
+        let republished = queue.flush(|topic, data| self.try_publish(topic, data));
+        self.publish_queue = queue;
+
+        if republished > 0 {
+            tracing::info!(
+                "Re-published {} queued announcements ({} still pending)",
+                republished,
+                self.publish_queue.len()
+            );
+        }
+    }
+
     async fn handle_command(&mut self, command: NetworkCommand) {
         match command {
             NetworkCommand::PublishBlock(data) => {
-                let topic = gossipsub::IdentTopic::new("excalibur-blocks");
-                if let Err(e) = self.swarm.behaviour_mut().gossipsub.publish(topic, data) {
-                    tracing::error!("Failed to publish block: {:?}", e);
-                }
+                self.publish(BLOCK_TOPIC, data);
             }
             NetworkCommand::PublishTransaction(data) => {
-                let topic = gossipsub::IdentTopic::new("excalibur-transactions");
-                if let Err(e) = self.swarm.behaviour_mut().gossipsub.publish(topic, data) {
-                    tracing::error!("Failed to publish transaction: {:?}", e);
-                }
+                self.publish(TRANSACTION_TOPIC, data);
+            }
+            NetworkCommand::PublishHeader(data) => {
+                self.publish(HEADER_TOPIC, data);
+            }
+            NetworkCommand::PublishEvidence(data) => {
+                self.publish(EVIDENCE_TOPIC, data);
             }
             NetworkCommand::ConnectPeer(addr) => {
+                let peer_id = addr.iter().find_map(|protocol| match protocol {
+                    libp2p::multiaddr::Protocol::P2p(peer_id) => Some(peer_id),
+                    _ => None,
+                });
+                if let Some(peer_id) = peer_id {
+                    if self.peers.is_banned(&peer_id) {
+                        tracing::debug!("Not dialing banned peer {}", peer_id);
+                        return;
+                    }
+                    self.peers.discovered(peer_id);
+                    let _ = self.peers.transition(peer_id, PeerState::Dialing);
+                }
                 if let Err(e) = self.swarm.dial(addr) {
                     tracing::error!("Failed to dial peer: {:?}", e);
+                    if let Some(peer_id) = peer_id {
+                        let _ = self.peers.transition(peer_id, PeerState::Discovered);
+                    }
                 }
             }
             NetworkCommand::DisconnectPeer(peer_id) => {
+                self.reconnect.suppress(peer_id);
+                let _ = self.peers.transition(peer_id, PeerState::Draining);
                 self.swarm.disconnect_peer_id(peer_id).ok();
             }
+            NetworkCommand::BanPeer(peer_id) => {
+                tracing::info!("Banning peer {}", peer_id);
+                self.ban_peer(peer_id);
+            }
+            NetworkCommand::SetPeerUploadLimit(bytes_per_sec) => {
+                self.set_peer_upload_limit(bytes_per_sec);
+            }
             NetworkCommand::GetPeers => {
                 let peers: Vec<PeerId> = self.swarm.connected_peers().cloned().collect();
                 let _ = self.event_sender.send(NetworkEvent::PeerList(peers)).await;
             }
+            NetworkCommand::GetPublishQueueStats => {
+                let stats = self.publish_queue.stats();
+                let _ = self.event_sender.send(NetworkEvent::PublishQueueStats(stats)).await;
+            }
+            NetworkCommand::RejectItem(peer_id, message) => {
+                self.send_reject(peer_id, message);
+            }
+            NetworkCommand::RevokeBlock(hash, reason) => {
+                let peers = self.subscribed_peers(BLOCK_TOPIC);
+                tracing::info!("Revoking block {} to {} peers: {}", hex::encode(hash), peers.len(), reason);
+                for peer_id in peers {
+                    let message = RejectMessage::new(RejectedItem::Block, hash, RejectCode::Invalid, &reason);
+                    self.send_reject(peer_id, message);
+                }
+            }
+            NetworkCommand::RelayBlock(hash) => {
+                self.release_block(&hash, gossipsub::MessageAcceptance::Accept);
+            }
+            NetworkCommand::DiscardBlock(hash, invalid) => {
+                let acceptance = if invalid {
+                    gossipsub::MessageAcceptance::Reject
+                } else {
+                    gossipsub::MessageAcceptance::Ignore
+                };
+                self.release_block(&hash, acceptance);
+            }
+            NetworkCommand::RequestSync(peer_id, request) => {
+                let len = serde_json::to_vec(&request).map(|bytes| bytes.len()).unwrap_or_default();
+                self.bandwidth.record_sent(Some(&peer_id), MessageKind::Sync, len);
+                self.swarm.behaviour_mut().sync.send_request(&peer_id, request);
+            }
+            NetworkCommand::RespondSync(id, response) => {
+                let Some((peer_id, channel)) = self.sync_responses.remove(&id) else {
+                    tracing::debug!("No pending sync request {}", id);
+                    return;
+                };
+                let encoded = match serde_json::value::to_raw_value(&response) {
+                    Ok(encoded) => encoded,
+                    Err(e) => {
+                        tracing::warn!("Failed to encode sync response {}: {}", id, e);
+                        return;
+                    }
+                };
+                // Dropping the channel refuses the request; the peer sees
+                // a failed request and asks someone else
+                let len = encoded.get().len();
+                if !self.upload_limiter.allow(&peer_id, len, Instant::now()) {
+                    tracing::debug!("Refusing sync request {} from {} (upload limit)", id, peer_id);
+                    self.bandwidth.record_throttled(&peer_id);
+                    return;
+                }
+                self.bandwidth.record_sent(Some(&peer_id), MessageKind::Sync, len);
+                if self.swarm.behaviour_mut().sync.send_response(channel, encoded).is_err() {
+                    tracing::debug!("Sync request {} was dropped before it was answered", id);
+                }
+            }
         }
     }
 
     async fn handle_swarm_event(&mut self, event: SwarmEvent<ExcaliburBehaviourEvent>) {
         match event {
             SwarmEvent::Behaviour(ExcaliburBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                propagation_source,
+                message_id,
                 message,
-                ..
             })) => {
-                let topic = message.topic.as_str();
-                if topic == "excalibur-blocks" {
+                // The relaying peer and the original publisher both have it
+                let hash = inventory_hash(&message.data);
+                self.inventory.mark_known(propagation_source, &hash);
+                if let Some(source) = message.source {
+                    self.inventory.mark_known(source, &hash);
+                }
+
+                // Replays of messages seen before a restart are neither
+                // processed nor propagated
+                let fresh = self.seen.insert(&message_id.0, unix_now());
+                // Another chain's topics match none of ours
+                let topic = self.topics.topic(message.topic.as_str()).unwrap_or_default();
+                if fresh && self.hold_blocks && topic == BLOCK_TOPIC {
+                    // Blocks propagate once the node decides to relay them
+                    if let Some((dropped, source)) = self.held_blocks.hold(hash, message_id, propagation_source) {
+                        let _ = self.swarm.behaviour_mut().gossipsub.report_message_validation_result(
+                            &dropped,
+                            &source,
+                            gossipsub::MessageAcceptance::Ignore,
+                        );
+                    }
+                } else {
+                    let acceptance = if fresh {
+                        gossipsub::MessageAcceptance::Accept
+                    } else {
+                        gossipsub::MessageAcceptance::Ignore
+                    };
+                    let _ = self.swarm.behaviour_mut().gossipsub.report_message_validation_result(
+                        &message_id,
+                        &propagation_source,
+                        acceptance,
+                    );
+                    if !fresh {
+                        tracing::trace!(
+                            "Ignoring already seen gossip {} from {}",
+                            hex::encode(&message_id.0),
+                            propagation_source
+                        );
+                        return;
+                    }
+                }
+
+                if let Some(kind) = MessageKind::for_topic(topic) {
+                    self.bandwidth.record_recv(&propagation_source, kind, message.data.len());
+                }
+                if topic == BLOCK_TOPIC {
+                    let _ = self.event_sender
+                        .send(NetworkEvent::BlockReceived(message.data, propagation_source))
+                        .await;
+                } else if topic == TRANSACTION_TOPIC {
+                    let _ = self.event_sender
+                        .send(NetworkEvent::TransactionReceived(message.data, propagation_source))
+                        .await;
+                } else if topic == HEADER_TOPIC {
                     let _ = self.event_sender
-                        .send(NetworkEvent::BlockReceived(message.data))
+                        .send(NetworkEvent::HeaderReceived(message.data, propagation_source))
                         .await;
-                } else if topic == "excalibur-transactions" {
+                } else if topic == EVIDENCE_TOPIC {
                     let _ = self.event_sender
-                        .send(NetworkEvent::TransactionReceived(message.data))
+                        .send(NetworkEvent::EvidenceReceived(message.data, propagation_source))
                         .await;
                 }
             }
-            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+            SwarmEvent::Behaviour(ExcaliburBehaviourEvent::Reject(request_response::Event::Message {
+                peer,
+                message: request_response::Message::Request { request, channel, .. },
+            })) => {
+                tracing::warn!(
+                    "Peer {} rejected our {:?} {}: {:?} {}",
+                    peer,
+                    request.item,
+                    hex::encode(request.item_hash),
+                    request.code,
+                    request.reason
+                );
+                let len = serde_json::to_vec(&request).map(|bytes| bytes.len()).unwrap_or_default();
+                self.bandwidth.record_recv(&peer, MessageKind::Reject, len);
+                let _ = self.swarm.behaviour_mut().reject.send_response(channel, ());
+                let _ = self.event_sender
+                    .send(NetworkEvent::RejectReceived(peer, request))
+                    .await;
+            }
+            SwarmEvent::Behaviour(ExcaliburBehaviourEvent::Sync(request_response::Event::Message {
+                peer,
+                message: request_response::Message::Request { request, channel, .. },
+            })) => {
+                let id = self.next_sync_request;
+                self.next_sync_request += 1;
+                self.sync_responses.insert(id, (peer, channel));
+                let _ = self.event_sender
+                    .send(NetworkEvent::SyncRequested(peer, id, request))
+                    .await;
+            }
+            SwarmEvent::Behaviour(ExcaliburBehaviourEvent::Sync(request_response::Event::Message {
+                peer,
+                message: request_response::Message::Response { response, .. },
+            })) => {
+                self.bandwidth.record_recv(&peer, MessageKind::Sync, response.get().len());
+                match serde_json::from_str::<SyncResponse>(response.get()) {
+                    Ok(response) => {
+                        let _ = self.event_sender
+                            .send(NetworkEvent::SyncResponseReceived(peer, response))
+                            .await;
+                    }
+                    Err(e) => {
+                        tracing::debug!("Malformed sync response from {}: {}", peer, e);
+                        let _ = self.event_sender.send(NetworkEvent::SyncFailed(peer)).await;
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(ExcaliburBehaviourEvent::Sync(request_response::Event::OutboundFailure {
+                peer,
+                error,
+                ..
+            })) => {
+                tracing::debug!("Sync request to {} failed: {}", peer, error);
+                let _ = self.event_sender.send(NetworkEvent::SyncFailed(peer)).await;
+            }
+            SwarmEvent::Behaviour(ExcaliburBehaviourEvent::Sync(request_response::Event::InboundFailure {
+                peer,
+                error,
+                ..
+            })) => {
+                tracing::debug!("Sync request from {} failed: {}", peer, error);
+                self.sync_responses.retain(|_, (_, channel)| channel.is_open());
+            }
+            SwarmEvent::Behaviour(ExcaliburBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed {
+                peer_id,
+                topic,
+            })) => {
+                tracing::debug!("Peer {} subscribed to {}", peer_id, topic);
+                self.flush_publish_queue();
+            }
+            SwarmEvent::Behaviour(ExcaliburBehaviourEvent::Identify(identify::Event::Received {
+                peer_id,
+                info,
+            })) => {
+                if protocol_magic(&info.protocol_version) != Some(self.magic) {
+                    tracing::warn!(
+                        "Disconnecting peer {}: its handshake {:?} does not carry this node's network magic {} \
+                         (is it on another network, or listening on another network's port?)",
+                        peer_id,
+                        info.protocol_version,
+                        hex::encode(self.magic)
+                    );
+                    self.reconnect.suppress(peer_id);
+                    let _ = self.peers.transition(peer_id, PeerState::Banned);
+                    self.swarm.disconnect_peer_id(peer_id).ok();
+                    return;
+                }
+                let preferences = RelayPreferences::from_agent_version(&info.agent_version);
+                tracing::debug!("Peer {} relay preferences: {:?}", peer_id, preferences);
+                self.peer_preferences.insert(peer_id, preferences);
+                self.peer_services.set(peer_id, preferences.services);
+                if self.peers.state(&peer_id) == Some(PeerState::Handshaking) {
+                    let _ = self.peers.transition(peer_id, PeerState::Ready);
+                }
+                let _ = self.event_sender
+                    .send(NetworkEvent::PeerPreferences(peer_id, preferences))
+                    .await;
+            }
+            SwarmEvent::Behaviour(ExcaliburBehaviourEvent::Kad(kad::Event::RoutingUpdated { peer, .. })) => {
+                self.peers.discovered(peer);
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                // Counted before the ban check: the close is counted too
+                self.connections.opened(endpoint.is_dialer());
+                if self.peers.is_banned(&peer_id) {
+                    tracing::debug!("Refusing connection from banned peer {}", peer_id);
+                    self.swarm.disconnect_peer_id(peer_id).ok();
+                    return;
+                }
                 tracing::debug!("Connected to peer: {}", peer_id);
+                self.reconnect.connected(&peer_id);
+                if !self.peers.state(&peer_id).is_some_and(PeerState::is_connected) {
+                    let _ = self.peers.transition(peer_id, PeerState::Handshaking);
+                }
                 let _ = self.event_sender
                     .send(NetworkEvent::PeerConnected(peer_id))
                     .await;
             }
-            SwarmEvent::ConnectionClosed { peer_id, .. } => {
+            SwarmEvent::ConnectionClosed { peer_id, endpoint, num_established, .. } => {
                 tracing::debug!("Disconnected from peer: {}", peer_id);
+                self.connections.closed(endpoint.is_dialer());
+                if num_established == 0 {
+                    if !self.peers.is_banned(&peer_id) {
+                        let _ = self.peers.transition(peer_id, PeerState::Discovered);
+                    }
+                    if endpoint.is_dialer() {
+                        self.reconnect
+                            .disconnected(peer_id, endpoint.get_remote_address().clone(), Instant::now());
+                    }
+                    self.peer_preferences.remove(&peer_id);
+                    self.peer_services.remove(&peer_id);
+                    self.reject_limiter.remove_peer(&peer_id);
+                    self.upload_limiter.remove_peer(&peer_id);
+                    self.bandwidth.remove_peer(&peer_id);
+                    self.inventory.remove(&peer_id);
+                }
                 let _ = self.event_sender
                     .send(NetworkEvent::PeerDisconnected(peer_id))
                     .await;
             }
+            SwarmEvent::OutgoingConnectionError { peer_id: Some(peer_id), error, .. } => {
+                tracing::debug!("Failed to connect to peer {}: {}", peer_id, error);
+                if self.peers.state(&peer_id) == Some(PeerState::Dialing) {
+                    let _ = self.peers.transition(peer_id, PeerState::Discovered);
+                }
+            }
             SwarmEvent::NewListenAddr { address, .. } => {
                 tracing::info!("Listening on {}", address);
+                self.connections.add_listen_addr(address);
+            }
+            SwarmEvent::ExpiredListenAddr { address, .. } => {
+                self.connections.remove_listen_addr(&address);
             }
             _ => {}
         }
@@ -230,4 +1255,112 @@ mod tests {
         let result = NetworkManager::new(listen_addr, vec![]).await;
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_relay_preferences_roundtrip() {
+        let prefs = RelayPreferences {
+            no_forge_relay: true,
+            compact_blocks: false,
+            services: ServiceFlags::FULL | ServiceFlags::ARCHIVAL | ServiceFlags::FILTERS,
+            max_message_size: 1024,
+        };
+        let agent = prefs.to_agent_version();
+        assert_eq!(node_version(), 10000);
+        assert_eq!(subversion(), "/excalibur-node:1.0.0/");
+        assert!(agent.starts_with(AGENT_VERSION));
+        assert_eq!(RelayPreferences::from_agent_version(&agent), prefs);
+
+        // Peers without a services bitfield are full but never archival
+        let legacy = RelayPreferences::from_agent_version("excalibur-node/1.0.0;prefs=04/1024");
+        assert_eq!(legacy.services, ServiceFlags::FULL | ServiceFlags::FILTERS);
+
+        // Unknown or foreign agents get the defaults
+        assert_eq!(
+            RelayPreferences::from_agent_version("rust-libp2p/0.44"),
+            RelayPreferences::default()
+        );
+        assert_eq!(
+            RelayPreferences::from_agent_version("excalibur-node/1.0.0;prefs=zz/1"),
+            RelayPreferences::default()
+        );
+    }
+
+    #[test]
+    fn test_protocol_magic() {
+        let testnet = crate::params::NetworkParams::testnet().magic;
+        assert_eq!(protocol_magic(&handshake_protocol(testnet)), Some(testnet));
+        assert_eq!(protocol_magic(PROTOCOL_VERSION), Some(MAINNET_MAGIC));
+        assert_eq!(protocol_magic("/excalibur/1.0.0/zz"), None);
+        assert_eq!(protocol_magic("/ipfs/0.1.0"), None);
+    }
+
+    #[test]
+    fn test_relay_preferences_accepts() {
+        let blocks_only = RelayPreferences {
+            no_forge_relay: true,
+            max_message_size: 100,
+            ..Default::default()
+        };
+        assert!(!blocks_only.accepts(TRANSACTION_TOPIC, 10));
+        assert!(blocks_only.accepts(BLOCK_TOPIC, 100));
+        assert!(!blocks_only.accepts(BLOCK_TOPIC, 101));
+    }
+
+    #[test]
+    fn test_publish_queue_bounded() {
+        let mut queue = PublishRetryQueue::new(2, PUBLISH_QUEUE_TTL);
+        queue.push(BLOCK_TOPIC, vec![1]);
+        queue.push(BLOCK_TOPIC, vec![2]);
+        queue.push(TRANSACTION_TOPIC, vec![3]);
+
+        let stats = queue.stats();
+        assert_eq!(stats.pending, 2);
+        assert_eq!(stats.queued, 3);
+        assert_eq!(stats.dropped_overflow, 1);
+
+        // Oldest entry was evicted
+        let mut published = Vec::new();
+        queue.flush(|_, data| {
+            published.push(data.to_vec());
+            RepublishOutcome::Published
+        });
+        assert_eq!(published, vec![vec![2], vec![3]]);
+        assert!(queue.is_empty());
+        assert_eq!(queue.stats().republished, 2);
+    }
+
+    #[test]
+    fn test_publish_queue_expiry() {
+        let mut queue = PublishRetryQueue::new(10, Duration::from_secs(60));
+        let now = Instant::now();
+        queue.push_at(BLOCK_TOPIC, vec![1], now);
+        queue.push_at(BLOCK_TOPIC, vec![2], now + Duration::from_secs(30));
+
+        assert_eq!(queue.expire(now + Duration::from_secs(61)), 1);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.stats().dropped_expired, 1);
+    }
+
+    #[test]
+    fn test_publish_queue_keeps_failed_entries() {
+        let mut queue = PublishRetryQueue::new(10, PUBLISH_QUEUE_TTL);
+        queue.push(BLOCK_TOPIC, vec![1]);
+        queue.push(TRANSACTION_TOPIC, vec![2]);
+        queue.push(HEADER_TOPIC, vec![3]);
+        queue.push("excalibur-unknown", vec![4]);
+
+        let republished = queue.flush(|topic, _| match topic {
+            BLOCK_TOPIC => RepublishOutcome::Published,
+            TRANSACTION_TOPIC => RepublishOutcome::Retry,
+            HEADER_TOPIC => RepublishOutcome::Unwanted,
+            _ => RepublishOutcome::Failed,
+        });
+        assert_eq!(republished, 1);
+        assert_eq!(queue.len(), 1);
+
+        // Failures and unwanted entries are dropped and counted apart from
+        // publishes
+        let stats = queue.stats();
+        assert_eq!((stats.republished, stats.dropped_error, stats.dropped_unwanted), (1, 1, 1));
+    }
 }
@@ -0,0 +1,252 @@
+//! Peer connection lifecycle
+//!
+//! Every peer the node knows of is in exactly one `PeerState`. Outbound
+//! peers go discovered → dialing → handshaking → ready and inbound ones
+//! start at handshaking. A ready peer may become the block download source
+//! (syncing-from) and back. Peers disconnected on purpose pass through
+//! draining, and a dropped connection returns a peer to discovered. Any
+//! state may move to banned, and unbanning returns to discovered.
+//! Transitions outside this graph are rejected and logged, so an event
+//! handler acting on stale state shows up instead of silently corrupting
+//! the table.
+
+use anyhow::{anyhow, Result};
+use libp2p::PeerId;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Lifecycle state of a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerState {
+    /// Known address, not connected
+    Discovered,
+    /// Outbound dial in progress
+    Dialing,
+    /// Connected, waiting for identify
+    Handshaking,
+    /// Identified and available for relay and requests
+    Ready,
+    /// Ready and currently the node's block download source
+    SyncingFrom,
+    /// Being disconnected on purpose
+    Draining,
+    /// Refused until unbanned
+    Banned,
+}
+
+impl PeerState {
+    /// States a peer may be first recorded in
+    pub fn is_initial(self) -> bool {
+        matches!(self, Self::Discovered | Self::Dialing | Self::Handshaking | Self::Banned)
+    }
+
+    /// Whether a connection to the peer is open
+    pub fn is_connected(self) -> bool {
+        matches!(self, Self::Handshaking | Self::Ready | Self::SyncingFrom | Self::Draining)
+    }
+
+    /// Whether moving from `self` to `next` is a valid transition
+    pub fn can_transition_to(self, next: PeerState) -> bool {
+        use PeerState::*;
+        matches!(
+            (self, next),
+            (Banned, Discovered)
+                | (_, Banned)
+                | (Discovered, Dialing | Handshaking)
+                | (Dialing, Handshaking | Discovered)
+                | (Handshaking, Ready | Draining | Discovered)
+                | (Ready, SyncingFrom | Draining | Discovered)
+                | (SyncingFrom, Ready | Draining | Discovered)
+                | (Draining, Discovered)
+        )
+    }
+}
+
+impl fmt::Display for PeerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Discovered => "discovered",
+            Self::Dialing => "dialing",
+            Self::Handshaking => "handshaking",
+            Self::Ready => "ready",
+            Self::SyncingFrom => "syncing_from",
+            Self::Draining => "draining",
+            Self::Banned => "banned",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PeerEntry {
+    state: PeerState,
+    since: Instant,
+}
+
+/// Lifecycle state of every known peer
+#[derive(Debug, Default)]
+pub struct PeerTable {
+    peers: Mutex<HashMap<PeerId, PeerEntry>>,
+}
+
+impl PeerTable {
+    /// Create an empty table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move a peer to `next`, returning its previous state (`None` if it
+    /// was unknown). Moving to the current state is a no-op.
+    pub fn transition(&self, peer: PeerId, next: PeerState) -> Result<Option<PeerState>> {
+        let mut peers = self.peers.lock().unwrap();
+        let previous = peers.get(&peer).map(|entry| entry.state);
+        let valid = match previous {
+            Some(state) if state == next => return Ok(previous),
+            Some(state) => state.can_transition_to(next),
+            None => next.is_initial(),
+        };
+        if !valid {
+            let from = previous.map_or("unknown".to_string(), |state| state.to_string());
+            tracing::warn!("Rejected peer {} transition {} -> {}", peer, from, next);
+            return Err(anyhow!("Invalid peer transition {} -> {} for {}", from, next, peer));
+        }
+
+        tracing::debug!(
+            "Peer {} {} -> {}",
+            peer,
+            previous.map_or("unknown".to_string(), |state| state.to_string()),
+            next
+        );
+        peers.insert(peer, PeerEntry { state: next, since: Instant::now() });
+        Ok(previous)
+    }
+
+    /// Record a discovered peer, unless it is already known
+    pub fn discovered(&self, peer: PeerId) {
+        let mut peers = self.peers.lock().unwrap();
+        peers.entry(peer).or_insert_with(|| PeerEntry {
+            state: PeerState::Discovered,
+            since: Instant::now(),
+        });
+    }
+
+    /// Current state of a peer
+    pub fn state(&self, peer: &PeerId) -> Option<PeerState> {
+        self.peers.lock().unwrap().get(peer).map(|entry| entry.state)
+    }
+
+    /// Whether a peer is banned
+    pub fn is_banned(&self, peer: &PeerId) -> bool {
+        self.state(peer) == Some(PeerState::Banned)
+    }
+
+    /// How long a peer has been in its current state
+    pub fn time_in_state(&self, peer: &PeerId, now: Instant) -> Option<std::time::Duration> {
+        self.peers
+            .lock()
+            .unwrap()
+            .get(peer)
+            .map(|entry| now.saturating_duration_since(entry.since))
+    }
+
+    /// Peers currently in `state`
+    pub fn peers_in(&self, state: PeerState) -> Vec<PeerId> {
+        self.peers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| entry.state == state)
+            .map(|(peer, _)| *peer)
+            .collect()
+    }
+
+    /// Number of peers in each state
+    pub fn counts(&self) -> BTreeMap<PeerState, usize> {
+        let mut counts = BTreeMap::new();
+        for entry in self.peers.lock().unwrap().values() {
+            *counts.entry(entry.state).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Forget a peer entirely
+    pub fn remove(&self, peer: &PeerId) {
+        self.peers.lock().unwrap().remove(peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PeerState::*;
+
+    #[test]
+    fn test_transition_graph() {
+        let all = [Discovered, Dialing, Handshaking, Ready, SyncingFrom, Draining, Banned];
+        let valid: Vec<(PeerState, PeerState)> = all
+            .iter()
+            .flat_map(|from| all.iter().map(move |to| (*from, *to)))
+            .filter(|(from, to)| from != to && from.can_transition_to(*to))
+            .collect();
+        assert_eq!(
+            valid,
+            vec![
+                (Discovered, Dialing),
+                (Discovered, Handshaking),
+                (Discovered, Banned),
+                (Dialing, Discovered),
+                (Dialing, Handshaking),
+                (Dialing, Banned),
+                (Handshaking, Discovered),
+                (Handshaking, Ready),
+                (Handshaking, Draining),
+                (Handshaking, Banned),
+                (Ready, Discovered),
+                (Ready, SyncingFrom),
+                (Ready, Draining),
+                (Ready, Banned),
+                (SyncingFrom, Discovered),
+                (SyncingFrom, Ready),
+                (SyncingFrom, Draining),
+                (SyncingFrom, Banned),
+                (Draining, Discovered),
+                (Draining, Banned),
+                (Banned, Discovered),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_peer_table_lifecycle() {
+        let table = PeerTable::new();
+        let peer = PeerId::random();
+
+        // Unknown peers can't jump straight to ready
+        assert!(table.transition(peer, Ready).is_err());
+        assert_eq!(table.state(&peer), None);
+
+        table.discovered(peer);
+        for next in [Dialing, Handshaking, Ready, SyncingFrom, Ready, Draining, Discovered] {
+            table.transition(peer, next).unwrap();
+        }
+        assert_eq!(table.transition(peer, Discovered).unwrap(), Some(Discovered));
+        assert!(table.transition(peer, Draining).is_err());
+        assert_eq!(table.state(&peer), Some(Discovered));
+
+        // Inbound peers start at handshaking; banned peers stay out
+        let inbound = PeerId::random();
+        assert_eq!(table.transition(inbound, Handshaking).unwrap(), None);
+        table.transition(inbound, Banned).unwrap();
+        assert!(table.is_banned(&inbound));
+        assert!(table.transition(inbound, Handshaking).is_err());
+
+        let counts = table.counts();
+        assert_eq!(counts.get(&Discovered), Some(&1));
+        assert_eq!(counts.get(&Banned), Some(&1));
+        assert_eq!(table.peers_in(Banned), vec![inbound]);
+    }
+}
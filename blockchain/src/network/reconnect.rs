@@ -0,0 +1,179 @@
+//! Reconnection to dialed peers
+//!
+//! When a connection we opened closes, the peer is redialed after an
+//! exponentially growing delay until it reconnects or runs out of attempts.
+//! Peers we disconnected on purpose are not redialed.
+
+use libp2p::{Multiaddr, PeerId};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Default delay before the first redial
+pub const DEFAULT_RECONNECT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Default longest delay between redials
+pub const DEFAULT_RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Redials attempted before a peer is given up on
+pub const MAX_RECONNECT_ATTEMPTS: u32 = 12;
+
+#[derive(Debug, Clone)]
+struct Redial {
+    address: Multiaddr,
+    /// Redials made so far
+    attempts: u32,
+    next_attempt: Instant,
+}
+
+/// A peer waiting to be redialed, for `getsyncstatus`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReconnectStatus {
+    pub peer: String,
+    pub address: String,
+    pub attempts: u32,
+    pub retry_in_secs: u64,
+}
+
+#[derive(Debug, Default)]
+struct Schedule {
+    redials: HashMap<PeerId, Redial>,
+    /// Peers disconnected on purpose
+    suppressed: HashSet<PeerId>,
+}
+
+/// Exponential backoff redial schedule
+#[derive(Debug)]
+pub struct ReconnectSchedule {
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    inner: Mutex<Schedule>,
+}
+
+impl Default for ReconnectSchedule {
+    fn default() -> Self {
+        Self::new(DEFAULT_RECONNECT_INITIAL_BACKOFF, DEFAULT_RECONNECT_MAX_BACKOFF)
+    }
+}
+
+impl ReconnectSchedule {
+    /// Create a schedule doubling from `initial_backoff` up to `max_backoff`
+    pub fn new(initial_backoff: Duration, max_backoff: Duration) -> Self {
+        Self {
+            initial_backoff,
+            max_backoff,
+            inner: Mutex::new(Schedule::default()),
+        }
+    }
+
+    /// Delay before redial number `attempt` (1-based)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+
+    /// Schedule a redial of a peer whose outbound connection closed
+    pub fn disconnected(&self, peer: PeerId, address: Multiaddr, now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.suppressed.remove(&peer) {
+            return;
+        }
+        let backoff = self.backoff(1);
+        inner.redials.entry(peer).or_insert_with(|| Redial {
+            address,
+            attempts: 0,
+            next_attempt: now + backoff,
+        });
+    }
+
+    /// Don't redial a peer we are disconnecting on purpose
+    pub fn suppress(&self, peer: PeerId) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.redials.remove(&peer);
+        inner.suppressed.insert(peer);
+    }
+
+    /// Stop redialing a peer that reconnected
+    pub fn connected(&self, peer: &PeerId) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.redials.remove(peer).is_some() {
+            tracing::info!("Reconnected to peer {}", peer);
+        }
+    }
+
+    /// Peers to redial now. Each redial pushes the peer's next attempt
+    /// further out, so a dial that fails is retried later without further
+    /// bookkeeping; peers out of attempts are dropped.
+    pub fn due(&self, now: Instant) -> Vec<(PeerId, Multiaddr)> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut due = Vec::new();
+        inner.redials.retain(|peer, redial| {
+            if redial.next_attempt > now {
+                return true;
+            }
+            if redial.attempts >= MAX_RECONNECT_ATTEMPTS {
+                tracing::warn!("Giving up on peer {} after {} redials", peer, redial.attempts);
+                return false;
+            }
+            redial.attempts += 1;
+            redial.next_attempt = now + self.backoff(redial.attempts + 1);
+            due.push((*peer, redial.address.clone()));
+            true
+        });
+        due
+    }
+
+    /// Peers waiting to be redialed
+    pub fn status(&self, now: Instant) -> Vec<ReconnectStatus> {
+        let inner = self.inner.lock().unwrap();
+        let mut status: Vec<ReconnectStatus> = inner
+            .redials
+            .iter()
+            .map(|(peer, redial)| ReconnectStatus {
+                peer: peer.to_string(),
+                address: redial.address.to_string(),
+                attempts: redial.attempts,
+                retry_in_secs: redial.next_attempt.saturating_duration_since(now).as_secs(),
+            })
+            .collect();
+        status.sort_by(|a, b| a.peer.cmp(&b.peer));
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redials_back_off_until_reconnected() {
+        let schedule = ReconnectSchedule::new(Duration::from_secs(1), Duration::from_secs(4));
+        let peer = PeerId::random();
+        let address: Multiaddr = "/ip4/127.0.0.1/tcp/8333".parse().unwrap();
+        let start = Instant::now();
+
+        schedule.disconnected(peer, address.clone(), start);
+        assert!(schedule.due(start).is_empty());
+
+        // Failed redials wait 2s, then 4s, capped at 4s
+        let mut now = start + Duration::from_secs(1);
+        let mut delays = Vec::new();
+        for _ in 0..4 {
+            assert_eq!(schedule.due(now), vec![(peer, address.clone())]);
+            let retry_in = schedule.status(now)[0].retry_in_secs;
+            delays.push(retry_in);
+            assert!(schedule.due(now + Duration::from_secs(retry_in - 1)).is_empty());
+            now += Duration::from_secs(retry_in);
+        }
+        assert_eq!(delays, vec![2, 4, 4, 4]);
+
+        schedule.connected(&peer);
+        assert!(schedule.status(now).is_empty());
+
+        // Deliberate disconnects are not redialed
+        schedule.suppress(peer);
+        schedule.disconnected(peer, address, now);
+        assert!(schedule.status(now).is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+//! Reject messages sent back to the peer that relayed an invalid item
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Request-response protocol name for reject messages
+pub const REJECT_PROTOCOL: &str = "/excalibur/reject/1.0.0";
+
+/// Maximum length of the human-readable reason, in bytes
+pub const MAX_REJECT_REASON_LEN: usize = 111;
+
+/// Maximum rejects sent to a single peer per window
+pub const MAX_REJECTS_PER_WINDOW: u32 = 10;
+
+/// Rate-limit window for outbound rejects
+pub const REJECT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Number of recently rejected (peer, item) pairs remembered for dedup
+const RECENT_REJECTS_CAPACITY: usize = 1024;
+
+/// Kind of item that was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectedItem {
+    Block,
+    Forge,
+}
+
+/// Structured reject reason codes (modelled on BIP-61)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectCode {
+    /// Item could not be decoded
+    Malformed,
+    /// Item violates a consensus rule
+    Invalid,
+    /// Item uses an obsolete version
+    Obsolete,
+    /// Item is already known (e.g. replayed proof)
+    Duplicate,
+    /// Item is valid but violates local relay policy
+    Nonstandard,
+    /// Item's fee is below the local minimum
+    InsufficientFee,
+}
+
+/// Reject notification sent to the peer that relayed an item
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RejectMessage {
+    pub item: RejectedItem,
+    pub item_hash: [u8; 32],
+    pub code: RejectCode,
+    pub reason: String,
+}
+
+impl RejectMessage {
+    /// Create a reject message, truncating the reason to `MAX_REJECT_REASON_LEN`
+    pub fn new(item: RejectedItem, item_hash: [u8; 32], code: RejectCode, reason: &str) -> Self {
+        let mut end = reason.len().min(MAX_REJECT_REASON_LEN);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        Self {
+            item,
+            item_hash,
+            code,
+            reason: reason[..end].to_string(),
+        }
+    }
+}
+
+/// Gate for outbound rejects so invalid traffic can't be amplified
+/// into reject traffic
+#[derive(Debug, Default)]
+pub struct RejectLimiter {
+    windows: HashMap<PeerId, (Instant, u32)>,
+    recent: HashSet<(PeerId, [u8; 32])>,
+    recent_order: VecDeque<(PeerId, [u8; 32])>,
+}
+
+impl RejectLimiter {
+    /// Create a new limiter
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether a reject for `item_hash` may be sent to `peer` now
+    pub fn allow(&mut self, peer: &PeerId, item_hash: &[u8; 32], now: Instant) -> bool {
+        let key = (*peer, *item_hash);
+        if self.recent.contains(&key) {
+            return false;
+        }
+
+        let (window_start, count) = self.windows.entry(*peer).or_insert((now, 0));
+        if now.saturating_duration_since(*window_start) >= REJECT_WINDOW {
+            *window_start = now;
+            *count = 0;
+        }
+        if *count >= MAX_REJECTS_PER_WINDOW {
+            return false;
+        }
+        *count += 1;
+
+        if self.recent_order.len() >= RECENT_REJECTS_CAPACITY {
+            if let Some(oldest) = self.recent_order.pop_front() {
+                self.recent.remove(&oldest);
+            }
+        }
+        self.recent.insert(key);
+        self.recent_order.push_back(key);
+        true
+    }
+
+    /// Forget rate-limit state for a disconnected peer
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.windows.remove(peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reason_truncated() {
+        let long = "x".repeat(500);
+        let msg = RejectMessage::new(RejectedItem::Forge, [0u8; 32], RejectCode::Invalid, &long);
+        assert_eq!(msg.reason.len(), MAX_REJECT_REASON_LEN);
+    }
+
+    #[test]
+    fn test_limiter_dedups_and_rate_limits() {
+        let mut limiter = RejectLimiter::new();
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        assert!(limiter.allow(&peer, &[0u8; 32], now));
+        // Same item is only rejected once
+        assert!(!limiter.allow(&peer, &[0u8; 32], now));
+
+        for i in 1..MAX_REJECTS_PER_WINDOW {
+            assert!(limiter.allow(&peer, &[i as u8; 32], now));
+        }
+        // Window exhausted
+        assert!(!limiter.allow(&peer, &[0xff; 32], now));
+        // Next window
+        assert!(limiter.allow(&peer, &[0xff; 32], now + REJECT_WINDOW));
+
+        // Other peers have their own budget
+        assert!(limiter.allow(&PeerId::random(), &[0xfe; 32], now));
+    }
+}
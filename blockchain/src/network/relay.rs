@@ -0,0 +1,242 @@
+//! Optimistic block relay
+//!
+//! By default a block is relayed once it is fully validated. With optimistic
+//! relay enabled, a block is relayed as soon as
+//! `ConsensusEngine::prevalidate_block` passes, while proof-of-forge
+//! verification continues; this cuts a full verification off every hop of
+//! propagation. A block that then fails validation is revoked: peers are
+//! sent a reject for it. Its source loses optimistic relay, and is
+//! disconnected once `MAX_OPTIMISTIC_FAILURES` of its blocks have passed
+//! prevalidation but failed full validation, relayed early or not.
+//!
+//! The network manager holds gossiped blocks back from propagation until the
+//! node sends `NetworkCommand::RelayBlock` or `DiscardBlock` for them.
+
+use libp2p::gossipsub::MessageId;
+use libp2p::PeerId;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+/// Prevalidated blocks failing full validation after which the source is
+/// disconnected
+pub const MAX_OPTIMISTIC_FAILURES: u32 = 3;
+
+/// Recently relayed block hashes remembered to avoid relaying twice
+const RELAYED_CAPACITY: usize = 1024;
+
+/// What to do about a prevalidated block that failed full validation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Revocation {
+    /// Peer the block came from
+    pub source: PeerId,
+    /// Whether the block was relayed early, so peers must be sent a reject
+    pub relayed: bool,
+    /// Prevalidated blocks from `source` that failed full validation
+    pub failures: u32,
+    /// Whether `source` should be disconnected
+    pub disconnect: bool,
+}
+
+/// Optimistic relay counters, for metrics and RPC
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RelayStats {
+    pub enabled: bool,
+    /// Blocks relayed before full validation
+    pub relayed_early: u64,
+    /// Early-relayed blocks that then failed validation
+    pub revoked: u64,
+    /// Early-relayed blocks still being validated
+    pub awaiting_validation: usize,
+}
+
+#[derive(Debug, Default)]
+struct RelayState {
+    /// Early-relayed blocks awaiting full validation, by source
+    awaiting: HashMap<[u8; 32], PeerId>,
+    failures: HashMap<PeerId, u32>,
+    /// Blocks already relayed, so they aren't relayed twice
+    relayed: HashSet<[u8; 32]>,
+    relayed_order: VecDeque<[u8; 32]>,
+    relayed_early: u64,
+    revoked: u64,
+}
+
+impl RelayState {
+    /// Remember a relayed block; false if it was already relayed
+    fn mark_relayed(&mut self, hash: &[u8; 32]) -> bool {
+        if !self.relayed.insert(*hash) {
+            return false;
+        }
+        self.relayed_order.push_back(*hash);
+        if self.relayed_order.len() > RELAYED_CAPACITY {
+            if let Some(oldest) = self.relayed_order.pop_front() {
+                self.relayed.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// Gossiped blocks awaiting the node's relay decision, by `inventory_hash`
+#[derive(Debug, Default)]
+pub(crate) struct HeldBlocks {
+    held: HashMap<[u8; 32], (MessageId, PeerId)>,
+    order: VecDeque<[u8; 32]>,
+}
+
+impl HeldBlocks {
+    /// Hold a block message. Returns a message to drop instead: this one if
+    /// the same block is already held, or the oldest once over capacity.
+    pub(crate) fn hold(
+        &mut self,
+        hash: [u8; 32],
+        message_id: MessageId,
+        source: PeerId,
+    ) -> Option<(MessageId, PeerId)> {
+        if self.held.contains_key(&hash) {
+            return Some((message_id, source));
+        }
+        self.held.insert(hash, (message_id, source));
+        self.order.push_back(hash);
+        if self.order.len() > RELAYED_CAPACITY {
+            return self.order.pop_front().and_then(|oldest| self.held.remove(&oldest));
+        }
+        None
+    }
+
+    /// Stop holding a block, returning its message if it was held
+    pub(crate) fn release(&mut self, hash: &[u8; 32]) -> Option<(MessageId, PeerId)> {
+        let released = self.held.remove(hash)?;
+        self.order.retain(|held| held != hash);
+        Some(released)
+    }
+}
+
+/// Decides when a received block is relayed
+#[derive(Debug, Default)]
+pub struct BlockRelay {
+    optimistic: bool,
+    state: Mutex<RelayState>,
+}
+
+impl BlockRelay {
+    /// Create a relay that relays before full validation if `optimistic`
+    pub fn new(optimistic: bool) -> Self {
+        Self {
+            optimistic,
+            state: Mutex::new(RelayState::default()),
+        }
+    }
+
+    /// Whether the block from `source` should be relayed now that it passed
+    /// header and structural checks
+    pub fn prevalidated(&self, hash: &[u8; 32], source: PeerId) -> bool {
+        if !self.optimistic {
+            return false;
+        }
+        let mut state = self.state.lock().unwrap();
+        // Sources that already relayed a bad block wait for full validation
+        if state.failures.contains_key(&source) || !state.mark_relayed(hash) {
+            return false;
+        }
+        state.awaiting.insert(*hash, source);
+        state.relayed_early += 1;
+        true
+    }
+
+    /// Whether a fully validated block should be relayed now, i.e. it
+    /// wasn't already relayed early
+    pub fn validated(&self, hash: &[u8; 32]) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.awaiting.remove(hash);
+        state.mark_relayed(hash)
+    }
+
+    /// Record a prevalidated block from `source` that failed full
+    /// validation, returning the revocation to carry out
+    pub fn failed(&self, hash: &[u8; 32], source: PeerId) -> Revocation {
+        let mut state = self.state.lock().unwrap();
+        let relayed = state.awaiting.remove(hash).is_some();
+        if relayed {
+            state.revoked += 1;
+            tracing::warn!("Revoking optimistically relayed block {} from {}", hex::encode(hash), source);
+        }
+        let failures = state.failures.entry(source).or_default();
+        *failures += 1;
+        Revocation {
+            source,
+            relayed,
+            failures: *failures,
+            disconnect: *failures >= MAX_OPTIMISTIC_FAILURES,
+        }
+    }
+
+    /// Current counters
+    pub fn stats(&self) -> RelayStats {
+        let state = self.state.lock().unwrap();
+        RelayStats {
+            enabled: self.optimistic,
+            relayed_early: state.relayed_early,
+            revoked: state.revoked,
+            awaiting_validation: state.awaiting.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_optimistic_relay_and_revocation() {
+        let cautious = BlockRelay::new(false);
+        assert!(!cautious.prevalidated(&[1; 32], PeerId::random()));
+        assert!(cautious.validated(&[1; 32]));
+        assert!(!cautious.validated(&[1; 32]));
+
+        let relay = BlockRelay::new(true);
+        let good = PeerId::random();
+        let bad = PeerId::random();
+
+        // Relayed early once, not again after validation
+        assert!(relay.prevalidated(&[1; 32], good));
+        assert!(!relay.prevalidated(&[1; 32], bad));
+        assert!(!relay.validated(&[1; 32]));
+
+        // After its first bad block the source waits for full validation,
+        // and enough bad blocks get it disconnected
+        for n in 1..=MAX_OPTIMISTIC_FAILURES {
+            let hash = [n as u8 + 1; 32];
+            assert_eq!(relay.prevalidated(&hash, bad), n == 1);
+            let revocation = relay.failed(&hash, bad);
+            assert_eq!(revocation.relayed, n == 1);
+            assert_eq!(revocation.failures, n);
+            assert_eq!(revocation.disconnect, n == MAX_OPTIMISTIC_FAILURES);
+        }
+
+        let stats = relay.stats();
+        assert_eq!((stats.relayed_early, stats.revoked, stats.awaiting_validation), (2, 1, 0));
+    }
+
+    #[test]
+    fn test_held_blocks() {
+        let mut held = HeldBlocks::default();
+        let source = PeerId::random();
+        let message = |n: u8| MessageId::from(vec![n]);
+
+        assert_eq!(held.hold([1; 32], message(1), source), None);
+        // The same block from another publisher is dropped
+        assert_eq!(held.hold([1; 32], message(2), source), Some((message(2), source)));
+        assert_eq!(held.release(&[1; 32]), Some((message(1), source)));
+        assert_eq!(held.release(&[1; 32]), None);
+
+        // Past capacity the oldest block is dropped
+        for n in 0..=RELAYED_CAPACITY as u64 {
+            let mut hash = [0u8; 32];
+            hash[..8].copy_from_slice(&n.to_le_bytes());
+            let evicted = held.hold(hash, message(n as u8), source);
+            assert_eq!(evicted, (n == RELAYED_CAPACITY as u64).then(|| (message(0), source)));
+        }
+    }
+}
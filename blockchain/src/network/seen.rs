@@ -0,0 +1,168 @@
+//! Recently seen gossip messages, kept across restarts
+//!
+//! Gossipsub only remembers message ids in memory, so a restarted node would
+//! process and re-relay every block and forge its peers replay to it. The
+//! ids seen in the last window are saved to the data directory on shutdown
+//! and loaded on startup, and gossip carrying one of them is dropped without
+//! being propagated.
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// File in the data directory holding the seen message ids
+pub const SEEN_MESSAGES_FILE: &str = "seen_messages.dat";
+
+/// Default number of message ids remembered
+pub const DEFAULT_SEEN_MESSAGES: usize = 50_000;
+
+/// Default time a message id is remembered for
+pub const DEFAULT_SEEN_WINDOW: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug, Default)]
+struct Seen {
+    /// Time each id was first seen (Unix seconds)
+    ids: HashMap<Vec<u8>, u64>,
+    /// Ids in the order they were seen
+    order: VecDeque<(Vec<u8>, u64)>,
+}
+
+/// Bounded, time-windowed set of gossip message ids
+#[derive(Debug)]
+pub struct SeenMessages {
+    capacity: usize,
+    window: Duration,
+    inner: Mutex<Seen>,
+}
+
+impl Default for SeenMessages {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEEN_MESSAGES, DEFAULT_SEEN_WINDOW)
+    }
+}
+
+impl SeenMessages {
+    /// Remember at most `capacity` ids (0 disables), each for `window`
+    pub fn new(capacity: usize, window: Duration) -> Self {
+        Self {
+            capacity,
+            window,
+            inner: Mutex::new(Seen::default()),
+        }
+    }
+
+    /// Record a message id seen at `now` (Unix seconds). Returns false if it
+    /// was already seen within the window.
+    pub fn insert(&self, id: &[u8], now: u64) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        self.expire(&mut inner, now);
+        if inner.ids.contains_key(id) {
+            return false;
+        }
+        inner.ids.insert(id.to_vec(), now);
+        inner.order.push_back((id.to_vec(), now));
+        while inner.order.len() > self.capacity {
+            let Some((oldest, _)) = inner.order.pop_front() else { break };
+            inner.ids.remove(&oldest);
+        }
+        true
+    }
+
+    /// Whether a message id was seen within the window
+    pub fn contains(&self, id: &[u8], now: u64) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        self.expire(&mut inner, now);
+        inner.ids.contains_key(id)
+    }
+
+    /// Number of ids remembered
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().order.len()
+    }
+
+    /// Whether no ids are remembered
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn expire(&self, inner: &mut Seen, now: u64) {
+        let cutoff = now.saturating_sub(self.window.as_secs());
+        while inner.order.front().is_some_and(|(_, seen_at)| *seen_at < cutoff) {
+            if let Some((id, _)) = inner.order.pop_front() {
+                inner.ids.remove(&id);
+            }
+        }
+    }
+
+    /// Load the ids saved at `path` that are still within the window. A
+    /// missing file yields an empty set.
+    pub fn load<P: AsRef<Path>>(path: P, capacity: usize, window: Duration, now: u64) -> Result<Self> {
+        let seen = Self::new(capacity, window);
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(seen);
+        }
+        let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let entries: Vec<(Vec<u8>, u64)> = bincode::deserialize(&bytes)
+            .map_err(|e| anyhow!("Corrupt seen message file {}: {}", path.display(), e))?;
+        for (id, seen_at) in entries {
+            if seen_at.saturating_add(window.as_secs()) >= now {
+                seen.insert(&id, seen_at);
+            }
+        }
+        Ok(seen)
+    }
+
+    /// Save the remembered ids to `path`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let entries: Vec<(Vec<u8>, u64)> = self.inner.lock().unwrap().order.iter().cloned().collect();
+        // Write to a temporary file first so a crash can't truncate the set
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, bincode::serialize(&entries)?)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seen_ids_bounded_and_windowed() {
+        let seen = SeenMessages::new(2, Duration::from_secs(60));
+        assert!(seen.insert(b"a", 100));
+        assert!(!seen.insert(b"a", 110), "duplicate within the window");
+        assert!(seen.insert(b"b", 120));
+        assert!(seen.insert(b"c", 130));
+        assert!(!seen.contains(b"a", 130), "evicted by capacity");
+        assert!(seen.contains(b"b", 180));
+        assert!(!seen.contains(b"b", 181), "expired");
+        assert_eq!(seen.len(), 1);
+    }
+
+    #[test]
+    fn test_seen_ids_persist() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join(SEEN_MESSAGES_FILE);
+        let seen = SeenMessages::new(10, Duration::from_secs(60));
+        seen.insert(b"old", 100);
+        seen.insert(b"new", 150);
+        seen.save(&path).unwrap();
+
+        let loaded = SeenMessages::load(&path, 10, Duration::from_secs(60), 200).unwrap();
+        assert!(loaded.contains(b"new", 200));
+        assert!(!loaded.contains(b"old", 200));
+        assert!(!loaded.insert(b"new", 201), "replayed gossip is a duplicate");
+
+        std::fs::write(&path, b"\xff").unwrap();
+        assert!(SeenMessages::load(&path, 10, Duration::from_secs(60), 200).is_err());
+    }
+}
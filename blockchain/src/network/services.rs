@@ -0,0 +1,189 @@
+//! Node roles advertised during identify
+//!
+//! Every node declares the services it offers as a bitfield in its identify
+//! agent string. Sync uses it to fetch deep history from archival peers, and
+//! light clients look for peers that serve filters.
+
+use libp2p::PeerId;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+/// Blocks deeper than this below the best height may be missing from
+/// non-archival peers
+pub const DEEP_HISTORY_DEPTH: u64 = 288;
+
+/// Services a node offers to its peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ServiceFlags(u8);
+
+impl ServiceFlags {
+    /// Validates and relays blocks and forges
+    pub const FULL: Self = Self(1 << 0);
+    /// Keeps and serves every historical block body
+    pub const ARCHIVAL: Self = Self(1 << 1);
+    /// Serves compact block filters
+    pub const FILTERS: Self = Self(1 << 2);
+    /// Answers light-client header and proof requests
+    pub const LIGHT_SERVE: Self = Self(1 << 3);
+
+    const NAMES: [(Self, &'static str); 4] = [
+        (Self::FULL, "full"),
+        (Self::ARCHIVAL, "archival"),
+        (Self::FILTERS, "filters"),
+        (Self::LIGHT_SERVE, "light_serve"),
+    ];
+
+    /// No services
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Raw bitfield as advertised on the wire
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Flags from a wire bitfield; unknown bits are kept for forward
+    /// compatibility
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    /// Whether every service in `other` is offered
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Add the services in `other`
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    /// Names of the known services offered
+    pub fn names(self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    /// Role summarizing these services: `archival`, `pruned` (a full node
+    /// without archival history) or `light`
+    pub fn role(self) -> &'static str {
+        if self.contains(Self::ARCHIVAL) {
+            "archival"
+        } else if self.contains(Self::FULL) {
+            "pruned"
+        } else {
+            "light"
+        }
+    }
+}
+
+impl std::ops::BitOr for ServiceFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Connected peers by role and by service (`getpeerroles`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RoleDistribution {
+    pub total: usize,
+    pub roles: BTreeMap<&'static str, usize>,
+    pub services: BTreeMap<&'static str, usize>,
+}
+
+/// Services advertised by connected peers, shared with the RPC server
+#[derive(Debug, Default)]
+pub struct PeerServices {
+    peers: Mutex<HashMap<PeerId, ServiceFlags>>,
+}
+
+impl PeerServices {
+    /// Create an empty table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the services a peer advertised
+    pub fn set(&self, peer: PeerId, services: ServiceFlags) {
+        self.peers.lock().unwrap().insert(peer, services);
+    }
+
+    /// Forget a disconnected peer
+    pub fn remove(&self, peer: &PeerId) {
+        self.peers.lock().unwrap().remove(peer);
+    }
+
+    /// Services advertised by a peer, if it completed identify
+    pub fn get(&self, peer: &PeerId) -> Option<ServiceFlags> {
+        self.peers.lock().unwrap().get(peer).copied()
+    }
+
+    /// Connected peers offering all of `services`, e.g. filter servers
+    /// for a light client
+    pub fn peers_with(&self, services: ServiceFlags) -> Vec<PeerId> {
+        let mut peers: Vec<PeerId> = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, offered)| offered.contains(services))
+            .map(|(peer, _)| *peer)
+            .collect();
+        peers.sort();
+        peers
+    }
+
+    /// How the connected peer set splits across roles and services
+    pub fn distribution(&self) -> RoleDistribution {
+        let peers = self.peers.lock().unwrap();
+        let mut distribution = RoleDistribution {
+            total: peers.len(),
+            ..Default::default()
+        };
+        for services in peers.values() {
+            *distribution.roles.entry(services.role()).or_default() += 1;
+            for name in services.names() {
+                *distribution.services.entry(name).or_default() += 1;
+            }
+        }
+        distribution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roles_and_distribution() {
+        let table = PeerServices::new();
+        let archive = PeerId::random();
+        let pruned = PeerId::random();
+        let light = PeerId::random();
+        table.set(archive, ServiceFlags::FULL | ServiceFlags::ARCHIVAL | ServiceFlags::FILTERS);
+        table.set(pruned, ServiceFlags::FULL | ServiceFlags::FILTERS);
+        table.set(light, ServiceFlags::empty());
+
+        assert_eq!(ServiceFlags::FULL.role(), "pruned");
+        assert_eq!(table.peers_with(ServiceFlags::ARCHIVAL), vec![archive]);
+        assert_eq!(table.peers_with(ServiceFlags::FILTERS).len(), 2);
+
+        let distribution = table.distribution();
+        assert_eq!(distribution.total, 3);
+        assert_eq!(distribution.roles["archival"], 1);
+        assert_eq!(distribution.roles["pruned"], 1);
+        assert_eq!(distribution.roles["light"], 1);
+        assert_eq!(distribution.services["filters"], 2);
+        assert!(!distribution.services.contains_key("light_serve"));
+
+        table.remove(&archive);
+        assert!(table.peers_with(ServiceFlags::ARCHIVAL).is_empty());
+    }
+}
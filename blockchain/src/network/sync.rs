@@ -0,0 +1,580 @@
+//! Block body download scheduling
+//!
+//! Header announcements carry a commitment to the total forge fees of the
+//! block. While syncing, bodies of blocks near the best announced height are
+//! fetched highest-fee first so the miner and mempool can react to the
+//! economically relevant tip; older blocks backfill in height order behind
+//! them. A peer whose body turns out to hold less than it announced has its
+//! later announcements scheduled as backfill only. Bodies deeper than
+//! `DEEP_HISTORY_DEPTH` are requested from archival announcers when there
+//! are any, since pruned peers may no longer have them.
+//!
+//! A request unanswered within the policy's timeout counts as a stall: the
+//! body is requeued and retried from another announcer where possible, and a
+//! peer that stalls `demote_after_stalls` times in a row is only asked when
+//! no other announcer has the body. A delivered body clears the streak.
+//!
+//! Headers and bodies are requested over the `SYNC_PROTOCOL`
+//! request-response protocol; `crate::sync` drives it.
+
+use super::services::{ServiceFlags, DEEP_HISTORY_DEPTH};
+use crate::consensus::{Block, BlockHeader, ForgeTransaction, MerkleProof};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// Blocks this close to the best announced height are fetched by fee
+pub const TIP_WINDOW: u64 = 16;
+
+/// Maximum body requests outstanding at once
+pub const MAX_BODIES_IN_FLIGHT: usize = 16;
+
+/// How long a body request may go unanswered before it is retried
+pub const BODY_REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Consecutive stalls after which a peer is demoted
+pub const DEFAULT_DEMOTE_AFTER_STALLS: u32 = 3;
+
+/// Request-response protocol name for header and block downloads
+pub const SYNC_PROTOCOL: &str = "/excalibur/sync/1.0.0";
+
+/// Maximum headers returned for one request
+pub const MAX_HEADERS_PER_REQUEST: usize = 2000;
+
+/// Maximum blocks returned for one request
+pub const MAX_BLOCKS_PER_REQUEST: usize = 16;
+
+/// Header or block download request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SyncRequest {
+    /// Main-chain headers following the first `locator` hash the responder
+    /// knows, or from genesis if it knows none
+    GetHeaders { locator: Vec<[u8; 32]>, max: u32 },
+    /// Main-chain blocks by hash; unknown hashes are skipped
+    GetBlocks { hashes: Vec<[u8; 32]> },
+    /// Inclusion proof of a mined forge, for light clients; answered by
+    /// peers advertising `ServiceFlags::LIGHT_SERVE` with a forge index
+    GetForgeProof { proof_hash: [u8; 32] },
+}
+
+impl SyncRequest {
+    /// Response carrying nothing, for requests that can't be served
+    pub fn empty_response(&self) -> SyncResponse {
+        match self {
+            Self::GetHeaders { .. } => SyncResponse::Headers { headers: Vec::new() },
+            Self::GetBlocks { .. } => SyncResponse::Blocks { blocks: Vec::new() },
+            Self::GetForgeProof { .. } => SyncResponse::ForgeProof { proof: None },
+        }
+    }
+}
+
+/// Response to a `SyncRequest`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SyncResponse {
+    Headers { headers: Vec<BlockHeader> },
+    Blocks { blocks: Vec<Block> },
+    /// `None` if the forge isn't in the responder's main chain
+    ForgeProof { proof: Option<Box<ForgeProof>> },
+}
+
+/// A mined forge with the merkle path placing it under the forge root of
+/// its block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForgeProof {
+    pub block_hash: [u8; 32],
+    pub height: u64,
+    pub forge: ForgeTransaction,
+    pub proof: MerkleProof,
+    /// Root over the block's transfers, which its header commits to along
+    /// with the forge root. `None` if the block has no transfers.
+    pub transfer_root: Option<[u8; 32]>,
+}
+
+/// Timeouts and stall handling for body downloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncPolicy {
+    /// How long a body request may go unanswered before it is retried
+    pub request_timeout: Duration,
+    /// Consecutive stalls after which a peer is only used as a last resort
+    pub demote_after_stalls: u32,
+}
+
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        Self {
+            request_timeout: BODY_REQUEST_TIMEOUT,
+            demote_after_stalls: DEFAULT_DEMOTE_AFTER_STALLS,
+        }
+    }
+}
+
+/// Download record of one peer
+#[derive(Debug, Clone, Copy, Default)]
+struct PeerStats {
+    /// Stalls since the peer last delivered a body
+    stalls: u32,
+    delivered: u64,
+}
+
+/// Download state of one peer, for `getsyncstatus`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PeerSyncStatus {
+    pub peer: String,
+    pub in_flight: usize,
+    pub stalls: u32,
+    pub delivered: u64,
+    pub demoted: bool,
+}
+
+/// Snapshot of the body download queue
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SyncStatus {
+    pub best_announced_height: u64,
+    pub pending: usize,
+    pub in_flight: usize,
+    /// Requests that timed out since the queue was created
+    pub timeouts: u64,
+    pub peers: Vec<PeerSyncStatus>,
+}
+
+/// Header gossiped ahead of its body, with the announcer's fee commitment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderAnnouncement {
+    pub hash: [u8; 32],
+    pub header: BlockHeader,
+    /// Total forge fees the announcer claims the block pays
+    pub fee_total: u64,
+}
+
+/// Scheduling order of a pending body; greater is fetched first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Priority {
+    /// Older history, lowest height first
+    Backfill { neg_height: Reverse<u64> },
+    /// Near the tip, highest fee first, then lowest height
+    Tip { fee_total: u64, neg_height: Reverse<u64> },
+}
+
+#[derive(Debug)]
+struct Pending {
+    height: u64,
+    fee_total: u64,
+    /// Peer whose commitment set `fee_total`, unless it is distrusted
+    fee_claimant: Option<PeerId>,
+    announcers: Vec<PeerId>,
+    /// Announcers whose request for this body timed out
+    stalled_by: Vec<PeerId>,
+}
+
+impl Pending {
+    fn priority(&self, best_height: u64) -> Priority {
+        let neg_height = Reverse(self.height);
+        if self.fee_claimant.is_some() && self.height + TIP_WINDOW >= best_height {
+            Priority::Tip {
+                fee_total: self.fee_total,
+                neg_height,
+            }
+        } else {
+            Priority::Backfill { neg_height }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct InFlight {
+    peer: PeerId,
+    sent: Instant,
+    pending: Pending,
+}
+
+/// Body download queue ordered by announced fee total near the tip
+#[derive(Debug, Default)]
+pub struct BodyFetchQueue {
+    pending: HashMap<[u8; 32], Pending>,
+    in_flight: HashMap<[u8; 32], InFlight>,
+    /// Peers that overstated a fee commitment
+    distrusted: HashSet<PeerId>,
+    /// Services advertised by announcers, from identify
+    services: HashMap<PeerId, ServiceFlags>,
+    peer_stats: HashMap<PeerId, PeerStats>,
+    policy: SyncPolicy,
+    timeouts: u64,
+    best_height: u64,
+}
+
+impl BodyFetchQueue {
+    /// Create an empty queue
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty queue with the given timeout and stall policy
+    pub fn with_policy(policy: SyncPolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    /// Record the services a peer advertised during identify
+    pub fn set_peer_services(&mut self, peer: PeerId, services: ServiceFlags) {
+        self.services.insert(peer, services);
+    }
+
+    /// Queue the body of an announced header. Returns `false` if it was
+    /// already known, in which case `peer` is only recorded as a source.
+    pub fn announce(&mut self, peer: PeerId, announcement: &HeaderAnnouncement) -> bool {
+        let height = announcement.header.height;
+        self.best_height = self.best_height.max(height);
+        let claimant = (!self.distrusted.contains(&peer)).then_some(peer);
+
+        let known = self
+            .pending
+            .get_mut(&announcement.hash)
+            .or_else(|| self.in_flight.get_mut(&announcement.hash).map(|entry| &mut entry.pending));
+        if let Some(pending) = known {
+            if !pending.announcers.contains(&peer) {
+                pending.announcers.push(peer);
+            }
+            // Keep the lowest trusted claim so one peer can't inflate another's
+            if claimant.is_some()
+                && (pending.fee_claimant.is_none() || announcement.fee_total < pending.fee_total)
+            {
+                pending.fee_total = announcement.fee_total;
+                pending.fee_claimant = claimant;
+            }
+            return false;
+        }
+
+        self.pending.insert(
+            announcement.hash,
+            Pending {
+                height,
+                fee_total: announcement.fee_total,
+                fee_claimant: claimant,
+                announcers: vec![peer],
+                stalled_by: Vec::new(),
+            },
+        );
+        true
+    }
+
+    /// Next body to request and the peer to ask, if a request slot is free
+    pub fn next_request(&mut self, now: Instant) -> Option<([u8; 32], PeerId)> {
+        if self.in_flight.len() >= MAX_BODIES_IN_FLIGHT {
+            return None;
+        }
+        let best_height = self.best_height;
+        let hash = *self
+            .pending
+            .iter()
+            .max_by_key(|(hash, pending)| (pending.priority(best_height), Reverse(**hash)))?
+            .0;
+        let pending = self.pending.remove(&hash)?;
+
+        // Spread requests over announcers, avoiding ones that stalled on
+        // this body or are demoted, then preferring archival ones for deep
+        // history, then idle ones
+        let busy: HashSet<PeerId> = self.in_flight.values().map(|entry| entry.peer).collect();
+        let deep = pending.height + DEEP_HISTORY_DEPTH < best_height;
+        let peer = *pending
+            .announcers
+            .iter()
+            .min_by_key(|peer| {
+                let archival = self
+                    .services
+                    .get(peer)
+                    .is_some_and(|services| services.contains(ServiceFlags::ARCHIVAL));
+                (
+                    pending.stalled_by.contains(peer),
+                    self.is_demoted(peer),
+                    !(deep && archival),
+                    busy.contains(peer),
+                )
+            })
+            .unwrap_or(&pending.announcers[0]);
+
+        self.in_flight.insert(hash, InFlight { peer, sent: now, pending });
+        Some((hash, peer))
+    }
+
+    /// Record a received body and its actual fee total. Returns the peer
+    /// whose announcement overstated the fees, if any.
+    pub fn complete(&mut self, hash: &[u8; 32], actual_fee_total: u64) -> Option<PeerId> {
+        let pending = match self.in_flight.remove(hash) {
+            Some(entry) => {
+                let stats = self.peer_stats.entry(entry.peer).or_default();
+                stats.stalls = 0;
+                stats.delivered += 1;
+                entry.pending
+            }
+            None => self.pending.remove(hash)?,
+        };
+        let claimant = pending.fee_claimant?;
+        if pending.fee_total <= actual_fee_total {
+            return None;
+        }
+
+        tracing::debug!(
+            "Peer {} announced {} fees for block {} which pays {}",
+            claimant,
+            pending.fee_total,
+            hex::encode(hash),
+            actual_fee_total
+        );
+        self.distrusted.insert(claimant);
+        for other in self.pending.values_mut() {
+            if other.fee_claimant == Some(claimant) {
+                other.fee_claimant = None;
+            }
+        }
+        Some(claimant)
+    }
+
+    /// Requeue requests unanswered for the policy's request timeout,
+    /// charging a stall to each peer asked. Returns how many were requeued.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let timeout = self.policy.request_timeout;
+        let expired: Vec<[u8; 32]> = self
+            .in_flight
+            .iter()
+            .filter(|(_, entry)| now.saturating_duration_since(entry.sent) >= timeout)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in &expired {
+            if let Some(mut entry) = self.in_flight.remove(hash) {
+                let stats = self.peer_stats.entry(entry.peer).or_default();
+                stats.stalls += 1;
+                if stats.stalls == self.policy.demote_after_stalls {
+                    tracing::info!("Demoting peer {} after {} stalled body requests", entry.peer, stats.stalls);
+                }
+                if !entry.pending.stalled_by.contains(&entry.peer) {
+                    entry.pending.stalled_by.push(entry.peer);
+                }
+                self.pending.insert(*hash, entry.pending);
+            }
+        }
+        self.timeouts += expired.len() as u64;
+        expired.len()
+    }
+
+    /// Whether a peer stalled too often to be asked while others can be
+    pub fn is_demoted(&self, peer: &PeerId) -> bool {
+        self.policy.demote_after_stalls > 0
+            && self
+                .peer_stats
+                .get(peer)
+                .is_some_and(|stats| stats.stalls >= self.policy.demote_after_stalls)
+    }
+
+    /// Snapshot of the queue and each peer's download record
+    pub fn status(&self) -> SyncStatus {
+        let known: HashSet<PeerId> = self
+            .peer_stats
+            .keys()
+            .chain(self.in_flight.values().map(|request| &request.peer))
+            .copied()
+            .collect();
+        let mut peers: Vec<PeerSyncStatus> = known
+            .into_iter()
+            .map(|peer| {
+                let stats = self.peer_stats.get(&peer).copied().unwrap_or_default();
+                PeerSyncStatus {
+                    peer: peer.to_string(),
+                    in_flight: self.in_flight.values().filter(|request| request.peer == peer).count(),
+                    stalls: stats.stalls,
+                    delivered: stats.delivered,
+                    demoted: self.is_demoted(&peer),
+                }
+            })
+            .collect();
+        peers.sort_by(|a, b| a.peer.cmp(&b.peer));
+        SyncStatus {
+            best_announced_height: self.best_height,
+            pending: self.pending.len(),
+            in_flight: self.in_flight.len(),
+            timeouts: self.timeouts,
+            peers,
+        }
+    }
+
+    /// Forget a disconnected peer as a source, requeueing its requests
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        let requested: Vec<[u8; 32]> = self
+            .in_flight
+            .iter()
+            .filter(|(_, entry)| entry.peer == *peer)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in requested {
+            if let Some(entry) = self.in_flight.remove(&hash) {
+                self.pending.insert(hash, entry.pending);
+            }
+        }
+        self.services.remove(peer);
+        self.peer_stats.remove(peer);
+        self.pending.retain(|_, pending| {
+            pending.announcers.retain(|announcer| announcer != peer);
+            !pending.announcers.is_empty()
+        });
+    }
+
+    /// Whether a body is queued or requested
+    pub fn is_wanted(&self, hash: &[u8; 32]) -> bool {
+        self.pending.contains_key(hash) || self.in_flight.contains_key(hash)
+    }
+
+    /// Bodies waiting to be requested
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Bodies requested and not yet received
+    pub fn in_flight_len(&self) -> usize {
+        self.in_flight.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn announcement(seed: u8, height: u64, fee_total: u64) -> HeaderAnnouncement {
+        HeaderAnnouncement {
+            hash: [seed; 32],
+            header: crate::consensus::testing::header(height, [0u8; 32]),
+            fee_total,
+        }
+    }
+
+    #[test]
+    fn test_tip_bodies_fetched_by_fee_before_backfill() {
+        let mut queue = BodyFetchQueue::new();
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        queue.announce(peer, &announcement(1, 10, 1_000_000));
+        queue.announce(peer, &announcement(2, 11, 5));
+        queue.announce(peer, &announcement(3, 100, 10));
+        queue.announce(peer, &announcement(4, 99, 500));
+        assert!(!queue.announce(PeerId::random(), &announcement(4, 99, 500)));
+
+        // Near-tip by fee, then history by height despite its higher claim
+        let order: Vec<u8> = std::iter::from_fn(|| queue.next_request(now))
+            .map(|(hash, _)| hash[0])
+            .collect();
+        assert_eq!(order, vec![4, 3, 1, 2]);
+        assert_eq!(queue.in_flight_len(), 4);
+    }
+
+    #[test]
+    fn test_overstated_fees_demote_announcer() {
+        let mut queue = BodyFetchQueue::new();
+        let liar = PeerId::random();
+        let honest = PeerId::random();
+        let now = Instant::now();
+
+        queue.announce(liar, &announcement(1, 50, 1_000));
+        queue.announce(liar, &announcement(2, 51, 900));
+        queue.announce(honest, &announcement(3, 52, 100));
+
+        let (hash, peer) = queue.next_request(now).unwrap();
+        assert_eq!((hash, peer), ([1; 32], liar));
+        assert_eq!(queue.complete(&hash, 10), Some(liar));
+
+        // The liar's remaining claim no longer jumps the queue
+        assert_eq!(queue.next_request(now).unwrap().0, [3; 32]);
+        assert_eq!(queue.complete(&[3; 32], 100), None);
+        queue.announce(liar, &announcement(4, 52, u64::MAX));
+        assert_eq!(queue.next_request(now).unwrap().0, [2; 32]);
+    }
+
+    #[test]
+    fn test_expired_and_orphaned_requests_requeued() {
+        let mut queue = BodyFetchQueue::new();
+        let a = PeerId::random();
+        let b = PeerId::random();
+        let now = Instant::now();
+
+        queue.announce(a, &announcement(1, 1, 0));
+        queue.announce(b, &announcement(1, 1, 0));
+        queue.announce(a, &announcement(2, 2, 0));
+        queue.next_request(now).unwrap();
+        queue.next_request(now).unwrap();
+        assert_eq!(queue.expire(now), 0);
+        assert_eq!(queue.expire(now + BODY_REQUEST_TIMEOUT), 2);
+        assert_eq!(queue.pending_len(), 2);
+
+        // Block 2 was only announced by `a`
+        queue.remove_peer(&a);
+        assert_eq!(queue.pending_len(), 1);
+        assert_eq!(queue.next_request(now), Some(([1; 32], b)));
+    }
+
+    #[test]
+    fn test_deep_history_prefers_archival_peers() {
+        let mut queue = BodyFetchQueue::new();
+        let pruned = PeerId::random();
+        let archive = PeerId::random();
+        queue.set_peer_services(pruned, ServiceFlags::FULL);
+        queue.set_peer_services(archive, ServiceFlags::FULL | ServiceFlags::ARCHIVAL);
+        let now = Instant::now();
+
+        let tip = DEEP_HISTORY_DEPTH + 10;
+        queue.announce(archive, &announcement(1, tip, 10));
+        queue.announce(pruned, &announcement(2, tip - 1, 0));
+        queue.announce(archive, &announcement(2, tip - 1, 0));
+        queue.announce(pruned, &announcement(3, 1, 0));
+        queue.announce(archive, &announcement(3, 1, 0));
+        assert_eq!(queue.next_request(now), Some(([1; 32], archive)));
+
+        // Near the tip the idle announcer is asked
+        assert_eq!(queue.next_request(now), Some(([2; 32], pruned)));
+        // Deep history waits on the busy archival peer rather than the pruned one
+        assert_eq!(queue.next_request(now), Some(([3; 32], archive)));
+    }
+
+    #[test]
+    fn test_stalled_requests_retried_elsewhere_and_peer_demoted() {
+        let mut queue = BodyFetchQueue::with_policy(SyncPolicy {
+            request_timeout: Duration::from_secs(5),
+            demote_after_stalls: 2,
+        });
+        let slow = PeerId::random();
+        let fast = PeerId::random();
+        let now = Instant::now();
+        let later = now + Duration::from_secs(5);
+
+        // The retry goes to the other announcer
+        queue.announce(slow, &announcement(1, 1, 0));
+        queue.announce(fast, &announcement(1, 1, 0));
+        assert_eq!(queue.next_request(now), Some(([1; 32], slow)));
+        assert_eq!(queue.expire(later), 1);
+        assert_eq!(queue.next_request(later), Some(([1; 32], fast)));
+        assert_eq!(queue.complete(&[1; 32], 0), None);
+
+        // A second stall demotes the slow peer, which is then only asked as
+        // a last resort
+        queue.announce(slow, &announcement(2, 2, 0));
+        assert_eq!(queue.next_request(later), Some(([2; 32], slow)));
+        queue.expire(later + Duration::from_secs(5));
+        assert!(queue.is_demoted(&slow));
+        queue.announce(slow, &announcement(3, 3, 10));
+        queue.announce(fast, &announcement(3, 3, 10));
+        assert_eq!(queue.next_request(later), Some(([3; 32], fast)));
+        assert_eq!(queue.next_request(later), Some(([2; 32], slow)));
+
+        let status = queue.status();
+        assert_eq!(status.timeouts, 2);
+        assert_eq!(status.in_flight, 2);
+        let slow_status = status.peers.iter().find(|p| p.peer == slow.to_string()).unwrap();
+        assert!(slow_status.demoted);
+        assert_eq!((slow_status.stalls, slow_status.in_flight), (2, 1));
+        let fast_status = status.peers.iter().find(|p| p.peer == fast.to_string()).unwrap();
+        assert_eq!((fast_status.delivered, fast_status.demoted), (1, false));
+    }
+}
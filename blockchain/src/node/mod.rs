@@ -0,0 +1,1521 @@
+//! Full node runtime
+//!
+//! `Node` opens the chain store, loads the consensus state persisted in it
+//! (rebuilding it from the stored blocks if there is none), and then runs
+//! the network, RPC server and block/forge processing until SIGINT.
+//! Gossiped blocks that extend the tip are validated, applied and stored;
+//! gossiped forges go through the same admission path as `submitforge`.
+//! Each identified full peer is asked for headers, and blocks found
+//! through them are downloaded and connected by `crate::sync`; a gossiped
+//! block above the tip starts the same catch-up from its relayer.
+//! A downloaded branch with more work than the tip is switched to by
+//! `Node::reorganize` through the reorg guard, which holds reorgs deeper
+//! than `chain.max_reorg_depth` for `acceptreorg`.
+
+use crate::chain::{ChainStore, ReorgDecision, ReorgGuard};
+use crate::config::{ConfigReloader, LogFilterHandle, NodeConfig, ReloadTargets};
+use crate::consensus::{AuthorityKey, Block, ConsensusEngine, ForgeTransaction};
+use crate::events::{BlockEvent, EventBus, NodeEvent, PeerEvent, WebhookNotifier, ZmqPublisher};
+use crate::ledger::LedgerSnapshot;
+use crate::mempool::{BlockTemplateCache, ForgeOrigin, ForgePool, MempoolRevalidator, ValidationQueue};
+use crate::miner::Miner;
+use crate::network::seen::SEEN_MESSAGES_FILE;
+use crate::network::sync::MAX_HEADERS_PER_REQUEST;
+use crate::network::{
+    inventory_hash, unix_now, BlockRelay, NetworkCommand, NetworkEvent, NetworkManager, RejectCode, RejectMessage,
+    RejectedItem, RelayPreferences, SeenMessages, ServiceFlags, SyncRequest, SyncResponse,
+};
+use crate::params::NetworkParams;
+use crate::rpc::{BlockExport, NodeIdentity, RpcServer, NODE_IDENTITY_FILE};
+use crate::shutdown::ShutdownCoordinator;
+use crate::supervisor::Supervisor;
+use crate::sync::{self, ChainSync};
+use crate::wallet::{ExternalSigner, Wallet};
+use crate::watchtower::{SpendLocation, SpendWatch, Watchtower};
+use anyhow::{anyhow, Context, Result};
+use libp2p::{Multiaddr, PeerId};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch, RwLock};
+
+/// Forges held in the mempool
+pub const MEMPOOL_MAX_FORGES: usize = 10_000;
+
+/// Seconds a forge may wait in the mempool before it is dropped
+pub const MEMPOOL_EXPIRY_SECS: u64 = 72 * 60 * 60;
+
+/// Interval of mempool expiry and RPC state refreshes
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Interval at which stalled block downloads are retried and new ones sent
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where and how a node runs, beyond the configuration file
+#[derive(Debug, Clone)]
+pub struct NodeOptions {
+    pub params: NetworkParams,
+    /// Directory holding the chain database and wallet
+    pub data_dir: PathBuf,
+    /// TCP port for P2P connections
+    pub port: u16,
+    /// Address of the HTTP RPC server (requires the `http-server` feature)
+    pub rpc_bind: String,
+    /// Peers dialed on startup
+    pub connect: Vec<Multiaddr>,
+}
+
+impl NodeOptions {
+    /// Default options for a network: its default ports, RPC on localhost
+    pub fn for_network(params: NetworkParams, data_dir: PathBuf) -> Self {
+        Self {
+            port: params.default_port,
+            rpc_bind: format!("127.0.0.1:{}", params.default_rpc_port),
+            params,
+            data_dir,
+            connect: Vec::new(),
+        }
+    }
+
+    /// Options from the configuration's network, data directory, ports
+    /// and peers, with defaults for anything unset
+    pub fn from_config(config: &NodeConfig) -> Result<Self> {
+        let params = config.chain.params()?;
+        let data_dir = match &config.chain.datadir {
+            Some(dir) => dir.clone(),
+            None => default_data_dir(&params)?,
+        };
+        let mut options = Self::for_network(params, data_dir);
+        if let Some(port) = config.network.port {
+            options.port = port;
+        }
+        if let Some(bind) = &config.rpc.bind {
+            options.rpc_bind = bind.clone();
+        }
+        options.connect = config.network.connect_peers()?;
+        Ok(options)
+    }
+
+    /// Warnings for ports that belong to another network
+    pub fn port_warnings(&self) -> Vec<String> {
+        let rpc_port = self
+            .rpc_bind
+            .rsplit_once(':')
+            .and_then(|(_, port)| port.parse().ok())
+            .unwrap_or(self.params.default_rpc_port);
+        self.params.port_conflicts(self.port, rpc_port)
+    }
+}
+
+/// `~/.excalibur`, or `~/.excalibur/<network>` for test networks
+pub fn default_data_dir(params: &NetworkParams) -> Result<PathBuf> {
+    let home = std::env::var_os("HOME").ok_or_else(|| anyhow!("HOME is not set; set a data directory"))?;
+    let base = PathBuf::from(home).join(".excalibur");
+    Ok(match params.name.as_str() {
+        "mainnet" => base,
+        name => base.join(name),
+    })
+}
+
+/// A running node's components
+pub struct Node {
+    config: NodeConfig,
+    options: NodeOptions,
+    store: Arc<ChainStore>,
+    engine: Arc<ConsensusEngine>,
+    pool: Arc<ForgePool>,
+    /// Forges waiting for validation, local submissions first
+    validation: Arc<ValidationQueue>,
+    sync: Arc<Mutex<ChainSync>>,
+    /// When gossiped blocks are relayed
+    relay: BlockRelay,
+    /// Holds back reorgs deeper than `chain.max_reorg_depth`
+    reorg_guard: Arc<ReorgGuard>,
+    /// Alerts when transfers spend watched outputs
+    spend_watch: Arc<SpendWatch>,
+    events: EventBus,
+    shutdown: ShutdownCoordinator,
+    /// Hash of each block connected, for the miner to drop stale work
+    tips: watch::Sender<[u8; 32]>,
+    /// Configuration file re-read on SIGHUP or `reloadconfig`, with its
+    /// contents at startup
+    config_file: Option<(PathBuf, NodeConfig)>,
+    log_filter: Option<LogFilterHandle>,
+}
+
+impl Node {
+    /// Open the chain database in the data directory and load its state
+    pub fn open(config: NodeConfig, options: NodeOptions) -> Result<Self> {
+        std::fs::create_dir_all(&options.data_dir)
+            .with_context(|| format!("Failed to create data directory {}", options.data_dir.display()))?;
+        let store = ChainStore::new(options.data_dir.join("chain"))?;
+        Self::with_store(config, options, store)
+    }
+
+    /// Load a node's state from an already opened chain store
+    pub fn with_store(config: NodeConfig, options: NodeOptions, store: ChainStore) -> Result<Self> {
+        if !store.consensus_state_matches_tip()? {
+            tracing::warn!("Persisted consensus state does not match the chain tip; rebuilding it from stored blocks");
+            store.clear_consensus_state()?;
+        }
+        let resumed = store.consensus_record()?.is_some();
+        let chain = &options.params.chain;
+        let engine = ConsensusEngine::from_store(&store, chain.initial_difficulty, chain.min_block_time)?
+            .with_params(&options.params);
+
+        let report = store
+            .verify_chain(&engine, config.chain.check_level, config.chain.check_blocks)
+            .context("Chain database failed startup verification")?;
+        tracing::info!(
+            "Verified {} blocks ({} forges) at level {}",
+            report.blocks_checked,
+            report.forges_checked,
+            u8::from(report.level)
+        );
+
+        // Persisted state already covers the tip. Otherwise the state is
+        // rebuilt by validating every stored block, and a snapshot-based
+        // store (no blocks below the snapshot) loads its snapshot first.
+        let start = match &config.chain.load_snapshot {
+            _ if resumed => engine.get_height() + 1,
+            Some(path) if store.get_block(0)?.is_none() => {
+                let snapshot = LedgerSnapshot::read_from(path)?;
+                engine.load_snapshot(&snapshot, &options.params)?;
+                tracing::info!("Loaded ledger snapshot at height {}", snapshot.height);
+                snapshot.height + 1
+            }
+            _ => 0,
+        };
+        if store.get_best_block()?.is_some() && start <= store.get_height()? {
+            let tip = store.get_height()?;
+            for height in start..=tip {
+                let block = store
+                    .load_block(height)?
+                    .ok_or_else(|| anyhow!("Missing block at height {}", height))?;
+                let parent_hash = if height == 0 { [0u8; 32] } else { engine.get_tip_hash() };
+                engine
+                    .validate_block(&block, &parent_hash)
+                    .map_err(|e| anyhow!("Stored block {} failed validation: {}", height, e))?;
+                engine.apply_block(&block)?;
+            }
+            tracing::info!("Loaded chain state at height {}", tip);
+        }
+
+        if config.chain.txindex {
+            store.build_forge_index()?;
+        } else if store.forge_index_enabled()? {
+            store.drop_forge_index()?;
+        }
+        if config.chain.searchindex {
+            store.build_search_index()?;
+        } else if store.search_index_enabled()? {
+            store.drop_search_index()?;
+        }
+
+        let pool = Self::open_mempool(&config)?.with_chain_params(&options.params.chain);
+        pool.set_tip_height(engine.get_height());
+        let sync = ChainSync::new(config.network.sync_policy(), config.chain.header_guard());
+        let relay = config.network.block_relay();
+        let events = EventBus::new();
+        let reorg_guard = Arc::new(ReorgGuard::new(config.chain.max_reorg_depth, events.clone()));
+
+        let (tips, _) = watch::channel(engine.get_tip_hash());
+        let store = Arc::new(store);
+        let spend_watch = Arc::new(SpendWatch::new(Arc::clone(&store), events.clone()));
+        let engine = Arc::new(engine);
+        let pool = Arc::new(pool);
+        let validation = Arc::new(ValidationQueue::new(
+            Arc::clone(&engine),
+            Arc::clone(&pool),
+            config.mempool.validation_workers,
+            config.mempool.local_queue_size,
+            config.mempool.gossip_queue_size,
+        ));
+        Ok(Self {
+            sync: Arc::new(Mutex::new(sync)),
+            relay,
+            config,
+            options,
+            store,
+            engine,
+            pool,
+            validation,
+            events,
+            reorg_guard,
+            spend_watch,
+            shutdown: ShutdownCoordinator::new(),
+            tips,
+            config_file: None,
+            log_filter: None,
+        })
+    }
+
+    #[cfg(feature = "wasm-policy")]
+    fn open_mempool(config: &NodeConfig) -> Result<ForgePool> {
+        let pool = ForgePool::new(config.mempool.max_forges, config.mempool.min_fee)
+            .with_rolling_min_fee(config.mempool.incremental_fee, config.mempool.min_fee_half_life_secs);
+        Ok(match &config.mempool.policy_filter {
+            Some(path) => pool.with_policy(Box::new(crate::mempool::wasm::WasmPolicy::load(
+                path,
+                config.mempool.policy_fuel,
+            )?)),
+            None => pool,
+        })
+    }
+
+    #[cfg(not(feature = "wasm-policy"))]
+    fn open_mempool(config: &NodeConfig) -> Result<ForgePool> {
+        if config.mempool.policy_filter.is_some() {
+            return Err(anyhow!("mempool.policy_filter requires the wasm-policy feature"));
+        }
+        Ok(ForgePool::new(config.mempool.max_forges, config.mempool.min_fee)
+            .with_rolling_min_fee(config.mempool.incremental_fee, config.mempool.min_fee_half_life_secs))
+    }
+
+    /// Re-read `path` on SIGHUP or `reloadconfig`, applying settings that
+    /// changed from `loaded`, the file as read at startup. A `log_filter`
+    /// handle lets reloads change the log level.
+    pub fn watch_config(&mut self, path: PathBuf, loaded: NodeConfig, log_filter: Option<LogFilterHandle>) {
+        self.config_file = Some((path, loaded));
+        self.log_filter = log_filter;
+    }
+
+    /// Coordinator that stops the node when triggered
+    pub fn shutdown_handle(&self) -> ShutdownCoordinator {
+        self.shutdown.clone()
+    }
+
+    /// Height and hash of the chain tip, if any block is connected or a
+    /// snapshot was loaded
+    pub fn tip(&self) -> Result<Option<(u64, [u8; 32])>> {
+        if let Some(hash) = self.store.get_best_block()? {
+            return Ok(Some((self.store.get_height()?, hash)));
+        }
+        Ok(self
+            .engine
+            .snapshot_status()
+            .map(|status| (status.height, self.engine.get_tip_hash())))
+    }
+
+    /// Height and parent hash the next block must have
+    fn next_block(&self) -> Result<(u64, [u8; 32])> {
+        Ok(match self.tip()? {
+            Some((height, hash)) => (height + 1, hash),
+            None => (0, [0u8; 32]),
+        })
+    }
+
+    /// Whether a block builds on the current tip
+    pub fn extends_tip(&self, block: &Block) -> Result<bool> {
+        let (height, parent_hash) = self.next_block()?;
+        Ok(block.header.height == height && block.header.prev_block_hash == parent_hash)
+    }
+
+    /// Validate a block extending the tip, apply it and store it
+    pub fn connect_block(&self, block: &Block) -> Result<[u8; 32]> {
+        let (next_height, parent_hash) = self.next_block()?;
+        if block.header.height != next_height || block.header.prev_block_hash != parent_hash {
+            return Err(anyhow!(
+                "Block at height {} does not extend the tip (next height {})",
+                block.header.height,
+                next_height
+            ));
+        }
+
+        self.engine.validate_block(block, &parent_hash)?;
+
+        // The block, its indexes and the engine state are written in one
+        // batch before anything in memory changes
+        let hash = self.engine.compute_block_hash(&block.header);
+        let height = block.header.height;
+        let effects = self.engine.block_effects(block)?;
+        let batch = self.store.connect_batch(block, &hash, &effects.ledger_info, &effects.used_proofs_hash)?;
+        self.engine.commit_block(block, effects, batch)?;
+
+        self.pool.remove_block_forges(block)?;
+        self.pool.remove_block_transfers(block)?;
+        for transfer in &block.transfers {
+            if let Err(e) = self.spend_watch.observe_transfer(&transfer.transfer, SpendLocation::Block { height, hash }) {
+                tracing::warn!("Failed to check a block transfer for watched outputs: {}", e);
+            }
+        }
+        self.pool.set_tip_height(height);
+        self.tips.send_replace(hash);
+        self.events.publish(NodeEvent::Block(BlockEvent {
+            height,
+            hash: hex::encode(hash),
+            forges: block.forges.len(),
+            timestamp: block.header.timestamp,
+        }));
+        tracing::info!("Connected block {} at height {}", hex::encode(hash), height);
+        Ok(hash)
+    }
+
+    /// Switch the main chain to `branch`, consecutive blocks the first of
+    /// which builds on a main-chain block, if it has more work than the tip
+    /// and the reorg guard lets it through. If a branch block fails to
+    /// connect, the previous chain is restored. Returns whether the chain
+    /// switched.
+    pub fn reorganize(&self, branch: &[Block]) -> Result<bool> {
+        let (Some(last), Some((_, tip_hash))) = (branch.last(), self.tip()?) else {
+            return Ok(false);
+        };
+        for block in branch {
+            let hash = self.engine.compute_block_hash(&block.header);
+            if self.store.get_header_index(&hash)?.is_none() {
+                self.store.index_header(&hash, &block.header)?;
+            }
+        }
+        let candidate_hash = self.engine.compute_block_hash(&last.header);
+        let chainwork = |hash: &[u8; 32]| -> Result<_> {
+            Ok(self
+                .store
+                .get_header_index(hash)?
+                .ok_or_else(|| anyhow!("Header {} is not indexed", hex::encode(hash)))?
+                .chainwork())
+        };
+        if chainwork(&candidate_hash)? <= chainwork(&tip_hash)? {
+            tracing::debug!("Branch to {} has no more work than the tip", hex::encode(candidate_hash));
+            return Ok(false);
+        }
+        let fork = match self.reorg_guard.check(&self.store, &tip_hash, &candidate_hash)? {
+            ReorgDecision::Proceed(fork) => fork,
+            ReorgDecision::AwaitingConfirmation(pending) => {
+                tracing::warn!(
+                    "Reorg of depth {} to {} awaits acceptreorg",
+                    pending.fork.disconnect,
+                    hex::encode(candidate_hash)
+                );
+                return Ok(false);
+            }
+        };
+
+        let mut disconnected = Vec::new();
+        let mut result = Ok(());
+        while result.is_ok() && self.engine.get_height() > fork.height {
+            result = self.disconnect_tip().map(|block| disconnected.push(block));
+        }
+        let reached_fork = result.is_ok();
+        if reached_fork {
+            result = branch
+                .iter()
+                .filter(|block| block.header.height > fork.height)
+                .try_for_each(|block| self.connect_block(block).map(|_| ()));
+        }
+        if let Err(e) = result {
+            // Drop the branch blocks connected so far, then reconnect the
+            // previous chain
+            let restore = || -> Result<()> {
+                while reached_fork && self.engine.get_height() > fork.height {
+                    self.disconnect_tip()?;
+                }
+                for block in disconnected.iter().rev() {
+                    self.connect_block(block)?;
+                }
+                Ok(())
+            };
+            restore().context("Failed to restore the chain after a failed reorg")?;
+            return Err(e.context(format!("Reorg to {} failed; kept the previous chain", hex::encode(candidate_hash))));
+        }
+
+        // Forges and transfers the new chain doesn't include go back
+        // through validation
+        for block in disconnected {
+            for forge in block.forges {
+                let _ = self.validation.submit(forge, ForgeOrigin::Reorg);
+            }
+            for transfer in block.transfers {
+                if self.engine.check_transfer(&transfer).is_ok() {
+                    let _ = self.pool.add_transfer(transfer);
+                }
+            }
+        }
+        tracing::info!(
+            "Reorganized to {} at height {} (fork at height {})",
+            hex::encode(candidate_hash),
+            self.engine.get_height(),
+            fork.height
+        );
+        Ok(true)
+    }
+
+    /// Disconnect the tip block from the engine and the store, returning it
+    fn disconnect_tip(&self) -> Result<Block> {
+        let (height, hash) = self.tip()?.ok_or_else(|| anyhow!("No chain tip to disconnect"))?;
+        let block = self
+            .store
+            .load_block(height)?
+            .ok_or_else(|| anyhow!("Missing block at height {}", height))?;
+        let batch = self.store.disconnect_batch(&block, &hash)?;
+        self.engine.disconnect_block_with(&block, batch)?;
+        self.pool.set_tip_height(height - 1);
+        self.tips.send_replace(block.header.prev_block_hash);
+        Ok(block)
+    }
+
+    /// Run the node until SIGINT or `shutdown_handle().trigger()`
+    pub async fn run(self) -> Result<()> {
+        for warning in self.options.port_warnings() {
+            tracing::warn!("Possible network mix-up: {}", warning);
+        }
+
+        let network_config = &self.config.network;
+        let listen_addr: Multiaddr = format!("/ip4/0.0.0.0/tcp/{}", self.options.port).parse()?;
+        let preferences = RelayPreferences {
+            services: network_config.services(),
+            ..RelayPreferences::default()
+        };
+        let (mut network, commands, mut network_events) =
+            NetworkManager::for_network(
+                listen_addr,
+                self.options.connect.clone(),
+                preferences,
+                &network_config.gossip,
+                &self.options.params,
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to start networking: {}", e))?;
+        network.set_peer_upload_limit(network_config.peer_upload_limit());
+        network.set_reconnect_schedule(network_config.reconnect_schedule());
+        network.set_known_inventory(network_config.known_inventory);
+        network.set_hold_blocks(true);
+        network.set_peer_filter(network_config.peer_filter()?);
+        let seen_path = self.options.data_dir.join(SEEN_MESSAGES_FILE);
+        let window = network_config.seen_messages_window();
+        match SeenMessages::load(&seen_path, network_config.seen_messages, window, unix_now()) {
+            Ok(seen) => {
+                tracing::info!("Loaded {} recently seen gossip messages", seen.len());
+                network.set_seen_messages(seen);
+            }
+            Err(e) => {
+                tracing::warn!("Starting with no seen gossip messages: {:#}", e);
+                network.set_seen_messages(SeenMessages::new(network_config.seen_messages, window));
+            }
+        }
+        let bandwidth = network.bandwidth();
+        let inventory = network.inventory();
+        let connections = network.connections();
+        let peer_filter = network.peer_filter();
+        let peer_services = network.peer_services();
+        let reconnects = network.reconnects();
+        let seen_messages = network.seen_messages();
+        let supervisor = Supervisor::new(self.shutdown.subscribe(), self.events.clone());
+        let policy = self.config.supervisor.restart_policy();
+        // The swarm can't be rebuilt, so the network loop runs once
+        let network_task = supervisor.spawn_once("network", true, async move {
+            network.run().await;
+            Err(anyhow!("Network loop stopped"))
+        })?;
+        for address in &self.options.connect {
+            commands.send(NetworkCommand::ConnectPeer(address.clone())).await?;
+        }
+
+        // Also started without webhooks to finish deliveries queued before
+        // they were removed from the config, or for ones a reload adds
+        let notifier = WebhookNotifier::new(
+            self.config.events.webhooks.clone(),
+            self.config.events.webhook_min_severity,
+            Arc::clone(&self.store),
+        );
+        if !self.config.events.webhooks.is_empty()
+            || self.config_file.is_some()
+            || !self.store.list_notifications()?.is_empty()
+        {
+            let notifier = notifier.clone();
+            let events = self.events.clone();
+            let shutdown = self.shutdown.clone();
+            supervisor.spawn("webhooks", false, policy, move || {
+                finished(notifier.clone().run(events.subscribe(), shutdown.subscribe()))
+            })?;
+        }
+        if let Some(endpoint) = &self.config.events.zmq_pub_sequence {
+            // Bound up front so a bad endpoint fails startup; restarts rebind
+            let bound = Mutex::new(Some(ZmqPublisher::bind(endpoint).await?));
+            tracing::info!("Publishing mempool sequence notifications on {}", endpoint);
+            let endpoint = endpoint.clone();
+            let pool = Arc::clone(&self.pool);
+            let shutdown = self.shutdown.clone();
+            supervisor.spawn("zmq", false, policy, move || {
+                let publisher = bound.lock().unwrap().take();
+                let endpoint = endpoint.clone();
+                let (mempool, shutdown) = (pool.subscribe(), shutdown.subscribe());
+                async move {
+                    let publisher = match publisher {
+                        Some(publisher) => publisher,
+                        None => ZmqPublisher::bind(&endpoint).await?,
+                    };
+                    publisher.run(mempool, shutdown).await;
+                    Ok(())
+                }
+            })?;
+        }
+        let reloader = match &self.config_file {
+            Some((path, loaded)) => {
+                let targets = ReloadTargets {
+                    log_filter: self.log_filter.clone(),
+                    pool: Arc::clone(&self.pool),
+                    webhooks: notifier,
+                    peer_filter: Arc::clone(&peer_filter),
+                    commands: commands.clone(),
+                };
+                Some(Arc::new(ConfigReloader::new(path.clone(), loaded, targets)?))
+            }
+            None => None,
+        };
+
+        let watchtower = self.config.watchtower.enabled.then(|| {
+            let mut tower = Watchtower::new(Arc::clone(&self.store), self.events.clone());
+            if let Some(authorities) = self.engine.authorities() {
+                tower = tower.with_authorities(authorities.clone());
+            }
+            if self.config.watchtower.gossip_evidence {
+                tower.with_gossip(commands.clone())
+            } else {
+                tower
+            }
+        });
+
+        let templates = Arc::new(BlockTemplateCache::new(Arc::clone(&self.engine), Arc::clone(&self.pool)));
+        let shutdown = self.shutdown.clone();
+        let prewarmer = Arc::clone(&templates);
+        supervisor.spawn("templates", false, policy, move || {
+            finished(Arc::clone(&prewarmer).run(shutdown.subscribe()))
+        })?;
+        let shutdown = self.shutdown.clone();
+        let validation = Arc::clone(&self.validation);
+        supervisor.spawn("validation", false, policy, move || {
+            finished(Arc::clone(&validation).run(shutdown.subscribe()))
+        })?;
+        let revalidator = Arc::new(MempoolRevalidator::new(Arc::clone(&self.engine), Arc::clone(&self.pool)));
+        let (tips, shutdown) = (self.tips.subscribe(), self.shutdown.clone());
+        supervisor.spawn("revalidator", false, policy, move || {
+            finished(Arc::clone(&revalidator).run(tips.clone(), shutdown.subscribe()))
+        })?;
+        let (pool, shutdown) = (Arc::clone(&self.pool), self.shutdown.clone());
+        let spend_watch = Arc::clone(&self.spend_watch);
+        supervisor.spawn("spend-watch", false, policy, move || {
+            finished(Arc::clone(&spend_watch).run(pool.subscribe_transfers(), shutdown.subscribe()))
+        })?;
+
+        let (mined_sender, mut mined_blocks) = mpsc::channel(1);
+        let authority_key = self.authority_key()?;
+        let miner = self.config.mining.enabled.then(|| {
+            let miner = Miner::new(Arc::clone(&self.engine), Arc::clone(&templates), self.config.mining.nonce_batch);
+            Arc::new(match &authority_key {
+                Some(key) => miner.with_authority(key.clone()),
+                None => miner,
+            })
+        });
+        let submitted_blocks = mined_sender.clone();
+        if let Some(miner) = &miner {
+            let miner = Arc::clone(miner);
+            let tips = self.tips.subscribe();
+            let shutdown = self.shutdown.clone();
+            supervisor.spawn("miner", false, policy, move || {
+                Arc::clone(&miner).run(mined_sender.clone(), tips.clone(), shutdown.subscribe())
+            })?;
+            tracing::info!("Mining enabled");
+        }
+
+        let mut rpc = self.rpc_server(&supervisor, &commands, bandwidth, inventory, peer_services, reconnects)?;
+        rpc.register_template_handlers(Arc::clone(&templates), miner);
+        if self.engine.authorities().is_some() {
+            rpc.register_authority_handlers(Arc::clone(&self.engine), authority_key, submitted_blocks);
+        }
+        rpc.register_network_info_handlers(connections);
+        rpc.register_peer_filter_handlers(peer_filter);
+        rpc.register_config_handlers(reloader.clone());
+        let (cache, events, shutdown) = (Arc::clone(rpc.response_cache()), self.events.clone(), self.shutdown.clone());
+        supervisor.spawn("response-cache", false, policy, move || {
+            finished(Arc::clone(&cache).run(events.subscribe(), shutdown.subscribe()))
+        })?;
+        let rpc_task = self.spawn_rpc(&supervisor, &rpc)?;
+
+        let mut maintenance = tokio::time::interval(MAINTENANCE_INTERVAL);
+        let mut sync_tick = tokio::time::interval(SYNC_INTERVAL);
+        let mut shutdown = self.shutdown.subscribe();
+        let mut hangups = Hangups::new()?;
+        let mut peers = 0usize;
+        tracing::info!(
+            "Node running on {} (P2P port {}, height {})",
+            self.options.params.name,
+            self.options.port,
+            self.engine.get_height()
+        );
+
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {
+                    tracing::info!("Received SIGINT, shutting down");
+                    self.shutdown.trigger();
+                }
+                _ = shutdown.recv() => break,
+                _ = hangups.recv() => match &reloader {
+                    Some(reloader) => match reloader.reload().await {
+                        Ok(report) => tracing::info!("Reloaded {}: {}", reloader.path().display(), report),
+                        Err(e) => tracing::error!("Config reload failed, nothing changed: {:#}", e),
+                    },
+                    None => tracing::warn!("Received SIGHUP, but the node was started without a config file"),
+                },
+                Some(event) = network_events.recv() => {
+                    match event {
+                        NetworkEvent::PeerConnected(peer) => {
+                            peers += 1;
+                            self.events.publish(NodeEvent::Peer(PeerEvent { peer: peer.to_string(), connected: true }));
+                        }
+                        NetworkEvent::PeerDisconnected(peer) => {
+                            peers = peers.saturating_sub(1);
+                            self.sync.lock().unwrap().peer_failed(&peer);
+                            self.events.publish(NodeEvent::Peer(PeerEvent { peer: peer.to_string(), connected: false }));
+                        }
+                        event => self.handle_network_event(event, &commands, watchtower.as_ref()).await,
+                    }
+                }
+                Some(block) = mined_blocks.recv() => {
+                    self.connect_mined(&block, &commands).await;
+                }
+                _ = sync_tick.tick() => {
+                    self.request_blocks(&commands).await;
+                }
+                _ = maintenance.tick() => {
+                    let expired = self.pool.remove_expired(MEMPOOL_EXPIRY_SECS);
+                    if expired > 0 {
+                        tracing::info!("Expired {} forges from the mempool", expired);
+                    }
+                    rpc.update_state(self.engine.get_height(), self.engine.get_total_forges(), peers).await;
+                }
+            }
+        }
+
+        if let Some(task) = rpc_task {
+            if let Err(e) = task.await {
+                tracing::warn!("RPC server task failed: {}", e);
+            }
+        }
+        network_task.abort();
+        if let Err(e) = seen_messages.save(&seen_path) {
+            tracing::warn!("Failed to save seen gossip messages: {:#}", e);
+        }
+        self.store.compact();
+        tracing::info!("Node stopped at height {}", self.engine.get_height());
+        Ok(())
+    }
+
+    fn rpc_server(
+        &self,
+        supervisor: &Supervisor,
+        commands: &mpsc::Sender<NetworkCommand>,
+        bandwidth: Arc<crate::network::BandwidthTracker>,
+        inventory: Arc<crate::network::PeerInventory>,
+        peer_services: Arc<crate::network::PeerServices>,
+        reconnects: Arc<crate::network::ReconnectSchedule>,
+    ) -> Result<RpcServer> {
+        let mut wallet = Wallet::open(self.options.data_dir.join("wallet.json"), self.options.params.network)?;
+        if let Some(signer) = &self.config.wallet.signer {
+            wallet.set_external_signer(Some(ExternalSigner::new(signer, self.config.wallet.signer_args.clone())));
+        }
+
+        let mut rpc = RpcServer::new();
+        rpc.response_cache().set_capacity(self.config.rpc.response_cache_entries);
+        rpc.register_block_handlers(Arc::clone(&self.store));
+        rpc.register_ledger_handlers(
+            Arc::clone(&self.engine),
+            Arc::clone(&self.store),
+            self.config.chain.treasury_addresses.clone(),
+        );
+        rpc.register_submit_handlers(Arc::clone(&self.validation), Arc::clone(&self.pool), Some(commands.clone()));
+        rpc.register_index_handlers(Arc::clone(&self.store));
+        rpc.register_forge_handlers(Arc::clone(&self.store), Arc::clone(&self.pool));
+        rpc.register_search_handlers(Arc::clone(&self.store));
+        rpc.register_prophecy_handlers(Arc::clone(&self.store));
+        rpc.register_spend_watch_handlers(Arc::clone(&self.spend_watch));
+        if self.config.watchtower.enabled {
+            rpc.register_watchtower_handlers(Arc::clone(&self.store));
+        }
+        rpc.register_notification_handlers(Arc::clone(&self.store));
+        rpc.register_supervisor_handlers(supervisor.clone());
+        rpc.register_network_handlers(bandwidth, inventory);
+        rpc.register_peer_role_handlers(peer_services);
+        rpc.register_sync_handlers(self.sync.lock().unwrap().body_queue(), reconnects);
+        rpc.register_blockchain_info_handlers(
+            Arc::clone(&self.engine),
+            self.options.params.name.clone(),
+            self.sync.lock().unwrap().body_queue(),
+        );
+        rpc.register_mempool_handlers(Arc::clone(&self.pool));
+        rpc.register_transfer_handlers(Arc::clone(&self.engine), Arc::clone(&self.pool));
+        if self.config.rpc.debug {
+            rpc.register_debug_handlers(
+                Arc::clone(&self.engine),
+                Arc::clone(&self.store),
+                Arc::clone(&self.sync),
+                Arc::clone(&self.validation),
+            );
+        }
+        rpc.enable_subscriptions(self.events.clone());
+        rpc.register_reorg_handlers(Arc::clone(&self.reorg_guard));
+        let wallet = Arc::new(RwLock::new(wallet));
+        rpc.register_wallet_handlers(Arc::clone(&wallet));
+        rpc.register_multisig_handlers(self.options.params.network);
+        rpc.register_vault_handlers(Arc::clone(&wallet), Arc::clone(&self.engine), Arc::clone(&self.pool));
+        rpc.register_funding_handlers(wallet, Arc::clone(&self.engine), self.pool.dust_threshold());
+        if let Some(token) = &self.config.rpc.export_token {
+            rpc.enable_block_export(BlockExport::new(Arc::clone(&self.store), token)?);
+        }
+        if self.config.rpc.sign_responses {
+            let identity = NodeIdentity::load_or_create(self.options.data_dir.join(NODE_IDENTITY_FILE))?;
+            tracing::info!("Signing RPC read responses as node {}", hex::encode(identity.node_id()));
+            rpc.enable_response_signing(identity);
+        }
+        Ok(rpc)
+    }
+
+    #[cfg(feature = "http-server")]
+    fn spawn_rpc(&self, supervisor: &Supervisor, rpc: &RpcServer) -> Result<Option<tokio::task::JoinHandle<()>>> {
+        let auth = crate::rpc::RpcAuth::from_config(
+            self.config.rpc.user.as_deref(),
+            self.config.rpc.password.as_deref(),
+            &self.options.data_dir,
+        )?;
+        if let Some(path) = auth.cookie_path() {
+            tracing::info!("RPC cookie written to {}", path.display());
+        }
+        let mut rpc = rpc.clone();
+        rpc.enable_auth(auth.clone());
+        rpc.set_policy(self.config.rpc.policy());
+        let addr = self.options.rpc_bind.clone();
+        let shutdown = self.shutdown.clone();
+        let grace = self.config.rpc.shutdown_grace();
+        let server = supervisor.spawn("rpc", true, self.config.supervisor.restart_policy(), move || {
+            let (rpc, addr, shutdown) = (rpc.clone(), addr.clone(), shutdown.subscribe());
+            async move {
+                rpc.run_http(&addr, shutdown, grace)
+                    .await
+                    .map_err(|e| anyhow!("RPC server on {} failed: {:#}", addr, e))
+            }
+        })?;
+        Ok(Some(tokio::spawn(async move {
+            let _ = server.await;
+            auth.remove_cookie();
+        })))
+    }
+
+    #[cfg(not(feature = "http-server"))]
+    fn spawn_rpc(&self, _supervisor: &Supervisor, _rpc: &RpcServer) -> Result<Option<tokio::task::JoinHandle<()>>> {
+        tracing::warn!("Built without the http-server feature; RPC is not served");
+        Ok(None)
+    }
+
+    async fn handle_network_event(
+        &self,
+        event: NetworkEvent,
+        commands: &mpsc::Sender<NetworkCommand>,
+        watchtower: Option<&Watchtower>,
+    ) {
+        let reject = match event {
+            NetworkEvent::BlockReceived(data, peer) => self.block_received(&data, peer, commands, watchtower).await,
+            NetworkEvent::TransactionReceived(data, peer) => {
+                let Ok(forge) = ForgeTransaction::decode(&data) else {
+                    tracing::debug!("Undecodable forge from {}", peer);
+                    return;
+                };
+                if let Some(tower) = watchtower {
+                    if let Err(e) = tower.observe_forge(&forge) {
+                        tracing::warn!("Watchtower failed to observe forge: {}", e);
+                    }
+                }
+                let proof_hash = forge.proof_hash;
+                let outcome = match self.validation.submit(forge, ForgeOrigin::Gossip) {
+                    Ok(outcome) => outcome,
+                    Err(e) => {
+                        tracing::debug!("Dropped forge from {}: {}", peer, e);
+                        return;
+                    }
+                };
+                // Validation runs behind queued forges; reject once it's done
+                let commands = commands.clone();
+                tokio::spawn(async move {
+                    if let Ok(Err(e)) = outcome.await {
+                        let message =
+                            RejectMessage::new(RejectedItem::Forge, proof_hash, RejectCode::Invalid, &e.to_string());
+                        let _ = commands.send(NetworkCommand::RejectItem(peer, message)).await;
+                    }
+                });
+                None
+            }
+            NetworkEvent::EvidenceReceived(data, peer) => {
+                if let Some(tower) = watchtower {
+                    if let Err(e) = tower.import_evidence(&data) {
+                        tracing::debug!("Invalid evidence from {}: {}", peer, e);
+                    }
+                }
+                None
+            }
+            NetworkEvent::RejectReceived(peer, message) => {
+                tracing::info!("Peer {} rejected {:?}: {:?} {}", peer, message.item, message.code, message.reason);
+                None
+            }
+            NetworkEvent::PeerPreferences(peer, preferences) => {
+                self.sync.lock().unwrap().body_queue().lock().unwrap().set_peer_services(peer, preferences.services);
+                if preferences.services.contains(ServiceFlags::FULL) {
+                    self.request_headers(peer, commands).await;
+                }
+                None
+            }
+            NetworkEvent::SyncRequested(peer, id, request) => {
+                // Forge proofs are only served when advertised
+                let light_request = matches!(request, SyncRequest::GetForgeProof { .. });
+                let response = if light_request && !self.config.network.light_serve {
+                    request.empty_response()
+                } else {
+                    sync::serve(&self.store, &request).unwrap_or_else(|e| {
+                        tracing::warn!("Failed to serve sync request from {}: {}", peer, e);
+                        request.empty_response()
+                    })
+                };
+                let _ = commands.send(NetworkCommand::RespondSync(id, response)).await;
+                None
+            }
+            NetworkEvent::SyncResponseReceived(peer, SyncResponse::Headers { headers }) => {
+                let received = self
+                    .sync
+                    .lock()
+                    .unwrap()
+                    .headers_received(&self.store, &self.engine, peer, &headers);
+                match received {
+                    Ok(queued) => {
+                        tracing::debug!("{} headers from {}, {} new blocks to fetch", headers.len(), peer, queued);
+                        if headers.len() == MAX_HEADERS_PER_REQUEST {
+                            self.request_headers(peer, commands).await;
+                        }
+                        self.request_blocks(commands).await;
+                    }
+                    Err(e) => {
+                        tracing::warn!("Disconnecting peer {}: bad headers: {:#}", peer, e);
+                        self.sync.lock().unwrap().peer_failed(&peer);
+                        let _ = commands.send(NetworkCommand::DisconnectPeer(peer)).await;
+                    }
+                }
+                None
+            }
+            NetworkEvent::SyncResponseReceived(peer, SyncResponse::Blocks { blocks }) => {
+                if blocks.is_empty() {
+                    // The peer doesn't have what it announced; ask others
+                    self.sync.lock().unwrap().peer_failed(&peer);
+                    return;
+                }
+                {
+                    let mut sync = self.sync.lock().unwrap();
+                    for block in blocks {
+                        if !sync.block_received(&self.engine, peer, block) {
+                            tracing::debug!("Ignoring unrequested block from {}", peer);
+                        }
+                    }
+                }
+                let reject = self.connect_downloaded();
+                self.request_blocks(commands).await;
+                reject
+            }
+            NetworkEvent::SyncFailed(peer) => {
+                self.sync.lock().unwrap().peer_failed(&peer);
+                None
+            }
+            _ => None,
+        };
+
+        if let Some((peer, message)) = reject {
+            let _ = commands.send(NetworkCommand::RejectItem(peer, message)).await;
+        }
+    }
+
+    /// Handle a gossiped block. It is relayed once prevalidated if
+    /// optimistic relay allows, or else once connected; a block failing
+    /// validation is dropped, or revoked if it was already relayed. Returns
+    /// the reject to send its source.
+    async fn block_received(
+        &self,
+        data: &[u8],
+        peer: PeerId,
+        commands: &mpsc::Sender<NetworkCommand>,
+        watchtower: Option<&Watchtower>,
+    ) -> Option<(PeerId, RejectMessage)> {
+        let gossip_hash = inventory_hash(data);
+        let Ok(block) = Block::decode(data) else {
+            tracing::debug!("Undecodable block from {}", peer);
+            let _ = commands.send(NetworkCommand::DiscardBlock(gossip_hash, true)).await;
+            return None;
+        };
+        let hash = self.engine.compute_block_hash(&block.header);
+        if let Some(tower) = watchtower {
+            if let Err(e) = tower.observe_block(&block, &hash) {
+                tracing::warn!("Watchtower failed to observe block: {}", e);
+            }
+        }
+        match self.extends_tip(&block) {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::debug!("Ignoring block {} from {}: not on the tip", hex::encode(hash), peer);
+                let _ = commands.send(NetworkCommand::DiscardBlock(gossip_hash, false)).await;
+                // A block above the tip means the peer is ahead
+                if self.next_block().is_ok_and(|(height, _)| block.header.height > height) {
+                    self.request_headers(peer, commands).await;
+                }
+                return None;
+            }
+            Err(e) => {
+                tracing::error!("Failed to read chain tip: {}", e);
+                let _ = commands.send(NetworkCommand::DiscardBlock(gossip_hash, false)).await;
+                return None;
+            }
+        }
+        let reject = |e: anyhow::Error| {
+            let message = RejectMessage::new(RejectedItem::Block, hash, RejectCode::Invalid, &e.to_string());
+            Some((peer, message))
+        };
+
+        if let Err(e) = self.engine.prevalidate_block(&block, &block.header.prev_block_hash) {
+            let _ = commands.send(NetworkCommand::DiscardBlock(gossip_hash, true)).await;
+            return reject(e);
+        }
+        if self.relay.prevalidated(&hash, peer) {
+            let _ = commands.send(NetworkCommand::RelayBlock(gossip_hash)).await;
+        }
+        match self.connect_block(&block) {
+            Ok(_) => {
+                if self.relay.validated(&hash) {
+                    let _ = commands.send(NetworkCommand::RelayBlock(gossip_hash)).await;
+                }
+                None
+            }
+            Err(e) => {
+                let revocation = self.relay.failed(&hash, peer);
+                if revocation.disconnect {
+                    tracing::warn!(
+                        "Disconnecting peer {}: {} of its blocks failed validation after prevalidation",
+                        peer,
+                        revocation.failures
+                    );
+                    let _ = commands.send(NetworkCommand::DisconnectPeer(peer)).await;
+                }
+                if revocation.relayed {
+                    // Every block-topic peer, the source included, is told
+                    let _ = commands.send(NetworkCommand::RevokeBlock(hash, e.to_string())).await;
+                    return None;
+                }
+                let _ = commands.send(NetworkCommand::DiscardBlock(gossip_hash, true)).await;
+                reject(e)
+            }
+        }
+    }
+
+    /// This node's key on a permissioned chain, if one is configured
+    fn authority_key(&self) -> Result<Option<AuthorityKey>> {
+        let Some(path) = &self.config.mining.authority_key_file else {
+            return Ok(None);
+        };
+        let authorities = self
+            .engine
+            .authorities()
+            .ok_or_else(|| anyhow!("mining.authority_key_file is set but [chain.authority] is not"))?;
+        let key = AuthorityKey::load(path, authorities)?;
+        tracing::info!("Signing blocks as authority {} of {}", key.index(), authorities.keys().len());
+        Ok(Some(key))
+    }
+
+    /// Connect a block found by the miner, or submitted over RPC, and
+    /// announce it
+    async fn connect_mined(&self, block: &Block, commands: &mpsc::Sender<NetworkCommand>) {
+        match self.connect_block(block) {
+            Ok(_) => {
+                let _ = commands.send(NetworkCommand::PublishBlock(block.encode())).await;
+            }
+            Err(e) => tracing::warn!("Mined block at height {} was not connected: {:#}", block.header.height, e),
+        }
+    }
+
+    /// Ask a peer for the headers following the node's chain
+    async fn request_headers(&self, peer: PeerId, commands: &mpsc::Sender<NetworkCommand>) {
+        let request = self
+            .sync
+            .lock()
+            .unwrap()
+            .request_headers(&self.store, &self.engine, peer, Instant::now());
+        match request {
+            Ok(Some(request)) => {
+                let _ = commands.send(NetworkCommand::RequestSync(peer, request)).await;
+            }
+            Ok(None) => {}
+            Err(e) => tracing::error!("Failed to build block locator: {}", e),
+        }
+    }
+
+    /// Send due block download requests
+    async fn request_blocks(&self, commands: &mpsc::Sender<NetworkCommand>) {
+        let requests = self.sync.lock().unwrap().block_requests(Instant::now());
+        for (peer, request) in requests {
+            let _ = commands.send(NetworkCommand::RequestSync(peer, request)).await;
+        }
+    }
+
+    /// Switch to a downloaded branch with more work, then connect
+    /// downloaded blocks that extend the tip, in order. Returns a reject for
+    /// the source of a branch that fails to connect, or of the first block
+    /// that fails to connect.
+    fn connect_downloaded(&self) -> Option<(PeerId, RejectMessage)> {
+        let branch = self
+            .next_block()
+            .and_then(|(height, _)| self.sync.lock().unwrap().take_branch(&self.store, height));
+        match branch {
+            Ok(branch) if !branch.is_empty() => {
+                let (source, tip) = branch.last().map(|(source, block)| (*source, block.header.clone()))?;
+                let blocks: Vec<Block> = branch.into_iter().map(|(_, block)| block).collect();
+                if let Err(e) = self.reorganize(&blocks) {
+                    let hash = self.engine.compute_block_hash(&tip);
+                    tracing::warn!("Downloaded branch to {} from {} failed: {:#}", hex::encode(hash), source, e);
+                    let message = RejectMessage::new(RejectedItem::Block, hash, RejectCode::Invalid, &e.to_string());
+                    return Some((source, message));
+                }
+            }
+            Ok(_) => {}
+            Err(e) => tracing::error!("Failed to collect a downloaded branch: {}", e),
+        }
+
+        let next_height = match self.next_block() {
+            Ok((height, _)) => height,
+            Err(e) => {
+                tracing::error!("Failed to read chain tip: {}", e);
+                return None;
+            }
+        };
+        let ready = self.sync.lock().unwrap().connectable(next_height);
+        for (source, block) in ready {
+            if let Err(e) = self.connect_block(&block) {
+                // Later blocks are dropped with it and fetched again on
+                // the next headers
+                let hash = self.engine.compute_block_hash(&block.header);
+                tracing::warn!("Downloaded block {} from {} failed: {}", hex::encode(hash), source, e);
+                let message = RejectMessage::new(RejectedItem::Block, hash, RejectCode::Invalid, &e.to_string());
+                return Some((source, message));
+            }
+        }
+        None
+    }
+}
+
+/// Supervised form of a task loop that only returns at shutdown
+async fn finished(run: impl Future<Output = ()>) -> Result<()> {
+    run.await;
+    Ok(())
+}
+
+/// SIGHUP deliveries; never fires where there are no signals
+struct Hangups {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangups {
+    fn new() -> Result<Self> {
+        Ok(Self {
+            #[cfg(unix)]
+            signal: Some(tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?),
+        })
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            if signal.recv().await.is_some() {
+                return;
+            }
+            self.signal = None;
+        }
+        std::future::pending::<()>().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::MemoryStore;
+    use crate::consensus::sighash::{
+        transfer_sighash, SighashType, SpendType, Transfer, TransferInput, TransferOutput,
+    };
+    use crate::consensus::transfer::sign_key_path;
+    use crate::consensus::{testing, SignedTransfer};
+    use crate::ledger::{OutPoint, FORGE_REWARD};
+    use crate::params::MAX_TRANSFERS_PER_BLOCK;
+    use bitcoin::secp256k1::SecretKey;
+    use bitcoin::Network;
+
+    fn options() -> NodeOptions {
+        NodeOptions::for_network(NetworkParams::regtest(), std::env::temp_dir())
+    }
+
+    #[test]
+    fn test_connect_block_requires_valid_tip_extension() {
+        let store = ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap();
+        let node = Node::with_store(NodeConfig::default(), options(), store).unwrap();
+        assert!(node.options.port_warnings().is_empty());
+        assert_eq!(node.tip().unwrap(), None);
+
+        let mut block = testing::block(5, [0u8; 32], vec![]);
+        let error = node.connect_block(&block).unwrap_err();
+        assert!(error.to_string().contains("does not extend the tip"));
+
+        // Extends the (empty) tip but fails validation: nothing is stored
+        block.header.height = 0;
+        assert!(node.connect_block(&block).is_err());
+        assert_eq!(node.tip().unwrap(), None);
+        assert_eq!(node.engine.get_height(), 0);
+    }
+
+    fn memory_node(config: NodeConfig) -> Node {
+        let store = ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap();
+        Node::with_store(config, options(), store).unwrap()
+    }
+
+    /// Connect `count` blocks on `node`'s tip, each with one fully valid
+    /// forge, with salts counting up from `salt`
+    fn grow(node: &Node, count: u8, salt: u8) -> Vec<Block> {
+        (salt..salt + count)
+            .map(|salt| {
+                let block = block_with(node, salt, Vec::new());
+                node.connect_block(&block).unwrap();
+                block
+            })
+            .collect()
+    }
+
+    /// Grind `block` after committing to its forges, transfers and state
+    fn seal(node: &Node, block: &mut Block) {
+        block.header.merkle_root = node.engine.compute_block_merkle_root(block);
+        block.set_state_root(&node.engine);
+        assert!(node.engine.grind_header(&mut block.header, 1_000));
+    }
+
+    /// Block on `node`'s tip with one fully valid forge salted `salt` and
+    /// `transfers`
+    fn block_with(node: &Node, salt: u8, transfers: Vec<SignedTransfer>) -> Block {
+        let (height, parent) = node.next_block().unwrap();
+        let mut block = testing::block(height, parent, vec![testing::derived_forge(7, salt)]);
+        block.header.timestamp = unix_now() + height;
+        block.transfers = transfers;
+        seal(node, &mut block);
+        block
+    }
+
+    /// Connect a block of the mempool's transfers
+    fn mine_transfers(node: &Node, salt: u8) -> Block {
+        let block = block_with(node, salt, node.pool.get_transfers_for_block(MAX_TRANSFERS_PER_BLOCK));
+        node.connect_block(&block).unwrap();
+        block
+    }
+
+    /// Key-path spend of a `grow` forge's output to `address`, paying 1,000
+    fn spend_forge(forge: &ForgeTransaction, address: &str) -> SignedTransfer {
+        let transfer = Transfer {
+            version: 1,
+            inputs: vec![TransferInput {
+                prevout: OutPoint { txid: forge.proof_hash, vout: 0 },
+                amount: FORGE_REWARD,
+                address: forge.taproot_address.clone(),
+            }],
+            outputs: vec![TransferOutput {
+                address: address.to_string(),
+                value: FORGE_REWARD - 1_000,
+            }],
+            fee: 1_000,
+            lock_height: 0,
+        };
+        let key = SecretKey::from_slice(&[7; 32]).unwrap();
+        let witness = sign_key_path(&transfer, 0, &key, SighashType::ALL, Network::Regtest).unwrap();
+        SignedTransfer {
+            transfer,
+            witnesses: vec![witness],
+        }
+    }
+
+    /// Next alert published on the event bus
+    async fn next_alert(events: &mut tokio::sync::broadcast::Receiver<NodeEvent>) -> crate::events::Alert {
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap();
+            if let NodeEvent::Alert(alert) = event {
+                return alert;
+            }
+        }
+    }
+
+    fn address_of(byte: u8) -> String {
+        crate::crypto::p2tr_address_for_key(&crate::crypto::derive_public_key(&[byte; 32]).unwrap(), Network::Regtest)
+    }
+
+    #[tokio::test]
+    async fn test_signed_transfer_mined_and_disconnected() {
+        let node = memory_node(NodeConfig::default());
+        let forge = grow(&node, 1, 1).remove(0).forges.remove(0);
+        let spent = OutPoint { txid: forge.proof_hash, vout: 0 };
+        let signed = spend_forge(&forge, &address_of(8));
+
+        // The spent output is watched, in the mempool and in blocks
+        let mut events = node.events.subscribe();
+        node.spend_watch.watch(spent, "custody").unwrap();
+        tokio::spawn(Arc::clone(&node.spend_watch).run(node.pool.subscribe_transfers(), node.shutdown.subscribe()));
+
+        // Only the signed transfer is admitted, once
+        let mut unsigned = signed.clone();
+        unsigned.witnesses.clear();
+        assert!(node.engine.check_transfer(&unsigned).is_err());
+        node.engine.check_transfer(&signed).unwrap();
+        let txid = node.pool.add_transfer(signed.clone()).unwrap();
+        assert!(node.pool.add_transfer(signed).is_err());
+        assert_eq!(next_alert(&mut events).await.data["location"], "mempool");
+
+        // A block carrying the transfer without its witness is invalid
+        let block = block_with(&node, 2, node.pool.get_transfers_for_block(MAX_TRANSFERS_PER_BLOCK));
+        let mut stripped = block.clone();
+        stripped.transfers[0].witnesses.clear();
+        seal(&node, &mut stripped);
+        assert!(node.connect_block(&stripped).is_err());
+
+        let before = node.engine.state_root();
+        node.connect_block(&block).unwrap();
+        assert_eq!(node.pool.transfer_count(), 0);
+        let alert = next_alert(&mut events).await;
+        assert_eq!((alert.data["location"].as_str(), alert.data["height"].as_u64()), (Some("block"), Some(1)));
+        let created = OutPoint { txid, vout: 0 };
+        node.engine.with_ledger(|ledger| {
+            assert!(ledger.get_output(&spent).is_none());
+            assert_eq!(ledger.get_output(&created).unwrap().value, FORGE_REWARD - 1_000);
+        });
+
+        // Disconnecting restores the spent output
+        node.disconnect_tip().unwrap();
+        assert_eq!(node.engine.state_root(), before);
+        node.engine.with_ledger(|ledger| {
+            assert!(ledger.get_output(&spent).is_some());
+            assert!(ledger.get_output(&created).is_none());
+        });
+    }
+
+    #[tokio::test]
+    async fn test_vault_spends_mined_and_checked() {
+        use crate::rpc::JsonRpcRequest;
+        use bitcoin::secp256k1::{Keypair, Message, Secp256k1};
+        use serde_json::{json, Value};
+
+        let node = memory_node(NodeConfig::default());
+        let secp = Secp256k1::new();
+        let keypair = |byte: u8| Keypair::from_seckey_slice(&secp, &[byte; 32]).unwrap();
+        let x_only = |byte: u8| hex::encode(keypair(byte).x_only_public_key().0.serialize());
+        let wallet = Arc::new(RwLock::new(Wallet::new(Network::Regtest)));
+        let vault = wallet.write().await.create_vault(&x_only(1), &x_only(2), &address_of(3), 2).unwrap();
+        let mut rpc = RpcServer::new();
+        rpc.register_vault_handlers(Arc::clone(&wallet), Arc::clone(&node.engine), Arc::clone(&node.pool));
+        let call = |method: &str, params: Value| {
+            rpc.handle_request(JsonRpcRequest {
+                jsonrpc: "2.0".to_string(),
+                method: method.to_string(),
+                params: Some(params),
+                id: json!(1),
+            })
+        };
+        // Signs each sighash of a vault spend response with `byte`'s key
+        let sign = |response: &Value, byte: u8| -> Value {
+            let signatures: Vec<String> = response["sighashes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|sighash| {
+                    let digest: [u8; 32] = hex::decode(sighash.as_str().unwrap()).unwrap().try_into().unwrap();
+                    let signature = secp.sign_schnorr_no_aux_rand(&Message::from_digest(digest), &keypair(byte));
+                    hex::encode(signature.serialize())
+                })
+                .collect();
+            json!(signatures)
+        };
+        let alerts = || async {
+            let checked = call("checkvaults", Value::Null).await.result.unwrap();
+            checked["alerts"].as_array().unwrap().clone()
+        };
+
+        // Fund the vault with two forge outputs
+        let forges: Vec<_> = grow(&node, 4, 1).into_iter().map(|mut block| block.forges.remove(0)).collect();
+        for forge in &forges[..2] {
+            node.pool.add_transfer(spend_forge(forge, &vault)).unwrap();
+        }
+        let funded = mine_transfers(&node, 10).header.height;
+        assert!(alerts().await.is_empty());
+
+        // The hot key withdraws once both outputs are two blocks deep
+        let destination = address_of(8);
+        let unvault = call("unvault", json!([vault, destination, 1_000])).await.result.unwrap();
+        assert_eq!(unvault["spendable_at"], json!(funded + 2));
+        assert_eq!(unvault["sighashes"].as_array().unwrap().len(), 2);
+        let signatures = sign(&unvault, 1);
+        let early = call("unvault", json!([vault, destination, 1_000, signatures])).await;
+        assert_eq!(early.error.unwrap().code, crate::rpc::RPC_VERIFY_REJECTED);
+        mine_transfers(&node, 11);
+        let submitted = call("unvault", json!([vault, destination, 1_000, signatures])).await.result.unwrap();
+        assert_eq!(submitted["hex"], unvault["hex"]);
+        mine_transfers(&node, 12);
+        assert_eq!(node.pool.transfer_count(), 0);
+        assert!(alerts().await.is_empty());
+
+        // The recovery key sweeps at once
+        node.pool.add_transfer(spend_forge(&forges[2], &vault)).unwrap();
+        mine_transfers(&node, 13);
+        assert!(alerts().await.is_empty());
+        let recovery = call("recovervault", json!([vault, 1_000])).await.result.unwrap();
+        let swept = call("recovervault", json!([vault, 1_000, sign(&recovery, 2)])).await.result.unwrap();
+        assert!(swept["txid"].is_string());
+        mine_transfers(&node, 14);
+        assert!(alerts().await.is_empty());
+        node.engine.with_ledger(|ledger| assert!(ledger.outputs().all(|(_, output)| output.address != vault)));
+
+        // A hot key spend the wallet did not start is reported
+        let funding = spend_forge(&forges[3], &vault);
+        let outpoint = OutPoint { txid: funding.txid().unwrap(), vout: 0 };
+        node.pool.add_transfer(funding).unwrap();
+        mine_transfers(&node, 15);
+        assert!(alerts().await.is_empty());
+        mine_transfers(&node, 16);
+        let transfer = Transfer {
+            version: 1,
+            inputs: vec![TransferInput {
+                prevout: outpoint,
+                amount: FORGE_REWARD - 1_000,
+                address: vault.clone(),
+            }],
+            outputs: vec![TransferOutput {
+                address: address_of(9),
+                value: FORGE_REWARD - 2_000,
+            }],
+            fee: 1_000,
+            lock_height: 0,
+        };
+        let sighash =
+            transfer_sighash(&transfer, 0, SighashType::ALL, SpendType::ScriptPath, Network::Regtest).unwrap();
+        let signature = secp.sign_schnorr_no_aux_rand(&Message::from_digest(sighash), &keypair(1));
+        let stolen = wallet.read().await.vaults()[0]
+            .with_signatures(transfer, false, vec![signature.serialize().to_vec()])
+            .unwrap();
+        node.engine.check_transfer(&stolen).unwrap();
+        node.pool.add_transfer(stolen).unwrap();
+        mine_transfers(&node, 17);
+        let alerts = alerts().await;
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0]["reason"], "spent while the vault was not unvaulting");
+    }
+
+    #[test]
+    fn test_reorg_held_by_guard_then_switched() {
+        let mut config = NodeConfig::default();
+        config.chain.max_reorg_depth = 1;
+        let node = memory_node(config);
+        let main = grow(&node, 3, 1);
+        let old_tip = node.tip().unwrap().unwrap();
+
+        // A heavier branch forking two blocks deep waits for acceptreorg
+        let other = memory_node(NodeConfig::default());
+        other.connect_block(&main[0]).unwrap();
+        let side = grow(&other, 3, 10);
+        let side_tip = other.engine.get_tip_hash();
+        assert!(!node.reorganize(&side).unwrap());
+        assert_eq!(node.tip().unwrap(), Some(old_tip));
+        assert_eq!(node.reorg_guard.pending()[0].tip_hash, side_tip);
+
+        // Once accepted, the node switches and the old blocks are gone
+        assert!(node.reorg_guard.accept(&side_tip));
+        assert!(node.reorganize(&side).unwrap());
+        assert_eq!(node.tip().unwrap(), Some((3, side_tip)));
+        assert_eq!(node.engine.state_root(), other.engine.state_root());
+        assert_eq!(node.store.get_block(1).unwrap().unwrap(), side[0].encode());
+        let old_hash = node.engine.compute_block_hash(&main[1].header);
+        assert_eq!(node.store.get_block_height_by_hash(&old_hash).unwrap(), None);
+        assert!(node.reorg_guard.pending().is_empty());
+
+        // The disconnected forges go back through validation as reorged
+        let mut requeued = node.validation.queued();
+        requeued.sort_by_key(|(_, proof_hash)| *proof_hash);
+        let mut disconnected: Vec<_> =
+            main[1..].iter().map(|block| (ForgeOrigin::Reorg, block.forges[0].proof_hash)).collect();
+        disconnected.sort_by_key(|(_, proof_hash)| *proof_hash);
+        assert_eq!(requeued, disconnected);
+
+        // A lighter branch is ignored, and a heavier one with an invalid
+        // block leaves the chain as it was
+        assert!(!node.reorganize(&main[1..]).unwrap());
+        let third = memory_node(NodeConfig::default());
+        third.connect_block(&main[0]).unwrap();
+        third.connect_block(&side[0]).unwrap();
+        let mut bad = grow(&third, 4, 20);
+        bad[3].forges[0].signature.clear();
+        let bad_tip = third.engine.compute_block_hash(&bad[3].header);
+        assert!(!node.reorganize(&bad).unwrap());
+        assert!(node.reorg_guard.accept(&bad_tip));
+        assert!(node.reorganize(&bad).is_err());
+        assert_eq!(node.tip().unwrap(), Some((3, side_tip)));
+        assert_eq!(node.engine.state_root(), other.engine.state_root());
+    }
+
+    #[tokio::test]
+    async fn test_gossiped_blocks_relayed_then_revoked() {
+        let mut config = NodeConfig::default();
+        config.network.optimistic_block_relay = true;
+        let store = ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap();
+        let node = Node::with_store(config, options(), store).unwrap();
+        let peer = PeerId::random();
+
+        // Passes prevalidation, but the forge's proof of forge is invalid
+        let mut block = testing::block(0, [0u8; 32], vec![testing::forge(1)]);
+        block.header.merkle_root = node.engine.compute_merkle_root(&block.forges);
+        block.header.timestamp = unix_now();
+        assert!(node.engine.grind_header(&mut block.header, 1_000));
+        node.engine.prevalidate_block(&block, &[0u8; 32]).unwrap();
+
+        let received = |block: &Block| {
+            let (commands, mut sent) = mpsc::channel(8);
+            let data = block.encode();
+            let node = &node;
+            async move {
+                node.handle_network_event(NetworkEvent::BlockReceived(data, peer), &commands, None).await;
+                drop(commands);
+                let mut sent_commands = Vec::new();
+                while let Some(command) = sent.recv().await {
+                    sent_commands.push(command);
+                }
+                sent_commands
+            }
+        };
+
+        // Relayed early, then revoked once full validation fails
+        let gossip_hash = inventory_hash(&block.encode());
+        let hash = node.engine.compute_block_hash(&block.header);
+        let sent = received(&block).await;
+        assert!(matches!(sent.as_slice(), [
+            NetworkCommand::RelayBlock(relayed),
+            NetworkCommand::RevokeBlock(revoked, _),
+        ] if *relayed == gossip_hash && *revoked == hash));
+        assert_eq!(node.tip().unwrap(), None);
+
+        // The source has lost optimistic relay: its next bad block is
+        // dropped, penalized and rejected back to it
+        block.header.timestamp += 1;
+        assert!(node.engine.grind_header(&mut block.header, 1_000));
+        let sent = received(&block).await;
+        assert!(matches!(sent.as_slice(), [
+            NetworkCommand::DiscardBlock(dropped, true),
+            NetworkCommand::RejectItem(to, _),
+        ] if *dropped == inventory_hash(&block.encode()) && *to == peer));
+
+        // A block failing prevalidation is never relayed
+        block.header.merkle_root = [0u8; 32];
+        let sent = received(&block).await;
+        assert!(matches!(sent.as_slice(), [NetworkCommand::DiscardBlock(_, true), NetworkCommand::RejectItem(..)]));
+
+        let stats = node.relay.stats();
+        assert_eq!((stats.relayed_early, stats.revoked, stats.awaiting_validation), (1, 1, 0));
+    }
+}
@@ -0,0 +1,165 @@
+//! Consensus and relay parameters that differ between chains
+//!
+//! Each network preset carries a `ChainParams`. Tests and private chains
+//! can start from a preset and change fields, e.g.
+//! `ChainParams { min_block_time: 0, ..ChainParams::regtest() }`.
+
+use crate::consensus::POW_LIMIT_BITS;
+
+/// Leading zero bytes a mainnet proof hash needs before any difficulty
+/// adjustment
+pub const INITIAL_FORGE_DIFFICULTY: u32 = 4;
+
+/// Minimum seconds between mainnet blocks
+pub const MIN_BLOCK_TIME: u64 = 600;
+
+/// Most forges a mainnet block may carry
+pub const MAX_FORGES_PER_BLOCK: usize = 100;
+
+/// Most transfers a block may carry, on every chain
+pub const MAX_TRANSFERS_PER_BLOCK: usize = 1_000;
+
+/// Forges between mainnet difficulty increases
+pub const DIFFICULTY_ADJUSTMENT_FORGES: u64 = 10_000;
+
+/// Fee owed by each forge, rising with the number of forges completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeSchedule {
+    /// Fee of the first forge, in satoshis
+    pub base_fee: u64,
+    /// Added every `step_forges` forges
+    pub step_fee: u64,
+    /// Forges between increases (0 = the fee never rises)
+    pub step_forges: u64,
+    /// Highest fee
+    pub max_fee: u64,
+}
+
+impl FeeSchedule {
+    /// Starts at 1 BTC, increases by 0.1 BTC every 10,000 forges, capped at
+    /// 21 BTC
+    pub fn mainnet() -> Self {
+        Self {
+            base_fee: 100_000_000,
+            step_fee: 10_000_000,
+            step_forges: 10_000,
+            max_fee: 2_100_000_000,
+        }
+    }
+
+    /// Fee of the forge following `forges_completed` others
+    pub fn forge_fee(&self, forges_completed: u64) -> u64 {
+        let steps = forges_completed.checked_div(self.step_forges).unwrap_or(0);
+        self.base_fee.saturating_add(steps.saturating_mul(self.step_fee)).min(self.max_fee)
+    }
+}
+
+/// Gossipsub topic names on the wire. The node refers to topics by their
+/// mainnet names (`BLOCK_TOPIC` and so on); other chains prefix them so
+/// their gossip never reaches a mainnet mesh.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct GossipTopics {
+    /// Prepended to every topic name (empty on mainnet)
+    pub prefix: String,
+}
+
+impl GossipTopics {
+    /// Topics named `<prefix><topic>`
+    pub fn with_prefix(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+        }
+    }
+
+    /// Wire name of a topic
+    pub fn wire(&self, topic: &str) -> String {
+        format!("{}{}", self.prefix, topic)
+    }
+
+    /// Topic a wire name refers to, if it belongs to this chain
+    pub fn topic<'a>(&self, wire: &'a str) -> Option<&'a str> {
+        wire.strip_prefix(self.prefix.as_str())
+    }
+}
+
+/// Difficulty, block limits, fees and gossip topics of a chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainParams {
+    /// Leading zero bytes a proof hash needs on a fresh chain
+    pub initial_difficulty: u32,
+    /// Forges between difficulty increases (0 = never adjusted)
+    pub difficulty_adjustment_forges: u64,
+    /// Easiest header target, in compact form
+    pub pow_limit_bits: u32,
+    /// Minimum seconds between blocks
+    pub min_block_time: u64,
+    /// Most forges a block may carry
+    pub max_forges_per_block: usize,
+    /// First height whose forges may be tempered with Argon2id; earlier
+    /// forges must use PBKDF2 (`u64::MAX` = not scheduled)
+    pub argon2id_activation_height: u64,
+    pub fee_schedule: FeeSchedule,
+    pub topics: GossipTopics,
+}
+
+impl ChainParams {
+    /// Mainnet parameters
+    pub fn mainnet() -> Self {
+        Self {
+            initial_difficulty: INITIAL_FORGE_DIFFICULTY,
+            difficulty_adjustment_forges: DIFFICULTY_ADJUSTMENT_FORGES,
+            pow_limit_bits: POW_LIMIT_BITS,
+            min_block_time: MIN_BLOCK_TIME,
+            max_forges_per_block: MAX_FORGES_PER_BLOCK,
+            argon2id_activation_height: u64::MAX,
+            fee_schedule: FeeSchedule::mainnet(),
+            topics: GossipTopics::default(),
+        }
+    }
+
+    /// Testnet parameters: mainnet's, on separate gossip topics
+    pub fn testnet() -> Self {
+        Self {
+            topics: GossipTopics::with_prefix("testnet/"),
+            ..Self::mainnet()
+        }
+    }
+
+    /// Regtest parameters: any proof hash is accepted, difficulty never
+    /// rises, blocks may follow each other immediately and Argon2id
+    /// tempering is active from genesis
+    pub fn regtest() -> Self {
+        Self {
+            initial_difficulty: 0,
+            difficulty_adjustment_forges: 0,
+            min_block_time: 0,
+            argon2id_activation_height: 0,
+            topics: GossipTopics::with_prefix("regtest/"),
+            ..Self::mainnet()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee_schedule_and_topics() {
+        let schedule = FeeSchedule::mainnet();
+        assert_eq!(schedule.forge_fee(0), 100_000_000);
+        assert_eq!(schedule.forge_fee(25_000), 120_000_000);
+        assert_eq!(schedule.forge_fee(u64::MAX), schedule.max_fee);
+        let flat = FeeSchedule {
+            step_forges: 0,
+            ..schedule
+        };
+        assert_eq!(flat.forge_fee(1_000_000), 100_000_000);
+
+        let regtest = ChainParams::regtest().topics;
+        assert_eq!(regtest.wire("excalibur-blocks"), "regtest/excalibur-blocks");
+        assert_eq!(regtest.topic("regtest/excalibur-blocks"), Some("excalibur-blocks"));
+        assert_eq!(regtest.topic("excalibur-blocks"), None);
+        assert_eq!(ChainParams::mainnet().topics.wire("excalibur-blocks"), "excalibur-blocks");
+    }
+}
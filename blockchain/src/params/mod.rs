@@ -0,0 +1,197 @@
+//! Per-network parameters
+
+use crate::consensus::AuthoritySet;
+use bitcoin::Network;
+
+mod chain;
+
+pub use chain::{
+    ChainParams, FeeSchedule, GossipTopics, DIFFICULTY_ADJUSTMENT_FORGES, INITIAL_FORGE_DIFFICULTY,
+    MAX_FORGES_PER_BLOCK, MAX_TRANSFERS_PER_BLOCK, MIN_BLOCK_TIME,
+};
+
+/// Trusted ledger snapshot commitment (assumeutxo)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AssumeUtxoData {
+    /// Height of the last block included in the snapshot
+    pub height: u64,
+    /// Expected `LedgerSnapshot::snapshot_hash()`
+    pub snapshot_hash: [u8; 32],
+}
+
+/// Connection magic of mainnet, also assumed for peers that predate magic
+pub const MAINNET_MAGIC: [u8; 4] = [0xe5, 0x78, 0x53, 0xb1];
+
+/// Parameters identifying a network
+#[derive(Debug, Clone)]
+pub struct NetworkParams {
+    /// Network name (mainnet, testnet, regtest)
+    pub name: String,
+    /// Bitcoin network used for address encoding
+    pub network: Network,
+    /// Connection magic exchanged during the handshake; peers presenting
+    /// another network's magic are disconnected
+    pub magic: [u8; 4],
+    /// Default P2P listen port
+    pub default_port: u16,
+    /// Default JSON-RPC port
+    pub default_rpc_port: u16,
+    /// Ledger snapshots that may be loaded instead of validating history
+    pub assume_utxo: Vec<AssumeUtxoData>,
+    /// First height at which forges must pay to a P2TR address; earlier
+    /// forges may still use the legacy P2WPKH form (`u64::MAX` = not scheduled)
+    pub p2tr_activation_height: u64,
+    /// First height whose header must commit to a post-block state root
+    /// (`VERSION_STATE_ROOT`); earlier headers may (`u64::MAX` = not scheduled)
+    pub state_root_activation_height: u64,
+    /// First height whose block time must be later than median-time-past,
+    /// compared in milliseconds (`u64::MAX` = not scheduled)
+    pub median_time_activation_height: u64,
+    /// Difficulty, block limits, fees and gossip topics
+    pub chain: ChainParams,
+    /// Keys that must sign blocks on a permissioned chain; `None` on the
+    /// public networks
+    pub authorities: Option<AuthoritySet>,
+}
+
+impl NetworkParams {
+    /// Mainnet parameters
+    pub fn mainnet() -> Self {
+        Self {
+            name: "mainnet".to_string(),
+            network: Network::Bitcoin,
+            magic: MAINNET_MAGIC,
+            default_port: 8333,
+            default_rpc_port: 8332,
+            assume_utxo: Vec::new(),
+            p2tr_activation_height: u64::MAX,
+            state_root_activation_height: u64::MAX,
+            median_time_activation_height: u64::MAX,
+            chain: ChainParams::mainnet(),
+            authorities: None,
+        }
+    }
+
+    /// Testnet parameters
+    pub fn testnet() -> Self {
+        Self {
+            name: "testnet".to_string(),
+            network: Network::Testnet,
+            magic: [0xe5, 0x78, 0x53, 0x7e],
+            default_port: 18333,
+            default_rpc_port: 18332,
+            assume_utxo: Vec::new(),
+            p2tr_activation_height: u64::MAX,
+            state_root_activation_height: u64::MAX,
+            median_time_activation_height: u64::MAX,
+            chain: ChainParams::testnet(),
+            authorities: None,
+        }
+    }
+
+    /// Regtest parameters
+    pub fn regtest() -> Self {
+        Self {
+            name: "regtest".to_string(),
+            network: Network::Regtest,
+            magic: [0xe5, 0x78, 0x53, 0xfa],
+            default_port: 18444,
+            default_rpc_port: 18443,
+            assume_utxo: Vec::new(),
+            p2tr_activation_height: 0,
+            state_root_activation_height: 0,
+            median_time_activation_height: 0,
+            chain: ChainParams::regtest(),
+            authorities: None,
+        }
+    }
+
+    /// Permissioned chain on these parameters, whose blocks `authorities`
+    /// sign. Its magic and gossip topics are derived from the authority set,
+    /// so its nodes never connect to or gossip with the network it is based
+    /// on, nor with chains of other authorities.
+    pub fn with_authorities(mut self, authorities: AuthoritySet) -> Self {
+        let id = authorities.id();
+        self.magic.copy_from_slice(&id[..4]);
+        self.chain.topics.prefix = format!("{}authority-{}/", self.chain.topics.prefix, hex::encode(&id[..4]));
+        self.authorities = Some(authorities);
+        self
+    }
+
+    /// Parameters for a network name, if known
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mainnet" => Some(Self::mainnet()),
+            "testnet" => Some(Self::testnet()),
+            "regtest" => Some(Self::regtest()),
+            _ => None,
+        }
+    }
+
+    /// Parameters of every known network
+    pub fn all() -> Vec<Self> {
+        vec![Self::mainnet(), Self::testnet(), Self::regtest()]
+    }
+
+    /// Warnings for configured ports that are another network's defaults,
+    /// the usual sign of a node started with the wrong `--network`
+    pub fn port_conflicts(&self, port: u16, rpc_port: u16) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for other in Self::all().into_iter().filter(|other| other.name != self.name) {
+            for (kind, configured) in [("P2P", port), ("RPC", rpc_port)] {
+                if configured == other.default_port {
+                    warnings.push(format!(
+                        "{} port {} is the default P2P port of {}, but this node is on {}",
+                        kind, configured, other.name, self.name
+                    ));
+                } else if configured == other.default_rpc_port {
+                    warnings.push(format!(
+                        "{} port {} is the default RPC port of {}, but this node is on {}",
+                        kind, configured, other.name, self.name
+                    ));
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Snapshot commitment for a height, if one exists
+    pub fn assume_utxo_for(&self, height: u64) -> Option<&AssumeUtxoData> {
+        self.assume_utxo.iter().find(|data| data.height == height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(NetworkParams::from_name("testnet").unwrap().network, Network::Testnet);
+        assert!(NetworkParams::from_name("devnet").is_none());
+    }
+
+    #[test]
+    fn test_networks_are_distinct_and_conflicts_detected() {
+        let all = NetworkParams::all();
+        for (i, a) in all.iter().enumerate() {
+            for b in &all[i + 1..] {
+                assert_ne!(a.magic, b.magic);
+                assert_ne!(a.default_port, b.default_port);
+                assert_ne!(a.default_rpc_port, b.default_rpc_port);
+            }
+        }
+
+        let testnet = NetworkParams::testnet();
+        assert!(testnet.port_conflicts(testnet.default_port, testnet.default_rpc_port).is_empty());
+        let warnings = testnet.port_conflicts(8333, 18332);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("default P2P port of mainnet"));
+        assert_eq!(testnet.port_conflicts(9000, 8332).len(), 1);
+
+        let key = crate::crypto::derive_public_key(&[1; 32]).unwrap().x_only_public_key().0;
+        let permissioned = NetworkParams::regtest().with_authorities(AuthoritySet::new(vec![key], 1, 0).unwrap());
+        assert_ne!(permissioned.magic, NetworkParams::regtest().magic);
+        assert!(permissioned.chain.topics.prefix.starts_with("regtest/authority-"));
+    }
+}
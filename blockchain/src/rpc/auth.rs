@@ -0,0 +1,220 @@
+//! HTTP RPC authentication
+//!
+//! Requests must carry HTTP basic auth matching either the configured
+//! `[rpc] user`/`password` or the cookie credentials. Like Bitcoin Core, the
+//! node writes a random password for the `__cookie__` user to `.cookie` in
+//! its data directory when no password is configured, so local tools that
+//! can read the data directory authenticate without a shared secret. The
+//! cookie is replaced on every start and removed on shutdown.
+
+use anyhow::{anyhow, Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Cookie file name in the data directory
+pub const COOKIE_FILE: &str = ".cookie";
+
+/// User name of cookie credentials
+pub const COOKIE_USER: &str = "__cookie__";
+
+/// Credentials accepted by the HTTP RPC server
+#[derive(Debug, Clone)]
+pub struct RpcAuth {
+    /// Digests of accepted `user:password` strings
+    credentials: Vec<[u8; 32]>,
+    /// Cookie file written for these credentials, if any
+    cookie: Option<PathBuf>,
+}
+
+impl RpcAuth {
+    /// Accept `user`/`password`
+    pub fn with_password(user: &str, password: &str) -> Result<Self> {
+        if user.contains(':') {
+            return Err(anyhow!("RPC user name must not contain ':'"));
+        }
+        if password.is_empty() {
+            return Err(anyhow!("RPC password must not be empty"));
+        }
+        Ok(Self {
+            credentials: vec![Self::digest(user, password)],
+            cookie: None,
+        })
+    }
+
+    /// Accept a fresh random password for `COOKIE_USER`, written to the
+    /// cookie file in `data_dir`
+    pub fn with_cookie(data_dir: &Path) -> Result<Self> {
+        let password = hex::encode(rand::random::<[u8; 32]>());
+        let path = data_dir.join(COOKIE_FILE);
+        write_private(&path, format!("{}:{}", COOKIE_USER, password).as_bytes())
+            .with_context(|| format!("Failed to write RPC cookie {}", path.display()))?;
+        Ok(Self {
+            credentials: vec![Self::digest(COOKIE_USER, &password)],
+            cookie: Some(path),
+        })
+    }
+
+    /// Configured credentials if a password is set, cookie credentials
+    /// otherwise
+    pub fn from_config(user: Option<&str>, password: Option<&str>, data_dir: &Path) -> Result<Self> {
+        match password {
+            Some(password) => Self::with_password(user.unwrap_or_default(), password),
+            None => Self::with_cookie(data_dir),
+        }
+    }
+
+    /// Cookie file written for these credentials
+    pub fn cookie_path(&self) -> Option<&Path> {
+        self.cookie.as_deref()
+    }
+
+    /// Delete the cookie file, if one was written
+    pub fn remove_cookie(&self) {
+        if let Some(path) = &self.cookie {
+            if let Err(e) = std::fs::remove_file(path) {
+                tracing::warn!("Failed to remove RPC cookie {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Whether an `Authorization` header carries accepted basic-auth
+    /// credentials. Digests are compared so the check doesn't leak the
+    /// password's length or prefix.
+    pub fn authorized(&self, authorization: Option<&str>) -> bool {
+        let Some(encoded) = authorization.and_then(|value| value.strip_prefix("Basic ")) else {
+            return false;
+        };
+        let Some(decoded) = decode_base64(encoded.trim()).and_then(|bytes| String::from_utf8(bytes).ok()) else {
+            return false;
+        };
+        let Some((user, password)) = decoded.split_once(':') else {
+            return false;
+        };
+        let presented = Self::digest(user, password);
+        self.credentials.iter().fold(false, |matched, accepted| {
+            let diff = presented
+                .iter()
+                .zip(accepted.iter())
+                .fold(0u8, |diff, (a, b)| diff | (a ^ b));
+            matched | (diff == 0)
+        })
+    }
+
+    fn digest(user: &str, password: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(user.as_bytes());
+        hasher.update(b":");
+        hasher.update(password.as_bytes());
+        hasher.finalize().into()
+    }
+}
+
+/// Write a file only the owner can read
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents)
+}
+
+/// `Authorization` header value presenting `user`/`password`, for clients
+pub fn basic_authorization(user: &str, password: &str) -> String {
+    format!("Basic {}", encode_base64(format!("{}:{}", user, password).as_bytes()))
+}
+
+/// Encode standard (padded) base64
+fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().fold(0u32, |group, &b| (group << 8) | u32::from(b)) << (8 * (3 - chunk.len()));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(group >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decode standard (padded) base64, as used by basic auth
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    let bytes = encoded.as_bytes();
+    if !bytes.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(bytes.len() / 4 * 3);
+    for (index, chunk) in bytes.chunks(4).enumerate() {
+        let last = index == bytes.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut group = 0u32;
+        for &c in &chunk[..4 - padding] {
+            group = (group << 6) | u32::from(value(c)?);
+        }
+        group <<= 6 * padding;
+        out.extend_from_slice(&group.to_be_bytes()[1..4 - padding]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_basic_auth_credentials() {
+        let auth = RpcAuth::with_password("alice", "secret").unwrap();
+        // "alice:secret"
+        assert!(auth.authorized(Some("Basic YWxpY2U6c2VjcmV0")));
+        // "alice:wrong"
+        assert!(!auth.authorized(Some("Basic YWxpY2U6d3Jvbmc=")));
+        assert!(!auth.authorized(Some("Bearer YWxpY2U6c2VjcmV0")));
+        assert!(!auth.authorized(Some("Basic !!!!")));
+        assert!(!auth.authorized(None));
+        assert!(RpcAuth::with_password("al:ice", "secret").is_err());
+
+        assert_eq!(decode_base64("YQ==").unwrap(), b"a");
+        assert_eq!(decode_base64("YWI=").unwrap(), b"ab");
+        assert!(decode_base64("YQ==YWI=").is_none());
+        assert_eq!(basic_authorization("alice", "secret"), "Basic YWxpY2U6c2VjcmV0");
+    }
+
+    #[test]
+    fn test_cookie_written_and_removed() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let auth = RpcAuth::from_config(None, None, tmp.path()).unwrap();
+        let path = tmp.path().join(COOKIE_FILE);
+        assert_eq!(auth.cookie_path(), Some(path.as_path()));
+
+        let cookie = std::fs::read_to_string(&path).unwrap();
+        assert!(cookie.starts_with("__cookie__:"));
+        let (user, password) = cookie.split_once(':').unwrap();
+        let header = basic_authorization(user, password);
+        assert!(auth.authorized(Some(&header)));
+
+        // A restart replaces the cookie
+        let restarted = RpcAuth::with_cookie(tmp.path()).unwrap();
+        assert!(!restarted.authorized(Some(&header)));
+        restarted.remove_cookie();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,254 @@
+//! Cache of responses for immutable chain data
+//!
+//! A block or mined forge looked up by hash only changes if a reorg
+//! disconnects it, so `getblock` and `getrawforge` responses are kept in a
+//! bounded LRU cache instead of being re-read and re-encoded for every
+//! explorer request. Entries remember the height they were mined at, and a
+//! `NodeEvent::Reorg` drops every entry above the fork point.
+
+use crate::events::NodeEvent;
+use crate::shutdown::ShutdownSignal;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// Default number of cached responses
+pub const DEFAULT_RESPONSE_CACHE_ENTRIES: usize = 1024;
+
+/// What a cached response is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheKey {
+    /// `getblock` by block hash, at a verbosity level
+    Block([u8; 32], u8),
+    /// `getrawforge` by proof hash
+    Forge([u8; 32]),
+}
+
+/// Response cache counters
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ResponseCacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped because a reorg disconnected their block
+    pub invalidated: u64,
+}
+
+/// Bounded LRU cache of RPC responses by block or proof hash
+#[derive(Debug)]
+pub struct ResponseCache {
+    inner: Mutex<CacheInner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    invalidated: AtomicU64,
+}
+
+#[derive(Debug)]
+struct CacheInner {
+    capacity: usize,
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// Keys by last use, oldest first
+    recency: BTreeMap<u64, CacheKey>,
+    tick: u64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    value: Value,
+    height: u64,
+    last_used: u64,
+}
+
+impl CacheInner {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+        }
+    }
+
+    fn evict_to_capacity(&mut self) {
+        while self.entries.len() > self.capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else { break };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+impl ResponseCache {
+    /// Create a cache holding at most `capacity` responses (0 disables it)
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(CacheInner {
+                capacity,
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            invalidated: AtomicU64::new(0),
+        }
+    }
+
+    /// Cached response, marking it recently used
+    pub fn get(&self, key: &CacheKey) -> Option<Value> {
+        let mut inner = self.inner.lock().unwrap();
+        let tick = inner.next_tick();
+        let CacheInner { entries, recency, .. } = &mut *inner;
+        match entries.get_mut(key) {
+            Some(entry) => {
+                recency.remove(&entry.last_used);
+                recency.insert(tick, *key);
+                entry.last_used = tick;
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(entry.value.clone())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Cache a response for data mined at `height`, evicting the least
+    /// recently used entry if full
+    pub fn insert(&self, key: CacheKey, height: u64, value: Value) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.capacity == 0 {
+            return;
+        }
+        inner.remove(&key);
+        let tick = inner.next_tick();
+        inner.recency.insert(tick, key);
+        inner.entries.insert(
+            key,
+            CacheEntry {
+                value,
+                height,
+                last_used: tick,
+            },
+        );
+        inner.evict_to_capacity();
+    }
+
+    /// Change the number of cached responses, evicting if it shrinks
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.capacity = capacity;
+        inner.evict_to_capacity();
+    }
+
+    /// Drop entries for data mined above `fork_height`. Returns the number
+    /// of entries dropped.
+    pub fn invalidate_above(&self, fork_height: u64) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let stale: Vec<CacheKey> = inner
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.height > fork_height)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &stale {
+            inner.remove(key);
+        }
+        self.invalidated.fetch_add(stale.len() as u64, Ordering::Relaxed);
+        stale.len()
+    }
+
+    /// Drop every entry
+    pub fn clear(&self) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let dropped = inner.entries.len();
+        inner.entries.clear();
+        inner.recency.clear();
+        self.invalidated.fetch_add(dropped as u64, Ordering::Relaxed);
+        dropped
+    }
+
+    /// Current size and hit counters
+    pub fn stats(&self) -> ResponseCacheStats {
+        let inner = self.inner.lock().unwrap();
+        ResponseCacheStats {
+            entries: inner.entries.len(),
+            capacity: inner.capacity,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            invalidated: self.invalidated.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Invalidate on reorg events from the bus until shutdown
+    pub async fn run(self: Arc<Self>, mut events: broadcast::Receiver<NodeEvent>, mut shutdown: ShutdownSignal) {
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(NodeEvent::Reorg(reorg)) => {
+                        let dropped = self.invalidate_above(reorg.fork_height);
+                        tracing::debug!("Reorg at height {} invalidated {} cached responses", reorg.fork_height, dropped);
+                    }
+                    Ok(_) => {}
+                    // A missed event may have been a reorg
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        self.clear();
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = shutdown.recv() => break,
+            }
+        }
+    }
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_RESPONSE_CACHE_ENTRIES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_least_recently_used_evicted() {
+        let cache = ResponseCache::new(2);
+        cache.insert(CacheKey::Block([1; 32], 1), 1, json!(1));
+        cache.insert(CacheKey::Block([2; 32], 1), 2, json!(2));
+        assert_eq!(cache.get(&CacheKey::Block([1; 32], 1)), Some(json!(1)));
+
+        cache.insert(CacheKey::Forge([3; 32]), 3, json!(3));
+        assert_eq!(cache.get(&CacheKey::Block([2; 32], 1)), None, "least recently used");
+        assert_eq!(cache.get(&CacheKey::Block([1; 32], 1)), Some(json!(1)));
+        assert_eq!(cache.get(&CacheKey::Forge([3; 32])), Some(json!(3)));
+
+        // Same hash, different method
+        assert_eq!(cache.get(&CacheKey::Forge([1; 32])), None);
+
+        cache.set_capacity(0);
+        cache.insert(CacheKey::Block([4; 32], 1), 4, json!(4));
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (0, 3, 2));
+    }
+
+    #[test]
+    fn test_reorg_invalidates_entries_above_fork() {
+        let cache = ResponseCache::default();
+        for height in 1..=5u8 {
+            cache.insert(CacheKey::Block([height; 32], 1), height as u64, json!(height));
+        }
+        assert_eq!(cache.invalidate_above(3), 2);
+        assert!(cache.get(&CacheKey::Block([3; 32], 1)).is_some());
+        assert!(cache.get(&CacheKey::Block([4; 32], 1)).is_none());
+        assert_eq!(cache.stats().invalidated, 2);
+    }
+}
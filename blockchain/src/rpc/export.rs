@@ -0,0 +1,184 @@
+//! Bulk block export over HTTP
+//!
+//! `GET /export/blocks?from=H&to=H2` streams blocks `H..=H2` (`to` defaults
+//! to the tip) as a chunked response of records
+//!
+//! ```text
+//! height (u64) | length (u32) | bincode-encoded block (length bytes)
+//! ```
+//!
+//! with integers big-endian and blocks in the same canonical encoding the
+//! chain store keeps, so they are copied out without being decoded. The
+//! blocks come from a single range scan, which every `KvStore` backend
+//! serves from a point-in-time view (RocksDB pins an implicit snapshot for
+//! the iterator), so blocks connected mid-export don't leak into it.
+//!
+//! The endpoint is only served when `rpc.export_token` is set, and requests
+//! must carry it as `Authorization: Bearer <token>`.
+
+#[cfg(any(feature = "http-server", test))]
+use super::serialize::ChunkWriter;
+use crate::chain::ChainStore;
+use crate::consensus::Block;
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+/// Bytes before each block in the export stream
+pub const EXPORT_RECORD_HEADER_LEN: usize = 8 + 4;
+
+/// Query string of an export request
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ExportQuery {
+    pub from: u64,
+    pub to: Option<u64>,
+}
+
+/// Chain store access and credentials for the export endpoint
+#[derive(Clone)]
+pub struct BlockExport {
+    store: Arc<ChainStore>,
+    token_hash: [u8; 32],
+}
+
+impl BlockExport {
+    /// Serve exports from `store` to clients presenting `token`
+    pub fn new(store: Arc<ChainStore>, token: &str) -> Result<Self> {
+        if token.is_empty() {
+            return Err(anyhow!("Export token must not be empty"));
+        }
+        Ok(Self {
+            store,
+            token_hash: Sha256::digest(token.as_bytes()).into(),
+        })
+    }
+
+    /// Whether an `Authorization` header carries the export token. Digests
+    /// are compared so the check doesn't leak the token's length or prefix.
+    pub fn authorized(&self, authorization: Option<&str>) -> bool {
+        let Some(token) = authorization.and_then(|value| value.strip_prefix("Bearer ")) else {
+            return false;
+        };
+        let presented: [u8; 32] = Sha256::digest(token.trim().as_bytes()).into();
+        presented
+            .iter()
+            .zip(self.token_hash.iter())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
+
+    /// Inclusive height range an export query covers, checked against the tip
+    pub fn range(&self, query: ExportQuery) -> Result<(u64, u64)> {
+        if self.store.get_best_block()?.is_none() {
+            return Err(anyhow!("No blocks to export"));
+        }
+        let tip = self.store.get_height()?;
+        let to = query.to.unwrap_or(tip).min(tip);
+        if query.from > to {
+            return Err(anyhow!("Range {}..={} is empty (tip is {})", query.from, to, tip));
+        }
+        Ok((query.from, to))
+    }
+
+    /// Write blocks `from..=to` to `out` as export records, returning the
+    /// number of blocks written
+    pub fn write_blocks<W: Write>(&self, from: u64, to: u64, out: &mut W) -> Result<u64> {
+        let mut written = 0;
+        for entry in self.store.iter_blocks_range(from, to.saturating_add(1)) {
+            let (height, bytes) = entry?;
+            let len = u32::try_from(bytes.len()).map_err(|_| anyhow!("Block {} is too large to export", height))?;
+            out.write_all(&height.to_be_bytes())?;
+            out.write_all(&len.to_be_bytes())?;
+            out.write_all(&bytes)?;
+            written += 1;
+        }
+        out.flush()?;
+        Ok(written)
+    }
+
+    /// Stream blocks `from..=to` into `chunks` from the blocking pool
+    #[cfg(any(feature = "http-server", test))]
+    pub fn spawn_stream(
+        &self,
+        from: u64,
+        to: u64,
+        chunks: tokio::sync::mpsc::Sender<Vec<u8>>,
+    ) -> tokio::task::JoinHandle<Result<u64>> {
+        let export = self.clone();
+        tokio::task::spawn_blocking(move || export.write_blocks(from, to, &mut ChunkWriter::new(chunks)))
+    }
+}
+
+/// Read the next record of an export stream, or `None` at its end
+pub fn read_export_record<R: Read>(reader: &mut R) -> Result<Option<(u64, Block)>> {
+    let mut header = [0u8; EXPORT_RECORD_HEADER_LEN];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let height = u64::from_be_bytes(header[..8].try_into().expect("8-byte slice"));
+    let len = u32::from_be_bytes(header[8..].try_into().expect("4-byte slice"));
+    let mut bytes = vec![0u8; len as usize];
+    reader.read_exact(&mut bytes)?;
+    let block = Block::decode(&bytes).map_err(|e| anyhow!("Corrupt block {} in export: {}", height, e))?;
+    Ok(Some((height, block)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::MemoryStore;
+    use crate::consensus::testing;
+
+    fn store_with_blocks(count: u64) -> Arc<ChainStore> {
+        let store = ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap();
+        for height in 0..count {
+            let block = testing::block(height, [height as u8; 32], vec![]);
+            store.put_block(height, &block.encode()).unwrap();
+            store.set_height(height).unwrap();
+            store.set_best_block(&[height as u8; 32]).unwrap();
+        }
+        Arc::new(store)
+    }
+
+    #[test]
+    fn test_export_auth_and_range() {
+        let export = BlockExport::new(store_with_blocks(5), "s3cret").unwrap();
+        assert!(export.authorized(Some("Bearer s3cret")));
+        assert!(!export.authorized(Some("Bearer s3cre")));
+        assert!(!export.authorized(Some("s3cret")));
+        assert!(!export.authorized(None));
+        assert!(BlockExport::new(store_with_blocks(0), "").is_err());
+
+        assert_eq!(export.range(ExportQuery { from: 1, to: None }).unwrap(), (1, 4));
+        assert_eq!(export.range(ExportQuery { from: 2, to: Some(99) }).unwrap(), (2, 4));
+        assert!(export.range(ExportQuery { from: 5, to: None }).is_err());
+        assert!(BlockExport::new(store_with_blocks(0), "t")
+            .unwrap()
+            .range(ExportQuery { from: 0, to: None })
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_streamed_export_round_trip() {
+        let export = BlockExport::new(store_with_blocks(6), "t").unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        let task = export.spawn_stream(2, 4, tx);
+        let mut body = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            body.extend(chunk);
+        }
+        assert_eq!(task.await.unwrap().unwrap(), 3);
+
+        let mut reader = body.as_slice();
+        let mut heights = Vec::new();
+        while let Some((height, block)) = read_export_record(&mut reader).unwrap() {
+            assert_eq!(block.header.height, height);
+            heights.push(height);
+        }
+        assert_eq!(heights, vec![2, 3, 4]);
+    }
+}
@@ -0,0 +1,178 @@
+//! Signed node identity for RPC responses
+//!
+//! Clients that aggregate answers from several third-party nodes need to
+//! know which node served each one. With response signing enabled, read
+//! responses over HTTP carry the node's id, a timestamp and a BIP-340
+//! signature over both and the hash of the response body. The node id is the
+//! x-only public key of a key kept in the data directory, so it survives
+//! restarts and can be pinned by clients.
+
+use anyhow::{anyhow, Context, Result};
+use bitcoin::secp256k1::{schnorr, Keypair, Message, Secp256k1, SecretKey, XOnlyPublicKey};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// File in the data directory holding the identity key (hex)
+pub const NODE_IDENTITY_FILE: &str = "rpc_identity.key";
+
+/// Header carrying the signing node's id (hex x-only public key)
+pub const NODE_ID_HEADER: &str = "X-Excalibur-Node-Id";
+/// Header carrying the signing time (Unix seconds)
+pub const NODE_TIMESTAMP_HEADER: &str = "X-Excalibur-Timestamp";
+/// Header carrying the BIP-340 signature (hex)
+pub const NODE_SIGNATURE_HEADER: &str = "X-Excalibur-Signature";
+
+/// Method name prefixes of read-only methods, whose responses are signed
+const READ_METHOD_PREFIXES: &[&str] = &["get", "list", "search", "validate"];
+
+/// Whether a method only reads state
+pub fn is_read_method(method: &str) -> bool {
+    READ_METHOD_PREFIXES.iter().any(|prefix| method.starts_with(prefix))
+}
+
+/// Hash a response signature commits to
+pub fn response_signing_hash(body: &[u8], timestamp: u64, node_id: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"ExcaliburRpc/response");
+    hasher.update(Sha256::digest(body));
+    hasher.update(timestamp.to_be_bytes());
+    hasher.update(node_id);
+    hasher.finalize().into()
+}
+
+/// Key a node signs its responses with
+pub struct NodeIdentity {
+    keypair: Keypair,
+}
+
+impl NodeIdentity {
+    /// Identity with a fresh random key
+    pub fn generate() -> Self {
+        // Almost every 32-byte string is a valid key
+        let secret = std::iter::repeat_with(rand::random::<[u8; 32]>)
+            .find_map(|bytes| SecretKey::from_slice(&bytes).ok())
+            .expect("random keys are eventually valid");
+        Self {
+            keypair: Keypair::from_secret_key(&Secp256k1::new(), &secret),
+        }
+    }
+
+    /// Load the identity key at `path`, creating it on first use
+    pub fn load_or_create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if path.exists() {
+            let encoded = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read node identity {}", path.display()))?;
+            let secret = hex::decode(encoded.trim())
+                .ok()
+                .and_then(|bytes| SecretKey::from_slice(&bytes).ok())
+                .ok_or_else(|| anyhow!("Corrupt node identity key {}", path.display()))?;
+            return Ok(Self {
+                keypair: Keypair::from_secret_key(&Secp256k1::new(), &secret),
+            });
+        }
+
+        let identity = Self::generate();
+        std::fs::write(path, hex::encode(identity.keypair.secret_bytes()))
+            .with_context(|| format!("Failed to write node identity {}", path.display()))?;
+        Ok(identity)
+    }
+
+    /// Node id clients attribute responses to
+    pub fn node_id(&self) -> [u8; 32] {
+        self.keypair.x_only_public_key().0.serialize()
+    }
+
+    /// Sign a response body at `timestamp`
+    pub fn sign_response(&self, body: &[u8], timestamp: u64) -> ResponseSignature {
+        let node_id = self.node_id();
+        let message = Message::from_digest(response_signing_hash(body, timestamp, &node_id));
+        let signature = Secp256k1::signing_only().sign_schnorr_with_aux_rand(&message, &self.keypair, &rand::random());
+        ResponseSignature {
+            node_id,
+            timestamp,
+            signature: *signature.as_ref(),
+        }
+    }
+}
+
+/// Identity headers of a signed response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseSignature {
+    pub node_id: [u8; 32],
+    pub timestamp: u64,
+    pub signature: [u8; 64],
+}
+
+impl ResponseSignature {
+    /// Header names and values, in the order they are sent
+    pub fn headers(&self) -> [(&'static str, String); 3] {
+        [
+            (NODE_ID_HEADER, hex::encode(self.node_id)),
+            (NODE_TIMESTAMP_HEADER, self.timestamp.to_string()),
+            (NODE_SIGNATURE_HEADER, hex::encode(self.signature)),
+        ]
+    }
+
+    /// Parse the identity header values
+    pub fn from_headers(node_id: &str, timestamp: &str, signature: &str) -> Result<Self> {
+        Ok(Self {
+            node_id: hex::decode(node_id)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| anyhow!("Invalid node id header"))?,
+            timestamp: timestamp.parse().map_err(|_| anyhow!("Invalid timestamp header"))?,
+            signature: hex::decode(signature)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| anyhow!("Invalid signature header"))?,
+        })
+    }
+
+    /// Check the signature covers `body` and was made by `node_id`
+    pub fn verify(&self, body: &[u8]) -> Result<()> {
+        let public_key = XOnlyPublicKey::from_slice(&self.node_id).map_err(|_| anyhow!("Invalid node id"))?;
+        let signature = schnorr::Signature::from_slice(&self.signature)?;
+        let message = Message::from_digest(response_signing_hash(body, self.timestamp, &self.node_id));
+        Secp256k1::verification_only()
+            .verify_schnorr(&signature, &message, &public_key)
+            .map_err(|_| anyhow!("Response signature does not match node {}", hex::encode(self.node_id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signed_response_verifies() {
+        let identity = NodeIdentity::generate();
+        let body = br#"{"jsonrpc":"2.0","result":7,"id":1}"#;
+        let signature = identity.sign_response(body, 1_700_000_000);
+        assert_eq!(signature.node_id, identity.node_id());
+
+        let [(_, node_id), (_, timestamp), (_, sig)] = signature.headers();
+        let parsed = ResponseSignature::from_headers(&node_id, &timestamp, &sig).unwrap();
+        parsed.verify(body).unwrap();
+
+        assert!(parsed.verify(br#"{"jsonrpc":"2.0","result":8,"id":1}"#).is_err());
+        let backdated = ResponseSignature { timestamp: 1, ..parsed.clone() };
+        assert!(backdated.verify(body).is_err());
+        let impostor = ResponseSignature { node_id: NodeIdentity::generate().node_id(), ..parsed };
+        assert!(impostor.verify(body).is_err());
+
+        assert!(is_read_method("getblock") && is_read_method("listevidence"));
+        assert!(!is_read_method("submitforge") && !is_read_method("settxindex"));
+    }
+
+    #[test]
+    fn test_identity_persists() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join(NODE_IDENTITY_FILE);
+        let created = NodeIdentity::load_or_create(&path).unwrap();
+        assert_eq!(NodeIdentity::load_or_create(&path).unwrap().node_id(), created.node_id());
+
+        std::fs::write(&path, "not hex").unwrap();
+        assert!(NodeIdentity::load_or_create(&path).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! Asynchronous forge submission jobs
+//!
+//! Full forge validation reruns the proof-of-forge pipeline and takes
+//! seconds, long enough for aggressive proxies to time out a synchronous
+//! `submitforge`. `submitforgeasync` instead records a job, validates on the
+//! blocking pool and returns the job id at once; `getsubmitjob` reports the
+//! outcome and `cancelsubmitjob` stops a pending job. Only the most recent
+//! `MAX_SUBMIT_JOBS` jobs are remembered.
+
+use crate::crypto::{CancelToken, ForgeCancelled};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Jobs remembered before the oldest finished ones are forgotten
+pub const MAX_SUBMIT_JOBS: usize = 1024;
+
+/// Outcome of a submission job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// Still being validated
+    Pending,
+    /// Validated and admitted to the mempool
+    Accepted,
+    /// Failed validation or mempool admission
+    Rejected,
+    /// Stopped by `cancelsubmitjob` before validation finished
+    Cancelled,
+}
+
+/// One submitted forge (`getsubmitjob`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmitJob {
+    pub id: u64,
+    pub proof_hash: [u8; 32],
+    pub status: JobStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct JobTable {
+    next_id: u64,
+    jobs: BTreeMap<u64, SubmitJob>,
+    /// Cancel tokens of pending jobs
+    cancels: BTreeMap<u64, CancelToken>,
+}
+
+/// Submission jobs by id
+#[derive(Debug, Default)]
+pub struct SubmitJobs {
+    table: Mutex<JobTable>,
+}
+
+impl SubmitJobs {
+    /// Create an empty job table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a pending job for a forge, forgetting the oldest finished job
+    /// if the table is full. Fails if every remembered job is still pending.
+    /// Validation should stop when the returned token is cancelled.
+    pub fn create(&self, proof_hash: [u8; 32]) -> Result<(u64, CancelToken)> {
+        let mut table = self.table.lock().unwrap();
+        if table.jobs.len() >= MAX_SUBMIT_JOBS {
+            let oldest_finished = table
+                .jobs
+                .values()
+                .find(|job| job.status != JobStatus::Pending)
+                .map(|job| job.id)
+                .ok_or_else(|| anyhow!("Too many forge submissions in progress"))?;
+            table.jobs.remove(&oldest_finished);
+        }
+
+        table.next_id += 1;
+        let id = table.next_id;
+        table.jobs.insert(
+            id,
+            SubmitJob {
+                id,
+                proof_hash,
+                status: JobStatus::Pending,
+                error: None,
+            },
+        );
+        let cancel = CancelToken::new();
+        table.cancels.insert(id, cancel.clone());
+        Ok((id, cancel))
+    }
+
+    /// Record the outcome of a job
+    pub fn finish(&self, id: u64, outcome: &Result<()>) {
+        let mut table = self.table.lock().unwrap();
+        table.cancels.remove(&id);
+        if let Some(job) = table.jobs.get_mut(&id) {
+            match outcome {
+                Ok(()) => job.status = JobStatus::Accepted,
+                Err(e) if e.is::<ForgeCancelled>() => job.status = JobStatus::Cancelled,
+                Err(e) => {
+                    job.status = JobStatus::Rejected;
+                    job.error = Some(format!("{:#}", e));
+                }
+            }
+        }
+    }
+
+    /// Ask a pending job to stop. Its status changes once validation has
+    /// stopped; None if the job isn't remembered.
+    pub fn cancel(&self, id: u64) -> Option<SubmitJob> {
+        let table = self.table.lock().unwrap();
+        if let Some(cancel) = table.cancels.get(&id) {
+            cancel.cancel();
+        }
+        table.jobs.get(&id).cloned()
+    }
+
+    /// Look up a job, if it is still remembered
+    pub fn get(&self, id: u64) -> Option<SubmitJob> {
+        self.table.lock().unwrap().jobs.get(&id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_finished_job_evicted() {
+        let jobs = SubmitJobs::new();
+        let (first, _) = jobs.create([1u8; 32]).unwrap();
+        let (second, _) = jobs.create([2u8; 32]).unwrap();
+        for _ in 2..MAX_SUBMIT_JOBS {
+            jobs.create([0u8; 32]).unwrap();
+        }
+
+        // Everything is pending, so nothing can be forgotten yet
+        assert!(jobs.create([3u8; 32]).is_err());
+
+        jobs.finish(second, &Err(anyhow!("Derived key mismatch")));
+        jobs.finish(first, &Ok(()));
+        let (replacement, _) = jobs.create([3u8; 32]).unwrap();
+        assert!(jobs.get(first).is_none());
+        assert_eq!(jobs.get(second).unwrap().error.as_deref(), Some("Derived key mismatch"));
+        assert_eq!(jobs.get(replacement).unwrap().status, JobStatus::Pending);
+    }
+
+    #[test]
+    fn test_cancel_pending_job() {
+        let jobs = SubmitJobs::new();
+        let (id, cancel) = jobs.create([1u8; 32]).unwrap();
+        assert_eq!(jobs.cancel(id).unwrap().status, JobStatus::Pending);
+        assert!(cancel.is_cancelled());
+        jobs.finish(id, &Err(ForgeCancelled.into()));
+        assert_eq!(jobs.get(id).unwrap().status, JobStatus::Cancelled);
+
+        // Finished jobs keep their outcome
+        let (accepted, cancel) = jobs.create([2u8; 32]).unwrap();
+        jobs.finish(accepted, &Ok(()));
+        assert_eq!(jobs.cancel(accepted).unwrap().status, JobStatus::Accepted);
+        assert!(!cancel.is_cancelled());
+        assert!(jobs.cancel(999).is_none());
+    }
+}
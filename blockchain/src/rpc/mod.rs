@@ -1,11 +1,61 @@
 //! JSON-RPC API server
 
+use crate::chain::{parse_query, ChainStore, ReorgGuard};
+use crate::codec::header_hash_preimage;
+use crate::config::ConfigReloader;
+use crate::consensus::sighash::{transfer_sighash, SighashType, SpendType, Transfer, TransferOutput};
+use crate::consensus::{
+    forge_leaf_hash, state_root, transfer_root, AuthorityKey, Block, ConsensusEngine, ForgeTransaction, MerkleTree,
+    SignedTransfer, StateProof,
+};
+use crate::crypto::musig::{self, KeyAggContext};
+use crate::crypto::prophecy_registry_hash;
+use crate::events::EventBus;
+use crate::ledger::{LedgerSetInfo, OutPoint};
+use crate::mempool::{BlockTemplateCache, ForgeOrigin, ForgePool, ValidationQueue};
+use crate::miner::Miner;
+use crate::network::{
+    node_version, subversion, BandwidthTracker, BodyFetchQueue, ConnectionStats, NetworkCommand, PeerFilter, PeerInventory,
+    PeerRule, PeerServices, ReconnectSchedule, RuleList, ServiceFlags,
+};
+use crate::supervisor::Supervisor;
+use crate::sync::ChainSync;
+use crate::wallet::{VaultState, Wallet, DEFAULT_VAULT_DELAY};
+use crate::watchtower::{SpendWatch, WatchedOutput};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Notify, RwLock};
 use anyhow::{Result, anyhow};
+#[cfg(feature = "http-server")]
+use crate::shutdown::ShutdownSignal;
+
+mod auth;
+mod cache;
+mod export;
+mod identity;
+mod jobs;
+mod page;
+mod policy;
+mod serialize;
+mod subscribe;
+
+pub use auth::{basic_authorization, RpcAuth, COOKIE_FILE, COOKIE_USER};
+pub use cache::{CacheKey, ResponseCache, ResponseCacheStats, DEFAULT_RESPONSE_CACHE_ENTRIES};
+pub use export::{read_export_record, BlockExport, ExportQuery, EXPORT_RECORD_HEADER_LEN};
+pub use identity::{
+    is_read_method, response_signing_hash, NodeIdentity, ResponseSignature, NODE_IDENTITY_FILE, NODE_ID_HEADER,
+    NODE_SIGNATURE_HEADER, NODE_TIMESTAMP_HEADER,
+};
+pub use jobs::{JobStatus, SubmitJob, SubmitJobs, MAX_SUBMIT_JOBS};
+pub use page::{KeyOrder, Page, PageRequest, DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT};
+pub use policy::{MethodAccess, RpcCaller, RpcPolicy};
+pub use serialize::{ResponseMetrics, LARGE_RESPONSE_BYTES, STREAM_CHUNK_BYTES};
+pub use subscribe::{mempool_event_notification, node_event_notification, Subscriptions, Topic};
 
 /// JSON-RPC request
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,16 +86,107 @@ pub struct JsonRpcError {
     pub data: Option<Value>,
 }
 
-/// RPC method handler (async)
+/// General application error
+pub const RPC_MISC_ERROR: i32 = -1;
+/// The access policy doesn't let the caller use the method
+pub const RPC_FORBIDDEN: i32 = -2;
+/// Requested item was not found
+pub const RPC_NOT_FOUND: i32 = -5;
+/// Unlocked wallet outputs don't cover the requested amount
+pub const RPC_WALLET_INSUFFICIENT_FUNDS: i32 = -6;
+/// Invalid, missing, or out-of-range parameter
+pub const RPC_INVALID_PARAMETER: i32 = -8;
+/// Submitted item failed validation
+pub const RPC_VERIFY_REJECTED: i32 = -26;
+/// Submitted item is already in the mempool or chain
+pub const RPC_VERIFY_ALREADY_IN_CHAIN: i32 = -27;
+/// Query needs an index that is disabled
+pub const RPC_INDEX_DISABLED: i32 = -20;
+
+/// Handler error carrying a specific JSON-RPC error code
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct RpcMethodError {
+    pub code: i32,
+    pub message: String,
+}
+
+impl RpcMethodError {
+    /// Create a new handler error
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Default and largest page size for `searchforges`
+pub const DEFAULT_SEARCH_LIMIT: usize = 25;
+pub const MAX_SEARCH_LIMIT: usize = 100;
+
+/// Introspection methods registered when `[rpc] debug` is set, local-only
+/// unless the operator's policy says otherwise
+pub const DEBUG_METHODS: [&str; 4] = [
+    "debug_getchaintips",
+    "debug_getusedprophecycount",
+    "debug_dumporphans",
+    "debug_getvalidationqueue",
+];
+
+/// Default time in-flight requests get to finish once shutdown starts
+pub const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+/// Tracks in-flight requests so shutdown can drain them
+#[derive(Debug, Default)]
+struct DrainState {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+/// Marks a request as in flight until dropped
+pub struct InFlightGuard {
+    drain: Arc<DrainState>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.drain.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.drain.idle.notify_waiters();
+        }
+    }
+}
+
 use std::future::Future;
 use std::pin::Pin;
 
+/// RPC method handler: returns a boxed future so handlers await shared
+/// state instead of blocking the runtime
 type RpcHandler = Arc<dyn Fn(Option<Value>) -> Pin<Box<dyn Future<Output = Result<Value>> + Send>> + Send + Sync>;
 
 /// JSON-RPC server
 pub struct RpcServer {
-    handlers: Arc<RwLock<HashMap<String, RpcHandler>>>,
+    /// Handlers by method. The lock is only held to insert or clone a
+    /// handler, never across an await.
+    handlers: Arc<std::sync::RwLock<HashMap<String, RpcHandler>>>,
     state: Arc<RwLock<ServerState>>,
+    drain: Arc<DrainState>,
+    /// Mempool whose events are streamed to WebSocket subscribers
+    mempool: Option<Arc<ForgePool>>,
+    /// Event bus behind `/ws` subscriptions, if enabled
+    events: Option<EventBus>,
+    response_metrics: Arc<ResponseMetrics>,
+    /// Responses for blocks and forges looked up by hash
+    response_cache: Arc<ResponseCache>,
+    /// Store and credentials behind `/export/blocks`, if enabled
+    export: Option<BlockExport>,
+    /// Key read responses are signed with, if enabled
+    identity: Option<Arc<NodeIdentity>>,
+    /// Credentials HTTP requests must present; open when unset
+    auth: Option<Arc<RpcAuth>>,
+    /// Which HTTP callers may use each method
+    policy: Arc<RpcPolicy>,
 }
 
 #[derive(Debug, Clone)]
@@ -56,17 +197,32 @@ struct ServerState {
     version: String,
 }
 
+impl Default for RpcServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RpcServer {
     /// Create a new RPC server
     pub fn new() -> Self {
         let mut server = RpcServer {
-            handlers: Arc::new(RwLock::new(HashMap::new())),
+            handlers: Arc::new(std::sync::RwLock::new(HashMap::new())),
             state: Arc::new(RwLock::new(ServerState {
                 chain_height: 0,
                 total_forges: 0,
                 peer_count: 0,
                 version: "1.0.0".to_string(),
             })),
+            drain: Arc::new(DrainState::default()),
+            mempool: None,
+            events: None,
+            response_metrics: Arc::new(ResponseMetrics::default()),
+            response_cache: Arc::new(ResponseCache::default()),
+            export: None,
+            identity: None,
+            auth: None,
+            policy: Arc::new(RpcPolicy::default()),
         };
         
         server.register_default_handlers();
@@ -98,56 +254,6 @@ impl RpcServer {
                     "blocks": state.chain_height,
                     "forges": state.total_forges,
                     "connections": state.peer_count,
-                    "network": "mainnet",
-                    "difficulty": 2,
-                }))
-            })
-        });
-
-        // getblock - Get block by height
-        self.register_handler("getblock", |params| {
-            Box::pin(async move {
-                let height = params
-                    .and_then(|p| p.as_u64())
-                    .ok_or_else(|| anyhow!("Missing or invalid 'height' parameter"))?;
-                
-                // This would normally fetch from chain store
-                Ok(json!({
-                    "height": height,
-                    "hash": format!("{:064x}", height),
-                    "forges": [],
-                    "timestamp": 0,
-                }))
-            })
-        });
-
-        // getforge - Get forge transaction by proof hash
-        self.register_handler("getforge", |params| {
-            Box::pin(async move {
-                let proof_hash = params
-                    .and_then(|p| p.as_str())
-                    .ok_or_else(|| anyhow!("Missing or invalid 'proof_hash' parameter"))?;
-                
-                // This would normally fetch from chain store
-                Ok(json!({
-                    "proof_hash": proof_hash,
-                    "prophecy": "sword legend pull magic kingdom artist stone destroy forget fire steel honey question",
-                    "taproot_address": "bc1p...",
-                    "timestamp": 0,
-                }))
-            })
-        });
-
-        // submitforge - Submit a new forge transaction
-        self.register_handler("submitforge", |params| {
-            Box::pin(async move {
-                let forge_data = params
-                    .ok_or_else(|| anyhow!("Missing forge data"))?;
-                
-                // This would normally validate and add to mempool
-                Ok(json!({
-                    "success": true,
-                    "txid": "0000000000000000000000000000000000000000000000000000000000000000",
                 }))
             })
         });
@@ -170,7 +276,7 @@ impl RpcServer {
         self.register_handler("validateprophecy", |params| {
             Box::pin(async move {
                 let prophecy = params
-                    .and_then(|p| p.as_str())
+                    .and_then(|p| p.as_str().map(String::from))
                     .ok_or_else(|| anyhow!("Missing or invalid 'prophecy' parameter"))?;
                 
                 let is_valid = prophecy == "sword legend pull magic kingdom artist stone destroy forget fire steel honey question";
@@ -182,213 +288,3444 @@ impl RpcServer {
             })
         });
 
-        // getdifficulty - Get current mining difficulty
-        self.register_handler("getdifficulty", |_params| {
+        let drain = Arc::clone(&self.drain);
+        let response_metrics = Arc::clone(&self.response_metrics);
+        let response_cache = Arc::clone(&self.response_cache);
+
+        // getrpcinfo - In-flight requests, response size and cache statistics
+        self.register_handler("getrpcinfo", move |_params| {
+            let drain = Arc::clone(&drain);
+            let response_metrics = Arc::clone(&response_metrics);
+            let response_cache = Arc::clone(&response_cache);
             Box::pin(async move {
-                Ok(json!(2))
+                Ok(json!({
+                    "in_flight": drain.in_flight.load(Ordering::SeqCst),
+                    "response_bytes": response_metrics.sizes(),
+                    "offloaded_responses": response_metrics.offloaded(),
+                    "response_cache": response_cache.stats(),
+                }))
             })
         });
     }
 
-    /// Register a custom RPC handler
-    pub fn register_handler<F, Fut>(&mut self, method: &str, handler: F)
-    where
-        F: Fn(Option<Value>) -> Fut + Send + Sync + 'static,
-        Fut: Future<Output = Result<Value>> + Send + 'static,
-    {
-        let handlers = Arc::clone(&self.handlers);
-        let wrapper = Arc::new(move |params: Option<Value>| {
-            Box::pin(handler(params)) as Pin<Box<dyn Future<Output = Result<Value>> + Send>>
+    /// Register ledger handlers backed by the consensus engine and chain store
+    pub fn register_ledger_handlers(
+        &mut self,
+        engine: Arc<ConsensusEngine>,
+        store: Arc<ChainStore>,
+        treasury_addresses: Vec<String>,
+    ) {
+        let proof_engine = Arc::clone(&engine);
+        let supply_engine = Arc::clone(&engine);
+        let treasury_addresses = Arc::new(treasury_addresses);
+
+        // getledgersetinfo - Output set statistics, optionally at a past height
+        self.register_handler("getledgersetinfo", move |params| {
+            let engine = Arc::clone(&engine);
+            let store = Arc::clone(&store);
+            Box::pin(async move {
+                let info = match params.as_ref().and_then(|p| p.as_u64()) {
+                    Some(height) => store
+                        .get_ledger_info(height)?
+                        .ok_or_else(|| anyhow!("No ledger statistics recorded at height {}", height))?,
+                    None => engine.get_ledger_info(),
+                };
+                Ok(ledger_info_json(&info))
+            })
+        });
+
+        // getstateproof - Proof of an output ({"txid", "vout"}) or a used
+        // proof hash ({"proof_hash"}) against the tip's state root
+        self.register_handler("getstateproof", move |params| {
+            let engine = Arc::clone(&proof_engine);
+            Box::pin(async move {
+                let params = params.unwrap_or(Value::Null);
+                let hash_field = |name: &str| {
+                    params
+                        .get(name)
+                        .and_then(|p| p.as_str())
+                        .and_then(|p| hex::decode(p).ok())
+                        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                };
+                let height = engine.get_height();
+                let proof = if let Some(proof_hash) = hash_field("proof_hash") {
+                    engine.prove_used_proof(&proof_hash)
+                } else if let Some(txid) = hash_field("txid") {
+                    let vout = params
+                        .get("vout")
+                        .and_then(|v| v.as_u64())
+                        .and_then(|v| u32::try_from(v).ok())
+                        .unwrap_or(0);
+                    engine.prove_output(&OutPoint { txid, vout })
+                } else {
+                    return Err(RpcMethodError::new(
+                        RPC_INVALID_PARAMETER,
+                        "Expected a 32-byte hex 'txid' (with 'vout') or 'proof_hash'",
+                    )
+                    .into());
+                };
+                Ok(state_proof_json(height, &proof))
+            })
         });
-        futures::executor::block_on(async {
-            let mut handlers = handlers.write().await;
-            handlers.insert(method.to_string(), wrapper);
+
+        // getsupplyinfo - Circulating supply, burned fees and treasury
+        // balance, checked against the emission schedule
+        self.register_handler("getsupplyinfo", move |_params| {
+            let engine = Arc::clone(&supply_engine);
+            let treasury_addresses = Arc::clone(&treasury_addresses);
+            Box::pin(async move {
+                let info = tokio::task::spawn_blocking(move || engine.get_supply_info(&treasury_addresses)).await?;
+                if info.discrepancy {
+                    tracing::error!(
+                        "Supply discrepancy at height {}: circulating {} differs from expected {}",
+                        info.height,
+                        info.circulating,
+                        info.expected_supply
+                    );
+                }
+                Ok(serde_json::to_value(info)?)
+            })
         });
     }
 
-    /// Handle a JSON-RPC request
-    pub async fn handle_request(&self, request: JsonRpcRequest) -> JsonRpcResponse {
-        // Validate JSON-RPC version
-        if request.jsonrpc != "2.0" {
-            return JsonRpcResponse {
-                jsonrpc: "2.0".to_string(),
-                result: None,
-                error: Some(JsonRpcError {
-                    code: -32600,
-                    message: "Invalid Request - jsonrpc must be '2.0'".to_string(),
-                    data: None,
-                }),
-                id: request.id,
-            };
-        }
+    /// Register forge submission handlers that queue forges for validation
+    /// as local submissions, admit them to the mempool and announce accepted
+    /// forges through `relay` (if given)
+    pub fn register_submit_handlers(
+        &mut self,
+        queue: Arc<ValidationQueue>,
+        pool: Arc<ForgePool>,
+        relay: Option<mpsc::Sender<NetworkCommand>>,
+    ) {
+        let jobs = Arc::new(SubmitJobs::new());
 
-        // Get handler
-        let handlers = self.handlers.read().await;
-        let handler = match handlers.get(&request.method) {
-            Some(h) => Arc::clone(h),
-            None => {
-                return JsonRpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    result: None,
-                    error: Some(JsonRpcError {
-                        code: -32601,
-                        message: format!("Method not found: {}", request.method),
-                        data: None,
-                    }),
-                    id: request.id,
-                };
-            }
-        };
-        
-        drop(handlers);
+        let submit_queue = Arc::clone(&queue);
+        let submit_pool = Arc::clone(&pool);
+        let submit_relay = relay.clone();
 
-        // Execute handler
-        match handler(request.params).await {
-            Ok(result) => JsonRpcResponse {
-                jsonrpc: "2.0".to_string(),
-                result: Some(result),
-                error: None,
-                id: request.id,
-            },
-            Err(e) => JsonRpcResponse {
-                jsonrpc: "2.0".to_string(),
-                result: None,
-                error: Some(JsonRpcError {
-                    code: -32603,
-                    message: "Internal error".to_string(),
-                    data: Some(json!({ "error": e.to_string() })),
-                }),
-                id: request.id,
-            },
-        }
-    }
+        // submitforge - Validate, admit and relay a forge, answering once done
+        self.register_handler("submitforge", move |params| {
+            let queue = Arc::clone(&submit_queue);
+            let pool = Arc::clone(&submit_pool);
+            let relay = submit_relay.clone();
+            Box::pin(async move {
+                let forge = forge_param(params)?;
+                let proof_hash = forge.proof_hash;
+                if pool.contains(&proof_hash) {
+                    return Err(RpcMethodError::new(RPC_VERIFY_ALREADY_IN_CHAIN, "Forge already in mempool").into());
+                }
+                let encoded = forge.encode();
+                let outcome = queue
+                    .submit(forge, ForgeOrigin::Local)
+                    .map_err(|e| RpcMethodError::new(RPC_MISC_ERROR, e.to_string()))?;
+                finish_submission(outcome, relay.as_ref(), encoded)
+                    .await
+                    .map_err(|e| RpcMethodError::new(RPC_VERIFY_REJECTED, format!("{:#}", e)))?;
+                Ok(json!({ "success": true, "proof_hash": hex::encode(proof_hash) }))
+            })
+        });
 
-    /// Handle a raw JSON request string
-    pub async fn handle_request_str(&self, request_str: &str) -> String {
-        let request: JsonRpcRequest = match serde_json::from_str(request_str) {
-            Ok(r) => r,
-            Err(e) => {
-                let error_response = JsonRpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    result: None,
-                    error: Some(JsonRpcError {
-                        code: -32700,
-                        message: "Parse error".to_string(),
-                        data: Some(json!({ "error": e.to_string() })),
-                    }),
-                    id: Value::Null,
+        let async_jobs = Arc::clone(&jobs);
+        let async_queue = Arc::clone(&queue);
+
+        // submitforgeasync - Queue a forge for validation and return a job id
+        self.register_handler("submitforgeasync", move |params| {
+            let queue = Arc::clone(&async_queue);
+            let pool = Arc::clone(&pool);
+            let relay = relay.clone();
+            let jobs = Arc::clone(&async_jobs);
+            Box::pin(async move {
+                let forge = forge_param(params)?;
+                let proof_hash = forge.proof_hash;
+                if pool.contains(&proof_hash) {
+                    return Err(RpcMethodError::new(RPC_VERIFY_ALREADY_IN_CHAIN, "Forge already in mempool").into());
+                }
+                let (id, cancel) = jobs
+                    .create(proof_hash)
+                    .map_err(|e| RpcMethodError::new(RPC_MISC_ERROR, e.to_string()))?;
+                let encoded = forge.encode();
+                let outcome = match queue.submit_cancellable(forge, ForgeOrigin::Local, cancel) {
+                    Ok(outcome) => outcome,
+                    Err(e) => {
+                        let message = e.to_string();
+                        jobs.finish(id, &Err(e));
+                        return Err(RpcMethodError::new(RPC_MISC_ERROR, message).into());
+                    }
                 };
-                return serde_json::to_string(&error_response).unwrap();
-            }
-        };
+                tokio::spawn(async move {
+                    let outcome = finish_submission(outcome, relay.as_ref(), encoded).await;
+                    if let Err(e) = &outcome {
+                        tracing::debug!("Submit job {} rejected: {:#}", id, e);
+                    }
+                    jobs.finish(id, &outcome);
+                });
+                Ok(json!({ "job_id": id, "proof_hash": hex::encode(proof_hash) }))
+            })
+        });
 
-        let response = self.handle_request(request).await;
-        serde_json::to_string(&response).unwrap()
-    }
+        // getvalidationqueueinfo - Queued local and gossiped forges and
+        // validation outcomes
+        self.register_handler("getvalidationqueueinfo", move |_params| {
+            let queue = Arc::clone(&queue);
+            Box::pin(async move { Ok(serde_json::to_value(queue.stats())?) })
+        });
 
-    /// Update server state
-    pub async fn update_state(&self, height: u64, forges: u64, peers: usize) {
-        let mut state = self.state.write().await;
-        state.chain_height = height;
-        state.total_forges = forges;
-        state.peer_count = peers;
+        let cancel_jobs = Arc::clone(&jobs);
+
+        // cancelsubmitjob - Stop a pending submitforgeasync job, even part
+        // way through re-deriving its proof
+        self.register_handler("cancelsubmitjob", move |params| {
+            let jobs = Arc::clone(&cancel_jobs);
+            Box::pin(async move {
+                let id = params
+                    .as_ref()
+                    .and_then(|p| p.as_u64())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected a job id"))?;
+                let job = jobs
+                    .cancel(id)
+                    .ok_or_else(|| RpcMethodError::new(RPC_NOT_FOUND, "Unknown or expired submit job"))?;
+                // Too late once the job has an outcome
+                Ok(json!({ "job_id": job.id, "cancelling": job.status == JobStatus::Pending }))
+            })
+        });
+
+        // getsubmitjob - Outcome of a submitforgeasync job
+        self.register_handler("getsubmitjob", move |params| {
+            let jobs = Arc::clone(&jobs);
+            Box::pin(async move {
+                let id = params
+                    .as_ref()
+                    .and_then(|p| p.as_u64())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected a job id"))?;
+                let job = jobs
+                    .get(id)
+                    .ok_or_else(|| RpcMethodError::new(RPC_NOT_FOUND, "Unknown or expired submit job"))?;
+                Ok(json!({
+                    "job_id": job.id,
+                    "proof_hash": hex::encode(job.proof_hash),
+                    "status": job.status,
+                    "error": job.error,
+                }))
+            })
+        });
     }
 
-    /// Run RPC server on HTTP endpoint
-    #[cfg(feature = "http-server")]
-    pub async fn run_http(&self, addr: &str) -> Result<()> {
-        use warp::Filter;
-        
-        let rpc = self.clone();
-        let rpc_handler = warp::path!("rpc")
-            .and(warp::post())
-            .and(warp::body::json())
-            .and_then(move |req: JsonRpcRequest| {
-                let rpc = rpc.clone();
-                async move {
-                    let response = rpc.handle_request(req).await;
-                    Ok::<_, std::convert::Infallible>(warp::reply::json(&response))
+    /// Register forge index (txindex) handlers
+    pub fn register_index_handlers(&mut self, store: Arc<ChainStore>) {
+        let building = Arc::new(AtomicBool::new(false));
+
+        let index_store = Arc::clone(&store);
+        let index_building = Arc::clone(&building);
+        let index_cache = Arc::clone(&self.response_cache);
+
+        // settxindex - Build (in the background) or drop the forge index
+        self.register_handler("settxindex", move |params| {
+            let store = Arc::clone(&index_store);
+            let building = Arc::clone(&index_building);
+            let cache = Arc::clone(&index_cache);
+            Box::pin(async move {
+                let enable = params
+                    .and_then(|p| p.as_bool())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected true or false"))?;
+
+                if building.load(Ordering::SeqCst) {
+                    return Err(RpcMethodError::new(RPC_MISC_ERROR, "Forge index build already in progress").into());
                 }
-            });
 
-        let addr: std::net::SocketAddr = addr.parse()?;
-        warp::serve(rpc_handler).run(addr).await;
-        Ok(())
-    }
-}
+                if enable {
+                    building.store(true, Ordering::SeqCst);
+                    tokio::task::spawn_blocking(move || {
+                        if let Err(e) = store.build_forge_index() {
+                            tracing::error!("Forge index build failed: {}", e);
+                        }
+                        building.store(false, Ordering::SeqCst);
+                    });
+                    Ok(json!({ "enabled": true, "building": true }))
+                } else {
+                    let removed = tokio::task::spawn_blocking(move || store.drop_forge_index()).await??;
+                    // Cached getrawforge responses must not outlive the index
+                    cache.clear();
+                    Ok(json!({ "enabled": false, "removed": removed }))
+                }
+            })
+        });
 
-impl Clone for RpcServer {
-    fn clone(&self) -> Self {
-        RpcServer {
-            handlers: Arc::clone(&self.handlers),
-            state: Arc::clone(&self.state),
-        }
-    }
-}
+        let info_store = Arc::clone(&store);
+        let info_building = Arc::clone(&building);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // gettxindexinfo - Forge index status
+        self.register_handler("gettxindexinfo", move |_params| {
+            let store = Arc::clone(&info_store);
+            let building = Arc::clone(&info_building);
+            Box::pin(async move {
+                Ok(json!({
+                    "enabled": store.forge_index_enabled()?,
+                    "building": building.load(Ordering::SeqCst),
+                    "indexed_blocks": store.forge_index_progress()?,
+                    "chain_height": store.get_height()?,
+                }))
+            })
+        });
 
-    #[tokio::test]
-    async fn test_rpc_server_creation() {
-        let server = RpcServer::new();
-        let request = JsonRpcRequest {
-            jsonrpc: "2.0".to_string(),
-            method: "getblockcount".to_string(),
-            params: None,
-            id: json!(1),
-        };
-        
-        let response = server.handle_request(request).await;
-        assert_eq!(response.jsonrpc, "2.0");
-        assert!(response.result.is_some());
-    }
+        let raw_store = Arc::clone(&store);
+        let raw_cache = Arc::clone(&self.response_cache);
 
-    #[tokio::test]
-    async fn test_getinfo() {
-        let server = RpcServer::new();
-        let request = JsonRpcRequest {
-            jsonrpc: "2.0".to_string(),
-            method: "getinfo".to_string(),
-            params: None,
-            id: json!(1),
-        };
-        
-        let response = server.handle_request(request).await;
-        assert!(response.result.is_some());
-        let result = response.result.unwrap();
-        assert!(result.get("version").is_some());
-        assert!(result.get("blocks").is_some());
-    }
+        // getrawforge - Historical forge lookup by proof hash (requires txindex)
+        self.register_handler("getrawforge", move |params| {
+            let store = Arc::clone(&raw_store);
+            let cache = Arc::clone(&raw_cache);
+            Box::pin(async move {
+                let proof_hash = params
+                    .as_ref()
+                    .and_then(|p| p.as_str())
+                    .and_then(|p| hex::decode(p).ok())
+                    .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected a 32-byte hex proof hash"))?;
 
-    #[tokio::test]
-    async fn test_method_not_found() {
-        let server = RpcServer::new();
-        let request = JsonRpcRequest {
-            jsonrpc: "2.0".to_string(),
-            method: "nonexistent_method".to_string(),
-            params: None,
-            id: json!(1),
-        };
-        
-        let response = server.handle_request(request).await;
-        assert!(response.error.is_some());
-        assert_eq!(response.error.unwrap().code, -32601);
-    }
+                if let Some(cached) = cache.get(&CacheKey::Forge(proof_hash)) {
+                    return Ok(cached);
+                }
+                if !store.forge_index_enabled()? {
+                    return Err(RpcMethodError::new(
+                        RPC_INDEX_DISABLED,
+                        "Forge index is disabled; enable txindex or call settxindex true",
+                    )
+                    .into());
+                }
 
-    #[tokio::test]
+                let (height, forge) = store
+                    .lookup_forge(&proof_hash)?
+                    .ok_or_else(|| RpcMethodError::new(RPC_NOT_FOUND, "No such forge in the chain"))?;
+
+                let response = json!({
+                    "proof_hash": hex::encode(forge.proof_hash),
+                    "prophecy": forge.prophecy,
+                    "taproot_address": forge.taproot_address,
+                    "timestamp": forge.timestamp,
+                    "height": height,
+                });
+                cache.insert(CacheKey::Forge(proof_hash), height, response.clone());
+                Ok(response)
+            })
+        });
+    }
+
+    /// Register forge lookup handlers backed by the mempool and chain store,
+    /// replacing the placeholder `getforge`
+    pub fn register_forge_handlers(&mut self, store: Arc<ChainStore>, pool: Arc<ForgePool>) {
+        let proof_store = Arc::clone(&store);
+
+        // getforge - Pending or mined forge by proof hash. Mined forges are
+        // found through the forge index (txindex).
+        self.register_handler("getforge", move |params| {
+            let store = Arc::clone(&store);
+            let pool = Arc::clone(&pool);
+            Box::pin(async move {
+                let proof_hash = params
+                    .as_ref()
+                    .and_then(|p| p.as_str())
+                    .and_then(|p| hex::decode(p).ok())
+                    .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected a 32-byte hex proof hash"))?;
+
+                if let Some(forge) = pool.get_forge(&proof_hash) {
+                    return Ok(forge_json(&forge, None, 0));
+                }
+                if !store.forge_index_enabled()? {
+                    return Err(RpcMethodError::new(
+                        RPC_INDEX_DISABLED,
+                        "Forge is not in the mempool and the forge index is disabled; enable txindex to look up mined forges",
+                    )
+                    .into());
+                }
+
+                let (height, forge) = store
+                    .lookup_forge(&proof_hash)?
+                    .ok_or_else(|| RpcMethodError::new(RPC_NOT_FOUND, "No such forge in the mempool or chain"))?;
+                let confirmations = store.get_height()?.saturating_sub(height) + 1;
+                Ok(forge_json(&forge, Some(height), confirmations))
+            })
+        });
+
+        // getforgeproof - Merkle path placing a mined forge under its
+        // block's forge root, for light clients (requires txindex). Blocks
+        // with transfers commit to the forge root tagged-hashed with
+        // `transfer_root`, so that is returned to reach `merkle_root`.
+        self.register_handler("getforgeproof", move |params| {
+            let store = Arc::clone(&proof_store);
+            Box::pin(async move {
+                let proof_hash = params
+                    .as_ref()
+                    .and_then(|p| p.as_str())
+                    .and_then(|p| hex::decode(p).ok())
+                    .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected a 32-byte hex proof hash"))?;
+                if !store.forge_index_enabled()? {
+                    return Err(RpcMethodError::new(
+                        RPC_INDEX_DISABLED,
+                        "Forge proofs need the forge index; enable txindex",
+                    )
+                    .into());
+                }
+
+                let (height, _) = store
+                    .lookup_forge(&proof_hash)?
+                    .ok_or_else(|| RpcMethodError::new(RPC_NOT_FOUND, "No such forge in the chain"))?;
+                let block = store
+                    .load_block(height)?
+                    .ok_or_else(|| RpcMethodError::new(RPC_NOT_FOUND, "No such block"))?;
+                let index = block
+                    .forges
+                    .iter()
+                    .position(|forge| forge.proof_hash == proof_hash)
+                    .ok_or_else(|| RpcMethodError::new(RPC_NOT_FOUND, "No such forge in the chain"))?;
+                let tree = MerkleTree::build(&block.forges);
+                let proof = tree.prove(index).expect("index is within the block");
+                let block_hash: [u8; 32] = Sha256::digest(header_hash_preimage(&block.header)).into();
+                Ok(json!({
+                    "proof_hash": hex::encode(proof_hash),
+                    "block_hash": hex::encode(block_hash),
+                    "height": height,
+                    "merkle_root": hex::encode(block.header.merkle_root),
+                    "forge_root": hex::encode(tree.root()),
+                    "transfer_root": transfer_root(&block.transfers).map(hex::encode),
+                    "leaf": hex::encode(forge_leaf_hash(&block.forges[index])),
+                    "index": proof.index,
+                    "siblings": proof.siblings.iter().map(hex::encode).collect::<Vec<_>>(),
+                }))
+            })
+        });
+    }
+
+    /// Register block lookup handlers backed by the chain store
+    pub fn register_block_handlers(&mut self, store: Arc<ChainStore>) {
+        let cache = Arc::clone(&self.response_cache);
+
+        // getblock - Block by hash or height, as `[block, verbosity]` like
+        // Bitcoin Core: 0 = hex, 1 = header and forge hashes (default),
+        // 2 = header and full forges. Responses are cached by hash.
+        self.register_handler("getblock", move |params| {
+            let store = Arc::clone(&store);
+            let cache = Arc::clone(&cache);
+            Box::pin(async move {
+                let (block_param, verbosity) = match params {
+                    Some(Value::Array(mut params)) if (1..=2).contains(&params.len()) => {
+                        let verbosity = match params.get(1) {
+                            Some(verbosity) => verbosity.as_u64().filter(|v| *v <= 2).ok_or_else(|| {
+                                RpcMethodError::new(RPC_INVALID_PARAMETER, "Verbosity must be 0, 1 or 2")
+                            })? as u8,
+                            None => 1,
+                        };
+                        (params.swap_remove(0), verbosity)
+                    }
+                    Some(param) => (param, 1),
+                    None => (Value::Null, 1),
+                };
+
+                let (height, requested) = match block_param {
+                    Value::Number(height) => (height.as_u64(), None),
+                    Value::String(hash) => {
+                        let hash = hex::decode(&hash)
+                            .ok()
+                            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                            .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected a 32-byte hex block hash"))?;
+                        if let Some(cached) = cache.get(&CacheKey::Block(hash, verbosity)) {
+                            return Ok(cached);
+                        }
+                        (store.get_block_height_by_hash(&hash)?, Some(hash))
+                    }
+                    _ => return Err(RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected a block hash or height").into()),
+                };
+
+                let block = match height {
+                    Some(height) => store.load_block(height)?,
+                    None => None,
+                };
+                let block = block.ok_or_else(|| RpcMethodError::new(RPC_NOT_FOUND, "No such block"))?;
+                let hash: [u8; 32] = Sha256::digest(header_hash_preimage(&block.header)).into();
+                // The hash index can still name the height of a replaced block
+                if requested.is_some_and(|requested| requested != hash) {
+                    return Err(RpcMethodError::new(RPC_NOT_FOUND, "No such block").into());
+                }
+
+                let response = block_json(&block, &hash, verbosity);
+                cache.insert(CacheKey::Block(hash, verbosity), block.header.height, response.clone());
+                Ok(response)
+            })
+        });
+    }
+
+    /// Register forge word search handlers (requires the search index)
+    pub fn register_search_handlers(&mut self, store: Arc<ChainStore>) {
+        // searchforges - Forges whose prophecy contains every query word,
+        // newest first; `word*` matches a prefix
+        self.register_handler("searchforges", move |params| {
+            let store = Arc::clone(&store);
+            Box::pin(async move {
+                let params = params.unwrap_or(Value::Null);
+                let query = params
+                    .get("query")
+                    .and_then(|q| q.as_str())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected {\"query\": \"words\"}"))?;
+                let terms = parse_query(query).map_err(|e| RpcMethodError::new(RPC_INVALID_PARAMETER, e.to_string()))?;
+                let request = PageRequest::with_limits(&params, DEFAULT_SEARCH_LIMIT, MAX_SEARCH_LIMIT)?;
+
+                if !store.search_index_enabled()? {
+                    return Err(RpcMethodError::new(
+                        RPC_INDEX_DISABLED,
+                        "Search index is disabled; enable searchindex in the chain config",
+                    )
+                    .into());
+                }
+
+                let page = tokio::task::spawn_blocking(move || -> Result<_> {
+                    let hits = store.search_forges(&terms)?;
+                    let total = hits.len();
+                    let keyed = hits.into_iter().map(|(height, proof_hash)| {
+                        ([height.to_be_bytes().as_slice(), &proof_hash].concat(), (height, proof_hash))
+                    });
+                    let page = request.page(keyed, KeyOrder::Descending, total);
+                    let mut results = Vec::with_capacity(page.results.len());
+                    for &(height, proof_hash) in &page.results {
+                        let Some(block) = store.load_block(height)? else {
+                            continue;
+                        };
+                        if let Some(forge) = block.forges.iter().find(|forge| forge.proof_hash == proof_hash) {
+                            results.push(json!({
+                                "proof_hash": hex::encode(forge.proof_hash),
+                                "prophecy": forge.prophecy,
+                                "taproot_address": forge.taproot_address,
+                                "timestamp": forge.timestamp,
+                                "height": height,
+                            }));
+                        }
+                    }
+                    Ok(page.with_results(results))
+                })
+                .await??;
+
+                Ok(page.to_json())
+            })
+        });
+    }
+
+    /// Register prophecy registry handlers backed by the chain store
+    pub fn register_prophecy_handlers(&mut self, store: Arc<ChainStore>) {
+        // getprophecyowner - Who forged a prophecy, by words or registry hash
+        self.register_handler("getprophecyowner", move |params| {
+            let store = Arc::clone(&store);
+            Box::pin(async move {
+                let query = params
+                    .as_ref()
+                    .and_then(|p| p.as_str())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected prophecy words or hash"))?;
+                let prophecy_hash = match hex::decode(query) {
+                    Ok(bytes) if bytes.len() == 32 => bytes.try_into().unwrap(),
+                    _ => prophecy_registry_hash(query),
+                };
+
+                let owner = store
+                    .get_prophecy_owner(&prophecy_hash)?
+                    .ok_or_else(|| RpcMethodError::new(RPC_NOT_FOUND, "Prophecy has not been forged"))?;
+                Ok(json!({
+                    "prophecy_hash": hex::encode(prophecy_hash),
+                    "owner": owner.owner,
+                    "height": owner.height,
+                    "proof_hash": hex::encode(owner.proof_hash),
+                }))
+            })
+        });
+    }
+
+    /// Register watchtower handlers backed by the chain store
+    pub fn register_watchtower_handlers(&mut self, store: Arc<ChainStore>) {
+        // listevidence - Recorded evidence of rule violations
+        self.register_handler("listevidence", move |_params| {
+            let store = Arc::clone(&store);
+            Box::pin(async move {
+                let evidence = store.list_evidence()?;
+                let mut entries = Vec::with_capacity(evidence.len());
+                for item in &evidence {
+                    let mut entry = item.to_json();
+                    entry["id"] = json!(hex::encode(item.id()?));
+                    entries.push(entry);
+                }
+                Ok(json!(entries))
+            })
+        });
+    }
+
+    /// Register handlers managing the outputs watched for spends
+    pub fn register_spend_watch_handlers(&mut self, watch: Arc<SpendWatch>) {
+        let watch_spends = Arc::clone(&watch);
+
+        // watchoutput - Raise a critical alert when an output
+        // ({"txid", "vout", "label"}) is spent in the mempool or a block
+        self.register_handler("watchoutput", move |params| {
+            let watch = Arc::clone(&watch_spends);
+            Box::pin(async move {
+                let params = params.unwrap_or(Value::Null);
+                let outpoint = outpoint_param(&params)?;
+                let label = params.get("label").and_then(|l| l.as_str()).unwrap_or_default();
+                let added = watch.watch(outpoint, label)?;
+                Ok(json!({ "added": added }))
+            })
+        });
+
+        let unwatch_spends = Arc::clone(&watch);
+
+        // unwatchoutput - Stop alerting on an output ({"txid", "vout"})
+        self.register_handler("unwatchoutput", move |params| {
+            let watch = Arc::clone(&unwatch_spends);
+            Box::pin(async move {
+                let outpoint = outpoint_param(&params.unwrap_or(Value::Null))?;
+                Ok(json!({ "removed": watch.unwatch(&outpoint)? }))
+            })
+        });
+
+        // listwatchedoutputs - Outputs that raise an alert when spent
+        self.register_handler("listwatchedoutputs", move |_params| {
+            let watch = Arc::clone(&watch);
+            Box::pin(async move {
+                let watched = watch.list()?;
+                Ok(json!(watched.iter().map(WatchedOutput::to_json).collect::<Vec<_>>()))
+            })
+        });
+    }
+
+    /// Register webhook queue handlers backed by the chain store
+    pub fn register_notification_handlers(&mut self, store: Arc<ChainStore>) {
+        // listpendingnotifications - Webhook deliveries awaiting a retry
+        self.register_handler("listpendingnotifications", move |_params| {
+            let store = Arc::clone(&store);
+            Box::pin(async move {
+                let pending = store.list_notifications()?;
+                Ok(json!(pending.iter().map(|n| n.to_json()).collect::<Vec<_>>()))
+            })
+        });
+    }
+
+    /// Register health handlers backed by the task supervisor
+    pub fn register_supervisor_handlers(&mut self, supervisor: Supervisor) {
+        // getnodehealth - Whether critical tasks are alive, and per-task status
+        self.register_handler("getnodehealth", move |_params| {
+            let supervisor = supervisor.clone();
+            Box::pin(async move {
+                Ok(json!({
+                    "healthy": supervisor.is_healthy(),
+                    "tasks": supervisor.tasks(),
+                }))
+            })
+        });
+    }
+
+    /// Register network handlers backed by the bandwidth tracker and the
+    /// per-peer known inventory
+    pub fn register_network_handlers(&mut self, bandwidth: Arc<BandwidthTracker>, inventory: Arc<PeerInventory>) {
+        // getnettotals - Bytes sent and received, by message type and peer,
+        // and relays skipped because every peer had the item
+        self.register_handler("getnettotals", move |_params| {
+            let bandwidth = Arc::clone(&bandwidth);
+            let inventory = Arc::clone(&inventory);
+            Box::pin(async move {
+                let totals = bandwidth.totals();
+                let mut peers: Vec<Value> = totals
+                    .peers
+                    .iter()
+                    .map(|(peer, traffic)| {
+                        json!({
+                            "peer": peer.to_string(),
+                            "bytesrecv": traffic.totals.bytes_recv,
+                            "bytessent": traffic.totals.bytes_sent,
+                            "throttled": traffic.throttled,
+                        })
+                    })
+                    .collect();
+                peers.sort_by(|a, b| a["peer"].as_str().cmp(&b["peer"].as_str()));
+
+                Ok(json!({
+                    "totalbytesrecv": totals.totals.bytes_recv,
+                    "totalbytessent": totals.totals.bytes_sent,
+                    "timemillis": totals.time_millis,
+                    "bytes_by_message": totals.by_message,
+                    "peers": peers,
+                    "peer_upload_limit": totals.peer_upload_limit,
+                    "inventory": inventory.stats(),
+                }))
+            })
+        });
+    }
+
+    /// Register handlers describing the roles of connected peers
+    pub fn register_peer_role_handlers(&mut self, services: Arc<PeerServices>) {
+        // getpeerroles - Connected peers by role and advertised service
+        self.register_handler("getpeerroles", move |_params| {
+            let services = Arc::clone(&services);
+            Box::pin(async move {
+                let distribution = services.distribution();
+                let peers_with = |flags: ServiceFlags| -> Vec<String> {
+                    services.peers_with(flags).iter().map(|peer| peer.to_string()).collect()
+                };
+                Ok(json!({
+                    "total": distribution.total,
+                    "roles": distribution.roles,
+                    "services": distribution.services,
+                    "archival_peers": peers_with(ServiceFlags::ARCHIVAL),
+                    "filter_peers": peers_with(ServiceFlags::FILTERS),
+                }))
+            })
+        });
+    }
+
+    /// Register initial block download status handlers
+    pub fn register_sync_handlers(
+        &mut self,
+        queue: Arc<std::sync::Mutex<BodyFetchQueue>>,
+        reconnects: Arc<ReconnectSchedule>,
+    ) {
+        // getsyncstatus - Body download progress, peer stalls and pending redials
+        self.register_handler("getsyncstatus", move |_params| {
+            let queue = Arc::clone(&queue);
+            let reconnects = Arc::clone(&reconnects);
+            Box::pin(async move {
+                let status = queue.lock().unwrap().status();
+                Ok(json!({
+                    "best_announced_height": status.best_announced_height,
+                    "pending": status.pending,
+                    "in_flight": status.in_flight,
+                    "timeouts": status.timeouts,
+                    "peers": status.peers,
+                    "reconnecting": reconnects.status(std::time::Instant::now()),
+                }))
+            })
+        });
+    }
+
+    /// Register Bitcoin Core-compatible chain info backed by the consensus
+    /// engine and the body download queue. Replaces the engine-less
+    /// `getinfo` with one that also reports the chain and difficulty.
+    pub fn register_blockchain_info_handlers(
+        &mut self,
+        engine: Arc<ConsensusEngine>,
+        chain: String,
+        queue: Arc<std::sync::Mutex<BodyFetchQueue>>,
+    ) {
+        let state = Arc::clone(&self.state);
+        let info_engine = Arc::clone(&engine);
+        let info_chain = chain.clone();
+
+        // getinfo - Get general blockchain info
+        self.register_handler("getinfo", move |_params| {
+            let state = Arc::clone(&state);
+            let engine = Arc::clone(&info_engine);
+            let chain = info_chain.clone();
+            Box::pin(async move {
+                let state = state.read().await;
+                Ok(json!({
+                    "version": state.version,
+                    "blocks": engine.get_height(),
+                    "forges": state.total_forges,
+                    "connections": state.peer_count,
+                    "network": chain,
+                    "difficulty": engine.get_difficulty(),
+                }))
+            })
+        });
+
+        let difficulty_engine = Arc::clone(&engine);
+
+        // getdifficulty - Get current mining difficulty
+        self.register_handler("getdifficulty", move |_params| {
+            let engine = Arc::clone(&difficulty_engine);
+            Box::pin(async move { Ok(json!(engine.get_difficulty())) })
+        });
+
+        // getblockchaininfo - Chain name, tip and sync progress
+        self.register_handler("getblockchaininfo", move |_params| {
+            let engine = Arc::clone(&engine);
+            let chain = chain.clone();
+            let queue = Arc::clone(&queue);
+            Box::pin(async move {
+                let blocks = engine.get_height();
+                let headers = blocks.max(queue.lock().unwrap().status().best_announced_height);
+                Ok(json!({
+                    "chain": chain,
+                    "blocks": blocks,
+                    "headers": headers,
+                    "bestblockhash": hex::encode(engine.get_tip_hash()),
+                    "difficulty": engine.get_difficulty(),
+                    "chainwork": hex::encode(engine.get_chainwork().to_be_bytes()),
+                    "verificationprogress": sync_progress(blocks, headers),
+                    "initialblockdownload": blocks < headers,
+                }))
+            })
+        });
+    }
+
+    /// Register the `DEBUG_METHODS`, exposing consensus and sync internals
+    /// for troubleshooting a running node
+    pub fn register_debug_handlers(
+        &mut self,
+        engine: Arc<ConsensusEngine>,
+        store: Arc<ChainStore>,
+        sync: Arc<std::sync::Mutex<ChainSync>>,
+        queue: Arc<ValidationQueue>,
+    ) {
+        // debug_getchaintips - Tips of every indexed branch, highest first,
+        // with the blocks each has past its fork from the active chain
+        self.register_handler("debug_getchaintips", move |_params| {
+            let store = Arc::clone(&store);
+            Box::pin(async move {
+                let best = store.get_best_block()?;
+                let mut tips = store.header_tips()?;
+                tips.sort_by_key(|tip| (std::cmp::Reverse(tip.height), tip.hash));
+                let tips = tips
+                    .iter()
+                    .map(|tip| {
+                        let (status, branchlen) = match best {
+                            Some(best) if best == tip.hash => ("active", 0),
+                            Some(best) => ("headers-only", store.find_fork(&best, &tip.hash)?.connect),
+                            None => ("headers-only", tip.height + 1),
+                        };
+                        Ok(json!({
+                            "height": tip.height,
+                            "hash": hex::encode(tip.hash),
+                            "chainwork": hex::encode(tip.chainwork),
+                            "branchlen": branchlen,
+                            "status": status,
+                        }))
+                    })
+                    .collect::<Result<Vec<Value>>>()?;
+                Ok(json!(tips))
+            })
+        });
+
+        // debug_getusedprophecycount - Size of the replay-protection set
+        // and the prophecy registry
+        self.register_handler("debug_getusedprophecycount", move |_params| {
+            let engine = Arc::clone(&engine);
+            Box::pin(async move {
+                Ok(json!({
+                    "height": engine.get_height(),
+                    "used_proofs": engine.used_proof_count(),
+                    "prophecy_owners": engine.prophecy_owner_count(),
+                    "used_proofs_hash": hex::encode(engine.used_proofs_hash()),
+                }))
+            })
+        });
+
+        // debug_dumporphans - Headers held for an unknown parent and
+        // downloaded blocks waiting for the tip to reach them
+        self.register_handler("debug_dumporphans", move |_params| {
+            let sync = Arc::clone(&sync);
+            Box::pin(async move {
+                let orphans: Vec<Value> = sync
+                    .lock()
+                    .unwrap()
+                    .orphans()
+                    .iter()
+                    .map(|orphan| {
+                        json!({
+                            "hash": hex::encode(orphan.hash),
+                            "height": orphan.height,
+                            "prev_block_hash": hex::encode(orphan.prev_block_hash),
+                            "peer": orphan.peer.to_string(),
+                            "downloaded": orphan.downloaded,
+                        })
+                    })
+                    .collect();
+                Ok(json!(orphans))
+            })
+        });
+
+        // debug_getvalidationqueue - Queued forges in the order workers
+        // will take them
+        self.register_handler("debug_getvalidationqueue", move |_params| {
+            let queue = Arc::clone(&queue);
+            Box::pin(async move {
+                let queued: Vec<Value> = queue
+                    .queued()
+                    .iter()
+                    .map(|(origin, proof_hash)| json!({ "origin": origin, "proof_hash": hex::encode(proof_hash) }))
+                    .collect();
+                Ok(json!({ "workers": queue.workers(), "queued": queued }))
+            })
+        });
+    }
+
+    /// Register Bitcoin Core-compatible network info backed by the
+    /// connection counters
+    pub fn register_network_info_handlers(&mut self, connections: Arc<ConnectionStats>) {
+        // getnetworkinfo - Version, connections by direction and listen addresses
+        self.register_handler("getnetworkinfo", move |_params| {
+            let connections = Arc::clone(&connections);
+            Box::pin(async move {
+                let (inbound, outbound) = (connections.inbound(), connections.outbound());
+                Ok(json!({
+                    "version": node_version(),
+                    "subversion": subversion(),
+                    "connections": inbound + outbound,
+                    "connections_in": inbound,
+                    "connections_out": outbound,
+                    "localaddresses": connections.local_addresses(),
+                }))
+            })
+        });
+    }
+
+    /// Register `reloadconfig`, which needs the node to have been started
+    /// with a config file
+    pub fn register_config_handlers(&mut self, reloader: Option<Arc<ConfigReloader>>) {
+        // reloadconfig - Re-read the config file and apply settings that
+        // can change without a restart
+        self.register_handler("reloadconfig", move |_params| {
+            let reloader = reloader.clone();
+            Box::pin(async move {
+                let reloader = reloader
+                    .ok_or_else(|| RpcMethodError::new(RPC_MISC_ERROR, "Node was started without a config file"))?;
+                let report = reloader
+                    .reload()
+                    .await
+                    .map_err(|e| RpcMethodError::new(RPC_MISC_ERROR, format!("Nothing changed: {:#}", e)))?;
+                tracing::info!("Reloaded {}: {}", reloader.path().display(), report);
+                Ok(serde_json::to_value(report)?)
+            })
+        });
+    }
+
+    /// Register handlers managing connection allow and deny rules
+    pub fn register_peer_filter_handlers(&mut self, filter: Arc<PeerFilter>) {
+        fn rule_params(params: Option<Value>) -> Result<(RuleList, PeerRule)> {
+            let params = params.unwrap_or(Value::Null);
+            let list = params
+                .get(0)
+                .and_then(|p| p.as_str())
+                .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected \"allow\" or \"deny\""))?
+                .parse()
+                .map_err(|e: String| RpcMethodError::new(RPC_INVALID_PARAMETER, e))?;
+            let rule = params
+                .get(1)
+                .and_then(|p| p.as_str())
+                .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected an IP, CIDR range or peer ID"))?
+                .parse()
+                .map_err(|e: String| RpcMethodError::new(RPC_INVALID_PARAMETER, e))?;
+            Ok((list, rule))
+        }
+
+        // addpeerrule - Allow or deny an IP, CIDR range or peer ID; open
+        // connections the new rule refuses are closed
+        let add_filter = Arc::clone(&filter);
+        self.register_handler("addpeerrule", move |params| {
+            let filter = Arc::clone(&add_filter);
+            Box::pin(async move {
+                let (list, rule) = rule_params(params)?;
+                Ok(json!(filter.add(list, rule)))
+            })
+        });
+
+        // removepeerrule - Drop an allow or deny rule
+        let remove_filter = Arc::clone(&filter);
+        self.register_handler("removepeerrule", move |params| {
+            let filter = Arc::clone(&remove_filter);
+            Box::pin(async move {
+                let (list, rule) = rule_params(params)?;
+                Ok(json!(filter.remove(list, &rule)))
+            })
+        });
+
+        // listpeerrules - Current rules and connections refused so far
+        self.register_handler("listpeerrules", move |_params| {
+            let filter = Arc::clone(&filter);
+            Box::pin(async move { Ok(serde_json::to_value(filter.rules())?) })
+        });
+    }
+
+    /// Serve `/ws` subscriptions to `newblock` and `peer` events from
+    /// `events`, and to `newforge` once a mempool is registered
+    pub fn enable_subscriptions(&mut self, events: EventBus) {
+        self.events = Some(events);
+    }
+
+    /// Require `auth` credentials on HTTP requests to `/rpc` and the
+    /// WebSocket endpoints
+    pub fn enable_auth(&mut self, auth: RpcAuth) {
+        self.auth = Some(Arc::new(auth));
+    }
+
+    /// Restrict HTTP callers per method. Without a policy every method
+    /// needs credentials.
+    pub fn set_policy(&mut self, policy: RpcPolicy) {
+        self.policy = Arc::new(policy);
+    }
+
+    /// Whether `caller` may use `method`
+    pub fn permits(&self, method: &str, caller: RpcCaller) -> bool {
+        self.policy.permits(method, caller)
+    }
+
+    /// Serve `GET /export/blocks` over HTTP
+    pub fn enable_block_export(&mut self, export: BlockExport) {
+        self.export = Some(export);
+    }
+
+    /// Register mempool handlers
+    pub fn register_mempool_handlers(&mut self, pool: Arc<ForgePool>) {
+        self.mempool = Some(Arc::clone(&pool));
+
+        let raw_pool = Arc::clone(&pool);
+
+        // getrawmempool - Proof hashes of mempool forges, in hash order
+        self.register_handler("getrawmempool", move |params| {
+            let pool = Arc::clone(&raw_pool);
+            Box::pin(async move {
+                let request = PageRequest::from_params(&params.unwrap_or(Value::Null))?;
+                let mut hashes = pool.get_all_hashes();
+                hashes.sort_unstable();
+                let total = hashes.len();
+                let page = request.page(
+                    hashes.into_iter().map(|hash| (hash.to_vec(), hex::encode(hash))),
+                    KeyOrder::Ascending,
+                    total,
+                );
+                Ok(page.to_json())
+            })
+        });
+
+        let info_pool = Arc::clone(&pool);
+
+        // getmempoolinfo - Mempool size and the minimum fee currently enforced
+        self.register_handler("getmempoolinfo", move |_params| {
+            let pool = Arc::clone(&info_pool);
+            Box::pin(async move {
+                let stats = pool.get_stats();
+                let min_fee = pool.min_fee_stats();
+                Ok(json!({
+                    "size": stats.size,
+                    "max_size": stats.max_size,
+                    "mempoolminfee": min_fee.current,
+                    "minrelayfee": min_fee.floor,
+                    "incrementalfee": min_fee.incremental_fee,
+                    "min_fee_half_life_secs": min_fee.half_life_secs,
+                    "evictions": min_fee.evictions,
+                }))
+            })
+        });
+
+        let sequence_pool = Arc::clone(&pool);
+
+        // getmempoolsequence - Mempool contents and the event sequence they match
+        self.register_handler("getmempoolsequence", move |_params| {
+            let pool = Arc::clone(&sequence_pool);
+            Box::pin(async move {
+                let (sequence, hashes) = pool.hashes_with_sequence();
+                let hashes: Vec<String> = hashes.iter().map(hex::encode).collect();
+                Ok(json!({
+                    "mempool_sequence": sequence,
+                    "forges": hashes,
+                }))
+            })
+        });
+
+        // getmempoolsnapshothash - Canonical hash of the mempool contents
+        self.register_handler("getmempoolsnapshothash", move |_params| {
+            let pool = Arc::clone(&pool);
+            Box::pin(async move {
+                let snapshot = pool.snapshot_hash();
+                Ok(json!({
+                    "size": snapshot.size,
+                    "hash": hex::encode(snapshot.hash),
+                }))
+            })
+        });
+    }
+
+    /// Register signed transfer submission, checked against the engine's
+    /// ledger before it enters the mempool
+    pub fn register_transfer_handlers(&mut self, engine: Arc<ConsensusEngine>, pool: Arc<ForgePool>) {
+        // sendrawtransfer - Admit a hex-encoded signed transfer to the
+        // mempool for the next block; returns its txid
+        self.register_handler("sendrawtransfer", move |params| {
+            let engine = Arc::clone(&engine);
+            let pool = Arc::clone(&pool);
+            Box::pin(async move {
+                let transfer = params
+                    .as_ref()
+                    .and_then(|p| p.as_str())
+                    .and_then(|p| hex::decode(p).ok())
+                    .and_then(|bytes| SignedTransfer::decode(&bytes).ok())
+                    .ok_or_else(|| {
+                        RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected a hex-encoded signed transfer")
+                    })?;
+                let txid = transfer.txid()?;
+                if pool.get_transfer(&txid).is_some() {
+                    return Err(RpcMethodError::new(RPC_VERIFY_ALREADY_IN_CHAIN, "Transfer already in mempool").into());
+                }
+                engine
+                    .check_transfer(&transfer)
+                    .and_then(|()| pool.add_transfer(transfer))
+                    .map_err(|e| RpcMethodError::new(RPC_VERIFY_REJECTED, format!("{:#}", e)))?;
+                Ok(json!({ "txid": hex::encode(txid) }))
+            })
+        });
+    }
+
+    /// Register block template handlers backed by a prewarmed template cache,
+    /// reporting on `miner` if the node mines
+    pub fn register_template_handlers(&mut self, templates: Arc<BlockTemplateCache>, miner: Option<Arc<Miner>>) {
+        let info_templates = Arc::clone(&templates);
+
+        // getblocktemplate - Candidate next block, rebuilt only if the
+        // prewarmed one is stale
+        self.register_handler("getblocktemplate", move |_params| {
+            let templates = Arc::clone(&templates);
+            Box::pin(async move {
+                let template = templates.get();
+                let forges: Vec<String> = template.forges.iter().map(|forge| hex::encode(forge.encode())).collect();
+                Ok(json!({
+                    "height": template.height,
+                    "previousblockhash": hex::encode(template.prev_block_hash),
+                    "merkleroot": hex::encode(template.merkle_root),
+                    "state_root": template.state_root.map(hex::encode),
+                    "difficulty": template.difficulty,
+                    "bits": format!("{:08x}", template.bits),
+                    "curtime": template.timestamp,
+                    "curtime_millis": template.timestamp_millis,
+                    "mempool_sequence": template.mempool_sequence,
+                    "forges": forges,
+                }))
+            })
+        });
+
+        // getmininginfo - Template staleness and rebuild statistics, and
+        // miner counters
+        self.register_handler("getmininginfo", move |_params| {
+            let templates = Arc::clone(&info_templates);
+            let miner = miner.clone();
+            Box::pin(async move {
+                Ok(json!({
+                    "mining": miner.is_some(),
+                    "miner": miner.as_ref().map(|miner| miner.stats()),
+                    "proposal": miner.and_then(|miner| miner.proposal()).map(|block| hex::encode(block.encode())),
+                    "template": templates.stats(),
+                }))
+            })
+        });
+    }
+
+    /// Register the block co-signing and submission handlers of a
+    /// permissioned chain. `blocks` takes complete blocks to the node, which
+    /// connects and announces them like mined ones.
+    pub fn register_authority_handlers(
+        &mut self,
+        engine: Arc<ConsensusEngine>,
+        key: Option<AuthorityKey>,
+        blocks: mpsc::Sender<Block>,
+    ) {
+        let sign_engine = Arc::clone(&engine);
+
+        // signblock - Add this authority's signature to a hex-encoded block
+        // proposed on the current tip
+        self.register_handler("signblock", move |params| {
+            let engine = Arc::clone(&sign_engine);
+            let key = key.clone();
+            Box::pin(async move {
+                let key = key.ok_or_else(|| RpcMethodError::new(RPC_MISC_ERROR, "No authority key configured"))?;
+                let authorities = engine
+                    .authorities()
+                    .ok_or_else(|| RpcMethodError::new(RPC_MISC_ERROR, "Not a permissioned chain"))?;
+                let mut block = block_param(params)?;
+                // Only the header and forge commitment; the forges are
+                // validated when the signed block is connected
+                engine
+                    .validate_header(&block.header, &engine.get_tip_hash(), engine.next_height())
+                    .and_then(|()| match engine.compute_block_merkle_root(&block) == block.header.merkle_root {
+                        true => Ok(()),
+                        false => Err(anyhow!("Merkle root mismatch")),
+                    })
+                    .map_err(|e| RpcMethodError::new(RPC_VERIFY_REJECTED, e.to_string()))?;
+                if !authorities.applies_at(block.header.height) {
+                    return Err(RpcMethodError::new(RPC_VERIFY_REJECTED, "Blocks at this height are unsigned").into());
+                }
+                let hash = engine.compute_block_hash(&block.header);
+                key.sign(&mut block, &hash);
+                Ok(json!({
+                    "hex": hex::encode(block.encode()),
+                    "signatures": block.authority_signatures.len(),
+                    "complete": block.authority_signatures.len() >= authorities.threshold(),
+                }))
+            })
+        });
+
+        // submitblock - Connect and announce a complete hex-encoded block
+        self.register_handler("submitblock", move |params| {
+            let engine = Arc::clone(&engine);
+            let blocks = blocks.clone();
+            Box::pin(async move {
+                let block = block_param(params)?;
+                engine
+                    .prevalidate_block(&block, &engine.get_tip_hash())
+                    .map_err(|e| RpcMethodError::new(RPC_VERIFY_REJECTED, e.to_string()))?;
+                let hash = engine.compute_block_hash(&block.header);
+                blocks.send(block).await.map_err(|_| anyhow!("Node is shutting down"))?;
+                Ok(json!({ "submitted": hex::encode(hash) }))
+            })
+        });
+    }
+
+    /// Register reorg guard admin handlers
+    pub fn register_reorg_handlers(&mut self, guard: Arc<ReorgGuard>) {
+        let accept_guard = Arc::clone(&guard);
+
+        // acceptreorg - Confirm a reorg deeper than max_reorg_depth
+        self.register_handler("acceptreorg", move |params| {
+            let guard = Arc::clone(&accept_guard);
+            Box::pin(async move {
+                let tip_hash = params
+                    .as_ref()
+                    .and_then(|p| p.as_str())
+                    .and_then(|p| hex::decode(p).ok())
+                    .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected a 32-byte hex tip hash"))?;
+
+                if !guard.accept(&tip_hash) {
+                    return Err(RpcMethodError::new(RPC_NOT_FOUND, "No pending reorg to that tip").into());
+                }
+                Ok(json!({ "accepted": hex::encode(tip_hash) }))
+            })
+        });
+
+        // getpendingreorgs - Reorgs awaiting confirmation
+        self.register_handler("getpendingreorgs", move |_params| {
+            let guard = Arc::clone(&guard);
+            Box::pin(async move {
+                let pending: Vec<Value> = guard
+                    .pending()
+                    .iter()
+                    .map(|reorg| {
+                        json!({
+                            "tip_hash": hex::encode(reorg.tip_hash),
+                            "fork_hash": hex::encode(reorg.fork.hash),
+                            "fork_height": reorg.fork.height,
+                            "depth": reorg.fork.disconnect,
+                            "detected_at": reorg.detected_at,
+                        })
+                    })
+                    .collect();
+                Ok(json!({
+                    "max_reorg_depth": guard.max_depth(),
+                    "pending": pending,
+                }))
+            })
+        });
+    }
+
+    /// Register wallet address book and history handlers
+    pub fn register_wallet_handlers(&mut self, wallet: Arc<RwLock<Wallet>>) {
+        let label_wallet = Arc::clone(&wallet);
+
+        // setlabel - Label a wallet or watched address
+        self.register_handler("setlabel", move |params| {
+            let wallet = Arc::clone(&label_wallet);
+            Box::pin(async move {
+                let params = params.unwrap_or(Value::Null);
+                let address = params
+                    .get(0)
+                    .and_then(|p| p.as_str())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Missing or invalid 'address' parameter"))?;
+                let label = params.get(1).and_then(|p| p.as_str()).unwrap_or("");
+
+                wallet
+                    .write()
+                    .await
+                    .set_label(address, label)
+                    .map_err(|e| RpcMethodError::new(RPC_INVALID_PARAMETER, e.to_string()))?;
+                Ok(Value::Null)
+            })
+        });
+
+        let by_label_wallet = Arc::clone(&wallet);
+
+        // getaddressesbylabel - Address book entries with a label
+        self.register_handler("getaddressesbylabel", move |params| {
+            let wallet = Arc::clone(&by_label_wallet);
+            Box::pin(async move {
+                let label = params
+                    .as_ref()
+                    .and_then(|p| p.as_str())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Missing or invalid 'label' parameter"))?;
+
+                let wallet = wallet.read().await;
+                let entries = wallet.addresses_by_label(label);
+                if entries.is_empty() {
+                    return Err(RpcMethodError::new(RPC_NOT_FOUND, format!("No addresses with label {}", label)).into());
+                }
+                let addresses: serde_json::Map<String, Value> = entries
+                    .into_iter()
+                    .map(|(address, entry)| (address.to_string(), json!({ "purpose": entry.purpose })))
+                    .collect();
+                Ok(Value::Object(addresses))
+            })
+        });
+
+        let provenance_wallet = Arc::clone(&wallet);
+
+        // exportprovenance - Signed statement that an address derives from a
+        // committed prophecy, without the words
+        self.register_handler("exportprovenance", move |params| {
+            let wallet = Arc::clone(&provenance_wallet);
+            Box::pin(async move {
+                let address = params
+                    .as_ref()
+                    .and_then(|p| p.as_str().or_else(|| p.get(0).and_then(|a| a.as_str())))
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Missing or invalid 'address' parameter"))?;
+                let wallet = wallet.read().await;
+                let statement = wallet
+                    .export_provenance(address)
+                    .map_err(|e| RpcMethodError::new(RPC_NOT_FOUND, e.to_string()))?;
+                Ok(statement.to_json())
+            })
+        });
+
+        let history_wallet = Arc::clone(&wallet);
+
+        // listtransactions - Forge history with address labels, oldest
+        // first
+        self.register_handler("listtransactions", move |params| {
+            let wallet = Arc::clone(&history_wallet);
+            Box::pin(async move {
+                let request = PageRequest::from_params(&params.unwrap_or(Value::Null))?;
+                let wallet = wallet.read().await;
+                let history = wallet.forge_history();
+                let keyed = history
+                    .iter()
+                    .enumerate()
+                    .map(|(index, record)| ((index as u64).to_be_bytes().to_vec(), record));
+                let page = request.page(keyed, KeyOrder::Ascending, history.len());
+                let results: Vec<Value> = page
+                    .results
+                    .iter()
+                    .map(|record| {
+                        json!({
+                            "proof_hash": hex::encode(record.proof_hash),
+                            "address": record.taproot_address,
+                            "label": wallet.label(&record.taproot_address).unwrap_or(""),
+                            "timestamp": record.timestamp,
+                            "height": record.height,
+                            "confirmed": record.height.is_some(),
+                        })
+                    })
+                    .collect();
+                Ok(page.with_results(results).to_json())
+            })
+        });
+
+        let backup_wallet = Arc::clone(&wallet);
+
+        // backupwallet - Write an encrypted backup of the wallet
+        self.register_handler("backupwallet", move |params| {
+            let wallet = Arc::clone(&backup_wallet);
+            Box::pin(async move {
+                let (path, passphrase) = backup_params(params)?;
+                wallet
+                    .read()
+                    .await
+                    .backup(&path, &passphrase)
+                    .map_err(|e| RpcMethodError::new(RPC_MISC_ERROR, e.to_string()))?;
+                Ok(Value::Null)
+            })
+        });
+
+        let lock_wallet = Arc::clone(&wallet);
+
+        // lockunspent - Lock (false) or unlock (true) outputs for coin
+        // selection; unlocking with no outputs unlocks all
+        self.register_handler("lockunspent", move |params| {
+            let wallet = Arc::clone(&lock_wallet);
+            Box::pin(async move {
+                let params = params.unwrap_or(Value::Null);
+                let unlock = params
+                    .get(0)
+                    .and_then(|p| p.as_bool())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Missing or invalid 'unlock' parameter"))?;
+                let outpoints = match params.get(1) {
+                    Some(Value::Array(entries)) => entries.iter().map(outpoint_param).collect::<Result<Vec<_>>>()?,
+                    None | Some(Value::Null) if unlock => {
+                        wallet
+                            .write()
+                            .await
+                            .unlock_all()
+                            .map_err(|e| RpcMethodError::new(RPC_MISC_ERROR, e.to_string()))?;
+                        return Ok(json!(true));
+                    }
+                    _ => {
+                        return Err(RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected an array of outputs").into())
+                    }
+                };
+
+                let mut wallet = wallet.write().await;
+                if unlock {
+                    wallet
+                        .unlock_unspent(&outpoints)
+                        .map_err(|e| RpcMethodError::new(RPC_INVALID_PARAMETER, e.to_string()))?;
+                } else {
+                    wallet
+                        .lock_unspent(&outpoints)
+                        .map_err(|e| RpcMethodError::new(RPC_MISC_ERROR, e.to_string()))?;
+                }
+                Ok(json!(true))
+            })
+        });
+
+        let list_locked_wallet = Arc::clone(&wallet);
+
+        // listlockunspent - Outputs excluded from coin selection
+        self.register_handler("listlockunspent", move |_params| {
+            let wallet = Arc::clone(&list_locked_wallet);
+            Box::pin(async move {
+                let wallet = wallet.read().await;
+                let locked: Vec<Value> = wallet
+                    .locked_outputs()
+                    .map(|outpoint| json!({ "txid": hex::encode(outpoint.txid), "vout": outpoint.vout }))
+                    .collect();
+                Ok(json!(locked))
+            })
+        });
+
+        let restore_wallet = Arc::clone(&wallet);
+
+        // restorewallet - Replace the wallet's contents with a backup
+        self.register_handler("restorewallet", move |params| {
+            let wallet = Arc::clone(&restore_wallet);
+            Box::pin(async move {
+                let (path, passphrase) = backup_params(params)?;
+                let summary = wallet
+                    .write()
+                    .await
+                    .restore_backup(&path, &passphrase)
+                    .map_err(|e| RpcMethodError::new(RPC_MISC_ERROR, e.to_string()))?;
+                Ok(serde_json::to_value(summary)?)
+            })
+        });
+    }
+
+    /// Register wallet coin selection handlers, which fund from the
+    /// engine's ledger and skip outputs the wallet has locked
+    pub fn register_funding_handlers(
+        &mut self,
+        wallet: Arc<RwLock<Wallet>>,
+        engine: Arc<ConsensusEngine>,
+        dust_threshold: u64,
+    ) {
+        // fundrawtransfer - Select inputs for [{"address": value, ...}, fee,
+        // change_address?] and return the unsigned transfer
+        self.register_handler("fundrawtransfer", move |params| {
+            let wallet = Arc::clone(&wallet);
+            let engine = Arc::clone(&engine);
+            Box::pin(async move {
+                let params = params.unwrap_or(Value::Null);
+                let outputs = params
+                    .get(0)
+                    .and_then(|p| p.as_object())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Missing or invalid 'outputs' parameter"))?
+                    .iter()
+                    .map(|(address, value)| {
+                        value
+                            .as_u64()
+                            .map(|value| TransferOutput {
+                                address: address.clone(),
+                                value,
+                            })
+                            .ok_or_else(|| {
+                                RpcMethodError::new(RPC_INVALID_PARAMETER, format!("Invalid amount for {}", address)).into()
+                            })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let fee = params
+                    .get(1)
+                    .and_then(|p| p.as_u64())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Missing or invalid 'fee' parameter"))?;
+                let change_address = params.get(2).and_then(|p| p.as_str());
+                let requested = outputs.len();
+
+                let wallet = wallet.read().await;
+                let transfer = engine
+                    .with_ledger(|ledger| wallet.fund_transfer(ledger, outputs, fee, change_address, dust_threshold))
+                    .map_err(|e| {
+                        let code = if e.to_string().starts_with("Insufficient funds") {
+                            RPC_WALLET_INSUFFICIENT_FUNDS
+                        } else {
+                            RPC_INVALID_PARAMETER
+                        };
+                        RpcMethodError::new(code, e.to_string())
+                    })?;
+                let changepos = if transfer.outputs.len() > requested { requested as i64 } else { -1 };
+                Ok(json!({
+                    "hex": hex::encode(transfer.encode()),
+                    "fee": transfer.fee,
+                    "changepos": changepos,
+                }))
+            })
+        });
+    }
+
+    /// Register time-locked vault handlers
+    pub fn register_vault_handlers(
+        &mut self,
+        wallet: Arc<RwLock<Wallet>>,
+        engine: Arc<ConsensusEngine>,
+        pool: Arc<ForgePool>,
+    ) {
+        // createvault - Create a vault address for [hot_key, recovery_key,
+        // recovery_address, delay?]
+        let vault_wallet = Arc::clone(&wallet);
+        self.register_handler("createvault", move |params| {
+            let wallet = Arc::clone(&vault_wallet);
+            Box::pin(async move {
+                let params = params.unwrap_or(Value::Null);
+                let arg = |index: usize, name: &str| {
+                    params
+                        .get(index)
+                        .and_then(|p| p.as_str())
+                        .map(str::to_string)
+                        .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, format!("Missing or invalid '{}' parameter", name)))
+                };
+                let hot_key = arg(0, "hot_key")?;
+                let recovery_key = arg(1, "recovery_key")?;
+                let recovery_address = arg(2, "recovery_address")?;
+                let delay = match params.get(3) {
+                    None | Some(Value::Null) => DEFAULT_VAULT_DELAY,
+                    Some(delay) => delay
+                        .as_u64()
+                        .and_then(|delay| u16::try_from(delay).ok())
+                        .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Invalid 'delay' parameter"))?,
+                };
+
+                let address = wallet
+                    .write()
+                    .await
+                    .create_vault(&hot_key, &recovery_key, &recovery_address, delay)
+                    .map_err(|e| RpcMethodError::new(RPC_INVALID_PARAMETER, e.to_string()))?;
+                Ok(json!({ "address": address, "delay": delay }))
+            })
+        });
+
+        // unvault - Start withdrawing a vault to a destination ([vault,
+        // destination, fee, signatures?]); returns the delay-locked unsigned
+        // transfer, and submits it once signed through the delayed leaf
+        let unvault_wallet = Arc::clone(&wallet);
+        let unvault_engine = Arc::clone(&engine);
+        let unvault_pool = Arc::clone(&pool);
+        self.register_handler("unvault", move |params| {
+            let wallet = Arc::clone(&unvault_wallet);
+            let engine = Arc::clone(&unvault_engine);
+            let pool = Arc::clone(&unvault_pool);
+            Box::pin(async move {
+                let params = params.unwrap_or(Value::Null);
+                let vault = params
+                    .get(0)
+                    .and_then(|p| p.as_str())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Missing or invalid 'vault' parameter"))?;
+                let destination = params
+                    .get(1)
+                    .and_then(|p| p.as_str())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Missing or invalid 'destination' parameter"))?;
+                let fee = params
+                    .get(2)
+                    .and_then(|p| p.as_u64())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Missing or invalid 'fee' parameter"))?;
+
+                let mut wallet = wallet.write().await;
+                let transfer = engine
+                    .with_ledger(|ledger| wallet.unvault(ledger, vault, destination, fee))
+                    .map_err(|e| RpcMethodError::new(RPC_INVALID_PARAMETER, e.to_string()))?;
+                vault_spend(&engine, &pool, &wallet, vault, transfer, false, params.get(3))
+            })
+        });
+
+        // recovervault - Sweep a vault to its recovery address ([vault, fee,
+        // signatures?]); returns the unsigned transfer, and submits it once
+        // signed through the recovery leaf
+        let recover_wallet = Arc::clone(&wallet);
+        let recover_engine = Arc::clone(&engine);
+        self.register_handler("recovervault", move |params| {
+            let wallet = Arc::clone(&recover_wallet);
+            let engine = Arc::clone(&recover_engine);
+            let pool = Arc::clone(&pool);
+            Box::pin(async move {
+                let params = params.unwrap_or(Value::Null);
+                let vault = params
+                    .get(0)
+                    .and_then(|p| p.as_str())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Missing or invalid 'vault' parameter"))?;
+                let fee = params
+                    .get(1)
+                    .and_then(|p| p.as_u64())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Missing or invalid 'fee' parameter"))?;
+
+                let mut wallet = wallet.write().await;
+                let transfer = engine
+                    .with_ledger(|ledger| wallet.recover_vault(ledger, vault, fee))
+                    .map_err(|e| RpcMethodError::new(RPC_INVALID_PARAMETER, e.to_string()))?;
+                vault_spend(&engine, &pool, &wallet, vault, transfer, true, params.get(2))
+            })
+        });
+
+        // checkvaults - Report vault outputs spent without an authorized,
+        // matured unvault
+        self.register_handler("checkvaults", move |_params| {
+            let wallet = Arc::clone(&wallet);
+            let engine = Arc::clone(&engine);
+            Box::pin(async move {
+                let mut wallet = wallet.write().await;
+                let height = engine.get_height();
+                let alerts = engine.with_ledger(|ledger| wallet.check_vaults(ledger, height))?;
+                let vaults: Vec<Value> = wallet
+                    .vaults()
+                    .iter()
+                    .map(|vault| {
+                        let (status, spendable_at) = match &vault.state {
+                            VaultState::Active => ("active", None),
+                            VaultState::Unvaulting { spendable_at, .. } => ("unvaulting", Some(*spendable_at)),
+                        };
+                        json!({
+                            "address": vault.address,
+                            "recovery_address": vault.recovery_address,
+                            "delay": vault.delay,
+                            "status": status,
+                            "spendable_at": spendable_at,
+                            "outputs": vault.known_outputs.len(),
+                        })
+                    })
+                    .collect();
+                let alerts: Vec<Value> = alerts
+                    .iter()
+                    .map(|alert| {
+                        json!({
+                            "vault": alert.vault,
+                            "txid": hex::encode(alert.outpoint.txid),
+                            "vout": alert.outpoint.vout,
+                            "reason": alert.reason,
+                        })
+                    })
+                    .collect();
+                Ok(json!({ "height": height, "vaults": vaults, "alerts": alerts }))
+            })
+        });
+    }
+
+    /// Register MuSig2 multisig address handlers for addresses on `network`
+    pub fn register_multisig_handlers(&mut self, network: bitcoin::Network) {
+        // createmultisig - Aggregate N public keys (`[keys, sort]`, sorted by
+        // default) into a key-path Taproot address and the context needed to
+        // sign for it
+        self.register_handler("createmultisig", move |params| {
+            Box::pin(async move {
+                let params = params.unwrap_or(Value::Null);
+                let keys = params
+                    .get(0)
+                    .and_then(|p| p.as_array())
+                    .filter(|keys| !keys.is_empty())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected a non-empty array of public keys"))?
+                    .iter()
+                    .map(|key| {
+                        key.as_str()
+                            .and_then(|key| hex::decode(key).ok())
+                            .ok_or_else(|| anyhow!("Public keys must be hex strings"))
+                            .and_then(|bytes| musig::parse_participant_key(&bytes))
+                            .map_err(|e| RpcMethodError::new(RPC_INVALID_PARAMETER, e.to_string()).into())
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let sort = params.get(1).and_then(|p| p.as_bool()).unwrap_or(true);
+
+                let context = KeyAggContext::new(&keys, sort)
+                    .map_err(|e| RpcMethodError::new(RPC_INVALID_PARAMETER, e.to_string()))?;
+                Ok(json!({
+                    "address": context.address(network),
+                    "keys": context.keys.iter().map(|key| hex::encode(key.serialize())).collect::<Vec<_>>(),
+                    "coefficients": context.coefficients.iter().map(hex::encode).collect::<Vec<_>>(),
+                    "aggregate_key": hex::encode(context.aggregate_key.serialize()),
+                    "internal_key": hex::encode(context.output.internal_key.serialize()),
+                    "tap_tweak": hex::encode(context.tap_tweak),
+                    "output_key": hex::encode(context.output.output_key.serialize()),
+                    "output_key_parity": u8::from(context.output_key_odd),
+                }))
+            })
+        });
+    }
+
+    /// Register a custom RPC handler
+    pub fn register_handler<F, Fut>(&mut self, method: &str, handler: F)
+    where
+        F: Fn(Option<Value>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Value>> + Send + 'static,
+    {
+        let wrapper = Arc::new(move |params: Option<Value>| {
+            Box::pin(handler(params)) as Pin<Box<dyn Future<Output = Result<Value>> + Send>>
+        });
+        self.handlers.write().unwrap().insert(method.to_string(), wrapper);
+    }
+
+    /// Handle a JSON-RPC request
+    pub async fn handle_request(&self, request: JsonRpcRequest) -> JsonRpcResponse {
+        // Validate JSON-RPC version
+        if request.jsonrpc != "2.0" {
+            return JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                result: None,
+                error: Some(JsonRpcError {
+                    code: -32600,
+                    message: "Invalid Request - jsonrpc must be '2.0'".to_string(),
+                    data: None,
+                }),
+                id: request.id,
+            };
+        }
+
+        // Get handler
+        let handler = self.handlers.read().unwrap().get(&request.method).cloned();
+        let handler = match handler {
+            Some(h) => h,
+            None => {
+                return JsonRpcResponse {
+                    jsonrpc: "2.0".to_string(),
+                    result: None,
+                    error: Some(JsonRpcError {
+                        code: -32601,
+                        message: format!("Method not found: {}", request.method),
+                        data: None,
+                    }),
+                    id: request.id,
+                };
+            }
+        };
+
+        // Execute handler
+        match handler(request.params).await {
+            Ok(result) => JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                result: Some(result),
+                error: None,
+                id: request.id,
+            },
+            Err(e) => {
+                let error = match e.downcast_ref::<RpcMethodError>() {
+                    Some(method_error) => JsonRpcError {
+                        code: method_error.code,
+                        message: method_error.message.clone(),
+                        data: None,
+                    },
+                    None => JsonRpcError {
+                        code: -32603,
+                        message: "Internal error".to_string(),
+                        data: Some(json!({ "error": e.to_string() })),
+                    },
+                };
+                JsonRpcResponse {
+                    jsonrpc: "2.0".to_string(),
+                    result: None,
+                    error: Some(error),
+                    id: request.id,
+                }
+            }
+        }
+    }
+
+    /// Handle a request from `caller`, refusing methods the policy doesn't
+    /// let it use
+    pub async fn handle_request_from(&self, request: JsonRpcRequest, caller: RpcCaller) -> JsonRpcResponse {
+        if !self.permits(&request.method, caller) {
+            return JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                result: None,
+                error: Some(JsonRpcError {
+                    code: RPC_FORBIDDEN,
+                    message: format!("Method {} is not available to this caller", request.method),
+                    data: None,
+                }),
+                id: request.id,
+            };
+        }
+        self.handle_request(request).await
+    }
+
+    /// Sign read responses over HTTP with `identity`, and serve its node
+    /// id from `getnodeidentity`
+    pub fn enable_response_signing(&mut self, identity: NodeIdentity) {
+        let node_id = hex::encode(identity.node_id());
+        self.identity = Some(Arc::new(identity));
+
+        // getnodeidentity - Id this node signs read responses with
+        self.register_handler("getnodeidentity", move |_params| {
+            let node_id = node_id.clone();
+            Box::pin(async move { Ok(json!({ "node_id": node_id })) })
+        });
+    }
+
+    /// Handle a request and encode the response, signed if it answers a
+    /// read method and response signing is enabled
+    pub async fn handle_request_signed(&self, request: JsonRpcRequest) -> (Vec<u8>, Option<ResponseSignature>) {
+        let identity = self.identity.clone().filter(|_| is_read_method(&request.method));
+        let response = self.handle_request(request).await;
+        let bytes = serialize::serialize_response(response, &self.response_metrics).await;
+        let signature = identity.map(|identity| {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            identity.sign_response(&bytes, now)
+        });
+        (bytes, signature)
+    }
+
+    /// Handle a raw JSON request string
+    pub async fn handle_request_str(&self, request_str: &str) -> String {
+        let request: JsonRpcRequest = match serde_json::from_str(request_str) {
+            Ok(r) => r,
+            Err(e) => {
+                let error_response = JsonRpcResponse {
+                    jsonrpc: "2.0".to_string(),
+                    result: None,
+                    error: Some(JsonRpcError {
+                        code: -32700,
+                        message: "Parse error".to_string(),
+                        data: Some(json!({ "error": e.to_string() })),
+                    }),
+                    id: Value::Null,
+                };
+                return serde_json::to_string(&error_response).unwrap();
+            }
+        };
+
+        let response = self.handle_request(request).await;
+        let bytes = serialize::serialize_response(response, &self.response_metrics).await;
+        String::from_utf8(bytes).unwrap()
+    }
+
+    /// Response size statistics
+    pub fn response_metrics(&self) -> &ResponseMetrics {
+        &self.response_metrics
+    }
+
+    /// Cache of block and forge responses by hash
+    pub fn response_cache(&self) -> &Arc<ResponseCache> {
+        &self.response_cache
+    }
+
+    /// Update server state
+    pub async fn update_state(&self, height: u64, forges: u64, peers: usize) {
+        let mut state = self.state.write().await;
+        state.chain_height = height;
+        state.total_forges = forges;
+        state.peer_count = peers;
+    }
+
+    /// Admit a new request, or `None` if the server is draining
+    pub fn begin_request(&self) -> Option<InFlightGuard> {
+        if self.drain.draining.load(Ordering::SeqCst) {
+            return None;
+        }
+        self.drain.in_flight.fetch_add(1, Ordering::SeqCst);
+        // Re-check so a request racing with `drain` is not admitted late
+        let guard = InFlightGuard {
+            drain: Arc::clone(&self.drain),
+        };
+        if self.drain.draining.load(Ordering::SeqCst) {
+            return None;
+        }
+        Some(guard)
+    }
+
+    /// Number of requests currently being handled
+    pub fn in_flight(&self) -> usize {
+        self.drain.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Whether the server has stopped admitting requests
+    pub fn is_draining(&self) -> bool {
+        self.drain.draining.load(Ordering::SeqCst)
+    }
+
+    /// Stop admitting requests and wait up to `grace` for in-flight ones.
+    ///
+    /// Returns `false` if requests were still running when the grace
+    /// period expired.
+    pub async fn drain(&self, grace: Duration) -> bool {
+        self.drain.draining.store(true, Ordering::SeqCst);
+
+        let wait_idle = async {
+            loop {
+                let idle = self.drain.idle.notified();
+                if self.in_flight() == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        };
+
+        match tokio::time::timeout(grace, wait_idle).await {
+            Ok(()) => true,
+            Err(_) => {
+                tracing::warn!(
+                    "RPC shutdown grace period expired with {} requests in flight",
+                    self.in_flight()
+                );
+                false
+            }
+        }
+    }
+
+    /// Run RPC server on HTTP endpoint until `shutdown` fires.
+    ///
+    /// On shutdown, new requests get `503 Service Unavailable` with a
+    /// `Retry-After` header while in-flight ones are given `grace` to
+    /// finish before the listener closes.
+    #[cfg(feature = "http-server")]
+    pub async fn run_http(&self, addr: &str, mut shutdown: ShutdownSignal, grace: Duration) -> Result<()> {
+        use warp::http::StatusCode;
+        use warp::{Filter, Reply};
+
+        // Whether a request carries accepted credentials (always, if none are required)
+        let auth = self.auth.clone();
+        let authenticated = warp::header::optional::<String>("authorization").map(move |authorization: Option<String>| {
+            auth.as_ref().is_none_or(|auth| auth.authorized(authorization.as_deref()))
+        });
+        let authorized = authenticated
+            .clone()
+            .and_then(|authenticated: bool| async move {
+                if authenticated {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(Unauthorized))
+                }
+            })
+            .untuple_one();
+        let caller = authenticated
+            .and(warp::addr::remote())
+            .map(|authenticated: bool, remote: Option<std::net::SocketAddr>| RpcCaller {
+                loopback: remote.is_some_and(|addr| addr.ip().is_loopback()),
+                authenticated,
+            });
+
+        let retry_after = grace.as_secs().max(1).to_string();
+        let rpc = self.clone();
+        let response_metrics = Arc::clone(&self.response_metrics);
+        let rpc_handler = warp::path!("rpc")
+            .and(warp::post())
+            .and(caller)
+            .and(warp::body::json())
+            .and_then(move |caller: RpcCaller, req: JsonRpcRequest| {
+                let rpc = rpc.clone();
+                let retry_after = retry_after.clone();
+                let response_metrics = Arc::clone(&response_metrics);
+                async move {
+                    let permitted = rpc.permits(&req.method, caller);
+                    // Anonymous callers of a closed method are asked for credentials
+                    if !permitted && !caller.authenticated {
+                        return Err(warp::reject::custom(Unauthorized));
+                    }
+                    let Some(_guard) = rpc.begin_request() else {
+                        let reply = warp::reply::with_status("RPC server is shutting down", StatusCode::SERVICE_UNAVAILABLE);
+                        return Ok(warp::reply::with_header(reply, "Retry-After", retry_after).into_response());
+                    };
+                    let mut reply = if permitted && rpc.identity.is_some() && is_read_method(&req.method) {
+                        // The signature covers the whole body, so it can't be streamed
+                        let (bytes, signature) = rpc.handle_request_signed(req).await;
+                        let mut reply = warp::reply::Response::new(bytes.into());
+                        for (name, value) in signature.iter().flat_map(ResponseSignature::headers) {
+                            if let Ok(value) = warp::http::HeaderValue::from_str(&value) {
+                                reply.headers_mut().insert(name, value);
+                            }
+                        }
+                        reply
+                    } else {
+                        let response = rpc.handle_request_from(req, caller).await;
+                        warp::reply::Response::new(serialize::response_body(response, response_metrics).await)
+                    };
+                    reply.headers_mut().insert(
+                        warp::http::header::CONTENT_TYPE,
+                        warp::http::HeaderValue::from_static("application/json"),
+                    );
+                    Ok(reply)
+                }
+            });
+
+        let mempool = self.mempool.clone();
+        let mempool_ws = warp::path!("ws" / "mempool")
+            .and(authorized.clone())
+            .and(warp::ws())
+            .and_then(move |ws: warp::ws::Ws| {
+                let mempool = mempool.clone();
+                async move {
+                    let Some(pool) = mempool else {
+                        return Err(warp::reject::not_found());
+                    };
+                    let events = pool.subscribe();
+                    Ok(ws.on_upgrade(move |socket| stream_mempool_events(socket, events)))
+                }
+            });
+
+        let events = self.events.clone();
+        let mempool = self.mempool.clone();
+        let subscriptions = warp::path!("ws")
+            .and(authorized)
+            .and(warp::ws())
+            .and_then(move |ws: warp::ws::Ws| {
+                let events = events.clone();
+                let mempool = mempool.clone();
+                async move {
+                    let Some(events) = events else {
+                        return Err(warp::reject::not_found());
+                    };
+                    let events = events.subscribe();
+                    let forges = mempool.map(|pool| pool.subscribe());
+                    Ok(ws.on_upgrade(move |socket| subscribe::serve_subscriptions(socket, events, forges)))
+                }
+            });
+
+        let rpc = self.clone();
+        let export_blocks = warp::path!("export" / "blocks")
+            .and(warp::get())
+            .and(warp::query::<ExportQuery>())
+            .and(warp::header::optional::<String>("authorization"))
+            .and_then(move |query: ExportQuery, authorization: Option<String>| {
+                let rpc = rpc.clone();
+                async move {
+                    let Some(export) = rpc.export.clone() else {
+                        return Err(warp::reject::not_found());
+                    };
+                    if !export.authorized(authorization.as_deref()) {
+                        let reply = warp::reply::with_status("Missing or invalid export token", StatusCode::UNAUTHORIZED);
+                        return Ok(warp::reply::with_header(reply, "WWW-Authenticate", "Bearer").into_response());
+                    }
+                    let Some(guard) = rpc.begin_request() else {
+                        return Ok(warp::reply::with_status("RPC server is shutting down", StatusCode::SERVICE_UNAVAILABLE)
+                            .into_response());
+                    };
+                    let (from, to) = match export.range(query) {
+                        Ok(range) => range,
+                        Err(e) => {
+                            return Ok(warp::reply::with_status(e.to_string(), StatusCode::BAD_REQUEST).into_response());
+                        }
+                    };
+
+                    let (tx, rx) = tokio::sync::mpsc::channel(4);
+                    let task = export.spawn_stream(from, to, tx);
+                    tokio::spawn(async move {
+                        // Shutdown waits for the export like any other request
+                        let _guard = guard;
+                        match task.await {
+                            Ok(Ok(blocks)) => tracing::debug!("Exported {} blocks ({}..={})", blocks, from, to),
+                            Ok(Err(e)) => tracing::debug!("Block export {}..={} aborted: {}", from, to, e),
+                            Err(e) => tracing::warn!("Block export task failed: {}", e),
+                        }
+                    });
+                    let stream = futures::stream::unfold(rx, |mut rx| async move {
+                        rx.recv()
+                            .await
+                            .map(|chunk| (Ok::<_, std::convert::Infallible>(chunk), rx))
+                    });
+                    let mut reply = warp::reply::Response::new(warp::hyper::Body::wrap_stream(stream));
+                    reply.headers_mut().insert(
+                        warp::http::header::CONTENT_TYPE,
+                        warp::http::HeaderValue::from_static("application/octet-stream"),
+                    );
+                    Ok(reply)
+                }
+            });
+
+        let addr: std::net::SocketAddr = addr.parse()?;
+        let rpc = self.clone();
+        let routes = rpc_handler
+            .or(mempool_ws)
+            .or(subscriptions)
+            .or(export_blocks)
+            .recover(|rejection: warp::Rejection| async move {
+                if rejection.find::<Unauthorized>().is_some() {
+                    let reply = warp::reply::with_status("Missing or invalid RPC credentials", StatusCode::UNAUTHORIZED);
+                    return Ok(warp::reply::with_header(reply, "WWW-Authenticate", "Basic realm=\"jsonrpc\""));
+                }
+                Err(rejection)
+            });
+        let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(addr, async move {
+            shutdown.recv().await;
+            tracing::info!("Draining RPC server");
+            rpc.drain(grace).await;
+        });
+        server.await;
+        tracing::info!("RPC server stopped");
+        Ok(())
+    }
+}
+
+/// Rejection for requests without accepted RPC credentials
+#[cfg(feature = "http-server")]
+#[derive(Debug)]
+struct Unauthorized;
+
+#[cfg(feature = "http-server")]
+impl warp::reject::Reject for Unauthorized {}
+
+/// Forward mempool events to a WebSocket client until either side closes.
+/// A `gap` message reports events dropped because the client fell behind.
+#[cfg(feature = "http-server")]
+async fn stream_mempool_events(
+    mut socket: warp::ws::WebSocket,
+    mut events: tokio::sync::broadcast::Receiver<crate::mempool::MempoolEvent>,
+) {
+    use futures::SinkExt;
+    use tokio::sync::broadcast::error::RecvError;
+    use warp::ws::Message;
+
+    loop {
+        let message = match events.recv().await {
+            Ok(event) => mempool_event_json(&event),
+            Err(RecvError::Lagged(missed)) => json!({ "event": "gap", "missed": missed }),
+            Err(RecvError::Closed) => break,
+        };
+        if socket.send(Message::text(message.to_string())).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(any(feature = "http-server", test))]
+fn mempool_event_json(event: &crate::mempool::MempoolEvent) -> Value {
+    let mut value = json!(event.kind);
+    value["sequence"] = json!(event.sequence);
+    value["proof_hash"] = json!(hex::encode(event.proof_hash));
+    value
+}
+
+/// Share of announced blocks validated, in `[0, 1]`
+fn sync_progress(blocks: u64, headers: u64) -> f64 {
+    if headers == 0 {
+        1.0
+    } else {
+        blocks as f64 / headers as f64
+    }
+}
+
+/// Forge given as a JSON object or in hex-encoded canonical form
+fn forge_param(params: Option<Value>) -> Result<ForgeTransaction> {
+    let forge = match params {
+        Some(Value::String(encoded)) => hex::decode(&encoded)
+            .ok()
+            .and_then(|bytes| ForgeTransaction::decode(&bytes).ok()),
+        Some(value @ Value::Object(_)) => serde_json::from_value(value).ok(),
+        _ => None,
+    };
+    forge.ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected a forge object or hex-encoded forge").into())
+}
+
+/// Vault spend for a signer: the unsigned transfer, the leaf its inputs
+/// are spent through and each input's `SighashType::ALL` sighash. Given
+/// hex `signatures`, one per input, the signed transfer is also checked and
+/// admitted to the mempool and its txid returned.
+fn vault_spend(
+    engine: &ConsensusEngine,
+    pool: &ForgePool,
+    wallet: &Wallet,
+    vault_address: &str,
+    transfer: Transfer,
+    recovery: bool,
+    signatures: Option<&Value>,
+) -> Result<Value> {
+    let vault = wallet
+        .vaults()
+        .iter()
+        .find(|vault| vault.address == vault_address)
+        .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, format!("Unknown vault {}", vault_address)))?;
+    let (script, control_block) = vault.spend_leaf(recovery)?;
+    let sighashes = (0..transfer.inputs.len())
+        .map(|index| {
+            transfer_sighash(&transfer, index, SighashType::ALL, SpendType::ScriptPath, wallet.network())
+                .map(hex::encode)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut response = json!({
+        "hex": hex::encode(transfer.encode()),
+        "fee": transfer.fee,
+        "spendable_at": transfer.lock_height,
+        "script": hex::encode(script.as_bytes()),
+        "control_block": hex::encode(control_block.serialize()),
+        "sighashes": sighashes,
+    });
+    let Some(signatures) = signatures.filter(|signatures| !signatures.is_null()) else {
+        return Ok(response);
+    };
+    let signatures = signatures
+        .as_array()
+        .and_then(|signatures| {
+            signatures
+                .iter()
+                .map(|signature| signature.as_str().and_then(|signature| hex::decode(signature).ok()))
+                .collect::<Option<Vec<_>>>()
+        })
+        .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Invalid 'signatures' parameter"))?;
+    let signed = vault
+        .with_signatures(transfer, recovery, signatures)
+        .map_err(|e| RpcMethodError::new(RPC_INVALID_PARAMETER, e.to_string()))?;
+    let txid = engine
+        .check_transfer(&signed)
+        .and_then(|()| pool.add_transfer(signed))
+        .map_err(|e| RpcMethodError::new(RPC_VERIFY_REJECTED, format!("{:#}", e)))?;
+    response["txid"] = json!(hex::encode(txid));
+    Ok(response)
+}
+
+/// Block in hex-encoded canonical form
+fn block_param(params: Option<Value>) -> Result<Block> {
+    params
+        .as_ref()
+        .and_then(|p| p.as_str())
+        .and_then(|p| hex::decode(p).ok())
+        .and_then(|bytes| Block::decode(&bytes).ok())
+        .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected a hex-encoded block").into())
+}
+
+/// `{"txid": hex, "vout": n}` output reference
+fn outpoint_param(param: &Value) -> Result<OutPoint> {
+    let txid = param
+        .get("txid")
+        .and_then(|p| p.as_str())
+        .and_then(|p| hex::decode(p).ok())
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok());
+    let vout = param
+        .get("vout")
+        .and_then(|v| v.as_u64())
+        .and_then(|v| u32::try_from(v).ok());
+    match (txid, vout) {
+        (Some(txid), Some(vout)) => Ok(OutPoint { txid, vout }),
+        _ => Err(RpcMethodError::new(RPC_INVALID_PARAMETER, "Expected {\"txid\": <32-byte hex>, \"vout\": n}").into()),
+    }
+}
+
+/// `[path, passphrase]` of a wallet backup call
+fn backup_params(params: Option<Value>) -> Result<(String, String)> {
+    let params = params.unwrap_or(Value::Null);
+    let path = params
+        .get(0)
+        .and_then(|p| p.as_str())
+        .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Missing or invalid 'path' parameter"))?;
+    let passphrase = params
+        .get(1)
+        .and_then(|p| p.as_str())
+        .filter(|p| !p.is_empty())
+        .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Missing or empty 'passphrase' parameter"))?;
+    Ok((path.to_string(), passphrase.to_string()))
+}
+
+/// Full validation, mempool admission and relay to peers; runs on the
+/// blocking pool
+/// Wait for a queued local forge's validation and relay it once admitted
+async fn finish_submission(
+    outcome: oneshot::Receiver<Result<()>>,
+    relay: Option<&mpsc::Sender<NetworkCommand>>,
+    encoded: Vec<u8>,
+) -> Result<()> {
+    outcome.await.map_err(|_| anyhow!("Validation queue stopped"))??;
+    if let Some(relay) = relay {
+        if relay.send(NetworkCommand::PublishTransaction(encoded)).await.is_err() {
+            tracing::warn!("Network is not running; admitted forge was not relayed");
+        }
+    }
+    Ok(())
+}
+
+fn ledger_info_json(info: &LedgerSetInfo) -> Value {
+    json!({
+        "height": info.height,
+        "outputs": info.output_count,
+        "total_value": info.total_value,
+        "set_hash": hex::encode(info.set_hash),
+    })
+}
+
+fn state_proof_json(height: u64, proof: &StateProof) -> Value {
+    json!({
+        "height": height,
+        "state_root": hex::encode(state_root(&proof.output_root, &proof.used_proofs_root)),
+        "output_root": hex::encode(proof.output_root),
+        "used_proofs_root": hex::encode(proof.used_proofs_root),
+        "siblings": proof.proof.siblings.iter().map(hex::encode).collect::<Vec<_>>(),
+        "leaf": proof.proof.leaf.map(|(key, value_hash)| json!({
+            "key": hex::encode(key),
+            "value_hash": hex::encode(value_hash),
+        })),
+    })
+}
+
+/// `getforge` result; `height` is None while the forge is pending
+fn forge_json(forge: &ForgeTransaction, height: Option<u64>, confirmations: u64) -> Value {
+    json!({
+        "proof_hash": hex::encode(forge.proof_hash),
+        "prophecy": forge.prophecy,
+        "taproot_address": forge.taproot_address,
+        "timestamp": forge.timestamp,
+        "in_mempool": height.is_none(),
+        "height": height,
+        "confirmations": confirmations,
+    })
+}
+
+/// `getblock` result at a verbosity level
+fn block_json(block: &Block, hash: &[u8; 32], verbosity: u8) -> Value {
+    let encoded = block.encode();
+    if verbosity == 0 {
+        return json!(hex::encode(encoded));
+    }
+    let header = &block.header;
+    let forges: Vec<Value> = block
+        .forges
+        .iter()
+        .map(|forge| match verbosity {
+            1 => json!(hex::encode(forge.proof_hash)),
+            _ => json!({
+                "proof_hash": hex::encode(forge.proof_hash),
+                "prophecy": forge.prophecy,
+                "derived_key": hex::encode(&forge.derived_key),
+                "taproot_address": forge.taproot_address,
+                "timestamp": forge.timestamp,
+                "not_before_height": forge.not_before_height,
+                "signature": hex::encode(&forge.signature),
+            }),
+        })
+        .collect();
+    json!({
+        "hash": hex::encode(hash),
+        "height": header.height,
+        "version": header.version,
+        "prev_block_hash": hex::encode(header.prev_block_hash),
+        "merkle_root": hex::encode(header.merkle_root),
+        "timestamp": header.timestamp,
+        "timestamp_millis": header.timestamp_millis,
+        "difficulty": header.difficulty,
+        "bits": format!("{:08x}", header.bits),
+        "nonce": header.nonce,
+        "aggregate_commitment": header.aggregate_commitment.map(|commitment| json!({
+            "proof_root": hex::encode(commitment.proof_root),
+            "tempered_keys_hash": hex::encode(commitment.tempered_keys_hash),
+        })),
+        "state_root": header.state_root.map(hex::encode),
+        "size": encoded.len(),
+        "forge_count": block.forges.len(),
+        "forges": forges,
+    })
+}
+
+impl Clone for RpcServer {
+    fn clone(&self) -> Self {
+        RpcServer {
+            handlers: Arc::clone(&self.handlers),
+            state: Arc::clone(&self.state),
+            drain: Arc::clone(&self.drain),
+            mempool: self.mempool.clone(),
+            events: self.events.clone(),
+            response_metrics: Arc::clone(&self.response_metrics),
+            response_cache: Arc::clone(&self.response_cache),
+            export: self.export.clone(),
+            identity: self.identity.clone(),
+            auth: self.auth.clone(),
+            policy: Arc::clone(&self.policy),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rpc_server_creation() {
+        let server = RpcServer::new();
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getblockcount".to_string(),
+            params: None,
+            id: json!(1),
+        };
+        
+        let response = server.handle_request(request).await;
+        assert_eq!(response.jsonrpc, "2.0");
+        assert!(response.result.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_policy_refuses_method() {
+        let mut server = RpcServer::new();
+        server.set_policy(RpcPolicy::default().with("getblockcount", MethodAccess::Local));
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getblockcount".to_string(),
+            params: None,
+            id: json!(1),
+        };
+        let remote = RpcCaller { loopback: false, authenticated: true };
+        let response = server.handle_request_from(request.clone(), remote).await;
+        assert_eq!(response.error.unwrap().code, RPC_FORBIDDEN);
+
+        let response = server.handle_request_from(request, RpcCaller::LOCAL).await;
+        assert!(response.error.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_getinfo() {
+        let server = RpcServer::new();
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getinfo".to_string(),
+            params: None,
+            id: json!(1),
+        };
+        
+        let response = server.handle_request(request).await;
+        assert!(response.result.is_some());
+        let result = response.result.unwrap();
+        assert!(result.get("version").is_some());
+        assert!(result.get("blocks").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_method_not_found() {
+        let server = RpcServer::new();
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "nonexistent_method".to_string(),
+            params: None,
+            id: json!(1),
+        };
+        
+        let response = server.handle_request(request).await;
+        assert!(response.error.is_some());
+        assert_eq!(response.error.unwrap().code, -32601);
+    }
+
+    #[tokio::test]
+    async fn test_register_handler_while_serving() {
+        let mut server = RpcServer::new();
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let released = Arc::new(tokio::sync::Mutex::new(Some(released)));
+        server.register_handler("wait", move |_params| {
+            let released = Arc::clone(&released);
+            async move {
+                if let Some(released) = released.lock().await.take() {
+                    let _ = released.await;
+                }
+                Ok(json!("done"))
+            }
+        });
+        let request = |method: &str| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: None,
+            id: json!(1),
+        };
+
+        // A handler still running must not hold up registration
+        let serving = server.clone();
+        let pending = tokio::spawn(async move { serving.handle_request(request("wait")).await });
+        tokio::task::yield_now().await;
+        server.register_handler("late", |_params| async { Ok(json!(7)) });
+        assert_eq!(server.handle_request(request("late")).await.result, Some(json!(7)));
+        assert_eq!(server.handle_request(request("getblockcount")).await.result, Some(json!(0)));
+
+        release.send(()).unwrap();
+        assert_eq!(pending.await.unwrap().result, Some(json!("done")));
+    }
+
+    #[tokio::test]
+    async fn test_createmultisig() {
+        let mut server = RpcServer::new();
+        server.register_multisig_handlers(bitcoin::Network::Regtest);
+        let call = |params: Value| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "createmultisig".to_string(),
+            params: Some(params),
+            id: json!(1),
+        };
+        let a = "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+        let b = "03dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659";
+
+        let forward = server.handle_request(call(json!([[a, b]]))).await.result.unwrap();
+        let reverse = server.handle_request(call(json!([[b, a]]))).await.result.unwrap();
+        assert_eq!(forward, reverse);
+        assert!(forward["address"].as_str().unwrap().starts_with("bcrt1p"));
+        assert_eq!(forward["keys"], json!([format!("02{}", a), b]));
+        assert_eq!(forward["coefficients"][1], hex::encode([&[0u8; 31][..], &[1]].concat()));
+
+        let unsorted = server.handle_request(call(json!([[b, a], false]))).await.result.unwrap();
+        assert_ne!(unsorted["address"], forward["address"]);
+
+        let response = server.handle_request(call(json!([[a, "00ff"]]))).await;
+        assert_eq!(response.error.unwrap().code, RPC_INVALID_PARAMETER);
+        let response = server.handle_request(call(json!([[]]))).await;
+        assert_eq!(response.error.unwrap().code, RPC_INVALID_PARAMETER);
+    }
+
+    #[tokio::test]
+    async fn test_vault_handlers() {
+        let mut server = RpcServer::new();
+        let wallet = Arc::new(RwLock::new(Wallet::new(bitcoin::Network::Regtest)));
+        let pool = Arc::new(ForgePool::new(100, 0));
+        server.register_vault_handlers(wallet, Arc::new(ConsensusEngine::new(0, 600)), pool);
+        let call = |method: &str, params: Value| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: Some(params),
+            id: json!(1),
+        };
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let key = |byte: u8| {
+            let secret = bitcoin::secp256k1::SecretKey::from_slice(&[byte; 32]).unwrap();
+            bitcoin::secp256k1::PublicKey::from_secret_key(&secp, &secret)
+        };
+        let x_only = |byte: u8| hex::encode(key(byte).x_only_public_key().0.serialize());
+        let recovery = crate::crypto::p2tr_address_for_key(&key(3), bitcoin::Network::Regtest);
+
+        let created = server
+            .handle_request(call("createvault", json!([x_only(1), x_only(2), recovery])))
+            .await
+            .result
+            .unwrap();
+        assert_eq!(created["delay"], json!(DEFAULT_VAULT_DELAY));
+        let vault = created["address"].as_str().unwrap();
+
+        // An empty vault has nothing to unvault
+        let response = server.handle_request(call("unvault", json!([vault, recovery, 100]))).await;
+        assert_eq!(response.error.unwrap().code, RPC_INVALID_PARAMETER);
+        let response = server.handle_request(call("createvault", json!([x_only(1), x_only(2), recovery, 70_000]))).await;
+        assert_eq!(response.error.unwrap().code, RPC_INVALID_PARAMETER);
+
+        let checked = server.handle_request(call("checkvaults", Value::Null)).await.result.unwrap();
+        assert_eq!(checked["vaults"][0]["status"], "active");
+        assert_eq!(checked["alerts"], json!([]));
+    }
+
+    #[tokio::test]
     async fn test_invalid_jsonrpc_version() {
         let server = RpcServer::new();
         let request = JsonRpcRequest {
-            jsonrpc: "1.0".to_string(),
-            method: "getinfo".to_string(),
+            jsonrpc: "1.0".to_string(),
+            method: "getinfo".to_string(),
+            params: None,
+            id: json!(1),
+        };
+        
+        let response = server.handle_request(request).await;
+        assert!(response.error.is_some());
+        assert_eq!(response.error.unwrap().code, -32600);
+    }
+
+    #[tokio::test]
+    async fn test_getledgersetinfo() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = Arc::new(ChainStore::new(tmp.path()).unwrap());
+        let engine = Arc::new(ConsensusEngine::new(2, 600));
+        store
+            .put_ledger_info(&LedgerSetInfo {
+                height: 5,
+                output_count: 3,
+                total_value: 150,
+                set_hash: [7u8; 32],
+            })
+            .unwrap();
+
+        let mut server = RpcServer::new();
+        server.register_ledger_handlers(engine, store, vec![]);
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getledgersetinfo".to_string(),
+            params: None,
+            id: json!(1),
+        };
+        let result = server.handle_request(request).await.result.unwrap();
+        assert_eq!(result["outputs"], 0);
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getledgersetinfo".to_string(),
+            params: Some(json!(5)),
+            id: json!(2),
+        };
+        let result = server.handle_request(request).await.result.unwrap();
+        assert_eq!(result["outputs"], 3);
+        assert_eq!(result["set_hash"], hex::encode([7u8; 32]));
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getstateproof".to_string(),
+            params: Some(json!({"txid": hex::encode([1u8; 32]), "vout": 0})),
+            id: json!(3),
+        };
+        let result = server.handle_request(request).await.result.unwrap();
+        assert_eq!(result["height"], 0);
+        assert_eq!(result["leaf"], Value::Null);
+        assert_eq!(result["output_root"], hex::encode([0u8; 32]));
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getstateproof".to_string(),
+            params: Some(json!({"txid": "zz"})),
+            id: json!(4),
+        };
+        assert!(server.handle_request(request).await.error.is_some());
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getsupplyinfo".to_string(),
+            params: None,
+            id: json!(5),
+        };
+        let result = server.handle_request(request).await.result.unwrap();
+        assert_eq!(result["circulating"], 0);
+        assert_eq!(result["discrepancy"], false);
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_in_flight() {
+        let server = RpcServer::new();
+        let guard = server.begin_request().unwrap();
+        assert_eq!(server.in_flight(), 1);
+
+        // Grace expires while the request is still running
+        assert!(!server.drain(Duration::from_millis(10)).await);
+        assert!(server.is_draining());
+        assert!(server.begin_request().is_none());
+
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(guard);
+        });
+        assert!(server.drain(Duration::from_secs(5)).await);
+        assert_eq!(server.in_flight(), 0);
+        release.await.unwrap();
+    }
+
+    #[test]
+    fn test_mempool_event_json() {
+        use crate::mempool::{MempoolEvent, MempoolEventKind, RemovalReason};
+
+        let event = MempoolEvent {
+            sequence: 7,
+            proof_hash: [1u8; 32],
+            kind: MempoolEventKind::Removed { reason: RemovalReason::Block },
+        };
+        let value = mempool_event_json(&event);
+        assert_eq!(value["event"], "removed");
+        assert_eq!(value["reason"], "block");
+        assert_eq!(value["sequence"], 7);
+        assert_eq!(value["proof_hash"], hex::encode([1u8; 32]));
+    }
+
+    #[tokio::test]
+    async fn test_wallet_label_handlers() {
+        let mut server = RpcServer::new();
+        let wallet = Arc::new(RwLock::new(Wallet::new(bitcoin::Network::Regtest)));
+        server.register_wallet_handlers(Arc::clone(&wallet));
+        server.register_funding_handlers(wallet, Arc::new(ConsensusEngine::new(0, 600)), 10_000);
+
+        let request = |method: &str, params: Value| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: Some(params),
+            id: json!(1),
+        };
+
+        let response = server.handle_request(request("setlabel", json!(["bogus", "cold"]))).await;
+        assert_eq!(response.error.unwrap().code, RPC_INVALID_PARAMETER);
+
+        let response = server.handle_request(request("getaddressesbylabel", json!("cold"))).await;
+        assert_eq!(response.error.unwrap().code, RPC_NOT_FOUND);
+
+        let response = server.handle_request(request("listtransactions", Value::Null)).await;
+        assert_eq!(response.result.unwrap(), json!({ "results": [], "next_cursor": null, "total_estimate": 0 }));
+
+        let response = server.handle_request(request("exportprovenance", json!(["bcrt1qunknown"]))).await;
+        assert_eq!(response.error.unwrap().code, RPC_NOT_FOUND);
+
+        let response = server.handle_request(request("backupwallet", json!(["/tmp/wallet.bak"]))).await;
+        assert_eq!(response.error.unwrap().code, RPC_INVALID_PARAMETER);
+        let response = server
+            .handle_request(request("restorewallet", json!(["/nonexistent/wallet.bak", "pass"])))
+            .await;
+        assert_eq!(response.error.unwrap().code, RPC_MISC_ERROR);
+
+        let outpoint = json!({ "txid": hex::encode([7u8; 32]), "vout": 1 });
+        let response = server.handle_request(request("lockunspent", json!([false, [outpoint.clone()]]))).await;
+        assert_eq!(response.result.unwrap(), json!(true));
+        let response = server.handle_request(request("listlockunspent", Value::Null)).await;
+        assert_eq!(response.result.unwrap(), json!([outpoint.clone()]));
+        let response = server.handle_request(request("lockunspent", json!([true, [{ "txid": "00" }]]))).await;
+        assert_eq!(response.error.unwrap().code, RPC_INVALID_PARAMETER);
+        let response = server.handle_request(request("lockunspent", json!([true]))).await;
+        assert_eq!(response.result.unwrap(), json!(true));
+        let response = server.handle_request(request("listlockunspent", Value::Null)).await;
+        assert_eq!(response.result.unwrap(), json!([]));
+
+        let response = server
+            .handle_request(request("fundrawtransfer", json!([{ "bcrt1pdest": 50_000 }, 1_000])))
+            .await;
+        assert_eq!(response.error.unwrap().code, RPC_WALLET_INSUFFICIENT_FUNDS);
+        let response = server.handle_request(request("fundrawtransfer", json!([{ "bcrt1pdest": -1 }, 1_000]))).await;
+        assert_eq!(response.error.unwrap().code, RPC_INVALID_PARAMETER);
+    }
+
+    #[tokio::test]
+    async fn test_getprophecyowner() {
+        use crate::consensus::{testing, ForgeTransaction};
+        use crate::crypto::CANONICAL_PROPHECY;
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = Arc::new(ChainStore::new(tmp.path()).unwrap());
+        let forge = ForgeTransaction {
+            prophecy: CANONICAL_PROPHECY.join(" "),
+            derived_key: vec![],
+            taproot_address: "bc1powner".to_string(),
+            proof_hash: [3u8; 32],
+            timestamp: 1000,
+            signature: vec![],
+            not_before_height: 0,
+            tempering: Default::default(),
+            salt: [0; 32],
+        };
+        let block = testing::block(7, [0u8; 32], vec![forge]);
+        ConsensusEngine::from_store(&store, 0, 600).unwrap().apply_block(&block).unwrap();
+
+        let mut server = RpcServer::new();
+        server.register_prophecy_handlers(Arc::clone(&store));
+        let request = |params: Value| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getprophecyowner".to_string(),
+            params: Some(params),
+            id: json!(1),
+        };
+
+        let response = server.handle_request(request(json!(CANONICAL_PROPHECY.join(" ")))).await;
+        let result = response.result.unwrap();
+        assert_eq!(result["owner"], "bc1powner");
+        assert_eq!(result["height"], 7);
+
+        let by_hash = server.handle_request(request(result["prophecy_hash"].clone())).await;
+        assert_eq!(by_hash.result.unwrap()["owner"], "bc1powner");
+
+        let missing = server.handle_request(request(json!("sword legend"))).await;
+        assert_eq!(missing.error.unwrap().code, RPC_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_watchoutput() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = Arc::new(ChainStore::new(tmp.path()).unwrap());
+        let mut server = RpcServer::new();
+        server.register_spend_watch_handlers(Arc::new(SpendWatch::new(store, crate::events::EventBus::new())));
+        let call = |method: &str, params: Value| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: Some(params),
+            id: json!(1),
+        };
+        let outpoint = json!({ "txid": hex::encode([3u8; 32]), "vout": 1, "label": "treasury" });
+
+        let added = server.handle_request(call("watchoutput", outpoint.clone())).await.result.unwrap();
+        assert_eq!(added["added"], true);
+        let listed = server.handle_request(call("listwatchedoutputs", Value::Null)).await.result.unwrap();
+        assert_eq!((listed[0]["vout"].clone(), listed[0]["label"].clone()), (json!(1), json!("treasury")));
+        let removed = server.handle_request(call("unwatchoutput", outpoint)).await.result.unwrap();
+        assert_eq!(removed["removed"], true);
+        let response = server.handle_request(call("watchoutput", json!({ "vout": 0 }))).await;
+        assert_eq!(response.error.unwrap().code, RPC_INVALID_PARAMETER);
+    }
+
+    #[tokio::test]
+    async fn test_getnettotals() {
+        use crate::network::MessageKind;
+
+        let bandwidth = Arc::new(BandwidthTracker::new());
+        let peer = libp2p::PeerId::random();
+        bandwidth.record_recv(&peer, MessageKind::Block, 300);
+        bandwidth.record_sent(None, MessageKind::Forge, 120);
+
+        let mut server = RpcServer::new();
+        let inventory = Arc::new(PeerInventory::default());
+        inventory.mark_known(peer, &[1; 32]);
+        server.register_network_handlers(Arc::clone(&bandwidth), inventory);
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getnettotals".to_string(),
             params: None,
             id: json!(1),
         };
-        
+        let result = server.handle_request(request).await.result.unwrap();
+        assert_eq!(result["totalbytesrecv"], 300);
+        assert_eq!(result["totalbytessent"], 120);
+        assert_eq!(result["bytes_by_message"]["block"]["bytes_recv"], 300);
+        assert_eq!(result["peers"][0]["peer"], peer.to_string());
+        assert_eq!(result["inventory"], json!({ "peers": 1, "known_items": 1, "relays_skipped": 0 }));
+    }
+
+    #[tokio::test]
+    async fn test_getblocktemplate() {
+        let engine = Arc::new(ConsensusEngine::new(0, 600));
+        let templates = Arc::new(BlockTemplateCache::new(engine, Arc::new(ForgePool::new(10, 0))));
+        let mut server = RpcServer::new();
+        server.register_template_handlers(Arc::clone(&templates), None);
+        let call = |method: &str| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: None,
+            id: json!(1),
+        };
+
+        let template = server.handle_request(call("getblocktemplate")).await.result.unwrap();
+        assert_eq!(template["height"], 0);
+        assert_eq!(template["forges"], json!([]));
+        server.handle_request(call("getblocktemplate")).await.result.unwrap();
+
+        let info = server.handle_request(call("getmininginfo")).await.result.unwrap();
+        assert_eq!(info["template"]["rebuilds"], 1, "fresh template served from the cache");
+        assert_eq!(info["template"]["tip_changed"], false);
+        assert_eq!(info["mining"], false);
+    }
+
+    #[tokio::test]
+    async fn test_signblock_and_submitblock() {
+        use crate::consensus::{testing, AuthoritySet};
+        use bitcoin::secp256k1::{Keypair, Secp256k1, SecretKey};
+
+        let secrets: Vec<SecretKey> = (1..=2).map(|seed| SecretKey::from_slice(&[seed; 32]).unwrap()).collect();
+        let keys = secrets
+            .iter()
+            .map(|secret| Keypair::from_secret_key(&Secp256k1::new(), secret).x_only_public_key().0);
+        let authorities = AuthoritySet::new(keys.collect(), 2, 0).unwrap();
+        let params = crate::params::NetworkParams::regtest().with_authorities(authorities.clone());
+        let engine = Arc::new(ConsensusEngine::new(0, 600).with_params(&params));
+        let key = |index: usize| AuthorityKey::new(&authorities, &secrets[index]).unwrap();
+        let (blocks, mut submitted) = mpsc::channel(1);
+        let mut server = RpcServer::new();
+        server.register_authority_handlers(Arc::clone(&engine), Some(key(1)), blocks);
+        let call = |method: &str, block: &Block| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: Some(json!(hex::encode(block.encode()))),
+            id: json!(1),
+        };
+
+        let forge = ForgeTransaction {
+            prophecy: "authority prophecy".to_string(),
+            derived_key: vec![],
+            taproot_address: "bcrt1p...".to_string(),
+            proof_hash: [4; 32],
+            timestamp: 1000,
+            signature: vec![],
+            not_before_height: 0,
+            tempering: Default::default(),
+            salt: [0; 32],
+        };
+        let mut block = testing::block(0, [0; 32], vec![forge]);
+        block.header.merkle_root = engine.compute_merkle_root(&block.forges);
+        assert!(engine.grind_header(&mut block.header, 1_000));
+
+        // The proposer signs, then the other authority co-signs over RPC
+        let error = server.handle_request(call("submitblock", &block)).await.error.unwrap();
+        assert_eq!(error.code, RPC_VERIFY_REJECTED);
+        let hash = engine.compute_block_hash(&block.header);
+        key(0).sign(&mut block, &hash);
+        let signed = server.handle_request(call("signblock", &block)).await.result.unwrap();
+        assert_eq!((signed["signatures"].as_u64(), signed["complete"].as_bool()), (Some(2), Some(true)));
+        let signed = Block::decode(&hex::decode(signed["hex"].as_str().unwrap()).unwrap()).unwrap();
+
+        let result = server.handle_request(call("submitblock", &signed)).await.result.unwrap();
+        assert_eq!(result["submitted"], hex::encode(engine.compute_block_hash(&signed.header)));
+        assert_eq!(submitted.recv().await.unwrap().authority_signatures, signed.authority_signatures);
+    }
+
+    #[tokio::test]
+    async fn test_debug_handlers() {
+        use crate::consensus::testing::header;
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = Arc::new(ChainStore::new(tmp.path()).unwrap());
+        let engine = Arc::new(ConsensusEngine::new(2, 600));
+        // Genesis with the active tip on one side and a two-block branch on the other
+        for (hash, height, prev) in [([1u8; 32], 0, [0u8; 32]), ([2u8; 32], 1, [1u8; 32]), ([3u8; 32], 1, [1u8; 32]), ([4u8; 32], 2, [3u8; 32])] {
+            store.index_header(&hash, &header(height, prev)).unwrap();
+        }
+        store.set_best_block(&[2u8; 32]).unwrap();
+
+        let sync = Arc::new(std::sync::Mutex::new(ChainSync::new(Default::default(), Default::default())));
+        let pool = Arc::new(ForgePool::new(100, 0));
+        let queue = Arc::new(ValidationQueue::new(Arc::clone(&engine), pool, 3, 10, 10));
+        let mut server = RpcServer::new();
+        server.register_debug_handlers(engine, store, sync, queue);
+        let call = |method: &str| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: None,
+            id: json!(1),
+        };
+
+        let tips = server.handle_request(call("debug_getchaintips")).await.result.unwrap();
+        assert_eq!(tips[0]["hash"], hex::encode([4u8; 32]));
+        assert_eq!((tips[0]["status"].clone(), tips[0]["branchlen"].clone()), (json!("headers-only"), json!(2)));
+        assert_eq!((tips[1]["status"].clone(), tips[1]["branchlen"].clone()), (json!("active"), json!(0)));
+
+        let used = server.handle_request(call("debug_getusedprophecycount")).await.result.unwrap();
+        assert_eq!(used["used_proofs"], 0);
+        assert_eq!(server.handle_request(call("debug_dumporphans")).await.result.unwrap(), json!([]));
+        let queued = server.handle_request(call("debug_getvalidationqueue")).await.result.unwrap();
+        assert_eq!(queued, json!({ "workers": 3, "queued": [] }));
+    }
+
+    #[tokio::test]
+    async fn test_submitforgeasync_reports_outcome() {
+        let mut server = RpcServer::new();
+        let pool = Arc::new(ForgePool::new(100, 0));
+        let (relay, mut relayed) = mpsc::channel(4);
+        let queue = Arc::new(ValidationQueue::new(Arc::new(ConsensusEngine::new(0, 600)), Arc::clone(&pool), 1, 1, 1));
+        let shutdown = crate::shutdown::ShutdownCoordinator::new();
+        tokio::spawn(Arc::clone(&queue).run(shutdown.subscribe()));
+        server.register_submit_handlers(queue, Arc::clone(&pool), Some(relay));
+        let call = |method: &str, params: Value| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: Some(params),
+            id: json!(1),
+        };
+        let mut forge = ForgeTransaction {
+            prophecy: "not the canonical axiom".to_string(),
+            derived_key: crate::crypto::derive_public_key(&[7u8; 32]).unwrap().serialize().to_vec(),
+            taproot_address: "bc1p...".to_string(),
+            proof_hash: [9u8; 32],
+            timestamp: 0,
+            signature: vec![],
+            not_before_height: 0,
+            tempering: Default::default(),
+            salt: [0; 32],
+        };
+        forge.sign(&[7u8; 32]).unwrap();
+
+        let submitted = server
+            .handle_request(call("submitforgeasync", serde_json::to_value(&forge).unwrap()))
+            .await
+            .result
+            .unwrap();
+        let job_id = submitted["job_id"].clone();
+        let job = loop {
+            let job = server.handle_request(call("getsubmitjob", job_id.clone())).await.result.unwrap();
+            if job["status"] != "pending" {
+                break job;
+            }
+            tokio::task::yield_now().await;
+        };
+        assert_eq!(job["status"], "rejected");
+        assert!(job["error"].as_str().unwrap().contains("Invalid prophecy"));
+
+        // The synchronous variant reports the same failure directly
+        let encoded = hex::encode(forge.encode());
+        let response = server.handle_request(call("submitforge", json!(encoded))).await;
+        assert_eq!(response.error.unwrap().code, RPC_VERIFY_REJECTED);
+        assert!(relayed.try_recv().is_err(), "rejected forges are not relayed");
+
+        pool.add_forge(forge.clone()).unwrap();
+        let response = server.handle_request(call("submitforge", json!(encoded))).await;
+        assert_eq!(response.error.unwrap().code, RPC_VERIFY_ALREADY_IN_CHAIN);
+        let response = server.handle_request(call("submitforge", json!("zz"))).await;
+        assert_eq!(response.error.unwrap().code, RPC_INVALID_PARAMETER);
+
+        let missing = server.handle_request(call("getsubmitjob", json!(999))).await;
+        assert_eq!(missing.error.unwrap().code, RPC_NOT_FOUND);
+
+        let info = server.handle_request(call("getvalidationqueueinfo", Value::Null)).await.result.unwrap();
+        assert_eq!(info["rejected"], 2);
+        assert_eq!(info["local_queued"], 0);
+        shutdown.trigger();
+    }
+
+    #[tokio::test]
+    async fn test_cancelsubmitjob_stops_validation() {
+        use crate::crypto::CANONICAL_PROPHECY;
+        let mut server = RpcServer::new();
+        let pool = Arc::new(ForgePool::new(100, 0));
+        let queue = Arc::new(ValidationQueue::new(Arc::new(ConsensusEngine::new(0, 600)), Arc::clone(&pool), 1, 1, 1));
+        server.register_submit_handlers(Arc::clone(&queue), pool, None);
+        let call = |method: &str, params: Value| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: Some(params),
+            id: json!(1),
+        };
+        let mut forge = ForgeTransaction {
+            prophecy: CANONICAL_PROPHECY.join(" "),
+            derived_key: crate::crypto::derive_public_key(&[7u8; 32]).unwrap().serialize().to_vec(),
+            taproot_address: "bc1p...".to_string(),
+            proof_hash: [9u8; 32],
+            timestamp: 0,
+            signature: vec![],
+            not_before_height: 0,
+            tempering: Default::default(),
+            salt: [3; 32],
+        };
+        forge.sign(&[7u8; 32]).unwrap();
+
+        // Cancelled while still queued, so it never gets a full derivation
+        let submitted = server
+            .handle_request(call("submitforgeasync", serde_json::to_value(&forge).unwrap()))
+            .await
+            .result
+            .unwrap();
+        let job_id = submitted["job_id"].clone();
+        let cancelled = server.handle_request(call("cancelsubmitjob", job_id.clone())).await.result.unwrap();
+        assert_eq!(cancelled["cancelling"], true);
+
+        let shutdown = crate::shutdown::ShutdownCoordinator::new();
+        tokio::spawn(Arc::clone(&queue).run(shutdown.subscribe()));
+        let job = loop {
+            let job = server.handle_request(call("getsubmitjob", job_id.clone())).await.result.unwrap();
+            if job["status"] != "pending" {
+                break job;
+            }
+            tokio::task::yield_now().await;
+        };
+        assert_eq!(job["status"], "cancelled");
+        let again = server.handle_request(call("cancelsubmitjob", job_id)).await.result.unwrap();
+        assert_eq!(again["cancelling"], false);
+        let missing = server.handle_request(call("cancelsubmitjob", json!(999))).await;
+        assert_eq!(missing.error.unwrap().code, RPC_NOT_FOUND);
+        shutdown.trigger();
+    }
+
+    #[tokio::test]
+    async fn test_getpeerroles() {
+        let services = Arc::new(PeerServices::new());
+        let archive = libp2p::PeerId::random();
+        services.set(archive, ServiceFlags::FULL | ServiceFlags::ARCHIVAL);
+        services.set(libp2p::PeerId::random(), ServiceFlags::FULL | ServiceFlags::FILTERS);
+
+        let mut server = RpcServer::new();
+        server.register_peer_role_handlers(services);
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getpeerroles".to_string(),
+            params: None,
+            id: json!(1),
+        };
+        let result = server.handle_request(request).await.result.unwrap();
+        assert_eq!(result["total"], 2);
+        assert_eq!(result["roles"]["archival"], 1);
+        assert_eq!(result["roles"]["pruned"], 1);
+        assert_eq!(result["archival_peers"], json!([archive.to_string()]));
+        assert_eq!(result["filter_peers"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_getrawforge_requires_txindex() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = Arc::new(ChainStore::new(tmp.path()).unwrap());
+
+        let mut server = RpcServer::new();
+        server.register_index_handlers(Arc::clone(&store));
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getrawforge".to_string(),
+            params: Some(json!(hex::encode([1u8; 32]))),
+            id: json!(1),
+        };
+        let response = server.handle_request(request.clone()).await;
+        assert_eq!(response.error.unwrap().code, RPC_INDEX_DISABLED);
+
+        store.build_forge_index().unwrap();
         let response = server.handle_request(request).await;
-        assert!(response.error.is_some());
-        assert_eq!(response.error.unwrap().code, -32600);
+        assert_eq!(response.error.unwrap().code, RPC_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_getforge_from_mempool_then_chain() {
+        use crate::consensus::sighash::TransferInput;
+        use crate::consensus::{block_merkle_root, commit_transfer_root, testing, MerkleProof};
+
+        let store = Arc::new(ChainStore::with_backend(Box::new(crate::chain::MemoryStore::new())).unwrap());
+        let pool = Arc::new(ForgePool::new(100, 0));
+        let mut forge = ForgeTransaction {
+            prophecy: "pending forge".to_string(),
+            derived_key: crate::crypto::derive_public_key(&[7u8; 32]).unwrap().serialize().to_vec(),
+            taproot_address: "bc1p...".to_string(),
+            proof_hash: [1u8; 32],
+            timestamp: 1000,
+            signature: vec![],
+            not_before_height: 0,
+            tempering: Default::default(),
+            salt: [0; 32],
+        };
+        forge.sign(&[7u8; 32]).unwrap();
+        pool.add_forge(forge.clone()).unwrap();
+
+        let mined = ForgeTransaction {
+            prophecy: "mined forge".to_string(),
+            proof_hash: [2u8; 32],
+            ..forge.clone()
+        };
+        let first = ForgeTransaction {
+            prophecy: "first forge".to_string(),
+            proof_hash: [4u8; 32],
+            ..forge
+        };
+        // The forge is mined alongside a transfer, so the header commits
+        // to more than the forge root
+        let transfer = SignedTransfer {
+            transfer: Transfer {
+                version: 1,
+                inputs: vec![TransferInput {
+                    prevout: OutPoint { txid: [5u8; 32], vout: 0 },
+                    amount: 50_000,
+                    address: "bc1p...".to_string(),
+                }],
+                outputs: vec![TransferOutput { address: "bc1q...".to_string(), value: 49_000 }],
+                fee: 1_000,
+                lock_height: 0,
+            },
+            witnesses: Vec::new(),
+        };
+        for height in 0..=3u64 {
+            let forges = if height == 1 { vec![first.clone(), mined.clone()] } else { vec![] };
+            let mut block = testing::block(height, [0u8; 32], forges);
+            if height == 1 {
+                block.transfers = vec![transfer.clone()];
+            }
+            block.header.merkle_root = block_merkle_root(MerkleTree::build(&block.forges).root(), &block.transfers);
+            store.put_block(height, &block.encode()).unwrap();
+        }
+        store.set_height(3).unwrap();
+
+        let mut server = RpcServer::new();
+        server.register_forge_handlers(Arc::clone(&store), pool);
+        let call = |proof_hash: [u8; 32]| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getforge".to_string(),
+            params: Some(json!(hex::encode(proof_hash))),
+            id: json!(1),
+        };
+
+        let pending = server.handle_request(call([1u8; 32])).await.result.unwrap();
+        assert_eq!(pending["prophecy"], "pending forge");
+        assert_eq!((pending["in_mempool"].clone(), pending["confirmations"].clone()), (json!(true), json!(0)));
+
+        let response = server.handle_request(call([2u8; 32])).await;
+        assert_eq!(response.error.unwrap().code, RPC_INDEX_DISABLED);
+
+        store.build_forge_index().unwrap();
+        let confirmed = server.handle_request(call([2u8; 32])).await.result.unwrap();
+        assert_eq!(confirmed["prophecy"], "mined forge");
+        assert_eq!((confirmed["height"].clone(), confirmed["confirmations"].clone()), (json!(1), json!(3)));
+        assert_eq!(server.handle_request(call([3u8; 32])).await.error.unwrap().code, RPC_NOT_FOUND);
+
+        // The proof leads to the forge root, which with the transfer root
+        // makes the header's merkle root
+        let request = JsonRpcRequest {
+            method: "getforgeproof".to_string(),
+            ..call([2u8; 32])
+        };
+        let proof = server.handle_request(request).await.result.unwrap();
+        let hash32 = |value: &Value| <[u8; 32]>::try_from(hex::decode(value.as_str().unwrap()).unwrap()).unwrap();
+        let merkle_proof = MerkleProof {
+            index: proof["index"].as_u64().unwrap() as usize,
+            siblings: proof["siblings"].as_array().unwrap().iter().map(hash32).collect(),
+        };
+        assert_eq!((proof["height"].clone(), merkle_proof.index), (json!(1), 1));
+        assert_eq!(hash32(&proof["leaf"]), forge_leaf_hash(&mined));
+        let forge_root = hash32(&proof["forge_root"]);
+        assert!(MerkleTree::verify(&forge_root, &merkle_proof, &forge_leaf_hash(&mined)));
+        let header_root = store.load_block(1).unwrap().unwrap().header.merkle_root;
+        assert_eq!(hash32(&proof["merkle_root"]), header_root);
+        assert_ne!(forge_root, header_root);
+        let transfer_root = Some(hash32(&proof["transfer_root"]));
+        assert_eq!(commit_transfer_root(forge_root, transfer_root), header_root);
+        let pending = JsonRpcRequest {
+            method: "getforgeproof".to_string(),
+            ..call([1u8; 32])
+        };
+        assert_eq!(server.handle_request(pending).await.error.unwrap().code, RPC_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_getblock_cached_until_reorg() {
+        use crate::consensus::testing;
+        use crate::events::{EventBus, NodeEvent, ReorgEvent};
+        use crate::shutdown::ShutdownCoordinator;
+
+        let store = Arc::new(ChainStore::with_backend(Box::new(crate::chain::MemoryStore::new())).unwrap());
+        let mut hashes = Vec::new();
+        for height in 0..3u64 {
+            let forge = ForgeTransaction {
+                prophecy: format!("block {}", height),
+                derived_key: vec![2; 33],
+                taproot_address: "bc1p...".to_string(),
+                proof_hash: [height as u8 + 1; 32],
+                timestamp: 1000,
+                signature: vec![],
+                not_before_height: 0,
+                tempering: Default::default(),
+                salt: [0; 32],
+            };
+            let block = testing::block(height, hashes.last().copied().unwrap_or([0u8; 32]), vec![forge]);
+            let hash: [u8; 32] = Sha256::digest(header_hash_preimage(&block.header)).into();
+            store.put_block(height, &block.encode()).unwrap();
+            store.put_block_hash(&hash, height).unwrap();
+            hashes.push(hash);
+        }
+
+        let mut server = RpcServer::new();
+        server.register_block_handlers(Arc::clone(&store));
+        let events = EventBus::new();
+        let shutdown = ShutdownCoordinator::new();
+        tokio::spawn(Arc::clone(server.response_cache()).run(events.subscribe(), shutdown.subscribe()));
+        let call = |params: Value| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getblock".to_string(),
+            params: Some(params),
+            id: json!(1),
+        };
+
+        for hash in &hashes {
+            let block = server.handle_request(call(json!(hex::encode(hash)))).await.result.unwrap();
+            assert_eq!(block["hash"], hex::encode(hash));
+        }
+        let by_height = server.handle_request(call(json!(2))).await.result.unwrap();
+        let cached = server.handle_request(call(json!([hex::encode(hashes[2]), 1]))).await.result.unwrap();
+        assert_eq!(by_height, cached);
+        assert_eq!(cached["forge_count"], 1);
+        assert_eq!(cached["forges"][0], hex::encode([3u8; 32]));
+        let stats = server.response_cache().stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (3, 1, 3));
+
+        let raw = server.handle_request(call(json!([2, 0]))).await.result.unwrap();
+        let decoded = Block::decode(&hex::decode(raw.as_str().unwrap()).unwrap()).unwrap();
+        assert_eq!(decoded.forges[0].prophecy, "block 2");
+        let full = server.handle_request(call(json!([hex::encode(hashes[2]), 2]))).await.result.unwrap();
+        assert_eq!(full["forges"][0]["prophecy"], "block 2");
+        assert_eq!(full["size"], raw.as_str().unwrap().len() / 2);
+        let bad = server.handle_request(call(json!([2, 3]))).await;
+        assert_eq!(bad.error.unwrap().code, RPC_INVALID_PARAMETER);
+
+        // Reorg at height 0 drops blocks 1 and 2, at every verbosity
+        events.publish(NodeEvent::Reorg(ReorgEvent {
+            fork_height: 0,
+            fork_hash: hex::encode(hashes[0]),
+            tip_hash: hex::encode([9u8; 32]),
+            disconnected: 2,
+            connected: 3,
+        }));
+        while server.response_cache().stats().invalidated < 4 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(server.response_cache().stats().entries, 1);
+
+        let info = JsonRpcRequest { method: "getrpcinfo".to_string(), ..call(Value::Null) };
+        let info = server.handle_request(info).await.result.unwrap();
+        assert_eq!(info["response_cache"]["invalidated"], 4);
+        let missing = server.handle_request(call(json!(hex::encode([9u8; 32])))).await;
+        assert_eq!(missing.error.unwrap().code, RPC_NOT_FOUND);
+        shutdown.trigger();
+    }
+
+    #[tokio::test]
+    async fn test_read_responses_signed() {
+        let mut server = RpcServer::new();
+        let call = |method: &str| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: None,
+            id: json!(1),
+        };
+        assert!(server.handle_request_signed(call("getblockcount")).await.1.is_none());
+
+        let identity = NodeIdentity::generate();
+        let node_id = identity.node_id();
+        server.enable_response_signing(identity);
+
+        let (body, signature) = server.handle_request_signed(call("getblockcount")).await;
+        let signature = signature.unwrap();
+        assert_eq!(signature.node_id, node_id);
+        signature.verify(&body).unwrap();
+
+        let (_, signature) = server.handle_request_signed(call("submitforge")).await;
+        assert!(signature.is_none(), "only read responses are signed");
+
+        let identity = server.handle_request(call("getnodeidentity")).await.result.unwrap();
+        assert_eq!(identity["node_id"], hex::encode(node_id));
+    }
+
+    #[tokio::test]
+    async fn test_searchforges_paginates() {
+        use crate::consensus::testing;
+
+        let store = Arc::new(ChainStore::with_backend(Box::new(crate::chain::MemoryStore::new())).unwrap());
+        let mut server = RpcServer::new();
+        server.register_search_handlers(Arc::clone(&store));
+
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "searchforges".to_string(),
+            params: Some(json!({ "query": "legend*", "limit": 1 })),
+            id: json!(1),
+        };
+        let response = server.handle_request(request.clone()).await;
+        assert_eq!(response.error.unwrap().code, RPC_INDEX_DISABLED);
+
+        for height in 0..2u64 {
+            let forge = ForgeTransaction {
+                prophecy: format!("sword legend {}", height),
+                derived_key: vec![],
+                taproot_address: "bc1p...".to_string(),
+                proof_hash: [height as u8; 32],
+                timestamp: 1000,
+                signature: vec![],
+                not_before_height: 0,
+                tempering: Default::default(),
+                salt: [0; 32],
+            };
+            let block = testing::block(height, [0u8; 32], vec![forge]);
+            store.put_block(height, &block.encode()).unwrap();
+            store.set_height(height).unwrap();
+        }
+        store.build_search_index().unwrap();
+
+        let result = server.handle_request(request.clone()).await.result.unwrap();
+        assert_eq!(result["total_estimate"], 2);
+        assert_eq!(result["results"].as_array().unwrap().len(), 1);
+        assert_eq!(result["results"][0]["height"], 1);
+        assert_eq!(result["results"][0]["prophecy"], "sword legend 1");
+
+        let mut next = request;
+        next.params = Some(json!({ "query": "legend*", "limit": 1, "cursor": result["next_cursor"] }));
+        let result = server.handle_request(next).await.result.unwrap();
+        assert_eq!(result["results"][0]["height"], 0);
+        assert_eq!(result["next_cursor"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_getrawmempool_pages() {
+        let pool = Arc::new(ForgePool::new(10, 0));
+        for seed in 1..=3u8 {
+            let mut forge = ForgeTransaction {
+                prophecy: format!("pending prophecy {}", seed),
+                derived_key: crate::crypto::derive_public_key(&[seed; 32]).unwrap().serialize().to_vec(),
+                taproot_address: "bc1p...".to_string(),
+                proof_hash: [seed; 32],
+                timestamp: 1000,
+                signature: vec![],
+                not_before_height: 0,
+                tempering: Default::default(),
+                salt: [0; 32],
+            };
+            forge.sign(&[seed; 32]).unwrap();
+            pool.add_forge(forge).unwrap();
+        }
+        let mut server = RpcServer::new();
+        server.register_mempool_handlers(pool);
+
+        let call = |params: Value| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getrawmempool".to_string(),
+            params: Some(params),
+            id: json!(1),
+        };
+        let first = server.handle_request(call(json!({ "limit": 2 }))).await.result.unwrap();
+        assert_eq!(first["results"], json!([hex::encode([1u8; 32]), hex::encode([2u8; 32])]));
+        assert_eq!(first["total_estimate"], 3);
+        let rest = server
+            .handle_request(call(json!({ "cursor": first["next_cursor"] })))
+            .await
+            .result
+            .unwrap();
+        assert_eq!(rest["results"], json!([hex::encode([3u8; 32])]));
+        assert_eq!(rest["next_cursor"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_getsyncstatus() {
+        let queue = Arc::new(std::sync::Mutex::new(BodyFetchQueue::new()));
+        let reconnects = Arc::new(ReconnectSchedule::default());
+        let peer = libp2p::PeerId::random();
+        reconnects.disconnected(peer, "/ip4/127.0.0.1/tcp/8333".parse().unwrap(), std::time::Instant::now());
+
+        let mut server = RpcServer::new();
+        server.register_sync_handlers(queue, reconnects);
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: "getsyncstatus".to_string(),
+            params: None,
+            id: json!(1),
+        };
+        let result = server.handle_request(request).await.result.unwrap();
+        assert_eq!(result["pending"], 0);
+        assert_eq!(result["timeouts"], 0);
+        assert_eq!(result["reconnecting"][0]["peer"], peer.to_string());
+        assert_eq!(result["reconnecting"][0]["attempts"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_blockchain_and_network_info() {
+        let engine = Arc::new(ConsensusEngine::new(0, 600));
+        let queue = Arc::new(std::sync::Mutex::new(BodyFetchQueue::new()));
+        let connections = Arc::new(ConnectionStats::new());
+        connections.opened(true);
+        connections.opened(false);
+        connections.add_listen_addr("/ip4/10.0.0.5/tcp/18444".parse().unwrap());
+
+        let mut server = RpcServer::new();
+        server.register_blockchain_info_handlers(engine, "regtest".to_string(), queue);
+        server.register_network_info_handlers(connections);
+        let request = |method: &str| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: None,
+            id: json!(1),
+        };
+
+        let info = server.handle_request(request("getinfo")).await.result.unwrap();
+        assert_eq!((info["network"].clone(), info["difficulty"].clone()), (json!("regtest"), json!(0)));
+        let difficulty = server.handle_request(request("getdifficulty")).await.result.unwrap();
+        assert_eq!(difficulty, json!(0));
+
+        let chain = server.handle_request(request("getblockchaininfo")).await.result.unwrap();
+        assert_eq!(chain["chain"], "regtest");
+        assert_eq!(chain["blocks"], 0);
+        assert_eq!(chain["verificationprogress"], 1.0);
+        assert_eq!(chain["bestblockhash"].as_str().unwrap().len(), 64);
+
+        let network = server.handle_request(request("getnetworkinfo")).await.result.unwrap();
+        assert_eq!(network["subversion"], subversion());
+        assert_eq!((network["connections_in"].clone(), network["connections_out"].clone()), (json!(1), json!(1)));
+        assert_eq!(network["localaddresses"], json!([{ "address": "10.0.0.5", "port": 18444 }]));
+        assert_eq!(sync_progress(50, 200), 0.25);
+    }
+
+    #[tokio::test]
+    async fn test_peer_rule_handlers() {
+        let filter = Arc::new(PeerFilter::default());
+        let mut server = RpcServer::new();
+        server.register_peer_filter_handlers(Arc::clone(&filter));
+        let request = |method: &str, params: Value| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params: Some(params),
+            id: json!(1),
+        };
+
+        let added = server.handle_request(request("addpeerrule", json!(["deny", "198.51.100.0/24"]))).await;
+        assert_eq!(added.result, Some(json!(true)));
+        let bad = server.handle_request(request("addpeerrule", json!(["deny", "198.51.100.0/99"]))).await;
+        assert_eq!(bad.error.unwrap().code, RPC_INVALID_PARAMETER);
+        let bad = server.handle_request(request("addpeerrule", json!(["block", "198.51.100.1"]))).await;
+        assert_eq!(bad.error.unwrap().code, RPC_INVALID_PARAMETER);
+        assert!(filter.check(None, Some(&"198.51.100.7".parse().unwrap())).is_err());
+
+        let rules = server.handle_request(request("listpeerrules", Value::Null)).await.result.unwrap();
+        assert_eq!(rules, json!({ "allow": [], "deny": ["198.51.100.0/24"], "refused": 0 }));
+        let removed = server.handle_request(request("removepeerrule", json!(["deny", "198.51.100.0/24"]))).await;
+        assert_eq!(removed.result, Some(json!(true)));
+        assert!(filter.rules().deny.is_empty());
     }
 }
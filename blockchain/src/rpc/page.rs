@@ -0,0 +1,161 @@
+//! Pagination shared by list methods
+//!
+//! List methods take `{"limit": n, "cursor": "..."}` and answer
+//! `{"results": [...], "next_cursor": ..., "total_estimate": n}`. Entries
+//! are listed in a fixed key order and the cursor is the hex-encoded key of
+//! the last entry returned, so the next page starts after that entry even
+//! if entries were added or removed in between. `next_cursor` is null on
+//! the last page. `total_estimate` counts the entries when the page was
+//! built; it can be stale by the time a client reaches the end.
+
+use super::{RpcMethodError, RPC_INVALID_PARAMETER};
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Default and largest page size of list methods without their own
+pub const DEFAULT_PAGE_LIMIT: usize = 100;
+pub const MAX_PAGE_LIMIT: usize = 1000;
+
+/// Order a method lists its entries in, by key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyOrder {
+    Ascending,
+    Descending,
+}
+
+/// Page asked for by a list call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageRequest {
+    pub limit: usize,
+    /// Key of the last entry of the previous page
+    pub after: Option<Vec<u8>>,
+}
+
+impl PageRequest {
+    /// Read `limit` and `cursor` from call params
+    pub fn from_params(params: &Value) -> Result<Self> {
+        Self::with_limits(params, DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT)
+    }
+
+    /// Read `limit` and `cursor` for a method with its own default and
+    /// largest page size
+    pub fn with_limits(params: &Value, default_limit: usize, max_limit: usize) -> Result<Self> {
+        let limit = match params.get("limit") {
+            None | Some(Value::Null) => default_limit,
+            Some(limit) => limit
+                .as_u64()
+                .filter(|&limit| limit > 0)
+                .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "limit must be a positive integer"))?
+                as usize,
+        };
+        let after = match params.get("cursor") {
+            None | Some(Value::Null) => None,
+            Some(cursor) => Some(
+                cursor
+                    .as_str()
+                    .and_then(|cursor| hex::decode(cursor).ok())
+                    .ok_or_else(|| RpcMethodError::new(RPC_INVALID_PARAMETER, "Malformed cursor"))?,
+            ),
+        };
+        Ok(Self {
+            limit: limit.min(max_limit),
+            after,
+        })
+    }
+
+    /// The requested page of `entries`, which are keyed and listed in
+    /// `order`
+    pub fn page<T>(
+        &self,
+        entries: impl IntoIterator<Item = (Vec<u8>, T)>,
+        order: KeyOrder,
+        total_estimate: usize,
+    ) -> Page<T> {
+        let mut results = Vec::new();
+        let mut last_key = None;
+        let mut more = false;
+        for (key, entry) in entries {
+            let past_cursor = self.after.as_ref().is_none_or(|after| match order {
+                KeyOrder::Ascending => key > *after,
+                KeyOrder::Descending => key < *after,
+            });
+            if !past_cursor {
+                continue;
+            }
+            if results.len() == self.limit {
+                more = true;
+                break;
+            }
+            last_key = Some(key);
+            results.push(entry);
+        }
+        Page {
+            results,
+            next_cursor: last_key.filter(|_| more).map(hex::encode),
+            total_estimate,
+        }
+    }
+}
+
+/// One page of a list method's answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page<T> {
+    pub results: Vec<T>,
+    pub next_cursor: Option<String>,
+    pub total_estimate: usize,
+}
+
+impl<T> Page<T> {
+    /// The same page with its entries replaced, e.g. by their full records
+    pub fn with_results<U>(self, results: Vec<U>) -> Page<U> {
+        Page {
+            results,
+            next_cursor: self.next_cursor,
+            total_estimate: self.total_estimate,
+        }
+    }
+}
+
+impl<T: Serialize> Page<T> {
+    /// Response body of a list method
+    pub fn to_json(&self) -> Value {
+        json!({
+            "results": self.results,
+            "next_cursor": self.next_cursor,
+            "total_estimate": self.total_estimate,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyed(values: &[u8]) -> Vec<(Vec<u8>, u8)> {
+        values.iter().map(|&value| (vec![value], value)).collect()
+    }
+
+    #[test]
+    fn test_pages_follow_cursor() {
+        let request = PageRequest::from_params(&json!({ "limit": 2 })).unwrap();
+        let first = request.page(keyed(&[1, 2, 3, 4, 5]), KeyOrder::Ascending, 5);
+        assert_eq!((first.results.clone(), first.next_cursor.clone()), (vec![1, 2], Some("02".to_string())));
+
+        // An entry removed before the next call doesn't shift the page
+        let request = PageRequest::from_params(&json!({ "limit": 2, "cursor": first.next_cursor })).unwrap();
+        let second = request.page(keyed(&[1, 3, 4, 5]), KeyOrder::Ascending, 4);
+        assert_eq!(second.results, vec![3, 4]);
+        let request = PageRequest::from_params(&json!({ "limit": 2, "cursor": second.next_cursor })).unwrap();
+        let last = request.page(keyed(&[1, 3, 4, 5]), KeyOrder::Ascending, 4);
+        assert_eq!(last.to_json(), json!({ "results": [5], "next_cursor": null, "total_estimate": 4 }));
+
+        let request = PageRequest::from_params(&json!({ "limit": 1, "cursor": "04" })).unwrap();
+        assert_eq!(request.page(keyed(&[5, 4, 3]), KeyOrder::Descending, 3).results, vec![3]);
+
+        assert_eq!(PageRequest::with_limits(&json!({ "limit": 500 }), 25, 100).unwrap().limit, 100);
+        assert_eq!(PageRequest::from_params(&Value::Null).unwrap().limit, DEFAULT_PAGE_LIMIT);
+        assert!(PageRequest::from_params(&json!({ "limit": 0 })).is_err());
+        assert!(PageRequest::from_params(&json!({ "cursor": "zz" })).is_err());
+    }
+}
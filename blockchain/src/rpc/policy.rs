@@ -0,0 +1,107 @@
+//! Per-method access control
+//!
+//! Every method needs RPC credentials unless the policy says otherwise.
+//! Operators exposing the server publicly can open read methods such as
+//! `getinfo` to anonymous callers, and confine methods such as
+//! `submitforge` to callers on the loopback interface.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Callers allowed to use a method
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MethodAccess {
+    /// Anyone, with or without credentials
+    Public,
+    /// Callers presenting RPC credentials
+    Authenticated,
+    /// Authenticated callers connecting from a loopback address
+    Local,
+}
+
+/// Where a request came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RpcCaller {
+    /// Connected from a loopback address
+    pub loopback: bool,
+    /// Presented accepted credentials, or the server requires none
+    pub authenticated: bool,
+}
+
+impl RpcCaller {
+    /// In-process caller, allowed everything
+    pub const LOCAL: RpcCaller = RpcCaller {
+        loopback: true,
+        authenticated: true,
+    };
+}
+
+/// Access level of each method
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcPolicy {
+    default: MethodAccess,
+    methods: HashMap<String, MethodAccess>,
+}
+
+impl Default for RpcPolicy {
+    fn default() -> Self {
+        Self::new(MethodAccess::Authenticated)
+    }
+}
+
+impl RpcPolicy {
+    /// Policy giving methods without an entry `default` access
+    pub fn new(default: MethodAccess) -> Self {
+        Self {
+            default,
+            methods: HashMap::new(),
+        }
+    }
+
+    /// Set the access level of `method`
+    pub fn set(&mut self, method: &str, access: MethodAccess) {
+        self.methods.insert(method.to_string(), access);
+    }
+
+    /// Builder form of `set`
+    pub fn with(mut self, method: &str, access: MethodAccess) -> Self {
+        self.set(method, access);
+        self
+    }
+
+    /// Access level of `method`
+    pub fn access(&self, method: &str) -> MethodAccess {
+        self.methods.get(method).copied().unwrap_or(self.default)
+    }
+
+    /// Whether `caller` may use `method`
+    pub fn permits(&self, method: &str, caller: RpcCaller) -> bool {
+        match self.access(method) {
+            MethodAccess::Public => true,
+            MethodAccess::Authenticated => caller.authenticated,
+            MethodAccess::Local => caller.authenticated && caller.loopback,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_access_levels() {
+        let policy = RpcPolicy::default()
+            .with("getinfo", MethodAccess::Public)
+            .with("submitforge", MethodAccess::Local);
+        let anonymous = RpcCaller { loopback: false, authenticated: false };
+        let remote = RpcCaller { loopback: false, authenticated: true };
+
+        assert!(policy.permits("getinfo", anonymous));
+        assert!(!policy.permits("getblockcount", anonymous));
+        assert!(policy.permits("getblockcount", remote));
+        assert!(!policy.permits("submitforge", remote));
+        assert!(policy.permits("submitforge", RpcCaller::LOCAL));
+        assert!(!policy.permits("submitforge", RpcCaller { loopback: true, authenticated: false }));
+    }
+}
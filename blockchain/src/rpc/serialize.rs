@@ -0,0 +1,262 @@
+//! Response serialization off the async workers
+//!
+//! Small responses are serialized inline. Responses estimated above
+//! `LARGE_RESPONSE_BYTES` are serialized on the blocking pool so a
+//! multi-megabyte result cannot stall other requests, and over HTTP they
+//! are streamed to the client in `STREAM_CHUNK_BYTES` chunks instead of
+//! being buffered whole.
+
+use super::JsonRpcResponse;
+use crate::metrics::{Histogram, HistogramSnapshot, SIZE_BUCKETS_BYTES};
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Estimated encoded size above which serialization leaves the async worker
+pub const LARGE_RESPONSE_BYTES: usize = 256 * 1024;
+
+/// Size of each chunk written to a streamed response body
+pub const STREAM_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Response size statistics
+#[derive(Debug)]
+pub struct ResponseMetrics {
+    sizes: Histogram,
+    offloaded: AtomicU64,
+}
+
+impl Default for ResponseMetrics {
+    fn default() -> Self {
+        Self {
+            sizes: Histogram::new(SIZE_BUCKETS_BYTES),
+            offloaded: AtomicU64::new(0),
+        }
+    }
+}
+
+impl ResponseMetrics {
+    /// Encoded response sizes in bytes
+    pub fn sizes(&self) -> HistogramSnapshot {
+        self.sizes.snapshot()
+    }
+
+    /// Number of responses serialized on the blocking pool
+    pub fn offloaded(&self) -> u64 {
+        self.offloaded.load(Ordering::Relaxed)
+    }
+
+    fn record(&self, bytes: usize, offloaded: bool) {
+        self.sizes.observe(bytes as u64);
+        if offloaded {
+            self.offloaded.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Lower bound on the encoded size of `value`, stopping early once it
+/// exceeds `limit`
+pub fn estimated_size(value: &Value, limit: usize) -> usize {
+    fn walk(value: &Value, total: &mut usize, limit: usize) {
+        if *total > limit {
+            return;
+        }
+        match value {
+            Value::Null | Value::Bool(_) => *total += 4,
+            Value::Number(_) => *total += 1,
+            Value::String(s) => *total += s.len() + 2,
+            Value::Array(items) => {
+                *total += 2 + items.len();
+                for item in items {
+                    walk(item, total, limit);
+                }
+            }
+            Value::Object(fields) => {
+                *total += 2 + fields.len();
+                for (key, item) in fields {
+                    *total += key.len() + 3;
+                    walk(item, total, limit);
+                }
+            }
+        }
+    }
+
+    let mut total = 0;
+    walk(value, &mut total, limit);
+    total
+}
+
+/// Whether a response should be serialized off the async worker
+pub fn is_large(response: &JsonRpcResponse) -> bool {
+    response
+        .result
+        .as_ref()
+        .is_some_and(|result| estimated_size(result, LARGE_RESPONSE_BYTES) > LARGE_RESPONSE_BYTES)
+}
+
+/// Serialize a response, using the blocking pool for large ones
+pub async fn serialize_response(response: JsonRpcResponse, metrics: &ResponseMetrics) -> Vec<u8> {
+    let offload = is_large(&response);
+    let bytes = if offload {
+        tokio::task::spawn_blocking(move || serde_json::to_vec(&response))
+            .await
+            .expect("response serialization task panicked")
+    } else {
+        serde_json::to_vec(&response)
+    }
+    .expect("JSON values always serialize");
+
+    metrics.record(bytes.len(), offload);
+    bytes
+}
+
+/// `io::Write` adapter that hands fixed-size chunks to an async consumer
+#[cfg(any(feature = "http-server", test))]
+pub(super) struct ChunkWriter {
+    chunks: tokio::sync::mpsc::Sender<Vec<u8>>,
+    buffer: Vec<u8>,
+    written: usize,
+}
+
+#[cfg(any(feature = "http-server", test))]
+impl ChunkWriter {
+    pub(super) fn new(chunks: tokio::sync::mpsc::Sender<Vec<u8>>) -> Self {
+        Self {
+            chunks,
+            buffer: Vec::with_capacity(STREAM_CHUNK_BYTES),
+            written: 0,
+        }
+    }
+
+    fn send_buffer(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(STREAM_CHUNK_BYTES));
+        self.chunks
+            .blocking_send(chunk)
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "client went away"))
+    }
+}
+
+#[cfg(any(feature = "http-server", test))]
+impl std::io::Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        self.written += data.len();
+        if self.buffer.len() >= STREAM_CHUNK_BYTES {
+            self.send_buffer()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.send_buffer()
+    }
+}
+
+/// Serialize `response` on the blocking pool into `chunks`, returning the
+/// number of bytes written
+#[cfg(any(feature = "http-server", test))]
+fn spawn_chunked(
+    response: JsonRpcResponse,
+    chunks: tokio::sync::mpsc::Sender<Vec<u8>>,
+) -> tokio::task::JoinHandle<std::io::Result<usize>> {
+    tokio::task::spawn_blocking(move || {
+        use std::io::Write;
+
+        let mut writer = ChunkWriter::new(chunks);
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.flush()?;
+        Ok(writer.written)
+    })
+}
+
+/// HTTP body for a response; large responses are serialized on the
+/// blocking pool and streamed as they are produced
+#[cfg(feature = "http-server")]
+pub async fn response_body(
+    response: JsonRpcResponse,
+    metrics: std::sync::Arc<ResponseMetrics>,
+) -> warp::hyper::Body {
+    if !is_large(&response) {
+        return warp::hyper::Body::from(serialize_response(response, &metrics).await);
+    }
+
+    let (tx, rx) = tokio::sync::mpsc::channel(4);
+    let task = spawn_chunked(response, tx);
+    tokio::spawn(async move {
+        match task.await {
+            Ok(Ok(written)) => metrics.record(written, true),
+            Ok(Err(e)) => tracing::debug!("Streaming RPC response aborted: {}", e),
+            Err(e) => tracing::warn!("RPC response serialization failed: {}", e),
+        }
+    });
+
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv()
+            .await
+            .map(|chunk| (Ok::<_, std::convert::Infallible>(chunk), rx))
+    });
+    warp::hyper::Body::wrap_stream(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn response_with(result: Value) -> JsonRpcResponse {
+        JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            result: Some(result),
+            error: None,
+            id: json!(1),
+        }
+    }
+
+    fn large_result() -> Value {
+        json!((0..2_000).map(|i| "f".repeat(200) + &i.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_estimated_size() {
+        assert!(estimated_size(&json!({"height": 1}), LARGE_RESPONSE_BYTES) < 64);
+        assert!(is_large(&response_with(large_result())));
+        assert!(!is_large(&response_with(json!([1, 2, 3]))));
+    }
+
+    #[tokio::test]
+    async fn test_serialize_response_offloads_large() {
+        let metrics = ResponseMetrics::default();
+
+        let small = serialize_response(response_with(json!("ok")), &metrics).await;
+        assert_eq!(metrics.offloaded(), 0);
+
+        let large = response_with(large_result());
+        let expected = serde_json::to_vec(&large).unwrap();
+        assert_eq!(serialize_response(large, &metrics).await, expected);
+        assert_eq!(metrics.offloaded(), 1);
+
+        let sizes = metrics.sizes();
+        assert_eq!(sizes.count, 2);
+        assert_eq!(sizes.sum, (small.len() + expected.len()) as u64);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_serialization() {
+        let response = response_with(large_result());
+        let expected = serde_json::to_vec(&response).unwrap();
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        let task = spawn_chunked(response, tx);
+        let mut body = Vec::new();
+        let mut chunks = 0;
+        while let Some(chunk) = rx.recv().await {
+            body.extend(chunk);
+            chunks += 1;
+        }
+
+        assert_eq!(task.await.unwrap().unwrap(), expected.len());
+        assert_eq!(body, expected);
+        assert!(chunks > 1);
+    }
+}
@@ -0,0 +1,218 @@
+//! WebSocket subscriptions (`/ws`)
+//!
+//! Clients send `{"id", "method": "subscribe" | "unsubscribe", "params":
+//! [topics]}` and are pushed `{"event", "data"}` notifications for the topics
+//! they hold: `newblock` for each block connected to the tip, `newforge` for
+//! each forge admitted to the mempool and `peer` for peer connections and
+//! disconnections. A `gap` notification reports events dropped because the
+//! client fell behind.
+
+use super::RPC_INVALID_PARAMETER;
+use crate::events::NodeEvent;
+use crate::mempool::{MempoolEvent, MempoolEventKind};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+
+/// Notification topic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Topic {
+    NewBlock,
+    NewForge,
+    Peer,
+}
+
+impl Topic {
+    /// Name clients subscribe with
+    pub fn name(self) -> &'static str {
+        match self {
+            Topic::NewBlock => "newblock",
+            Topic::NewForge => "newforge",
+            Topic::Peer => "peer",
+        }
+    }
+
+    /// Topic with the given name
+    pub fn parse(name: &str) -> Option<Self> {
+        [Topic::NewBlock, Topic::NewForge, Topic::Peer]
+            .into_iter()
+            .find(|topic| topic.name() == name)
+    }
+}
+
+/// Topics one WebSocket client is subscribed to
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    topics: BTreeSet<Topic>,
+}
+
+impl Subscriptions {
+    /// Whether notifications for `topic` are sent
+    pub fn contains(&self, topic: Topic) -> bool {
+        self.topics.contains(&topic)
+    }
+
+    /// Apply a `subscribe` or `unsubscribe` request and build the reply,
+    /// which lists the topics held afterwards
+    pub fn handle_request(&mut self, text: &str) -> Value {
+        let Ok(request) = serde_json::from_str::<Value>(text) else {
+            return json!({ "id": null, "error": { "code": -32700, "message": "Parse error" } });
+        };
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let error = |code: i32, message: String| json!({ "id": id, "error": { "code": code, "message": message } });
+
+        let method = request.get("method").and_then(|m| m.as_str()).unwrap_or_default();
+        let subscribe = match method {
+            "subscribe" => true,
+            "unsubscribe" => false,
+            _ => return error(-32601, format!("Method '{}' not found", method)),
+        };
+        let Some(names) = request.get("params").and_then(|p| p.as_array()) else {
+            return error(RPC_INVALID_PARAMETER, "Expected an array of topics".to_string());
+        };
+        let mut topics = Vec::with_capacity(names.len());
+        for name in names {
+            let name = name.as_str().unwrap_or_default();
+            match Topic::parse(name) {
+                Some(topic) => topics.push(topic),
+                None => return error(RPC_INVALID_PARAMETER, format!("Unknown topic '{}'", name)),
+            }
+        }
+        for topic in topics {
+            if subscribe {
+                self.topics.insert(topic);
+            } else {
+                self.topics.remove(&topic);
+            }
+        }
+        let held: Vec<_> = self.topics.iter().map(|topic| topic.name()).collect();
+        json!({ "id": id, "result": held })
+    }
+}
+
+/// Notification for a node event, if it belongs to a topic
+pub fn node_event_notification(event: &NodeEvent) -> Option<(Topic, Value)> {
+    let (topic, data) = match event {
+        NodeEvent::Block(block) => (Topic::NewBlock, serde_json::to_value(block).ok()?),
+        NodeEvent::Peer(peer) => (Topic::Peer, serde_json::to_value(peer).ok()?),
+        NodeEvent::Alert(_) | NodeEvent::Reorg(_) => return None,
+    };
+    Some((topic, notification(topic, data)))
+}
+
+/// Notification for a mempool event; only admissions are announced
+pub fn mempool_event_notification(event: &MempoolEvent) -> Option<(Topic, Value)> {
+    if event.kind != MempoolEventKind::Added {
+        return None;
+    }
+    let data = json!({
+        "proof_hash": hex::encode(event.proof_hash),
+        "mempool_sequence": event.sequence,
+    });
+    Some((Topic::NewForge, notification(Topic::NewForge, data)))
+}
+
+fn notification(topic: Topic, data: Value) -> Value {
+    json!({ "event": topic.name(), "data": data })
+}
+
+/// Answer a client's subscription requests and push notifications for its
+/// topics until either side closes. `forges` is absent when the server has
+/// no mempool, in which case `newforge` is accepted but never fires.
+#[cfg(feature = "http-server")]
+pub(crate) async fn serve_subscriptions(
+    socket: warp::ws::WebSocket,
+    mut events: tokio::sync::broadcast::Receiver<NodeEvent>,
+    mut forges: Option<tokio::sync::broadcast::Receiver<MempoolEvent>>,
+) {
+    use futures::{SinkExt, StreamExt};
+    use tokio::sync::broadcast::error::RecvError;
+    use warp::ws::Message;
+
+    let (mut sink, mut stream) = socket.split();
+    let mut subscriptions = Subscriptions::default();
+    loop {
+        let outgoing = tokio::select! {
+            message = stream.next() => match message {
+                Some(Ok(message)) if message.is_close() => break,
+                Some(Ok(message)) => match message.to_str() {
+                    Ok(text) => Some(subscriptions.handle_request(text)),
+                    // Pings are answered by warp; binary frames are ignored
+                    Err(()) => None,
+                },
+                Some(Err(_)) | None => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) => node_event_notification(&event)
+                    .filter(|(topic, _)| subscriptions.contains(*topic))
+                    .map(|(_, message)| message),
+                Err(RecvError::Lagged(missed)) => Some(json!({ "event": "gap", "missed": missed })),
+                Err(RecvError::Closed) => break,
+            },
+            event = async { forges.as_mut().expect("branch requires a receiver").recv().await }, if forges.is_some() => {
+                match event {
+                    Ok(event) => mempool_event_notification(&event)
+                        .filter(|(topic, _)| subscriptions.contains(*topic))
+                        .map(|(_, message)| message),
+                    Err(RecvError::Lagged(missed)) => Some(json!({ "event": "gap", "missed": missed })),
+                    Err(RecvError::Closed) => {
+                        forges = None;
+                        None
+                    }
+                }
+            }
+        };
+        if let Some(message) = outgoing {
+            if sink.send(Message::text(message.to_string())).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{BlockEvent, PeerEvent};
+
+    #[test]
+    fn test_subscribe_and_unsubscribe() {
+        let mut subscriptions = Subscriptions::default();
+        let reply = subscriptions.handle_request(r#"{"id": 1, "method": "subscribe", "params": ["peer", "newblock"]}"#);
+        assert_eq!(reply["result"], json!(["newblock", "peer"]));
+        assert!(subscriptions.contains(Topic::Peer));
+
+        let reply = subscriptions.handle_request(r#"{"id": 2, "method": "subscribe", "params": ["blocks"]}"#);
+        assert_eq!(reply["error"]["code"], RPC_INVALID_PARAMETER);
+        assert!(!subscriptions.contains(Topic::NewForge));
+
+        let reply = subscriptions.handle_request(r#"{"id": 3, "method": "unsubscribe", "params": ["peer"]}"#);
+        assert_eq!(reply["result"], json!(["newblock"]));
+        assert_eq!(subscriptions.handle_request("not json")["error"]["code"], -32700);
+    }
+
+    #[test]
+    fn test_notifications() {
+        let block = NodeEvent::Block(BlockEvent {
+            height: 7,
+            hash: "ab".to_string(),
+            forges: 2,
+            timestamp: 1000,
+        });
+        let (topic, message) = node_event_notification(&block).unwrap();
+        assert_eq!(topic, Topic::NewBlock);
+        assert_eq!(message, json!({ "event": "newblock", "data": { "height": 7, "hash": "ab", "forges": 2, "timestamp": 1000 } }));
+
+        let peer = NodeEvent::Peer(PeerEvent { peer: "12D3".to_string(), connected: false });
+        assert_eq!(node_event_notification(&peer).unwrap().1["data"]["connected"], false);
+
+        let added = MempoolEvent { sequence: 4, proof_hash: [1; 32], kind: MempoolEventKind::Added };
+        let (topic, message) = mempool_event_notification(&added).unwrap();
+        assert_eq!(topic, Topic::NewForge);
+        assert_eq!(message["data"]["mempool_sequence"], 4);
+        let removed = MempoolEvent {
+            kind: MempoolEventKind::Removed { reason: crate::mempool::RemovalReason::Block },
+            ..added
+        };
+        assert!(mempool_event_notification(&removed).is_none());
+    }
+}
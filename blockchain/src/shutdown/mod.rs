@@ -0,0 +1,79 @@
+//! Node-wide shutdown coordination
+
+use tokio::sync::watch;
+
+/// Broadcasts a single shutdown request to every node component
+#[derive(Debug, Clone)]
+pub struct ShutdownCoordinator {
+    sender: watch::Sender<bool>,
+}
+
+/// Per-component handle that resolves once shutdown is requested
+#[derive(Debug, Clone)]
+pub struct ShutdownSignal {
+    receiver: watch::Receiver<bool>,
+}
+
+impl ShutdownCoordinator {
+    /// Create a new coordinator
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(false);
+        Self { sender }
+    }
+
+    /// Get a signal for a component to wait on
+    pub fn subscribe(&self) -> ShutdownSignal {
+        ShutdownSignal {
+            receiver: self.sender.subscribe(),
+        }
+    }
+
+    /// Request shutdown of all subscribed components
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Whether shutdown has been requested
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+}
+
+impl Default for ShutdownCoordinator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShutdownSignal {
+    /// Wait until shutdown is requested
+    pub async fn recv(&mut self) {
+        // An error means the coordinator was dropped, which also ends the node
+        let _ = self.receiver.wait_for(|triggered| *triggered).await;
+    }
+
+    /// Whether shutdown has been requested
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_signal_resolves_after_trigger() {
+        let coordinator = ShutdownCoordinator::new();
+        let mut signal = coordinator.subscribe();
+        assert!(!signal.is_triggered());
+
+        let waiter = tokio::spawn(async move { signal.recv().await });
+        coordinator.trigger();
+        waiter.await.unwrap();
+
+        assert!(coordinator.is_triggered());
+        // Late subscribers see the request immediately
+        coordinator.subscribe().recv().await;
+    }
+}
@@ -0,0 +1,411 @@
+//! Supervision of long-running node tasks
+//!
+//! Every long-running task of the full node (network loop, miner,
+//! janitors, indexers, RPC) is spawned through a `Supervisor`. When a task
+//! panics or returns an error the supervisor logs it with the task name,
+//! restarts it after an exponential backoff, and gives up once the task has
+//! failed `max_restarts` times in a row. Tasks that own state they can't
+//! rebuild, like the network loop and its swarm, run once through
+//! `spawn_once` and are given up on at their first failure. A critical task
+//! that cannot be revived flips the node to unhealthy and raises a critical
+//! alert.
+
+use crate::events::{Alert, AlertSeverity, EventBus};
+use crate::shutdown::ShutdownSignal;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::{AbortHandle, JoinError, JoinHandle};
+
+/// When and how often a failed task is restarted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Consecutive failures restarted before giving up (0 = never restart)
+    pub max_restarts: u32,
+    /// Delay before the first restart, doubled for each further one
+    pub initial_backoff: Duration,
+    /// Upper bound on the restart delay
+    pub max_backoff: Duration,
+    /// A run lasting this long resets the consecutive failure count
+    pub stable_after: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            stable_after: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Delay before restart number `attempt` (starting at 1)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+/// Lifecycle state of a supervised task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskState {
+    Running,
+    /// Waiting out the backoff before a restart
+    Restarting,
+    /// Exited cleanly or stopped for shutdown
+    Stopped,
+    /// Gave up after exhausting the restart policy
+    Failed,
+}
+
+/// Status of one supervised task (`getnodehealth`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TaskStatus {
+    pub name: String,
+    pub critical: bool,
+    pub state: TaskState,
+    /// Restarts over the task's lifetime
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    tasks: Mutex<BTreeMap<String, TaskStatus>>,
+    healthy: AtomicBool,
+    events: EventBus,
+}
+
+impl Inner {
+    fn update(&self, name: &str, f: impl FnOnce(&mut TaskStatus)) {
+        if let Some(status) = self.tasks.lock().unwrap().get_mut(name) {
+            f(status);
+        }
+    }
+}
+
+/// Spawns, monitors and restarts long-running tasks
+#[derive(Debug, Clone)]
+pub struct Supervisor {
+    inner: Arc<Inner>,
+    shutdown: ShutdownSignal,
+}
+
+impl Supervisor {
+    /// Create a supervisor that alerts on `events` and stops restarting
+    /// tasks once `shutdown` fires
+    pub fn new(shutdown: ShutdownSignal, events: EventBus) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                tasks: Mutex::new(BTreeMap::new()),
+                healthy: AtomicBool::new(true),
+                events,
+            }),
+            shutdown,
+        }
+    }
+
+    /// Whether every critical task is still running or restarting
+    pub fn is_healthy(&self) -> bool {
+        self.inner.healthy.load(Ordering::SeqCst)
+    }
+
+    /// Status of all supervised tasks, by name
+    pub fn tasks(&self) -> Vec<TaskStatus> {
+        self.inner.tasks.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Run the future built by `task` under supervision, rebuilding it after
+    /// each failure according to `policy`.
+    ///
+    /// A task that returns `Ok(())` is considered finished and is not
+    /// restarted. Names must be unique.
+    pub fn spawn<F, Fut>(&self, name: &str, critical: bool, policy: RestartPolicy, task: F) -> Result<JoinHandle<()>>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        {
+            let mut tasks = self.inner.tasks.lock().unwrap();
+            if tasks.contains_key(name) {
+                return Err(anyhow!("Task {} is already supervised", name));
+            }
+            tasks.insert(
+                name.to_string(),
+                TaskStatus {
+                    name: name.to_string(),
+                    critical,
+                    state: TaskState::Running,
+                    restarts: 0,
+                    last_error: None,
+                },
+            );
+        }
+
+        let inner = Arc::clone(&self.inner);
+        let mut shutdown = self.shutdown.clone();
+        let name = name.to_string();
+        Ok(tokio::spawn(async move {
+            let mut failures = 0u32;
+            loop {
+                let started = Instant::now();
+                let run = tokio::spawn(task());
+                // Aborting the supervisor's handle stops the running task too
+                let _abort = AbortOnDrop(run.abort_handle());
+                let error = match run.await {
+                    Ok(Ok(())) => {
+                        tracing::info!("Task {} finished", name);
+                        break;
+                    }
+                    Ok(Err(e)) => format!("{:#}", e),
+                    Err(e) => panic_message(e),
+                };
+                if shutdown.is_triggered() {
+                    tracing::warn!("Task {} failed during shutdown: {}", name, error);
+                    break;
+                }
+
+                if started.elapsed() >= policy.stable_after {
+                    failures = 0;
+                }
+                failures += 1;
+                tracing::error!("Task {} crashed (failure {}): {}", name, failures, error);
+                inner.update(&name, |status| status.last_error = Some(error.clone()));
+
+                if failures > policy.max_restarts {
+                    give_up(&inner, &name, &error);
+                    return;
+                }
+
+                inner.update(&name, |status| status.state = TaskState::Restarting);
+                let delay = policy.backoff(failures);
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = shutdown.recv() => break,
+                }
+                tracing::info!("Restarting task {} after {:?}", name, delay);
+                inner.update(&name, |status| {
+                    status.state = TaskState::Running;
+                    status.restarts += 1;
+                });
+            }
+            inner.update(&name, |status| status.state = TaskState::Stopped);
+        }))
+    }
+
+    /// Run `task` under supervision once. It can't be rebuilt, so a panic
+    /// or error gives up on it straight away.
+    pub fn spawn_once<Fut>(&self, name: &str, critical: bool, task: Fut) -> Result<JoinHandle<()>>
+    where
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let task = Mutex::new(Some(task));
+        let policy = RestartPolicy {
+            max_restarts: 0,
+            ..RestartPolicy::default()
+        };
+        self.spawn(name, critical, policy, move || {
+            let task = task.lock().unwrap().take();
+            async move {
+                match task {
+                    Some(task) => task.await,
+                    None => Err(anyhow!("Task cannot be restarted")),
+                }
+            }
+        })
+    }
+}
+
+/// Aborts a task when dropped
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+fn give_up(inner: &Inner, name: &str, error: &str) {
+    let mut critical = false;
+    inner.update(name, |status| {
+        status.state = TaskState::Failed;
+        critical = status.critical;
+    });
+
+    let severity = if critical {
+        inner.healthy.store(false, Ordering::SeqCst);
+        AlertSeverity::Critical
+    } else {
+        AlertSeverity::Warning
+    };
+    inner.events.alert(Alert::new(
+        severity,
+        "task_failed",
+        format!("Task {} could not be revived: {}", name, error),
+        json!({ "task": name, "critical": critical, "error": error }),
+    ));
+}
+
+/// Readable description of why a task's `JoinHandle` failed
+fn panic_message(error: JoinError) -> String {
+    if !error.is_panic() {
+        return error.to_string();
+    }
+    let payload = error.into_panic();
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string panic payload".to_string());
+    format!("panicked: {}", message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::NodeEvent;
+    use crate::shutdown::ShutdownCoordinator;
+    use std::sync::atomic::AtomicU32;
+
+    fn fast_policy(max_restarts: u32) -> RestartPolicy {
+        RestartPolicy {
+            max_restarts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+            stable_after: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_limit() {
+        let policy = RestartPolicy::default();
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(10), Duration::from_secs(60));
+        assert_eq!(policy.backoff(40), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_panicking_task_restarted() {
+        let coordinator = ShutdownCoordinator::new();
+        let supervisor = Supervisor::new(coordinator.subscribe(), EventBus::new());
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let counter = Arc::clone(&runs);
+        let handle = supervisor
+            .spawn("flaky", true, fast_policy(3), move || {
+                let counter = Arc::clone(&counter);
+                async move {
+                    if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                        panic!("boom");
+                    }
+                    Ok(())
+                }
+            })
+            .unwrap();
+        handle.await.unwrap();
+
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        let status = &supervisor.tasks()[0];
+        assert_eq!(status.state, TaskState::Stopped);
+        assert_eq!(status.restarts, 2);
+        assert_eq!(status.last_error.as_deref(), Some("panicked: boom"));
+        assert!(supervisor.is_healthy());
+        assert!(supervisor.spawn("flaky", false, fast_policy(0), || async { Ok(()) }).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_critical_failure_marks_unhealthy() {
+        let coordinator = ShutdownCoordinator::new();
+        let events = EventBus::new();
+        let mut alerts = events.subscribe();
+        let supervisor = Supervisor::new(coordinator.subscribe(), events);
+
+        supervisor
+            .spawn("janitor", false, fast_policy(0), || async { Err(anyhow!("disk full")) })
+            .unwrap()
+            .await
+            .unwrap();
+        assert!(supervisor.is_healthy());
+
+        supervisor
+            .spawn("network", true, fast_policy(1), || async { Err(anyhow!("socket closed")) })
+            .unwrap()
+            .await
+            .unwrap();
+        assert!(!supervisor.is_healthy());
+
+        let NodeEvent::Alert(alert) = alerts.try_recv().unwrap() else {
+            panic!("expected an alert");
+        };
+        assert_eq!(alert.severity, AlertSeverity::Warning);
+        let NodeEvent::Alert(alert) = alerts.try_recv().unwrap() else {
+            panic!("expected an alert");
+        };
+        assert_eq!(alert.severity, AlertSeverity::Critical);
+        assert_eq!(alert.data["task"], "network");
+
+        let network = supervisor.tasks().into_iter().find(|t| t.name == "network").unwrap();
+        assert_eq!(network.state, TaskState::Failed);
+        assert_eq!(network.restarts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_restarts() {
+        let coordinator = ShutdownCoordinator::new();
+        let supervisor = Supervisor::new(coordinator.subscribe(), EventBus::new());
+        let policy = RestartPolicy {
+            initial_backoff: Duration::from_secs(60),
+            ..fast_policy(5)
+        };
+
+        let handle = supervisor
+            .spawn("miner", true, policy, || async { Err(anyhow!("no work")) })
+            .unwrap();
+        while supervisor.tasks()[0].state != TaskState::Restarting {
+            tokio::task::yield_now().await;
+        }
+        coordinator.trigger();
+        handle.await.unwrap();
+
+        assert_eq!(supervisor.tasks()[0].state, TaskState::Stopped);
+        assert!(supervisor.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn test_spawn_once_gives_up_at_first_panic() {
+        let coordinator = ShutdownCoordinator::new();
+        let supervisor = Supervisor::new(coordinator.subscribe(), EventBus::new());
+
+        supervisor
+            .spawn_once("network", true, async { panic!("swarm lost") })
+            .unwrap()
+            .await
+            .unwrap();
+        let status = &supervisor.tasks()[0];
+        assert_eq!(status.state, TaskState::Failed);
+        assert_eq!(status.restarts, 0);
+        assert_eq!(status.last_error.as_deref(), Some("panicked: swarm lost"));
+        assert!(!supervisor.is_healthy());
+
+        // Aborting the handle stops the task it runs
+        let (_keep, mut closed) = tokio::sync::mpsc::channel::<()>(1);
+        let handle = supervisor
+            .spawn_once("rpc", true, async move {
+                let _keep = _keep;
+                std::future::pending::<Result<()>>().await
+            })
+            .unwrap();
+        handle.abort();
+        assert!(closed.recv().await.is_none());
+    }
+}
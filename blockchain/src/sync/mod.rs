@@ -0,0 +1,518 @@
+//! Headers-first chain synchronization
+//!
+//! A node that is behind asks each identified full peer for the headers
+//! following a `BlockLocator` of its own chain, which the peer answers from
+//! the fork point. Every header is checked against its parent with
+//! `ConsensusEngine::validate_header`, passed through the `HeaderGuard` and
+//! indexed; a full batch of `MAX_HEADERS_PER_REQUEST` means the peer has
+//! more, so the next batch is requested from the last header received.
+//! Bodies of indexed headers that aren't stored yet go on a
+//! `BodyFetchQueue`, which spreads requests over every peer that sent the
+//! header. Downloaded blocks are buffered and handed out in height order so
+//! the node can connect them to its tip and persist them.
+//!
+//! The same module answers peers' requests from the `ChainStore`,
+//! including the forge inclusion proofs light clients ask for (see
+//! `crate::light`).
+
+use crate::chain::{BlockLocator, ChainStore, HeaderGuard};
+use crate::codec::header_hash_preimage;
+use crate::consensus::{transfer_root, Block, BlockHeader, ConsensusEngine, MerkleTree};
+use crate::network::sync::{MAX_BLOCKS_PER_REQUEST, MAX_HEADERS_PER_REQUEST};
+use crate::network::{BodyFetchQueue, ForgeProof, HeaderAnnouncement, SyncPolicy, SyncRequest, SyncResponse};
+use anyhow::{anyhow, Context, Result};
+use libp2p::PeerId;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a header request may go unanswered before it is sent again
+pub const HEADER_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Downloaded blocks buffered ahead of the tip before body requests pause
+pub const MAX_BUFFERED_BLOCKS: usize = 1024;
+
+/// Block locator of the node's main chain (see `BlockLocator`). A node
+/// started from a snapshot without blocks above it sends just the snapshot
+/// tip.
+pub fn locator(store: &ChainStore, engine: &ConsensusEngine) -> Result<Vec<[u8; 32]>> {
+    if store.get_best_block()?.is_none() {
+        return Ok(engine
+            .snapshot_status()
+            .map(|_| vec![engine.get_tip_hash()])
+            .unwrap_or_default());
+    }
+    Ok(BlockLocator::from_store(store)?.into_hashes())
+}
+
+/// Answer a peer's sync request from the store
+pub fn serve(store: &ChainStore, request: &SyncRequest) -> Result<SyncResponse> {
+    match request {
+        SyncRequest::GetHeaders { locator, max } => {
+            // Headers follow the fork point, or start at genesis if the
+            // locator shares nothing with our main chain
+            let start = BlockLocator::new(locator.clone())
+                .find_fork_point(store)?
+                .map_or(0, |(height, _)| height + 1);
+            let max = (*max as usize).min(MAX_HEADERS_PER_REQUEST);
+            let mut headers = Vec::new();
+            if store.get_best_block()?.is_some() {
+                let tip = store.get_height()?;
+                let mut height = start;
+                while height <= tip && headers.len() < max {
+                    let Some(block) = store.load_block(height)? else { break };
+                    headers.push(block.header);
+                    height += 1;
+                }
+            }
+            Ok(SyncResponse::Headers { headers })
+        }
+        SyncRequest::GetBlocks { hashes } => {
+            let mut blocks = Vec::new();
+            for hash in hashes.iter().take(MAX_BLOCKS_PER_REQUEST) {
+                if let Some(height) = store.get_block_height_by_hash(hash)? {
+                    if let Some(block) = store.load_block(height)? {
+                        blocks.push(block);
+                    }
+                }
+            }
+            Ok(SyncResponse::Blocks { blocks })
+        }
+        SyncRequest::GetForgeProof { proof_hash } => Ok(SyncResponse::ForgeProof {
+            proof: forge_proof(store, proof_hash)?.map(Box::new),
+        }),
+    }
+}
+
+/// Inclusion proof of the main-chain forge with `proof_hash`. `None` if
+/// there is no such forge or the store keeps no forge index.
+pub fn forge_proof(store: &ChainStore, proof_hash: &[u8; 32]) -> Result<Option<ForgeProof>> {
+    if !store.forge_index_enabled()? {
+        return Ok(None);
+    }
+    let Some((height, _)) = store.lookup_forge(proof_hash)? else {
+        return Ok(None);
+    };
+    let Some(block) = store.load_block(height)? else {
+        return Ok(None);
+    };
+    let Some(index) = block.forges.iter().position(|forge| forge.proof_hash == *proof_hash) else {
+        return Ok(None);
+    };
+    let proof = MerkleTree::build(&block.forges).prove(index).expect("index is within the block");
+    Ok(Some(ForgeProof {
+        block_hash: Sha256::digest(header_hash_preimage(&block.header)).into(),
+        height,
+        forge: block.forges[index].clone(),
+        proof,
+        transfer_root: transfer_root(&block.transfers),
+    }))
+}
+
+/// A downloaded block waiting for its parent to be connected
+#[derive(Debug)]
+struct Downloaded {
+    hash: [u8; 32],
+    source: PeerId,
+    block: Block,
+}
+
+/// A header or downloaded block waiting for its parent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Orphan {
+    pub hash: [u8; 32],
+    pub height: u64,
+    pub prev_block_hash: [u8; 32],
+    /// Peer it came from
+    pub peer: PeerId,
+    /// Whether the body is downloaded, rather than only the header held
+    pub downloaded: bool,
+}
+
+/// Header and block download state
+pub struct ChainSync {
+    bodies: Arc<Mutex<BodyFetchQueue>>,
+    guard: HeaderGuard,
+    /// Outstanding header requests, by peer
+    header_requests: HashMap<PeerId, Instant>,
+    /// Last header each peer sent, where its next batch continues
+    peer_tips: HashMap<PeerId, [u8; 32]>,
+    /// Downloaded blocks by height
+    downloaded: BTreeMap<u64, Downloaded>,
+}
+
+impl ChainSync {
+    /// Create sync state with the given body download policy and header guard
+    pub fn new(policy: SyncPolicy, guard: HeaderGuard) -> Self {
+        Self {
+            bodies: Arc::new(Mutex::new(BodyFetchQueue::with_policy(policy))),
+            guard,
+            header_requests: HashMap::new(),
+            peer_tips: HashMap::new(),
+            downloaded: BTreeMap::new(),
+        }
+    }
+
+    /// The body download queue, shared with `getsyncstatus`
+    pub fn body_queue(&self) -> Arc<Mutex<BodyFetchQueue>> {
+        Arc::clone(&self.bodies)
+    }
+
+    /// Header request for `peer`, unless one is already outstanding
+    pub fn request_headers(
+        &mut self,
+        store: &ChainStore,
+        engine: &ConsensusEngine,
+        peer: PeerId,
+        now: Instant,
+    ) -> Result<Option<SyncRequest>> {
+        if let Some(sent) = self.header_requests.get(&peer) {
+            if now.saturating_duration_since(*sent) < HEADER_REQUEST_TIMEOUT {
+                return Ok(None);
+            }
+        }
+        let mut locator = locator(store, engine)?;
+        if let Some(tip) = self.peer_tips.get(&peer) {
+            if !locator.contains(tip) {
+                locator.insert(0, *tip);
+            }
+        }
+        self.header_requests.insert(peer, now);
+        Ok(Some(SyncRequest::GetHeaders {
+            locator,
+            max: MAX_HEADERS_PER_REQUEST as u32,
+        }))
+    }
+
+    /// Validate and index headers sent by `peer`, queueing the bodies the
+    /// node doesn't have. Returns how many bodies were newly queued.
+    ///
+    /// Errors mean the peer sent headers that don't connect, don't link up
+    /// or fail validation, and should be disconnected.
+    pub fn headers_received(
+        &mut self,
+        store: &ChainStore,
+        engine: &ConsensusEngine,
+        peer: PeerId,
+        headers: &[BlockHeader],
+    ) -> Result<usize> {
+        self.header_requests.remove(&peer);
+        let Some(first) = headers.first() else {
+            return Ok(0);
+        };
+        let (mut parent_hash, mut height) = if first.height == 0 {
+            ([0u8; 32], 0)
+        } else {
+            let parent = store
+                .get_header_index(&first.prev_block_hash)?
+                .ok_or_else(|| anyhow!("Headers from {} do not connect to a known header", peer))?;
+            (parent.hash, parent.height + 1)
+        };
+
+        // Check the whole batch before indexing any of it
+        let mut hashes = Vec::with_capacity(headers.len());
+        for header in headers {
+            engine
+                .validate_header(header, &parent_hash, height)
+                .with_context(|| format!("Invalid header at height {}", height))?;
+            parent_hash = engine.compute_block_hash(header);
+            hashes.push(parent_hash);
+            height += 1;
+        }
+
+        let mut queued = 0;
+        for (header, hash) in headers.iter().zip(hashes) {
+            self.guard.accept(store, peer, &hash, header)?;
+            let downloaded = self.downloaded.get(&header.height).is_some_and(|block| block.hash == hash);
+            if !downloaded && store.get_block_height_by_hash(&hash)?.is_none() {
+                let announcement = HeaderAnnouncement {
+                    hash,
+                    header: header.clone(),
+                    fee_total: 0,
+                };
+                if self.bodies.lock().unwrap().announce(peer, &announcement) {
+                    queued += 1;
+                }
+            }
+        }
+        self.peer_tips.insert(peer, parent_hash);
+        Ok(queued)
+    }
+
+    /// Body requests to send now, batched per peer
+    pub fn block_requests(&mut self, now: Instant) -> Vec<(PeerId, SyncRequest)> {
+        let mut bodies = self.bodies.lock().unwrap();
+        let expired = bodies.expire(now);
+        if expired > 0 {
+            tracing::debug!("Retrying {} stalled block requests", expired);
+        }
+        if self.downloaded.len() >= MAX_BUFFERED_BLOCKS {
+            return Vec::new();
+        }
+
+        let mut batches: HashMap<PeerId, Vec<[u8; 32]>> = HashMap::new();
+        while let Some((hash, peer)) = bodies.next_request(now) {
+            batches.entry(peer).or_default().push(hash);
+        }
+        batches
+            .into_iter()
+            .flat_map(|(peer, hashes)| {
+                hashes
+                    .chunks(MAX_BLOCKS_PER_REQUEST)
+                    .map(|chunk| (peer, SyncRequest::GetBlocks { hashes: chunk.to_vec() }))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Buffer a requested block from `peer`. Returns `false` for blocks
+    /// that weren't asked for.
+    pub fn block_received(&mut self, engine: &ConsensusEngine, peer: PeerId, block: Block) -> bool {
+        let hash = engine.compute_block_hash(&block.header);
+        let mut bodies = self.bodies.lock().unwrap();
+        if !bodies.is_wanted(&hash) {
+            return false;
+        }
+        bodies.complete(&hash, 0);
+        self.downloaded.insert(
+            block.header.height,
+            Downloaded {
+                hash,
+                source: peer,
+                block,
+            },
+        );
+        true
+    }
+
+    /// Take the downloaded blocks that continue the chain from
+    /// `next_height`, in order, with the peer each came from. Blocks below
+    /// `next_height` are dropped.
+    pub fn connectable(&mut self, next_height: u64) -> Vec<(PeerId, Block)> {
+        self.downloaded = self.downloaded.split_off(&next_height);
+        let mut ready = Vec::new();
+        let mut height = next_height;
+        while let Some(downloaded) = self.downloaded.remove(&height) {
+            ready.push((downloaded.source, downloaded.block));
+            height += 1;
+        }
+        ready
+    }
+
+    /// Take the downloaded blocks forking off the main chain below
+    /// `next_height` once no body downloads are outstanding: the run from
+    /// the lowest downloaded block, if its parent is stored, in order with
+    /// the peer each came from. The node switches to it if it has more work.
+    pub fn take_branch(&mut self, store: &ChainStore, next_height: u64) -> Result<Vec<(PeerId, Block)>> {
+        let Some(first) = self.downloaded.values().next() else {
+            return Ok(Vec::new());
+        };
+        let bodies = self.bodies.lock().unwrap();
+        if first.block.header.height >= next_height || bodies.pending_len() > 0 || bodies.in_flight_len() > 0 {
+            return Ok(Vec::new());
+        }
+        drop(bodies);
+        let mut parent_hash = first.block.header.prev_block_hash;
+        if store.get_block_height_by_hash(&parent_hash)?.is_none() {
+            return Ok(Vec::new());
+        }
+
+        let mut branch = Vec::new();
+        let mut height = first.block.header.height;
+        while self
+            .downloaded
+            .get(&height)
+            .is_some_and(|downloaded| downloaded.block.header.prev_block_hash == parent_hash)
+        {
+            let downloaded = self.downloaded.remove(&height).expect("checked above");
+            parent_hash = downloaded.hash;
+            branch.push((downloaded.source, downloaded.block));
+            height += 1;
+        }
+        Ok(branch)
+    }
+
+    /// Headers held for an unknown parent and downloaded blocks waiting
+    /// for the tip to reach them, lowest height first
+    pub fn orphans(&self) -> Vec<Orphan> {
+        let held = self.guard.unconnected_headers().into_iter().map(|(peer, hash, header)| Orphan {
+            hash,
+            height: header.height,
+            prev_block_hash: header.prev_block_hash,
+            peer,
+            downloaded: false,
+        });
+        let downloaded = self.downloaded.values().map(|downloaded| Orphan {
+            hash: downloaded.hash,
+            height: downloaded.block.header.height,
+            prev_block_hash: downloaded.block.header.prev_block_hash,
+            peer: downloaded.source,
+            downloaded: true,
+        });
+        let mut orphans: Vec<Orphan> = held.chain(downloaded).collect();
+        orphans.sort_by_key(|orphan| (orphan.height, orphan.hash));
+        orphans
+    }
+
+    /// Forget a peer that disconnected or failed a request; its outstanding
+    /// body requests go to other peers
+    pub fn peer_failed(&mut self, peer: &PeerId) {
+        self.header_requests.remove(peer);
+        self.peer_tips.remove(peer);
+        self.bodies.lock().unwrap().remove_peer(peer);
+    }
+
+    /// Whether headers or blocks are still being downloaded
+    pub fn is_syncing(&self) -> bool {
+        let bodies = self.bodies.lock().unwrap();
+        !self.header_requests.is_empty()
+            || bodies.pending_len() > 0
+            || bodies.in_flight_len() > 0
+            || !self.downloaded.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::MemoryStore;
+    use crate::consensus::testing;
+
+    fn headers(engine: &ConsensusEngine, count: u64) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = Vec::new();
+        for height in 0..count {
+            let prev_block_hash = headers
+                .last()
+                .map_or([0u8; 32], |parent| engine.compute_block_hash(parent));
+            let mut header = testing::header(height, prev_block_hash);
+            assert!(engine.grind_header(&mut header, 1_000_000));
+            headers.push(header);
+        }
+        headers
+    }
+
+    fn store() -> ChainStore {
+        ChainStore::with_backend(Box::new(MemoryStore::new())).unwrap()
+    }
+
+    #[test]
+    fn test_headers_validated_then_blocks_released_in_order() {
+        let engine = ConsensusEngine::new(0, 600);
+        let store = store();
+        let mut sync = ChainSync::new(SyncPolicy::default(), HeaderGuard::default());
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+        let chain = headers(&engine, 4);
+
+        // A fresh node asks from genesis, once per peer at a time
+        match sync.request_headers(&store, &engine, a, now).unwrap() {
+            Some(SyncRequest::GetHeaders { locator, .. }) => assert!(locator.is_empty()),
+            other => panic!("unexpected request {:?}", other),
+        }
+        assert!(sync.request_headers(&store, &engine, a, now).unwrap().is_none());
+
+        // Broken linkage and bad work are refused without indexing anything
+        let mut unlinked = chain.clone();
+        unlinked.remove(1);
+        assert!(sync.headers_received(&store, &engine, a, &unlinked).is_err());
+        let mut unworked = chain.clone();
+        unworked[0].nonce = unworked[0].nonce.wrapping_add(1);
+        while engine.check_header_pow(&unworked[0]).is_ok() {
+            unworked[0].nonce = unworked[0].nonce.wrapping_add(1);
+        }
+        assert!(sync.headers_received(&store, &engine, a, &unworked[..1]).is_err());
+        assert!(store.get_header_index(&engine.compute_block_hash(&chain[0])).unwrap().is_none());
+
+        assert_eq!(sync.headers_received(&store, &engine, a, &chain).unwrap(), 4);
+        assert_eq!(sync.headers_received(&store, &engine, b, &chain).unwrap(), 0);
+        let tip = engine.compute_block_hash(&chain[3]);
+        assert_eq!(store.get_header_index(&tip).unwrap().unwrap().height, 3);
+
+        // Bodies are spread over both peers
+        let requests = sync.block_requests(now);
+        let requested: usize = requests
+            .iter()
+            .map(|(_, request)| match request {
+                SyncRequest::GetBlocks { hashes } => hashes.len(),
+                other => panic!("unexpected request {:?}", other),
+            })
+            .sum();
+        assert_eq!((requests.len(), requested), (2, 4));
+        assert!(sync.is_syncing());
+
+        // Out-of-order arrivals wait for the gap to fill; unrequested
+        // blocks are ignored
+        let block = |height: usize| Block {
+            header: chain[height].clone(),
+            forges: vec![],
+            transfers: Vec::new(),
+            authority_signatures: Vec::new(),
+        };
+        assert!(sync.block_received(&engine, a, block(2)));
+        assert!(sync.block_received(&engine, b, block(1)));
+        assert!(!sync.block_received(&engine, b, block(1)));
+        assert!(sync.connectable(0).is_empty());
+        let orphans: Vec<(u64, bool)> = sync.orphans().iter().map(|orphan| (orphan.height, orphan.downloaded)).collect();
+        assert_eq!(orphans, vec![(1, true), (2, true)]);
+        assert!(sync.block_received(&engine, a, block(0)));
+        let ready: Vec<u64> = sync.connectable(0).iter().map(|(_, block)| block.header.height).collect();
+        assert_eq!(ready, vec![0, 1, 2]);
+
+        // With both peers gone there is nothing left to download
+        sync.peer_failed(&a);
+        sync.peer_failed(&b);
+        assert!(!sync.is_syncing());
+    }
+
+    #[test]
+    fn test_serve_headers_after_locator_and_blocks_by_hash() {
+        let engine = ConsensusEngine::new(0, 600);
+        let store = store();
+        let chain = headers(&engine, 5);
+        for header in &chain {
+            let block = Block {
+                header: header.clone(),
+                forges: vec![],
+                transfers: Vec::new(),
+                authority_signatures: Vec::new(),
+            };
+            let hash = engine.compute_block_hash(header);
+            store.put_block(header.height, &block.encode()).unwrap();
+            store.put_block_hash(&hash, header.height).unwrap();
+            store.set_height(header.height).unwrap();
+            store.set_best_block(&hash).unwrap();
+        }
+
+        let locator = locator(&store, &engine).unwrap();
+        assert_eq!(locator.len(), 5);
+        assert_eq!(locator[0], engine.compute_block_hash(&chain[4]));
+
+        // Unknown locator hashes are skipped; headers follow the first known one
+        let request = SyncRequest::GetHeaders {
+            locator: vec![[9; 32], engine.compute_block_hash(&chain[1])],
+            max: 2,
+        };
+        match serve(&store, &request).unwrap() {
+            SyncResponse::Headers { headers } => {
+                assert_eq!(headers.iter().map(|h| h.height).collect::<Vec<_>>(), vec![2, 3]);
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+
+        let request = SyncRequest::GetBlocks {
+            hashes: vec![engine.compute_block_hash(&chain[3]), [9; 32]],
+        };
+        match serve(&store, &request).unwrap() {
+            SyncResponse::Blocks { blocks } => {
+                assert_eq!(blocks.len(), 1);
+                assert_eq!(blocks[0].header.height, 3);
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+
+        // Forge proofs need the forge index
+        let request = SyncRequest::GetForgeProof { proof_hash: [9; 32] };
+        assert!(matches!(serve(&store, &request).unwrap(), SyncResponse::ForgeProof { proof: None }));
+    }
+}
@@ -0,0 +1,268 @@
+//! Encrypted wallet backups
+//!
+//! A backup is the wallet's address book, forge history and external signer
+//! configuration (the wallet itself holds no private keys) as JSON,
+//! encrypted with ChaCha20-Poly1305 under a PBKDF2-HMAC-SHA256 key derived
+//! from a passphrase. The file layout is
+//!
+//! ```text
+//! magic (8) | format version (u16) | kdf iterations (u32) | salt (16) | nonce (12)
+//! | ciphertext | sha256 checksum (32)
+//! ```
+//!
+//! with integers big-endian and the header authenticated as associated
+//! data. The checksum catches a truncated or corrupted file before the
+//! passphrase is blamed. Payload fields added in later versions are
+//! optional, so older backups restore into newer wallets.
+
+use super::{ExternalSigner, Wallet, WalletFile, WALLET_FILE_VERSION};
+use anyhow::{anyhow, Context, Result};
+use bitcoin::Network;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use pbkdf2::pbkdf2_hmac;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Current backup file format version
+pub const BACKUP_FORMAT_VERSION: u16 = 1;
+
+/// PBKDF2 iterations used for new backups
+pub const BACKUP_KDF_ITERATIONS: u32 = 600_000;
+
+const BACKUP_MAGIC: &[u8; 8] = b"EXSWBAK\0";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = BACKUP_MAGIC.len() + 2 + 4 + SALT_LEN + NONCE_LEN;
+const CHECKSUM_LEN: usize = 32;
+
+/// Decrypted backup contents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupPayload {
+    /// Network name (bitcoin, testnet, regtest, ...)
+    pub network: String,
+    /// Unix time the backup was taken
+    #[serde(default)]
+    pub created_at: u64,
+    /// Signer holding the wallet's keys
+    #[serde(default)]
+    pub signer: Option<ExternalSigner>,
+    pub wallet: WalletFile,
+}
+
+/// What a restore brought back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RestoreSummary {
+    /// Wallet file version the backup was taken from
+    pub wallet_version: u32,
+    pub addresses: usize,
+    pub forges: usize,
+    pub signer: bool,
+    pub created_at: u64,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
+/// Encrypt a backup payload
+pub fn encrypt_backup(payload: &BackupPayload, passphrase: &str, iterations: u32) -> Result<Vec<u8>> {
+    if passphrase.is_empty() {
+        return Err(anyhow!("Backup passphrase must not be empty"));
+    }
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let mut file = Vec::with_capacity(HEADER_LEN);
+    file.extend_from_slice(BACKUP_MAGIC);
+    file.extend_from_slice(&BACKUP_FORMAT_VERSION.to_be_bytes());
+    file.extend_from_slice(&iterations.to_be_bytes());
+    file.extend_from_slice(&salt);
+    file.extend_from_slice(&nonce);
+
+    let key = derive_key(passphrase, &salt, iterations);
+    let plaintext = serde_json::to_vec(payload)?;
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: &plaintext, aad: &file })
+        .map_err(|_| anyhow!("Failed to encrypt backup"))?;
+    file.extend_from_slice(&ciphertext);
+
+    let checksum = Sha256::digest(&file);
+    file.extend_from_slice(&checksum);
+    Ok(file)
+}
+
+/// Check and decrypt a backup file
+pub fn decrypt_backup(file: &[u8], passphrase: &str) -> Result<BackupPayload> {
+    if file.len() < HEADER_LEN + CHECKSUM_LEN || !file.starts_with(BACKUP_MAGIC) {
+        return Err(anyhow!("Not a wallet backup"));
+    }
+    let (body, checksum) = file.split_at(file.len() - CHECKSUM_LEN);
+    if Sha256::digest(body).as_slice() != checksum {
+        return Err(anyhow!("Wallet backup is corrupted (checksum mismatch)"));
+    }
+
+    let (header, ciphertext) = body.split_at(HEADER_LEN);
+    let mut offset = BACKUP_MAGIC.len();
+    let version = u16::from_be_bytes(header[offset..offset + 2].try_into().expect("2-byte slice"));
+    offset += 2;
+    if version > BACKUP_FORMAT_VERSION {
+        return Err(anyhow!(
+            "Backup format version {} is newer than supported version {}",
+            version,
+            BACKUP_FORMAT_VERSION
+        ));
+    }
+    let iterations = u32::from_be_bytes(header[offset..offset + 4].try_into().expect("4-byte slice"));
+    offset += 4;
+    let salt = &header[offset..offset + SALT_LEN];
+    let nonce = &header[offset + SALT_LEN..];
+
+    let key = derive_key(passphrase, salt, iterations);
+    let plaintext = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+        .map_err(|_| anyhow!("Wrong passphrase for wallet backup"))?;
+    let payload: BackupPayload = serde_json::from_slice(&plaintext).context("Invalid wallet backup payload")?;
+    if payload.wallet.version > WALLET_FILE_VERSION {
+        return Err(anyhow!(
+            "Backup holds wallet version {}, newer than supported version {}",
+            payload.wallet.version,
+            WALLET_FILE_VERSION
+        ));
+    }
+    Ok(payload)
+}
+
+impl Wallet {
+    /// Write an encrypted backup of the wallet to `path`
+    pub fn backup<P: AsRef<Path>>(&self, path: P, passphrase: &str) -> Result<()> {
+        let path = path.as_ref();
+        let payload = BackupPayload {
+            network: self.network.to_string(),
+            created_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0),
+            signer: self.signer.clone(),
+            wallet: self.data.clone(),
+        };
+        let file = encrypt_backup(&payload, passphrase, BACKUP_KDF_ITERATIONS)?;
+
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, file)
+            .with_context(|| format!("Failed to write wallet backup {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Replace the wallet's contents with an encrypted backup. The signer is
+    /// only replaced if the backup has one.
+    pub fn restore_backup<P: AsRef<Path>>(&mut self, path: P, passphrase: &str) -> Result<RestoreSummary> {
+        let path = path.as_ref();
+        let file = std::fs::read(path).with_context(|| format!("Failed to read wallet backup {}", path.display()))?;
+        let payload = decrypt_backup(&file, passphrase)?;
+        self.restore_payload(payload)
+    }
+
+    fn restore_payload(&mut self, payload: BackupPayload) -> Result<RestoreSummary> {
+        let network = Network::from_str(&payload.network)
+            .map_err(|_| anyhow!("Unknown backup network {}", payload.network))?;
+        if network != self.network {
+            return Err(anyhow!("Backup is for {}, wallet is on {}", network, self.network));
+        }
+
+        let summary = RestoreSummary {
+            wallet_version: payload.wallet.version,
+            addresses: payload.wallet.addresses.len(),
+            forges: payload.wallet.forges.len(),
+            signer: payload.signer.is_some(),
+            created_at: payload.created_at,
+        };
+        self.data = WalletFile {
+            version: WALLET_FILE_VERSION,
+            ..payload.wallet
+        };
+        if payload.signer.is_some() {
+            self.signer = payload.signer;
+        }
+        self.save()?;
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::{AddressEntry, AddressPurpose, ForgeRecord};
+
+    fn payload() -> BackupPayload {
+        let mut wallet = WalletFile::default();
+        wallet.addresses.insert(
+            "bcrt1qexample".to_string(),
+            AddressEntry {
+                label: "cold".to_string(),
+                purpose: AddressPurpose::Watch,
+            },
+        );
+        wallet.forges.push(ForgeRecord {
+            proof_hash: [7; 32],
+            taproot_address: "bcrt1pexample".to_string(),
+            timestamp: 1000,
+            height: Some(3),
+        });
+        BackupPayload {
+            network: Network::Regtest.to_string(),
+            created_at: 1234,
+            signer: Some(ExternalSigner::new("/usr/bin/signer", vec!["--device".to_string()])),
+            wallet,
+        }
+    }
+
+    #[test]
+    fn test_backup_round_trip_and_tamper_detection() {
+        let file = encrypt_backup(&payload(), "hunter2", 1000).unwrap();
+        let restored = decrypt_backup(&file, "hunter2").unwrap();
+        assert_eq!(restored.wallet.addresses, payload().wallet.addresses);
+        assert_eq!(restored.wallet.forges, payload().wallet.forges);
+        assert_eq!(restored.signer, payload().signer);
+
+        assert!(decrypt_backup(&file, "wrong").unwrap_err().to_string().contains("passphrase"));
+        let mut corrupted = file.clone();
+        corrupted[HEADER_LEN] ^= 1;
+        assert!(decrypt_backup(&corrupted, "hunter2").unwrap_err().to_string().contains("checksum"));
+        assert!(decrypt_backup(&file[..20], "hunter2").is_err());
+        assert!(encrypt_backup(&payload(), "", 1000).is_err());
+
+        // Newer formats are refused rather than misread
+        let mut newer = file[..file.len() - CHECKSUM_LEN].to_vec();
+        newer[BACKUP_MAGIC.len()..BACKUP_MAGIC.len() + 2].copy_from_slice(&(BACKUP_FORMAT_VERSION + 1).to_be_bytes());
+        newer.extend_from_slice(&Sha256::digest(&newer));
+        assert!(decrypt_backup(&newer, "hunter2").unwrap_err().to_string().contains("newer"));
+    }
+
+    #[test]
+    fn test_restore_old_payload_into_wallet() {
+        // A minimal payload, as written before optional fields existed
+        let old = serde_json::json!({
+            "network": "regtest",
+            "wallet": { "version": 0, "addresses": { "bcrt1qexample": { "label": "cold", "purpose": "watch" } } },
+        });
+        let payload: BackupPayload = serde_json::from_value(old).unwrap();
+
+        let mut wallet = Wallet::new(Network::Regtest);
+        let summary = wallet.restore_payload(payload.clone()).unwrap();
+        assert_eq!((summary.wallet_version, summary.addresses, summary.forges), (0, 1, 0));
+        assert!(!summary.signer);
+        assert_eq!(wallet.label("bcrt1qexample"), Some("cold"));
+        assert_eq!(wallet.data.version, WALLET_FILE_VERSION);
+
+        assert!(Wallet::new(Network::Bitcoin).restore_payload(payload).is_err());
+    }
+}
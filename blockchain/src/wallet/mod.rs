@@ -0,0 +1,688 @@
+//! Wallet for constructing forge transactions
+
+use crate::consensus::sighash::{Transfer, TransferInput, TransferOutput};
+use crate::consensus::{meets_difficulty, ForgeTransaction};
+use crate::ledger::{check_transfer_outputs, is_dust, Ledger, LedgerOutput, OutPoint};
+use crate::crypto::{
+    forge_proof_hash, forge_tempering_salt, p2tr_address_for_key, proof_of_forge_with_tempering, ProofOfForgeResult,
+    TemperingAlgorithm,
+};
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::{Address, Network};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use anyhow::{anyhow, Context, Result};
+
+mod backup;
+mod provenance;
+mod signer;
+mod vault;
+
+pub use backup::{decrypt_backup, encrypt_backup, BackupPayload, RestoreSummary, BACKUP_FORMAT_VERSION, BACKUP_KDF_ITERATIONS};
+pub use provenance::{
+    prophecy_commitment, KeyOrigin, ProvenanceStatement, FORGE_DERIVATION_PATH, FORGE_DERIVATION_VERSION,
+};
+pub use signer::{ExternalSigner, SigningPayload, SigningRequest, SigningResponse, SIGNER_PROTOCOL_VERSION};
+pub use vault::{delayed_leaf, recovery_leaf, vault_spend_info, Vault, VaultAlert, VaultState, DEFAULT_VAULT_DELAY};
+
+/// Current wallet file format version
+pub const WALLET_FILE_VERSION: u32 = 1;
+
+/// Why an address is in the address book
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressPurpose {
+    /// Address derived by this wallet
+    Receive,
+    /// Third-party address watched for bookkeeping
+    Watch,
+}
+
+/// Address book entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressEntry {
+    pub label: String,
+    pub purpose: AddressPurpose,
+}
+
+/// Forge built by this wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForgeRecord {
+    pub proof_hash: [u8; 32],
+    pub taproot_address: String,
+    pub timestamp: u64,
+    /// Height of the block the forge was mined in, once confirmed
+    pub height: Option<u64>,
+}
+
+/// Persistent wallet contents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletFile {
+    pub version: u32,
+    #[serde(default)]
+    pub addresses: BTreeMap<String, AddressEntry>,
+    #[serde(default)]
+    pub forges: Vec<ForgeRecord>,
+    /// Outputs excluded from automatic coin selection
+    #[serde(default)]
+    pub locked_outputs: BTreeSet<OutPoint>,
+    /// Time-locked vaults
+    #[serde(default)]
+    pub vaults: Vec<Vault>,
+    /// Origin of each key derived from a prophecy, by address
+    #[serde(default)]
+    pub key_origins: BTreeMap<String, KeyOrigin>,
+}
+
+impl Default for WalletFile {
+    fn default() -> Self {
+        Self {
+            version: WALLET_FILE_VERSION,
+            addresses: BTreeMap::new(),
+            forges: Vec::new(),
+            locked_outputs: BTreeSet::new(),
+            vaults: Vec::new(),
+            key_origins: BTreeMap::new(),
+        }
+    }
+}
+
+/// Per-forge construction options
+#[derive(Debug, Clone, Default)]
+pub struct ForgeOptions {
+    /// Explicit lock height, overriding the anti-fee-sniping default
+    pub not_before_height: Option<u64>,
+    /// Forge salt to temper with, random by default
+    pub salt: Option<[u8; 32]>,
+    /// Tempering algorithm, PBKDF2 by default
+    pub tempering: TemperingAlgorithm,
+}
+
+/// Forge-constructing wallet
+pub struct Wallet {
+    network: Network,
+    /// Lock new forges to the current tip by default
+    anti_fee_sniping: bool,
+    /// Wallet file, if persisted
+    path: Option<PathBuf>,
+    data: WalletFile,
+    /// External signer holding the wallet's keys
+    signer: Option<ExternalSigner>,
+}
+
+impl Wallet {
+    /// Create a new wallet
+    pub fn new(network: Network) -> Self {
+        Self {
+            network,
+            anti_fee_sniping: true,
+            path: None,
+            data: WalletFile::default(),
+            signer: None,
+        }
+    }
+
+    /// Open a wallet file, creating an empty wallet if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P, network: Network) -> Result<Self> {
+        let path = path.as_ref();
+        let data = if path.exists() {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read wallet file {}", path.display()))?;
+            let data: WalletFile = serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid wallet file {}", path.display()))?;
+            if data.version > WALLET_FILE_VERSION {
+                return Err(anyhow!(
+                    "Wallet file version {} is newer than supported version {}",
+                    data.version,
+                    WALLET_FILE_VERSION
+                ));
+            }
+            data
+        } else {
+            WalletFile::default()
+        };
+
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            data,
+            ..Self::new(network)
+        })
+    }
+
+    /// Write the wallet file, if this wallet is persisted
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        // Write to a temporary file first so a crash can't truncate the wallet
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(&self.data)?)
+            .with_context(|| format!("Failed to write wallet file {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Set the label of an address, adding it as a watched address if it
+    /// isn't already in the address book
+    pub fn set_label(&mut self, address: &str, label: &str) -> Result<()> {
+        Address::from_str(address)
+            .map_err(|e| anyhow!("Invalid address {}: {}", address, e))?
+            .require_network(self.network)
+            .map_err(|e| anyhow!("Invalid address {}: {}", address, e))?;
+
+        self.data
+            .addresses
+            .entry(address.to_string())
+            .and_modify(|entry| entry.label = label.to_string())
+            .or_insert_with(|| AddressEntry {
+                label: label.to_string(),
+                purpose: AddressPurpose::Watch,
+            });
+        self.save()
+    }
+
+    /// Label of an address, if it is in the address book
+    pub fn label(&self, address: &str) -> Option<&str> {
+        self.data.addresses.get(address).map(|entry| entry.label.as_str())
+    }
+
+    /// Address book entry for an address
+    pub fn address_entry(&self, address: &str) -> Option<&AddressEntry> {
+        self.data.addresses.get(address)
+    }
+
+    /// Address book entries with the given label
+    pub fn addresses_by_label(&self, label: &str) -> Vec<(&str, &AddressEntry)> {
+        self.data
+            .addresses
+            .iter()
+            .filter(|(_, entry)| entry.label == label)
+            .map(|(address, entry)| (address.as_str(), entry))
+            .collect()
+    }
+
+    /// Record a forge built by this wallet and add its address to the
+    /// address book
+    pub fn record_forge(&mut self, forge: &ForgeTransaction) -> Result<()> {
+        self.data
+            .addresses
+            .entry(forge.taproot_address.clone())
+            .or_insert_with(|| AddressEntry {
+                label: String::new(),
+                purpose: AddressPurpose::Receive,
+            });
+        if !self.data.forges.iter().any(|record| record.proof_hash == forge.proof_hash) {
+            self.data.forges.push(ForgeRecord {
+                proof_hash: forge.proof_hash,
+                taproot_address: forge.taproot_address.clone(),
+                timestamp: forge.timestamp,
+                height: None,
+            });
+        }
+        self.save()
+    }
+
+    /// Record where the key of a derivation came from, signing a
+    /// provenance statement for its address with the derived key
+    pub fn record_key_origin(
+        &mut self,
+        prophecy_words: &[String],
+        salt: Option<&[u8]>,
+        result: &ProofOfForgeResult,
+    ) -> Result<()> {
+        let origin = KeyOrigin::new(prophecy_words, salt, result)?;
+        self.data.key_origins.insert(result.taproot_address.clone(), origin);
+        self.save()
+    }
+
+    /// Origin of the key behind `address`, if it was derived here
+    pub fn key_origin(&self, address: &str) -> Option<&KeyOrigin> {
+        self.data.key_origins.get(address)
+    }
+
+    /// Provenance statement for a derived address
+    pub fn export_provenance(&self, address: &str) -> Result<ProvenanceStatement> {
+        self.key_origin(address)
+            .map(|origin| origin.statement(address))
+            .ok_or_else(|| anyhow!("No key origin recorded for {}", address))
+    }
+
+    /// Mark a recorded forge as mined at `height`
+    pub fn confirm_forge(&mut self, proof_hash: &[u8; 32], height: u64) -> Result<bool> {
+        let Some(record) = self.data.forges.iter_mut().find(|record| &record.proof_hash == proof_hash) else {
+            return Ok(false);
+        };
+        record.height = Some(height);
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Forges built by this wallet, oldest first
+    pub fn forge_history(&self) -> &[ForgeRecord] {
+        &self.data.forges
+    }
+
+    /// Exclude outputs from automatic coin selection, e.g. while they are
+    /// promised to a buyer. Locks persist in the wallet file.
+    pub fn lock_unspent(&mut self, outpoints: &[OutPoint]) -> Result<()> {
+        self.data.locked_outputs.extend(outpoints.iter().copied());
+        self.save()
+    }
+
+    /// Make locked outputs available to coin selection again. Fails
+    /// without unlocking anything if one of them isn't locked.
+    pub fn unlock_unspent(&mut self, outpoints: &[OutPoint]) -> Result<()> {
+        if let Some(outpoint) = outpoints.iter().find(|outpoint| !self.data.locked_outputs.contains(outpoint)) {
+            return Err(anyhow!(
+                "Output {}:{} is not locked",
+                hex::encode(outpoint.txid),
+                outpoint.vout
+            ));
+        }
+        for outpoint in outpoints {
+            self.data.locked_outputs.remove(outpoint);
+        }
+        self.save()
+    }
+
+    /// Unlock every locked output
+    pub fn unlock_all(&mut self) -> Result<()> {
+        self.data.locked_outputs.clear();
+        self.save()
+    }
+
+    /// Whether an output is excluded from coin selection
+    pub fn is_locked(&self, outpoint: &OutPoint) -> bool {
+        self.data.locked_outputs.contains(outpoint)
+    }
+
+    /// Locked outputs, in outpoint order
+    pub fn locked_outputs(&self) -> impl Iterator<Item = &OutPoint> {
+        self.data.locked_outputs.iter()
+    }
+
+    /// Unlocked ledger outputs paying one of the wallet's own addresses
+    fn spendable_outputs<'a>(&'a self, ledger: &'a Ledger) -> impl Iterator<Item = (&'a OutPoint, &'a LedgerOutput)> {
+        ledger.outputs().filter(|(outpoint, output)| {
+            !self.is_locked(outpoint)
+                && self
+                    .data
+                    .addresses
+                    .get(&output.address)
+                    .is_some_and(|entry| entry.purpose == AddressPurpose::Receive)
+        })
+    }
+
+    /// Enable or disable locking new forges to the current tip
+    pub fn set_anti_fee_sniping(&mut self, enabled: bool) {
+        self.anti_fee_sniping = enabled;
+    }
+
+    /// Lock height to use for a forge built on top of `tip_height`.
+    ///
+    /// Locking to the current tip means the forge can only be mined in the
+    /// next block, so a miner gains nothing by re-mining the tip to steal it.
+    pub fn lock_height(&self, tip_height: u64, options: &ForgeOptions) -> u64 {
+        match options.not_before_height {
+            Some(height) => height,
+            None if self.anti_fee_sniping => tip_height,
+            None => 0,
+        }
+    }
+
+    /// Delegate signing to an external signer (or stop delegating)
+    pub fn set_external_signer(&mut self, signer: Option<ExternalSigner>) {
+        self.signer = signer;
+    }
+
+    /// Sign a forge with the configured external signer
+    pub fn sign_forge(&self, forge: &mut ForgeTransaction) -> Result<()> {
+        let signer = self
+            .signer
+            .as_ref()
+            .ok_or_else(|| anyhow!("No external signer configured"))?;
+        let request = ExternalSigner::forge_request(forge, self.network);
+        forge.signature = signer.sign(&request)?;
+        Ok(())
+    }
+
+    /// Run the proof-of-forge pipeline and build an unsigned forge
+    /// transaction by `forger_key`, to be signed with its secret key
+    /// (`ForgeTransaction::sign`) or by the external signer (`sign_forge`).
+    /// Without a salt in `options`, random salts are tried until the proof
+    /// hash meets `difficulty`.
+    pub fn build_forge(
+        &self,
+        prophecy_words: &[String],
+        forger_key: &PublicKey,
+        tip_height: u64,
+        difficulty: u32,
+        options: &ForgeOptions,
+    ) -> Result<ForgeTransaction> {
+        loop {
+            let salt = options.salt.unwrap_or_else(rand::random);
+            let bound_salt = forge_tempering_salt(&forger_key.serialize(), &salt);
+            let result =
+                proof_of_forge_with_tempering(prophecy_words, Some(&bound_salt), self.network, options.tempering)?;
+            if options.salt.is_some() || meets_difficulty(&forge_proof_hash(&result, &salt), difficulty) {
+                return Ok(self.forge_from_result(prophecy_words, &result, forger_key, &salt, tip_height, options));
+            }
+        }
+    }
+
+    /// Build an unsigned forge transaction by `forger_key` from an already
+    /// computed derivation, tempered with `forge_tempering_salt` of that
+    /// key and `salt`
+    pub fn forge_from_result(
+        &self,
+        prophecy_words: &[String],
+        result: &ProofOfForgeResult,
+        forger_key: &PublicKey,
+        salt: &[u8; 32],
+        tip_height: u64,
+        options: &ForgeOptions,
+    ) -> ForgeTransaction {
+        ForgeTransaction {
+            prophecy: prophecy_words.join(" "),
+            derived_key: forger_key.serialize().to_vec(),
+            taproot_address: p2tr_address_for_key(forger_key, self.network),
+            proof_hash: forge_proof_hash(result, salt),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            signature: vec![],
+            not_before_height: self.lock_height(tip_height, options),
+            tempering: result.tempering,
+            salt: *salt,
+        }
+    }
+
+    /// Build an unsigned transfer paying `outputs` and `fee` from unlocked
+    /// wallet outputs, largest first. Change goes to `change_address` (by
+    /// default the largest input's address) unless it would be dust, in
+    /// which case it is added to the fee.
+    pub fn fund_transfer(
+        &self,
+        ledger: &Ledger,
+        outputs: Vec<TransferOutput>,
+        fee: u64,
+        change_address: Option<&str>,
+        dust_threshold: u64,
+    ) -> Result<Transfer> {
+        if outputs.is_empty() {
+            return Err(anyhow!("Transfer must have at least one output"));
+        }
+        let target = outputs
+            .iter()
+            .try_fold(fee, |total, output| total.checked_add(output.value))
+            .ok_or_else(|| anyhow!("Transfer amount overflows"))?;
+
+        let mut candidates: Vec<_> = self.spendable_outputs(ledger).collect();
+        candidates.sort_by(|(a_outpoint, a), (b_outpoint, b)| b.value.cmp(&a.value).then(a_outpoint.cmp(b_outpoint)));
+
+        let mut inputs = Vec::new();
+        let mut total = 0u64;
+        for (outpoint, output) in candidates {
+            if total >= target {
+                break;
+            }
+            total += output.value;
+            inputs.push(TransferInput {
+                prevout: *outpoint,
+                amount: output.value,
+                address: output.address.clone(),
+            });
+        }
+        if total < target {
+            return Err(anyhow!(
+                "Insufficient funds: unlocked wallet outputs total {}, need {}",
+                total,
+                target
+            ));
+        }
+        let change_address = change_address.unwrap_or(&inputs[0].address).to_string();
+        inputs.sort_by_key(|input| input.prevout);
+
+        let mut transfer = Transfer {
+            version: 1,
+            inputs,
+            outputs,
+            fee,
+            lock_height: 0,
+        };
+        let change = total - target;
+        if is_dust(change, dust_threshold) {
+            transfer.fee += change;
+        } else {
+            transfer.outputs.push(TransferOutput {
+                address: change_address,
+                value: change,
+            });
+        }
+        check_transfer_outputs(&transfer)?;
+        Ok(transfer)
+    }
+
+    /// Build an unsigned transfer consolidating every unlocked dust output
+    /// paying a wallet address into a single output to `destination`
+    pub fn sweep_dust(
+        &self,
+        ledger: &Ledger,
+        destination: &str,
+        fee: u64,
+        dust_threshold: u64,
+    ) -> Result<Transfer> {
+        let mut inputs: Vec<TransferInput> = self
+            .spendable_outputs(ledger)
+            .filter(|(_, output)| is_dust(output.value, dust_threshold))
+            .map(|(outpoint, output)| TransferInput {
+                prevout: *outpoint,
+                amount: output.value,
+                address: output.address.clone(),
+            })
+            .collect();
+        if inputs.is_empty() {
+            return Err(anyhow!("No dust outputs to sweep"));
+        }
+        inputs.sort_by_key(|input| input.prevout);
+
+        let total: u64 = inputs.iter().map(|input| input.amount).sum();
+        let value = total
+            .checked_sub(fee)
+            .filter(|value| !is_dust(*value, dust_threshold))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Dust outputs total {} which is not enough to sweep with a fee of {}",
+                    total,
+                    fee
+                )
+            })?;
+
+        let transfer = Transfer {
+            version: 1,
+            inputs,
+            outputs: vec![TransferOutput {
+                address: destination.to_string(),
+                value,
+            }],
+            fee,
+            lock_height: 0,
+        };
+        check_transfer_outputs(&transfer)?;
+        Ok(transfer)
+    }
+
+    /// Network this wallet builds forges for
+    pub fn network(&self) -> Network {
+        self.network
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_result() -> ProofOfForgeResult {
+        ProofOfForgeResult {
+            prophecy_hash: vec![1u8; 64],
+            tetra_hash: vec![2u8; 32],
+            tempered_key: vec![3u8; 64].into(),
+            final_seed: vec![4u8; 32].into(),
+            public_key: [2u8; 33],
+            internal_key: [5u8; 32],
+            output_key: [6u8; 32],
+            taproot_address: "bc1p...".to_string(),
+            tempering: Default::default(),
+        }
+    }
+
+    fn forger_key() -> PublicKey {
+        crate::crypto::derive_public_key(&[4u8; 32]).unwrap()
+    }
+
+    fn prophecy() -> Vec<String> {
+        crate::crypto::CANONICAL_PROPHECY.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_lock_height_defaults_to_tip() {
+        let wallet = Wallet::new(Network::Regtest);
+        let forge = wallet
+            .forge_from_result(&prophecy(), &test_result(), &forger_key(), &[9u8; 32], 42, &ForgeOptions::default());
+        assert_eq!(forge.not_before_height, 42);
+        assert_eq!(forge.derived_key.len(), 33);
+    }
+
+    fn regtest_address(seed: u8) -> String {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let secret = bitcoin::secp256k1::SecretKey::from_slice(&[seed; 32]).unwrap();
+        let (xonly, _) = secret.x_only_public_key(&secp);
+        Address::p2tr(&secp, xonly, None, Network::Regtest).to_string()
+    }
+
+    #[test]
+    fn test_labels_persisted() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("wallet.json");
+        let watched = regtest_address(1);
+
+        let mut wallet = Wallet::open(&path, Network::Regtest).unwrap();
+        wallet.set_label(&watched, "exchange cold").unwrap();
+        assert!(wallet.set_label("not-an-address", "x").is_err());
+
+        let mut forge = wallet
+            .forge_from_result(&prophecy(), &test_result(), &forger_key(), &[9u8; 32], 42, &ForgeOptions::default());
+        forge.taproot_address = regtest_address(2);
+        wallet.record_forge(&forge).unwrap();
+        wallet.set_label(&forge.taproot_address, "treasury").unwrap();
+        assert!(wallet.confirm_forge(&forge.proof_hash, 43).unwrap());
+
+        let reopened = Wallet::open(&path, Network::Regtest).unwrap();
+        assert_eq!(reopened.label(&watched), Some("exchange cold"));
+        let treasury = reopened.addresses_by_label("treasury");
+        assert_eq!(treasury.len(), 1);
+        assert_eq!(treasury[0].1.purpose, AddressPurpose::Receive);
+        assert_eq!(reopened.forge_history()[0].height, Some(43));
+    }
+
+    #[test]
+    fn test_lock_height_override() {
+        let mut wallet = Wallet::new(Network::Regtest);
+        let options = ForgeOptions {
+            not_before_height: Some(7),
+            ..Default::default()
+        };
+        assert_eq!(wallet.lock_height(42, &options), 7);
+
+        wallet.set_anti_fee_sniping(false);
+        assert_eq!(wallet.lock_height(42, &ForgeOptions::default()), 0);
+    }
+
+    #[test]
+    fn test_sweep_dust() {
+        use crate::ledger::{LedgerOutput, OutPoint, DUST_THRESHOLD};
+
+        let mut wallet = Wallet::new(Network::Regtest);
+        let mut forge = wallet
+            .forge_from_result(&prophecy(), &test_result(), &forger_key(), &[9u8; 32], 42, &ForgeOptions::default());
+        forge.taproot_address = regtest_address(2);
+        wallet.record_forge(&forge).unwrap();
+        let watched = regtest_address(1);
+        wallet.set_label(&watched, "watched").unwrap();
+
+        let mut ledger = Ledger::new();
+        for (n, address, value) in [
+            (1u8, &forge.taproot_address, 6_000),
+            (2, &forge.taproot_address, 7_000),
+            (3, &forge.taproot_address, 50_000),
+            (4, &watched, 3_000),
+        ] {
+            let output = LedgerOutput { address: address.clone(), value, height: 1 };
+            ledger.add_output(OutPoint { txid: [n; 32], vout: 0 }, output).unwrap();
+        }
+
+        let sweep = wallet.sweep_dust(&ledger, &forge.taproot_address, 1_000, DUST_THRESHOLD).unwrap();
+        let swept: Vec<u8> = sweep.inputs.iter().map(|input| input.prevout.txid[0]).collect();
+        assert_eq!(swept, vec![1, 2]);
+        assert_eq!(sweep.outputs[0].value, 12_000);
+        ledger.apply_transfer(&sweep, 2).unwrap();
+
+        // Sweeping would only create new dust
+        let mut ledger = Ledger::new();
+        let output = LedgerOutput { address: forge.taproot_address.clone(), value: 9_000, height: 1 };
+        ledger.add_output(OutPoint { txid: [1; 32], vout: 0 }, output).unwrap();
+        assert!(wallet.sweep_dust(&ledger, &watched, 1_000, DUST_THRESHOLD).is_err());
+    }
+
+    #[test]
+    fn test_locked_outputs_skipped_by_coin_selection() {
+        use crate::ledger::DUST_THRESHOLD;
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("wallet.json");
+        let mut wallet = Wallet::open(&path, Network::Regtest).unwrap();
+        let mut forge = wallet
+            .forge_from_result(&prophecy(), &test_result(), &forger_key(), &[9u8; 32], 42, &ForgeOptions::default());
+        forge.taproot_address = regtest_address(2);
+        wallet.record_forge(&forge).unwrap();
+
+        let mut ledger = Ledger::new();
+        for (n, value) in [(1u8, 80_000), (2, 50_000), (3, 30_000), (4, 5_000)] {
+            let output = LedgerOutput { address: forge.taproot_address.clone(), value, height: 1 };
+            ledger.add_output(OutPoint { txid: [n; 32], vout: 0 }, output).unwrap();
+        }
+        let pay = |value| vec![TransferOutput { address: regtest_address(1), value }];
+
+        let transfer = wallet.fund_transfer(&ledger, pay(60_000), 1_000, None, DUST_THRESHOLD).unwrap();
+        assert_eq!(transfer.inputs.len(), 1);
+        assert_eq!(transfer.inputs[0].prevout.txid, [1; 32]);
+        assert_eq!(transfer.outputs[1].value, 19_000);
+        assert_eq!(transfer.outputs[1].address, forge.taproot_address);
+
+        // The largest output is promised elsewhere; locks survive a reopen
+        let sold = OutPoint { txid: [1; 32], vout: 0 };
+        let dust = OutPoint { txid: [4; 32], vout: 0 };
+        wallet.lock_unspent(&[sold, dust]).unwrap();
+        let wallet = Wallet::open(&path, Network::Regtest).unwrap();
+        assert!(wallet.is_locked(&sold));
+        let transfer = wallet.fund_transfer(&ledger, pay(60_000), 1_000, None, DUST_THRESHOLD).unwrap();
+        let spent: Vec<u8> = transfer.inputs.iter().map(|input| input.prevout.txid[0]).collect();
+        assert_eq!(spent, vec![2, 3]);
+        // 19_000 change, but the 4_000 left after a larger fee is dust
+        let transfer = wallet.fund_transfer(&ledger, pay(60_000), 16_000, None, DUST_THRESHOLD).unwrap();
+        assert_eq!((transfer.outputs.len(), transfer.fee), (1, 20_000));
+        assert!(wallet.fund_transfer(&ledger, pay(90_000), 1_000, None, DUST_THRESHOLD).is_err());
+        assert!(wallet.sweep_dust(&ledger, &forge.taproot_address, 0, DUST_THRESHOLD).is_err());
+
+        let mut wallet = wallet;
+        assert!(wallet.unlock_unspent(&[sold, OutPoint { txid: [9; 32], vout: 0 }]).is_err());
+        assert_eq!(wallet.locked_outputs().count(), 2);
+        wallet.unlock_unspent(&[sold]).unwrap();
+        assert!(wallet.fund_transfer(&ledger, pay(90_000), 1_000, None, DUST_THRESHOLD).is_ok());
+        wallet.unlock_all().unwrap();
+        assert_eq!(wallet.locked_outputs().count(), 0);
+    }
+}
@@ -0,0 +1,188 @@
+//! Key origin metadata and proof-of-provenance statements
+//!
+//! When the wallet derives a key from a prophecy it keeps where the key came
+//! from: the prophecy's registry hash, the tempering salt, the derivation
+//! path and the pipeline version. It also signs, with the derived key, a
+//! statement committing to the prophecy as
+//! `SHA256(tag || registry hash || salt || nonce)` under a random nonce.
+//! The statement proves the address holder made the commitment without
+//! revealing the words. Handing a verifier the nonce opens it: anyone who
+//! knows the prophecy can then check the address derives from it.
+
+use crate::crypto::{
+    p2tr_address_for_key, prophecy_registry_hash, sign_taproot_key_path, verify_taproot_key_path,
+    ProofOfForgeResult,
+};
+use anyhow::{anyhow, Result};
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::Network;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Derivation path of the proof-of-forge pipeline: SHA-512 binding,
+/// Tetra-POW, PBKDF2 tempering, Zetahash and a P2TR key path
+pub const FORGE_DERIVATION_PATH: &str = "pof/sha512/tetra128/pbkdf2-600k/zetahash/p2tr";
+
+/// Version of the derivation pipeline new keys come from
+pub const FORGE_DERIVATION_VERSION: u32 = 1;
+
+/// Where a wallet key came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyOrigin {
+    /// `prophecy_registry_hash` of the prophecy
+    pub prophecy_hash: [u8; 32],
+    /// PBKDF2 salt, if not the default
+    pub salt: Option<Vec<u8>>,
+    pub derivation_path: String,
+    pub forge_version: u32,
+    /// Compressed public key of the derived seed
+    pub public_key: Vec<u8>,
+    /// Blinding nonce of the prophecy commitment
+    pub nonce: [u8; 32],
+    /// Signature by the address key over the provenance statement
+    pub signature: Vec<u8>,
+}
+
+impl KeyOrigin {
+    /// Record the origin of a derivation and sign its provenance statement
+    pub fn new(prophecy_words: &[String], salt: Option<&[u8]>, result: &ProofOfForgeResult) -> Result<Self> {
+        let mut origin = Self {
+            prophecy_hash: prophecy_registry_hash(&prophecy_words.join(" ")),
+            salt: salt.map(<[u8]>::to_vec),
+            derivation_path: FORGE_DERIVATION_PATH.to_string(),
+            forge_version: FORGE_DERIVATION_VERSION,
+            public_key: result.public_key.to_vec(),
+            nonce: rand::random(),
+            signature: Vec::new(),
+        };
+        let statement = origin.statement(&result.taproot_address);
+        origin.signature = sign_taproot_key_path(&result.final_seed, &statement.signing_hash())?.to_vec();
+        Ok(origin)
+    }
+
+    /// Provenance statement for `address`, signed if the origin is
+    pub fn statement(&self, address: &str) -> ProvenanceStatement {
+        ProvenanceStatement {
+            address: address.to_string(),
+            public_key: self.public_key.clone(),
+            commitment: prophecy_commitment(&self.prophecy_hash, self.salt.as_deref(), &self.nonce),
+            derivation_path: self.derivation_path.clone(),
+            forge_version: self.forge_version,
+            signature: self.signature.clone(),
+        }
+    }
+}
+
+/// Commitment to a prophecy (by registry hash) and salt under `nonce`
+pub fn prophecy_commitment(prophecy_hash: &[u8; 32], salt: Option<&[u8]>, nonce: &[u8; 32]) -> [u8; 32] {
+    let salt = salt.unwrap_or_default();
+    let mut hasher = Sha256::new();
+    hasher.update(b"ExcaliburProvenance/commitment");
+    hasher.update(prophecy_hash);
+    hasher.update((salt.len() as u64).to_be_bytes());
+    hasher.update(salt);
+    hasher.update(nonce);
+    hasher.finalize().into()
+}
+
+/// Statement by an address holder that the address derives from a
+/// committed prophecy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProvenanceStatement {
+    pub address: String,
+    pub public_key: Vec<u8>,
+    pub commitment: [u8; 32],
+    pub derivation_path: String,
+    pub forge_version: u32,
+    /// BIP-340 signature by the address's Taproot key over `signing_hash`
+    pub signature: Vec<u8>,
+}
+
+impl ProvenanceStatement {
+    /// Hash the signature commits to
+    pub fn signing_hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(b"ExcaliburProvenance/statement");
+        let fields = (
+            &self.address,
+            &self.public_key,
+            &self.commitment,
+            &self.derivation_path,
+            self.forge_version,
+        );
+        hasher.update(bincode::serialize(&fields).unwrap());
+        hasher.finalize().into()
+    }
+
+    /// Check the address pays to `public_key` on `network` and the key
+    /// signed the statement
+    pub fn verify(&self, network: Network) -> Result<()> {
+        let public_key = PublicKey::from_slice(&self.public_key).map_err(|_| anyhow!("Invalid public key"))?;
+        if p2tr_address_for_key(&public_key, network) != self.address {
+            return Err(anyhow!("Address does not pay to the statement's key"));
+        }
+        verify_taproot_key_path(&public_key, &self.signing_hash(), &self.signature)
+    }
+
+    /// Whether the commitment opens to `prophecy` and `salt` under `nonce`
+    pub fn opens_to(&self, prophecy: &str, salt: Option<&[u8]>, nonce: &[u8; 32]) -> bool {
+        prophecy_commitment(&prophecy_registry_hash(prophecy), salt, nonce) == self.commitment
+    }
+
+    /// JSON form returned by `exportprovenance`
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "address": self.address,
+            "public_key": hex::encode(&self.public_key),
+            "commitment": hex::encode(self.commitment),
+            "derivation_path": self.derivation_path,
+            "forge_version": self.forge_version,
+            "signature": hex::encode(&self.signature),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{derive_public_key, TaprootOutput, CANONICAL_PROPHECY};
+
+    fn derivation(seed: u8) -> ProofOfForgeResult {
+        let final_seed = vec![seed; 32];
+        let public_key = derive_public_key(&final_seed).unwrap();
+        let taproot = TaprootOutput::for_key(&public_key);
+        ProofOfForgeResult {
+            prophecy_hash: vec![1u8; 64],
+            tetra_hash: vec![2u8; 32],
+            tempered_key: vec![3u8; 64].into(),
+            final_seed: final_seed.into(),
+            public_key: public_key.serialize(),
+            internal_key: taproot.internal_key.serialize(),
+            output_key: taproot.output_key.to_inner().serialize(),
+            taproot_address: taproot.address(Network::Regtest),
+            tempering: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_provenance_statement_verifies_and_opens() {
+        let words: Vec<String> = CANONICAL_PROPHECY.iter().map(|s| s.to_string()).collect();
+        let result = derivation(4);
+        let origin = KeyOrigin::new(&words, Some(b"pepper"), &result).unwrap();
+        let statement = origin.statement(&result.taproot_address);
+        statement.verify(Network::Regtest).unwrap();
+
+        let prophecy = words.join(" ");
+        assert!(statement.opens_to(&prophecy, Some(b"pepper"), &origin.nonce));
+        assert!(!statement.opens_to(&prophecy, None, &origin.nonce));
+        assert!(!statement.opens_to(&prophecy, Some(b"pepper"), &[0u8; 32]));
+        assert!(!statement.to_json().to_string().contains(CANONICAL_PROPHECY[0]));
+
+        // Claiming another address or altering the commitment breaks it
+        let other = derivation(5);
+        assert!(origin.statement(&other.taproot_address).verify(Network::Regtest).is_err());
+        let mut tampered = statement.clone();
+        tampered.commitment[0] ^= 1;
+        assert!(tampered.verify(Network::Regtest).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! External signer protocol (HWI-style)
+//!
+//! The wallet writes a single JSON [`SigningRequest`] to the signer's stdin
+//! and reads a single JSON [`SigningResponse`] from its stdout, so keys can
+//! live in an HSM or hardware device the node never touches.
+
+use crate::consensus::ForgeTransaction;
+use crate::crypto::verify_taproot_key_path;
+use bitcoin::secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use anyhow::{anyhow, Context, Result};
+
+/// Current signer protocol version
+pub const SIGNER_PROTOCOL_VERSION: u32 = 1;
+
+/// Item the signer is asked to sign
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SigningPayload {
+    Forge { forge: ForgeTransaction },
+}
+
+/// Request written to the signer's stdin
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningRequest {
+    pub version: u32,
+    /// Network name (bitcoin, testnet, regtest, ...)
+    pub network: String,
+    /// Public key whose BIP-86 Taproot output key must sign (hex); the
+    /// signer tweaks its secret key to match
+    pub pubkey: String,
+    /// Hash to sign with BIP-340 Schnorr (hex)
+    pub sighash: String,
+    /// Full unsigned item, so the signer can display and check it
+    pub payload: SigningPayload,
+}
+
+/// Response read from the signer's stdout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningResponse {
+    /// 64-byte Schnorr signature (hex)
+    #[serde(default)]
+    pub signature: Option<String>,
+    /// Reason the signer declined, if it did
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Signer binary the wallet delegates to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExternalSigner {
+    command: PathBuf,
+    args: Vec<String>,
+}
+
+impl ExternalSigner {
+    /// Create a signer that runs `command` with `args`
+    pub fn new(command: impl Into<PathBuf>, args: Vec<String>) -> Self {
+        Self {
+            command: command.into(),
+            args,
+        }
+    }
+
+    /// Build the request for signing a forge
+    pub fn forge_request(forge: &ForgeTransaction, network: bitcoin::Network) -> SigningRequest {
+        SigningRequest {
+            version: SIGNER_PROTOCOL_VERSION,
+            network: network.to_string(),
+            pubkey: hex::encode(&forge.derived_key),
+            sighash: hex::encode(forge.signing_hash()),
+            payload: SigningPayload::Forge { forge: forge.clone() },
+        }
+    }
+
+    /// Run the signer and return the signature, checked against the
+    /// request's public key
+    pub fn sign(&self, request: &SigningRequest) -> Result<Vec<u8>> {
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("Failed to start external signer {}", self.command.display()))?;
+
+        {
+            let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("Signer stdin unavailable"))?;
+            serde_json::to_writer(&mut stdin, request)?;
+            stdin.write_all(b"\n")?;
+        }
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(anyhow!("External signer exited with {}", output.status));
+        }
+
+        let response: SigningResponse = serde_json::from_slice(&output.stdout)
+            .context("Invalid external signer response")?;
+        if let Some(error) = response.error {
+            return Err(anyhow!("External signer declined: {}", error));
+        }
+        let signature = hex::decode(
+            response
+                .signature
+                .ok_or_else(|| anyhow!("External signer returned no signature"))?,
+        )?;
+
+        verify_signature(request, &signature)?;
+        Ok(signature)
+    }
+}
+
+/// Check a signer's signature so a faulty device can't produce an
+/// unspendable forge
+fn verify_signature(request: &SigningRequest, signature: &[u8]) -> Result<()> {
+    let pubkey = PublicKey::from_slice(&hex::decode(&request.pubkey)?)?;
+    let sighash: [u8; 32] = hex::decode(&request.sighash)?
+        .try_into()
+        .map_err(|_| anyhow!("Invalid sighash length"))?;
+    verify_taproot_key_path(&pubkey, &sighash, signature)
+        .map_err(|_| anyhow!("External signer returned an invalid signature"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{derive_public_key, sign_taproot_key_path};
+
+    fn signed_forge() -> (ForgeTransaction, String) {
+        let forge = ForgeTransaction {
+            prophecy: crate::crypto::CANONICAL_PROPHECY.join(" "),
+            derived_key: derive_public_key(&[7u8; 32]).unwrap().serialize().to_vec(),
+            taproot_address: "bc1p...".to_string(),
+            proof_hash: [1u8; 32],
+            timestamp: 1000,
+            signature: vec![],
+            not_before_height: 0,
+            tempering: Default::default(),
+            salt: [0; 32],
+        };
+        let signature = sign_taproot_key_path(&[7u8; 32], &forge.signing_hash()).unwrap();
+        (forge, hex::encode(signature))
+    }
+
+    fn script_signer(response: &str) -> ExternalSigner {
+        ExternalSigner::new(
+            "sh",
+            vec!["-c".to_string(), format!("cat > /dev/null; echo '{}'", response)],
+        )
+    }
+
+    #[test]
+    fn test_external_signer_roundtrip() {
+        let (forge, signature) = signed_forge();
+        let request = ExternalSigner::forge_request(&forge, bitcoin::Network::Regtest);
+
+        let signer = script_signer(&format!(r#"{{"signature":"{}"}}"#, signature));
+        assert_eq!(hex::encode(signer.sign(&request).unwrap()), signature);
+
+        // A signature over anything else is rejected
+        let mut other = forge.clone();
+        other.timestamp += 1;
+        let other_request = ExternalSigner::forge_request(&other, bitcoin::Network::Regtest);
+        assert!(signer.sign(&other_request).is_err());
+    }
+
+    #[test]
+    fn test_external_signer_declines() {
+        let (forge, _) = signed_forge();
+        let request = ExternalSigner::forge_request(&forge, bitcoin::Network::Regtest);
+
+        let signer = script_signer(r#"{"error":"rejected on device"}"#);
+        let err = signer.sign(&request).unwrap_err();
+        assert!(err.to_string().contains("rejected on device"));
+    }
+}
@@ -0,0 +1,404 @@
+//! Time-locked vaults
+//!
+//! A vault address is a Taproot output with no usable key path and two
+//! script leaves: the recovery key may spend at once, and the hot key only
+//! after the output is `delay` blocks deep (`OP_CHECKSEQUENCEVERIFY`). The
+//! wallet only ever builds recovery-path spends paying the vault's recovery
+//! address, so a stolen hot key gives an attacker a delay window in which
+//! the owner can sweep the funds to safety.
+//!
+//! Unvaulting records which outputs are being withdrawn, where to, and from
+//! which height; recovering records a withdrawal to the recovery address
+//! with no delay. Vault outputs that leave the ledger any other way are
+//! reported by `check_vaults` as unauthorized spends.
+//!
+//! The wallet holds no vault keys. Spends are signed elsewhere through the
+//! leaf `Vault::spend_leaf` names, and `Vault::with_signatures` turns the
+//! signatures into the script-path witnesses a block carries.
+
+use super::Wallet;
+use crate::consensus::sighash::{Transfer, TransferInput, TransferOutput};
+use crate::consensus::{SignedTransfer, TransferWitness};
+use crate::ledger::{check_transfer_outputs, Ledger, OutPoint};
+use anyhow::{anyhow, Result};
+use bitcoin::opcodes::all::{OP_CHECKSIG, OP_CSV, OP_DROP};
+use bitcoin::script::{Builder, ScriptBuf};
+use bitcoin::secp256k1::{Secp256k1, XOnlyPublicKey};
+use bitcoin::taproot::{ControlBlock, LeafVersion, TaprootBuilder, TaprootSpendInfo};
+use bitcoin::Address;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+/// Default unvaulting delay in blocks (about a day)
+pub const DEFAULT_VAULT_DELAY: u16 = 144;
+
+/// BIP-341 "nothing up my sleeve" point; no one knows its discrete log, so
+/// vault outputs cannot be spent through the key path
+const UNSPENDABLE_INTERNAL_KEY: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e, 0x07, 0x8a, 0x5a,
+    0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// Script leaf the recovery key spends through at any time
+pub fn recovery_leaf(recovery_key: &XOnlyPublicKey) -> ScriptBuf {
+    Builder::new()
+        .push_x_only_key(recovery_key)
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+/// Script leaf the hot key spends through once the output is `delay` blocks deep
+pub fn delayed_leaf(hot_key: &XOnlyPublicKey, delay: u16) -> ScriptBuf {
+    Builder::new()
+        .push_int(i64::from(delay))
+        .push_opcode(OP_CSV)
+        .push_opcode(OP_DROP)
+        .push_x_only_key(hot_key)
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+/// Taproot tree of a vault: the recovery and delayed leaves under an
+/// unspendable internal key
+pub fn vault_spend_info(hot_key: &XOnlyPublicKey, recovery_key: &XOnlyPublicKey, delay: u16) -> Result<TaprootSpendInfo> {
+    let internal_key = XOnlyPublicKey::from_slice(&UNSPENDABLE_INTERNAL_KEY)?;
+    TaprootBuilder::new()
+        .add_leaf(1, recovery_leaf(recovery_key))?
+        .add_leaf(1, delayed_leaf(hot_key, delay))?
+        .finalize(&Secp256k1::verification_only(), internal_key)
+        .map_err(|_| anyhow!("Incomplete vault script tree"))
+}
+
+/// Where a vault is in its lifecycle
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum VaultState {
+    /// Funds stay put unless the recovery key moves them
+    Active,
+    /// Outputs are being withdrawn through the delayed leaf, or through
+    /// the recovery leaf to the recovery address (`spendable_at` 0)
+    Unvaulting {
+        destination: String,
+        outputs: BTreeSet<OutPoint>,
+        /// First height the withdrawal can be mined at
+        spendable_at: u64,
+    },
+}
+
+/// A vault created by this wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vault {
+    pub address: String,
+    /// Hot key (x-only, hex) allowed to spend after the delay
+    pub hot_key: String,
+    /// Recovery key (x-only, hex) allowed to spend at once
+    pub recovery_key: String,
+    /// Address recovery-path spends pay
+    pub recovery_address: String,
+    /// Blocks a vault output must be buried before the hot key can spend it
+    pub delay: u16,
+    pub state: VaultState,
+    /// Vault outputs seen in the ledger at the last check
+    #[serde(default)]
+    pub known_outputs: BTreeSet<OutPoint>,
+}
+
+impl Vault {
+    /// Leaf script and control block a spend goes through: the recovery
+    /// leaf, or the hot key's delayed leaf
+    pub fn spend_leaf(&self, recovery: bool) -> Result<(ScriptBuf, ControlBlock)> {
+        let hot = parse_x_only(&self.hot_key)?;
+        let recovery_key = parse_x_only(&self.recovery_key)?;
+        let script = if recovery {
+            recovery_leaf(&recovery_key)
+        } else {
+            delayed_leaf(&hot, self.delay)
+        };
+        let control_block = vault_spend_info(&hot, &recovery_key, self.delay)?
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .ok_or_else(|| anyhow!("Leaf is not in the tree of vault {}", self.address))?;
+        Ok((script, control_block))
+    }
+
+    /// `transfer` with script-path witnesses through `spend_leaf(recovery)`,
+    /// from one signature per input
+    pub fn with_signatures(
+        &self,
+        transfer: Transfer,
+        recovery: bool,
+        signatures: Vec<Vec<u8>>,
+    ) -> Result<SignedTransfer> {
+        if signatures.len() != transfer.inputs.len() {
+            return Err(anyhow!(
+                "Expected {} signatures, one per input, got {}",
+                transfer.inputs.len(),
+                signatures.len()
+            ));
+        }
+        let (script, control_block) = self.spend_leaf(recovery)?;
+        let witnesses = signatures
+            .into_iter()
+            .map(|signature| TransferWitness::ScriptPath {
+                signature,
+                script: script.to_bytes(),
+                control_block: control_block.serialize(),
+            })
+            .collect();
+        Ok(SignedTransfer { transfer, witnesses })
+    }
+}
+
+/// A vault output that left the ledger without an authorized unvault
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VaultAlert {
+    pub vault: String,
+    pub outpoint: OutPoint,
+    pub reason: String,
+}
+
+fn parse_x_only(key: &str) -> Result<XOnlyPublicKey> {
+    hex::decode(key)
+        .ok()
+        .and_then(|bytes| XOnlyPublicKey::from_slice(&bytes).ok())
+        .ok_or_else(|| anyhow!("Invalid x-only public key {}", key))
+}
+
+/// Unsigned transfer of every output of `vault` to `destination`
+fn vault_transfer(ledger: &Ledger, vault: &Vault, destination: &str, fee: u64, lock_height: u64) -> Result<Transfer> {
+    let mut inputs: Vec<TransferInput> = ledger
+        .outputs()
+        .filter(|(_, output)| output.address == vault.address)
+        .map(|(outpoint, output)| TransferInput {
+            prevout: *outpoint,
+            amount: output.value,
+            address: output.address.clone(),
+        })
+        .collect();
+    if inputs.is_empty() {
+        return Err(anyhow!("Vault {} holds no outputs", vault.address));
+    }
+    inputs.sort_by_key(|input| input.prevout);
+
+    let total: u64 = inputs.iter().map(|input| input.amount).sum();
+    let value = total
+        .checked_sub(fee)
+        .ok_or_else(|| anyhow!("Vault holds {}, less than the fee {}", total, fee))?;
+    let transfer = Transfer {
+        version: 1,
+        inputs,
+        outputs: vec![TransferOutput {
+            address: destination.to_string(),
+            value,
+        }],
+        fee,
+        lock_height,
+    };
+    check_transfer_outputs(&transfer)?;
+    Ok(transfer)
+}
+
+impl Wallet {
+    /// Create a vault spendable by `recovery_key` at once and by `hot_key`
+    /// after `delay` blocks. Returns the vault address.
+    pub fn create_vault(&mut self, hot_key: &str, recovery_key: &str, recovery_address: &str, delay: u16) -> Result<String> {
+        if delay == 0 {
+            return Err(anyhow!("Vault delay must be at least one block"));
+        }
+        let hot = parse_x_only(hot_key)?;
+        let recovery = parse_x_only(recovery_key)?;
+        if hot == recovery {
+            return Err(anyhow!("Hot and recovery keys must differ"));
+        }
+        Address::from_str(recovery_address)
+            .map_err(|e| anyhow!("Invalid recovery address {}: {}", recovery_address, e))?
+            .require_network(self.network)?;
+
+        let spend_info = vault_spend_info(&hot, &recovery, delay)?;
+        let address = Address::p2tr_tweaked(spend_info.output_key(), self.network).to_string();
+        if !self.data.vaults.iter().any(|vault| vault.address == address) {
+            self.data.vaults.push(Vault {
+                address: address.clone(),
+                hot_key: hex::encode(hot.serialize()),
+                recovery_key: hex::encode(recovery.serialize()),
+                recovery_address: recovery_address.to_string(),
+                delay,
+                state: VaultState::Active,
+                known_outputs: BTreeSet::new(),
+            });
+            self.save()?;
+        }
+        Ok(address)
+    }
+
+    /// Vaults created by this wallet
+    pub fn vaults(&self) -> &[Vault] {
+        &self.data.vaults
+    }
+
+    fn vault_mut(&mut self, vault_address: &str) -> Result<&mut Vault> {
+        self.data
+            .vaults
+            .iter_mut()
+            .find(|vault| vault.address == vault_address)
+            .ok_or_else(|| anyhow!("Unknown vault {}", vault_address))
+    }
+
+    /// Start withdrawing every output of a vault to `destination`. Returns
+    /// the unsigned transfer, locked until the youngest output has waited
+    /// out the vault's delay.
+    pub fn unvault(&mut self, ledger: &Ledger, vault_address: &str, destination: &str, fee: u64) -> Result<Transfer> {
+        let vault = self.vault_mut(vault_address)?;
+        let spendable_at = ledger
+            .outputs()
+            .filter(|(_, output)| output.address == vault.address)
+            .map(|(_, output)| output.height + u64::from(vault.delay))
+            .max()
+            .unwrap_or_default();
+        let transfer = vault_transfer(ledger, vault, destination, fee, spendable_at)?;
+
+        vault.state = VaultState::Unvaulting {
+            destination: destination.to_string(),
+            outputs: transfer.inputs.iter().map(|input| input.prevout).collect(),
+            spendable_at,
+        };
+        self.save()?;
+        Ok(transfer)
+    }
+
+    /// Sweep every output of a vault to its recovery address through the
+    /// recovery leaf, replacing any unvault in progress with a withdrawal
+    /// to the recovery address that needs no delay
+    pub fn recover_vault(&mut self, ledger: &Ledger, vault_address: &str, fee: u64) -> Result<Transfer> {
+        let vault = self.vault_mut(vault_address)?;
+        let recovery_address = vault.recovery_address.clone();
+        let transfer = vault_transfer(ledger, vault, &recovery_address, fee, 0)?;
+        vault.state = VaultState::Unvaulting {
+            destination: recovery_address,
+            outputs: transfer.inputs.iter().map(|input| input.prevout).collect(),
+            spendable_at: 0,
+        };
+        self.save()?;
+        Ok(transfer)
+    }
+
+    /// Compare vault outputs with the ledger at `height`. Outputs that
+    /// disappeared without being part of a matured unvault are reported.
+    pub fn check_vaults(&mut self, ledger: &Ledger, height: u64) -> Result<Vec<VaultAlert>> {
+        let mut alerts = Vec::new();
+        for vault in &mut self.data.vaults {
+            let current: BTreeSet<OutPoint> = ledger
+                .outputs()
+                .filter(|(_, output)| output.address == vault.address)
+                .map(|(outpoint, _)| *outpoint)
+                .collect();
+            for outpoint in vault.known_outputs.difference(&current) {
+                let reason = match &vault.state {
+                    VaultState::Active => "spent while the vault was not unvaulting",
+                    VaultState::Unvaulting { outputs, .. } if !outputs.contains(outpoint) => {
+                        "spent but not part of the unvault"
+                    }
+                    VaultState::Unvaulting { spendable_at, .. } if height < *spendable_at => {
+                        "spent before the unvault delay elapsed"
+                    }
+                    VaultState::Unvaulting { .. } => continue,
+                };
+                alerts.push(VaultAlert {
+                    vault: vault.address.clone(),
+                    outpoint: *outpoint,
+                    reason: reason.to_string(),
+                });
+            }
+            if let VaultState::Unvaulting { outputs, .. } = &vault.state {
+                if outputs.iter().all(|outpoint| !current.contains(outpoint)) {
+                    vault.state = VaultState::Active;
+                }
+            }
+            vault.known_outputs = current;
+        }
+        self.save()?;
+        Ok(alerts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::LedgerOutput;
+    use bitcoin::Network;
+
+    fn key(byte: u8) -> String {
+        let secret = bitcoin::secp256k1::SecretKey::from_slice(&[byte; 32]).unwrap();
+        hex::encode(secret.x_only_public_key(&Secp256k1::new()).0.serialize())
+    }
+
+    fn recovery_address() -> String {
+        let public_key = bitcoin::secp256k1::PublicKey::from_secret_key(
+            &Secp256k1::new(),
+            &bitcoin::secp256k1::SecretKey::from_slice(&[9u8; 32]).unwrap(),
+        );
+        crate::crypto::p2tr_address_for_key(&public_key, Network::Regtest)
+    }
+
+    #[test]
+    fn test_vault_address_commits_to_both_leaves() {
+        let mut wallet = Wallet::new(Network::Regtest);
+        let address = wallet.create_vault(&key(1), &key(2), &recovery_address(), 144).unwrap();
+        assert!(address.starts_with("bcrt1p"));
+        assert_eq!(wallet.create_vault(&key(1), &key(2), &recovery_address(), 144).unwrap(), address);
+        assert_ne!(wallet.create_vault(&key(1), &key(2), &recovery_address(), 145).unwrap(), address);
+        assert_eq!(wallet.vaults().len(), 2);
+
+        let hot = parse_x_only(&key(1)).unwrap();
+        let recovery = parse_x_only(&key(2)).unwrap();
+        let info = vault_spend_info(&hot, &recovery, 144).unwrap();
+        for leaf in [recovery_leaf(&recovery), delayed_leaf(&hot, 144)] {
+            assert!(info.control_block(&(leaf, bitcoin::taproot::LeafVersion::TapScript)).is_some());
+        }
+
+        assert!(wallet.create_vault(&key(1), &key(1), &recovery_address(), 144).is_err());
+        assert!(wallet.create_vault(&key(1), &key(2), &recovery_address(), 0).is_err());
+        assert!(wallet.create_vault(&key(1), &key(2), "bc1qnotanaddress", 144).is_err());
+    }
+
+    #[test]
+    fn test_unauthorized_vault_spends_reported() {
+        let mut wallet = Wallet::new(Network::Regtest);
+        let vault = wallet.create_vault(&key(1), &key(2), &recovery_address(), 10).unwrap();
+        let mut ledger = Ledger::new();
+        let output = |height| LedgerOutput {
+            address: vault.clone(),
+            value: 10_000,
+            height,
+        };
+        let first = OutPoint { txid: [1; 32], vout: 0 };
+        let second = OutPoint { txid: [2; 32], vout: 0 };
+        ledger.add_output(first, output(5)).unwrap();
+        ledger.add_output(second, output(8)).unwrap();
+        assert!(wallet.check_vaults(&ledger, 8).unwrap().is_empty());
+
+        // Spent with no unvault in progress
+        ledger.spend_output(&first).unwrap();
+        let alerts = wallet.check_vaults(&ledger, 9).unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].outpoint, first);
+
+        let transfer = wallet.unvault(&ledger, &vault, &recovery_address(), 100).unwrap();
+        assert_eq!(transfer.lock_height, 18);
+        assert_eq!(transfer.outputs[0].value, 9_900);
+
+        // Spent early
+        ledger.spend_output(&second).unwrap();
+        let alerts = wallet.check_vaults(&ledger, 12).unwrap();
+        assert_eq!(alerts[0].reason, "spent before the unvault delay elapsed");
+        assert_eq!(wallet.vaults()[0].state, VaultState::Active);
+
+        // The recovery path pays the recovery address with no lock
+        let third = OutPoint { txid: [3; 32], vout: 0 };
+        ledger.add_output(third, output(20)).unwrap();
+        let recovery = wallet.recover_vault(&ledger, &vault, 100).unwrap();
+        assert_eq!(recovery.lock_height, 0);
+        assert_eq!(recovery.outputs[0].address, recovery_address());
+        assert!(matches!(&wallet.vaults()[0].state, VaultState::Unvaulting { spendable_at: 0, .. }));
+    }
+}
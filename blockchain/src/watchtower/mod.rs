@@ -0,0 +1,489 @@
+//! Watchtower mode: detect and record ruleset violations
+//!
+//! The watchtower observes forges (from the mempool and from blocks on any
+//! branch) and block headers, and records evidence when it sees:
+//!
+//! - a double forge: one key signing two forges of the same prophecy with
+//!   different proofs
+//! - an equivocation: one authority signing two different blocks at the
+//!   same height
+//!
+//! Evidence always carries both conflicting signatures, so it can be
+//! checked without trusting whoever gossiped it, and blames the key that
+//! made them. Unsigned public-chain blocks have no producer to blame and
+//! yield no equivocation evidence. Evidence is stored in the chain store
+//! (keeping the `MAX_STORED_EVIDENCE` most recent), raised as an alert,
+//! and optionally gossiped on `EVIDENCE_TOPIC` so a future rule set can
+//! penalize offenders.
+
+mod spend;
+
+pub use spend::{SpendLocation, SpendWatch, WatchedOutput, SPEND_ALERT_KIND};
+
+use crate::chain::ChainStore;
+use crate::codec::header_hash_preimage;
+use crate::consensus::{AuthoritySet, AuthoritySignature, Block, BlockHeader, ForgeTransaction};
+use crate::crypto::prophecy_registry_hash;
+use crate::events::{Alert, AlertSeverity, EventBus};
+use crate::network::NetworkCommand;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// Heights below the highest observed block for which forges and
+/// authority signatures are remembered
+pub const WATCH_WINDOW: u64 = 1_000;
+
+/// Forges remembered at once, however many arrive within the window
+pub const MAX_WATCHED_FORGES: usize = 100_000;
+
+/// Evidence entries kept in the store; the oldest are pruned first
+pub const MAX_STORED_EVIDENCE: usize = 10_000;
+
+/// Proof that a participant broke the rules
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Evidence {
+    /// The same prophecy forged with two different proofs
+    DoubleForge {
+        prophecy_hash: [u8; 32],
+        #[serde(with = "canonical_forge")]
+        first: ForgeTransaction,
+        #[serde(with = "canonical_forge")]
+        second: ForgeTransaction,
+    },
+    /// Two different blocks at the same height signed by the same authority
+    Equivocation {
+        /// X-only public key of the authority
+        signer: [u8; 32],
+        height: u64,
+        first: SignedHeader,
+        second: SignedHeader,
+    },
+}
+
+/// A block header and an authority's signature over its hash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedHeader {
+    pub header: BlockHeader,
+    pub signature: Vec<u8>,
+}
+
+/// Evidence carries forges in their canonical encoding. The serde derive
+/// leaves a PBKDF2 forge's tempering out, which bincode can't read back.
+mod canonical_forge {
+    use crate::consensus::ForgeTransaction;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(forge: &ForgeTransaction, serializer: S) -> Result<S::Ok, S::Error> {
+        forge.encode().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ForgeTransaction, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        ForgeTransaction::decode(&bytes).map_err(D::Error::custom)
+    }
+}
+
+impl Evidence {
+    /// Evidence for two forges of one prophecy, ordered so the same pair
+    /// always produces the same evidence
+    pub fn double_forge(a: ForgeTransaction, b: ForgeTransaction) -> Self {
+        let (first, second) = if a.proof_hash <= b.proof_hash { (a, b) } else { (b, a) };
+        Evidence::DoubleForge {
+            prophecy_hash: prophecy_registry_hash(&first.prophecy),
+            first,
+            second,
+        }
+    }
+
+    /// Evidence for two headers signed by one authority at one height
+    pub fn equivocation(signer: [u8; 32], a: SignedHeader, b: SignedHeader) -> Result<Self> {
+        let a_bytes = bincode::serialize(&a.header)?;
+        let b_bytes = bincode::serialize(&b.header)?;
+        let (first, second) = if a_bytes <= b_bytes { (a, b) } else { (b, a) };
+        Ok(Evidence::Equivocation {
+            signer,
+            height: first.header.height,
+            first,
+            second,
+        })
+    }
+
+    /// Stable identifier of this evidence
+    pub fn id(&self) -> Result<[u8; 32]> {
+        Ok(Sha256::digest(bincode::serialize(self)?).into())
+    }
+
+    /// Machine-readable kind, also used as the alert kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Evidence::DoubleForge { .. } => "double_forge",
+            Evidence::Equivocation { .. } => "equivocation",
+        }
+    }
+
+    /// Hex public key that signed both conflicting messages
+    pub fn offender(&self) -> String {
+        match self {
+            Evidence::DoubleForge { second, .. } => hex::encode(&second.derived_key),
+            Evidence::Equivocation { signer, .. } => hex::encode(signer),
+        }
+    }
+
+    /// Check the evidence holds two valid, conflicting signatures by the
+    /// same key, e.g. when received from a peer. Equivocations are only
+    /// checkable against the chain's `authorities`.
+    pub fn verify(&self, authorities: Option<&AuthoritySet>) -> Result<()> {
+        match self {
+            Evidence::DoubleForge { prophecy_hash, first, second } => {
+                if prophecy_registry_hash(&first.prophecy) != *prophecy_hash
+                    || prophecy_registry_hash(&second.prophecy) != *prophecy_hash
+                {
+                    return Err(anyhow!("Forges do not share the claimed prophecy"));
+                }
+                if first.proof_hash == second.proof_hash {
+                    return Err(anyhow!("Forges are the same proof"));
+                }
+                if first.derived_key != second.derived_key {
+                    return Err(anyhow!("Forges are by different keys"));
+                }
+                first.verify_signature()?;
+                second.verify_signature()?;
+            }
+            Evidence::Equivocation { signer, height, first, second } => {
+                let authorities =
+                    authorities.ok_or_else(|| anyhow!("Equivocation evidence on a chain without authorities"))?;
+                let index = authorities
+                    .keys()
+                    .iter()
+                    .position(|key| key.serialize() == *signer)
+                    .ok_or_else(|| anyhow!("Equivocation signer is not an authority"))?;
+                if first.header.height != *height || second.header.height != *height {
+                    return Err(anyhow!("Headers are not at the claimed height"));
+                }
+                let first_hash = header_hash(&first.header);
+                let second_hash = header_hash(&second.header);
+                if first_hash == second_hash {
+                    return Err(anyhow!("Headers are identical"));
+                }
+                for (signed, hash) in [(first, first_hash), (second, second_hash)] {
+                    let entry = AuthoritySignature {
+                        signer: index as u32,
+                        signature: signed.signature.clone(),
+                    };
+                    authorities.check_signature(&entry, &hash)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Summary for RPC output
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Evidence::DoubleForge { prophecy_hash, first, second } => json!({
+                "kind": self.kind(),
+                "prophecy_hash": hex::encode(prophecy_hash),
+                "proof_hashes": [hex::encode(first.proof_hash), hex::encode(second.proof_hash)],
+                "offender": self.offender(),
+            }),
+            Evidence::Equivocation { height, .. } => json!({
+                "kind": self.kind(),
+                "height": height,
+                "offender": self.offender(),
+            }),
+        }
+    }
+}
+
+fn header_hash(header: &BlockHeader) -> [u8; 32] {
+    Sha256::digest(header_hash_preimage(header)).into()
+}
+
+#[derive(Default)]
+struct WatchState {
+    /// First forge seen for each prophecy, with the height it was seen at
+    forges: HashMap<[u8; 32], (u64, ForgeTransaction)>,
+    /// First header seen signed by each (authority index, height)
+    signers: HashMap<(u32, u64), ([u8; 32], SignedHeader)>,
+    highest: u64,
+}
+
+impl WatchState {
+    /// Note a block at `height`, forgetting what fell out of the window
+    fn advance(&mut self, height: u64) {
+        if height <= self.highest {
+            return;
+        }
+        self.highest = height;
+        let floor = height.saturating_sub(WATCH_WINDOW);
+        self.forges.retain(|_, (seen, _)| *seen >= floor);
+        self.signers.retain(|(_, seen), _| *seen >= floor);
+    }
+}
+
+/// Observes forges and blocks and records evidence of violations
+pub struct Watchtower {
+    store: Arc<ChainStore>,
+    events: EventBus,
+    gossip: Option<mpsc::Sender<NetworkCommand>>,
+    authorities: Option<AuthoritySet>,
+    state: Mutex<WatchState>,
+}
+
+impl Watchtower {
+    /// Create a watchtower recording evidence in `store` and alerting through `events`
+    pub fn new(store: Arc<ChainStore>, events: EventBus) -> Self {
+        Self {
+            store,
+            events,
+            gossip: None,
+            authorities: None,
+            state: Mutex::new(WatchState::default()),
+        }
+    }
+
+    /// Watch for equivocating signatures by the chain's authorities
+    pub fn with_authorities(mut self, authorities: AuthoritySet) -> Self {
+        self.authorities = Some(authorities);
+        self
+    }
+
+    /// Also gossip new evidence to peers
+    pub fn with_gossip(mut self, network: mpsc::Sender<NetworkCommand>) -> Self {
+        self.gossip = Some(network);
+        self
+    }
+
+    /// Observe a forge from the mempool
+    pub fn observe_forge(&self, forge: &ForgeTransaction) -> Result<Option<Evidence>> {
+        let height = self.state.lock().unwrap().highest;
+        self.observe_forge_at(forge, height)
+    }
+
+    /// Observe a forge seen at `height`. Only a second forge of the same
+    /// prophecy by the same key is evidence; different keys forging one
+    /// prophecy is a race consensus settles.
+    fn observe_forge_at(&self, forge: &ForgeTransaction, height: u64) -> Result<Option<Evidence>> {
+        let prophecy_hash = prophecy_registry_hash(&forge.prophecy);
+        let evidence = {
+            let mut state = self.state.lock().unwrap();
+            match state.forges.get(&prophecy_hash) {
+                Some((_, first))
+                    if first.proof_hash != forge.proof_hash
+                        && first.derived_key == forge.derived_key
+                        && forge.verify_signature().is_ok() =>
+                {
+                    Evidence::double_forge(first.clone(), forge.clone())
+                }
+                Some(_) => return Ok(None),
+                None => {
+                    if state.forges.len() < MAX_WATCHED_FORGES && forge.verify_signature().is_ok() {
+                        state.forges.insert(prophecy_hash, (height, forge.clone()));
+                    }
+                    return Ok(None);
+                }
+            }
+        };
+        self.record(evidence, true)
+    }
+
+    /// Observe a block with header hash `hash` on any branch
+    pub fn observe_block(&self, block: &Block, hash: &[u8; 32]) -> Result<Vec<Evidence>> {
+        let height = block.header.height;
+        self.state.lock().unwrap().advance(height);
+
+        let mut found = Vec::new();
+        for forge in &block.forges {
+            found.extend(self.observe_forge_at(forge, height)?);
+        }
+
+        let Some(authorities) = &self.authorities else {
+            return Ok(found);
+        };
+        for entry in &block.authority_signatures {
+            if authorities.check_signature(entry, hash).is_err() {
+                continue;
+            }
+            let signed = SignedHeader {
+                header: block.header.clone(),
+                signature: entry.signature.clone(),
+            };
+            let evidence = {
+                let mut state = self.state.lock().unwrap();
+                match state.signers.get(&(entry.signer, height)) {
+                    Some((seen_hash, _)) if seen_hash == hash => None,
+                    Some((_, first)) => {
+                        let signer = authorities.keys()[entry.signer as usize].serialize();
+                        Some(Evidence::equivocation(signer, first.clone(), signed)?)
+                    }
+                    None => {
+                        state.signers.insert((entry.signer, height), (*hash, signed));
+                        None
+                    }
+                }
+            };
+            if let Some(evidence) = evidence {
+                found.extend(self.record(evidence, true)?);
+            }
+        }
+        Ok(found)
+    }
+
+    /// Record evidence gossiped by a peer. Returns `true` if it was new.
+    pub fn import_evidence(&self, bytes: &[u8]) -> Result<bool> {
+        let evidence: Evidence = bincode::deserialize(bytes)?;
+        // Gossipsub already propagates the message; don't publish it again
+        Ok(self.record(evidence, false)?.is_some())
+    }
+
+    /// Verify, store, alert on, and optionally gossip evidence. Returns it
+    /// if it had not been recorded before.
+    fn record(&self, evidence: Evidence, gossip: bool) -> Result<Option<Evidence>> {
+        evidence.verify(self.authorities.as_ref())?;
+        if !self.store.put_evidence(&evidence, MAX_STORED_EVIDENCE)? {
+            return Ok(None);
+        }
+
+        let mut data = evidence.to_json();
+        data["id"] = json!(hex::encode(evidence.id()?));
+        self.events.alert(Alert::new(
+            AlertSeverity::Warning,
+            evidence.kind(),
+            format!("Recorded {} evidence against {}", evidence.kind(), evidence.offender()),
+            data,
+        ));
+
+        if let (true, Some(network)) = (gossip, &self.gossip) {
+            let bytes = bincode::serialize(&evidence)?;
+            if network.try_send(NetworkCommand::PublishEvidence(bytes)).is_err() {
+                tracing::warn!("Network busy; {} evidence not gossiped", evidence.kind());
+            }
+        }
+        Ok(Some(evidence))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::testing::{self, forge_with};
+    use crate::consensus::AuthorityKey;
+    use crate::crypto::CANONICAL_PROPHECY;
+    use crate::events::NodeEvent;
+    use bitcoin::secp256k1::{Keypair, Secp256k1, SecretKey};
+    use tempfile::TempDir;
+
+    /// Forge of the canonical prophecy by the key of `[key; 32]`
+    fn forge(key: u8, proof_byte: u8) -> ForgeTransaction {
+        forge_with(key, |forge| {
+            forge.prophecy = CANONICAL_PROPHECY.join(" ");
+            forge.proof_hash = [proof_byte; 32];
+        })
+    }
+
+    fn block(height: u64, nonce: u64, forges: Vec<ForgeTransaction>) -> Block {
+        let mut block = testing::block(height, [0u8; 32], forges);
+        block.header.nonce = nonce;
+        block
+    }
+
+    fn authority() -> (AuthoritySet, AuthorityKey) {
+        let secret = SecretKey::from_slice(&[9u8; 32]).unwrap();
+        let public = Keypair::from_secret_key(&Secp256k1::new(), &secret).x_only_public_key().0;
+        let set = AuthoritySet::new(vec![public], 1, 0).unwrap();
+        let key = AuthorityKey::new(&set, &secret).unwrap();
+        (set, key)
+    }
+
+    #[test]
+    fn test_double_forge_detected_and_gossiped() {
+        let tmp = TempDir::new().unwrap();
+        let store = Arc::new(ChainStore::new(tmp.path()).unwrap());
+        let events = EventBus::new();
+        let mut alerts = events.subscribe();
+        let (network, mut commands) = mpsc::channel(4);
+        let tower = Watchtower::new(Arc::clone(&store), events).with_gossip(network);
+
+        assert!(tower.observe_forge(&forge(1, 1)).unwrap().is_none());
+        assert!(tower.observe_forge(&forge(1, 1)).unwrap().is_none());
+        // Another key racing for the prophecy is not evidence
+        assert!(tower.observe_forge(&forge(2, 2)).unwrap().is_none());
+        let second = forge(1, 2);
+        let evidence = tower.observe_forge(&second).unwrap().unwrap();
+        assert_eq!(evidence.kind(), "double_forge");
+        assert_eq!(evidence.offender(), hex::encode(&second.derived_key));
+
+        // Recorded once, alerted, and gossiped
+        assert!(tower.observe_forge(&second).unwrap().is_none());
+        assert_eq!(store.list_evidence().unwrap().len(), 1);
+        let NodeEvent::Alert(alert) = alerts.try_recv().unwrap() else {
+            panic!("expected an alert");
+        };
+        assert_eq!(alert.kind, "double_forge");
+        let Ok(NetworkCommand::PublishEvidence(bytes)) = commands.try_recv() else {
+            panic!("evidence was not gossiped");
+        };
+
+        // A peer importing the same evidence doesn't record it twice
+        assert!(!tower.import_evidence(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_equivocation_detected() {
+        let tmp = TempDir::new().unwrap();
+        let store = Arc::new(ChainStore::new(tmp.path()).unwrap());
+        let (set, key) = authority();
+        let tower = Watchtower::new(Arc::clone(&store), EventBus::new()).with_authorities(set.clone());
+
+        let mut a = block(5, 0, vec![forge(1, 1)]);
+        let mut b = block(5, 1, vec![forge(1, 1)]);
+        // Unsigned blocks have no producer to blame
+        assert!(tower.observe_block(&b, &header_hash(&b.header)).unwrap().is_empty());
+
+        let (a_hash, b_hash) = (header_hash(&a.header), header_hash(&b.header));
+        key.sign(&mut a, &a_hash);
+        key.sign(&mut b, &b_hash);
+        assert!(tower.observe_block(&a, &a_hash).unwrap().is_empty());
+        assert!(tower.observe_block(&a, &a_hash).unwrap().is_empty());
+
+        let found = tower.observe_block(&b, &b_hash).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind(), "equivocation");
+        assert_eq!(found[0].offender(), hex::encode(set.keys()[0].serialize()));
+        assert!(found[0].verify(Some(&set)).is_ok());
+        assert!(found[0].verify(None).is_err());
+    }
+
+    #[test]
+    fn test_import_rejects_unsigned_or_unrelated_forges() {
+        let tmp = TempDir::new().unwrap();
+        let store = Arc::new(ChainStore::new(tmp.path()).unwrap());
+        let tower = Watchtower::new(Arc::clone(&store), EventBus::new());
+        let import = |evidence: Evidence| tower.import_evidence(&bincode::serialize(&evidence).unwrap());
+
+        assert!(import(Evidence::double_forge(forge(1, 1), forge(1, 1))).is_err());
+        assert!(import(Evidence::double_forge(forge(1, 1), forge(2, 2))).is_err());
+        let mut unsigned = forge(1, 2);
+        unsigned.signature.clear();
+        assert!(import(Evidence::double_forge(forge(1, 1), unsigned)).is_err());
+        assert!(tower.import_evidence(b"garbage").is_err());
+        assert!(store.list_evidence().unwrap().is_empty());
+
+        // Valid evidence is stored, keeping only the most recent entries
+        for proof in 2..6 {
+            let evidence = Evidence::double_forge(forge(1, 1), forge(1, proof));
+            assert!(store.put_evidence(&evidence, 2).unwrap());
+        }
+        let kept = store.list_evidence().unwrap();
+        assert_eq!(kept.len(), 2);
+        assert!(kept
+            .iter()
+            .all(|evidence| matches!(evidence, Evidence::DoubleForge { second, .. } if second.proof_hash[0] >= 4)));
+    }
+}
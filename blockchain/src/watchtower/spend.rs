@@ -0,0 +1,236 @@
+//! Spend alerts for watched outputs
+//!
+//! Custodians register the forge outputs they hold. When a transfer
+//! spending one of them is observed, in the mempool or in a block, a
+//! critical `watched_output_spent` alert carrying the spending transfer is
+//! raised on the event bus, so webhooks deliver it right away. Registrations
+//! are kept in the chain store and survive restarts.
+
+use crate::chain::ChainStore;
+use crate::consensus::sighash::Transfer;
+use crate::consensus::SignedTransfer;
+use crate::events::{Alert, AlertSeverity, EventBus};
+use crate::ledger::OutPoint;
+use crate::network::unix_now;
+use crate::shutdown::ShutdownSignal;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Alert kind raised when a watched output is spent
+pub const SPEND_ALERT_KIND: &str = "watched_output_spent";
+
+/// An output registered for spend alerts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchedOutput {
+    pub outpoint: OutPoint,
+    /// Operator's name for the output, echoed in alerts
+    pub label: String,
+    /// When the output was registered (Unix seconds)
+    pub registered_at: u64,
+}
+
+impl WatchedOutput {
+    /// Summary for RPC output
+    pub fn to_json(&self) -> Value {
+        json!({
+            "txid": hex::encode(self.outpoint.txid),
+            "vout": self.outpoint.vout,
+            "label": self.label,
+            "registered_at": self.registered_at,
+        })
+    }
+}
+
+/// Where a spending transfer was seen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpendLocation {
+    Mempool,
+    Block { height: u64, hash: [u8; 32] },
+}
+
+/// Raises alerts when watched outputs are spent
+pub struct SpendWatch {
+    store: Arc<ChainStore>,
+    events: EventBus,
+}
+
+impl SpendWatch {
+    /// Create a spend watch over the registrations in `store`, alerting
+    /// through `events`
+    pub fn new(store: Arc<ChainStore>, events: EventBus) -> Self {
+        Self { store, events }
+    }
+
+    /// Register an output. Returns `false` if it was already watched, in
+    /// which case its label is updated.
+    pub fn watch(&self, outpoint: OutPoint, label: &str) -> Result<bool> {
+        let existing = self.store.get_watched_output(&outpoint)?;
+        let registered_at = existing.as_ref().map_or_else(unix_now, |watched| watched.registered_at);
+        self.store.put_watched_output(&WatchedOutput {
+            outpoint,
+            label: label.to_string(),
+            registered_at,
+        })?;
+        Ok(existing.is_none())
+    }
+
+    /// Stop watching an output. Returns `false` if it wasn't watched.
+    pub fn unwatch(&self, outpoint: &OutPoint) -> Result<bool> {
+        if self.store.get_watched_output(outpoint)?.is_none() {
+            return Ok(false);
+        }
+        self.store.delete_watched_output(outpoint)?;
+        Ok(true)
+    }
+
+    /// Every watched output, by outpoint
+    pub fn list(&self) -> Result<Vec<WatchedOutput>> {
+        self.store.list_watched_outputs()
+    }
+
+    /// Observe a transfer, alerting for each watched output it spends.
+    /// Returns the watched outputs spent.
+    pub fn observe_transfer(&self, transfer: &Transfer, location: SpendLocation) -> Result<Vec<WatchedOutput>> {
+        let mut spent = Vec::new();
+        for input in &transfer.inputs {
+            if let Some(watched) = self.store.get_watched_output(&input.prevout)? {
+                spent.push(watched);
+            }
+        }
+        if spent.is_empty() {
+            return Ok(spent);
+        }
+
+        let txid = transfer.txid()?;
+        let (location_name, height, block_hash) = match location {
+            SpendLocation::Mempool => ("mempool", None, None),
+            SpendLocation::Block { height, hash } => ("block", Some(height), Some(hex::encode(hash))),
+        };
+        let spending = transfer_json(transfer, &txid);
+        for watched in &spent {
+            self.events.alert(Alert::new(
+                AlertSeverity::Critical,
+                SPEND_ALERT_KIND,
+                format!(
+                    "Watched output {}:{} ({}) spent by {} in the {}",
+                    hex::encode(watched.outpoint.txid),
+                    watched.outpoint.vout,
+                    watched.label,
+                    hex::encode(txid),
+                    location_name
+                ),
+                json!({
+                    "output": watched.to_json(),
+                    "location": location_name,
+                    "height": height,
+                    "block_hash": block_hash,
+                    "spending_transfer": spending,
+                }),
+            ));
+        }
+        Ok(spent)
+    }
+
+    /// Observe transfers admitted to the mempool until shutdown
+    pub async fn run(self: Arc<Self>, mut transfers: broadcast::Receiver<SignedTransfer>, mut shutdown: ShutdownSignal) {
+        loop {
+            tokio::select! {
+                transfer = transfers.recv() => match transfer {
+                    Ok(transfer) => {
+                        if let Err(e) = self.observe_transfer(&transfer.transfer, SpendLocation::Mempool) {
+                            tracing::warn!("Failed to check a mempool transfer for watched outputs: {}", e);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::warn!("Spend watch fell behind, {} mempool transfers not checked", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = shutdown.recv() => break,
+            }
+        }
+    }
+}
+
+/// A transfer with hex-encoded hashes, for alert payloads
+fn transfer_json(transfer: &Transfer, txid: &[u8; 32]) -> Value {
+    json!({
+        "txid": hex::encode(txid),
+        "inputs": transfer
+            .inputs
+            .iter()
+            .map(|input| json!({
+                "txid": hex::encode(input.prevout.txid),
+                "vout": input.prevout.vout,
+                "amount": input.amount,
+                "address": input.address,
+            }))
+            .collect::<Vec<_>>(),
+        "outputs": transfer.outputs,
+        "fee": transfer.fee,
+        "lock_height": transfer.lock_height,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::sighash::{TransferInput, TransferOutput};
+    use crate::events::NodeEvent;
+    use tempfile::TempDir;
+
+    fn spending(prevout: OutPoint) -> Transfer {
+        Transfer {
+            version: 1,
+            inputs: vec![TransferInput {
+                prevout,
+                amount: 5_000_000_000,
+                address: "bc1pcustody".to_string(),
+            }],
+            outputs: vec![TransferOutput {
+                address: "bc1pthief".to_string(),
+                value: 4_999_990_000,
+            }],
+            fee: 10_000,
+            lock_height: 0,
+        }
+    }
+
+    #[test]
+    fn test_spend_of_watched_output_alerts() {
+        let tmp = TempDir::new().unwrap();
+        let store = Arc::new(ChainStore::new(tmp.path()).unwrap());
+        let events = EventBus::new();
+        let mut alerts = events.subscribe();
+        let watch = SpendWatch::new(Arc::clone(&store), events);
+        let cold = OutPoint { txid: [1; 32], vout: 0 };
+
+        assert!(watch.watch(cold, "cold storage").unwrap());
+        assert!(!watch.watch(cold, "cold wallet").unwrap());
+        assert_eq!(watch.list().unwrap()[0].label, "cold wallet");
+
+        // Unwatched outputs are ignored
+        let other = spending(OutPoint { txid: [2; 32], vout: 0 });
+        assert!(watch.observe_transfer(&other, SpendLocation::Mempool).unwrap().is_empty());
+        assert!(alerts.try_recv().is_err());
+
+        let theft = spending(cold);
+        let location = SpendLocation::Block { height: 7, hash: [9; 32] };
+        assert_eq!(watch.observe_transfer(&theft, location).unwrap().len(), 1);
+        let NodeEvent::Alert(alert) = alerts.try_recv().unwrap() else {
+            panic!("expected an alert");
+        };
+        assert_eq!((alert.kind.as_str(), alert.severity), (SPEND_ALERT_KIND, AlertSeverity::Critical));
+        assert_eq!(alert.data["height"], 7);
+        assert_eq!(alert.data["output"]["label"], "cold wallet");
+        assert_eq!(alert.data["spending_transfer"]["txid"], hex::encode(theft.txid().unwrap()));
+        assert_eq!(alert.data["spending_transfer"]["outputs"][0]["address"], "bc1pthief");
+
+        assert!(watch.unwatch(&cold).unwrap());
+        assert!(!watch.unwatch(&cold).unwrap());
+        assert!(watch.observe_transfer(&theft, SpendLocation::Mempool).unwrap().is_empty());
+    }
+}